@@ -0,0 +1,53 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+//! Decodes arbitrary streams from a plugin, checking that how they're
+//! split across reads doesn't change what the client sees.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sudo_pair_client::{Event, Parser};
+
+fuzz_target!(|data: &[u8]| {
+    let (sizes, stream) = match data.split_first() {
+        Some((size, stream)) => (usize::from(*size).max(1), stream),
+        None                 => return,
+    };
+
+    let whole = Parser::new().feed(stream);
+
+    let mut parser = Parser::new();
+    let mut split  = Vec::new();
+
+    for chunk in stream.chunks(sizes) {
+        split.extend(parser.feed(chunk));
+    }
+
+    assert_eq!(coalesce(whole), coalesce(split));
+});
+
+/// Merges adjacent output, since how it's divided into events depends
+/// on how the stream was read.
+fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut merged : Vec<Event> = Vec::new();
+
+    for event in events {
+        match (merged.last_mut(), event) {
+            (Some(Event::Output(last)), Event::Output(more)) => last.extend(more),
+            (_, event)                                       => merged.push(event),
+        }
+    }
+
+    merged
+}
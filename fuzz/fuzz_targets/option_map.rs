@@ -0,0 +1,45 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+//! Parses arbitrary option vectors, and every key in them as each of
+//! the types plugins commonly ask for.
+
+#![no_main]
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+use sudo_plugin::{ByteSize, Cidr};
+
+fuzz_target!(|data: &[u8]| {
+    let map  = sudo_pair_fuzz::option_map(data);
+    let keys : Vec<_> = map.keys()
+        .map(|key| String::from_utf8_lossy(key).into_owned())
+        .collect();
+
+    for key in &keys {
+        let _ = map.get::<bool>(key);
+        let _ = map.get::<i32>(key);
+        let _ = map.get::<u64>(key);
+        let _ = map.get::<String>(key);
+        let _ = map.get::<PathBuf>(key);
+        let _ = map.get::<Duration>(key);
+        let _ = map.get::<ByteSize>(key);
+        let _ = map.get::<Vec<u32>>(key);
+        let _ = map.get::<Vec<Cidr>>(key);
+        let _ = map.get::<HashSet<u16>>(key);
+        let _ = map.get_bytes(key.as_bytes());
+    }
+});
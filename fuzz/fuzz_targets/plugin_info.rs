@@ -0,0 +1,31 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+//! Converts arbitrary option vectors into the `settings`,
+//! `user_info`, and `command_info` structures sudo provides to every
+//! plugin.
+
+#![no_main]
+
+use std::convert::TryFrom;
+
+use libfuzzer_sys::fuzz_target;
+use sudo_plugin::{CommandInfo, Settings, UserInfo};
+
+fuzz_target!(|data: &[u8]| {
+    let map = sudo_pair_fuzz::option_map(data);
+
+    let _ = Settings   ::try_from(map.clone());
+    let _ = UserInfo   ::try_from(map.clone());
+    let _ = CommandInfo::try_from(map);
+});
@@ -0,0 +1,36 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+//! Helpers shared between the fuzz targets.
+
+use std::ffi::CString;
+use std::ptr;
+
+use sudo_plugin::OptionMap;
+
+/// Builds an `OptionMap` the way sudo hands one to a plugin, treating
+/// each NUL-separated chunk of `data` as one `key=value` entry.
+pub fn option_map(data: &[u8]) -> OptionMap {
+    // `split` never yields a chunk containing a NUL, so `CString::new`
+    // can't fail
+    let entries : Vec<_> = data.split(|b| *b == 0)
+        .map(|entry| CString::new(entry).unwrap())
+        .collect();
+
+    let mut ptrs : Vec<_> = entries.iter().map(|e| e.as_ptr()).collect();
+
+    ptrs.push(ptr::null());
+
+    // `ptrs` is NULL-terminated and `entries` outlives the call
+    unsafe { OptionMap::from_raw(ptrs.as_ptr()) }
+}
@@ -0,0 +1,48 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Work deferred until sudo exits. sudo closes I/O plugins as soon as
+//! the command finishes, but some things (like the files a `sudoedit`
+//! session edited) only settle after that, and sudo never unloads its
+//! plugins, so the work can be left to an `atexit(3)` handler.
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, Once};
+
+type Deferred = Box<dyn FnOnce() + Send>;
+
+static DEFERRED : Mutex<Vec<Deferred>> = Mutex::new(Vec::new());
+static REGISTER : Once                 = Once::new();
+
+/// Runs `f` when the process exits normally.
+pub(crate) fn defer<F: FnOnce() + Send + 'static>(f: F) {
+    REGISTER.call_once(|| unsafe {
+        let _ = libc::atexit(run);
+    });
+
+    DEFERRED.lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(Box::new(f));
+}
+
+extern "C" fn run() {
+    let deferred = mem::take(&mut *DEFERRED.lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()));
+
+    // unwinding out of an `atexit` handler would abort the process
+    for f in deferred {
+        let _ = panic::catch_unwind(AssertUnwindSafe(f));
+    }
+}
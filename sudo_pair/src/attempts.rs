@@ -0,0 +1,145 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A limit on how often each user can connect to a session's socket, so
+//! someone connecting over and over (to keep the plugin busy while an
+//! approver is trying to connect, or just to flood the log with dropped
+//! connections) is shut out for a while instead.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libc::uid_t;
+
+/// What became of a connection attempt.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Verdict {
+    /// the connection can be considered as usual
+    Allowed,
+
+    /// the connection's user is banned, and it should be dropped
+    Banned,
+
+    /// the connection's user has just been banned for it, and it should
+    /// be dropped
+    NewlyBanned,
+}
+
+/// The connection attempts made by each user, counted in fixed windows.
+#[derive(Debug)]
+pub(crate) struct Attempts {
+    limit:  u32,
+    window: Duration,
+    ban:    Duration,
+
+    peers: HashMap<uid_t, Peer>,
+}
+
+#[derive(Debug)]
+struct Peer {
+    /// when the current window began, and how many attempts have been
+    /// made in it
+    since: Instant,
+    count: u32,
+
+    /// when the user's ban ends, if they're banned
+    banned_until: Option<Instant>,
+}
+
+impl Attempts {
+    /// Allows each user `limit` attempts in any `window`, banning those
+    /// who make more for `ban`. A `limit` of `0` never bans anyone.
+    pub(crate) fn new(limit: u32, window: Duration, ban: Duration) -> Self {
+        Self { limit, window, ban, peers: HashMap::new() }
+    }
+
+    /// Counts an attempt by `uid` at `now`, returning whether it's
+    /// allowed.
+    pub(crate) fn attempt(&mut self, uid: uid_t, now: Instant) -> Verdict {
+        if self.limit == 0 {
+            return Verdict::Allowed;
+        }
+
+        let peer = self.peers.entry(uid).or_insert(Peer {
+            since:        now,
+            count:        0,
+            banned_until: None,
+        });
+
+        match peer.banned_until {
+            Some(until) if now < until => return Verdict::Banned,
+
+            // a ban that's over starts the user afresh
+            Some(_) => *peer = Peer { since: now, count: 0, banned_until: None },
+            None    => {},
+        }
+
+        if now.saturating_duration_since(peer.since) >= self.window {
+            peer.since = now;
+            peer.count = 0;
+        }
+
+        peer.count += 1;
+
+        if peer.count > self.limit {
+            peer.banned_until = Some(now + self.ban);
+
+            return Verdict::NewlyBanned;
+        }
+
+        Verdict::Allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_users_over_the_limit() {
+        let start        = Instant::now();
+        let mut attempts = Attempts::new(2, Duration::from_secs(10), Duration::from_secs(60));
+
+        assert_eq!(Verdict::Allowed,     attempts.attempt(1000, start));
+        assert_eq!(Verdict::Allowed,     attempts.attempt(1000, start));
+        assert_eq!(Verdict::NewlyBanned, attempts.attempt(1000, start));
+        assert_eq!(Verdict::Banned,      attempts.attempt(1000, start + Duration::from_secs(59)));
+
+        // others are counted separately
+        assert_eq!(Verdict::Allowed, attempts.attempt(1001, start));
+
+        // and bans end
+        assert_eq!(Verdict::Allowed, attempts.attempt(1000, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn counts_attempts_per_window() {
+        let start        = Instant::now();
+        let mut attempts = Attempts::new(1, Duration::from_secs(10), Duration::from_secs(60));
+
+        assert_eq!(Verdict::Allowed, attempts.attempt(1000, start));
+        assert_eq!(Verdict::Allowed, attempts.attempt(1000, start + Duration::from_secs(10)));
+        assert_eq!(Verdict::Allowed, attempts.attempt(1000, start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn never_bans_without_a_limit() {
+        let start        = Instant::now();
+        let mut attempts = Attempts::new(0, Duration::from_secs(10), Duration::from_secs(60));
+
+        for _ in 0..100 {
+            assert_eq!(Verdict::Allowed, attempts.attempt(1000, start));
+        }
+    }
+}
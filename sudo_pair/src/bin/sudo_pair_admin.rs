@@ -0,0 +1,361 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Lists the sessions running on the host, and kills them, for
+//! administrators responding to an incident.
+//!
+//! Sessions are found in the registry under `socket_dir` (see
+//! `registry`), which is read from the `sudo_pair` line of
+//! `/etc/sudo.conf` (or `--sudo-conf`) unless `--socket-dir` is given.
+//! A session is killed by leaving a request in the registry naming who
+//! killed it and why, which the session records in its manifest and
+//! logs, then hanging up on `sudo`, which relays the hangup to the
+//! command. A command that ignores it is ended the next time it reads
+//! input or writes output.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{registry, status};
+
+use registry::{KillRequest, Running};
+use status::Report;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use sudo_pair_types::manifest::utc;
+
+const DEFAULT_SUDO_CONF  : &str = "/etc/sudo.conf";
+const DEFAULT_SOCKET_DIR : &str = "/var/run/sudo_pair";
+const DEFAULT_WAIT       : u64  = 10;
+
+/// How often a killed session is checked for having ended.
+const POLL_INTERVAL : Duration = Duration::from_millis(100);
+
+const USAGE : &str = "\
+usage: sudo_pair_admin [options] list
+       sudo_pair_admin [options] kill SESSION --reason REASON
+
+Lists the paired sessions running on this host, or kills the session
+SESSION, recording who killed it and why in its manifest and logs. Sessions
+run with in_memory_only aren't registered, so can be neither listed nor
+killed. The exit status is 1 if a killed session hadn't ended by the time
+kill stopped waiting, or 2 if nothing could be done at all.
+
+options:
+    --sudo-conf PATH   read socket_dir from the sudo_pair line of PATH
+                       [/etc/sudo.conf]
+    --socket-dir DIR   find sessions in DIR rather than sudo.conf's socket_dir
+    --reason REASON    why the session is being killed (e.g., an incident)
+    --wait SECONDS     how long to wait for a killed session to end [10]
+    --json             print the sessions (or problems, or error) as a JSON
+                       object
+    -h, --help         print this message
+";
+
+/// What was asked of the tool.
+#[derive(Debug, Eq, PartialEq)]
+enum Command {
+    List,
+    Kill { id: String, reason: String },
+}
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let json     = status::json_requested(&mut args);
+
+    let report = run(args.into_iter(), json).unwrap_or_else(|e| Report::error(&e));
+
+    report.exit("sudo_pair_admin", json)
+}
+
+fn run<I: Iterator<Item = String>>(mut args: I, json: bool) -> Result<Report, String> {
+    let mut sudo_conf  = PathBuf::from(DEFAULT_SUDO_CONF);
+    let mut socket_dir = None;
+    let mut reason     = None;
+    let mut wait       = DEFAULT_WAIT;
+    let mut words      = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next()
+            .ok_or_else(|| format!("{} requires a value", flag));
+
+        match arg.as_str() {
+            "--sudo-conf"   => sudo_conf  = PathBuf::from(value(&arg)?),
+            "--socket-dir"  => socket_dir = Some(PathBuf::from(value(&arg)?)),
+            "--reason"      => reason     = Some(value(&arg)?),
+            "--wait"        => wait       = value(&arg)?.parse()
+                .map_err(|_| "--wait must be a number of seconds")?,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(Report::success());
+            },
+            _ if arg.starts_with('-') => return Err(format!("unrecognized argument {}\n\n{}", arg, USAGE)),
+            _                         => words.push(arg),
+        }
+    }
+
+    let command = command(&words, reason)?;
+
+    let socket_dir = match socket_dir {
+        Some(dir) => dir,
+        None      => {
+            let conf = fs::read_to_string(&sudo_conf)
+                .map_err(|e| format!("couldn't read {}: {}", sudo_conf.display(), e))?;
+
+            configured_socket_dir(&conf)
+                .ok_or("no 'Plugin sudo_pair <path>' line was found")?
+        },
+    };
+
+    let registry = socket_dir.join("sessions");
+
+    match command {
+        Command::List                => list(&registry, json),
+        Command::Kill { id, reason } => kill(&registry, &id, reason, Duration::from_secs(wait), json),
+    }
+}
+
+/// Interprets the words given on the command line, which name what to
+/// do (and to which session).
+fn command(words: &[String], reason: Option<String>) -> Result<Command, String> {
+    let words : Vec<_> = words.iter().map(String::as_str).collect();
+
+    match (words.as_slice(), reason) {
+        (["list"], _)          => Ok(Command::List),
+        (["kill", id], Some(reason)) if !reason.trim().is_empty() => Ok(Command::Kill {
+            id: (*id).to_owned(),
+            reason,
+        }),
+        (["kill", _], _)       => Err("kill requires a --reason, which is recorded with the session".into()),
+        _                      => Err(format!("expected list or kill SESSION\n\n{}", USAGE)),
+    }
+}
+
+/// The `socket_dir` on the `sudo_pair` line of the contents of a
+/// `sudo.conf`, or the plugin's default if the line doesn't set one.
+/// Returns `None` if there's no such line.
+fn configured_socket_dir(conf: &str) -> Option<PathBuf> {
+    let words = conf.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|words| words.len() >= 3 && words[0] == "Plugin" && words[1] == "sudo_pair")?;
+
+    let dir = words.iter().skip(3)
+        .rev()
+        .find_map(|option| option.strip_prefix("socket_dir="))
+        .unwrap_or(DEFAULT_SOCKET_DIR);
+
+    Some(PathBuf::from(dir))
+}
+
+fn list(registry: &Path, json: bool) -> Result<Report, String> {
+    let sessions = registry::list(registry)
+        .map_err(|e| format!("couldn't list sessions in {}: {}", registry.display(), e))?;
+
+    if !json {
+        for session in &sessions {
+            println!("{}", describe(session));
+        }
+    }
+
+    let sessions : Vec<_> = sessions.iter().map(Running::to_json).collect();
+
+    Ok(Report::success().objects("sessions", &sessions))
+}
+
+fn kill(registry: &Path, id: &str, reason: String, wait: Duration, json: bool) -> Result<Report, String> {
+    let request = KillRequest {
+        requested_by: requested_by(),
+        reason,
+        requested_at: SystemTime::now(),
+    };
+
+    let session = registry::request_kill(registry, id, &request)
+        .map_err(|e| format!("couldn't kill session {}: {}", id, e))?;
+
+    // sudo relays the hangup to the command, which ends most of them
+    // (including interactive shells) the way losing their terminal would
+    if let Some(pid) = session.pid {
+        if unsafe { libc::kill(pid, libc::SIGHUP) } == -1 {
+            let e = std::io::Error::last_os_error();
+
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                return Err(format!("couldn't signal sudo (pid {}): {}", pid, e));
+            }
+        }
+    }
+
+    let deadline = Instant::now() + wait;
+    let mut problems = Vec::new();
+
+    while still_running(registry, id)? {
+        if Instant::now() >= deadline {
+            problems.push(format!(
+                "session {} is still running; it will end the next time it reads input or writes output",
+                id,
+            ));
+
+            break;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    if !json {
+        for problem in &problems {
+            println!("{}", problem);
+        }
+
+        if problems.is_empty() {
+            println!("session {} ended", id);
+        }
+    }
+
+    Ok(Report::problems(&problems).string("session", id))
+}
+
+fn still_running(registry: &Path, id: &str) -> Result<bool, String> {
+    registry::list(registry)
+        .map(|sessions| sessions.iter().any(|session| session.id == id))
+        .map_err(|e| format!("couldn't list sessions in {}: {}", registry.display(), e))
+}
+
+/// Who's killing the session, as recorded with it: the user who ran
+/// `sudo` to become root, if they did, or whoever's logged in.
+fn requested_by() -> String {
+    ["SUDO_USER", "USER", "LOGNAME"].iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|user| !user.is_empty())
+        .unwrap_or_else(|| format!("uid {}", unsafe { libc::getuid() }))
+}
+
+/// A line describing a running session, for people.
+fn describe(session: &Running) -> String {
+    let mut line = session.id.clone();
+
+    if let Some(pid) = session.pid {
+        line.push_str(&format!(" (pid {})", pid));
+    }
+
+    if let Some(ref about) = session.about {
+        line.push_str(&format!(
+            " {} as {} since {}: {}",
+            about.user,
+            about.runas_user,
+            utc(about.started_at),
+            about.command,
+        ));
+    }
+
+    if let Some(ref kill) = session.kill {
+        line.push_str(&format!(" [killed by {}: {}]", kill.requested_by, kill.reason));
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use registry::About;
+
+    use std::time::UNIX_EPOCH;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|&word| word.to_owned()).collect()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Ok(Command::List), command(&words(&["list"]), None));
+        assert_eq!(
+            Ok(Command::Kill { id: "1600000000-1".into(), reason: "INC-1234".into() }),
+            command(&words(&["kill", "1600000000-1"]), Some("INC-1234".into())),
+        );
+
+        assert!(command(&words(&["kill", "1600000000-1"]), None).is_err());
+        assert!(command(&words(&["kill", "1600000000-1"]), Some(" ".into())).is_err());
+        assert!(command(&words(&["kill"]), Some("INC-1234".into())).is_err());
+        assert!(command(&words(&[]), None).is_err());
+    }
+
+    #[test]
+    fn finds_the_configured_socket_dir() {
+        assert_eq!(
+            Some(PathBuf::from("/tmp/pair")),
+            configured_socket_dir("Plugin sudoers_policy sudoers.so\nPlugin sudo_pair sudo_pair.so socket_dir=/tmp/pair verbose\n"),
+        );
+
+        assert_eq!(
+            Some(PathBuf::from(DEFAULT_SOCKET_DIR)),
+            configured_socket_dir("Plugin sudo_pair sudo_pair.so verbose"),
+        );
+
+        assert_eq!(None, configured_socket_dir("Plugin sudoers_policy sudoers.so"));
+    }
+
+    #[test]
+    fn describes_sessions() {
+        let mut session = Running {
+            id:    "1600000000-1".into(),
+            pid:   Some(4242),
+            about: None,
+            kill:  None,
+        };
+
+        assert_eq!("1600000000-1 (pid 4242)", describe(&session));
+
+        session.about = Some(About {
+            user:       "alice".into(),
+            runas_user: "root".into(),
+            command:    "/bin/sh".into(),
+            started_at: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        });
+
+        session.kill = Some(KillRequest {
+            requested_by: "bob".into(),
+            reason:       "INC-1234".into(),
+            requested_at: UNIX_EPOCH,
+        });
+
+        assert_eq!(
+            "1600000000-1 (pid 4242) alice as root since 2020-09-13T12:26:40Z: /bin/sh [killed by bob: INC-1234]",
+            describe(&session),
+        );
+    }
+}
@@ -0,0 +1,739 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Checks the `sudo_pair` line of `/etc/sudo.conf` (or `--sudo-conf`,
+//! or a line given with `--line`) before it's deployed: every option is
+//! parsed exactly as the plugin parses it, and the files and directories
+//! they name are checked for the ownership and permissions the plugin
+//! will insist on. Every problem is printed, and the exit status is
+//! nonzero if there were any, so it can gate configuration management.
+//!
+//! Recordings left incomplete by sessions that never finished (e.g.,
+//! because sudo was killed) are reported too, and with `--recover`,
+//! completed with what was known about their sessions (see `recovery`).
+//!
+//! With `--describe-options`, every option the plugin accepts is printed
+//! instead, as a JSON array of objects with each option's `name`,
+//! `type`, `default`, and `description` (see `schema`).
+//!
+//! With `--find-session <id>`, the session's entry in the configured
+//! `session_index` is printed instead (see `index`).
+//!
+//! With `--footprint <path>`, the plugin built at `path` is checked
+//! instead, for its size and the symbols it exports (see `footprint`).
+//!
+//! With `--decide <path>`, what the configuration would decide for each
+//! invocation captured at `path` (or in the directory it names) is
+//! printed instead, so a change to the configuration or pair rules can
+//! be tried against real sessions before it's deployed (see `policy`).
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{capture, footprint, hints, index, labels, options, policy, recovery, rules, schema, status, terminal, totp};
+
+use capture::Capture;
+use hints::Hints;
+use labels::LABEL_KEY;
+use options::{PluginOptions, RecordingSinkKind};
+use policy::{Decision, Invocation};
+use rules::Rules;
+use status::Report;
+use terminal::Terminal;
+use totp::TotpKeys;
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::io::BufReader;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use sudo_plugin::{ByteSize, FromSudoOption, OptionMap};
+
+const DEFAULT_SUDO_CONF : &str = "/etc/sudo.conf";
+
+const USAGE : &str = "\
+usage: sudo_pair_check [options]
+
+Checks the options given to sudo_pair in sudo.conf, and the files and
+directories they name. Every problem found is printed, and the exit status
+is 1 if there were any, or 2 if they couldn't be checked at all.
+
+options:
+    --sudo-conf PATH   check the sudo_pair line of PATH [/etc/sudo.conf]
+    --line LINE        check LINE (e.g., 'Plugin sudo_pair sudo_pair.so ...')
+    --recover          complete recordings left incomplete by sessions that
+                       never finished, rather than only reporting them
+    --describe-options print every option the plugin accepts as JSON, with
+                       its type, default, and description, and exit
+    --find-session ID  print the session_index entry of the session ID, and
+                       exit
+    --footprint PATH   check that the plugin built at PATH exports only what
+                       sudo loads it by, and is no larger than --max-size
+    --max-size SIZE    the largest --footprint allows (e.g., 4MiB) [3MiB]
+    --decide PATH      print whether the session captured at PATH (or each
+                       .capture file in it) would need a pair, be exempted,
+                       or be denied, rather than checking files; may be
+                       given more than once. Captures don't include the
+                       command's arguments, so arg pair rules never match
+    --json             print the problems (or error) as a JSON object
+    -h, --help         print this message
+";
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let json     = status::json_requested(&mut args);
+
+    let report = match run(args.into_iter()) {
+        Ok(problems) => {
+            if !json {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+            }
+
+            Report::problems(&problems)
+        },
+
+        Err(e) => Report::error(&e),
+    };
+
+    report.exit("sudo_pair_check", json)
+}
+
+/// Returns every problem with the configuration the arguments point
+/// to, or an error if it couldn't be checked at all.
+fn run<I: Iterator<Item = String>>(mut args: I) -> Result<Vec<String>, String> {
+    let mut sudo_conf = PathBuf::from(DEFAULT_SUDO_CONF);
+    let mut line      = None;
+    let mut recover   = false;
+    let mut find      = None;
+    let mut footprint = None;
+    let mut max_size  = footprint::MAX_SIZE;
+    let mut decide    = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next()
+            .ok_or_else(|| format!("{} requires a value", flag));
+
+        match arg.as_str() {
+            "--sudo-conf"   => sudo_conf = PathBuf::from(value(&arg)?),
+            "--line"        => line      = Some(value(&arg)?),
+            "--recover"     => recover   = true,
+            "--find-session" => find = Some(value(&arg)?),
+            "--footprint"   => footprint = Some(PathBuf::from(value(&arg)?)),
+            "--decide"      => decide.push(PathBuf::from(value(&arg)?)),
+            "--max-size"    => {
+                let size = value(&arg)?;
+
+                max_size = ByteSize::from_sudo_option(&size)
+                    .map_err(|_| format!("--max-size: {} isn't a size (e.g., 4MiB)", size))?
+                    .as_u64();
+            },
+            "--describe-options" => {
+                println!("{}", schema::to_json(&schema::options()));
+                return Ok(Vec::new());
+            },
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(Vec::new());
+            },
+            _ => return Err(format!("unrecognized argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    if let Some(path) = footprint {
+        return footprint::check(&path, max_size);
+    }
+
+    let conf = match line {
+        Some(line) => line,
+        None       => fs::read_to_string(&sudo_conf)
+            .map_err(|e| format!("couldn't read {}: {}", sudo_conf.display(), e))?,
+    };
+
+    let raw = plugin_options(&conf)
+        .ok_or("no 'Plugin sudo_pair <path>' line was found")?;
+
+    match (parse(&raw)?, find) {
+        (Ok(options), _) if !decide.is_empty() => decide_captures(&options, &decide),
+        (Ok(options), Some(id)) => find_session(&options, &id),
+        (Ok(options), None)     => Ok(check(&options, recover)),
+        (Err(problems), _)      => Ok(problems),
+    }
+}
+
+/// Prints the `session_index` entry of the session `id`, or returns
+/// the problem if it has none.
+fn find_session(options: &PluginOptions, id: &str) -> Result<Vec<String>, String> {
+    let path = options.session_index.as_ref()
+        .ok_or("session_index isn't set, so sessions aren't indexed")?;
+
+    match index::find(path, id) {
+        Ok(Some(entry)) => {
+            println!("{}", entry.to_json());
+            Ok(Vec::new())
+        },
+
+        Ok(None) => Ok(vec![format!("session_index: {} has no entry for session {}", path.display(), id)]),
+        Err(e)   => Err(format!("couldn't read {}: {}", path.display(), e)),
+    }
+}
+
+/// Prints what the plugin, configured with `options`, would decide for
+/// each invocation captured at `paths`, and returns the problems with
+/// those that couldn't be decided.
+fn decide_captures(options: &PluginOptions, paths: &[PathBuf]) -> Result<Vec<String>, String> {
+    let mut problems = Vec::new();
+
+    // the plugin carries on without rules it can't load, requiring a
+    // pair for every session, and so is the decision here
+    let rules = match options.pair_rules.as_ref().map(|path| (path, Rules::load(path))) {
+        Some((_, Ok(rules))) => Some(rules),
+        Some((path, Err(e))) => {
+            problems.push(format!("pair_rules: {}: {}; every session will require a pair until it's fixed", path.display(), e));
+            None
+        },
+        None => None,
+    };
+
+    for path in captures(paths)? {
+        match decide(options, rules.as_ref(), &path) {
+            Ok(decision) => println!("{}: {}", path.display(), decision),
+            Err(e)       => problems.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Decides the invocation captured at `path` as the plugin would, were
+/// it configured with `options` and `rules`.
+fn decide(options: &PluginOptions, rules: Option<&Rules>, path: &Path) -> Result<Decision, String> {
+    let file   = fs::File::open(path).map_err(|e| e.to_string())?;
+    let plugin = Capture::read_from(BufReader::new(file))?.plugin()?;
+
+    options.apply_identity_source(&plugin);
+
+    let (labels, _) = plugin.command_info.raw.get_lossy(LABEL_KEY)
+        .map(|value| labels::parse(&value))
+        .unwrap_or_default();
+
+    // the processes the session was run from aren't captured, so a
+    // multiplexer is only recognized by the environment it left
+    let terminal = Terminal::detect(
+        plugin.user_info.tty.as_deref(),
+        |var| plugin.user_env.get_str(var).map(String::from),
+        &[],
+    );
+
+    let invocation = Invocation {
+        plugin:   &plugin,
+        options,
+        labels:   &labels,
+        terminal: &terminal,
+    };
+
+    Ok(policy::decide(invocation, &plugin, rules))
+}
+
+/// The captures at `paths`, each either a capture or a directory whose
+/// `.capture` files are taken in order.
+fn captures(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut captures = Vec::new();
+
+    for path in paths {
+        if !path.is_dir() {
+            captures.push(path.clone());
+            continue;
+        }
+
+        let mut found : Vec<_> = fs::read_dir(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "capture"))
+            .collect();
+
+        found.sort();
+        captures.extend(found);
+    }
+
+    Ok(captures)
+}
+
+/// Returns each option (as `key=value`, or just `key`) on the
+/// `sudo_pair` line of the contents of a `sudo.conf`, if it has one.
+fn plugin_options(conf: &str) -> Option<Vec<String>> {
+    conf.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|words| words.len() >= 3 && words[0] == "Plugin" && words[1] == "sudo_pair")
+        .map(|words| words.into_iter().skip(3).map(String::from).collect())
+}
+
+/// Parses `raw` options exactly as sudo hands them to the plugin.
+fn parse(raw: &[String]) -> Result<Result<PluginOptions, Vec<String>>, String> {
+    let options = raw.iter()
+        .map(|option| CString::new(option.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let pointers : Vec<_> = options.iter()
+        .map(|option| option.as_ptr())
+        .chain(Some(ptr::null()))
+        .collect();
+
+    // the array is terminated by a NULL pointer, and every string is
+    // kept alive by `options` for as long as the map is built
+    let map = unsafe { OptionMap::from_raw(pointers.as_ptr()) };
+
+    Ok(PluginOptions::try_from(&map))
+}
+
+/// Returns every problem with the files and directories `options`
+/// name, each with what to do about it. Incomplete recordings are
+/// completed first if asked to `recover` them.
+fn check(options: &PluginOptions, recover: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    check_executable(&mut problems, "binary_path", &options.binary_path, false);
+
+    // sockets are only named after it when nothing's written to disk
+    if !options.in_memory_only {
+        check_directory(&mut problems, "socket_dir", &options.socket_dir, 0o022, true);
+    }
+
+    if options.recording_sink == RecordingSinkKind::File || options.recording_sink == RecordingSinkKind::Http {
+        // the plugin creates it when it doesn't exist, but leaves one
+        // that does exist as it is
+        check_directory(&mut problems, "recording_dir", &options.recording_dir, 0o002, false);
+        check_recordings(&mut problems, &options.recording_dir, recover);
+    }
+
+    if let Some(command) = options.recording_command.as_ref() {
+        check_executable(&mut problems, "recording_command", command, true);
+    }
+
+    if let Some(hook) = options.close_hook.as_ref() {
+        check_executable(&mut problems, "close_hook", hook, true);
+    }
+
+    if let Some(key) = options.preapproval_key.as_ref() {
+        match fs::metadata(key) {
+            Err(e) => problems.push(format!(
+                "preapproval_key: couldn't read {} ({}); pre-approval tokens will all be rejected",
+                key.display(),
+                e,
+            )),
+
+            Ok(metadata) if metadata.uid() != 0 || metadata.mode() & 0o077 != 0 => problems.push(format!(
+                "preapproval_key: {} has to be owned by root and inaccessible to anyone else (chown root {0} && chmod 600 {0})",
+                key.display(),
+            )),
+
+            Ok(_) => {},
+        }
+    }
+
+    if let Some(path) = options.pair_rules.as_ref() {
+        if let Err(e) = Rules::load(path) {
+            problems.push(format!(
+                "pair_rules: {}: {}; every session will require a pair until it's fixed",
+                path.display(),
+                e,
+            ));
+        }
+    }
+
+    if let Some(path) = options.wait_hints.as_ref() {
+        if let Err(e) = Hints::load(path) {
+            problems.push(format!(
+                "wait_hints: {}: {}; users will only be shown the countdown until it's fixed",
+                path.display(),
+                e,
+            ));
+        }
+    }
+
+    if let Some(path) = options.approver_totp_keys.as_ref() {
+        if let Err(e) = TotpKeys::load(path) {
+            problems.push(format!(
+                "approver_totp_keys: {}: {}; no session can be approved until it's fixed",
+                path.display(),
+                e,
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Checks that `path` is a directory owned by root, and that nobody
+/// else has any of the permissions in `forbidden`. A directory that
+/// doesn't exist is only a problem if it's `required`.
+fn check_directory(problems: &mut Vec<String>, key: &str, path: &Path, forbidden: u32, required: bool) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+
+        Err(_) if !required => return,
+
+        Err(e) => {
+            problems.push(format!(
+                "{}: {} can't be used ({}); create it as a directory owned by root (mkdir -m 755 {1})",
+                key,
+                path.display(),
+                e,
+            ));
+
+            return;
+        },
+    };
+
+    if !metadata.is_dir() {
+        problems.push(format!("{}: {} isn't a directory", key, path.display()));
+    } else if metadata.uid() != 0 {
+        problems.push(format!("{}: {} has to be owned by root (chown root {1})", key, path.display()));
+    } else if metadata.mode() & forbidden != 0 {
+        problems.push(format!(
+            "{}: {} is writable by users other than root (chmod {:o} {1})",
+            key,
+            path.display(),
+            metadata.mode() & 0o7777 & !forbidden,
+        ));
+    }
+}
+
+/// Reports every recording in `dir` left incomplete by a session that
+/// never finished, completing each first if asked to `recover` them.
+fn check_recordings(problems: &mut Vec<String>, dir: &Path, recover: bool) {
+    let incomplete = match recovery::find(dir) {
+        Ok(incomplete) => incomplete,
+        Err(e)         => {
+            problems.push(format!(
+                "recording_dir: couldn't look for incomplete recordings in {} ({})",
+                dir.display(),
+                e,
+            ));
+
+            return;
+        },
+    };
+
+    for recording in incomplete {
+        let path = recording.recording.display();
+
+        problems.push(match recover {
+            false => format!(
+                "recording_dir: {} was left incomplete by a session that never finished (complete it with --recover)",
+                path,
+            ),
+
+            true => match recording.recover() {
+                Ok(())  => format!(
+                    "recording_dir: {} was left incomplete by a session that never finished, and has been completed",
+                    path,
+                ),
+
+                Err(e) => format!(
+                    "recording_dir: {} was left incomplete by a session that never finished, and couldn't be completed ({})",
+                    path,
+                    e,
+                ),
+            },
+        });
+    }
+}
+
+/// Checks that `path` is an executable file. Anything run as root also
+/// has to be owned by root and writable by nobody else.
+fn check_executable(problems: &mut Vec<String>, key: &str, path: &Path, as_root: bool) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e)       => {
+            problems.push(format!("{}: {} can't be used ({})", key, path.display(), e));
+            return;
+        },
+    };
+
+    if !metadata.is_file() || metadata.mode() & 0o111 == 0 {
+        problems.push(format!("{}: {} isn't an executable file (chmod +x {1})", key, path.display()));
+    } else if as_root && (metadata.uid() != 0 || metadata.mode() & 0o022 != 0) {
+        problems.push(format!(
+            "{}: {} is run as root, so it has to be owned by root and writable by nobody else (chown root {1} && chmod go-w {1})",
+            key,
+            path.display(),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sudo_pair::{ownership, recording, scratch};
+
+    use sudo_pair_types::manifest::{Manifest, Outcome};
+
+    use std::os::unix::fs::PermissionsExt;
+
+    fn check_line(line: &str) -> Vec<String> {
+        run(vec!["--line".to_string(), line.into()].into_iter()).unwrap()
+    }
+
+    #[test]
+    fn finds_the_plugin_line() {
+        let options = plugin_options(
+            "# Plugin sudo_pair sudo_pair.so commented=out\n\
+             Plugin sudoers_io sudoers.so\n\
+             Plugin sudo_pair sudo_pair.so socket_dir=/tmp/pair verbose\n"
+        );
+
+        assert_eq!(Some(vec!["socket_dir=/tmp/pair".into(), "verbose".into()]), options);
+        assert_eq!(None, plugin_options("Plugin sudoers_io sudoers.so\n"));
+    }
+
+    #[test]
+    fn reports_invalid_options() {
+        let problems = check_line("Plugin sudo_pair sudo_pair.so socket_dir=relative no_tty_policy=maybe frobnicate");
+
+        assert_eq!(3, problems.len());
+        assert!(problems.contains(&"no_tty_policy has an invalid value: maybe".into()));
+        assert!(problems.contains(&"socket_dir must be an absolute path, got relative".into()));
+        assert!(problems.contains(&"unknown option frobnicate".into()));
+
+        assert!(run(vec!["--line".to_string(), "Plugin sudoers_io sudoers.so".into()].into_iter()).is_err());
+    }
+
+    #[test]
+    fn checks_the_plugins_footprint() {
+        let dir    = scratch::dir("check", "footprint", true);
+        let plugin = dir.join("libsudo_pair.so");
+
+        fs::write(&plugin, b"not a shared library").unwrap();
+
+        let footprint = |size: &str| run(vec![
+            "--footprint".to_string(), plugin.display().to_string(),
+            "--max-size".into(), size.into(),
+        ].into_iter());
+
+        assert!(footprint("3MiB").unwrap_err().contains("isn't a 64-bit, little-endian ELF shared library"));
+        assert_eq!(Err("--max-size: lots isn't a size (e.g., 4MiB)".into()), footprint("lots"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn describes_every_option() {
+        let options = schema::options();
+
+        assert!(options.len() > 50);
+
+        // every option described is one the plugin accepts
+        for option in &options {
+            let problems = match parse(&[format!("{}=", option.name)]).unwrap() {
+                Ok(_)         => Vec::new(),
+                Err(problems) => problems,
+            };
+
+            assert!(!problems.contains(&format!("unknown option {}", option.name)), "{:?}", option);
+            assert!(!option.description.is_empty(), "{:?}", option);
+        }
+    }
+
+    #[test]
+    fn checks_directories() {
+        let dir     = scratch::dir("check", "dirs", true);
+        let sockets = dir.join("sockets");
+        let binary  = dir.join("sudo_approve");
+
+        fs::create_dir(&sockets).unwrap();
+        fs::set_permissions(&sockets, fs::Permissions::from_mode(0o777)).unwrap();
+
+        fs::write(&binary, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let line = format!(
+            "Plugin sudo_pair sudo_pair.so binary_path={} socket_dir={}",
+            binary.display(),
+            sockets.display(),
+        );
+
+        // tests are run as root, so everything they create is owned by
+        // root
+        assert_eq!(
+            vec![format!("socket_dir: {} is writable by users other than root (chmod 755 {0})", sockets.display())],
+            check_line(&line),
+        );
+
+        fs::set_permissions(&sockets, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(Vec::<String>::new(), check_line(&line));
+
+        fs::remove_dir(&sockets).unwrap();
+        assert!(check_line(&line)[0].starts_with(&format!("socket_dir: {} can't be used", sockets.display())));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn checks_files_run_as_root() {
+        let dir  = scratch::dir("check", "files", true);
+        let hook = dir.join("hook");
+        let key  = dir.join("key");
+
+        fs::write(&hook, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o777)).unwrap();
+
+        fs::write(&key, "secret").unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let options = parse(&[
+            format!("close_hook={}", hook.display()),
+            format!("preapproval_key={}", key.display()),
+            format!("pair_rules={}", dir.join("missing").display()),
+            format!("approver_totp_keys={}", key.display()),
+        ]).unwrap().unwrap();
+
+        let problems = check(&options, false);
+
+        assert!(problems.iter().any(|p| p.starts_with("close_hook: ") && p.contains("writable by nobody else")));
+        assert!(problems.iter().any(|p| p.starts_with("preapproval_key: ") && p.contains("chmod 600")));
+        assert!(problems.iter().any(|p| p.starts_with("pair_rules: ")));
+        assert!(problems.iter().any(|p| p.starts_with("approver_totp_keys: ")));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn recovers_incomplete_recordings() {
+        let dir       = scratch::dir("check", "recordings", true);
+        let recording = dir.join("1-2.rec");
+        let marker    = dir.join("1-2.pending");
+
+        let manifest = Manifest {
+            outcome: Outcome::Incomplete,
+            ..Manifest::example()
+        };
+
+        fs::write(&recording, recording::HEADER).unwrap();
+        fs::write(&marker, manifest.to_json()).unwrap();
+
+        let line = format!("Plugin sudo_pair sudo_pair.so recording_sink=file recording_dir={}", dir.display());
+
+        // only the recordings are of interest, not the paths the rest
+        // of the options default to
+        let recordings = |args: Vec<String>| -> Vec<String> {
+            run(args.into_iter()).unwrap().into_iter()
+                .filter(|problem| problem.starts_with("recording_dir: "))
+                .collect()
+        };
+
+        assert_eq!(
+            vec![format!(
+                "recording_dir: {} was left incomplete by a session that never finished (complete it with --recover)",
+                recording.display(),
+            )],
+            recordings(vec!["--line".into(), line.clone()]),
+        );
+
+        assert!(marker.exists());
+
+        let problems = recordings(vec!["--recover".into(), "--line".into(), line.clone()]);
+
+        assert_eq!(1, problems.len());
+        assert!(problems[0].ends_with("and has been completed"));
+
+        assert!(!marker.exists());
+        assert!(String::from_utf8_lossy(&fs::read(&recording).unwrap()).contains("\"incomplete\":true"));
+        assert_eq!(Vec::<String>::new(), recordings(vec!["--line".into(), line]));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn finds_indexed_sessions() {
+        let dir   = scratch::dir("check", "index", true);
+        let index = dir.join("index.jsonl");
+        let entry = index::Entry::new(&Manifest::example(), "1600000000-31337.rec".into());
+
+        index::append(&index, ownership::Ownership::default(), &entry).unwrap();
+
+        let find = |id: &str| run(vec![
+            "--find-session".into(), id.into(),
+            "--line".into(),         format!("Plugin sudo_pair sudo_pair.so session_index={}", index.display()),
+        ].into_iter());
+
+        assert_eq!(Ok(Vec::new()), find("1600000000-31337"));
+        assert_eq!(
+            Ok(vec![format!("session_index: {} has no entry for session 1-1", index.display())]),
+            find("1-1"),
+        );
+
+        assert!(run(vec!["--find-session".into(), "1-1".into(), "--line".into(), "Plugin sudo_pair sudo_pair.so".into()].into_iter()).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn decides_captured_invocations() {
+        let dir     = scratch::dir("check", "decide", true);
+        let capture = dir.join("psql.capture");
+
+        fs::write(&capture, &include_bytes!("../../fixtures/sudo-1.9.5.capture")[..]).unwrap();
+        fs::write(dir.join("broken.capture"), "not a capture").unwrap();
+        fs::write(dir.join("notes.txt"), "not a capture either").unwrap();
+
+        let decided = |line: &str| decide(&parse(&[line.into()]).unwrap().unwrap(), None, &capture)
+            .unwrap()
+            .to_string();
+
+        // psql is run as postgres, whose group isn't enforced by default
+        assert_eq!("exempt (unenforced_group)", decided("socket_dir=/var/run/sudo_pair"));
+        assert_eq!("require 1 approver, full",  decided("gids_enforced=109"));
+        assert_eq!("exempt (exempt_network)",   decided("networks_exempted=10.0.0.0/8"));
+        assert_eq!("exempt (exempt_group)",     decided("gids_exempted=27"));
+
+        let problems = run(vec![
+            "--decide".into(), dir.display().to_string(),
+            "--line".into(),   "Plugin sudo_pair sudo_pair.so".into(),
+        ].into_iter()).unwrap();
+
+        assert_eq!(
+            vec![format!("{}: line 1: entry outside of any section", dir.join("broken.capture").display())],
+            problems,
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Renders the configured `sudo_pair` prompts with sample values, so
+//! they can be previewed without performing a real `sudo` invocation.
+//!
+//! The prompt locations are read from the `sudo_pair` line of
+//! `/etc/sudo.conf` (or `--sudo-conf`), and can be overridden with
+//! `--user-prompt` and `--pair-prompt`. Any expansion can be given a
+//! value of its own with an argument like `U=alice`.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{status, template};
+
+use status::Report;
+use template::{
+    Spec,
+    DEFAULT_PAIR_PROMPT,
+    DEFAULT_PAIR_PROMPT_PATH,
+    DEFAULT_USER_PROMPT,
+    DEFAULT_USER_PROMPT_PATH,
+    MAX_PROMPT_LEN,
+};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_SUDO_CONF   : &str = "/etc/sudo.conf";
+const DEFAULT_BINARY_PATH : &str = "/usr/bin/sudo_approve";
+
+const USAGE : &str = "\
+usage: sudo_pair_prompt_test [options] [X=value ...]
+
+Renders the configured sudo_pair prompts with sample values. Each X=value
+argument replaces the sample value of the %X expansion. The exit status is
+2 if they couldn't be rendered.
+
+options:
+    --sudo-conf PATH     read plugin options from PATH [/etc/sudo.conf]
+    --user-prompt PATH   render the user prompt template at PATH
+    --pair-prompt PATH   render the pair prompt template at PATH
+    --json               print the rendered prompts (or error) as a JSON
+                         object
+    -h, --help           print this message
+";
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let json     = status::json_requested(&mut args);
+
+    // with `--json`, the rendered prompts are only collected, to be
+    // reported as `output`
+    let stdout     = io::stdout();
+    let mut output = Vec::new();
+
+    let rendered = if json {
+        run(args.into_iter(), &mut output)
+    } else {
+        run(args.into_iter(), &mut stdout.lock())
+    };
+
+    let report = match rendered {
+        Ok(()) => Report::success(),
+        Err(e) => Report::error(&e),
+    };
+
+    report
+        .string("output", &String::from_utf8_lossy(&output))
+        .exit("sudo_pair_prompt_test", json)
+}
+
+fn run<I: Iterator<Item = String>>(mut args: I, out: &mut dyn Write) -> Result<(), String> {
+    let mut sudo_conf   = PathBuf::from(DEFAULT_SUDO_CONF);
+    let mut user_prompt = None;
+    let mut pair_prompt = None;
+    let mut overrides   = HashMap::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next()
+            .map(PathBuf::from)
+            .ok_or_else(|| format!("{} requires a path", flag));
+
+        match arg.as_str() {
+            "--sudo-conf"   => sudo_conf   = value(&arg)?,
+            "--user-prompt" => user_prompt = Some(value(&arg)?),
+            "--pair-prompt" => pair_prompt = Some(value(&arg)?),
+            "-h" | "--help" => {
+                return write!(out, "{}", USAGE).map_err(|e| e.to_string());
+            },
+            _ => {
+                let (key, val) = parse_override(&arg)
+                    .ok_or_else(|| format!("unrecognized argument {}\n\n{}", arg, USAGE))?;
+
+                let _ = overrides.insert(key, val.into_bytes());
+            },
+        }
+    }
+
+    // a missing sudo.conf is fine; everything just takes its default
+    let options = match fs::read_to_string(&sudo_conf) {
+        Ok(conf) => plugin_options(&conf),
+        Err(_)   => HashMap::new(),
+    };
+
+    let configured = |key: &str, default: &str| options.get(key)
+        .map_or_else(|| PathBuf::from(default), PathBuf::from);
+
+    let user_prompt = user_prompt.unwrap_or_else(|| configured("user_prompt_path", DEFAULT_USER_PROMPT_PATH));
+    let pair_prompt = pair_prompt.unwrap_or_else(|| configured("pair_prompt_path", DEFAULT_PAIR_PROMPT_PATH));
+    let binary_path = configured("binary_path", DEFAULT_BINARY_PATH);
+
+    let mut expansions = sample_expansions(&binary_path);
+
+    expansions.extend(overrides);
+
+    let spec = Spec::from(expansions);
+
+    render(out, &spec, "user", &user_prompt, DEFAULT_USER_PROMPT)
+        .and_then(|_| render(out, &spec, "pair", &pair_prompt, DEFAULT_PAIR_PROMPT))
+        .map_err(|e| e.to_string())
+}
+
+/// Writes `name`d template at `path`, expanded by `spec`, to `out`,
+/// preceded by a header saying where the template came from.
+fn render(
+    out:      &mut dyn Write,
+    spec:     &Spec,
+    name:     &str,
+    path:     &Path,
+    fallback: &[u8],
+) -> io::Result<()> {
+    let mut rendered = Vec::new();
+
+    let truncated = match fs::File::open(path).and_then(|file| spec.render(file, &mut rendered, MAX_PROMPT_LEN)) {
+        Ok(truncated) => {
+            writeln!(out, "==> {} prompt ({})", name, path.display())?;
+            truncated
+        },
+
+        Err(e) => {
+            writeln!(out, "==> {} prompt (built-in default; {}: {})", name, path.display(), e)?;
+
+            rendered.clear();
+            spec.render(fallback, &mut rendered, MAX_PROMPT_LEN)?
+        },
+    };
+
+    out.write_all(&rendered)?;
+
+    // prompts often don't end in a newline, since the user is meant to
+    // type after them
+    if !rendered.ends_with(b"\n") {
+        writeln!(out)?;
+    }
+
+    if truncated {
+        writeln!(out, "(truncated to {} bytes)", MAX_PROMPT_LEN)?;
+    }
+
+    writeln!(out)
+}
+
+/// Parses an argument like `U=alice` into the expansion it replaces and
+/// its new value.
+fn parse_override(arg: &str) -> Option<(u8, String)> {
+    let mut chars = arg.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(key), Some('=')) if key.is_ascii_alphabetic() =>
+            Some((key as u8, chars.as_str().into())),
+        _ => None,
+    }
+}
+
+/// Returns the options provided to the `sudo_pair` plugin in the
+/// contents of a `sudo.conf`.
+fn plugin_options(conf: &str) -> HashMap<String, String> {
+    let line = conf.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|words| words.len() >= 3 && words[0] == "Plugin" && words[1] == "sudo_pair");
+
+    line.into_iter()
+        .flat_map(|words| words.into_iter().skip(3))
+        .map(|option| match option.find('=') {
+            Some(i) => (option[..i].into(), option[i + 1 ..].into()),
+            None    => (option.into(), option.into()),
+        })
+        .collect()
+}
+
+/// Plausible values for every expansion the plugin provides.
+fn sample_expansions(binary_path: &Path) -> HashMap<u8, Vec<u8>> {
+    let binary_name = binary_path.file_name()
+        .map_or_else(|| binary_path.display().to_string(), |name| name.to_string_lossy().into());
+
+    vec![
+        (b'b', binary_name),
+        (b'B', binary_path.display().to_string()),
+        (b'C', "sudo -u postgres psql".into()),
+        (b'd', "/home/alice".into()),
+        (b'e', "109".into()),
+        (b'E', "postgres".into()),
+        (b'F', "".into()),
+        (b'h', "db01.example.com".into()),
+        (b'H', "24".into()),
+        (b'I', "no".into()),
+        (b'L', "pci-scope".into()),
+        (b'g', "1000".into()),
+        (b'G', "alice".into()),
+        (b'p', "31337".into()),
+        (b'P', "session timeout 30m".into()),
+        (b'r', "109".into()),
+        (b'R', "postgres".into()),
+        (b'S', "/var/run/sudo_pair/1000.31337.sock".into()),
+        (b'T', "30m".into()),
+        (b'u', "1000".into()),
+        (b'U', "alice".into()),
+        (b'W', "80".into()),
+        (b'X', "no".into()),
+    ].into_iter().map(|(key, value)| (key, value.into_bytes())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_plugin_options() {
+        let options = plugin_options(
+            "# Plugin sudo_pair sudo_pair.so commented=out\n\
+             Plugin sudoers_io sudoers.so\n\
+             Plugin sudo_pair sudo_pair.so pair_prompt_path=/tmp/pair debug\n"
+        );
+
+        assert_eq!(Some("/tmp/pair"), options.get("pair_prompt_path").map(String::as_str));
+        assert_eq!(Some("debug"),     options.get("debug").map(String::as_str));
+        assert!(!options.contains_key("commented=out"));
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!(Some((b'U', "bob".into())), parse_override("U=bob"));
+        assert_eq!(Some((b'C', "".into())),    parse_override("C="));
+        assert_eq!(None,                       parse_override("UU=bob"));
+        assert_eq!(None,                       parse_override("--verbose"));
+    }
+
+    #[test]
+    fn renders_default_prompts() {
+        let spec = Spec::from(sample_expansions(Path::new(DEFAULT_BINARY_PATH)));
+        let mut out = Vec::new();
+
+        render(&mut out, &spec, "pair", Path::new("/nonexistent"), DEFAULT_PAIR_PROMPT).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("==> pair prompt (built-in default; /nonexistent: "));
+        assert!(out.ends_with("alice@db01.example.com:/home/alice$ sudo -u postgres psql\ny/n? [n]: \n\n"));
+    }
+}
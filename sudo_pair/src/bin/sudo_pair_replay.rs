@@ -0,0 +1,657 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Replays a capture written by the plugin's `debug_capture_dir`
+//! option through the same parsing sudo's vectors go through when the
+//! plugin is opened, so parsing bugs reported from production can be
+//! reproduced (and debugged) offline.
+//!
+//! With `search`, it instead searches the output and manifests of
+//! recorded sessions for a pattern, printing each session that matched
+//! with the lines around each match, so that (e.g.) whoever ran the
+//! command that touched a file can be found without replaying every
+//! recording by hand.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{capture, options, recording, recovery, status};
+
+use capture::{api_version, Capture};
+use options::PluginOptions;
+use recording::Frame;
+use status::Report;
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::{CString, NulError};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use libc::c_char;
+
+use regex_lite::Regex;
+
+use sudo_plugin::{Plugin, PrintFacility};
+
+use sudo_pair_types::humanize;
+
+/// How many lines before and after each match `search` prints, unless
+/// asked for some other number.
+const DEFAULT_CONTEXT : usize = 2;
+
+const USAGE : &str = "\
+usage: sudo_pair_replay CAPTURE
+       sudo_pair_replay search [--context N] [--utc] PATTERN PATH...
+
+Replays a capture written by the sudo_pair debug_capture_dir option through
+the same parsing sudo's settings, user_info, command_info, user_env, and
+plugin options go through when the plugin is opened, and reports what was
+parsed or what failed to be. The exit status is 1 if anything failed, or 2
+if the capture couldn't be replayed at all.
+
+With search, the output and manifests of the recordings at each PATH (or
+the .rec files anywhere under it) are searched for PATTERN, a regular
+expression (prefix it with (?i) to ignore case), and each session that
+matched is printed with when it started (in the local timezone) and how
+long it ran, and the lines around each match. The exit status is 1 if
+nothing matched or a recording couldn't be read.
+
+options:
+    --context N  print N lines before and after each match [2]
+    --utc        print when sessions started in UTC, not the local timezone
+    --json       print what was parsed, and what failed to be (or the
+                 error), as a JSON object
+    -h, --help   print this message
+";
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let json     = status::json_requested(&mut args);
+
+    // with `--json`, what's normally printed is only collected, to be
+    // reported as `output`
+    let stdout     = io::stdout();
+    let mut output = Vec::new();
+
+    let replayed = if json {
+        run(args.into_iter(), &mut output)
+    } else {
+        run(args.into_iter(), &mut stdout.lock())
+    };
+
+    let report = match replayed {
+        Ok(problems) => Report::problems(&problems),
+        Err(e)       => Report::error(&e),
+    };
+
+    report
+        .string("output", &String::from_utf8_lossy(&output))
+        .exit("sudo_pair_replay", json)
+}
+
+/// Replays the capture named by the arguments, describing the results
+/// to `out`, and returning everything that failed to be parsed.
+fn run<I: Iterator<Item = String>>(mut args: I, out: &mut dyn Write) -> Result<Vec<String>, String> {
+    let first = args.next();
+
+    if first.as_deref() == Some("search") {
+        return search(args, out);
+    }
+
+    let path = match (first, args.next()) {
+        (Some(ref arg), None) if arg == "-h" || arg == "--help" => {
+            write!(out, "{}", USAGE).map_err(|e| e.to_string())?;
+            return Ok(Vec::new());
+        },
+
+        (Some(path), None) => path,
+        _                  => return Err(format!("a single capture is required\n\n{}", USAGE)),
+    };
+
+    let file = File::open(&path)
+        .map_err(|e| format!("couldn't read {}: {}", path, e))?;
+
+    let capture = Capture::read_from(BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    replay(&capture, out)
+}
+
+/// Opens a `Plugin` from `capture` exactly as the plugin's `open`
+/// callback does, and parses the plugin's options from it, describing
+/// the results to `out`. Returns everything that failed to be parsed.
+fn replay(capture: &Capture, out: &mut dyn Write) -> Result<Vec<String>, String> {
+    let version = api_version(&capture.version)
+        .ok_or_else(|| format!("invalid version {}", capture.version))?;
+
+    let vector = |entries: &[Vec<u8>]| Vector::new(entries)
+        .map_err(|e| e.to_string());
+
+    let argv           = vector(&capture.argv)?;
+    let settings       = vector(&capture.settings)?;
+    let user_info      = vector(&capture.user_info)?;
+    let command_info   = vector(&capture.command_info)?;
+    let user_env       = vector(&capture.user_env)?;
+    let plugin_options = vector(&capture.plugin_options)?;
+
+    // there's no sudo to print through or converse with, so both are
+    // left unconnected, as they are when sudo doesn't provide them
+    let plugin = unsafe {
+        let (stdout, stderr) = PrintFacility::new(Some("sudo_pair"), None);
+
+        Plugin::new(
+            "sudo_pair".into(),
+            None,
+            version,
+            capture.argv.len() as _,
+            argv.as_ptr(),
+            settings.as_ptr(),
+            user_info.as_ptr(),
+            command_info.as_ptr(),
+            user_env.as_ptr(),
+            plugin_options.as_ptr(),
+            stdout,
+            stderr,
+            None,
+        )
+    };
+
+    let report = |out: &mut dyn Write, line: String| writeln!(out, "{}", line)
+        .map_err(|e| e.to_string());
+
+    let plugin = match plugin {
+        Ok(plugin) => plugin,
+
+        Err(e) => {
+            let problems : Vec<_> = e.iter().map(ToString::to_string).collect();
+
+            report(out, "sudo's vectors couldn't be parsed:".into())?;
+
+            for problem in &problems {
+                report(out, format!("  {}", problem))?;
+            }
+
+            return Ok(problems);
+        },
+    };
+
+    report(out, format!("sudo {} vectors parsed:", plugin.version))?;
+    report(out, format!("  user:         {} (uid {}, gid {}, groups {:?})",
+        plugin.user_info.user,
+        plugin.user_info.uid,
+        plugin.user_info.gid,
+        plugin.user_info.groups,
+    ))?;
+    report(out, format!("  host:         {}", plugin.user_info.host))?;
+    report(out, format!("  umask:        {}", plugin.user_info.umask
+        .map_or_else(|| "unknown".into(), |umask| format!("{:04o}", umask))))?;
+    report(out, format!("  tty:          {:?}", plugin.user_info.tty))?;
+    report(out, format!("  cwd:          {}", plugin.cwd().display()))?;
+    report(out, format!("  command:      {}", plugin.command_info.command.display()))?;
+    report(out, format!("  runas:        euid {}, egid {}, groups {:?}",
+        plugin.command_info.runas_euid,
+        plugin.command_info.runas_egid,
+        plugin.command_info.runas_groups,
+    ))?;
+    report(out, format!("  invocation:   {}", String::from_utf8_lossy(&plugin.invocation())))?;
+    report(out, format!("  arguments:    {}", plugin.invocation_json()))?;
+
+    match PluginOptions::try_from(&plugin.plugin_options) {
+        Ok(_) => {
+            report(out, "plugin options parsed".into())?;
+
+            Ok(Vec::new())
+        },
+
+        Err(problems) => {
+            report(out, "plugin options couldn't be parsed:".into())?;
+
+            for problem in &problems {
+                report(out, format!("  {}", problem))?;
+            }
+
+            Ok(problems)
+        },
+    }
+}
+
+/// Searches the recordings named by the arguments, and those found
+/// under the directories they name, describing each session that
+/// matched to `out`. Returns the recordings that couldn't be searched,
+/// or that nothing matched.
+fn search<I: Iterator<Item = String>>(mut args: I, out: &mut dyn Write) -> Result<Vec<String>, String> {
+    let mut context = DEFAULT_CONTEXT;
+    let mut utc     = false;
+    let mut pattern = None;
+    let mut paths   = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context = args.next()
+                .and_then(|lines| lines.parse().ok())
+                .ok_or_else(|| format!("--context requires a number of lines\n\n{}", USAGE))?,
+
+            "--utc" => utc = true,
+
+            _ if pattern.is_none() => pattern = Some(arg),
+            _                      => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let pattern = pattern
+        .ok_or_else(|| format!("a pattern is required\n\n{}", USAGE))?;
+
+    let pattern = Regex::new(&pattern)
+        .map_err(|e| format!("invalid pattern {}: {}", pattern, e))?;
+
+    if paths.is_empty() {
+        return Err(format!("a recording or directory to search is required\n\n{}", USAGE));
+    }
+
+    let mut problems   = Vec::new();
+    let mut recordings = Vec::new();
+    let mut matched    = false;
+
+    for path in &paths {
+        find_recordings(path, &mut recordings, &mut problems);
+    }
+
+    for path in &recordings {
+        let found = fs::read(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+            .and_then(|contents| search_recording(path, &contents, &pattern, context, utc));
+
+        match found {
+            Ok(Some(lines)) => {
+                for line in lines {
+                    writeln!(out, "{}", line).map_err(|e| e.to_string())?;
+                }
+
+                matched = true;
+            },
+
+            Ok(None)       => (),
+            Err(problem)   => problems.push(problem),
+        }
+    }
+
+    if !matched {
+        problems.push(format!("no recorded session matched {}", pattern));
+    }
+
+    Ok(problems)
+}
+
+/// Adds `path` to `recordings` if it's a file, or every `.rec` file
+/// under it (in order) if it's a directory. Symlinks are only followed
+/// if they're given.
+fn find_recordings(path: &Path, recordings: &mut Vec<PathBuf>, problems: &mut Vec<String>) {
+    if !path.is_dir() {
+        recordings.push(path.to_path_buf());
+        return;
+    }
+
+    let entries = fs::read_dir(path)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>());
+
+    let mut entries = match entries {
+        Ok(entries) => entries,
+        Err(e)      => return problems.push(format!("couldn't read {}: {}", path.display(), e)),
+    };
+
+    entries.sort_by_key(fs::DirEntry::file_name);
+
+    for entry in entries {
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => find_recordings(&entry.path(), recordings, problems),
+
+            Ok(kind) if kind.is_file() && entry.path().extension().is_some_and(|ext| ext == "rec") => {
+                recordings.push(entry.path());
+            },
+
+            _ => (),
+        }
+    }
+}
+
+/// Searches the `contents` of the recording at `path` for `pattern`,
+/// returning the lines describing the session and where it matched, or
+/// `None` if it didn't. Each line of output that matched is printed
+/// with `context` lines before and after it. When the session started
+/// is printed in the local timezone, unless it's asked to be in `utc`.
+fn search_recording(path: &Path, contents: &[u8], pattern: &Regex, context: usize, utc: bool) -> Result<Option<Vec<String>>, String> {
+    let frames = recording::frames(contents)
+        .ok_or_else(|| format!("{} isn't a sudo_pair recording", path.display()))?;
+
+    let streams = [Frame::TtyOut.tag(), Frame::StdOut.tag(), Frame::StdErr.tag()];
+
+    let mut output   = Vec::new();
+    let mut manifest = None;
+
+    for (tag, _, data) in frames {
+        if streams.contains(&tag) {
+            output.extend_from_slice(data);
+        } else if tag == Frame::Manifest.tag() {
+            manifest = Some(String::from_utf8_lossy(data).into_owned());
+        }
+    }
+
+    // the manifest of a recording left incomplete is still in its
+    // marker
+    let manifest = manifest
+        .or_else(|| fs::read_to_string(recovery::marker(path)).ok())
+        .unwrap_or_default();
+
+    let output = String::from_utf8_lossy(&output);
+    let lines  = output.lines().collect::<Vec<_>>();
+
+    let matches = (0 .. lines.len())
+        .filter(|&n| pattern.is_match(lines[n]))
+        .collect::<Vec<_>>();
+
+    if matches.is_empty() && !pattern.is_match(&manifest) {
+        return Ok(None);
+    }
+
+    let field = |key: &str| recovery::value(&manifest, key)
+        .map_or("unknown", |range| &manifest[range]);
+
+    let time = |key: &str| recovery::value(&manifest, key)
+        .and_then(|range| sudo_pair_types::manifest::parse_utc(&manifest[range]));
+
+    let mut header = format!(
+        "{}: session {} ({} as {})",
+        path.display(),
+        field("session_id"),
+        field("user"),
+        field("runas_user"),
+    );
+
+    // the manifest has them in UTC, whatever the timezone of whoever's
+    // searching
+    if let Some(started) = time("started_at") {
+        let offset = if utc { None } else { humanize::utc_offset(started) };
+
+        header.push_str(&format!(", started {}", humanize::local(started, offset)));
+
+        if let Some(ran) = time("ended_at").and_then(|ended| ended.duration_since(started).ok()) {
+            header.push_str(&format!(", ran {}", humanize::duration(ran)));
+        }
+    }
+
+    let mut described = vec![header];
+
+    if pattern.is_match(&manifest) {
+        described.push("  manifest matched".into());
+    }
+
+    let mut printed = None;
+
+    for &n in &matches {
+        let end   = (n + context + 1).min(lines.len());
+        let start = match printed {
+            Some(last) if n.saturating_sub(context) <= last => last + 1,
+            Some(_) => {
+                described.push("  --".into());
+                n.saturating_sub(context)
+            },
+            None => n.saturating_sub(context),
+        };
+
+        for (line, text) in lines.iter().enumerate().take(end).skip(start) {
+            let separator = if matches.contains(&line) { ':' } else { '-' };
+
+            described.push(format!("  {}{} {}", line + 1, separator, printable(text)));
+        }
+
+        printed = Some(end - 1);
+    }
+
+    Ok(Some(described))
+}
+
+/// `line` with its control characters escaped, so that a recorded
+/// session's output can't take over the terminal it's searched from.
+fn printable(line: &str) -> String {
+    line.chars()
+        .map(|c| if c.is_control() && c != '\t' { c.escape_default().to_string() } else { c.to_string() })
+        .collect()
+}
+
+/// A NULL-terminated array of C strings, as sudo provides its vectors.
+#[derive(Debug)]
+struct Vector {
+    // owns what `pointers` point into
+    _strings: Vec<CString>,
+    pointers: Vec<*mut c_char>,
+}
+
+impl Vector {
+    fn new(entries: &[Vec<u8>]) -> Result<Self, NulError> {
+        let strings = entries.iter()
+            .map(|entry| CString::new(entry.as_slice()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // the strings are never written through these pointers; they're
+        // only `mut` because that's how sudo declares them
+        let pointers = strings.iter()
+            .map(|string| string.as_ptr() as *mut c_char)
+            .chain(Some(ptr::null_mut()))
+            .collect();
+
+        Ok(Self { _strings: strings, pointers })
+    }
+
+    fn as_ptr(&self) -> *const *mut c_char {
+        self.pointers.as_ptr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sudo_pair::fixtures;
+
+    use sudo_pair_types::manifest::Manifest;
+
+    fn entries(entries: &[&str]) -> Vec<Vec<u8>> {
+        entries.iter().map(|entry| entry.as_bytes().to_vec()).collect()
+    }
+
+    fn capture() -> Capture {
+        Capture {
+            version:  "1.17".into(),
+            argv:     entries(&["psql", "<redacted>"]),
+            settings: entries(&[
+                "plugin_dir=/usr/libexec/sudo",
+                "plugin_path=sudo_pair.so",
+                "progname=sudo",
+                "runas_user=postgres",
+            ]),
+            user_info: entries(&[
+                "cwd=/home/alice", "egid=1000", "euid=0", "gid=1000",
+                "groups=1000,27", "host=db01", "pgid=31337", "pid=31337",
+                "ppid=31336", "uid=1000", "user=alice",
+            ]),
+            command_info: entries(&[
+                "command=/usr/bin/psql", "runas_euid=109", "runas_egid=109",
+                "runas_groups=109",
+            ]),
+            user_env:       entries(&["PATH=/usr/bin:/bin", "PGPASSWORD=<redacted>"]),
+            plugin_options: entries(&["socket_dir=/var/run/sudo_pair"]),
+        }
+    }
+
+    fn replayed(capture: &Capture) -> (bool, String) {
+        let mut out  = Vec::new();
+        let problems = replay(capture, &mut out).unwrap();
+
+        (problems.is_empty(), String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn replays_captures() {
+        let (parsed, out) = replayed(&capture());
+
+        assert!(parsed, "{}", out);
+        assert!(out.starts_with("sudo 1.17 vectors parsed:\n"));
+        assert!(out.contains("  user:         alice (uid 1000, gid 1000, groups [1000, 27])\n"));
+        assert!(out.contains("  runas:        euid 109, egid 109, groups Some([109])\n"));
+        assert!(out.contains("  invocation:   sudo --user postgres psql '<redacted>'\n"));
+        assert!(out.contains("  arguments:    [\"sudo\",\"--user\",\"postgres\",\"psql\",\"<redacted>\"]\n"));
+        assert!(out.ends_with("plugin options parsed\n"));
+    }
+
+    #[test]
+    fn reports_what_failed_to_parse() {
+        let mut capture = capture();
+        capture.user_info.retain(|entry| !entry.starts_with(b"uid="));
+
+        let (parsed, out) = replayed(&capture);
+
+        assert!(!parsed);
+        assert!(out.starts_with("sudo's vectors couldn't be parsed:\n"));
+        assert!(out.contains("uid"), "{}", out);
+
+        let mut capture = self::capture();
+        capture.plugin_options.push(b"frobnicate".to_vec());
+
+        let (parsed, out) = replayed(&capture);
+
+        assert!(!parsed);
+        assert!(out.ends_with("plugin options couldn't be parsed:\n  unknown option frobnicate\n"));
+        assert_eq!(vec!["unknown option frobnicate".to_string()], replay(&capture, &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn replays_every_fixture() {
+        for fixture in fixtures::FIXTURES {
+            let (parsed, out) = replayed(&fixture.capture());
+
+            assert!(parsed, "{}", out);
+            assert!(out.contains(&format!("  invocation:   {}\n", fixture.invocation)), "{}", out);
+
+            // sudo 1.8.16 doesn't provide the umask, but it's filled in
+            assert!(!out.contains("  umask:        unknown\n"), "{}", out);
+        }
+    }
+
+    #[test]
+    fn searches_recordings() {
+        let dir = env::temp_dir().join(format!("sudo_pair-replay-search-{}", std::process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("00/00")).unwrap();
+
+        let recording = |frames: &[(Frame, &[u8])]| -> Vec<u8> {
+            let mut recording = recording::HEADER.to_vec();
+
+            for (frame, data) in frames {
+                recording.extend(recording::encode(*frame, 0, data));
+            }
+
+            recording
+        };
+
+        let manifest = Manifest::example().to_json();
+
+        fs::write(dir.join("00/00/01.rec"), recording(&[
+            (Frame::TtyOut,   b"$ ls /etc\r\nhosts\r\n"),
+            (Frame::Winsize,  &[0; 8]),
+            (Frame::TtyOut,   b"passwd\r\nshadow\r\n\x1b]0;pwned\x07\r\n$ rm /etc/shadow\r\n"),
+            (Frame::Manifest, manifest.as_bytes()),
+        ])).unwrap();
+
+        fs::write(dir.join("00/00/02.rec"), recording(&[(Frame::StdOut, b"nothing to see\n")])).unwrap();
+        fs::write(dir.join("notes.txt"),    b"rm /etc/shadow").unwrap();
+
+        let search = |args: &[&str]| {
+            let mut out  = Vec::new();
+            let problems = run(
+                Some("search".to_string()).into_iter().chain(args.iter().map(|arg| arg.to_string())),
+                &mut out,
+            ).unwrap();
+
+            (problems, String::from_utf8(out).unwrap())
+        };
+
+        let (problems, out) = search(&["--context", "1", "--utc", "rm /etc", dir.to_str().unwrap()]);
+
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(
+            format!(
+                "{}: session 1600000000-31337 (alice as postgres), \
+                 started 2020-09-13 12:26:40 UTC, ran 1 minute 1 second\n  \
+                   5- \\u{{1b}}]0;pwned\\u{{7}}\n  \
+                   6: $ rm /etc/shadow\n",
+                dir.join("00/00/01.rec").display(),
+            ),
+            out,
+        );
+
+        let (_, out) = search(&["--context", "0", "shadow|hosts", dir.to_str().unwrap()]);
+
+        assert!(out.ends_with("  2: hosts\n  --\n  4: shadow\n  --\n  6: $ rm /etc/shadow\n"), "{}", out);
+
+        // the manifest is searched too
+        let (problems, out) = search(&["postgres", dir.to_str().unwrap()]);
+
+        assert!(problems.is_empty());
+        assert!(out.contains("  manifest matched\n"), "{}", out);
+
+        let (problems, out) = search(&["nowhere", dir.to_str().unwrap(), dir.join("notes.txt").to_str().unwrap()]);
+
+        assert!(out.is_empty());
+        assert_eq!(
+            vec![
+                format!("{} isn't a sudo_pair recording", dir.join("notes.txt").display()),
+                "no recorded session matched nowhere".into(),
+            ],
+            problems,
+        );
+
+        assert!(run(vec!["search".to_string()].into_iter(), &mut Vec::new()).is_err());
+        assert!(run(vec!["search".to_string(), "(".into(), "/".into()].into_iter(), &mut Vec::new()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        assert!(replay(&Capture { version: "1.x".into(), ..capture() }, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,773 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Simulates a paired session without `sudo` or root, for demos,
+//! training, and CI. The approver is prompted, and the approved
+//! command's output streamed to them, by the same code the plugin uses;
+//! only what `sudo` itself would do (running the command in a pty and
+//! calling back with its output) is done here instead.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{clock, deadline, display, errors, options, protocol, session, signals, socket, template, transcript};
+
+use clock::SystemClock;
+use deadline::Deadline;
+use display::Display;
+use errors::ErrorKind;
+use options::PluginOptions;
+use protocol::{Hello, LIMITS};
+use session::{before_deadline, AwaitingApproval, Session};
+use signals::SignalGuard;
+use socket::{Admission, Socket};
+use template::{Spec, DEFAULT_PAIR_PROMPT, DEFAULT_USER_PROMPT, QUIET_USER_PROMPT};
+use transcript::Transcript;
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::ptr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use libc::{gid_t, uid_t};
+
+use sudo_plugin::OptionMap;
+
+use sudo_pair_types::protocol::{Capabilities, Encoding, PROTOCOL_VERSION};
+
+const USAGE : &str = "\
+usage: sudo_pair_sim [options] [key=value ...] [-- command [args ...]]
+
+Simulates a paired session without sudo or root. An approver connects to
+the session's socket (e.g., with `socat STDIO unix-connect:PATH`) as the
+same user, and is prompted exactly as the plugin would prompt them. Once
+they approve, the command (by default, $SHELL) is run in a pty and its
+output is streamed to them. Each key=value argument is a sudo_pair option,
+as it would appear in sudo.conf (e.g., approval_timeout=1m).
+
+Re-approval and handoffs aren't simulated, and nothing is recorded.
+
+options:
+    --socket-dir DIR   create the session's socket in DIR [a new temporary
+                       directory]
+    -h, --help         print this message
+";
+
+fn main() {
+    match run(env::args().skip(1)) {
+        Ok(status) => process::exit(status),
+
+        Err(e) => {
+            eprintln!("sudo_pair_sim: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+/// Runs the simulation the arguments describe, returning the command's
+/// exit status.
+fn run<I: Iterator<Item = String>>(mut args: I) -> Result<i32, String> {
+    let mut socket_dir = None;
+    let mut raw        = Vec::new();
+    let mut command    = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket-dir" => socket_dir = Some(PathBuf::from(
+                args.next().ok_or("--socket-dir requires a path")?
+            )),
+
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(0);
+            },
+
+            "--" => {
+                command.extend(args.by_ref());
+            },
+
+            _ if arg.contains('=') => raw.push(arg),
+
+            _ => return Err(format!("unrecognized argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let options = parse(&raw)?
+        .map_err(|problems| problems.join("\n"))?;
+
+    if command.is_empty() {
+        command.push(env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into()));
+    }
+
+    let socket_dir = match socket_dir {
+        Some(dir) => SocketDir { path: dir, temporary: false },
+        None      => SocketDir::create().map_err(|e| e.to_string())?,
+    };
+
+    let stdout = io::stdout();
+    let stderr = io::stderr();
+
+    simulate(
+        &options,
+        &command,
+        &socket_dir.path,
+        libc::STDIN_FILENO,
+        &mut stdout.lock(),
+        &mut stderr.lock(),
+    )
+}
+
+/// Parses `raw` options exactly as sudo hands them to the plugin.
+fn parse(raw: &[String]) -> Result<Result<PluginOptions, Vec<String>>, String> {
+    let options = raw.iter()
+        .map(|option| CString::new(option.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let pointers : Vec<_> = options.iter()
+        .map(|option| option.as_ptr())
+        .chain(Some(ptr::null()))
+        .collect();
+
+    // the array is terminated by a NULL pointer, and every string is
+    // kept alive by `options` for as long as the map is built
+    let map = unsafe { OptionMap::from_raw(pointers.as_ptr()) };
+
+    Ok(PluginOptions::try_from(&map))
+}
+
+/// Runs a paired session of `command`, whose socket is created in
+/// `socket_dir`. The user types into `input` and sees the command's
+/// output on `output`, and is told what's going on through `notices`.
+/// Returns the command's exit status.
+fn simulate(
+    options:    &PluginOptions,
+    command:    &[String],
+    socket_dir: &Path,
+    input:      RawFd,
+    output:     &mut dyn Write,
+    notices:    &mut dyn Write,
+) -> Result<i32, String> {
+    let uid  = unsafe { libc::getuid() };
+    let gid  = unsafe { libc::getgid() };
+    let pid  = process::id();
+    let path = socket_dir.join(format!("{}.{}.sock", uid, pid));
+    let slog = slog::Logger::root(slog::Discard, slog::o!());
+
+    let (rows, cols) = winsize(input).unwrap_or((24, 80));
+
+    let spec = template_spec(options, command, &path, uid, gid, (rows, cols));
+
+    let prompt = if options.quiet {
+        spec.expand(QUIET_USER_PROMPT)
+    } else {
+        spec.render_file(&options.user_prompt_path, DEFAULT_USER_PROMPT)
+    };
+
+    let _ = notices.write_all(&prompt);
+    let _ = writeln!(notices, "(simulated: approve with `socat STDIO unix-connect:{}`)", path.display());
+
+    let signals = SignalGuard::install()
+        .map_err(|e| e.to_string())?;
+
+    let deadline = Some(options.approval_timeout)
+        .filter(|timeout| *timeout > Duration::from_secs(0))
+        .map(|timeout| Deadline::after_on(&SystemClock, timeout));
+
+    let socket = Socket::open(
+        &path,
+        uid,
+        gid,
+        0o700,
+        &Admission {
+            backlog:     options.socket_backlog,
+            max_pending: options.max_pending_connections,
+            uids:        None,
+            groups:      &|_| None,
+            banned:      None,
+            dropped:     &|_, _| {},
+        },
+        signals.fd(),
+        deadline.map(|deadline| deadline.instant()),
+    );
+
+    let mut awaiting = AwaitingApproval::new();
+    let result       = before_deadline(socket, ErrorKind::CommunicationError)
+        .map(|socket| awaiting.connect(socket));
+
+    let transcript = Transcript::new(options.transcript_digest_interval, Arc::new(SystemClock));
+
+    let mut capabilities = Capabilities::WINSIZE;
+    capabilities.insert(Capabilities::COMMENTS);
+    capabilities.insert(Capabilities::MARKERS);
+
+    if transcript.is_streamed() {
+        capabilities.insert(Capabilities::DIGESTS);
+    }
+
+    let hello = Hello {
+        version:    PROTOCOL_VERSION,
+        capabilities,
+        deadline:   deadline.map(|deadline| deadline.unix_secs()),
+        encoding:   Encoding::Text,
+        utc_offset: None,
+        winsize:    None,
+        limits:     LIMITS,
+    };
+
+    let mut prompt = hello.encode();
+
+    if !options.host_tags.is_empty() {
+        prompt.extend_from_slice(&protocol::tags(&options.host_tags));
+    }
+
+    prompt.extend_from_slice(&spec.render_file(&options.pair_prompt_path, DEFAULT_PAIR_PROMPT));
+
+    let answer = result.and_then(|_| awaiting.prompt(
+        &slog,
+        &prompt,
+        hello,
+        deadline.map(|deadline| deadline.instant()),
+    ));
+
+    if signals.caught().is_some() {
+        // the approver may already be connected, so let them know what
+        // happened
+        awaiting.decline(b"\nsession cancelled by the user\n");
+
+        return Err(ErrorKind::Cancelled.to_string());
+    }
+
+    drop(signals);
+
+    let answer = answer.map_err(|e| e.to_string())?;
+
+    if let Some(comment) = answer.comment.as_ref() {
+        let verdict = if answer.approved { "approved" } else { "declined" };
+
+        let _ = writeln!(notices, "session {}: {}", verdict, comment);
+    }
+
+    if !answer.approved {
+        return Err(ErrorKind::SessionDeclined.to_string());
+    }
+
+    let mut active = awaiting.approve()
+        .ok_or_else(|| ErrorKind::CommunicationError.to_string())?;
+
+    // the approver's terminal is unlikely to match the user's, so
+    // start them off with the size the session began with
+    active.send_winsize(rows.into(), cols.into())
+        .map_err(|e| e.to_string())?;
+
+    active.expires = Some(options.soft_max_session_duration)
+        .filter(|limit| *limit > Duration::from_secs(0))
+        .map(|limit| Instant::now() + limit);
+
+    let (mut master, mut child) = spawn(command, rows, cols)
+        .map_err(|e| format!("couldn't run {}: {}", command[0], e))?;
+
+    let raw_mode    = RawMode::enable(input);
+    let mut display = Display::new(
+        options.sanitize_escapes.clone(),
+        options.utf8_chunking,
+        options.binary_output,
+    );
+
+    let mut stream = Stream {
+        transcript,
+        slog,
+        display: &mut display,
+    };
+
+    let ended = stream.relay(&mut active, input, &mut master, output);
+
+    drop(raw_mode);
+
+    let mut session = match ended {
+        None       => Session::Active(active),
+        Some(kind) => {
+            unsafe { let _ = libc::kill(child.id() as libc::pid_t, libc::SIGHUP); }
+
+            Session::Terminated(active.terminate(kind))
+        },
+    };
+
+    stream.close(&mut session);
+
+    let status = wait(&mut child).map_err(|e| e.to_string())?;
+
+    match ended {
+        Some(kind) => Err(kind.to_string()),
+        None       => Ok(status),
+    }
+}
+
+/// The output of the session as it's streamed to the approver.
+#[derive(Debug)]
+struct Stream<'a> {
+    transcript: Transcript,
+    slog:       slog::Logger,
+    display:    &'a mut Display,
+}
+
+impl Stream<'_> {
+    /// Relays the user's input to the command, and the command's output
+    /// to both the user and the approver, until the command is done.
+    /// Returns why the session ended early, if it did.
+    fn relay(
+        &mut self,
+        active: &mut session::Active,
+        input:  RawFd,
+        master: &mut File,
+        output: &mut dyn Write,
+    ) -> Option<ErrorKind> {
+        let mut buffer = [0; 8192];
+        let mut input  = Some(input);
+
+        loop {
+            // sudo only checks a session's limits when it calls back,
+            // but nothing here would wake up to do so without a timeout
+            let timeout = active.expires.map_or(-1, |expires| {
+                let remaining = expires.saturating_duration_since(Instant::now());
+
+                remaining.as_millis().min(libc::c_int::MAX as _) as libc::c_int
+            });
+
+            let mut fds = [
+                libc::pollfd { fd: input.unwrap_or(-1),  events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: master.as_raw_fd(),   events: libc::POLLIN, revents: 0 },
+            ];
+
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) };
+
+            if ready == -1 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return Some(ErrorKind::CommunicationError);
+            }
+
+            if matches!(active.expires, Some(expires) if Instant::now() >= expires) {
+                let _ = active.send_output(
+                    &mut self.transcript,
+                    &self.slog,
+                    b"\nsession ended: it exceeded its maximum duration\n",
+                );
+
+                return Some(ErrorKind::SessionExpired);
+            }
+
+            if fds[0].revents != 0 {
+                let read = unsafe {
+                    libc::read(fds[0].fd, buffer.as_mut_ptr() as _, buffer.len())
+                };
+
+                if read <= 0 {
+                    input = None;
+                } else if master.write_all(&buffer[..read as usize]).is_err() {
+                    return None;
+                }
+            }
+
+            if fds[1].revents != 0 {
+                // once the command (and everything it started) is gone,
+                // reads fail with `EIO` rather than returning nothing
+                let read = match master.read(&mut buffer) {
+                    Ok(0) | Err(_) => return None,
+                    Ok(read)       => read,
+                };
+
+                let _ = output.write_all(&buffer[..read]);
+                let _ = output.flush();
+
+                let shown = self.display.process(&buffer[..read]);
+
+                if let Err(e) = active.send_output(&mut self.transcript, &self.slog, &shown) {
+                    return Some(e.kind());
+                }
+
+                for suppression in self.display.take_suppressed() {
+                    if let Err(e) = active.send_suppressed(&suppression) {
+                        return Some(e.kind());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the approver whatever's left of the session, along with a
+    /// final checkpoint, and disconnects them.
+    fn close(&mut self, session: &mut Session) {
+        if let Some(active) = session.watched() {
+            let output = self.display.flush();
+            let _      = active.send_output(&mut self.transcript, &self.slog, &output);
+
+            if self.transcript.is_streamed() && active.protocol().streams(Capabilities::DIGESTS) {
+                let _ = active.socket().write_all(&self.transcript.checkpoint().to_escape_sequence());
+            }
+        }
+
+        session.close();
+    }
+}
+
+/// Runs `command` on a new pty sized `rows` by `cols`, returning the
+/// pty's master side and the running command.
+fn spawn(command: &[String], rows: u16, cols: u16) -> io::Result<(File, Child)> {
+    let master = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        File::from_raw_fd(fd)
+    };
+
+    let name = unsafe {
+        if libc::grantpt(master.as_raw_fd()) == -1 || libc::unlockpt(master.as_raw_fd()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let name = libc::ptsname(master.as_raw_fd());
+
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        CStr::from_ptr(name).to_owned()
+    };
+
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(OsStr::from_bytes(name.to_bytes()))?;
+
+    let winsize = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+
+    unsafe {
+        let _ = libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &winsize);
+    }
+
+    // the command has to be dropped once it's spawned, so that the
+    // master sees the pty close when the child exits
+    let child = {
+        let mut child = Command::new(&command[0]);
+
+        let _ = child
+            .args(&command[1..])
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+
+        unsafe {
+            let _ = child.pre_exec(|| {
+                // the pty becomes the controlling terminal of a new
+                // session, so job control and Ctrl-C work as under sudo
+                if libc::setsid() == -1 || libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+        }
+
+        child.spawn()?
+    };
+
+    Ok((master, child))
+}
+
+/// Waits for `child` to exit, returning its exit status as a shell
+/// would report it.
+fn wait(child: &mut Child) -> io::Result<i32> {
+    let status = child.wait()?;
+
+    Ok(status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+}
+
+/// The size of the terminal `fd` refers to, if it's a terminal.
+fn winsize(fd: RawFd) -> Option<(u16, u16)> {
+    let mut winsize : libc::winsize = unsafe { mem::zeroed() };
+
+    match unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) } {
+        -1 => None,
+        _  => Some((winsize.ws_row, winsize.ws_col)),
+    }
+}
+
+/// The expansions for prompt templates, as the plugin would provide
+/// them were `command` run under `sudo` by the current user.
+fn template_spec(
+    options: &PluginOptions,
+    command: &[String],
+    socket:  &Path,
+    uid:     uid_t,
+    gid:     gid_t,
+    size:    (u16, u16),
+) -> Spec {
+    let mut spec = Spec::with_escape(b'%');
+
+    let cwd = env::current_dir().unwrap_or_default();
+
+    spec.replace(b'b', options.binary_name());
+    spec.replace(b'B', options.binary_path.as_os_str().as_bytes());
+    spec.replace(b'C', format!("sudo {}", command.join(" ")));
+    spec.replace(b'd', cwd.as_os_str().as_bytes());
+    spec.replace(b'e', gid.to_string());
+    spec.replace(b'E', group_name(gid));
+    spec.replace(b'h', hostname());
+    spec.replace(b'H', size.0.to_string());
+    spec.replace(b'g', gid.to_string());
+    spec.replace(b'G', group_name(gid));
+    spec.replace(b'p', process::id().to_string());
+    spec.replace(b'S', socket.as_os_str().as_bytes());
+    spec.replace(b'r', uid.to_string());
+    spec.replace(b'R', user_name(uid));
+    spec.replace(b'u', uid.to_string());
+    spec.replace(b'U', user_name(uid));
+    spec.replace(b'W', size.1.to_string());
+
+    spec
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+
+    match unsafe { libc::gethostname(name.as_mut_ptr() as _, name.len()) } {
+        0 => CStr::from_bytes_until_nul(&name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        _ => "localhost".into(),
+    }
+}
+
+fn user_name(uid: uid_t) -> String {
+    let passwd = unsafe { libc::getpwuid(uid) };
+
+    if passwd.is_null() {
+        return uid.to_string();
+    }
+
+    unsafe { CStr::from_ptr((*passwd).pw_name) }.to_string_lossy().into_owned()
+}
+
+fn group_name(gid: gid_t) -> String {
+    let group = unsafe { libc::getgrgid(gid) };
+
+    if group.is_null() {
+        return gid.to_string();
+    }
+
+    unsafe { CStr::from_ptr((*group).gr_name) }.to_string_lossy().into_owned()
+}
+
+/// The directory the session's socket is created in. Temporary ones
+/// are removed when it's dropped.
+#[derive(Debug)]
+struct SocketDir {
+    path:      PathBuf,
+    temporary: bool,
+}
+
+impl SocketDir {
+    /// Creates a new directory only the current user can use, as the
+    /// plugin insists of the directory its sockets are created in.
+    fn create() -> io::Result<Self> {
+        let path = env::temp_dir().join(format!("sudo_pair_sim.{}", process::id()));
+
+        DirBuilder::new().mode(0o700).create(&path)?;
+
+        Ok(Self { path, temporary: true })
+    }
+}
+
+impl Drop for SocketDir {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Puts the user's terminal in raw mode, so their keystrokes (including
+/// Ctrl-C) reach the command's pty untouched, as they do under sudo.
+/// The terminal is restored when it's dropped.
+#[derive(Debug)]
+struct RawMode {
+    fd:       RawFd,
+    original: Option<libc::termios>,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> Self {
+        let mut termios : libc::termios = unsafe { mem::zeroed() };
+
+        if unsafe { libc::tcgetattr(fd, &mut termios) } == -1 {
+            return Self { fd, original: None };
+        }
+
+        let original = termios;
+
+        unsafe {
+            libc::cfmakeraw(&mut termios);
+            let _ = libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        }
+
+        Self { fd, original: Some(original) }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(original) = self.original.as_ref() {
+            unsafe { let _ = libc::tcsetattr(self.fd, libc::TCSANOW, original); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sudo_pair::scratch;
+
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    /// Connects to the only socket that appears in `dir`, answers its
+    /// prompt with `answer`, and returns everything it was sent.
+    fn approve(dir: PathBuf, answer: &'static [u8]) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            // the socket appears when it's bound, slightly before it's
+            // listened on, so connections are retried until accepted
+            let mut stream = loop {
+                let socket = fs::read_dir(&dir).unwrap()
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .find(|path| path.extension() == Some(OsStr::new("sock")));
+
+                match socket.map(UnixStream::connect) {
+                    Some(Ok(stream)) => break stream,
+                    Some(Err(ref e)) if e.kind() != io::ErrorKind::ConnectionRefused => panic!("{}", e),
+                    _                => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+
+            stream.write_all(answer).unwrap();
+
+            let mut received = String::new();
+            let _            = stream.read_to_string(&mut received).unwrap();
+
+            received
+        })
+    }
+
+    fn simulate_in(dir: &Path, command: &[&str], options: &[&str]) -> (Result<i32, String>, String) {
+        let options : Vec<_> = options.iter().map(|option| option.to_string()).collect();
+        let command : Vec<_> = command.iter().map(|arg| arg.to_string()).collect();
+        let options          = parse(&options).unwrap().unwrap();
+        let input            = File::open("/dev/null").unwrap();
+
+        let mut output  = Vec::new();
+        let mut notices = Vec::new();
+
+        let result = simulate(&options, &command, dir, input.as_raw_fd(), &mut output, &mut notices);
+
+        (result, String::from_utf8_lossy(&output).into_owned())
+    }
+
+    #[test]
+    fn streams_approved_sessions() {
+        let dir      = scratch::dir("sim", "approved", true);
+        let approver = approve(dir.clone(), b"y");
+
+        let (result, output) = simulate_in(
+            &dir,
+            &["sh", "-c", "printf 'hello\\n'; exit 3"],
+            &["pair_prompt_path=/nonexistent", "sanitize_escapes=title"],
+        );
+
+        let received = approver.join().unwrap();
+
+        assert_eq!(Ok(3), result);
+        assert!(output.contains("hello"));
+        assert!(received.contains("y/n? [n]: y\n"));
+        assert!(received.ends_with("hello\r\n"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn runs_nothing_unless_approved() {
+        let dir      = scratch::dir("sim", "declined", true);
+        let ran      = dir.join("ran");
+        let approver = approve(dir.clone(), b"n");
+
+        let touch = format!("touch {}", ran.display());
+
+        let (result, output) = simulate_in(&dir, &["sh", "-c", &touch], &[]);
+
+        let _ = approver.join().unwrap();
+
+        assert_eq!(Err(ErrorKind::SessionDeclined.to_string()), result);
+        assert_eq!("", output);
+        assert!(!ran.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ends_sessions_that_outlive_their_limit() {
+        let dir      = scratch::dir("sim", "expired", true);
+        let approver = approve(dir.clone(), b"y");
+
+        let (result, _) = simulate_in(&dir, &["sleep", "10"], &["soft_max_session_duration=1s"]);
+
+        let received = approver.join().unwrap();
+
+        assert_eq!(Err(ErrorKind::SessionExpired.to_string()), result);
+        assert!(received.ends_with("session ended: it exceeded its maximum duration\n"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,935 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Soak-tests the plugin by running hundreds of paired sessions at once
+//! without `sudo` or root. Each session is approved over its own socket
+//! by a simulated approver, and synthetic output is streamed to them
+//! (and recorded, if the options given enable recording) by the same
+//! code the plugin uses. The latency of approvals and of each write,
+//! and the overall throughput, are reported, and can be saved and
+//! compared against a previous run to catch regressions.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{clock, deadline, display, errors, latency, options, ownership, protocol, recording, session, signals, socket, transcript};
+
+use clock::SystemClock;
+use deadline::Deadline;
+use display::Display;
+use errors::ErrorKind;
+use latency::Samples;
+use options::PluginOptions;
+use ownership::Ownership;
+use protocol::{Hello, LIMITS};
+use recording::{Durability, Frame, Recording};
+use session::{before_deadline, AwaitingApproval, Session};
+use signals::SignalGuard;
+use socket::{Admission, Socket};
+use transcript::Transcript;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, DirBuilder};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use sudo_plugin::{ByteSize, FromSudoOption, OptionMap};
+
+use sudo_pair_types::manifest::{Manifest, Outcome, Percentiles};
+use sudo_pair_types::protocol::{Capabilities, Encoding, PROTOCOL_VERSION};
+
+const DEFAULT_SESSIONS  : usize = 200;
+const DEFAULT_BYTES     : &str  = "1MiB";
+const DEFAULT_CHUNK     : &str  = "4KiB";
+const DEFAULT_TOLERANCE : u64   = 20;
+
+/// The prompt each simulated approver is sent, as the plugin would
+/// render its default one.
+const PROMPT : &[u8] = b"sudo_pair_soak is asking to run a synthetic session\nApprove? y/n [n]: ";
+
+const USAGE : &str = "\
+usage: sudo_pair_soak [options] [key=value ...]
+
+Runs many paired sessions at once, without sudo or root, to find how the
+plugin holds up under load. Each session creates its own socket, which a
+simulated approver connects to and approves, and then streams synthetic
+output to the approver (and records it, if the options enable recording)
+through the same code the plugin uses. Each key=value argument is a
+sudo_pair option, as it would appear in sudo.conf (e.g.,
+recording_sink=file).
+
+The latency of each approval and of each write of output, the time each
+session took, and the overall throughput are reported. Given --save, they
+are written to a file that a later run can be compared against with
+--baseline; the exit status is 1 if any session failed or anything
+regressed beyond the tolerance, and 2 if the soak couldn't be run.
+
+options:
+    --sessions N      run N sessions at once [200]
+    --bytes SIZE      stream SIZE of output in each session [1MiB]
+    --chunk SIZE      write the output SIZE at a time [4KiB]
+    --output KIND     generate `text`, `escapes` (text with colors, titles,
+                      and cursor movement), or `binary` output [text]
+    --dir DIR         create the sessions' sockets in DIR [a new temporary
+                      directory]
+    --save PATH       write the results to PATH
+    --baseline PATH   compare the results against those saved in PATH
+    --tolerance PCT   allow latencies and throughput to be up to PCT
+                      percent worse than the baseline's [20]
+    -h, --help        print this message
+";
+
+fn main() {
+    match run(env::args().skip(1)) {
+        Ok(status) => process::exit(status),
+
+        Err(e) => {
+            eprintln!("sudo_pair_soak: {}", e);
+            process::exit(2);
+        },
+    }
+}
+
+/// Runs the soak the arguments describe, returning the exit status.
+fn run<I: Iterator<Item = String>>(mut args: I) -> Result<i32, String> {
+    let mut soak      = Soak::default();
+    let mut save      = None;
+    let mut baseline  = None;
+    let mut tolerance = DEFAULT_TOLERANCE;
+    let mut raw       = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next()
+            .ok_or_else(|| format!("{} requires a value", name));
+
+        match arg.as_str() {
+            "--sessions" => soak.sessions = value("--sessions")?
+                .parse()
+                .ok()
+                .filter(|sessions| *sessions > 0)
+                .ok_or("--sessions must be a positive number")?,
+
+            "--bytes" => soak.bytes = size("--bytes", &value("--bytes")?)?,
+            "--chunk" => soak.chunk = size("--chunk", &value("--chunk")?)?.max(1),
+
+            "--output" => soak.output = match value("--output")?.as_str() {
+                "text"    => Output::Text,
+                "escapes" => Output::Escapes,
+                "binary"  => Output::Binary,
+                other     => return Err(format!("--output must be text, escapes, or binary, got {}", other)),
+            },
+
+            "--dir"      => soak.dir = Some(PathBuf::from(value("--dir")?)),
+            "--save"     => save     = Some(PathBuf::from(value("--save")?)),
+            "--baseline" => baseline = Some(PathBuf::from(value("--baseline")?)),
+
+            "--tolerance" => tolerance = value("--tolerance")?
+                .parse()
+                .map_err(|_| "--tolerance must be a percentage")?,
+
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(0);
+            },
+
+            _ if arg.contains('=') => raw.push(arg),
+
+            _ => return Err(format!("unrecognized argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let options = parse(&raw)?
+        .map_err(|problems| problems.join("\n"))?;
+
+    // read before the soak, so a missing baseline doesn't waste a run
+    let baseline = match baseline {
+        Some(path) => Some(
+            fs::read_to_string(&path)
+                .map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+                .and_then(|contents| load(&contents)
+                    .map_err(|e| format!("{}: {}", path.display(), e)))?
+        ),
+
+        None => None,
+    };
+
+    let report  = soak.run(Arc::new(options))?;
+    let metrics = report.metrics();
+
+    print!("{}", report);
+
+    if let Some(path) = save {
+        fs::write(&path, save_metrics(&metrics))
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+    }
+
+    let regressions = match baseline {
+        Some(baseline) => compare(&baseline, &metrics, tolerance)?,
+        None           => Vec::new(),
+    };
+
+    for regression in &regressions {
+        println!("regression: {}", regression);
+    }
+
+    match (report.failures.is_empty(), regressions.is_empty()) {
+        (true, true) => Ok(0),
+        _            => Ok(1),
+    }
+}
+
+/// Parses the size given for the argument `name`.
+fn size(name: &str, value: &str) -> Result<usize, String> {
+    ByteSize::from_sudo_option(value)
+        .ok()
+        .and_then(|size| usize::try_from(size.as_u64()).ok())
+        .ok_or_else(|| format!("{} must be a size (e.g., 64KiB), got {}", name, value))
+}
+
+/// Parses `raw` options exactly as sudo hands them to the plugin.
+fn parse(raw: &[String]) -> Result<Result<PluginOptions, Vec<String>>, String> {
+    let options = raw.iter()
+        .map(|option| CString::new(option.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let pointers : Vec<_> = options.iter()
+        .map(|option| option.as_ptr())
+        .chain(Some(ptr::null()))
+        .collect();
+
+    // the array is terminated by a NULL pointer, and every string is
+    // kept alive by `options` for as long as the map is built
+    let map = unsafe { OptionMap::from_raw(pointers.as_ptr()) };
+
+    Ok(PluginOptions::try_from(&map))
+}
+
+/// The kind of output the sessions stream.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum Output {
+    /// lines of plain text, as most commands print
+    #[default]
+    Text,
+
+    /// text interspersed with colors, window titles, and cursor
+    /// movement, as interactive programs print
+    Escapes,
+
+    /// arbitrary bytes (e.g., `cat` of a binary file)
+    Binary,
+}
+
+/// Generates a session's output, deterministically from the seed it's
+/// created with, so runs can be compared with one another.
+#[derive(Debug)]
+struct Generator {
+    output: Output,
+    state:  u64,
+}
+
+impl Generator {
+    fn new(output: Output, seed: u64) -> Self {
+        // xorshift never leaves zero, so it mustn't start there
+        Self { output, state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    /// The next `len` bytes of output.
+    fn generate(&mut self, len: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(len + 32);
+
+        while output.len() < len {
+            match self.output {
+                Output::Text    => self.line(&mut output),
+                Output::Escapes => self.escaped_line(&mut output),
+                Output::Binary  => output.extend_from_slice(&self.next().to_le_bytes()),
+            }
+        }
+
+        output.truncate(len);
+        output
+    }
+
+    fn line(&mut self, output: &mut Vec<u8>) {
+        let words = 1 + self.next() % 12;
+
+        for i in 0..words {
+            if i > 0 {
+                output.push(b' ');
+            }
+
+            for _ in 0..(1 + self.next() % 9) {
+                output.push(b'a' + (self.next() % 26) as u8);
+            }
+        }
+
+        output.extend_from_slice(b"\r\n");
+    }
+
+    fn escaped_line(&mut self, output: &mut Vec<u8>) {
+        match self.next() % 4 {
+            0 => output.extend_from_slice(format!("\x1b[{}m", 30 + self.next() % 8).as_bytes()),
+            1 => output.extend_from_slice(format!("\x1b]0;step {}\x07", self.next() % 1000).as_bytes()),
+            2 => output.extend_from_slice(format!("\x1b[{};{}H", 1 + self.next() % 24, 1 + self.next() % 80).as_bytes()),
+            _ => {},
+        }
+
+        self.line(output);
+        output.extend_from_slice(b"\x1b[0m");
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+/// What's run: how many sessions, and what they stream.
+#[derive(Debug)]
+struct Soak {
+    sessions: usize,
+    bytes:    usize,
+    chunk:    usize,
+    output:   Output,
+
+    /// where the sessions' sockets are created, if not in a new
+    /// temporary directory
+    dir: Option<PathBuf>,
+}
+
+impl Default for Soak {
+    fn default() -> Self {
+        Self {
+            sessions: DEFAULT_SESSIONS,
+            bytes:    size("--bytes", DEFAULT_BYTES).unwrap_or_default(),
+            chunk:    size("--chunk", DEFAULT_CHUNK).unwrap_or_default(),
+            output:   Output::default(),
+            dir:      None,
+        }
+    }
+}
+
+impl Soak {
+    /// Runs every session at once, and reports how they fared.
+    fn run(&self, options: Arc<PluginOptions>) -> Result<Report, String> {
+        let dir = match self.dir {
+            Some(ref dir) => SocketDir { path: dir.clone(), temporary: false },
+            None          => SocketDir::create().map_err(|e| e.to_string())?,
+        };
+
+        // sessions are cancelled by a signal just as the plugin's are,
+        // so one is installed for all of them to share
+        let signals = SignalGuard::install()
+            .map_err(|e| e.to_string())?;
+
+        let started  = Instant::now();
+        let sessions : Vec<_> = (0..self.sessions).map(|i| {
+            let path = dir.path.join(format!("soak.{}.{}.sock", process::id(), i));
+
+            let approver = approve(path.clone());
+            let plugin   = {
+                let options = Arc::clone(&options);
+                let output  = Generator::new(self.output, i as u64);
+                let (bytes, chunk, cancel) = (self.bytes, self.chunk, signals.fd());
+
+                thread::spawn(move || stream(&options, &path, i, output, bytes, chunk, cancel))
+            };
+
+            (approver, plugin)
+        }).collect();
+
+        let mut report = Report {
+            sessions:      self.sessions,
+            session_bytes: self.bytes as u64,
+            ..Report::default()
+        };
+
+        for (i, (approver, plugin)) in sessions.into_iter().enumerate() {
+            let received = approver.join().unwrap_or_else(|_| Err("the approver panicked".into()));
+            let streamed = plugin.join().unwrap_or_else(|_| Err("the session panicked".into()));
+
+            let failure = match (streamed, received) {
+                (Err(e), _) | (_, Err(e)) => Some(e),
+
+                (Ok(timings), Ok(received)) => {
+                    report.bytes += timings.bytes;
+                    report.approvals.add(timings.approval);
+                    report.durations.add(timings.duration);
+
+                    for write in &timings.writes {
+                        report.writes.add(*write);
+                    }
+
+                    Some(received)
+                        .filter(|received| *received < timings.shown)
+                        .map(|received| format!(
+                            "the approver was sent {} bytes, but received only {}",
+                            timings.shown,
+                            received,
+                        ))
+                },
+            };
+
+            if let Some(failure) = failure {
+                report.failures.push(format!("session {}: {}", i, failure));
+            }
+        }
+
+        report.elapsed = started.elapsed();
+
+        Ok(report)
+    }
+}
+
+/// How long one session took to get through each stage.
+#[derive(Debug)]
+struct Timings {
+    /// from creating the socket until the approver approved
+    approval: Duration,
+
+    /// each write of output, to the approver and the recording
+    writes: Vec<Duration>,
+
+    /// from being approved until the session closed
+    duration: Duration,
+
+    /// the bytes of output generated, and those sent to the approver
+    /// once filtered for display
+    bytes: u64,
+    shown: u64,
+}
+
+/// Runs session `i` as the plugin would: waits for its approver on the
+/// socket at `path`, prompts them, and once they approve, streams
+/// `bytes` of `output` to them `chunk` bytes at a time, recording it if
+/// `options` enable recording.
+fn stream(
+    options: &PluginOptions,
+    path:    &Path,
+    i:       usize,
+    mut output: Generator,
+    bytes:   usize,
+    chunk:   usize,
+    cancel:  RawFd,
+) -> Result<Timings, String> {
+    let uid  = unsafe { libc::getuid() };
+    let gid  = unsafe { libc::getgid() };
+    let id   = format!("soak-{}-{}", process::id(), i);
+    let slog = slog::Logger::root(slog::Discard, slog::o!());
+
+    let opened   = Instant::now();
+    let deadline = Some(options.approval_timeout)
+        .filter(|timeout| *timeout > Duration::from_secs(0))
+        .map(|timeout| Deadline::after_on(&SystemClock, timeout));
+
+    let socket = Socket::open(
+        path,
+        uid,
+        gid,
+        0o700,
+        &Admission {
+            backlog:     options.socket_backlog,
+            max_pending: options.max_pending_connections,
+            uids:        None,
+            groups:      &|_| None,
+            banned:      None,
+            dropped:     &|_, _| {},
+        },
+        cancel,
+        deadline.map(|deadline| deadline.instant()),
+    );
+
+    let mut awaiting = AwaitingApproval::new();
+
+    before_deadline(socket, ErrorKind::CommunicationError)
+        .map(|socket| awaiting.connect(socket))
+        .map_err(|e| e.to_string())?;
+
+    let mut transcript = Transcript::new(options.transcript_digest_interval, Arc::new(SystemClock));
+
+    let hello = Hello {
+        version:      PROTOCOL_VERSION,
+        capabilities: Capabilities::default(),
+        deadline:     deadline.map(|deadline| deadline.unix_secs()),
+        encoding:     Encoding::Text,
+        utc_offset:   None,
+        winsize:      None,
+        limits:       LIMITS,
+    };
+
+    let mut prompt = hello.encode();
+    prompt.extend_from_slice(PROMPT);
+
+    let answer = awaiting.prompt(&slog, &prompt, hello, deadline.map(|deadline| deadline.instant()))
+        .map_err(|e| e.to_string())?;
+
+    if !answer.approved {
+        return Err(ErrorKind::SessionDeclined.to_string());
+    }
+
+    let approval = opened.elapsed();
+    let approved = Instant::now();
+
+    let mut active = awaiting.approve()
+        .ok_or_else(|| ErrorKind::CommunicationError.to_string())?;
+
+    let mut recording = match recording::sink(options, Ownership::default(), &id, &id) {
+        Ok(Some(sink)) => Some(Recording::start(sink, Arc::new(SystemClock), Durability::configured(options))
+            .map_err(|e| format!("couldn't start recording: {}", e))?),
+        Ok(None)       => None,
+        Err(e)         => return Err(format!("couldn't open recording: {}", e)),
+    };
+
+    let mut display = Display::new(
+        options.sanitize_escapes.clone(),
+        options.utf8_chunking,
+        options.binary_output,
+    );
+
+    let mut writes = Vec::with_capacity(bytes / chunk + 1);
+    let mut shown  = 0;
+
+    for offset in (0..bytes).step_by(chunk) {
+        let data  = output.generate(chunk.min(bytes - offset));
+        let start = Instant::now();
+        let out   = display.process(&data);
+
+        active.send_output(&mut transcript, &slog, &out)
+            .map_err(|e| e.to_string())?;
+
+        for suppression in display.take_suppressed() {
+            active.send_suppressed(&suppression)
+                .map_err(|e| e.to_string())?;
+        }
+
+        if let Some(recording) = recording.as_mut() {
+            recording.output(Frame::TtyOut, &data)
+                .map_err(|e| format!("couldn't record output: {}", e))?;
+        }
+
+        writes.push(start.elapsed());
+        shown += out.len() as u64;
+    }
+
+    let out = display.flush();
+
+    active.send_output(&mut transcript, &slog, &out)
+        .map_err(|e| e.to_string())?;
+
+    shown += out.len() as u64;
+
+    let mut session = Session::Active(active);
+    session.close();
+
+    if let Some(recording) = recording {
+        let manifest = manifest(&id, &recording, bytes as u64);
+
+        recording.finish(&manifest)
+            .map_err(|e| format!("couldn't finish recording: {}", e))?;
+    }
+
+    Ok(Timings {
+        approval,
+        writes,
+        duration: approved.elapsed(),
+        bytes:    bytes as u64,
+        shown,
+    })
+}
+
+/// The manifest of a synthetic session, as the plugin would write it
+/// for a command that exited successfully.
+fn manifest(id: &str, recording: &Recording, bytes: u64) -> Manifest {
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+
+    Manifest {
+        session_id:  id.into(),
+        host:        "localhost".into(),
+        host_tags:   Vec::new(),
+        user:        uid.to_string(),
+        uid,
+        runas_user:  uid.to_string(),
+        runas_uid:   uid,
+        runas_group: gid.to_string(),
+        runas_gid:   gid,
+
+        cwd:        "/".into(),
+        argv:       vec!["sudo_pair_soak".into()],
+        executable: "sudo_pair_soak".into(),
+
+        command_path:   None,
+        command_sha256: None,
+
+        paired:    true,
+        transfers: 0,
+
+        ticket:            None,
+        approver_comments: Vec::new(),
+        correlation_id:    None,
+
+        colocated_approver: None,
+        web_approver:       None,
+
+        subcommands:         Vec::new(),
+        subcommands_omitted: 0,
+
+        maintenance_window: None,
+        killed_by:          None,
+        kill_reason:        None,
+        labels:             Vec::new(),
+        multiplexer:        None,
+
+        started_at:   recording.started(),
+        ended_at:     SystemTime::now(),
+        output_bytes: bytes,
+
+        approver_bytes:  None,
+        approver_sha256: None,
+
+        keystroke_latency: None,
+        edits:             Vec::new(),
+
+        outcome: Outcome::Exited(0),
+    }
+}
+
+/// Approves the session at `path` once its socket appears, returning
+/// how many bytes the approver was sent.
+fn approve(path: PathBuf) -> thread::JoinHandle<Result<u64, String>> {
+    thread::spawn(move || {
+        // the socket appears when it's bound, slightly before it's
+        // listened on, so connections are retried until accepted
+        let mut stream = loop {
+            match UnixStream::connect(&path) {
+                Ok(stream) => break stream,
+
+                Err(ref e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) =>
+                    thread::sleep(Duration::from_millis(10)),
+
+                Err(e) => return Err(format!("the approver couldn't connect: {}", e)),
+            }
+        };
+
+        stream.write_all(b"y")
+            .map_err(|e| format!("the approver couldn't approve: {}", e))?;
+
+        let mut buffer   = [0; 65536];
+        let mut received = 0;
+
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0)    => return Ok(received),
+                Ok(read) => received += read as u64,
+
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(format!("the approver couldn't read the session: {}", e)),
+            }
+        }
+    })
+}
+
+/// How the sessions fared.
+#[derive(Debug, Default)]
+struct Report {
+    sessions:      usize,
+    session_bytes: u64,
+    failures:      Vec<String>,
+
+    /// the bytes of output streamed by the sessions that succeeded, and
+    /// how long it took for every session to finish
+    bytes:   u64,
+    elapsed: Duration,
+
+    approvals: Samples,
+    writes:    Samples,
+    durations: Samples,
+}
+
+impl Report {
+    /// The output streamed per second, over the whole soak.
+    fn throughput(&self) -> u64 {
+        let elapsed = self.elapsed.as_secs_f64();
+
+        if elapsed == 0.0 {
+            return 0;
+        }
+
+        (self.bytes as f64 / elapsed) as u64
+    }
+
+    /// The results that can be saved and compared, as `(name, value)`
+    /// pairs. Latencies are in microseconds.
+    fn metrics(&self) -> Vec<(&'static str, u64)> {
+        let mut metrics = vec![
+            ("sessions",                 self.sessions as u64),
+            ("session_bytes",            self.session_bytes),
+            ("failed",                   self.failures.len() as u64),
+            ("throughput_bytes_per_sec", self.throughput()),
+        ];
+
+        let latencies = [
+            ("approval_p50_us", "approval_p99_us", &self.approvals),
+            ("write_p50_us",    "write_p99_us",    &self.writes),
+            ("duration_p50_us", "duration_p99_us", &self.durations),
+        ];
+
+        for (p50, p99, samples) in latencies.iter() {
+            if let Some(percentiles) = samples.percentiles() {
+                metrics.push((p50, percentiles.p50));
+                metrics.push((p99, percentiles.p99));
+            }
+        }
+
+        metrics
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mib = |bytes: u64| bytes as f64 / (1 << 20) as f64;
+
+        writeln!(f, "sessions: {} ({} failed)", self.sessions, self.failures.len())?;
+
+        for failure in &self.failures {
+            writeln!(f, "  {}", failure)?;
+        }
+
+        writeln!(
+            f,
+            "output:   {:.1} MiB in {:.2}s ({:.1} MiB/s)",
+            mib(self.bytes),
+            self.elapsed.as_secs_f64(),
+            mib(self.throughput()),
+        )?;
+
+        let latencies = [
+            ("approval", &self.approvals),
+            ("writes",   &self.writes),
+            ("duration", &self.durations),
+        ];
+
+        for (name, samples) in latencies.iter() {
+            match samples.percentiles() {
+                Some(Percentiles { p50, p90, p99, max }) => writeln!(
+                    f,
+                    "{:<9} p50 {}us, p90 {}us, p99 {}us, max {}us",
+                    format!("{}:", name), p50, p90, p99, max,
+                )?,
+
+                None => writeln!(f, "{:<9} none", format!("{}:", name))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Renders `metrics` as they're saved, one `name=value` per line.
+fn save_metrics(metrics: &[(&'static str, u64)]) -> String {
+    metrics.iter()
+        .map(|(name, value)| format!("{}={}\n", name, value))
+        .collect()
+}
+
+/// Loads metrics saved by `save_metrics`.
+fn load(contents: &str) -> Result<HashMap<String, u64>, String> {
+    contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split_once('=')
+            .and_then(|(name, value)| Some((name.trim().into(), value.trim().parse().ok()?)))
+            .ok_or_else(|| format!("couldn't parse {:?}", line)))
+        .collect()
+}
+
+/// Compares `metrics` against those of the `baseline`, returning how
+/// they regressed beyond `tolerance` percent, if they did. Baselines of
+/// a different number or size of sessions can't be compared against.
+fn compare(
+    baseline:  &HashMap<String, u64>,
+    metrics:   &[(&'static str, u64)],
+    tolerance: u64,
+) -> Result<Vec<String>, String> {
+    let mut regressions = Vec::new();
+
+    for &(name, value) in metrics {
+        let base = match baseline.get(name) {
+            Some(base) => *base,
+            None       => continue,
+        };
+
+        let regressed = match name {
+            "sessions" | "session_bytes" if value != base => return Err(format!(
+                "the baseline's {} was {}, not {}",
+                name,
+                base,
+                value,
+            )),
+
+            "sessions" | "session_bytes" => false,
+            "failed"                     => value > base,
+            "throughput_bytes_per_sec"   => value.saturating_mul(100) < base.saturating_mul(100u64.saturating_sub(tolerance)),
+            _                            => value.saturating_mul(100) > base.saturating_mul(100 + tolerance),
+        };
+
+        if regressed {
+            regressions.push(format!("{} was {}, against {} in the baseline", name, value, base));
+        }
+    }
+
+    Ok(regressions)
+}
+
+/// The directory the sessions' sockets are created in. Temporary ones
+/// are removed when it's dropped.
+#[derive(Debug)]
+struct SocketDir {
+    path:      PathBuf,
+    temporary: bool,
+}
+
+impl SocketDir {
+    /// Creates a new directory only the current user can use, as the
+    /// plugin insists of the directory its sockets are created in.
+    fn create() -> io::Result<Self> {
+        let path = env::temp_dir().join(format!("sudo_pair_soak.{}", process::id()));
+
+        DirBuilder::new().mode(0o700).create(&path)?;
+
+        Ok(Self { path, temporary: true })
+    }
+}
+
+impl Drop for SocketDir {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sudo_pair::scratch;
+
+    #[test]
+    fn generates_output_deterministically() {
+        for output in [Output::Text, Output::Escapes, Output::Binary] {
+            let first  = Generator::new(output, 7).generate(10_000);
+            let second = Generator::new(output, 7).generate(10_000);
+
+            assert_eq!(10_000, first.len());
+            assert_eq!(first, second);
+            assert_ne!(first, Generator::new(output, 8).generate(10_000));
+        }
+
+        let text    = Generator::new(Output::Text,    1).generate(10_000);
+        let escapes = Generator::new(Output::Escapes, 1).generate(10_000);
+
+        assert!(text.iter().all(|b| b.is_ascii_lowercase() || b" \r\n".contains(b)));
+        assert!(escapes.contains(&0x1b));
+    }
+
+    #[test]
+    fn compares_against_baselines() {
+        let baseline = load("sessions=10\nfailed=0\nthroughput_bytes_per_sec=1000\nwrite_p99_us=100\n").unwrap();
+
+        assert_eq!(Ok(Vec::new()), compare(&baseline, &[
+            ("sessions",                 10),
+            ("failed",                   0),
+            ("throughput_bytes_per_sec", 850),
+            ("write_p99_us",             115),
+            ("approval_p99_us",          999_999),
+        ], 20));
+
+        assert_eq!(3, compare(&baseline, &[
+            ("failed",                   1),
+            ("throughput_bytes_per_sec", 750),
+            ("write_p99_us",             130),
+        ], 20).unwrap().len());
+
+        assert!(compare(&baseline, &[("sessions", 20)], 20).is_err());
+        assert!(load("sessions=ten\n").is_err());
+    }
+
+    #[test]
+    fn saves_metrics_as_they_are_loaded() {
+        let metrics = [("sessions", 3), ("write_p99_us", 42)];
+        let loaded  = load(&save_metrics(&metrics)).unwrap();
+
+        assert_eq!(Some(&42), loaded.get("write_p99_us"));
+        assert_eq!(2, loaded.len());
+    }
+
+    #[test]
+    fn soaks_sessions() {
+        let dir        = scratch::dir("soak", "sessions", true);
+        let recordings = dir.join("recordings");
+        let options    = parse(&[
+            "recording_sink=file".into(),
+            format!("recording_dir={}", recordings.display()),
+        ]).unwrap().unwrap();
+
+        let soak = Soak {
+            sessions: 4,
+            bytes:    65536,
+            chunk:    1000,
+            output:   Output::Escapes,
+            dir:      Some(dir.clone()),
+        };
+
+        let report = soak.run(Arc::new(options)).unwrap();
+
+        assert_eq!(Vec::<String>::new(), report.failures);
+        assert_eq!(4 * 65536, report.bytes);
+        assert!(report.writes.percentiles().is_some());
+        assert_eq!(4, fs::read_dir(&recordings).unwrap().count());
+        assert!(report.to_string().starts_with("sessions: 4 (0 failed)\n"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
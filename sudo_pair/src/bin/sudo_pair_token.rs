@@ -0,0 +1,203 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Generates a token pre-approving one exact command, for an approver
+//! who can't (or needn't) watch it run live. The token is printed to
+//! stdout, for the user to pass to `sudo` in `SUDO_PAIR_TOKEN` or when
+//! prompted.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use sudo_pair::{status, token};
+
+use status::Report;
+use token::{Claims, Window};
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sudo_plugin::FromSudoOption;
+
+const DEFAULT_RUNAS : &str = "root";
+const DEFAULT_VALID : &str = "15m";
+
+const USAGE : &str = "\
+usage: sudo_pair_token --key PATH --host HOST --user USER [options] -- COMMAND [ARG ...]
+
+Prints a token pre-approving USER to run exactly COMMAND with exactly the
+given arguments on HOST. COMMAND must be the absolute path sudo will run.
+The exit status is 2 if no token could be made.
+
+options:
+    --key PATH       read the signing key from PATH
+    --host HOST      the host the command will run on, as sudo names it
+    --user USER      the user who will invoke sudo
+    --runas USER     the user the command will run as [root]
+    --valid DURATION how long the token is valid for, from now [15m]
+    --json           print the token (or error) as a JSON object
+    -h, --help       print this message
+";
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let json     = status::json_requested(&mut args);
+
+    let report = match run(args.into_iter()) {
+        Ok(Some(token)) => {
+            if !json {
+                println!("{}", token);
+            }
+
+            Report::success().string("token", &token)
+        },
+
+        Ok(None) => {
+            print!("{}", USAGE);
+            Report::success()
+        },
+
+        Err(e) => Report::error(&e),
+    };
+
+    report.exit("sudo_pair_token", json)
+}
+
+fn run<I: Iterator<Item = String>>(mut args: I) -> Result<Option<String>, String> {
+    let mut key     = None;
+    let mut host    = None;
+    let mut user    = None;
+    let mut runas   = DEFAULT_RUNAS.to_owned();
+    let mut valid   = DEFAULT_VALID.to_owned();
+    let mut command = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next()
+            .ok_or_else(|| format!("{} requires a value", flag));
+
+        match arg.as_str() {
+            "--key"         => key   = Some(PathBuf::from(value(&arg)?)),
+            "--host"        => host  = Some(value(&arg)?),
+            "--user"        => user  = Some(value(&arg)?),
+            "--runas"       => runas = value(&arg)?,
+            "--valid"       => valid = value(&arg)?,
+            "-h" | "--help" => return Ok(None),
+            "--"            => {
+                command.extend(args.by_ref());
+                break;
+            },
+            _ => return Err(format!("unrecognized argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let key  = key .ok_or_else(|| format!("--key is required\n\n{}",  USAGE))?;
+    let host = host.ok_or_else(|| format!("--host is required\n\n{}", USAGE))?;
+    let user = user.ok_or_else(|| format!("--user is required\n\n{}", USAGE))?;
+
+    let valid = Duration::from_sudo_option(&valid)
+        .map_err(|_| format!("--valid must be a duration, got {}", valid))?;
+
+    match command.first() {
+        Some(executable) if Path::new(executable).is_absolute() => (),
+        Some(executable) => return Err(format!("the command must be an absolute path, got {}", executable)),
+        None             => return Err(format!("no command was given\n\n{}", USAGE)),
+    }
+
+    let key = token::read_key(&key)
+        .map_err(|e| format!("couldn't read {}: {}", key.display(), e))?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs();
+
+    let command : Vec<&[u8]> = command.iter().map(|arg| arg.as_bytes()).collect();
+
+    let claims = Claims {
+        host:       host .as_bytes(),
+        user:       user .as_bytes(),
+        runas_user: runas.as_bytes(),
+        command:    &command,
+    };
+
+    let window = Window {
+        not_before: now,
+        not_after:  now + valid.as_secs(),
+    };
+
+    Ok(Some(token::sign(&key, &claims, window)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn requires_absolute_commands() {
+        let error = run(args(&["--key", "/k", "--host", "db01", "--user", "alice", "--", "psql"])).unwrap_err();
+
+        assert_eq!("the command must be an absolute path, got psql", error);
+    }
+
+    #[test]
+    fn signs_commands() {
+        let path = env::temp_dir().join(format!("sudo_pair-token-bin-{}", process::id()));
+
+        std::fs::write(&path, b"secret\n").unwrap();
+
+        let token = run(args(&[
+            "--key", path.to_str().unwrap(), "--host", "db01", "--user", "alice", "--valid", "5m",
+            "--", "/usr/bin/psql", "-c", "select 1",
+        ])).unwrap().unwrap();
+
+        let _ = std::fs::remove_file(&path);
+
+        let parts : Vec<_> = token.split('.').collect();
+        let start : u64    = parts[1].parse().unwrap();
+
+        assert_eq!("v1", parts[0]);
+        assert_eq!(start + 300, parts[2].parse::<u64>().unwrap());
+
+        let command : &[&[u8]] = &[b"/usr/bin/psql", b"-c", b"select 1"];
+        let claims             = Claims { host: b"db01", user: b"alice", runas_user: b"root", command };
+
+        assert!(token::verify(b"secret", &claims, &token, start, 300).is_ok());
+    }
+}
@@ -0,0 +1,460 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Captures of everything sudo provides the plugin when it's opened,
+//! so that a session that was misparsed in production can be replayed
+//! offline (by `sudo_pair_replay`).
+//!
+//! A capture is a text file with a `version` line followed by one
+//! section for each of sudo's vectors, each headed by its name in
+//! brackets (e.g., `[settings]`) and listing one entry per line. Bytes
+//! that aren't printable ASCII, backslashes, and a leading `[` or `#`
+//! are escaped as `\xNN`, so any entry survives the round trip. Lines
+//! starting with `#` are comments.
+
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use libc::c_uint;
+use sudo_plugin::{OptionMap, Plugin, PluginBuilder};
+
+use crate::ownership::Ownership;
+
+/// Variables in the command's environment whose values are captured;
+/// every other variable is captured with its value redacted, since it
+/// may hold a secret (e.g., a pre-approval token or an API key).
+const CAPTURED_ENV : &[&[u8]] = &[
+    b"HOME", b"LANG", b"LOGNAME", b"PATH", b"SHELL", b"TERM", b"USER",
+];
+
+/// What redacted values are replaced with.
+const REDACTED : &[u8] = b"<redacted>";
+
+/// The names of the sections of a capture, in the order they're
+/// written.
+const SECTIONS : [&str; 6] = [
+    "argv", "settings", "user_info", "command_info", "user_env", "plugin_options",
+];
+
+/// Everything sudo provided the plugin when it was opened.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Capture {
+    /// the plugin API version sudo supports, as `major.minor`
+    pub version: String,
+
+    /// the command, and each of sudo's vectors as `key=value` entries
+    pub argv:           Vec<Vec<u8>>,
+    pub settings:       Vec<Vec<u8>>,
+    pub user_info:      Vec<Vec<u8>>,
+    pub command_info:   Vec<Vec<u8>>,
+    pub user_env:       Vec<Vec<u8>>,
+    pub plugin_options: Vec<Vec<u8>>,
+}
+
+impl Capture {
+    /// Captures what sudo provided `plugin`. The command's arguments
+    /// (though not its name) and the values of most of its environment
+    /// are redacted.
+    pub fn of(plugin: &Plugin) -> Self {
+        let argv = plugin.command.iter()
+            .enumerate()
+            .map(|(i, arg)| if i == 0 { arg.as_bytes() } else { REDACTED }.to_vec())
+            .collect();
+
+        let user_env = entries(&plugin.user_env).into_iter()
+            .map(|entry| {
+                let key = entry.split(|b| *b == b'=').next().unwrap_or_default();
+
+                if CAPTURED_ENV.contains(&key) {
+                    return entry;
+                }
+
+                [key, b"=", REDACTED].concat()
+            })
+            .collect();
+
+        Self {
+            version:        plugin.version.to_string(),
+            argv,
+            settings:       entries(&plugin.settings.raw),
+            user_info:      entries(&plugin.user_info.raw),
+            command_info:   entries(&plugin.command_info.raw),
+            user_env,
+            plugin_options: entries(&plugin.plugin_options),
+        }
+    }
+
+    /// Writes the capture for the session `id` into `dir`, creating the
+    /// directory if needed. Captures are only readable by root.
+    pub fn save(&self, dir: &Path, id: &str) -> io::Result<PathBuf> {
+        Ownership::default().create_dir(dir)?;
+
+        let path = dir.join(format!("{}.capture", id));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        self.write_to(&mut file)?;
+
+        Ok(path)
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# sudo_pair debug capture")?;
+        writeln!(out, "version {}", self.version)?;
+
+        for (name, entries) in SECTIONS.iter().zip(self.sections()) {
+            writeln!(out, "[{}]", name)?;
+
+            for entry in entries {
+                writeln!(out, "{}", escape(entry))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a capture back. Only `sudo_pair_replay` reads captures;
+    /// the plugin just writes them.
+    pub fn read_from<R: BufRead>(input: R) -> Result<Self, String> {
+        let mut capture = Self::default();
+        let mut section = None;
+
+        for (number, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(version) = line.strip_prefix("version ") {
+                capture.version = version.into();
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                section = Some(SECTIONS.iter().position(|s| *s == name)
+                    .ok_or_else(|| format!("line {}: unknown section {}", number + 1, name))?);
+
+                continue;
+            }
+
+            let entry = unescape(&line)
+                .ok_or_else(|| format!("line {}: invalid escape", number + 1))?;
+
+            match section {
+                Some(i) => capture.sections_mut()[i].push(entry),
+                None    => return Err(format!("line {}: entry outside of any section", number + 1)),
+            }
+        }
+
+        if capture.version.is_empty() {
+            return Err("no version was captured".into());
+        }
+
+        Ok(capture)
+    }
+
+    /// Opens a `Plugin` from the capture, as sudo would have. Only
+    /// `sudo_pair_check --decide` opens captures this way, since it
+    /// needs just the plugin; `sudo_pair_replay` passes sudo's vectors
+    /// as they were, to report everything that fails to be parsed.
+    pub fn plugin(&self) -> Result<Plugin, String> {
+        let version = api_version(&self.version)
+            .ok_or_else(|| format!("invalid version {}", self.version))?;
+
+        let mut builder = PluginBuilder::new("sudo_pair")
+            .api_version(version)
+            .command(&self.argv);
+
+        for (k, v) in self.settings.iter().map(|entry| split(entry)) {
+            builder = builder.setting(k, v);
+        }
+
+        for (k, v) in self.user_info.iter().map(|entry| split(entry)) {
+            builder = builder.user_info(k, v);
+        }
+
+        for (k, v) in self.command_info.iter().map(|entry| split(entry)) {
+            builder = builder.command_info(k, v);
+        }
+
+        for (k, v) in self.user_env.iter().map(|entry| split(entry)) {
+            builder = builder.user_env(k, v);
+        }
+
+        for (k, v) in self.plugin_options.iter().map(|entry| split(entry)) {
+            builder = builder.plugin_option(k, v);
+        }
+
+        builder.build().map_err(|e| e.to_string())
+    }
+
+    fn sections(&self) -> [&Vec<Vec<u8>>; 6] {
+        [
+            &self.argv,
+            &self.settings,
+            &self.user_info,
+            &self.command_info,
+            &self.user_env,
+            &self.plugin_options,
+        ]
+    }
+
+    fn sections_mut(&mut self) -> [&mut Vec<Vec<u8>>; 6] {
+        [
+            &mut self.argv,
+            &mut self.settings,
+            &mut self.user_info,
+            &mut self.command_info,
+            &mut self.user_env,
+            &mut self.plugin_options,
+        ]
+    }
+}
+
+/// Returns every entry of `map` as `key=value`, sorted by key (as
+/// `OptionMap::keys` yields them) so captures of the same invocation are
+/// identical. Valueless options (whose value is their key) are written
+/// as such, which sudo parses the same way.
+fn entries(map: &OptionMap) -> Vec<Vec<u8>> {
+    map.keys()
+        .map(|key| [key, b"=", map.get_bytes(key).unwrap_or_default()].concat())
+        .collect()
+}
+
+/// Converts a `major.minor` version back into the form sudo passes it
+/// to plugins in.
+pub fn api_version(version: &str) -> Option<c_uint> {
+    let mut parts = version.splitn(2, '.');
+
+    let major : u16 = parts.next()?.parse().ok()?;
+    let minor : u16 = parts.next()?.parse().ok()?;
+
+    Some(c_uint::from(major) << 16 | c_uint::from(minor))
+}
+
+/// Splits an entry into its key and value. Entries without a value
+/// are valueless options, whose value is their key.
+fn split(entry: &[u8]) -> (&[u8], &[u8]) {
+    match entry.iter().position(|b| *b == b'=') {
+        Some(i) => (&entry[..i], &entry[i + 1..]),
+        None    => (entry, entry),
+    }
+}
+
+fn escape(entry: &[u8]) -> String {
+    let mut escaped = String::with_capacity(entry.len());
+
+    for (i, &byte) in entry.iter().enumerate() {
+        let leading = i == 0 && (byte == b'[' || byte == b'#');
+
+        if leading || byte == b'\\' || !(0x20..0x7f).contains(&byte) {
+            let _ = write!(escaped, "\\x{:02x}", byte);
+        } else {
+            escaped.push(byte as char);
+        }
+    }
+
+    escaped
+}
+
+fn unescape(line: &str) -> Option<Vec<u8>> {
+    let bytes       = line.as_bytes();
+    let mut entry   = Vec::with_capacity(bytes.len());
+    let mut i       = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            entry.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let hex = bytes.get(i + 1 .. i + 4)
+            .filter(|hex| hex[0] == b'x')
+            .and_then(|hex| std::str::from_utf8(&hex[1..]).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())?;
+
+        // sudo's vectors are C strings, so they can't contain a NUL
+        if hex == 0 {
+            return None;
+        }
+
+        entry.push(hex);
+        i += 4;
+    }
+
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::{FIXTURES, fixture};
+
+    use sudo_plugin::InvocationKind;
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn capture() -> Capture {
+        Capture {
+            version:        "1.17".into(),
+            argv:           vec![b"psql".to_vec(), REDACTED.to_vec()],
+            settings:       vec![b"progname=sudo".to_vec(), b"network_addrs=10.0.0.1/255.0.0.0".to_vec()],
+            user_info:      vec![b"user=alice".to_vec(), b"cwd=/home/alice\\ \xff\nx".to_vec()],
+            command_info:   vec![b"[not a section]".to_vec(), b"#not a comment".to_vec()],
+            user_env:       vec![],
+            plugin_options: vec![b"socket_dir=/tmp/pair".to_vec()],
+        }
+    }
+
+    #[test]
+    fn round_trips_every_entry() {
+        let mut written = Vec::new();
+        capture().write_to(&mut written).unwrap();
+
+        let written = String::from_utf8(written).unwrap();
+
+        assert!(written.contains("[user_info]\nuser=alice\ncwd=/home/alice\\x5c \\xff\\x0ax\n"));
+        assert!(written.contains("[command_info]\n\\x5bnot a section]\n\\x23not a comment\n"));
+
+        assert_eq!(Ok(capture()), Capture::read_from(written.as_bytes()));
+    }
+
+    #[test]
+    fn rejects_malformed_captures() {
+        for capture in &[
+            "[settings]\nprogname=sudo\n",
+            "version 1.17\nprogname=sudo\n",
+            "version 1.17\n[nonsense]\n",
+            "version 1.17\n[settings]\nprogname=\\x0\n",
+            "version 1.17\n[settings]\nprogname=\\x00\n",
+            "version 1.17\n[settings]\nprogname=\\\n",
+        ] {
+            assert!(Capture::read_from(capture.as_bytes()).is_err(), "{:?}", capture);
+        }
+    }
+
+    #[test]
+    fn opens_plugins_from_captures() {
+        let mut capture = capture();
+
+        capture.settings.push(b"plugin_dir=/usr/libexec/sudo".to_vec());
+        capture.settings.push(b"plugin_path=sudo_pair.so".to_vec());
+        capture.user_info = vec![
+            b"cwd=/home/alice".to_vec(), b"egid=1000".to_vec(), b"euid=0".to_vec(),
+            b"gid=1000".to_vec(), b"groups=1000".to_vec(), b"host=db01".to_vec(),
+            b"pgid=31337".to_vec(), b"pid=31337".to_vec(), b"ppid=31336".to_vec(),
+            b"uid=1000".to_vec(), b"user=alice".to_vec(),
+        ];
+        capture.command_info = vec![b"command=/usr/bin/psql".to_vec(), b"runas_uid=109".to_vec()];
+
+        let plugin = capture.plugin().unwrap();
+
+        assert_eq!("alice",         plugin.user_info.user);
+        assert_eq!(109,             plugin.command_info.runas_euid);
+        assert_eq!(Some("/tmp/pair"), plugin.plugin_options.get_str("socket_dir"));
+
+        assert!(Capture { version: "1.x".into(), ..capture.clone() }.plugin().is_err());
+        assert!(Capture { user_info: vec![], ..capture }.plugin().is_err());
+    }
+
+    #[test]
+    fn opens_plugins_from_every_fixture() {
+        for fixture in FIXTURES {
+            let capture = fixture.capture();
+            let plugin  = fixture.plugin();
+
+            let mut written = Vec::new();
+            capture.write_to(&mut written).unwrap();
+
+            assert_eq!(Ok(capture.clone()), Capture::read_from(&written[..]), "{}", fixture.name);
+
+            assert_eq!(capture.version, plugin.version.to_string(), "{}", fixture.name);
+            assert_eq!(fixture.invocation, String::from_utf8_lossy(&plugin.invocation()), "{}", fixture.name);
+            assert!(plugin.invocation_kind().runs_command(), "{}", fixture.name);
+        }
+    }
+
+    #[test]
+    fn parses_fixtures_of_each_invocation() {
+        for name in &["sudo-1.8.31-sudoedit", "sudo-1.9.5-sudoedit"] {
+            let plugin = fixture(name).plugin();
+
+            assert_eq!(InvocationKind::Edit,            plugin.invocation_kind(),  "{}", name);
+            assert_eq!(vec![PathBuf::from("/etc/hosts")], plugin.edited_files(), "{}", name);
+        }
+
+        let login = fixture("sudo-1.9.5-login").plugin();
+
+        assert!(login.settings.login_shell && login.settings.implied_shell);
+        assert_eq!(Some(&PathBuf::from("/root")), login.command_info.cwd.as_ref());
+        assert_eq!(0, login.command_info.runas_euid);
+
+        let shell = fixture("sudo-1.9.5-shell").plugin();
+
+        assert!(shell.settings.run_shell && !shell.settings.login_shell);
+        assert_eq!(&PathBuf::from("/home/alice"), shell.cwd());
+
+        // sudo doesn't tell plugins it was invoked with `-b`
+        let background = fixture("sudo-1.9.5-background").plugin();
+
+        assert!(!background.is_background());
+        assert_eq!(InvocationKind::Run, background.invocation_kind());
+
+        let nopasswd = fixture("sudo-1.9.5-nopasswd").plugin();
+
+        assert!(nopasswd.settings.noninteractive);
+        assert_eq!(None,  nopasswd.user_info.tty);
+        assert_eq!(-1,    nopasswd.user_info.tcpgid);
+        assert_eq!(1001,  nopasswd.user_info.uid);
+        assert!(nopasswd.command_info.iolog_path.is_some());
+        assert!(!nopasswd.command_info.use_pty);
+
+        // sudo 1.8.16 predates `umask` in `user_info`, but it's filled in
+        assert!(fixture("sudo-1.8.16").plugin().user_info.umask.is_some());
+    }
+
+    #[test]
+    fn converts_versions() {
+        assert_eq!(Some(0x0001_0011), api_version("1.17"));
+        assert_eq!(None,              api_version("1"));
+        assert_eq!(None,              api_version("one.two"));
+    }
+
+    #[test]
+    fn saves_captures_only_root_can_read() {
+        let dir  = std::env::temp_dir().join(format!("sudo_pair-capture-{}", std::process::id()));
+        let path = capture().save(&dir, "1-2").unwrap();
+
+        assert_eq!(dir.join("1-2.capture"), path);
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        assert_eq!(Ok(capture()), Capture::read_from(&fs::read(&path).unwrap()[..]));
+
+        // captures are never overwritten
+        assert!(capture().save(&dir, "1-2").is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
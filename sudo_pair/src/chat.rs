@@ -0,0 +1,244 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Short messages between the approver and the user while a session is
+//! underway (e.g., "hold on, checking the change ticket").
+//!
+//! An approver whose client negotiated `CHAT` sends them as `chat`
+//! messages on the session's socket, mixed in with the `y`/`n` answers
+//! they give when they're asked to re-approve or hand off the session.
+//! Everything the approver sends goes through an `Inbox`, which sets
+//! the messages aside and keeps the keys they typed for whatever's
+//! asked of them next.
+//!
+//! Messages are shown to the user on their own line of their terminal,
+//! between chunks of the command's output. When the approver asks for
+//! a reply, the user is prompted for one through sudo's conversation,
+//! and it's sent back to the approver as a `chat` message of its own.
+
+use crate::protocol::{Reply, LIMITS};
+
+use sudo_pair_types::protocol::{truncate, BEL, ESC};
+
+use std::time::Duration;
+
+/// How long the user has to reply to the approver before the prompt
+/// gives up and the command carries on.
+pub const REPLY_TIMEOUT : Duration = Duration::from_secs(60);
+
+/// The prompt the user is given when the approver asks for a reply.
+pub const REPLY_PROMPT : &[u8] = b"reply to your approver (leave empty to not reply): ";
+
+/// The most keys kept from the approver between the times they're
+/// asked something, which is far more than any answer needs.
+const MAX_KEYS : usize = 256;
+
+/// A message from the approver to the user.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chat {
+    pub text: String,
+
+    /// whether the approver asked the user to reply
+    pub ask: bool,
+}
+
+/// Everything the approver's sent that hasn't yet been looked at.
+#[derive(Clone, Debug, Default)]
+pub struct Inbox {
+    /// keys the approver typed outside of any message
+    keys: Vec<u8>,
+
+    /// a message whose terminating `BEL` hasn't arrived yet
+    partial: Vec<u8>,
+
+    /// whether the rest of a message that ran on too long is being
+    /// skipped, up to its `BEL`
+    skipping: bool,
+
+    /// messages that have arrived in full
+    chats: Vec<Chat>,
+}
+
+impl Inbox {
+    /// Sorts `input` from the approver into keys and messages. Messages
+    /// are only looked for when the approver's client negotiated
+    /// `CHAT`; otherwise, everything they send is a key, as it always
+    /// was. Messages that aren't chats, or that run on longer than the
+    /// plugin accepts, are dropped.
+    pub fn receive(&mut self, input: &[u8], messages: bool) {
+        for &byte in input {
+            if self.skipping {
+                self.skipping = byte != BEL;
+                continue;
+            }
+
+            match self.partial.len() {
+                0 if byte == ESC && messages => self.partial.push(byte),
+                0                            => self.key(byte),
+
+                // an escape that doesn't begin an `OSC` sequence is a
+                // key (e.g., an arrow) like any other
+                1 if byte != b']' => {
+                    self.key(ESC);
+                    self.key(byte);
+                    self.partial.clear();
+                },
+
+                _ if byte == BEL => {
+                    if let Some(Reply::Chat(chat)) = Reply::decode(&self.partial[1..]) {
+                        self.chats.push(chat);
+                    }
+
+                    self.partial.clear();
+                },
+
+                len if len > LIMITS.message => {
+                    self.partial.clear();
+                    self.skipping = true;
+                },
+
+                _ => self.partial.push(byte),
+            }
+        }
+    }
+
+    /// Takes the keys the approver has typed since this was last
+    /// called.
+    pub fn take_keys(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.keys)
+    }
+
+    /// Discards the keys the approver has typed so far.
+    pub fn discard_keys(&mut self) {
+        self.keys.clear();
+    }
+
+    /// Takes the messages the approver has sent since this was last
+    /// called, oldest first.
+    pub fn take_chats(&mut self) -> Vec<Chat> {
+        std::mem::take(&mut self.chats)
+    }
+
+    fn key(&mut self, byte: u8) {
+        if self.keys.len() < MAX_KEYS {
+            self.keys.push(byte);
+        }
+    }
+}
+
+/// How a message from the approver is shown on the user's terminal,
+/// starting on a line of its own. The terminal may be in raw mode, so
+/// lines are ended with a carriage return as well.
+pub fn notice(chat: &Chat, at_line_start: bool) -> Vec<u8> {
+    let separator = if at_line_start { "" } else { "\r\n" };
+
+    format!("{}[sudo_pair] your approver says: {}\r\n", separator, chat.text).into_bytes()
+}
+
+/// The user's reply, as it's sent to the approver: a single line of
+/// printable text, cut short to `limit` characters. Returns `None` if
+/// they didn't reply.
+pub fn reply(input: &[u8], limit: usize) -> Option<String> {
+    let input = String::from_utf8_lossy(input);
+    let input : String = input.trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+
+    if input.is_empty() {
+        return None;
+    }
+
+    Some(truncate(&input, limit).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(text: &str) -> Chat {
+        Chat { text: text.into(), ask: false }
+    }
+
+    #[test]
+    fn sets_messages_aside_from_keys() {
+        let mut inbox = Inbox::default();
+
+        inbox.receive(b"x\x1b]5379;sudo_pair;chat;text=one sec\x07y", true);
+
+        assert_eq!(vec![chat("one sec")], inbox.take_chats());
+        assert_eq!(b"xy".to_vec(),        inbox.take_keys());
+        assert!(inbox.take_chats().is_empty());
+        assert!(inbox.take_keys().is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_message() {
+        let mut inbox = Inbox::default();
+
+        inbox.receive(b"\x1b]5379;sudo_pair;chat;te", true);
+
+        assert!(inbox.take_chats().is_empty());
+
+        inbox.receive(b"xt=done\x07n", true);
+
+        assert_eq!(vec![chat("done")], inbox.take_chats());
+        assert_eq!(b"n".to_vec(),      inbox.take_keys());
+    }
+
+    #[test]
+    fn drops_everything_but_chats() {
+        let mut inbox = Inbox::default();
+        let endless   = [b'x'; 1024];
+
+        inbox.receive(b"\x1b]5379;sudo_pair;comment;text=hi\x07", true);
+        inbox.receive(b"\x1b]5379;sudo_pair;chat;text=", true);
+        inbox.receive(&endless, true);
+        inbox.receive(b"\x07\x1b[Ay", true);
+
+        assert!(inbox.take_chats().is_empty());
+        assert_eq!(b"\x1b[Ay".to_vec(), inbox.take_keys());
+    }
+
+    #[test]
+    fn takes_everything_as_keys_without_chat() {
+        let mut inbox = Inbox::default();
+
+        inbox.receive(b"\x1b]5379;sudo_pair;chat;text=hi\x07", false);
+
+        assert!(inbox.take_chats().is_empty());
+        assert_eq!(b"\x1b]5379;sudo_pair;chat;text=hi\x07".to_vec(), inbox.take_keys());
+    }
+
+    #[test]
+    fn shows_messages_on_their_own_line() {
+        assert_eq!(
+            b"[sudo_pair] your approver says: one sec\r\n".to_vec(),
+            notice(&chat("one sec"), true),
+        );
+
+        assert_eq!(
+            b"\r\n[sudo_pair] your approver says: one sec\r\n".to_vec(),
+            notice(&chat("one sec"), false),
+        );
+    }
+
+    #[test]
+    fn cleans_up_replies() {
+        assert_eq!(Some("ticket 42".into()),  reply(b"  ticket 42\n", 16));
+        assert_eq!(Some("abc".into()),        reply(b"a\x1bbc", 16));
+        assert_eq!(Some("tick\u{2026}".into()), reply(b"ticket 42", 5));
+        assert_eq!(None,                      reply(b" \n", 16));
+    }
+}
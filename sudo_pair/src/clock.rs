@@ -0,0 +1,181 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The plugin's view of time.
+//!
+//! Durations (timeouts, deadlines, how long a session ran, and the
+//! timestamps within recordings) are measured with the monotonic clock,
+//! which never jumps. The wall clock is only read to tell people and
+//! other programs when something happened, always in UTC, and every
+//! wall-clock time derived during a session is measured from a single
+//! reading of it (an `Anchor`). An NTP correction partway through a
+//! session can't make it appear to end before it began, or shift its
+//! recording out of line with its manifest.
+//!
+//! Pre-approval tokens and one-time codes are the exception, since
+//! they're only valid at particular wall-clock times and are checked
+//! against the wall clock as it is when they're used.
+//!
+//! Everything that times out, expires, or comes due reads the time from
+//! a `Clock` it's given rather than from the system, so tests can stand
+//! in a `ManualClock` and move time forward instead of sleeping through
+//! it.
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of the current time.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time on the monotonic clock.
+    fn instant(&self) -> Instant;
+
+    /// The current time on the wall clock.
+    fn wall(&self) -> SystemTime;
+}
+
+/// The system's clocks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A moment read from both clocks at once, from which wall-clock times
+/// are derived by how much time has passed on the monotonic clock.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Anchor {
+    instant: Instant,
+    time:    SystemTime,
+}
+
+impl Anchor {
+    /// The current moment on `clock`.
+    pub fn now(clock: &dyn Clock) -> Self {
+        Self {
+            instant: clock.instant(),
+            time:    clock.wall(),
+        }
+    }
+
+    /// The moment on the monotonic clock.
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// The moment on the wall clock.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// The moment `duration` later.
+    pub fn after(&self, duration: Duration) -> Self {
+        Self {
+            instant: self.instant + duration,
+            time:    self.time    + duration,
+        }
+    }
+
+    /// The wall-clock time at `instant`, as measured from this moment
+    /// rather than by reading the wall clock again.
+    pub fn time_at(&self, instant: Instant) -> SystemTime {
+        match instant.checked_duration_since(self.instant) {
+            Some(since) => self.time + since,
+            None        => self.time - self.instant.duration_since(instant),
+        }
+    }
+}
+
+/// A clock that only moves when it's told to, for tests.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: std::sync::Arc<std::sync::Mutex<Anchor>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    /// A clock stopped at `time` on the wall clock.
+    pub fn at(time: SystemTime) -> Self {
+        let now = Anchor { instant: Instant::now(), time };
+
+        Self { now: std::sync::Arc::new(std::sync::Mutex::new(now)) }
+    }
+
+    /// Moves both clocks forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+
+        *now = now.after(duration);
+    }
+
+    /// Sets the wall clock to `time` without moving the monotonic
+    /// clock, as an NTP correction would.
+    pub fn set_wall(&self, time: SystemTime) {
+        self.now.lock().unwrap().time = time;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn instant(&self) -> Instant {
+        self.now.lock().unwrap().instant
+    }
+
+    fn wall(&self) -> SystemTime {
+        self.now.lock().unwrap().time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn derives_wall_clock_times_from_the_monotonic_clock() {
+        let clock  = ManualClock::at(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let anchor = Anchor::now(&clock);
+
+        clock.advance(Duration::from_secs(30));
+
+        // the wall clock is stepped back an hour partway through
+        clock.set_wall(UNIX_EPOCH + Duration::from_secs(1_600_000_000 - 3600));
+        clock.advance(Duration::from_secs(30));
+
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(1_599_996_430), clock.wall());
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(1_600_000_060), anchor.time_at(clock.instant()));
+
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(1_599_999_990),
+            anchor.time_at(anchor.instant() - Duration::from_secs(10)),
+        );
+    }
+
+    #[test]
+    fn moves_both_clocks_together() {
+        let anchor = Anchor::now(&SystemClock);
+        let later  = anchor.after(Duration::from_secs(90));
+
+        assert_eq!(Duration::from_secs(90), later.instant() - anchor.instant());
+        assert_eq!(later.time(), anchor.time_at(later.instant()));
+    }
+}
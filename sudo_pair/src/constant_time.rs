@@ -0,0 +1,56 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Comparisons of secrets (e.g., pre-approval token signatures and
+//! one-time codes) that take the same time wherever the values first
+//! differ, so how long a guess takes to be rejected doesn't reveal how
+//! much of it was right.
+
+use std::hint;
+
+/// Compares `a` and `b` in time that depends only on their lengths.
+/// Lengths aren't secret: tokens and codes are a fixed length, and a
+/// guess of the wrong length is rejected anyway.
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    // every byte is compared, and the optimizer is kept from noticing
+    // that it could stop at the first that differs
+    let diff = a.iter()
+        .zip(b)
+        .fold(0, |acc, (x, y)| acc | hint::black_box(x ^ y));
+
+    diff == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_equal_values() {
+        assert!(eq(b"",       b""));
+        assert!(eq(b"287082", b"287082"));
+    }
+
+    #[test]
+    fn compares_unequal_values() {
+        assert!(!eq(b"287082", b"287083"));
+        assert!(!eq(b"287082", b"987082"));
+        assert!(!eq(b"287082", b"28708"));
+        assert!(!eq(b"",       b"2"));
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Facts about the command being run and where it lives on the
+//! filesystem, captured when the session opens so they can be recorded
+//! and shown to the approver.
+
+use crate::crypto::{to_hex, Sha256};
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use libc::uid_t;
+
+use sudo_plugin::Plugin;
+
+/// The filesystem context of the command being run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct CommandContext {
+    /// the working directory the command is run from
+    pub(crate) cwd: PathBuf,
+
+    /// the command's binary, as resolved by the policy plugin
+    pub(crate) executable: PathBuf,
+
+    /// the command's binary, with symlinks resolved
+    pub(crate) path: PathBuf,
+
+    pub(crate) dev:   u64,
+    pub(crate) ino:   u64,
+    pub(crate) owner: uid_t,
+    pub(crate) mode:  u32,
+
+    /// the hex-encoded SHA-256 digest of the binary's contents
+    pub(crate) sha256: String,
+
+    /// the nearest directory containing the binary (if any) that
+    /// anyone can write to
+    pub(crate) writable_dir: Option<PathBuf>,
+}
+
+impl CommandContext {
+    /// Inspects the command `plugin` is about to run.
+    pub(crate) fn capture(plugin: &Plugin) -> io::Result<Self> {
+        let command = &plugin.command_info.command;
+
+        // the command's path is relative to the chroot, if there is one
+        let command = match plugin.command_info.chroot.as_ref() {
+            Some(chroot) => Path::new(chroot).join(command.strip_prefix("/").unwrap_or(command)),
+            None         => command.clone(),
+        };
+
+        Self::inspect(plugin.cwd().to_owned(), &command)
+    }
+
+    fn inspect(cwd: PathBuf, command: &Path) -> io::Result<Self> {
+        let path     = fs::canonicalize(command)?;
+        let mut file = File::open(&path)?;
+        let metadata = file.metadata()?;
+
+        let mut hasher = Sha256::new();
+        let _          = io::copy(&mut file, &mut hasher)?;
+
+        Ok(Self {
+            cwd,
+            executable:   command.to_path_buf(),
+            dev:          metadata.dev(),
+            ino:          metadata.ino(),
+            owner:        metadata.uid(),
+            mode:         metadata.mode(),
+            sha256:       to_hex(&hasher.finish()),
+            writable_dir: writable_ancestor(&path),
+            path,
+        })
+    }
+
+    /// Returns a description of what's actually run when the command
+    /// was invoked as `argv0`, if its name doesn't match the binary's
+    /// (e.g., `ls` running `/bin/busybox` through a symlink).
+    pub(crate) fn resolution(&self, argv0: &[u8]) -> Option<String> {
+        let invoked = Path::new(OsStr::from_bytes(argv0));
+
+        if invoked.file_name() == self.path.file_name() {
+            return None;
+        }
+
+        let mut resolution = format!(
+            "{} runs {}",
+            invoked.display(),
+            self.executable.display(),
+        );
+
+        if self.executable != self.path {
+            resolution.push_str(&format!(", a link to {}", self.path.display()));
+        }
+
+        Some(resolution)
+    }
+
+    /// Returns a description of each reason the approver may not be
+    /// able to trust that the command is what it claims to be.
+    pub(crate) fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let     path     = self.path.display();
+
+        if self.owner != 0 {
+            warnings.push(format!("{} is owned by uid {}, not root", path, self.owner));
+        }
+
+        if self.mode & 0o022 != 0 {
+            warnings.push(format!("{} is writable by users other than its owner", path));
+        }
+
+        if let Some(dir) = self.writable_dir.as_ref() {
+            warnings.push(format!("{} is in {}, which anyone can write to", path, dir.display()));
+        }
+
+        warnings
+    }
+}
+
+/// Returns the nearest ancestor of `path` that's world-writable. Even
+/// with the sticky bit set, such a directory lets anyone plant files
+/// (and, above the binary, directories) of their own.
+fn writable_ancestor(path: &Path) -> Option<PathBuf> {
+    path.ancestors().skip(1).find(|dir|
+        fs::metadata(dir).map(|m| m.mode() & 0o002 != 0).unwrap_or(false)
+    ).map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn captures_the_command() {
+        let dir     = scratch::dir("context", "capture", true);
+        let command = dir.join("command");
+        let link    = dir.join("link");
+
+        fs::write(&command, b"abc").unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink(&command, &link).unwrap();
+
+        let context = CommandContext::inspect("/".into(), &link).unwrap();
+
+        assert_eq!(fs::canonicalize(&command).unwrap(), context.path);
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            context.sha256,
+        );
+
+        assert_eq!(0o755, context.mode & 0o7777);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn describes_commands_run_under_another_name() {
+        let dir     = scratch::dir("context", "resolution", true);
+        let busybox = dir.join("busybox");
+        let link    = dir.join("ls");
+
+        fs::write(&busybox, b"").unwrap();
+        std::os::unix::fs::symlink(&busybox, &link).unwrap();
+
+        let context = CommandContext::inspect("/".into(), &link).unwrap();
+
+        assert_eq!(None, context.resolution(b"busybox"));
+        assert_eq!(
+            Some(format!("ls runs {}, a link to {}", link.display(), context.path.display())),
+            context.resolution(b"ls"),
+        );
+
+        // inspected by its canonical path, so there's no link to follow
+        let busybox = fs::canonicalize(&busybox).unwrap();
+        let context = CommandContext::inspect("/".into(), &busybox).unwrap();
+
+        assert_eq!(None, context.resolution(b"./busybox"));
+        assert_eq!(
+            Some(format!("sh runs {}", busybox.display())),
+            context.resolution(b"sh"),
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn warns_about_writable_commands() {
+        let dir      = scratch::dir("context", "writable", true);
+        let shared   = dir.join("shared");
+        let command  = shared.join("command");
+
+        fs::create_dir(&shared).unwrap();
+        fs::write(&command, b"").unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o775)).unwrap();
+        fs::set_permissions(&shared,  fs::Permissions::from_mode(0o1777)).unwrap();
+
+        let context  = CommandContext::inspect("/".into(), &command).unwrap();
+        let warnings = context.warnings();
+
+        assert_eq!(Some(fs::canonicalize(&shared).unwrap()), context.writable_dir);
+        assert!(warnings.iter().any(|w| w.contains("writable by users other than its owner")));
+        assert!(warnings.iter().any(|w| w.contains("which anyone can write to")));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
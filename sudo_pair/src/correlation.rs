@@ -0,0 +1,111 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Correlation IDs tying together the sessions of one change that spans
+//! several hosts (e.g., the same command run across a fleet), so their
+//! approvals and recordings can be grouped afterwards.
+//!
+//! A session's correlation ID is read from the `SUDO_PAIR_CORRELATION_ID`
+//! environment variable, which sudoers must be configured to keep, or
+//! failing that, taken from the ticket the user cited with the
+//! `correlation_pattern` option. It's logged with every event of the
+//! session, and written to its manifest, its `session_index` entry, and
+//! what the close hook and the approval service are told.
+
+use regex_lite::Regex;
+use sudo_plugin::FromSudoOption;
+
+/// The environment variable a correlation ID is passed in.
+pub const CORRELATION_ENV : &str = "SUDO_PAIR_CORRELATION_ID";
+
+/// The longest a correlation ID may be.
+const MAX_LEN : usize = 64;
+
+/// A regular expression that takes a correlation ID from a ticket: the
+/// first group it captures if it has one, or otherwise all it matched
+/// (e.g., `^(CHG-[0-9]+)` takes `CHG-42` from `CHG-42/db01`).
+#[derive(Clone, Debug)]
+pub struct CorrelationPattern(Regex);
+
+impl CorrelationPattern {
+    /// The correlation ID in `ticket`, if the pattern finds a valid one
+    /// there.
+    pub fn extract(&self, ticket: &str) -> Option<String> {
+        let captures = self.0.captures(ticket)?;
+        let found    = captures.get(1).or_else(|| captures.get(0))?;
+
+        parse(found.as_str()).ok()
+    }
+}
+
+impl FromSudoOption for CorrelationPattern {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s)
+            .map(Self)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Checks that `value` is a valid correlation ID, returning it without
+/// surrounding whitespace. Correlation IDs are made of letters, digits,
+/// `.`, `_`, `-`, and `:`, since they're shown to approvers and written
+/// to logs and manifests as they are.
+pub fn parse(value: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        return Err("the correlation ID is empty".into());
+    }
+
+    if value.len() > MAX_LEN {
+        return Err(format!("the correlation ID is longer than {} characters", MAX_LEN));
+    }
+
+    if !value.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-:".contains(&b)) {
+        return Err(format!("{} isn't a valid correlation ID", value.escape_debug()));
+    }
+
+    Ok(value.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_correlation_ids() {
+        assert_eq!(Ok("CHG-42".into()),            parse(" CHG-42\n"));
+        assert_eq!(Ok("rollout:2020.09_a".into()), parse("rollout:2020.09_a"));
+
+        assert!(parse("").is_err());
+        assert!(parse("CHG 42").is_err());
+        assert!(parse("CHG-42\x1b[2J").is_err());
+        assert!(parse(&"x".repeat(MAX_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn extracts_correlation_ids_from_tickets() {
+        let grouped = CorrelationPattern::from_sudo_option("^(CHG-[0-9]+)/").unwrap();
+        let whole   = CorrelationPattern::from_sudo_option("CHG-[0-9]+").unwrap();
+
+        assert_eq!(Some("CHG-42".into()), grouped.extract("CHG-42/db01"));
+        assert_eq!(None,                  grouped.extract("CHG-42"));
+        assert_eq!(Some("CHG-42".into()), whole.extract("fixes CHG-42"));
+        assert_eq!(None,                  whole.extract("INC-1234"));
+
+        assert!(CorrelationPattern::from_sudo_option("(").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The hashing, MACs, and signature verification the plugin relies on,
+//! behind one small interface so that what implements them can be
+//! chosen when the plugin is built, e.g., for deployments that may only
+//! ship particular implementations.
+//!
+//! * `rustcrypto` (the default) uses the RustCrypto crates (`sha1`,
+//!   `sha2`, `hmac`) and `ed25519-dalek`
+//! * `ring` uses `ring`, and takes precedence over `rustcrypto` if both
+//!   are enabled (build with `--no-default-features` to leave the
+//!   RustCrypto crates out entirely)
+//!
+//! Ed25519 keys are only needed by the `signatures` feature, and are
+//! left out of the plugin without it.
+//!
+//! Both give identical results for everything here. Nothing else in the
+//! plugin should use a cryptographic crate directly; another backend is
+//! added by implementing each of these once more, under its own feature.
+
+#[cfg(not(any(feature = "rustcrypto", feature = "ring")))]
+compile_error!("a crypto backend is required: enable either the `rustcrypto` or `ring` feature");
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+
+/// The name of the backend the plugin was built with.
+pub const BACKEND : &str = if cfg!(feature = "ring") { "ring" } else { "rustcrypto" };
+
+/// An Ed25519 key and its signature of `[0x72]`, from RFC 8032 (section
+/// 7.1, test 2).
+#[cfg(any(test, feature = "signatures"))]
+const ED25519_KEY : [u8; 32] = [
+    0x3d, 0x40, 0x17, 0xc3, 0xe8, 0x43, 0x89, 0x5a, 0x92, 0xb7, 0x0a, 0xa7, 0x4d, 0x1b, 0x7e, 0xbc,
+    0x9c, 0x98, 0x2c, 0xcf, 0x2e, 0xc4, 0x96, 0x8c, 0xc0, 0xcd, 0x55, 0xf1, 0x2a, 0xf4, 0x66, 0x0c,
+];
+
+#[cfg(any(test, feature = "signatures"))]
+const ED25519_SIGNATURE : [u8; 64] = [
+    0x92, 0xa0, 0x09, 0xa9, 0xf0, 0xd4, 0xca, 0xb8, 0x72, 0x0e, 0x82, 0x0b, 0x5f, 0x64, 0x25, 0x40,
+    0xa2, 0xb2, 0x7b, 0x54, 0x16, 0x50, 0x3f, 0x8f, 0xb3, 0x76, 0x22, 0x23, 0xeb, 0xdb, 0x69, 0xda,
+    0x08, 0x5a, 0xc1, 0xe4, 0x3e, 0x15, 0x99, 0x6e, 0x45, 0x8f, 0x36, 0x13, 0xd0, 0xf1, 0x1d, 0x8c,
+    0x38, 0x7b, 0x2e, 0xae, 0xb4, 0x30, 0x2a, 0xee, 0xb0, 0x0d, 0x29, 0x16, 0x12, 0xbb, 0x0c, 0x00,
+];
+
+/// Checks the backend against known answers (from FIPS 180-2, RFC 4231,
+/// RFC 2202, and, with the `signatures` feature, RFC 8032), so a
+/// backend that was miscompiled or linked against the wrong library is
+/// caught by the self-test rather than by approvers who can't
+/// authenticate. Returns what gave the wrong answer, if anything did.
+pub fn self_test() -> Result<(), String> {
+    let answers = [
+        ("SHA-256",      sha256(b"abc"),                                             "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        ("HMAC-SHA-256", hmac_sha256(b"Jefe", b"what do ya want for nothing?"),      "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+        ("HMAC-SHA-1",   hmac_sha1(b"Jefe", b"what do ya want for nothing?"),        "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"),
+    ];
+
+    for (name, answer, known) in answers.iter() {
+        if to_hex(answer) != *known {
+            return Err(format!("{} gave the wrong answer", name));
+        }
+    }
+
+    #[cfg(feature = "signatures")]
+    {
+        let verified = Ed25519Key::from_bytes(&ED25519_KEY)
+            .is_some_and(|key| key.verify(&[0x72], &ED25519_SIGNATURE) && !key.verify(&[0x73], &ED25519_SIGNATURE));
+
+        if !verified {
+            return Err("Ed25519 gave the wrong answer".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes `bytes` (e.g., a digest or a MAC) as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes hex encoded by `to_hex` (in either case), or returns `None`
+/// if `s` isn't hex.
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let pairs = s.as_bytes().chunks_exact(2);
+
+    if !pairs.remainder().is_empty() || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    pairs
+        .map(|pair| std::str::from_utf8(pair).ok())
+        .map(|pair| pair.and_then(|p| u8::from_str_radix(p, 16).ok()))
+        .collect()
+}
+
+/// An incremental SHA-256 hash.
+#[derive(Clone)]
+pub struct Sha256 {
+    #[cfg(not(feature = "ring"))]
+    inner: sha2::Sha256,
+
+    #[cfg(feature = "ring")]
+    inner: ring::digest::Context,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    #[cfg(not(feature = "ring"))]
+    pub fn new() -> Self {
+        use sha2::Digest;
+
+        Self { inner: sha2::Sha256::new() }
+    }
+
+    #[cfg(feature = "ring")]
+    pub fn new() -> Self {
+        Self { inner: ring::digest::Context::new(&ring::digest::SHA256) }
+    }
+
+    #[cfg(not(feature = "ring"))]
+    pub fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.inner, data);
+    }
+
+    #[cfg(feature = "ring")]
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    #[cfg(not(feature = "ring"))]
+    pub fn finish(self) -> Vec<u8> {
+        sha2::Digest::finalize(self.inner).to_vec()
+    }
+
+    #[cfg(feature = "ring")]
+    pub fn finish(self) -> Vec<u8> {
+        self.inner.finish().as_ref().to_vec()
+    }
+}
+
+impl fmt::Debug for Sha256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sha256")
+    }
+}
+
+impl io::Write for Sha256 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The SHA-256 hash of `data`.
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// The SHA-512 hash of `data`.
+#[cfg(not(feature = "ring"))]
+pub fn sha512(data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+
+    sha2::Sha512::digest(data).to_vec()
+}
+
+#[cfg(feature = "ring")]
+pub fn sha512(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA512, data).as_ref().to_vec()
+}
+
+/// The HMAC-SHA-1 of `message` under `key`, which is only used where a
+/// standard requires it (TOTP).
+#[cfg(not(feature = "ring"))]
+pub fn hmac_sha1(key: &[u8], message: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(key)
+        .expect("HMAC can take a key of any size");
+
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(feature = "ring")]
+pub fn hmac_sha1(key: &[u8], message: &[u8]) -> Vec<u8> {
+    hmac(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key, message)
+}
+
+/// The HMAC-SHA-256 of `message` under `key`.
+#[cfg(not(feature = "ring"))]
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key)
+        .expect("HMAC can take a key of any size");
+
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(feature = "ring")]
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    hmac(ring::hmac::HMAC_SHA256, key, message)
+}
+
+#[cfg(feature = "ring")]
+fn hmac(algorithm: ring::hmac::Algorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+    ring::hmac::sign(&ring::hmac::Key::new(algorithm, key), message).as_ref().to_vec()
+}
+
+/// An Ed25519 public key that signatures can be verified against.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ed25519Key([u8; 32]);
+
+impl Ed25519Key {
+    /// Parses a key from its 32 bytes, or returns `None` if it isn't
+    /// one.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let key = <[u8; 32]>::try_from(bytes).ok()?;
+
+        #[cfg(not(feature = "ring"))]
+        let _ = ed25519_dalek::VerifyingKey::from_bytes(&key).ok()?;
+
+        Some(Self(key))
+    }
+
+    /// Whether `signature` is this key's signature of `message`.
+    /// Non-canonical signatures, which could be forged from a genuine
+    /// one, are rejected.
+    #[cfg(not(feature = "ring"))]
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let key       = ed25519_dalek::VerifyingKey::from_bytes(&self.0);
+        let signature = ed25519_dalek::Signature::from_slice(signature);
+
+        match (key, signature) {
+            (Ok(key), Ok(signature)) => key.verify_strict(message, &signature).is_ok(),
+            _                        => false,
+        }
+    }
+
+    #[cfg(feature = "ring")]
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &self.0)
+            .verify(message, signature)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn hashes() {
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            to_hex(&sha256(b"abc")),
+        );
+
+        let mut hasher = Sha256::new();
+
+        hasher.update(b"a");
+        hasher.write_all(b"bc").unwrap();
+
+        assert_eq!(sha256(b"abc"), hasher.finish());
+
+        assert_eq!(
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            to_hex(&sha512(b"abc")),
+        );
+    }
+
+    #[test]
+    fn encodes_hex() {
+        assert_eq!("00ff7f", to_hex(&[0x00, 0xff, 0x7f]));
+        assert_eq!("",       to_hex(&[]));
+
+        assert_eq!(Some(vec![0x00, 0xff, 0x7f]), from_hex("00ff7f"));
+        assert_eq!(Some(vec![0xab]),             from_hex("AB"));
+
+        assert_eq!(None, from_hex("abc"));
+        assert_eq!(None, from_hex("zz"));
+        assert_eq!(None, from_hex("+f"));
+    }
+
+    #[test]
+    fn authenticates_messages() {
+        // RFC 4231, test case 2, and RFC 2202, test case 2
+        assert_eq!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            to_hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        );
+
+        assert_eq!(
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+            to_hex(&hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+        );
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let key       = Ed25519Key::from_bytes(&ED25519_KEY).unwrap();
+        let signature = ED25519_SIGNATURE;
+
+        assert!( key.verify(&[0x72], &signature));
+        assert!(!key.verify(&[0x73], &signature));
+        assert!(!key.verify(&[0x72], &signature[..63]));
+
+        assert_eq!(None, Ed25519Key::from_bytes(&[0; 31]));
+    }
+
+    #[test]
+    fn passes_its_self_test() {
+        assert_eq!(Ok(()), self_test());
+    }
+}
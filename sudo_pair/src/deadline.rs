@@ -0,0 +1,291 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The deadline for a pair to approve a session, and the countdown
+//! shown to the user while they wait for one.
+
+use crate::clock::{Anchor, Clock};
+use crate::hints::Hints;
+use crate::template::Spec;
+use crate::threads::{Thread, Threads};
+
+use std::io::{self, Write};
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the countdown is redrawn.
+const TICK : Duration = Duration::from_secs(1);
+
+/// The moment a session is declined if it hasn't been approved.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    /// for measuring how long is left, unaffected by changes to the
+    /// system clock
+    instant: Instant,
+
+    /// for telling the approval client, which can't share our `Instant`
+    time: SystemTime,
+}
+
+impl Deadline {
+    /// Returns the deadline `timeout` after the current time on
+    /// `clock`, reading both of its clocks at once so they agree.
+    pub fn after_on(clock: &dyn Clock, timeout: Duration) -> Self {
+        let at = Anchor::now(clock).after(timeout);
+
+        Self {
+            instant: at.instant(),
+            time:    at.time(),
+        }
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// How long is left before the deadline passes on `clock`, or zero
+    /// if it already has.
+    pub fn remaining_on(&self, clock: &dyn Clock) -> Duration {
+        self.instant.saturating_duration_since(clock.instant())
+    }
+
+    /// Whether the deadline has passed on `clock`.
+    pub fn has_passed_on(&self, clock: &dyn Clock) -> bool {
+        clock.instant() >= self.instant
+    }
+
+    /// The deadline by the system clock, for showing the approver.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// The deadline in seconds since the epoch, rounded up so clients
+    /// never show less time than the user really has.
+    pub fn unix_secs(&self) -> u64 {
+        let since = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        since.as_secs() + if since.subsec_nanos() > 0 { 1 } else { 0 }
+    }
+}
+
+/// Displays the time left before a deadline (if there is one), or
+/// the hint for how long the user's been waiting, updating it in place
+/// on its own line, until it's dropped.
+#[derive(Debug)]
+pub struct Countdown {
+    stop:   Sender<()>,
+    thread: Option<Thread>,
+}
+
+impl Countdown {
+    /// Starts counting down to `deadline` on `out` (e.g., the user's
+    /// TTY), as measured by `clock`, on a thread started from
+    /// `threads`. `hints` take the countdown's place as the user waits,
+    /// expanded with `spec`.
+    pub fn start<W: Write + Send + 'static>(
+        threads:  &Threads,
+        mut out:  W,
+        deadline: Option<Deadline>,
+        hints:    Hints,
+        mut spec: Spec,
+        clock:    Arc<dyn Clock>,
+    ) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel();
+        let started         = clock.instant();
+
+        let thread = threads.spawn("countdown", move || {
+            // signals that cancel the session (e.g., Ctrl-C) have to
+            // interrupt the thread waiting on the pair, which they
+            // can't do if they're delivered here instead
+            block_signals();
+
+            // start below whatever prompt was printed, which may not
+            // have ended with a newline
+            let _ = out.write_all(b"\n");
+
+            loop {
+                let waited    = clock.instant().saturating_duration_since(started);
+                let remaining = deadline.map(|deadline| deadline.remaining_on(clock.as_ref()));
+
+                let _ = out.write_all(b"\r\x1b[K");
+                let _ = out.write_all(&line(&hints, &mut spec, waited, remaining));
+                let _ = out.flush();
+
+                match stopped.recv_timeout(TICK) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _                              => break,
+                }
+            }
+
+            let _ = out.write_all(b"\r\x1b[K");
+            let _ = out.flush();
+        })?;
+
+        Ok(Self { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for Countdown {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+
+        // joined, so the countdown is erased before anything else is
+        // written
+        drop(self.thread.take());
+    }
+}
+
+/// The line shown to the user once they've `waited`, with `remaining`
+/// before the deadline if there is one: the latest of `hints` they've
+/// waited for (with `%t` and `%w` in `spec` giving those times), or
+/// otherwise just how long they have left.
+fn line(hints: &Hints, spec: &mut Spec, waited: Duration, remaining: Option<Duration>) -> Vec<u8> {
+    let template = match hints.at(waited) {
+        Some(template) => template,
+        None           => return match remaining {
+            Some(remaining) => format!("waiting for approval: {} remaining", format(remaining)).into_bytes(),
+            None            => b"waiting for approval".to_vec(),
+        },
+    };
+
+    spec.replace(b't', remaining.map_or_else(|| "no limit".into(), format));
+    spec.replace(b'w', format(Duration::from_secs(waited.as_secs())));
+
+    // the line's redrawn in place, so nothing in it can be allowed to
+    // move the cursor
+    let mut line = spec.expand(template);
+
+    line.retain(|b| !b.is_ascii_control());
+    line
+}
+
+/// Formats `remaining` as minutes and seconds (e.g., `4:05`), rounding
+/// up so the countdown reaches `0:00` only once the deadline passes.
+pub fn format(remaining: Duration) -> String {
+    let secs = remaining.as_secs() + if remaining.subsec_nanos() > 0 { 1 } else { 0 };
+
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Blocks every signal on the calling thread, so they're delivered to
+/// other threads instead.
+fn block_signals() {
+    unsafe {
+        let mut set = mem::zeroed::<libc::sigset_t>();
+
+        let _ = libc::sigfillset(&mut set);
+        let _ = libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    use std::sync::Mutex;
+
+    /// A writer whose output can be inspected after it's been moved to
+    /// another thread.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn formats_remaining_time() {
+        assert_eq!("5:00",  format(Duration::from_secs(300)));
+        assert_eq!("4:05",  format(Duration::from_millis(244_001)));
+        assert_eq!("0:01",  format(Duration::from_millis(1)));
+        assert_eq!("0:00",  format(Duration::from_secs(0)));
+        assert_eq!("61:40", format(Duration::from_secs(3_700)));
+    }
+
+    #[test]
+    fn rounds_deadlines_up() {
+        let clock    = ManualClock::at(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let deadline = Deadline::after_on(&clock, Duration::from_millis(250));
+
+        assert_eq!(1_600_000_001, deadline.unix_secs());
+    }
+
+    #[test]
+    fn passes_on_the_clock_it_was_set_on() {
+        let clock    = ManualClock::at(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let deadline = Deadline::after_on(&clock, Duration::from_secs(90));
+
+        clock.advance(Duration::from_secs(89));
+
+        assert_eq!(Duration::from_secs(1), deadline.remaining_on(&clock));
+        assert!(!deadline.has_passed_on(&clock));
+
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(Duration::from_secs(0), deadline.remaining_on(&clock));
+        assert!(deadline.has_passed_on(&clock));
+    }
+
+    #[test]
+    fn counts_down_until_dropped() {
+        let clock     = ManualClock::at(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let deadline  = Deadline::after_on(&clock, Duration::from_secs(90));
+        let out       = Shared::default();
+        let threads   = Threads::default();
+
+        // the user has been waiting a while before it's shown
+        clock.advance(Duration::from_secs(65));
+
+        let countdown = Countdown::start(&threads, out.clone(), Some(deadline), Hints::default(), Spec::new(), Arc::new(clock)).unwrap();
+
+        drop(countdown);
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+
+        assert!(out.starts_with("\n\r\x1b[Kwaiting for approval: 0:25 remaining"));
+        assert!(out.ends_with("\r\x1b[K"));
+    }
+
+    #[test]
+    fn replaces_the_countdown_with_hints() {
+        let hints    = Hints::parse("30s still waiting; ping #oncall\n2m cancelling in %t\x1b[2J after %w on %h").unwrap();
+        let mut spec = Spec::new();
+
+        spec.replace(b'h', "db01");
+
+        let line = |spec: &mut Spec, waited, remaining: Option<u64>| String::from_utf8(line(
+            &hints,
+            spec,
+            Duration::from_millis(waited),
+            remaining.map(Duration::from_secs),
+        )).unwrap();
+
+        assert_eq!("waiting for approval: 3:00 remaining", line(&mut spec, 0,       Some(180)));
+        assert_eq!("waiting for approval",                 line(&mut spec, 0,       None));
+        assert_eq!("still waiting; ping #oncall",          line(&mut spec, 30_000,  Some(150)));
+        assert_eq!("cancelling in 0:59[2J after 2:00 on db01", line(&mut spec, 120_500, Some(59)));
+        assert_eq!("cancelling in no limit[2J after 10:00 on db01", line(&mut spec, 600_000, None));
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Delivery of the pairing instructions to the user invoking `sudo`,
+//! through whichever of the configured channels can reach them.
+
+use crate::options::DeliveryChannel;
+
+use std::io::{Error, ErrorKind, Result, Write};
+
+use sudo_plugin::errors::ErrorKind as SudoPluginErrorKind;
+use sudo_plugin::Plugin;
+
+/// The outcome of attempting to deliver a message.
+#[derive(Debug, Default)]
+pub(crate) struct Delivery {
+    /// the channel that delivered the message, if any did
+    pub(crate) channel: Option<DeliveryChannel>,
+
+    /// each channel that was tried and failed, and why
+    pub(crate) failures: Vec<(DeliveryChannel, Error)>,
+}
+
+/// Delivers `message` through the first of `channels` that can reach the
+/// user. Channels that can't apply to this invocation (e.g., `tty` when
+/// sudo didn't find one) are skipped. If the user interrupts a
+/// conversation, nothing further is tried, since they've chosen not to
+/// read it.
+pub(crate) fn deliver(
+    plugin:   &Plugin,
+    channels: &[DeliveryChannel],
+    message:  &[u8],
+) -> Delivery {
+    let mut delivery = Delivery::default();
+
+    for &channel in channels {
+        match attempt(plugin, channel, message) {
+            Ok(()) => {
+                delivery.channel = Some(channel);
+                break;
+            },
+
+            Err(e) => {
+                let interrupted = e.kind() == ErrorKind::Interrupted;
+
+                delivery.failures.push((channel, e));
+
+                if interrupted {
+                    break;
+                }
+            },
+        }
+    }
+
+    delivery
+}
+
+fn attempt(plugin: &Plugin, channel: DeliveryChannel, message: &[u8]) -> Result<()> {
+    match channel {
+        // sudo only provides the TTY it found in `user_info`; we write
+        // to it directly, which works even when stdout and stderr are
+        // redirected to pipes
+        DeliveryChannel::Tty => plugin.tty()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "sudo didn't detect a tty"))?
+            .write_all(message),
+
+        // sudo decides how to reach the user, which works even without
+        // a TTY (e.g., with an askpass helper, or over plain stderr)
+        DeliveryChannel::Conversation => plugin.conversation().info(message)
+            .map_err(|e| {
+                let kind = match e.kind() {
+                    SudoPluginErrorKind::ConversationInterrupted => ErrorKind::Interrupted,
+                    _                                            => ErrorKind::Other,
+                };
+
+                Error::new(kind, e.to_string())
+            }),
+
+        // TODO: the stderr write is returning an error (EINVAL) even
+        // though it prints successfully; I'm not entirely sure why. It
+        // started failing when I added some new operators for the
+        // templating code, but nothing in that commit seems like it
+        // should have obviously started causing writes to fail.
+        //
+        // EINVAL is raised by the underlying libc vfprintf call, which
+        // appears to only be problematic if the underlying write fails.
+        // As far as I can tell, this only happens if something isn't
+        // aligned correctly and the `fd` is opened with `O_DIRECT`. But
+        // it seems unlikely that STDIN is opened that way or that
+        // anything Rust allocates is misaligned. The other possibility
+        // is that STDIN is "unsuitable for writing" which also seems
+        // improbable. For now, I'm ignoring the situation but hopefully
+        // there's enough information here for someone (probably me) to
+        // pick up where I left off.
+        //
+        // Until then, a write to stderr is treated as having succeeded
+        // so that later channels don't print the message a second time.
+        DeliveryChannel::Stderr => {
+            let _ = plugin.stderr().write_all(message);
+            Ok(())
+        },
+    }
+}
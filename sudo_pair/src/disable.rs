@@ -0,0 +1,211 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A file whose presence disables the plugin, as an escape hatch for
+//! when pairing (or recording) is down, that doesn't require editing
+//! `sudo.conf` on every host.
+//!
+//! The file's contents are optional; its first line, if there is one,
+//! is taken as the reason it was disabled (e.g., an incident number).
+//! Since anyone who could write the file could skip pairing, it has to
+//! be a regular file owned by root and writable by nobody else, in a
+//! directory owned by root and writable by nobody else (so nobody else
+//! could have put it there, or could swap it for another).
+
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+
+/// The most of the reason that's kept, so the file can't flood the
+/// logs.
+const MAX_REASON : usize = 256;
+
+/// The plugin being disabled by its disable file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Disabled {
+    /// why, if the file says
+    pub(crate) reason: Option<String>,
+}
+
+impl Disabled {
+    /// Whether the plugin is disabled by the file at `path`.
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, String> {
+        // the file that's checked is the one that's read: symlinks
+        // aren't followed, and opening a FIFO doesn't wait for a writer
+        let opened = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+            .open(path);
+
+        let mut file = match opened {
+            Ok(file)   => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound        => return Ok(None),
+            Err(ref e) if e.raw_os_error() == Some(libc::ELOOP) => return Err("the disable file isn't a regular file".into()),
+            Err(e)     => return Err(e.to_string()),
+        };
+
+        let metadata = file.metadata().map_err(|e| e.to_string())?;
+
+        if !metadata.is_file() {
+            return Err("the disable file isn't a regular file".into());
+        }
+
+        check_ownership(&metadata).map_err(|problem| format!("the disable file {}", problem))?;
+
+        let dir = path.parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+
+        let dir_metadata = fs::metadata(dir).map_err(|e| e.to_string())?;
+
+        check_ownership(&dir_metadata).map_err(|problem| format!("the disable file's directory {}", problem))?;
+
+        Ok(Some(Self::read(&mut file)?))
+    }
+
+    /// Reads the reason the plugin was disabled from the open disable
+    /// `file`.
+    fn read(file: &mut File) -> Result<Self, String> {
+        let mut contents = Vec::new();
+
+        let _ = file.read_to_end(&mut contents).map_err(|e| e.to_string())?;
+
+        Ok(Self::parse(&String::from_utf8_lossy(&contents)))
+    }
+
+    /// Reads the reason the plugin was disabled from the file's
+    /// `contents`.
+    pub(crate) fn parse(contents: &str) -> Self {
+        let reason = contents.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(|line| line.chars().take(MAX_REASON).collect());
+
+        Self { reason }
+    }
+
+    /// The reason, if there is one, as it's shown to users following
+    /// "sudo_pair is disabled".
+    pub(crate) fn describe(&self) -> String {
+        self.reason.as_ref()
+            .map(|reason| format!(" ({})", reason))
+            .unwrap_or_default()
+    }
+}
+
+/// Checks that a file (or directory) with `metadata` is owned by root
+/// and writable by nobody else, saying what's wrong with it if not.
+fn check_ownership(metadata: &Metadata) -> Result<(), &'static str> {
+    if metadata.uid() != 0 {
+        return Err("isn't owned by root");
+    }
+
+    if metadata.mode() & 0o022 != 0 {
+        return Err("is writable by users other than root");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    #[test]
+    fn parses_reasons() {
+        assert_eq!(None, Disabled::parse("").reason);
+        assert_eq!(None, Disabled::parse(" \n\t\n").reason);
+
+        assert_eq!(
+            Some(String::from("INC-42: approvals are down")),
+            Disabled::parse("\n  INC-42: approvals are down  \nsee the incident channel\n").reason,
+        );
+
+        assert_eq!(MAX_REASON, Disabled::parse(&"x".repeat(1000)).reason.unwrap().len());
+    }
+
+    #[test]
+    fn describes_reasons() {
+        assert_eq!("", Disabled::parse("").describe());
+        assert_eq!(" (INC-42)", Disabled::parse("INC-42\n").describe());
+    }
+
+    #[test]
+    fn loads_nothing_without_a_file() {
+        let path = env::temp_dir().join(format!("sudo_pair-disabled-{}", process::id()));
+
+        assert_eq!(Ok(None), Disabled::load(&path));
+    }
+
+    #[test]
+    fn rejects_files_others_can_write() {
+        let path = env::temp_dir().join(format!("sudo_pair-disabled-writable-{}", process::id()));
+
+        fs::write(&path, "INC-42\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+
+        // whether or not the tests are run as root, the file can't be
+        // trusted
+        assert!(Disabled::load(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_files_in_directories_others_can_write() {
+        let path = env::temp_dir().join(format!("sudo_pair-disabled-shared-{}", process::id()));
+
+        fs::write(&path, "INC-42\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        // anyone can replace a file in the temporary directory, even if
+        // root owns the file
+        let error = Disabled::load(&path);
+
+        if unsafe { libc::geteuid() } == 0 {
+            assert_eq!(Err(String::from("the disable file's directory is writable by users other than root")), error);
+        } else {
+            assert!(error.is_err());
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_symlinks() {
+        let path = env::temp_dir().join(format!("sudo_pair-disabled-symlink-{}", process::id()));
+
+        std::os::unix::fs::symlink("/etc/hostname", &path).unwrap();
+
+        assert_eq!(
+            Err(String::from("the disable file isn't a regular file")),
+            Disabled::load(&path),
+        );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_anything_but_files() {
+        assert_eq!(
+            Err(String::from("the disable file isn't a regular file")),
+            Disabled::load(&env::temp_dir()),
+        );
+    }
+}
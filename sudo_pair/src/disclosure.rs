@@ -0,0 +1,137 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The notice shown to users as their session starts when
+//! `monitoring_notice` is enabled, telling them what of the session is
+//! captured and where it goes, for sites that have to disclose
+//! monitoring to the people being monitored.
+//!
+//! What's listed is worked out from the session as it was actually
+//! opened (e.g., a pre-approved session isn't watched by anyone, and a
+//! session whose recording couldn't be started isn't recorded), rather
+//! than written out by hand, so the notice can't claim less (or more)
+//! than is really captured.
+
+/// Where the notice template is read from, unless configured otherwise.
+pub const DEFAULT_NOTICE_PATH : &str = "/etc/sudo_pair.notice";
+
+/// The template used when none can be read from the filesystem.
+pub const DEFAULT_NOTICE : &[u8] = b"NOTICE: this session is monitored.\n%M";
+
+/// What of a session is captured, and where it goes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Disclosure {
+    /// whether an approver watches the session's output as it happens
+    pub watched: bool,
+
+    /// where the plugin records the session's output, if it does
+    pub recording: Option<String>,
+
+    /// whether the timing of the user's keystrokes is recorded
+    pub keystroke_timing: bool,
+
+    /// whether the commands the command runs are recorded
+    pub subcommands: bool,
+
+    /// where sudo logs the session's input and output, if it does
+    pub sudo_input:  Option<String>,
+    pub sudo_output: Option<String>,
+}
+
+impl Disclosure {
+    /// Whether nothing of the session is captured at all, in which
+    /// case there's nothing to disclose.
+    pub fn is_empty(&self) -> bool {
+        self.items().is_empty()
+    }
+
+    /// Each thing captured, as a sentence fragment addressed to the
+    /// user.
+    pub fn items(&self) -> Vec<String> {
+        let mut items = Vec::new();
+
+        if self.watched {
+            items.push("your output is shown to an approver as it happens".into());
+        }
+
+        if let Some(ref recording) = self.recording {
+            items.push(format!("your output is recorded to {}", recording));
+
+            if self.keystroke_timing {
+                items.push("the timing of your keystrokes is recorded, but not what you type".into());
+            }
+
+            if self.subcommands {
+                items.push("the commands your command runs are recorded".into());
+            }
+        }
+
+        if let Some(ref path) = self.sudo_input {
+            items.push(format!("your input is logged by sudo to {}", path));
+        }
+
+        if let Some(ref path) = self.sudo_output {
+            items.push(format!("your output is logged by sudo to {}", path));
+        }
+
+        items
+    }
+
+    /// The list of what's captured, as it's expanded for `%M`: one
+    /// item per line.
+    pub fn to_list(&self) -> Vec<u8> {
+        self.items().iter()
+            .map(|item| format!("  - {}\n", item))
+            .collect::<String>()
+            .into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_what_is_captured() {
+        let disclosure = Disclosure {
+            watched:          true,
+            recording:        Some("/var/log/sudo_pair/1600000000-31337.rec".into()),
+            keystroke_timing: true,
+            subcommands:      false,
+            sudo_input:       None,
+            sudo_output:      Some("/var/log/sudo-io/00/00/01".into()),
+        };
+
+        assert_eq!(
+            "  - your output is shown to an approver as it happens\n\
+             \x20 - your output is recorded to /var/log/sudo_pair/1600000000-31337.rec\n\
+             \x20 - the timing of your keystrokes is recorded, but not what you type\n\
+             \x20 - your output is logged by sudo to /var/log/sudo-io/00/00/01\n",
+            String::from_utf8(disclosure.to_list()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn only_recordings_capture_timing_and_subcommands() {
+        let disclosure = Disclosure {
+            keystroke_timing: true,
+            subcommands:      true,
+            ..Disclosure::default()
+        };
+
+        // without a recording, neither has anywhere to go
+        assert!(disclosure.is_empty());
+        assert!(disclosure.to_list().is_empty());
+    }
+}
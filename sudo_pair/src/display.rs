@@ -0,0 +1,438 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Adjustments made to session output before it's displayed to the
+//! approver. These only affect what's sent over the socket; sudo's own
+//! I/O logs always receive the output exactly as the command wrote it.
+
+use crate::options::{BinaryOutput, EscapeClass};
+use crate::sanitize::Sanitizer;
+use crate::suppression::{Reason, Suppression};
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// The number of bytes shown on each line of a hex dump.
+const HEXDUMP_WIDTH : usize = 16;
+
+/// Transforms session output for display to the approver.
+#[derive(Debug)]
+pub struct Display {
+    sanitizer: Option<Sanitizer>,
+    utf8:      Option<Utf8Chunker>,
+    binary:    BinaryFilter,
+}
+
+impl Display {
+    /// Creates a pipeline that removes escape sequences in any of the
+    /// `sanitize` classes, then (if `utf8_chunking` is enabled) holds
+    /// back multibyte characters split across writes until they're
+    /// complete, and finally handles binary output according to
+    /// `binary`.
+    pub fn new(
+        sanitize:      HashSet<EscapeClass>,
+        utf8_chunking: bool,
+        binary:        BinaryOutput,
+    ) -> Self {
+        Self {
+            sanitizer: Some(Sanitizer::new(sanitize)).filter(|s| !s.is_empty()),
+            utf8:      if utf8_chunking { Some(Utf8Chunker::default()) } else { None },
+            binary:    BinaryFilter::new(binary),
+        }
+    }
+
+    /// Returns the output to display for `data`, which may include
+    /// output held back from previous calls. Anything kept from the
+    /// approver along the way is reported by `take_suppressed`.
+    pub fn process(&mut self, data: &[u8]) -> Vec<u8> {
+        let sanitized = match self.sanitizer.as_mut() {
+            Some(sanitizer) => sanitizer.process(data),
+            None            => data.to_vec(),
+        };
+
+        let chunk = match self.utf8.as_mut() {
+            Some(utf8) => utf8.push(&sanitized),
+            None       => sanitized,
+        };
+
+        self.binary.filter(chunk)
+    }
+
+    /// Returns any output still being held back, for when the session
+    /// ends.
+    pub fn flush(&mut self) -> Vec<u8> {
+        let mut chunk = self.sanitizer.as_mut().map(Sanitizer::flush).unwrap_or_default();
+
+        if let Some(utf8) = self.utf8.as_mut() {
+            chunk = utf8.push(&chunk);
+            chunk.extend(utf8.flush());
+        }
+
+        let mut output = self.binary.filter(chunk);
+
+        output.extend(self.binary.end_run());
+        output
+    }
+
+    /// Returns what's been kept from the approver since this was last
+    /// called. Binary output has already been replaced with a marker in
+    /// the output itself; escape sequences are removed without one,
+    /// since text injected partway through a full-screen program's
+    /// output would garble it.
+    pub fn take_suppressed(&mut self) -> Vec<Suppression> {
+        let mut suppressed = Vec::new();
+
+        let escapes = self.sanitizer.as_mut().map_or(0, Sanitizer::take_removed);
+
+        if escapes > 0 {
+            suppressed.push(Suppression { reason: Reason::Escapes, bytes: escapes });
+        }
+
+        suppressed.append(&mut self.binary.suppressed);
+        suppressed
+    }
+}
+
+/// Replaces chunks of output that look binary according to the
+/// configured `BinaryOutput` mode.
+#[derive(Debug)]
+struct BinaryFilter {
+    mode: BinaryOutput,
+
+    /// the number of bytes in the current run of binary chunks
+    run: u64,
+
+    /// the runs that have been summarized
+    suppressed: Vec<Suppression>,
+}
+
+impl BinaryFilter {
+    fn new(mode: BinaryOutput) -> Self {
+        Self { mode, run: 0, suppressed: Vec::new() }
+    }
+
+    fn filter(&mut self, chunk: Vec<u8>) -> Vec<u8> {
+        if self.mode == BinaryOutput::Pass || chunk.is_empty() {
+            return chunk;
+        }
+
+        if !looks_binary(&chunk) {
+            let mut output = self.end_run();
+
+            output.extend(chunk);
+            return output;
+        }
+
+        let offset = self.run;
+
+        self.run += chunk.len() as u64;
+
+        match self.mode {
+            BinaryOutput::Hexdump => hexdump(&chunk, offset),
+            _                     => Vec::new(),
+        }
+    }
+
+    /// Ends the current run of binary output, returning anything that
+    /// should be displayed about it.
+    fn end_run(&mut self) -> Vec<u8> {
+        let run = std::mem::take(&mut self.run);
+
+        if run == 0 || self.mode != BinaryOutput::Summarize {
+            return Vec::new();
+        }
+
+        let suppression = Suppression { reason: Reason::Binary, bytes: run };
+
+        self.suppressed.push(suppression);
+
+        suppression.marker()
+    }
+}
+
+/// Returns true if `chunk` looks like binary data rather than output
+/// meant for a terminal: it contains a `NUL`, or more than an eighth of
+/// it is made up of control characters that terminals don't commonly
+/// receive.
+fn looks_binary(chunk: &[u8]) -> bool {
+    if chunk.contains(&0) {
+        return true;
+    }
+
+    let unusual = chunk.iter().filter(|b| match **b {
+        // tab, newline, form feed, carriage return, bell, backspace,
+        // and escape all appear in ordinary terminal output
+        b'\t' | b'\n' | 0x0c | b'\r' | 0x07 | 0x08 | 0x1b => false,
+        0x00 ..= 0x1f | 0x7f                             => true,
+        _                                                => false,
+    }).count();
+
+    unusual * 8 > chunk.len()
+}
+
+/// Formats `chunk` as a hex dump, with offsets beginning at `offset`.
+fn hexdump(chunk: &[u8], offset: u64) -> Vec<u8> {
+    let mut dump = String::new();
+
+    for (i, line) in chunk.chunks(HEXDUMP_WIDTH).enumerate() {
+        let _ = write!(dump, "{:08x} ", offset + (i * HEXDUMP_WIDTH) as u64);
+
+        for j in 0 .. HEXDUMP_WIDTH {
+            if j % 8 == 0 {
+                dump.push(' ');
+            }
+
+            match line.get(j) {
+                Some(byte) => { let _ = write!(dump, "{:02x} ", byte); },
+                None       => dump.push_str("   "),
+            }
+        }
+
+        dump.push_str(" |");
+        dump.extend(line.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }));
+        dump.push_str("|\r\n");
+    }
+
+    dump.into_bytes()
+}
+
+/// Re-chunks a byte stream so that no chunk ends partway through a
+/// UTF-8 encoded character. Bytes that aren't valid UTF-8 are passed
+/// through untouched; only a trailing sequence that could still become a
+/// valid character is held back.
+#[derive(Debug, Default)]
+pub struct Utf8Chunker {
+    pending: Vec<u8>,
+}
+
+impl Utf8Chunker {
+    /// Adds `data` to the stream, returning everything up to the last
+    /// complete character.
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut chunk = std::mem::take(&mut self.pending);
+
+        chunk.extend_from_slice(data);
+
+        let split = incomplete_suffix(&chunk);
+
+        self.pending = chunk.split_off(chunk.len() - split);
+
+        chunk
+    }
+
+    /// Returns whatever is being held back, complete or not.
+    pub fn flush(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Returns the length of the incomplete character at the end of `data`,
+/// if there is one. At most three bytes can be incomplete, since no
+/// character is encoded in more than four.
+fn incomplete_suffix(data: &[u8]) -> usize {
+    for (i, byte) in data.iter().rev().take(3).enumerate() {
+        let len = i + 1;
+
+        // continuation bytes don't tell us where the character started
+        if byte & 0b1100_0000 == 0b1000_0000 {
+            continue;
+        }
+
+        let width = match byte {
+            0xc2 ..= 0xdf => 2,
+            0xe0 ..= 0xef => 3,
+            0xf0 ..= 0xf4 => 4,
+            _             => 1,
+        };
+
+        return if len < width { len } else { 0 };
+    }
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The length of the simulated runaway streams: more than would fit
+    /// in memory on most machines were any of it retained, though only
+    /// when tests are optimized (`cargo test --release`), since pushing
+    /// gigabytes through an unoptimized build takes minutes.
+    const HUGE : usize = if cfg!(debug_assertions) { 64 << 20 } else { 4 << 30 };
+
+    /// The size of each write in the simulated streams.
+    const CHUNK : usize = 64 << 10;
+
+    fn everything() -> HashSet<EscapeClass> {
+        [
+            EscapeClass::Title,
+            EscapeClass::Clipboard,
+            EscapeClass::Osc,
+            EscapeClass::Dcs,
+            EscapeClass::Apc,
+            EscapeClass::Reports,
+            EscapeClass::Paste,
+            EscapeClass::Protocol,
+        ].iter().cloned().collect()
+    }
+
+    #[test]
+    fn passes_through_complete_characters() {
+        let mut chunker = Utf8Chunker::default();
+
+        assert_eq!("héllo, wörld ✓".as_bytes(), &chunker.push("héllo, wörld ✓".as_bytes())[..]);
+        assert!(chunker.flush().is_empty());
+    }
+
+    #[test]
+    fn holds_back_split_characters() {
+        let mut chunker = Utf8Chunker::default();
+        let check       = "✓".as_bytes();
+
+        assert_eq!(b"ok ", &chunker.push(&[b"ok ", &check[..1]].concat())[..]);
+        assert!(chunker.push(&check[1..2]).is_empty());
+        assert_eq!(b"\xe2\x9c\x93!", &chunker.push(&[&check[2..], b"!"].concat())[..]);
+    }
+
+    #[test]
+    fn passes_through_invalid_bytes() {
+        let mut chunker = Utf8Chunker::default();
+
+        assert_eq!(b"\xff\xfe", &chunker.push(b"\xff\xfe")[..]);
+        assert_eq!(b"a\x80", &chunker.push(b"a\x80")[..]);
+    }
+
+    #[test]
+    fn flushes_incomplete_characters() {
+        let mut chunker = Utf8Chunker::default();
+
+        assert!(chunker.push(b"\xf0\x9f").is_empty());
+        assert_eq!(b"\xf0\x9f", &chunker.flush()[..]);
+    }
+
+    #[test]
+    fn is_transparent_when_disabled() {
+        let mut display = Display::new(HashSet::new(), false, BinaryOutput::Pass);
+
+        assert_eq!(b"\xe2", &display.process(b"\xe2")[..]);
+        assert_eq!(b"\x00\x01", &display.process(b"\x00\x01")[..]);
+        assert!(display.flush().is_empty());
+    }
+
+    #[test]
+    fn detects_binary_output() {
+        assert!(!looks_binary(b"\x1b[1mhello\x1b[0m\r\n\tworld\x07"));
+        assert!(!looks_binary("✓ done\n".as_bytes()));
+
+        assert!(looks_binary(b"ELF\x00"));
+        assert!(looks_binary(b"\x01\x02\x03\x04abcd"));
+    }
+
+    #[test]
+    fn summarizes_runs_of_binary_output() {
+        let mut display = Display::new(HashSet::new(), false, BinaryOutput::Summarize);
+
+        assert_eq!(b"$ cat a.out\r\n", &display.process(b"$ cat a.out\r\n")[..]);
+        assert!(display.process(b"\x7fELF\x02\x01\x01\x00").is_empty());
+        assert!(display.process(b"\x00\x00\x00").is_empty());
+        assert_eq!(
+            &b"\r\n[11 bytes of binary output suppressed]\r\n$ "[..],
+            &display.process(b"$ ")[..],
+        );
+
+        assert!(display.process(b"\x00").is_empty());
+        assert_eq!(&b"\r\n[1 byte of binary output suppressed]\r\n"[..], &display.flush()[..]);
+
+        assert_eq!(
+            vec![
+                Suppression { reason: Reason::Binary, bytes: 11 },
+                Suppression { reason: Reason::Binary, bytes: 1 },
+            ],
+            display.take_suppressed(),
+        );
+    }
+
+    #[test]
+    fn reports_removed_escape_sequences() {
+        let mut display = Display::new(everything(), false, BinaryOutput::Pass);
+
+        assert_eq!(b"$ ", &display.process(b"\x1b]0;title\x07$ ")[..]);
+        assert_eq!(
+            vec![Suppression { reason: Reason::Escapes, bytes: 10 }],
+            display.take_suppressed(),
+        );
+
+        assert_eq!(b"ls", &display.process(b"ls")[..]);
+        assert!(display.take_suppressed().is_empty());
+    }
+
+    #[test]
+    fn hexdumps_binary_output() {
+        let mut display = Display::new(HashSet::new(), false, BinaryOutput::Hexdump);
+
+        assert_eq!(
+            "00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|\r\n\
+             00000010  41                                                |A|\r\n".as_bytes(),
+            &display.process(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00A")[..],
+        );
+
+        // offsets continue across chunks in the same run
+        assert!(String::from_utf8(display.process(b"\x00")).unwrap().starts_with("00000011 "));
+    }
+
+    #[test]
+    fn streams_runaway_output() {
+        let mut display = Display::new(everything(), true, BinaryOutput::Summarize);
+        let     chunk   = vec![b'a'; CHUNK];
+
+        for _ in 0 .. HUGE / CHUNK {
+            assert_eq!(CHUNK, display.process(&chunk).len());
+        }
+
+        assert!(display.flush().is_empty());
+    }
+
+    #[test]
+    fn streams_runaway_escape_sequences() {
+        let mut display = Display::new(everything(), true, BinaryOutput::Summarize);
+        let     chunk   = vec![b'a'; CHUNK];
+
+        // a clipboard write that never ends is discarded as it arrives,
+        // rather than buffered in the hope of seeing its end
+        assert!(display.process(b"\x1b]52;c;").is_empty());
+
+        for _ in 0 .. HUGE / CHUNK {
+            assert!(display.process(&chunk).is_empty());
+        }
+
+        assert!(display.flush().is_empty());
+        assert_eq!(b"ok", &display.process(b"ok")[..]);
+    }
+
+    #[test]
+    fn streams_runaway_binary_output() {
+        let mut display = Display::new(HashSet::new(), false, BinaryOutput::Summarize);
+        let     chunk   = vec![0; CHUNK];
+
+        for _ in 0 .. HUGE / CHUNK {
+            assert!(display.process(&chunk).is_empty());
+        }
+
+        assert_eq!(
+            format!("\r\n[{} bytes of binary output suppressed]\r\n", HUGE).as_bytes(),
+            &display.flush()[..],
+        );
+    }
+}
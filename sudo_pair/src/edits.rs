@@ -0,0 +1,405 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! What `sudoedit` sessions changed. The files being edited are hashed
+//! (and, if diffs are enabled, read) when the session opens, and
+//! compared against what's there once sudo has written the edited
+//! copies back.
+
+use crate::crypto::{to_hex, Sha256};
+
+use sudo_pair_types::manifest::FileEdit;
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+/// The lines of context around each change in a diff.
+const CONTEXT : usize = 3;
+
+/// The most pairs of changed lines a diff compares line-by-line; larger
+/// changes are shown as the old lines replaced by the new ones.
+const MAX_COMPARISONS : usize = 1 << 20;
+
+/// Appended to diffs cut short by the size limit.
+const TRUNCATION_MARKER : &str = "[diff truncated]\n";
+
+/// The files being edited, as they were before the editor ran.
+#[derive(Debug)]
+pub(crate) struct EditCapture {
+    /// the most bytes of a file (or of its diff) to diff; `0` disables
+    /// diffs
+    max_diff: usize,
+
+    before: Vec<State>,
+}
+
+/// A file's state at some point during the session.
+#[derive(Debug)]
+struct State {
+    path: PathBuf,
+
+    /// the hex-encoded SHA-256 digest of the file's contents, or `None`
+    /// if it doesn't exist (e.g., it's being created) or couldn't be
+    /// read
+    sha256: Option<String>,
+
+    /// the file's contents, if they're needed for a diff and no larger
+    /// than its limit
+    contents: Option<Vec<u8>>,
+}
+
+impl EditCapture {
+    /// Captures the state of `files` before they're edited, keeping the
+    /// contents of any no larger than `max_diff` bytes so they can be
+    /// diffed afterward.
+    pub(crate) fn before(files: Vec<PathBuf>, max_diff: usize) -> Self {
+        Self {
+            max_diff,
+            before: files.into_iter().map(|path| State::read(path, max_diff)).collect(),
+        }
+    }
+
+    /// Compares each file against how it was before it was edited.
+    pub(crate) fn after(self) -> Vec<FileEdit> {
+        let max_diff = self.max_diff;
+
+        self.before.into_iter().map(|before| {
+            let after = State::read(before.path.clone(), max_diff);
+
+            let diff = match (before.contents.as_ref(), after.contents.as_ref()) {
+                _ if before.sha256 == after.sha256 => None,
+                (Some(old), Some(new))             => Some(unified(old, new, max_diff)),
+                _                                  => None,
+            };
+
+            FileEdit {
+                path:          before.path.to_string_lossy().into_owned(),
+                before_sha256: before.sha256,
+                after_sha256:  after.sha256,
+                diff,
+            }
+        }).collect()
+    }
+}
+
+impl State {
+    fn read(path: PathBuf, max_contents: usize) -> Self {
+        let (sha256, contents) = match digest(&path, max_contents) {
+            Ok((sha256, contents)) => (Some(sha256), contents),
+            Err(_)                 => (None, None),
+        };
+
+        Self { path, sha256, contents }
+    }
+}
+
+/// Hashes the file at `path`, returning its contents as well if they're
+/// no larger than `max_contents` bytes.
+fn digest(path: &PathBuf, max_contents: usize) -> io::Result<(String, Option<Vec<u8>>)> {
+    let mut file     = File::open(path)?;
+    let mut hasher   = Sha256::new();
+    let mut contents = Some(Vec::new()).filter(|_| max_contents > 0);
+    let mut buf      = [0; 8192];
+
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0)  => break,
+            Ok(n)  => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        hasher.update(&buf[..n]);
+
+        // stop keeping a file's contents once they're too large to diff,
+        // but keep hashing it
+        match contents.as_mut() {
+            Some(c) if c.len() + n <= max_contents => c.extend_from_slice(&buf[..n]),
+            _                                      => contents = None,
+        }
+    }
+
+    Ok((to_hex(&hasher.finish()), contents))
+}
+
+/// A step in transforming one file's lines into another's.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Returns a unified diff of `old` and `new`, cut short after `max`
+/// bytes.
+fn unified(old: &[u8], new: &[u8], max: usize) -> String {
+    let old = String::from_utf8_lossy(old);
+    let new = String::from_utf8_lossy(new);
+    let a   = lines(&old);
+    let b   = lines(&new);
+    let ops = ops(&a, &b);
+
+    // the position in each file at the start of each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+
+    for op in &ops {
+        positions.push((i, j));
+
+        match op {
+            Op::Equal  => { i += 1; j += 1; },
+            Op::Delete => i += 1,
+            Op::Insert => j += 1,
+        }
+    }
+
+    positions.push((i, j));
+
+    let mut diff = String::new();
+
+    for (start, end) in hunks(&ops) {
+        let (a_start, b_start) = positions[start];
+        let (a_end,   b_end)   = positions[end];
+
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(a_start, a_end - a_start),
+            range(b_start, b_end - b_start),
+        ));
+
+        for (op, &(i, j)) in ops[start..end].iter().zip(&positions[start..end]) {
+            let (prefix, line) = match op {
+                Op::Equal  => (' ', a[i]),
+                Op::Delete => ('-', a[i]),
+                Op::Insert => ('+', b[j]),
+            };
+
+            diff.push(prefix);
+            diff.push_str(line);
+
+            if !line.ends_with('\n') {
+                diff.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    if diff.len() > max {
+        let mut end = max;
+
+        while !diff.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        diff.truncate(end);
+
+        if !diff.ends_with('\n') {
+            diff.push('\n');
+        }
+
+        diff.push_str(TRUNCATION_MARKER);
+    }
+
+    diff
+}
+
+/// Splits `s` into lines, each keeping its newline.
+fn lines(s: &str) -> Vec<&str> {
+    s.split_inclusive('\n').collect()
+}
+
+/// Returns the steps transforming `a` into `b`, keeping as many lines
+/// in common as possible.
+fn ops(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let a_mid = &a[prefix .. a.len() - suffix];
+    let b_mid = &b[prefix .. b.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+
+    if a_mid.len().saturating_mul(b_mid.len()) <= MAX_COMPARISONS {
+        // the length of the longest common subsequence of every pair of
+        // suffixes of the changed lines
+        let width   = b_mid.len() + 1;
+        let mut lcs = vec![0_u32; (a_mid.len() + 1) * width];
+
+        for i in (0 .. a_mid.len()).rev() {
+            for j in (0 .. b_mid.len()).rev() {
+                lcs[i * width + j] = if a_mid[i] == b_mid[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    cmp::max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+
+        while i < a_mid.len() && j < b_mid.len() {
+            if a_mid[i] == b_mid[j] {
+                ops.push(Op::Equal);
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                ops.push(Op::Delete);
+                i += 1;
+            } else {
+                ops.push(Op::Insert);
+                j += 1;
+            }
+        }
+
+        ops.extend(vec![Op::Delete; a_mid.len() - i]);
+        ops.extend(vec![Op::Insert; b_mid.len() - j]);
+    } else {
+        ops.extend(vec![Op::Delete; a_mid.len()]);
+        ops.extend(vec![Op::Insert; b_mid.len()]);
+    }
+
+    ops.extend(vec![Op::Equal; suffix]);
+    ops
+}
+
+/// Groups changes separated by no more than twice the context into
+/// hunks, returning the range of `ops` each covers (including context).
+fn hunks(ops: &[Op]) -> Vec<(usize, usize)> {
+    let mut hunks : Vec<(usize, usize)> = Vec::new();
+
+    for (i, _) in ops.iter().enumerate().filter(|(_, op)| **op != Op::Equal) {
+        let start = i.saturating_sub(CONTEXT);
+        let end   = cmp::min(i + 1 + CONTEXT, ops.len());
+
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _                             => hunks.push((start, end)),
+        }
+    }
+
+    hunks
+}
+
+/// Formats a range of `len` lines starting after line `start` the way
+/// unified diffs do.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    use std::fs;
+
+    #[test]
+    fn diffs_changed_lines_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n";
+
+        assert_eq!(
+            "@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n",
+            unified(old.as_bytes(), new.as_bytes(), 4096),
+        );
+    }
+
+    #[test]
+    fn diffs_additions_and_missing_newlines() {
+        assert_eq!(
+            "@@ -0,0 +1 @@\n+root:x:0:0\n",
+            unified(b"", b"root:x:0:0\n", 4096),
+        );
+
+        assert_eq!(
+            "@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+a\n",
+            unified(b"a", b"a\n", 4096),
+        );
+    }
+
+    #[test]
+    fn separates_distant_changes_into_hunks() {
+        let old : String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let new = old.replace("2\n", "two\n").replace("19\n", "nineteen\n");
+
+        let diff = unified(old.as_bytes(), new.as_bytes(), 4096);
+
+        assert_eq!(2, diff.matches("@@ -").count());
+        assert!(diff.starts_with("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n"));
+    }
+
+    #[test]
+    fn truncates_large_diffs() {
+        let diff = unified(b"a\n", b"b\n", 4);
+
+        assert_eq!(format!("@@ -\n{}", TRUNCATION_MARKER), diff);
+    }
+
+    #[test]
+    fn captures_what_changed() {
+        let dir       = scratch::dir("edits", "changed", true);
+        let changed   = dir.join("changed");
+        let unchanged = dir.join("unchanged");
+        let created   = dir.join("created");
+
+        fs::write(&changed,   "old\n").unwrap();
+        fs::write(&unchanged, "same\n").unwrap();
+
+        let capture = EditCapture::before(vec![changed.clone(), unchanged, created.clone()], 4096);
+
+        fs::write(&changed, "new\n").unwrap();
+        fs::write(&created, "created\n").unwrap();
+
+        let edits = capture.after();
+
+        assert_ne!(edits[0].before_sha256, edits[0].after_sha256);
+        assert_eq!(Some("@@ -1 +1 @@\n-old\n+new\n"), edits[0].diff.as_deref());
+
+        assert_eq!(edits[1].before_sha256, edits[1].after_sha256);
+        assert_eq!(None, edits[1].diff);
+
+        assert_eq!(None, edits[2].before_sha256);
+        assert!(edits[2].after_sha256.is_some());
+        assert_eq!(created.to_string_lossy(), edits[2].path);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hashes_without_diffing_when_disabled() {
+        let dir  = scratch::dir("edits", "disabled", true);
+        let file = dir.join("file");
+
+        fs::write(&file, "old\n").unwrap();
+
+        let capture = EditCapture::before(vec![file.clone()], 0);
+
+        fs::write(&file, "new\n").unwrap();
+
+        let edits = capture.after();
+
+        assert_ne!(edits[0].before_sha256, edits[0].after_sha256);
+        assert_eq!(None, edits[0].diff);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
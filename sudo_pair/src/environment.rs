@@ -0,0 +1,164 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Highlights of environment variables that can change what a command
+//! does without changing the command itself (e.g., `LD_PRELOAD`), so the
+//! approver isn't approving something other than what they see.
+
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use sudo_plugin::{FromSudoOption, FromSudoOptionList, OptionMap};
+
+/// The name of an environment variable, or a prefix of names if it ends
+/// with `*` (e.g., `LD_*`).
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct EnvPattern(String);
+
+impl EnvPattern {
+    fn matches(&self, name: &[u8]) -> bool {
+        match self.0.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix.as_bytes()),
+            None         => name == self.0.as_bytes(),
+        }
+    }
+}
+
+impl From<&str> for EnvPattern {
+    fn from(s: &str) -> Self {
+        Self(s.into())
+    }
+}
+
+impl FromSudoOption for EnvPattern {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.contains('=') {
+            return Err(format!("invalid environment variable name {}", s));
+        }
+
+        Ok(s.into())
+    }
+}
+
+impl FromSudoOptionList for EnvPattern {}
+
+/// Returns a diff-style line for each variable in `env` matching any of
+/// `risky` (e.g., `+ LD_PRELOAD=/tmp/x.so`), followed by a line for each
+/// entry in `PATH` that lets someone other than root choose which binary
+/// is run.
+pub fn highlights(env: &OptionMap, risky: &[EnvPattern]) -> Vec<String> {
+    let mut names : Vec<_> = env.keys()
+        .filter(|name| risky.iter().any(|pattern| pattern.matches(name)))
+        .collect();
+
+    names.sort();
+
+    let mut lines : Vec<_> = names.into_iter().map(|name| format!(
+        "+ {}={}",
+        String::from_utf8_lossy(name),
+        String::from_utf8_lossy(env.get_bytes(name).unwrap_or_default()),
+    )).collect();
+
+    if let Some(path) = env.get_bytes(b"PATH") {
+        lines.extend(path_highlights(path));
+    }
+
+    lines
+}
+
+fn path_highlights(path: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for entry in path.split(|b| *b == b':') {
+        let dir = Path::new(std::ffi::OsStr::from_bytes(entry));
+
+        if !dir.is_absolute() {
+            lines.push(format!(
+                "! PATH includes {:?}, which is relative to the working directory",
+                dir.display().to_string(),
+            ));
+
+            continue;
+        }
+
+        let writable = fs::metadata(dir)
+            .map(|m| m.mode() & 0o002 != 0)
+            .unwrap_or(false);
+
+        if writable {
+            lines.push(format!(
+                "! PATH includes {}, which anyone can write to",
+                dir.display(),
+            ));
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    fn env(vars: &[&[u8]]) -> OptionMap {
+        let mut ptrs : Vec<_> = vars.iter().map(|v| v.as_ptr() as _).collect();
+
+        ptrs.push(ptr::null());
+
+        unsafe { OptionMap::from_raw(ptrs.as_ptr()) }
+    }
+
+    fn risky() -> Vec<EnvPattern> {
+        vec!["LD_*".into(), "BASH_ENV".into()]
+    }
+
+    #[test]
+    fn highlights_risky_variables() {
+        let env = env(&[
+            b"HOME=/root\0",
+            b"LD_PRELOAD=/tmp/x.so\0",
+            b"BASH_ENV=/tmp/rc\0",
+            b"BASH_ENVY=1\0",
+            b"PATH=/usr/bin:/bin\0",
+        ]);
+
+        assert_eq!(vec![
+            "+ BASH_ENV=/tmp/rc".to_string(),
+            "+ LD_PRELOAD=/tmp/x.so".to_string(),
+        ], highlights(&env, &risky()));
+    }
+
+    #[test]
+    fn highlights_unsafe_path_entries() {
+        let env = env(&[b"PATH=.:/usr/bin::/tmp\0"]);
+
+        assert_eq!(vec![
+            "! PATH includes \".\", which is relative to the working directory".to_string(),
+            "! PATH includes \"\", which is relative to the working directory".to_string(),
+            "! PATH includes /tmp, which anyone can write to".to_string(),
+        ], highlights(&env, &risky()));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(EnvPattern::from_sudo_option("").is_err());
+        assert!(EnvPattern::from_sudo_option("A=B").is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use std::error::Error as StdError;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::result::Result as StdResult;
 
@@ -8,27 +9,121 @@ use sudo_plugin::errors::{
     ErrorKind as SudoPluginErrorKind,
 };
 
-pub(crate) type Result<T> = StdResult<T, Error>;
+pub type Result<T> = StdResult<T, Error>;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub(crate) enum ErrorKind {
+pub enum ErrorKind {
+    ApprovalTimedOut,
+    ApproverTooNarrow,
+    ApproverUnauthenticated,
+    AutomationAccount,
+    Background,
+    Cancelled,
     CommunicationError,
+    InvalidOptions,
+    NoTty,
+    NonInteractive,
+    PairRequired,
+    PreviewTimedOut,
+    ReapprovalTimedOut,
+    RecordingFailed,
+    RecordingRequired,
     SessionDeclined,
+    SessionExpired,
+    SessionKilled,
     SessionTerminated,
     StdinRedirected,
     SudoToUserAndGroup,
+    TicketRejected,
+    TooManySessions,
+    Unhealthy,
+    UnknownApproverGroup,
+    UnknownSocketOwner,
+    UnreadableApproverKeys,
+    Unrecorded,
 }
 
 impl ErrorKind {
     fn as_str(self) -> &'static str {
         match self {
-            ErrorKind::CommunicationError => "couldn't establish communications with the pair",
-            ErrorKind::SessionDeclined    => "pair declined the session",
-            ErrorKind::SessionTerminated  => "pair ended the session",
-            ErrorKind::StdinRedirected    => "redirection of stdin to paired sessions is prohibited",
-            ErrorKind::SudoToUserAndGroup => "the -u and -g options may not both be specified",
+            ErrorKind::ApprovalTimedOut        => "the session wasn't approved in time",
+            ErrorKind::ApproverTooNarrow       => "the approver's terminal is narrower than the user's",
+            ErrorKind::ApproverUnauthenticated => "the approver couldn't be authenticated",
+            ErrorKind::AutomationAccount       => "automation accounts can't wait for a pair",
+            ErrorKind::Background              => "paired sessions can't be run in the background",
+            ErrorKind::Cancelled               => "session cancelled while waiting for a pair",
+            ErrorKind::CommunicationError      => "couldn't establish communications with the pair",
+            ErrorKind::InvalidOptions          => "the plugin options in sudo.conf are invalid",
+            ErrorKind::NoTty                   => "paired sessions require a tty",
+            ErrorKind::NonInteractive          => "paired sessions can't be run non-interactively",
+            ErrorKind::PairRequired            => "this host requires sessions be approved by a pair, and this one wasn't",
+            ErrorKind::PreviewTimedOut         => "the approver didn't answer in time",
+            ErrorKind::ReapprovalTimedOut      => "the session wasn't re-approved in time",
+            ErrorKind::RecordingFailed         => "the session couldn't be recorded",
+            ErrorKind::RecordingRequired       => "this host requires sessions be recorded, and this one wasn't",
+            ErrorKind::SessionDeclined         => "pair declined the session",
+            ErrorKind::SessionExpired          => "the session exceeded its maximum duration",
+            ErrorKind::SessionKilled           => "an administrator ended the session",
+            ErrorKind::SessionTerminated       => "pair ended the session",
+            ErrorKind::StdinRedirected         => "redirection of stdin to paired sessions is prohibited",
+            ErrorKind::SudoToUserAndGroup      => "the -u and -g options may not both be specified",
+            ErrorKind::TicketRejected          => "the ticket cited for the session couldn't be verified",
+            ErrorKind::TooManySessions         => "too many sessions are already running on this host",
+            ErrorKind::Unhealthy               => "the plugin failed its self-check",
+            ErrorKind::UnknownApproverGroup    => "the group required to approve the session doesn't exist",
+            ErrorKind::UnknownSocketOwner      => "the owner configured for the session's socket doesn't exist",
+            ErrorKind::UnreadableApproverKeys  => "the keys approvers authenticate with couldn't be read",
+            ErrorKind::Unrecorded              => "unpaired sessions must be recorded by sudo",
         }
     }
+
+    /// A stable identifier for the kind of error, for programs reading
+    /// the records of rejected sessions.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::ApprovalTimedOut        => "approval_timed_out",
+            ErrorKind::ApproverTooNarrow       => "approver_too_narrow",
+            ErrorKind::ApproverUnauthenticated => "approver_unauthenticated",
+            ErrorKind::AutomationAccount       => "automation_account",
+            ErrorKind::Background              => "background",
+            ErrorKind::Cancelled               => "cancelled",
+            ErrorKind::CommunicationError      => "communication_error",
+            ErrorKind::InvalidOptions          => "invalid_options",
+            ErrorKind::NoTty                   => "no_tty",
+            ErrorKind::NonInteractive          => "non_interactive",
+            ErrorKind::PairRequired            => "pair_required",
+            ErrorKind::PreviewTimedOut         => "preview_timed_out",
+            ErrorKind::ReapprovalTimedOut      => "reapproval_timed_out",
+            ErrorKind::RecordingFailed         => "recording_failed",
+            ErrorKind::RecordingRequired       => "recording_required",
+            ErrorKind::SessionDeclined         => "session_declined",
+            ErrorKind::SessionExpired          => "session_expired",
+            ErrorKind::SessionKilled           => "session_killed",
+            ErrorKind::SessionTerminated       => "session_terminated",
+            ErrorKind::StdinRedirected         => "stdin_redirected",
+            ErrorKind::SudoToUserAndGroup      => "sudo_to_user_and_group",
+            ErrorKind::TicketRejected          => "ticket_rejected",
+            ErrorKind::TooManySessions         => "too_many_sessions",
+            ErrorKind::Unhealthy               => "unhealthy",
+            ErrorKind::UnknownApproverGroup    => "unknown_approver_group",
+            ErrorKind::UnknownSocketOwner      => "unknown_socket_owner",
+            ErrorKind::UnreadableApproverKeys  => "unreadable_approver_keys",
+            ErrorKind::Unrecorded              => "unrecorded",
+        }
+    }
+
+    /// Whether the error is a failure of the plugin itself, rather than
+    /// the session being refused (by the user, an approver, or policy),
+    /// and so something `on_internal_error` may let the session past.
+    pub fn is_internal(self) -> bool {
+        matches!(self,
+            ErrorKind::CommunicationError     |
+            ErrorKind::RecordingFailed        |
+            ErrorKind::UnknownApproverGroup   |
+            ErrorKind::UnknownSocketOwner     |
+            ErrorKind::UnreadableApproverKeys
+        )
+    }
 }
 
 impl Display for ErrorKind {
@@ -38,17 +133,39 @@ impl Display for ErrorKind {
 }
 
 #[derive(Debug)]
-pub(crate) struct Error {
+pub struct Error {
     inner: Context<ErrorKind>,
 }
 
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        *self.inner.get_context()
+    }
+
+    /// A record of the session having been rejected because of this
+    /// error, in the `key=value` form sudo's own log entries take.
+    pub fn rejection(&self) -> String {
+        format!("session rejected: reason={} message=\"{}\"", self.kind().code(), self.kind())
+    }
+
+    /// A record of the session having been allowed to run unpaired in
+    /// spite of this error, in the same form as `rejection`.
+    pub fn allowance(&self) -> String {
+        format!("session allowed unpaired: reason={} message=\"{}\"", self.kind().code(), self.kind())
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         self.inner.fmt(f)
     }
 }
 
-impl Fail for Error {}
+impl Fail for Error {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.inner.cause()
+    }
+}
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
@@ -65,17 +182,51 @@ impl From<Context<ErrorKind>> for Error {
 ///
 /// Implements conversion from `Error` to `sudo_plugin::errors::Error`.
 /// Since this plugin is security-sensitive, all errors should be
-/// converted to an Unauthorized error.
+/// converted to an Unauthorized error. The user is only told what went
+/// wrong, while the causes behind it are kept for the debug log.
 ///
 impl From<Error> for SudoPluginError {
     fn from(error: Error) -> Self {
         Self::with_chain(
-            error.compat(),
+            Chain::new(&error),
             SudoPluginErrorKind::Unauthorized
         )
     }
 }
 
+///
+/// A failure and its causes as a `std::error::Error`, since `Compat`
+/// would hide the causes.
+///
+#[derive(Debug)]
+struct Chain {
+    message: String,
+    source:  Option<Box<Chain>>,
+}
+
+impl Chain {
+    fn new(fail: &dyn Fail) -> Self {
+        Self {
+            message: fail.to_string(),
+            source:  fail.cause().map(|cause| Box::new(Self::new(cause))),
+        }
+    }
+}
+
+impl Display for Chain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.message.fmt(f)
+    }
+}
+
+impl StdError for Chain {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        let source : &(dyn StdError + 'static) = self.source.as_deref()?;
+
+        Some(source)
+    }
+}
+
 ///
 /// Also allow converting directly from an `ErrorKind`, which will be
 /// implicitly wrapped in a new `Error`.
@@ -85,3 +236,42 @@ impl From<ErrorKind> for SudoPluginError {
         Self::from(Error::from(kind))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_rejections() {
+        assert_eq!(
+            "session rejected: reason=session_declined message=\"pair declined the session\"",
+            Error::from(ErrorKind::SessionDeclined).rejection(),
+        );
+    }
+
+    #[test]
+    fn tells_internal_errors_from_refusals() {
+        assert!(ErrorKind::CommunicationError.is_internal());
+        assert!(ErrorKind::RecordingFailed.is_internal());
+        assert!(!ErrorKind::SessionDeclined.is_internal());
+        assert!(!ErrorKind::ApprovalTimedOut.is_internal());
+        assert!(!ErrorKind::Unhealthy.is_internal());
+
+        assert_eq!(
+            "session allowed unpaired: reason=recording_failed message=\"the session couldn't be recorded\"",
+            Error::from(ErrorKind::RecordingFailed).allowance(),
+        );
+    }
+
+    #[test]
+    fn keeps_causes_out_of_users_sight() {
+        let cause = std::io::Error::new(std::io::ErrorKind::PermissionDenied, "/var/log/sudo_pair: permission denied");
+        let error = SudoPluginError::from(Error::from(cause.context(ErrorKind::RecordingFailed)));
+
+        assert_eq!(format!("command unauthorized: {}", ErrorKind::RecordingFailed), error.user_message());
+        assert_eq!(
+            format!("command unauthorized: {}: /var/log/sudo_pair: permission denied", ErrorKind::RecordingFailed),
+            error.debug_message().lines().next().unwrap(),
+        );
+    }
+}
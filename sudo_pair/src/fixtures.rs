@@ -0,0 +1,122 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Captures (see `capture`) of what sudo provides the plugin across the
+//! versions it supports and the ways it's commonly invoked, so tests of
+//! parsing and of the policy run against the shapes sudo's vectors
+//! actually take, rather than only ones built by hand.
+//!
+//! Fixtures are sanitized as `debug_capture_dir` sanitizes captures,
+//! with the command's arguments and most of its environment redacted,
+//! except that the `sudoedit` fixtures keep the names of the files being
+//! edited. Some invocations leave no trace of their own: `-l`, `-v`, and
+//! `-k` are handled by the policy plugin without ever opening I/O
+//! plugins, sudo doesn't tell plugins about `-b`, and a NOPASSWD rule
+//! only shows in the user never being asked for a password, so their
+//! fixtures show what the plugin sees of them instead.
+//!
+//! The fixtures are reconstructed from each version's sudo_plugin(8),
+//! as each file's header says, rather than captured from sudo itself.
+//! To replace one with a real capture, build the plugin with the
+//! `debug_capture` feature, point `debug_capture_dir` at a directory,
+//! run the invocation, and copy the capture written there over the
+//! fixture (its header saying which sudo it came from).
+
+use crate::capture::Capture;
+
+use sudo_plugin::Plugin;
+
+/// A capture, and what it's a capture of.
+#[derive(Clone, Copy, Debug)]
+pub struct Fixture {
+    /// the name of the fixture's file, without `.capture`
+    pub name: &'static str,
+
+    /// the invocation the plugin reconstructs from the capture, quoted
+    /// as `Plugin::invocation` quotes it
+    pub invocation: &'static str,
+
+    contents: &'static [u8],
+}
+
+/// Every fixture.
+pub const FIXTURES : &[Fixture] = &[
+    Fixture {
+        name:       "sudo-1.8.16",
+        invocation: "sudo --user postgres psql '<redacted>'",
+        contents:   include_bytes!("../fixtures/sudo-1.8.16.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.8.31",
+        invocation: "sudo --user postgres psql '<redacted>'",
+        contents:   include_bytes!("../fixtures/sudo-1.8.31.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.8.31-sudoedit",
+        invocation: "sudo --edit /etc/hosts",
+        contents:   include_bytes!("../fixtures/sudo-1.8.31-sudoedit.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.9.5",
+        invocation: "sudo --user postgres psql '<redacted>'",
+        contents:   include_bytes!("../fixtures/sudo-1.9.5.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.9.5-background",
+        invocation: "sudo rsync '<redacted>' '<redacted>' '<redacted>'",
+        contents:   include_bytes!("../fixtures/sudo-1.9.5-background.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.9.5-login",
+        invocation: "sudo --login",
+        contents:   include_bytes!("../fixtures/sudo-1.9.5-login.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.9.5-nopasswd",
+        invocation: "sudo --non-interactive systemctl '<redacted>' '<redacted>'",
+        contents:   include_bytes!("../fixtures/sudo-1.9.5-nopasswd.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.9.5-shell",
+        invocation: "sudo --shell",
+        contents:   include_bytes!("../fixtures/sudo-1.9.5-shell.capture"),
+    },
+    Fixture {
+        name:       "sudo-1.9.5-sudoedit",
+        invocation: "sudoedit /etc/hosts",
+        contents:   include_bytes!("../fixtures/sudo-1.9.5-sudoedit.capture"),
+    },
+];
+
+/// The fixture named `name`.
+pub fn fixture(name: &str) -> &'static Fixture {
+    FIXTURES.iter()
+        .find(|fixture| fixture.name == name)
+        .unwrap_or_else(|| panic!("there's no fixture named {}", name))
+}
+
+impl Fixture {
+    /// The fixture's capture.
+    pub fn capture(&self) -> Capture {
+        Capture::read_from(self.contents)
+            .unwrap_or_else(|e| panic!("{} couldn't be read: {}", self.name, e))
+    }
+
+    /// A `Plugin` opened from the fixture's capture, as sudo would have
+    /// opened it.
+    pub fn plugin(&self) -> Plugin {
+        self.capture().plugin()
+            .unwrap_or_else(|e| panic!("{} couldn't be opened: {}", self.name, e))
+    }
+}
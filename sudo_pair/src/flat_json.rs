@@ -0,0 +1,188 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A reader of flat JSON objects, whose values are only strings and
+//! unsigned integers. It's all the plugin needs to read back: the
+//! maintenance windows configuration management drops on the host, and
+//! the lines of the session index it writes itself.
+
+use std::str;
+
+/// Parses `s` as a single flat JSON object, returning its fields in the
+/// order they appear (including any repeated keys). Returns `None` if
+/// it's anything else, or if it's followed by anything but whitespace.
+pub fn parse(s: &str) -> Option<Vec<(String, Value)>> {
+    let mut reader = Reader { bytes: s.as_bytes(), pos: 0 };
+
+    reader.object()
+        .filter(|_| reader.pos == reader.bytes.len())
+}
+
+/// A value in a flat JSON object.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Str(String),
+    Uint(u64),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos:   usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn object(&mut self) -> Option<Vec<(String, Value)>> {
+        let mut fields = Vec::new();
+
+        self.skip_whitespace();
+
+        if self.byte()? != b'{' {
+            return None;
+        }
+
+        self.skip_whitespace();
+
+        if self.peek()? == b'}' {
+            self.pos += 1;
+            self.skip_whitespace();
+
+            return Some(fields);
+        }
+
+        loop {
+            self.skip_whitespace();
+
+            let key = self.string()?;
+
+            self.skip_whitespace();
+
+            if self.byte()? != b':' {
+                return None;
+            }
+
+            self.skip_whitespace();
+
+            let value = match self.peek()? {
+                b'"'          => Value::Str(self.string()?),
+                b'0' ..= b'9' => Value::Uint(self.uint()?),
+                _             => return None,
+            };
+
+            fields.push((key, value));
+
+            self.skip_whitespace();
+
+            match self.byte()? {
+                b',' => continue,
+                b'}' => break,
+                _    => return None,
+            }
+        }
+
+        self.skip_whitespace();
+
+        Some(fields)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn uint(&mut self) -> Option<u64> {
+        let start = self.pos;
+
+        while let Some(b'0' ..= b'9') = self.peek() {
+            self.pos += 1;
+        }
+
+        str::from_utf8(&self.bytes[start .. self.pos]).ok()?.parse().ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.byte()? != b'"' {
+            return None;
+        }
+
+        let mut string = Vec::new();
+
+        loop {
+            match self.byte()? {
+                b'"'  => break,
+                b'\\' => match self.byte()? {
+                    b'"'  => string.push(b'"'),
+                    b'\\' => string.push(b'\\'),
+                    b'/'  => string.push(b'/'),
+                    b'b'  => string.push(0x08),
+                    b'f'  => string.push(0x0c),
+                    b'n'  => string.push(b'\n'),
+                    b'r'  => string.push(b'\r'),
+                    b't'  => string.push(b'\t'),
+
+                    // nothing read this way has any reason to escape
+                    // anything outside the basic multilingual plane, so
+                    // surrogate pairs aren't supported
+                    b'u'  => {
+                        let hex = self.bytes.get(self.pos .. self.pos + 4)?;
+                        let hex = str::from_utf8(hex).ok()?;
+                        let c   = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+
+                        self.pos += 4;
+                        string.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    },
+
+                    _ => return None,
+                },
+
+                byte => string.push(byte),
+            }
+        }
+
+        String::from_utf8(string).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flat_objects() {
+        assert_eq!(
+            Some(vec![
+                ("id".into(),    Value::Str("a\u{2014}\"b\"".into())),
+                ("count".into(), Value::Uint(42)),
+                ("id".into(),    Value::Str("c".into())),
+            ]),
+            parse(r#" { "id": "a\u2014\"b\"", "count": 42, "id": "c" } "#),
+        );
+
+        assert_eq!(Some(Vec::new()), parse("{}"));
+        assert_eq!(None,             parse(r#"{"n":-1}"#));
+        assert_eq!(None,             parse(r#"{"n":{}}"#));
+        assert_eq!(None,             parse(r#"{"n":1} {}"#));
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Checks of the built plugin's footprint. The plugin is loaded into
+//! every `sudo` invocation, so it's kept from growing (or exporting
+//! more than `sudo` loads it by) without anyone noticing: CI builds it
+//! in release mode with its default features, and fails if it's larger
+//! than `MAX_SIZE` or exports anything but `EXPORTS`.
+//!
+//! Only ELF shared libraries (64-bit and little-endian, as on x86-64
+//! and aarch64 Linux) can be checked. Their exports are read from the
+//! dynamic symbol table directly, rather than through `nm`, which isn't
+//! installed everywhere and disagrees with itself across platforms.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+/// The symbols `sudo` loads the plugin by: its I/O plugin, and the
+/// audit plugin it uses to see subcommands.
+pub const EXPORTS : &[&str] = &["sudo_pair", "sudo_pair_audit"];
+
+/// The largest the plugin may be, built in release mode with its
+/// default features. It leaves room for the plugin to grow a little
+/// without this being raised, but not for a new dependency to be
+/// pulled in unnoticed.
+pub const MAX_SIZE : u64 = 3 * 1024 * 1024;
+
+/// The type of the section holding the dynamic symbol table.
+const SHT_DYNSYM : u32 = 11;
+
+/// The size of a section header, and of an entry of the dynamic symbol
+/// table, in 64-bit ELF files.
+const SECTION_HEADER_SIZE : usize = 64;
+const SYMBOL_SIZE         : usize = 24;
+
+/// Returns every problem with the footprint of the plugin built at
+/// `path`, if it's no larger than `max_size` and exports nothing but
+/// `EXPORTS`, or an error if it couldn't be checked at all.
+pub fn check(path: &Path, max_size: u64) -> Result<Vec<String>, String> {
+    let plugin = fs::read(path)
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+
+    let exports = exports(&plugin)
+        .ok_or_else(|| format!("{} isn't a 64-bit, little-endian ELF shared library", path.display()))?;
+
+    let mut problems = Vec::new();
+    let size         = plugin.len() as u64;
+
+    if size > max_size {
+        problems.push(format!(
+            "{}: {} bytes is larger than the {} allowed; build with fewer features, or raise the limit if the growth is expected",
+            path.display(), size, max_size,
+        ));
+    }
+
+    for symbol in exports.iter().filter(|symbol| !EXPORTS.contains(&symbol.as_str())) {
+        problems.push(format!("{}: exports {}, which sudo never loads", path.display(), symbol));
+    }
+
+    for symbol in EXPORTS.iter().filter(|symbol| !exports.iter().any(|export| export == *symbol)) {
+        problems.push(format!("{}: doesn't export {}, which sudo loads it by", path.display(), symbol));
+    }
+
+    Ok(problems)
+}
+
+/// The names of the symbols `elf` exports, or `None` if it isn't a
+/// 64-bit, little-endian ELF file (or is malformed).
+pub fn exports(elf: &[u8]) -> Option<Vec<String>> {
+    if elf.get(..6)? != b"\x7fELF\x02\x01" {
+        return None;
+    }
+
+    let sections = usize::try_from(u64_at(elf, 0x28)?).ok()?;
+    let count    = usize::from(u16_at(elf, 0x3c)?);
+
+    let header = |index: usize| elf.get(sections + index * SECTION_HEADER_SIZE ..)?
+        .get(..SECTION_HEADER_SIZE);
+
+    let mut exports = Vec::new();
+
+    for index in 0..count {
+        let dynsym = header(index)?;
+
+        if u32_at(dynsym, 4)? != SHT_DYNSYM {
+            continue;
+        }
+
+        let symbols = slice(elf, dynsym)?;
+        let names   = slice(elf, header(usize::try_from(u32_at(dynsym, 40)?).ok()?)?)?;
+
+        for symbol in symbols.chunks_exact(SYMBOL_SIZE) {
+            let binding    = symbol[4] >> 4;
+            let visibility = symbol[5] & 0x3;
+            let defined    = u16_at(symbol, 6)? != 0;
+
+            // global and weak symbols, with default or protected
+            // visibility, are the ones other objects can link to
+            if !defined || !matches!(binding, 1 | 2) || !matches!(visibility, 0 | 3) {
+                continue;
+            }
+
+            let name = names.get(usize::try_from(u32_at(symbol, 0)?).ok()?..)?;
+            let end  = name.iter().position(|&b| b == 0)?;
+
+            exports.push(String::from_utf8_lossy(&name[..end]).into_owned());
+        }
+    }
+
+    exports.sort();
+
+    Some(exports)
+}
+
+/// The contents of the section `header` describes.
+fn slice<'a>(elf: &'a [u8], header: &[u8]) -> Option<&'a [u8]> {
+    let offset = usize::try_from(u64_at(header, 24)?).ok()?;
+    let size   = usize::try_from(u64_at(header, 32)?).ok()?;
+
+    elf.get(offset..offset.checked_add(size)?)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(<[u8; 2]>::try_from(bytes.get(at..at + 2)?).ok()?))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(<[u8; 4]>::try_from(bytes.get(at..at + 4)?).ok()?))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(<[u8; 8]>::try_from(bytes.get(at..at + 8)?).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// A minimal ELF file whose dynamic symbol table holds `symbols`,
+    /// each a name, its `st_info`, and whether it's defined.
+    fn elf(symbols: &[(&str, u8, bool)]) -> Vec<u8> {
+        let mut names = vec![0];
+        let mut table = vec![0; SYMBOL_SIZE];
+
+        for &(name, info, defined) in symbols {
+            let mut symbol = vec![0; SYMBOL_SIZE];
+
+            symbol[..4].copy_from_slice(&(names.len() as u32).to_le_bytes());
+            symbol[4] = info;
+            symbol[6] = u8::from(defined);
+
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+            table.extend_from_slice(&symbol);
+        }
+
+        let dynsym   = 64;
+        let dynstr   = dynsym + table.len();
+        let sections = dynstr + names.len();
+
+        let section = |kind: u32, offset: usize, size: usize, link: u32| {
+            let mut header = vec![0; SECTION_HEADER_SIZE];
+
+            header[4..8]  .copy_from_slice(&kind.to_le_bytes());
+            header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            header[40..44].copy_from_slice(&link.to_le_bytes());
+            header
+        };
+
+        let mut elf = vec![0; 64];
+
+        elf[..6]        .copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x28..0x30] .copy_from_slice(&(sections as u64).to_le_bytes());
+        elf[0x3c..0x3e] .copy_from_slice(&3u16.to_le_bytes());
+
+        elf.extend_from_slice(&table);
+        elf.extend_from_slice(&names);
+        elf.extend_from_slice(&section(0, 0, 0, 0));
+        elf.extend_from_slice(&section(SHT_DYNSYM, dynsym, table.len(), 2));
+        elf.extend_from_slice(&section(3, dynstr, names.len(), 0));
+        elf
+    }
+
+    fn write(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sudo_pair-footprint-{}-{}", std::process::id(), name));
+
+        fs::write(&path, contents).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn reads_exported_symbols() {
+        let elf = elf(&[
+            ("sudo_pair_audit", 0x11, true),
+            ("sudo_pair",       0x11, true),
+            ("memcpy",          0x12, false),
+            ("local",           0x01, true),
+            ("weak",            0x22, true),
+        ]);
+
+        assert_eq!(Some(vec!["sudo_pair".into(), "sudo_pair_audit".into(), "weak".into()]), exports(&elf));
+        assert_eq!(None, exports(b"\x7fELF\x01\x01"));
+        assert_eq!(None, exports(&elf[..100]));
+    }
+
+    #[test]
+    fn checks_the_footprint() {
+        let lean    = write("lean", &elf(&[("sudo_pair", 0x11, true), ("sudo_pair_audit", 0x11, true)]));
+        let leaking = write("leaking", &elf(&[("sudo_pair", 0x11, true), ("rust_eh_personality", 0x12, true)]));
+
+        assert_eq!(Vec::<String>::new(), check(&lean, MAX_SIZE).unwrap());
+        assert_eq!(1, check(&lean, 64).unwrap().len());
+
+        assert_eq!(vec![
+            format!("{}: exports rust_eh_personality, which sudo never loads", leaking.display()),
+            format!("{}: doesn't export sudo_pair_audit, which sudo loads it by", leaking.display()),
+        ], check(&leaking, MAX_SIZE).unwrap());
+
+        assert!(check(&std::env::temp_dir().join("sudo_pair-footprint-missing"), MAX_SIZE).is_err());
+
+        fs::remove_file(lean).unwrap();
+        fs::remove_file(leaking).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A self-check of what the plugin depends on, run as each session
+//! opens: that its options parsed, that the directories it writes to
+//! are writable, and that syslog is reachable. Most of these would
+//! otherwise only surface partway through a session (or, for syslog,
+//! never), so the results are summarized on a single line where an
+//! administrator can find them, and `strict_health` refuses sessions
+//! when anything fails.
+//!
+//! `sudo -V`, run as root, runs the same checks along with a few that
+//! are too slow or too intrusive for every session (the recording sink
+//! and the crypto backend), and prints their results as a table.
+
+use crate::crypto;
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+/// The results of each check, in the order they were run.
+#[derive(Debug, Default)]
+pub(crate) struct Health {
+    checks: Vec<(&'static str, Option<String>)>,
+}
+
+impl Health {
+    /// Records that the check `name` passed.
+    pub(crate) fn pass(&mut self, name: &'static str) {
+        self.checks.push((name, None));
+    }
+
+    /// Records that the check `name` failed because of `problem`.
+    pub(crate) fn fail(&mut self, name: &'static str, problem: String) {
+        self.checks.push((name, Some(problem)));
+    }
+
+    /// Checks that the plugin (as root) can create files in `dir`, or
+    /// in the nearest directory above it that exists, since missing
+    /// directories are created when they're needed.
+    pub(crate) fn check_writable(&mut self, name: &'static str, dir: &Path) {
+        match writable(dir) {
+            Ok(())       => self.pass(name),
+            Err(problem) => self.fail(name, problem),
+        }
+    }
+
+    /// Checks that `path` is a file the plugin (as root) can execute.
+    pub(crate) fn check_executable(&mut self, name: &'static str, path: &Path) {
+        let problem = match fs::metadata(path) {
+            Ok(ref metadata) if !metadata.is_file() => Some(format!("{} isn't a file", path.display())),
+            Ok(_)  => access(path, libc::X_OK).err()
+                .map(|e| format!("{} isn't executable: {}", path.display(), e)),
+            Err(e) => Some(format!("{}: {}", path.display(), e)),
+        };
+
+        match problem {
+            Some(problem) => self.fail(name, problem),
+            None          => self.pass(name),
+        }
+    }
+
+    /// Checks that something is listening on the UNIX socket at `path`.
+    /// It's only checked for, rather than connected to, since whatever
+    /// is listening would take a connection for a session.
+    pub(crate) fn check_socket(&mut self, name: &'static str, path: &Path) {
+        match fs::metadata(path) {
+            Ok(ref metadata) if metadata.file_type().is_socket() => self.pass(name),
+            Ok(_)  => self.fail(name, format!("{} isn't a socket", path.display())),
+            Err(e) => self.fail(name, format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Checks that the crypto backend the plugin was built with gives
+    /// the answers it's known to (see `crypto::self_test`).
+    pub(crate) fn check_crypto(&mut self) {
+        match crypto::self_test() {
+            Ok(())       => self.pass(crypto::BACKEND),
+            Err(problem) => self.fail(crypto::BACKEND, problem),
+        }
+    }
+
+    /// Checks that the syslog daemon is listening at `path`.
+    #[cfg(feature = "syslog")]
+    pub(crate) fn check_syslog(&mut self, path: &Path) {
+        let reachable = std::os::unix::net::UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(path));
+
+        match reachable {
+            Ok(()) => self.pass("syslog"),
+            Err(e) => self.fail("syslog", format!("{} is unreachable: {}", path.display(), e)),
+        }
+    }
+
+    /// Returns true if every check passed.
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|(_, problem)| problem.is_none())
+    }
+
+    /// Summarizes the results on a single line (e.g., `options ok,
+    /// socket_dir ok, syslog failed (/dev/log is unreachable: ...)`).
+    pub(crate) fn summary(&self) -> String {
+        let results : Vec<_> = self.checks.iter()
+            .map(|(name, problem)| match problem {
+                Some(problem) => format!("{} failed ({})", name, problem),
+                None          => format!("{} ok", name),
+            })
+            .collect();
+
+        results.join(", ")
+    }
+
+    /// Lays the results out as a table, a line for each check.
+    pub(crate) fn table(&self) -> String {
+        let width = self.checks.iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+
+        self.checks.iter()
+            .map(|(name, problem)| match problem {
+                Some(problem) => format!("    {:width$}  failed: {}\n", name, problem, width = width),
+                None          => format!("    {:width$}  ok\n", name, width = width),
+            })
+            .collect()
+    }
+}
+
+fn writable(dir: &Path) -> Result<(), String> {
+    let mut existing = dir;
+
+    let metadata = loop {
+        match fs::metadata(existing) {
+            Ok(metadata) => break metadata,
+
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => match existing.parent() {
+                Some(parent) => existing = parent,
+                None         => return Err(format!("{} doesn't exist", dir.display())),
+            },
+
+            Err(e) => return Err(format!("{}: {}", existing.display(), e)),
+        }
+    };
+
+    if !metadata.is_dir() {
+        return Err(format!("{} isn't a directory", existing.display()));
+    }
+
+    access(existing, libc::W_OK | libc::X_OK)
+        .map_err(|e| format!("{} isn't writable: {}", existing.display(), e))
+}
+
+fn access(path: &Path, mode: libc::c_int) -> io::Result<()> {
+    let cpath = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // sudo leaves the real uid as the invoking user's, so access has to
+    // be checked against the effective one
+    let rv = unsafe {
+        libc::faccessat(libc::AT_FDCWD, cpath.as_ptr(), mode, libc::AT_EACCESS)
+    };
+
+    if rv != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn summarizes_checks() {
+        let mut health = Health::default();
+
+        health.pass("options");
+        assert!(health.is_healthy());
+
+        health.fail("syslog", "/dev/log is unreachable".into());
+        assert!(!health.is_healthy());
+
+        assert_eq!("options ok, syslog failed (/dev/log is unreachable)", health.summary());
+
+        assert_eq!(
+            "    options  ok\n    syslog   failed: /dev/log is unreachable\n",
+            health.table(),
+        );
+    }
+
+    #[test]
+    fn checks_sinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir     = env::temp_dir().join(format!("sudo_pair-health-sinks-{}", process::id()));
+        let command = dir.join("command");
+        let socket  = dir.join("socket");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&command, b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        let mut health = Health::default();
+
+        health.check_executable("recording_command", &command);
+        health.check_socket("recording_socket", &socket);
+        health.check_executable("recording_command", &dir);
+        health.check_socket("recording_socket", &command);
+
+        assert_eq!(
+            format!(
+                "recording_command ok, recording_socket ok, recording_command failed ({} isn't a file), recording_socket failed ({} isn't a socket)",
+                dir.display(), command.display(),
+            ),
+            health.summary(),
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checks_crypto() {
+        let mut health = Health::default();
+
+        health.check_crypto();
+        assert_eq!(format!("{} ok", crypto::BACKEND), health.summary());
+    }
+
+    #[test]
+    fn checks_directories() {
+        let dir  = env::temp_dir().join(format!("sudo_pair-health-{}", process::id()));
+        let file = dir.join("file");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&file, b"").unwrap();
+
+        let mut health = Health::default();
+
+        // a directory that's yet to be created is checked by its parent
+        health.check_writable("socket_dir",    &dir.join("missing/sockets"));
+        health.check_writable("recording_dir", &file);
+
+        assert_eq!(
+            format!("socket_dir ok, recording_dir failed ({} isn't a directory)", file.display()),
+            health.summary(),
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "syslog")]
+    #[test]
+    fn checks_syslog() {
+        let dir  = env::temp_dir().join(format!("sudo_pair-syslog-{}", process::id()));
+        let path = dir.join("log");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut health = Health::default();
+
+        health.check_syslog(&path);
+        assert!(!health.is_healthy());
+
+        let _listener = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let mut health = Health::default();
+
+        health.check_syslog(&path);
+        assert_eq!("syslog ok", health.summary());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Hints shown to the user in place of the countdown as the wait for a
+//! pair drags on, so they know what to do next instead of staring at a
+//! static banner.
+//!
+//! Hints are read from a file, one per line, each giving how long the
+//! user has to have been waiting for it to be shown, with blank lines
+//! and lines starting with `#` ignored:
+//!
+//! ```text
+//! 30s still waiting; try pinging #oncall-approvals
+//! 2m  nobody's answered yet; this will be cancelled in %t
+//! ```
+//!
+//! The latest hint the user has waited long enough for replaces those
+//! before it. Hints are templates like the prompts, with `%t` for the
+//! time left before the session's declined, and `%w` for how long the
+//! user's been waiting.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use sudo_plugin::FromSudoOption;
+
+/// The hints loaded from a file, in the order they're shown.
+#[derive(Clone, Debug, Default)]
+pub struct Hints(Vec<Hint>);
+
+/// A single hint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hint {
+    /// how long the user has to have waited for the hint to be shown
+    after: Duration,
+
+    /// the hint as written, before it's expanded
+    template: Vec<u8>,
+}
+
+impl Hints {
+    /// Reads hints from `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut hints = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (after, template) = line.split_once(char::is_whitespace)
+                .map(|(after, template)| (after, template.trim()))
+                .filter(|(_, template)| !template.is_empty())
+                .ok_or_else(|| format!("line {}: a hint needs a duration and a message", i + 1))?;
+
+            let after = Duration::from_sudo_option(after)
+                .map_err(|_| format!("line {}: {} isn't a duration", i + 1, after))?;
+
+            hints.push(Hint { after, template: template.as_bytes().to_vec() });
+        }
+
+        // stable, so hints for the same time keep the order they were
+        // written in, and the last of them wins
+        hints.sort_by_key(|hint| hint.after);
+
+        Ok(Self(hints))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The template of the hint to show once the user has `waited`, if
+    /// they've waited long enough for any.
+    pub fn at(&self, waited: Duration) -> Option<&[u8]> {
+        self.0.iter()
+            .rev()
+            .find(|hint| hint.after <= waited)
+            .map(|hint| &hint.template[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_the_latest_hint_waited_for() {
+        let hints = Hints::parse("\
+            # nudges for anyone waiting too long\n\
+            2m   this will be cancelled in %t\n\
+            \n\
+            30s  still waiting; try pinging #oncall-approvals\n\
+        ").unwrap();
+
+        assert_eq!(None, hints.at(Duration::from_secs(29)));
+        assert_eq!(Some(&b"still waiting; try pinging #oncall-approvals"[..]), hints.at(Duration::from_secs(30)));
+        assert_eq!(Some(&b"still waiting; try pinging #oncall-approvals"[..]), hints.at(Duration::from_secs(119)));
+        assert_eq!(Some(&b"this will be cancelled in %t"[..]), hints.at(Duration::from_secs(600)));
+
+        assert!(Hints::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_hints() {
+        assert_eq!("line 1: a hint needs a duration and a message", Hints::parse("30s").unwrap_err());
+        assert_eq!("line 2: soon isn't a duration", Hints::parse("1m ok\nsoon wait").unwrap_err());
+    }
+}
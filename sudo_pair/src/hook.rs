@@ -0,0 +1,135 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Commands run when a session ends, so downstream automation can react
+//! to how it went (e.g., alerting on sessions whose command crashed or
+//! was killed).
+
+use sudo_pair_types::event::CloseEvent;
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often to check whether a hook has exited.
+const POLL_INTERVAL : Duration = Duration::from_millis(10);
+
+/// Runs `command` for `event`, waiting at most `timeout` for it to
+/// finish before killing it. It runs as root, so it's given a minimal
+/// environment rather than inheriting one the invoking user could have
+/// influenced.
+pub(crate) fn run(command: &Path, event: &CloseEvent, timeout: Duration) -> Result<ExitStatus> {
+    let mut child = Command::new(command)
+        .env_clear()
+        .env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+        .envs(event.env())
+        .current_dir("/")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("{} was killed after {:?}", command.display(), timeout),
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    use sudo_pair_types::manifest::Outcome;
+
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn event(outcome: Outcome) -> CloseEvent {
+        CloseEvent {
+            session_id:     "1600000000-31337".into(),
+            user:           "alice".into(),
+            runas_user:     "postgres".into(),
+            executable:     "/usr/bin/psql".into(),
+            paired:         true,
+            outcome,
+            duration:       Duration::from_millis(61_500),
+            output_bytes:   42,
+
+            approver_bytes:  40,
+            approver_sha256: "ba7816bf".into(),
+
+            ticket:            Some("INC-1234".into()),
+            approver_comments: vec!["approved for INC-1234".into()],
+            labels:            vec!["pci-scope".into(), "prod".into()],
+            correlation_id:    None,
+        }
+    }
+
+    /// Writes a shell script with `body` into a directory of its own.
+    fn script(name: &str, body: &str) -> (PathBuf, PathBuf) {
+        let dir  = scratch::dir("hook", name, true);
+        let hook = dir.join("hook");
+
+        fs::write(&hook, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o700)).unwrap();
+
+        (dir, hook)
+    }
+
+    #[test]
+    fn runs_hooks_with_a_minimal_environment() {
+        let (dir, hook) = script("env", "env > \"$(dirname \"$0\")/env\"");
+
+        assert!(run(&hook, &event(Outcome::Exited(3)), Duration::from_secs(5)).unwrap().success());
+
+        let env = fs::read_to_string(dir.join("env")).unwrap();
+
+        assert!(env.contains("SUDO_PAIR_EXIT_STATUS=3\n"));
+        assert!(env.contains("SUDO_PAIR_COMMAND=/usr/bin/psql\n"));
+        assert!(!env.contains("HOME="));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn kills_hooks_that_run_too_long() {
+        let (dir, hook) = script("slow", "exec sleep 10");
+        let started     = Instant::now();
+        let error       = run(&hook, &event(Outcome::Unknown), Duration::from_millis(50)).unwrap_err();
+
+        assert_eq!(ErrorKind::TimedOut, error.kind());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
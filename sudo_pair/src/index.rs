@@ -0,0 +1,280 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! An index of the sessions recorded on this host, so a session's
+//! recording can be found (and sessions listed) without reading every
+//! recording in `recording_dir`, or wherever else they're sent.
+//!
+//! With `session_index` set, a line is appended to the index as each
+//! recorded session closes: a flat JSON object with the session's
+//! `session_id`, `user`, `runas_user`, `command`, `started_at`,
+//! `ended_at`, how it ended (as the manifest gives it: `exit_code`,
+//! `signal`, or `errno`), its `correlation_id` if it had one, and the
+//! `recording` it was written to.
+//!
+//! ```text
+//! {"session_id":"1600000000-31337","user":"alice","runas_user":"postgres","command":"psql -c select","started_at":"2020-09-13T12:26:40Z","ended_at":"2020-09-13T12:27:41Z","exit_code":3,"recording":"/var/log/sudo_pair/1600000000-31337.rec"}
+//! ```
+//!
+//! Lines are appended while holding an exclusive lock on the index, so
+//! those of sessions closing at once are never interleaved, and anything
+//! rotating or pruning the index should take the same lock.
+
+use crate::flat_json::{self, Value};
+use crate::ownership::Ownership;
+
+use sudo_pair_types::json::quote;
+use sudo_pair_types::manifest::{parse_utc, utc, Manifest, Outcome};
+
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// A recorded session, as it's listed in the index.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub session_id: String,
+    pub user:       String,
+    pub runas_user: String,
+
+    /// the command's arguments, separated by spaces
+    pub command: String,
+
+    pub started_at: SystemTime,
+    pub ended_at:   SystemTime,
+    pub outcome:    Outcome,
+
+    /// the ID grouping the session with the others of the same change,
+    /// if it has one
+    pub correlation_id: Option<String>,
+
+    /// where the session was recorded (e.g., the path of its recording)
+    pub recording: String,
+}
+
+impl Entry {
+    /// The entry for the session described by `manifest`, which was
+    /// recorded to `recording`.
+    pub fn new(manifest: &Manifest, recording: String) -> Self {
+        Self {
+            session_id: manifest.session_id.clone(),
+            user:       manifest.user.clone(),
+            runas_user: manifest.runas_user.clone(),
+            command:    manifest.argv.join(" "),
+            started_at: manifest.started_at,
+            ended_at:   manifest.ended_at,
+            outcome:    manifest.outcome,
+
+            correlation_id: manifest.correlation_id.clone(),
+            recording,
+        }
+    }
+
+    /// Serializes the entry as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let outcome = match self.outcome {
+            Outcome::Exited(code)     => format!(",{}:{}", quote("exit_code"), code),
+            Outcome::Signaled(signal) => format!(",{}:{}", quote("signal"),    signal),
+            Outcome::NotRun(errno)    => format!(",{}:{}", quote("errno"),     errno),
+            Outcome::Unknown          => String::new(),
+            Outcome::Incomplete       => String::new(),
+        };
+
+        let correlation_id = match self.correlation_id.as_ref() {
+            Some(id) => format!(",{}:{}", quote("correlation_id"), quote(id)),
+            None     => String::new(),
+        };
+
+        format!(
+            "{{{}:{},{}:{},{}:{},{}:{},{}:{},{}:{}{}{},{}:{}}}",
+            quote("session_id"), quote(&self.session_id),
+            quote("user"),       quote(&self.user),
+            quote("runas_user"), quote(&self.runas_user),
+            quote("command"),    quote(&self.command),
+            quote("started_at"), quote(&utc(self.started_at)),
+            quote("ended_at"),   quote(&utc(self.ended_at)),
+            outcome,
+            correlation_id,
+            quote("recording"),  quote(&self.recording),
+        )
+    }
+
+    /// Parses an entry from a line of the index, or returns `None` if
+    /// it isn't one.
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields = flat_json::parse(line)?;
+
+        let field = |key: &str| fields.iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value);
+
+        let string = |key: &str| match field(key) {
+            Some(Value::Str(value)) => Some(value.clone()),
+            _                       => None,
+        };
+
+        let number = |key: &str| match field(key) {
+            Some(Value::Uint(value)) => i32::try_from(*value).ok(),
+            _                        => None,
+        };
+
+        let outcome = match (number("exit_code"), number("signal"), number("errno")) {
+            (Some(code), _, _)   => Outcome::Exited(code),
+            (_, Some(signal), _) => Outcome::Signaled(signal),
+            (_, _, Some(errno))  => Outcome::NotRun(errno),
+            _                    => Outcome::Unknown,
+        };
+
+        Some(Self {
+            session_id: string("session_id")?,
+            user:       string("user")?,
+            runas_user: string("runas_user")?,
+            command:    string("command")?,
+            started_at: parse_utc(&string("started_at")?)?,
+            ended_at:   parse_utc(&string("ended_at")?)?,
+            outcome,
+
+            correlation_id: string("correlation_id"),
+            recording:      string("recording")?,
+        })
+    }
+}
+
+/// Appends `entry` to the index at `path`, creating it (and the
+/// directory it's in) with `ownership` if it doesn't exist.
+pub fn append(path: &Path, ownership: Ownership, entry: &Entry) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        ownership.create_dir(dir)?;
+    }
+
+    // never follow a symlink someone else may have planted
+    let created = OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path);
+
+    let mut file = match created {
+        Ok(file) => {
+            ownership.apply(&file)?;
+            file
+        },
+
+        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => OpenOptions::new()
+            .append(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)?,
+
+        Err(e) => return Err(e),
+    };
+
+    lock(&file, libc::LOCK_EX)?;
+
+    file.write_all(format!("{}\n", entry.to_json()).as_bytes())
+}
+
+/// Finds the entry for the session `id` in the index at `path`. Lines
+/// that aren't entries are skipped, and if the session was indexed more
+/// than once, its last entry is returned.
+pub fn find(path: &Path, id: &str) -> Result<Option<Entry>> {
+    let file = File::open(path)?;
+
+    lock(&file, libc::LOCK_SH)?;
+
+    let mut found = None;
+
+    for line in BufReader::new(file).lines() {
+        if let Some(entry) = Entry::parse(&line?).filter(|entry| entry.session_id == id) {
+            found = Some(entry);
+        }
+    }
+
+    Ok(found)
+}
+
+/// Locks `file` with `operation` until it's closed.
+fn lock(file: &File, operation: libc::c_int) -> Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    #[test]
+    fn round_trips_entries() {
+        let entry = Entry::new(&Manifest::example(), "/var/log/sudo_pair/1600000000-31337.rec".into());
+
+        assert_eq!(
+            "{\"session_id\":\"1600000000-31337\",\"user\":\"alice\",\"runas_user\":\"postgres\",\
+             \"command\":\"psql -c select \\\"now\\\"()\\n\",\"started_at\":\"2020-09-13T12:26:40Z\",\
+             \"ended_at\":\"2020-09-13T12:27:41Z\",\"exit_code\":3,\
+             \"recording\":\"/var/log/sudo_pair/1600000000-31337.rec\"}",
+            entry.to_json(),
+        );
+
+        assert_eq!(Some(entry.clone()), Entry::parse(&entry.to_json()));
+        assert_eq!(None,                Entry::parse("{\"session_id\":\"1600000000-31337\"}"));
+
+        let entry = Entry::new(&Manifest {
+            correlation_id: Some("CHG-42".into()),
+            ..Manifest::example()
+        }, "1600000000-31337.rec".into());
+
+        assert!(entry.to_json().ends_with(",\"exit_code\":3,\"correlation_id\":\"CHG-42\",\"recording\":\"1600000000-31337.rec\"}"));
+        assert_eq!(Some(entry.clone()), Entry::parse(&entry.to_json()));
+    }
+
+    #[test]
+    fn finds_the_last_entry_for_a_session() {
+        let dir  = env::temp_dir().join(format!("sudo_pair-index-{}", process::id()));
+        let path = dir.join("index.jsonl");
+
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut first  = Entry::new(&Manifest::example(), "first.rec".into());
+        let mut second = first.clone();
+        let mut other  = first.clone();
+
+        first.outcome     = Outcome::Signaled(9);
+        second.recording  = "second.rec".into();
+        other.session_id  = "1600000000-4242".into();
+
+        append(&path, Ownership::default(), &first).unwrap();
+        append(&path, Ownership::default(), &other).unwrap();
+        append(&path, Ownership::default(), &second).unwrap();
+
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        assert_eq!(Some(second), find(&path, "1600000000-31337").unwrap());
+        assert_eq!(Some(other),  find(&path, "1600000000-4242").unwrap());
+        assert_eq!(None,         find(&path, "1600000000-1").unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
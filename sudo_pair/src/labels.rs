@@ -0,0 +1,73 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Labels a policy plugin attaches to a session (e.g., `pci-scope`), so
+//! central policy can annotate sessions without sudo_pair having to know
+//! why. They're passed as a comma-separated list in the command info the
+//! policy plugin returns, shown to approvers, matched by `label` pair
+//! rules, and logged and recorded with the session.
+
+/// The command info key a policy plugin sets to label a session.
+pub const LABEL_KEY : &str = "sudo_pair_label";
+
+/// The longest a label may be.
+const MAX_LABEL_LEN : usize = 64;
+
+/// Splits `value` into the labels it lists, in order and without
+/// duplicates, and those that aren't valid labels. Labels are made of
+/// letters, digits, `.`, `_`, `-`, and `:`, since they're shown to
+/// approvers and written to logs as they are.
+pub fn parse(value: &str) -> (Vec<String>, Vec<String>) {
+    let mut labels  = Vec::new();
+    let mut invalid = Vec::new();
+
+    for label in value.split(',').map(str::trim).filter(|label| !label.is_empty()) {
+        if !is_valid(label) {
+            invalid.push(label.escape_debug().to_string());
+        } else if !labels.iter().any(|l| l == label) {
+            labels.push(label.into());
+        }
+    }
+
+    (labels, invalid)
+}
+
+fn is_valid(label: &str) -> bool {
+    label.len() <= MAX_LABEL_LEN && label.bytes().all(|b|
+        b.is_ascii_alphanumeric() || b"._-:".contains(&b)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_labels() {
+        assert_eq!(
+            (vec!["pci-scope".into(), "tier:1".into()], Vec::<String>::new()),
+            parse(" pci-scope,tier:1,,pci-scope "),
+        );
+
+        assert_eq!((Vec::<String>::new(), Vec::<String>::new()), parse(""));
+    }
+
+    #[test]
+    fn rejects_invalid_labels() {
+        let (labels, invalid) = parse(&format!("ok,has space,\x1b[2J,{}", "x".repeat(65)));
+
+        assert_eq!(vec!["ok".to_string()], labels);
+        assert_eq!(vec!["has space".to_string(), "\\u{1b}[2J".into(), "x".repeat(65)], invalid);
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Timing statistics for the user's keystrokes, so operators can tell
+//! how much latency pairing adds to a session. Only when keystrokes
+//! arrive is tracked, never what they are.
+
+use sudo_pair_types::manifest::{LatencySummary, Percentiles};
+
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+/// The most samples kept of each statistic; beyond this, samples are
+/// thinned out evenly over the session so memory stays bounded.
+const MAX_SAMPLES : usize = 4096;
+
+/// Pauses between keystrokes longer than this are the user thinking
+/// rather than typing, and aren't counted as intervals.
+const MAX_INTERVAL : Duration = Duration::from_secs(2);
+
+/// Keystrokes that aren't followed by output within this long aren't
+/// echoed (e.g., they're buffered by the command), and aren't counted.
+const MAX_ECHO : Duration = Duration::from_secs(1);
+
+/// Tracks the timing of the user's keystrokes over a session.
+#[derive(Debug, Default)]
+pub struct KeystrokeLatency {
+    keystrokes: u64,
+
+    /// when the last keystroke arrived, and when the last keystroke
+    /// that hasn't been echoed yet did
+    last:     Option<Instant>,
+    unechoed: Option<Instant>,
+
+    /// the time between consecutive keystrokes, and between each
+    /// keystroke and the plugin finishing with the output that follows
+    /// it (including sending it to the approver)
+    intervals: Samples,
+    echoes:    Samples,
+}
+
+impl KeystrokeLatency {
+    /// Records a keystroke (or several, typed too quickly to be told
+    /// apart) that arrived at `now`.
+    pub fn keystroke(&mut self, now: Instant) {
+        self.keystrokes += 1;
+
+        if let Some(interval) = self.last.map(|last| now.saturating_duration_since(last)) {
+            if interval <= MAX_INTERVAL {
+                self.intervals.add(interval);
+            }
+        }
+
+        self.last = Some(now);
+
+        // only the first of several keystrokes typed before any output
+        // is timed, since their echoes can't be told apart
+        if self.unechoed.is_none() {
+            self.unechoed = Some(now);
+        }
+    }
+
+    /// Records that the plugin finished with output from the command at
+    /// `now`, which echoes any keystroke still awaiting it.
+    pub fn output(&mut self, now: Instant) {
+        if let Some(echo) = self.unechoed.take().map(|typed| now.saturating_duration_since(typed)) {
+            if echo <= MAX_ECHO {
+                self.echoes.add(echo);
+            }
+        }
+    }
+
+    /// Summarizes the keystrokes seen so far, if there were any.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        if self.keystrokes == 0 {
+            return None;
+        }
+
+        Some(LatencySummary {
+            keystrokes: self.keystrokes,
+            intervals:  self.intervals.percentiles(),
+            echoes:     self.echoes.percentiles(),
+        })
+    }
+}
+
+/// A bounded sample of durations. Once it's full, every other sample
+/// is dropped and only every other new one is kept from then on, so the
+/// samples remain spread evenly over everything that was added.
+#[derive(Debug)]
+pub struct Samples {
+    samples: Vec<u64>,
+    stride:  u64,
+    skipped: u64,
+    max:     u64,
+}
+
+impl Default for Samples {
+    fn default() -> Self {
+        Self {
+            samples: Vec::new(),
+            stride:  1,
+            skipped: 0,
+            max:     0,
+        }
+    }
+}
+
+impl Samples {
+    pub fn add(&mut self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+
+        // the maximum is exact, however the rest are sampled
+        self.max = self.max.max(micros);
+
+        self.skipped += 1;
+
+        if self.skipped < self.stride {
+            return;
+        }
+
+        self.skipped = 0;
+
+        if self.samples.len() == MAX_SAMPLES {
+            let mut i = 0;
+
+            self.samples.retain(|_| { i += 1; i % 2 == 0 });
+            self.stride *= 2;
+        }
+
+        self.samples.push(micros);
+    }
+
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+
+        // nearest-rank percentiles
+        let rank = |p: usize| sorted[(sorted.len() * p).div_ceil(100) - 1];
+
+        Some(Percentiles {
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: self.max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn times_keystrokes_and_their_echoes() {
+        let start       = Instant::now();
+        let mut latency = KeystrokeLatency::default();
+
+        assert_eq!(None, latency.summary());
+
+        // each keystroke is echoed 5ms later, and typed 100ms apart
+        for i in 0..10 {
+            latency.keystroke(start + ms(100 * i));
+            latency.output(start + ms(100 * i + 5));
+        }
+
+        // a pause to think isn't an interval, and output that isn't an
+        // echo isn't timed
+        latency.keystroke(start + ms(60_000));
+        latency.output(start + ms(65_000));
+        latency.output(start + ms(65_001));
+
+        let summary = latency.summary().unwrap();
+
+        assert_eq!(11, summary.keystrokes);
+        assert_eq!(Some(Percentiles { p50: 100_000, p90: 100_000, p99: 100_000, max: 100_000 }), summary.intervals);
+        assert_eq!(Some(Percentiles { p50: 5_000,   p90: 5_000,   p99: 5_000,   max: 5_000 }),   summary.echoes);
+    }
+
+    #[test]
+    fn times_only_the_first_unechoed_keystroke() {
+        let start       = Instant::now();
+        let mut latency = KeystrokeLatency::default();
+
+        latency.keystroke(start);
+        latency.keystroke(start + ms(10));
+        latency.output(start + ms(30));
+
+        assert_eq!(30_000, latency.summary().unwrap().echoes.unwrap().p50);
+    }
+
+    #[test]
+    fn computes_nearest_rank_percentiles() {
+        let mut samples = Samples::default();
+
+        for i in 1..=100 {
+            samples.add(Duration::from_micros(i));
+        }
+
+        assert_eq!(Some(Percentiles { p50: 50, p90: 90, p99: 99, max: 100 }), samples.percentiles());
+        assert_eq!(None, Samples::default().percentiles());
+    }
+
+    #[test]
+    fn bounds_samples() {
+        let mut samples = Samples::default();
+
+        for i in 0..(MAX_SAMPLES as u64 * 10) {
+            samples.add(Duration::from_micros(i));
+        }
+
+        assert!(samples.samples.len() <= MAX_SAMPLES);
+        assert_eq!(MAX_SAMPLES as u64 * 10 - 1, samples.percentiles().unwrap().max);
+
+        // the samples still span the whole range that was added
+        let p50 = samples.percentiles().unwrap().p50;
+
+        assert!((MAX_SAMPLES as u64 * 4 .. MAX_SAMPLES as u64 * 6).contains(&p50));
+    }
+}
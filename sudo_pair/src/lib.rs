@@ -25,7 +25,6 @@
 // TODO: docs on docs.rs
 // TODO: various badges
 // TODO: fill out all fields of https://doc.rust-lang.org/cargo/reference/manifest.html
-// TODO: implement change_winsize
 
 #![warn(bad_style)]
 #![warn(future_incompatible)]
@@ -55,19 +54,181 @@
 
 #![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
 
-mod errors;
-mod template;
-mod socket;
-
+mod at_exit;
+mod attempts;
+mod context;
+mod delivery;
+mod disable;
+mod edits;
+mod health;
+mod hook;
+mod maintenance;
+#[cfg(feature = "pam")]
+mod pam;
+mod restrictions;
+mod throttle;
+mod truncation;
+#[cfg(feature = "http")]
+mod web_approval;
+
+// the helper binaries in `src/bin` link against this library rather
+// than each compiling its own copy of what it shares with the plugin,
+// so the modules they use are public; they're hidden from the docs,
+// since they aren't meant to be used by anything else
+#[doc(hidden)]
+pub mod capture;
+#[doc(hidden)]
+pub mod chat;
+#[doc(hidden)]
+pub mod clock;
+#[doc(hidden)]
+pub mod constant_time;
+#[doc(hidden)]
+pub mod correlation;
+#[doc(hidden)]
+pub mod crypto;
+#[doc(hidden)]
+pub mod deadline;
+#[doc(hidden)]
+pub mod disclosure;
+#[doc(hidden)]
+pub mod display;
+#[doc(hidden)]
+pub mod environment;
+#[doc(hidden)]
+pub mod errors;
+#[doc(hidden)]
+pub mod fixtures;
+#[doc(hidden)]
+pub mod flat_json;
+#[doc(hidden)]
+pub mod footprint;
+#[doc(hidden)]
+pub mod hints;
+#[doc(hidden)]
+pub mod index;
+#[doc(hidden)]
+pub mod labels;
+#[doc(hidden)]
+pub mod latency;
+#[doc(hidden)]
+pub mod manifest;
+#[doc(hidden)]
+pub mod options;
+#[doc(hidden)]
+pub mod ownership;
+#[doc(hidden)]
+pub mod policy;
+#[doc(hidden)]
+pub mod protocol;
+#[doc(hidden)]
+pub mod reapproval;
+#[doc(hidden)]
+pub mod recording;
+#[doc(hidden)]
+pub mod recovery;
+#[doc(hidden)]
+pub mod registry;
+#[doc(hidden)]
+pub mod rules;
+#[doc(hidden)]
+pub mod sanitize;
+#[doc(hidden)]
+pub mod schema;
+#[doc(hidden)]
+pub mod scratch;
+#[doc(hidden)]
+pub mod session;
+#[doc(hidden)]
+pub mod signals;
+#[doc(hidden)]
+pub mod socket;
+#[doc(hidden)]
+pub mod socket_name;
+#[doc(hidden)]
+pub mod sshsig;
+#[doc(hidden)]
+pub mod status;
+#[doc(hidden)]
+pub mod suppression;
+#[doc(hidden)]
+pub mod template;
+#[doc(hidden)]
+pub mod terminal;
+#[doc(hidden)]
+pub mod threads;
+#[doc(hidden)]
+pub mod ticket;
+#[doc(hidden)]
+pub mod token;
+#[doc(hidden)]
+pub mod totp;
+#[doc(hidden)]
+pub mod transcript;
+#[doc(hidden)]
+pub mod transfer;
+#[cfg(feature = "http")]
+#[doc(hidden)]
+pub mod upload;
+#[doc(hidden)]
+pub mod wire;
+
+use crate::attempts::{Attempts, Verdict};
+#[cfg(feature = "debug_capture")]
+use crate::capture::Capture;
+use crate::clock::{Clock, SystemClock};
+use crate::context::CommandContext;
+use crate::correlation::CORRELATION_ENV;
+use crate::display::Display;
+use crate::edits::EditCapture;
 use crate::errors::*;
-use crate::template::Spec;
-use crate::socket::Socket;
-
+use crate::health::Health;
+use crate::options::{DisableMode, Group, HostTag, InternalErrorPolicy, Mode, PluginOptions, InputSource, RecordErrorPolicy, RecordingSinkKind, UmaskPolicy, UnattendedPolicy, User, WidthPolicy};
+use crate::ownership::Ownership;
+use crate::protocol::{Credentials, Hello, LIMITS};
+use crate::deadline::{Countdown, Deadline};
+use crate::hints::Hints;
+use crate::disable::Disabled;
+use crate::disclosure::{Disclosure, DEFAULT_NOTICE};
+use crate::labels::LABEL_KEY;
+use crate::latency::KeystrokeLatency;
+use crate::maintenance::Window;
+use crate::policy::{Exemption, Invocation, Names};
+use crate::reapproval::Reapproval;
+use crate::recording::{Durability, Frame, Recording};
+use crate::registry::{About, ClaimError, KillRequest, Slot};
+use crate::restrictions::Restrictions;
+use crate::rules::{Coverage, Quorum, Rules};
+use crate::session::{before_deadline, Active, AwaitingApproval, Session, Termination};
+use crate::signals::SignalGuard;
+use crate::suppression::Suppression;
+use crate::threads::Threads;
+use crate::throttle::Throttle;
+use crate::template::{Spec, DEFAULT_PAIR_PROMPT, DEFAULT_USER_PROMPT, QUIET_USER_PROMPT};
+use crate::terminal::Terminal;
+use crate::socket::{Admission, Listener, Socket};
+use crate::socket_name::Vars;
+use crate::sshsig::AuthorizedKeys;
+use crate::ticket::TICKET_ENV;
+use crate::token::{Claims, PREAPPROVAL_ENV};
+use crate::totp::{Ledger, TotpKeys};
+use crate::transcript::Transcript;
+use crate::truncation::CommandLimits;
+use crate::transfer::{Offer, Response};
+#[cfg(feature = "http")]
+use crate::web_approval::{ApprovalUrl, Decision};
+
+use std::cell::RefCell;
 use std::collections::HashSet;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::mem;
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use libc::{gid_t, mode_t, uid_t};
 
@@ -75,35 +236,242 @@ use failure::ResultExt;
 
 use sudo_plugin::*;
 
-const DEFAULT_BINARY_PATH      : &str       = "/usr/bin/sudo_approve";
-const DEFAULT_USER_PROMPT_PATH : &str       = "/etc/sudo_pair.prompt.user";
-const DEFAULT_PAIR_PROMPT_PATH : &str       = "/etc/sudo_pair.prompt.pair";
-const DEFAULT_SOCKET_DIR       : &str       = "/var/run/sudo_pair";
-const DEFAULT_GIDS_ENFORCED    : [gid_t; 1] = [0];
-
-const DEFAULT_USER_PROMPT : &[u8] = b"%B '%p %u'\n";
-const DEFAULT_PAIR_PROMPT : &[u8] = b"%U@%h:%d$ %C\ny/n? [n]: ";
+use sudo_pair_types::event::CloseEvent;
+use sudo_pair_types::humanize;
+use sudo_pair_types::manifest::{Manifest, Outcome};
+use sudo_pair_types::protocol::{Capabilities, Encoding, Stream, PROTOCOL_VERSION};
 
 sudo_io_plugin! {
      sudo_pair: SudoPair {
         close:      close,
+        log_ttyin:  log_ttyin,
         log_ttyout: log_ttyout,
         log_stdin:  log_disabled,
         log_stdout: log_stdout,
         log_stderr: log_stderr,
+
+        // the `change_winsize` callback only exists in newer versions
+        // of the plugin API, so it's opt-in to keep supporting older
+        // versions of sudo
+        #[cfg(feature = "change_winsize")]
+        change_winsize: change_winsize,
+
+        // `sudo -V`, which runs the self-check when root runs it
+        show_version: show_version,
+     }
+
+     // the commands run by the session's command, which only sudo 1.9.8
+     // and later report (and only to audit plugins)
+     sudo_pair_audit: audit {
+        subcommand: subcommand,
      }
 }
 
 struct SudoPair {
     plugin:  &'static Plugin,
     options: PluginOptions,
-    socket:  Option<Socket>,
+    session: Session,
+
+    /// where the session's socket is created, once it's known to need
+    /// one, and the owner and group configured for it (if any)
+    socket_path:  PathBuf,
+    socket_owner: Option<uid_t>,
+    socket_group: Option<gid_t>,
+
+    /// the approvals the session needs, and the gid approvers have to
+    /// be able to act as if they're restricted to a group
+    quorum:       Quorum,
+    approver_gid: Option<gid_t>,
+
+    /// whether the session is paired, recorded, or both, as the pair
+    /// rule it matched says
+    coverage: Coverage,
+
+    /// the keys approvers have to sign a challenge with, if they have
+    /// to authenticate
+    approver_keys: Option<AuthorizedKeys>,
+
+    /// the secrets approvers' one-time codes are checked against, if
+    /// they have to enter one
+    approver_totp: Option<TotpKeys>,
+
+    /// the command's filesystem context, if it could be inspected
+    context: Option<CommandContext>,
+
+    /// the ticket the user cited for the session, once it's been
+    /// verified
+    ticket: Option<String>,
+
+    /// the maintenance window the session began during, if it began
+    /// during one
+    maintenance: Option<Window>,
+
+    /// the plugin's disable file, if it was present when the session
+    /// began
+    disabled: Option<Disabled>,
+
+    /// whether the session is one of an automation account's (see
+    /// `automation_users`), which can't answer prompts
+    automation: bool,
+
+    /// the labels the policy plugin attached to the session
+    labels: Vec<String>,
+
+    /// the ID grouping the session with the others of the same change,
+    /// if it has one (see `correlation`)
+    correlation_id: Option<String>,
+
+    /// the kind of terminal the session was run from, and the
+    /// multiplexer it was run in, if any
+    terminal: Terminal,
+
+    /// the comments approvers attached to their approvals, in the
+    /// order they approved
+    approver_comments: Vec<String>,
+
+    /// the approver who approved the session from the user's own
+    /// terminal, if it was approved that way
+    colocated_approver: Option<String>,
+
+    /// the approver who approved the session through the approval
+    /// service, if it was approved that way
+    web_approver: Option<String>,
+
+    /// the commands the session's command ran, as far as sudo reported
+    /// them, and how many more were left out once there were too many
+    subcommands:         Vec<String>,
+    subcommands_omitted: u64,
+
+    /// what every timeout, deadline, and limit on the session is
+    /// measured on
+    clock: Arc<dyn Clock>,
+
+    display:    Display,
+    transcript: Transcript,
+
+    /// the limit on how quickly output is relayed to the approver, if
+    /// there is one
+    throttle: Option<Throttle>,
+
+    /// the attempts each user has made to connect to the session's
+    /// socket, which are counted as connections are accepted
+    attempts: RefCell<Attempts>,
+
+    /// the stream the command last wrote output to, which any output
+    /// still held back for display came from
+    stream: Stream,
+
+    /// an identifier for the session, unique on this host
+    session_id: String,
+
+    /// when the session began, and how many bytes of output the command
+    /// has written since
+    started:      Instant,
+    output_bytes: u64,
+
+    /// the size of the user's terminal, for approvers who join partway
+    /// through the session
+    winsize: (u32, u32),
+
+    /// whether the command's output to the user's terminal last ended a
+    /// line, so messages from the approver can be shown on their own
+    at_line_start: bool,
+
+    /// the timing of the user's keystrokes, if sudo is logging them
+    latency: Option<KeystrokeLatency>,
+
+    /// the recording of the session's output, if it's being recorded
+    recording: Option<Recording>,
+
+    /// whether the rest of the session's output is logged to syslog,
+    /// since its recording failed
+    recording_to_syslog: bool,
+
+    /// the threads started during the session, which are all joined
+    /// once it closes
+    threads: Threads,
+
+    /// the session's place among those running on the host, if it
+    /// could be given one
+    slot: Option<Slot>,
+
+    /// the request that the session end, once an administrator's
+    /// request has been noticed
+    killed: Option<KillRequest>,
+
+    /// the files being edited as they were before the session, if this
+    /// is a `sudoedit` session
+    edits: Option<EditCapture>,
 
     slog: slog::Logger,
 }
 
 impl SudoPair {
     fn open(plugin: &'static Plugin) -> Result<Self> {
+        let pair = Self::open_session(plugin);
+
+        match pair.as_ref() {
+            // what's captured is only disclosed once it's known how the
+            // session was opened (e.g., whether anyone is watching it)
+            Ok(pair) => pair.disclose(),
+
+            // sudo's own logs would otherwise only show that the plugin
+            // failed to open; sudo only hands audit plugins an I/O
+            // plugin's reason for failing through the `errstr` of
+            // version 1.15 of the I/O plugin API, and this is built
+            // against an older one (`sudo_pair_audit` is only told
+            // about subcommands), so it goes to sudo's debug log
+            Err(e) => {
+                let _ = plugin.print(Level::Debug, e.rejection().as_bytes());
+            },
+        }
+
+        pair
+    }
+
+    /// Runs the self-check when root runs `sudo -V` (which sudo asks
+    /// plugins to be verbose for), along with the checks too slow or
+    /// intrusive to run for every session, and prints the results as a
+    /// table. Nothing is logged, since no session is being opened.
+    fn show_version(plugin: &'static Plugin, verbose: bool) -> Result<()> {
+        if !verbose {
+            return Ok(());
+        }
+
+        let mut health = Health::default();
+
+        match PluginOptions::try_from(&plugin.plugin_options) {
+            Ok(options) => {
+                health.pass("options");
+
+                check_dependencies(&options, &mut health);
+
+                match options.recording_sink {
+                    RecordingSinkKind::Command => if let Some(path) = options.recording_command.as_ref() {
+                        health.check_executable("recording_command", path);
+                    },
+
+                    RecordingSinkKind::Socket => if let Some(path) = options.recording_socket.as_ref() {
+                        health.check_socket("recording_socket", path);
+                    },
+
+                    _ => {},
+                }
+            },
+
+            Err(problems) => health.fail("options", problems.join("; ")),
+        }
+
+        health.check_crypto();
+
+        let verdict = if health.is_healthy() { "passed" } else { "failed" };
+
+        let _ = write!(plugin.stdout(), "{} self-check {}:\n{}", plugin.plugin_name, verdict, health.table());
+
+        Ok(())
+    }
+
+    fn open_session(plugin: &'static Plugin) -> Result<Self> {
         let mut slog = slog(
             &plugin.plugin_name,
             &plugin.plugin_version.as_deref().unwrap_or("<unknown>"),
@@ -111,56 +479,482 @@ impl SudoPair {
 
         slog::debug!(slog, "plugin initializing");
 
-        let args : Vec<_> = plugin.command.iter()
-            .skip(1)
-            .map (|arg| arg.to_string_lossy())
-            .collect();
+        let clock : Arc<dyn Clock> = Arc::new(SystemClock);
 
         slog = slog::Logger::new(&slog, slog::o!(
             "uid"           => &plugin.user_info.uid,
             "runas_euid"    => &plugin.command_info.runas_euid,
             "runas_egid"    => &plugin.command_info.runas_egid,
             "command"       => plugin.command_info.command.to_string_lossy().into_owned(),
-            "args"          => format!("{:?}", args),
+            "sudo_api"      => plugin.version.to_string(),
         ));
 
-        let options = PluginOptions::from(&plugin.plugin_options);
+        // sudo before 1.8.21 never calls `change_winsize`, so approvers
+        // only ever learn the terminal's initial size
+        #[cfg(feature = "change_winsize")]
+        {
+            if !plugin.calls_change_winsize() {
+                slog::debug!(slog, "sudo doesn't report terminal resizes");
+            }
+        }
+
+        // parsing the options is part of the self-check, so its results
+        // are reported even when they can't be parsed
+        let mut health = Health::default();
+
+        // report every problem with the provided options at once,
+        // since fixing them one sudo invocation at a time would be
+        // needlessly painful
+        let options = match PluginOptions::try_from(&plugin.plugin_options) {
+            Ok(options) => {
+                health.pass("options");
+
+                options
+            },
+
+            Err(problems) => {
+                for problem in &problems {
+                    slog::error!(slog, "invalid plugin option"; "problem" => problem);
+
+                    let _ = plugin.print(Level::Error, problem.as_bytes());
+                }
+
+                health.fail("options", format!("{} invalid", problems.len()));
+                report_health(plugin, &slog, &health);
+
+                return Err(ErrorKind::InvalidOptions.into());
+            },
+        };
 
         slog::debug!(slog, "initialized with plugin options:";
              "plugin_options" => &options
         );
 
+        // arguments are only logged once it's known how many of them
+        // can be, since some commands have thousands
+        let command : Vec<_> = plugin.command.iter().map(|arg| arg.as_bytes()).collect();
+
+        slog = slog::Logger::new(&slog, slog::o!(
+            "args" => format!("{:?}", command_limits(&options).apply(&command).args()),
+        ));
+
+        // every event from here on is tagged, so they can be routed
+        // without matching on the hostname
+        if !options.host_tags.is_empty() {
+            let tags : Vec<_> = options.host_tags.iter().map(HostTag::to_string).collect();
+
+            slog = slog::Logger::new(&slog, slog::o!("host_tags" => tags.join(",")));
+        }
+
+        // and with whatever the policy plugin labeled the session as
+        let labels = session_labels(plugin, &slog);
+
+        if !labels.is_empty() {
+            slog = slog::Logger::new(&slog, slog::o!("labels" => labels.join(",")));
+        }
+
+        // and with the change it's a part of, so the sessions of a
+        // change spanning several hosts can be found together
+        let correlation_id = session_correlation_id(plugin, &slog);
+
+        if let Some(id) = correlation_id.as_ref() {
+            slog = slog::Logger::new(&slog, slog::o!("correlation_id" => id.clone()));
+        }
+
+        // and with the kind of terminal it was run from, since sessions
+        // left in a multiplexer can outlive their approver's attention
+        let terminal = Terminal::detect(
+            plugin.user_info.tty.as_deref(),
+            |var| plugin.user_env.get_str(var).map(String::from),
+            &terminal::ancestors(plugin.user_info.ppid),
+        );
+
+        slog = slog::Logger::new(&slog, slog::o!("tty_kind" => terminal.kind.as_str()));
+
+        if let Some(multiplexer) = terminal.multiplexer.as_ref() {
+            slog = slog::Logger::new(&slog, slog::o!("multiplexer" => multiplexer.name));
+        }
+
+        // as are events during a maintenance window, whether or not the
+        // session ends up needing a pair
+        let maintenance = options.maintenance_window.as_ref()
+            .and_then(|path| maintenance_window(&slog, path, clock.wall()));
+
+        if let Some(window) = maintenance.as_ref() {
+            slog = slog::Logger::new(&slog, slog::o!("maintenance_window" => window.id.clone()));
+        }
+
+        // and while the plugin's disabled, so nothing that happens
+        // while it is can be mistaken for business as usual
+        let disabled = options.disable_file.as_ref()
+            .and_then(|path| disabled(&slog, path));
+
+        if disabled.is_some() {
+            slog = slog::Logger::new(&slog, slog::o!("disable_mode" => options.disable_mode.name()));
+        }
+
+        if options.quiet {
+            plugin.set_min_level(Level::Warn);
+        }
+
+        check_dependencies(&options, &mut health);
+        report_health(plugin, &slog, &health);
+
+        if options.strict_health && !health.is_healthy() {
+            return Err(ErrorKind::Unhealthy.into());
+        }
+
+        options.apply_identity_source(plugin);
+
+        let session_id = recording::session_id();
+
+        // sessions are counted before any of them opens a socket or a
+        // recording, so the limit bounds every resource they use; the
+        // registry they're counted in is on disk, so they aren't when
+        // nothing may be written there (which requires there be no limit)
+        let slot = if options.in_memory_only {
+            None
+        } else {
+            match Slot::claim(&options.socket_dir.join("sessions"), &session_id, options.max_sessions.into()) {
+                Ok(slot) => {
+                    slog::debug!(slog, "sessions running"; "sessions" => slot.running + 1);
+
+                    let about = About {
+                        user:       plugin.user_name(),
+                        runas_user: plugin.runas_user_name(),
+                        command:    plugin.argv().iter()
+                            .map(|arg| arg.to_string_lossy())
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        started_at: clock.wall(),
+                    };
+
+                    // the session still counts without it, but can't be
+                    // told apart from others when they're listed
+                    if let Err(e) = slot.describe(&about) {
+                        slog::warn!(slog, "unable to describe session"; "error" => e.to_string());
+                    }
+
+                    Some(slot)
+                },
+
+                Err(ClaimError::Full { running }) => {
+                    slog::error!(slog, "too many concurrent sessions";
+                        "sessions"     => running,
+                        "max_sessions" => options.max_sessions,
+                    );
+
+                    return Err(ErrorKind::TooManySessions.into());
+                },
+
+                // the limit protects the host rather than anything the
+                // session could reach, so a registry that can't be used
+                // doesn't keep anyone from working
+                Err(ClaimError::Io(e)) => {
+                    slog::warn!(slog, "unable to count running sessions"; "error" => e.to_string());
+
+                    None
+                },
+            }
+        };
+
+        #[cfg(feature = "debug_capture")]
+        {
+            if let Some(dir) = options.debug_capture_dir.as_ref() {
+                match Capture::of(plugin).save(dir, &session_id) {
+                    Ok(path) => slog::debug!(slog, "plugin inputs captured";
+                        "capture" => path.to_string_lossy().into_owned(),
+                    ),
+
+                    Err(e) => slog::warn!(slog, "unable to capture plugin inputs";
+                        "error" => e.to_string(),
+                    ),
+                }
+            }
+        }
+
+        let context = match CommandContext::capture(plugin) {
+            Ok(context) => {
+                slog::info!(slog, "command context";
+                    "command_cwd"        => context.cwd.to_string_lossy().into_owned(),
+                    "command_executable" => context.executable.to_string_lossy().into_owned(),
+                    "command_path"       => context.path.to_string_lossy().into_owned(),
+                    "command_dev"        => context.dev,
+                    "command_ino"        => context.ino,
+                    "command_owner"      => context.owner,
+                    "command_mode"       => format!("{:#06o}", context.mode & 0o7777),
+                    "command_sha256"     => &context.sha256,
+                );
+
+                Some(context)
+            },
+
+            Err(e) => {
+                slog::warn!(slog, "unable to inspect command"; "error" => e.to_string());
+
+                None
+            },
+        };
+
+        slog::info!(slog, "command restrictions";
+            "restrictions" => Restrictions::of(&plugin.command_info).summary(),
+        );
+
+        if let Some(shell) = plugin.runas_shell() {
+            slog::info!(slog, "shell session";
+                "shell"       => shell.display().to_string(),
+                "login"       => plugin.is_login_shell(),
+                "login_class" => plugin.login_class().unwrap_or(""),
+            );
+        }
+
+        let edits = if plugin.invocation_kind() == InvocationKind::Edit {
+            let files : Vec<_> = plugin.edited_files().iter()
+                .map(|file| file.to_string_lossy().into_owned())
+                .collect();
+
+            slog::info!(slog, "sudoedit session"; "edited_files" => format!("{:?}", files));
+
+            Some(EditCapture::before(plugin.edited_files(), options.sudoedit_diff_limit as usize))
+        } else {
+            None
+        };
+
         // TODO: convert all outgoing errors to be unauthorized errors
+        let display    = Display::new(
+            options.sanitize_escapes.clone(),
+            options.utf8_chunking,
+            options.binary_output,
+        );
+        let transcript = Transcript::new(options.transcript_digest_interval, clock.clone());
+        let throttle   = Some(options.output_rate_limit.as_u64())
+            .filter(|&rate| rate > 0)
+            .map(|rate| Throttle::new(rate, clock.instant()));
+
+        let attempts = RefCell::new(Attempts::new(
+            options.connection_attempt_limit,
+            options.connection_attempt_window,
+            options.connection_ban,
+        ));
+
         let mut pair = Self {
             plugin,
             options,
-            socket:  None,
+            session: Session::Unpaired,
+            context,
+
+            ticket:             None,
+            approver_comments:  Vec::new(),
+            colocated_approver: None,
+            web_approver:       None,
+            maintenance,
+            disabled,
+            automation: false,
+
+            subcommands:         Vec::new(),
+            subcommands_omitted: 0,
+
+            labels,
+            correlation_id,
+            terminal,
+
+            socket_path:  PathBuf::new(),
+            socket_owner: None,
+            socket_group: None,
+
+            quorum:       Quorum::default(),
+            approver_gid: None,
+            coverage:     Coverage::Full,
+
+            approver_keys: None,
+            approver_totp: None,
+
+            started:      clock.instant(),
+            clock,
+            display,
+            transcript,
+            throttle,
+            attempts,
+            stream:       Stream::TtyOut,
+            session_id,
+            output_bytes: 0,
+            winsize:      (plugin.user_info.lines as _, plugin.user_info.cols as _),
+            at_line_start: true,
+            latency:      Some(KeystrokeLatency::default())
+                .filter(|_| plugin.command_info.iolog_ttyin),
+            recording:    None,
+            recording_to_syslog: false,
+            threads:      Threads::default(),
+            slot,
+            killed:       None,
+            edits,
 
             slog,
         };
 
-        if pair.is_exempt() {
-            slog::info!(pair.slog, "pair session exempt from pairing requirements");
+        match pair.require_pair() {
+            Err(e) if e.kind().is_internal() && pair.options.on_internal_error == InternalErrorPolicy::AllowWithAudit => {
+                pair.allow_unpaired(&e);
+
+                Ok(pair)
+            },
 
-            return Ok(pair)
+            // automation accounts are only shown this, which their
+            // logs can be searched for
+            Err(e) if pair.automation => {
+                let _ = pair.plugin.print(Level::Error, e.rejection().as_bytes());
+
+                Err(e)
+            },
+
+            result => result.map(|_| pair),
+        }
+    }
+
+    /// Decides whether the session needs a pair and, if it does, waits
+    /// for one to approve it, leaving the session active once they do.
+    fn require_pair(&mut self) -> Result<()> {
+        // automation accounts can't answer prompts, so they're only
+        // shown what went wrong
+        if self.is_automation_account() {
+            slog::info!(self.slog, "automation account, interactive output suppressed");
+
+            self.automation = true;
+            self.plugin.set_min_level(Level::Error);
+        }
+
+        // the pair rules decide whether the session is recorded as well
+        // as who has to approve it, so they're consulted before either
+        let (quorum, coverage) = self.match_pair_rules();
+
+        self.quorum   = quorum;
+        self.coverage = coverage;
+
+        if self.is_bypassed() {
+            return Ok(());
+        }
+
+        // every session is recorded, whether or not it needs a pair,
+        // unless it matched a rule saying it must never be
+        if self.coverage == Coverage::PairOnly {
+            slog::info!(self.slog, "pair session not recorded, as pair rules require");
+        } else {
+            self.start_recording()?;
+        }
+
+        if self.is_exempt() {
+            slog::info!(self.slog, "pair session exempt from pairing requirements");
+
+            return Ok(())
+        }
+
+        // on hosts requiring both, there's no sense in keeping anyone
+        // waiting to approve a session that can't be run anyway
+        if self.options.require_pair_and_recording && self.recording.is_none() {
+            return Err(self.partially_set_up(ErrorKind::RecordingRequired));
+        }
+
+        self.approve()?;
+
+        if self.options.require_pair_and_recording && !self.is_approved_by_pair() {
+            return Err(self.partially_set_up(ErrorKind::PairRequired));
         }
 
-        slog::info!(pair.slog, "pair session required");
+        Ok(())
+    }
 
-        if pair.is_sudoing_to_user_and_group() {
-            slog::error!(pair.slog, "both -u and -g were provided to sudo"; slog::o!(
-                "user"  => &pair.plugin.settings.runas_user,
-                "group" => &pair.plugin.settings.runas_group,
+    /// Gets the session approved, whether by a pair or by whichever
+    /// policy lets it go ahead without one.
+    fn approve(&mut self) -> Result<()> {
+        slog::info!(self.slog, "pair session required");
+
+        if self.is_sudoing_to_user_and_group() {
+            slog::error!(self.slog, "both -u and -g were provided to sudo"; slog::o!(
+                "user"  => &self.plugin.settings.runas_user,
+                "group" => &self.plugin.settings.runas_group,
             ));
 
             return Err(ErrorKind::SudoToUserAndGroup.into());
         }
 
-        let template_spec = pair.template_spec();
+        if self.is_disabled() {
+            return Ok(());
+        }
+
+        if self.is_in_maintenance() {
+            return Ok(());
+        }
+
+        if self.is_preapproved() {
+            return Ok(());
+        }
+
+        // sessions that nobody can interact with can't be paired in
+        // the usual way, so follow the configured policies instead of
+        // waiting on a pair that may never arrive
+        if self.is_unattended()? {
+            return Ok(());
+        }
+
+        self.ticket = self.verify_ticket()?;
+        self.correlate_with_ticket();
+
+        if self.is_colocated_approved()? {
+            return Ok(());
+        }
+
+        if self.is_web_approved()? {
+            return Ok(());
+        }
+
+        self.load_approver_keys()?;
+
+        if let Some(path) = self.options.approver_totp_keys.as_ref() {
+            let keys = TotpKeys::load(path).map_err(|e| {
+                slog::error!(self.slog, "unable to load approver one-time code secrets";
+                    "approver_totp_keys" => path.to_string_lossy().into_owned(),
+                    "error"              => e,
+                );
+
+                ErrorKind::UnreadableApproverKeys
+            })?;
+
+            slog::debug!(self.slog, "approvers must enter one-time codes"; "approvers" => keys.len());
+
+            self.approver_totp = Some(keys);
+        }
+
+        if let Some(group) = self.quorum.group.clone() {
+            let gid = self.gid(&group)
+                .ok_or(ErrorKind::UnknownApproverGroup)?;
+
+            self.approver_gid = Some(gid);
+        }
+
+        if let Some(user) = self.options.socket_owner.clone() {
+            self.socket_owner = Some(self.uid(&user)
+                .ok_or(ErrorKind::UnknownSocketOwner)?);
+        }
+
+        if let Some(group) = self.options.socket_group.clone() {
+            self.socket_group = Some(self.gid(&group)
+                .ok_or(ErrorKind::UnknownSocketOwner)?);
+        }
+
+        self.socket_path = self.free_socket_path();
+
+        let template_spec = self.template_spec();
 
-        pair.local_pair_prompt(&template_spec);
-        pair.remote_pair_connect()?;
-        pair.remote_pair_prompt(&template_spec)?;
+        self.local_pair_prompt(&template_spec);
+
+        let mut active = self.remote_pair(&template_spec)?;
+
+        if self.options.verbose {
+            let _ = self.plugin.print(Level::Info, format!(
+                "session approved after {}",
+                humanize::duration(self.elapsed()),
+            ).as_bytes());
+        }
 
         // TODO(security): provide a configurable option to deny or log
         // if the remote euid is the same as the local euid. For some
@@ -187,394 +981,2782 @@ impl SudoPair {
         // through providing the token from their original user). This
         // shouldn't be too hard, but I haven't gotten around to it yet.
 
-        slog::info!(pair.slog, "pair session started");
+        // the approver's terminal is unlikely to match the user's, so
+        // start them off with the size the session began with
+        let (rows, cols) = self.winsize;
+
+        active.send_winsize(rows, cols)?;
+
+        // the limit counts from approval, not from when the user began
+        // waiting for it
+        active.expires = Some(self.options.soft_max_session_duration)
+            .filter(|limit| *limit > Duration::from_secs(0))
+            .map(|limit| self.clock.instant() + limit);
+
+        active.reapproval = Some(self.options.reapproval_interval)
+            .filter(|interval| *interval > Duration::from_secs(0))
+            .map(|interval| Reapproval::after_on(self.clock.as_ref(), interval));
+
+        if self.options.transfer_timeout > Duration::from_secs(0) {
+            active.listener = self.listen_for_transfers();
+        }
+
+        self.session = Session::Active(active);
+
+        slog::info!(self.slog, "pair session started");
+
+        Ok(())
+    }
+
+    /// Whether an approver accepted the session, rather than it being
+    /// let go ahead without one (e.g., by a maintenance window).
+    fn is_approved_by_pair(&self) -> bool {
+        self.session.is_paired() ||
+            self.colocated_approver.is_some() ||
+            self.web_approver.is_some()
+    }
+
+    /// Refuses a session that `require_pair_and_recording` requires be
+    /// both paired and recorded, but that's missing the half `kind`
+    /// names, logging which it has.
+    fn partially_set_up(&self, kind: ErrorKind) -> Error {
+        slog::error!(self.slog, "pair session refused, both a pair and a recording are required";
+            "reason"   => kind.code(),
+            "paired"   => self.is_approved_by_pair(),
+            "recorded" => self.recording.is_some(),
+        );
+
+        kind.into()
+    }
+
+    /// Lets the session run unpaired in spite of an internal `error`,
+    /// as `on_internal_error` allows, making sure it can't go unnoticed
+    /// by whoever reads the logs or the user running it.
+    fn allow_unpaired(&self, error: &Error) {
+        slog::crit!(self.slog, "pair session allowed unpaired after an internal error";
+            "reason"            => error.kind().code(),
+            "error"             => error.to_string(),
+            "on_internal_error" => self.options.on_internal_error.name(),
+        );
+
+        let _ = self.plugin.print(Level::Debug, error.allowance().as_bytes());
+        let _ = self.plugin.print(Level::Warn, format!(
+            "{}; this session is running without a pair, and that's been logged",
+            error.kind(),
+        ).as_bytes());
+    }
+
+    fn close(&mut self, status: CommandStatus) {
+        slog::trace!(self.slog, "pair session ending");
+
+        // what the approver didn't see is marked in the recording once
+        // they've been sent everything else
+        let mut marked = Vec::new();
+
+        // record (and send, if we're streaming them) a final checkpoint
+        // so the approver's view of the session can be compared against
+        // the recording
+        if let Some(active) = self.session.watched() {
+            // send anything still being held back for display, since
+            // there won't be any more output to complete it
+            let output     = self.display.flush();
+            let suppressed = self.display.take_suppressed();
+            let admitted   = match self.throttle.as_mut() {
+                Some(throttle) => throttle.admit(output.len(), self.clock.instant()),
+                None           => true,
+            };
+
+            if admitted {
+                let _ = active.send_stream(&mut self.transcript, &self.slog, self.stream, &output);
+
+                for suppression in &suppressed {
+                    let _ = active.send_suppressed(suppression);
+                }
+
+                marked.extend(suppressed);
+            }
+
+            // and tell the approver about anything they didn't see
+            if let Some(withheld) = self.throttle.as_mut().and_then(Throttle::flush) {
+                let _ = active.send_output(&mut self.transcript, &self.slog, &withheld.marker());
+                let _ = active.send_suppressed(&withheld);
+
+                marked.push(withheld);
+            }
+
+            // the approver approved a command that sudo couldn't then
+            // execute, which they'd otherwise only see as no output
+            if let CommandStatus::CommandNotRun(_) = status {
+                let _ = active.send_output(
+                    &mut self.transcript,
+                    &self.slog,
+                    format!("\ncommand {}\n", status).as_bytes(),
+                );
+            }
+
+            let checkpoint = self.transcript.checkpoint();
+
+            if self.transcript.is_streamed() && active.protocol().streams(Capabilities::DIGESTS) {
+                let _ = active.send_checkpoint(&checkpoint);
+            }
+
+            slog::info!(self.slog, "pair session transcript";
+                "bytes"  => checkpoint.bytes,
+                "sha256" => &checkpoint.digest,
+            );
+
+            if self.options.verbose {
+                let _ = self.plugin.print(Level::Info, format!(
+                    "session {} ended after {}; {} bytes were relayed to the approver (sha256 {})",
+                    self.session_id,
+                    humanize::duration(self.elapsed()),
+                    checkpoint.bytes,
+                    checkpoint.digest,
+                ).as_bytes());
+            }
+        }
+
+        for suppression in &marked {
+            let _ = self.mark(suppression);
+        }
+
+        let terminated = self.session.termination();
+
+        // disconnect the approver, and anyone offering to take over a
+        // session that's over
+        self.session.close();
+
+        for panic in self.threads.join_all() {
+            slog::error!(self.slog, "pair session thread panicked";
+                "thread" => panic.thread,
+                "panic"  => panic.message,
+            );
+        }
+
+        // the command may have ended because of the request (sudo
+        // relays the hangup `sudo_pair_admin` sends it) before the
+        // session was checked again
+        let _ = self.check_killed();
+
+        let outcome = manifest::outcome(status);
+
+        let recording = self.recording.take()
+            .map(|recording| (self.manifest(&recording, outcome), recording));
+
+        let index = self.options.session_index.clone().and_then(|path| {
+            self.recording_ownership().ok().map(|ownership| (path, ownership))
+        });
+
+        // the session no longer counts against `max_sessions` once
+        // everything it started has finished
+        let slot = self.slot.take();
+
+        match self.edits.take() {
+            // sudo only copies edited files back once it's closed its
+            // plugins, so what changed can't be known until it exits,
+            // and the session holds its slot until then
+            Some(edits) => {
+                let slog = self.slog.clone();
+
+                at_exit::defer(move || {
+                    let edits = edits.after();
+
+                    for edit in &edits {
+                        slog::info!(slog, "sudoedit file";
+                            "path"          => &edit.path,
+                            "before_sha256" => edit.before_sha256.as_deref().unwrap_or("none"),
+                            "after_sha256"  => edit.after_sha256.as_deref().unwrap_or("none"),
+                            "changed"       => edit.before_sha256 != edit.after_sha256,
+                        );
+                    }
+
+                    if let Some((mut manifest, recording)) = recording {
+                        manifest.edits = edits;
+
+                        finish_recording(&slog, recording, &manifest, index);
+                    }
+
+                    drop(slot);
+                });
+
+                self.run_close_hook(outcome);
+            },
+
+            None => {
+                if let Some((manifest, recording)) = recording {
+                    finish_recording(&self.slog, recording, &manifest, index);
+                }
+
+                self.run_close_hook(outcome);
+
+                drop(slot);
+            },
+        }
+
+        let checkpoint = self.transcript.checkpoint();
+
+        slog::info!(self.slog, "pair session ended";
+            "outcome"         => format!("{:?}", outcome),
+            "terminated"      => terminated.map_or("no", ErrorKind::code),
+            "duration_ms"     => self.elapsed().as_millis() as u64,
+            "output_bytes"    => self.output_bytes,
+            "withheld_bytes"  => self.throttle.as_ref().map_or(0, Throttle::withheld),
+            "approver_bytes"  => checkpoint.bytes,
+            "approver_sha256" => checkpoint.digest,
+        );
+    }
+
+    fn log_ttyin(&mut self, _: &[u8]) -> Result<()> {
+        // keystrokes aren't logged (only when they arrive, if sudo is
+        // logging input), but they're the only sign of life from an
+        // idle session that's outlived its limit or is awaiting
+        // re-approval
+        if let Some(latency) = self.latency.as_mut() {
+            latency.keystroke(self.clock.instant());
+        }
+
+        self.check_session()
+    }
+
+    fn log_ttyout(&mut self, log: &[u8]) -> Result<()> {
+        self.check_session()?;
+
+        if let Some(&last) = log.last() {
+            self.at_line_start = last == b'\n';
+        }
+
+        if !self.plugin.command_info.iolog_ttyout {
+            return Ok(())
+        }
+
+        self.record(Frame::TtyOut, log)?;
+        self.log_output(Stream::TtyOut, log)?;
+
+        // sudo only shows the user this output once we're done with it,
+        // so this is when any keystroke it echoes is echoed
+        if let Some(latency) = self.latency.as_mut() {
+            latency.output(self.clock.instant());
+        }
+
+        Ok(())
+    }
+
+    fn log_stdout(&mut self, log: &[u8]) -> Result<()> {
+        self.check_session()?;
+
+        if !self.plugin.command_info.iolog_stdout {
+            return Ok(())
+        }
+
+        self.record(Frame::StdOut, log)?;
+        self.log_output(Stream::StdOut, log)
+    }
+
+    fn log_stderr(&mut self, log: &[u8]) -> Result<()> {
+        self.check_session()?;
+
+        if !self.plugin.command_info.iolog_stderr {
+            return Ok(())
+        }
+
+        self.record(Frame::StdErr, log)?;
+        self.log_output(Stream::StdErr, log)
+    }
+
+    fn log_output(&mut self, stream: Stream, log: &[u8]) -> Result<()> {
+        // output is only streamed to an approver who's approved the
+        // session
+        if !matches!(self.session, Session::Active(_)) {
+            return Ok(());
+        }
+
+        let output     = self.display.process(log);
+        let suppressed = self.display.take_suppressed();
+
+        self.stream = stream;
+
+        let admitted = match self.throttle.as_mut() {
+            Some(throttle) => throttle.admit(output.len(), self.clock.instant()),
+            None           => true,
+        };
+
+        // anything in output that's withheld is covered by the marker
+        // for the rate limit, once output is relayed again
+        if !admitted {
+            return Ok(());
+        }
+
+        let withheld = self.throttle.as_mut().and_then(Throttle::flush);
+
+        for suppression in withheld.iter().chain(&suppressed) {
+            self.mark(suppression)?;
+        }
+
+        // marking them may have ended the session
+        let active = match self.session {
+            Session::Active(ref mut active) => active,
+            _                               => return Ok(()),
+        };
+
+        if let Some(withheld) = withheld {
+            active.send_output(&mut self.transcript, &self.slog, &withheld.marker())?;
+            active.send_suppressed(&withheld)?;
+        }
+
+        active.send_stream(&mut self.transcript, &self.slog, stream, &output)?;
+
+        for suppression in &suppressed {
+            active.send_suppressed(suppression)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks a paired session that's still active, terminating it if
+    /// it should be. Sessions that have been terminated already never
+    /// get this far, since sudo_plugin rejects their callbacks.
+    fn check_session(&mut self) -> Result<()> {
+        if self.check_killed() {
+            return Err(self.terminate(Termination::new(
+                ErrorKind::SessionKilled,
+                "session ended: an administrator killed it",
+            )));
+        }
+
+        let mut active = match mem::replace(&mut self.session, Session::Unpaired) {
+            Session::Active(active) => active,
+            session                 => {
+                self.session = session;
+
+                return Ok(());
+            },
+        };
+
+        let checked = self.check_active(&mut active);
+
+        self.session = Session::Active(active);
+
+        checked.map_err(|reason| self.terminate(reason))
+    }
+
+    /// Ends the session early for `reason`, however it's noticed: the
+    /// approver is told why, and every callback from sudo from this one
+    /// on rejects the command, the first telling the user why. Returns
+    /// the error the callback that noticed fails with.
+    fn terminate(&mut self, reason: Termination) -> Error {
+        slog::warn!(self.slog, "pair session terminated";
+            "reason" => reason.kind.code(),
+        );
+
+        self.session.terminate(&mut self.transcript, &self.slog, reason);
+        self.plugin.terminate(reason.kind.to_string());
+
+        reason.kind.into()
+    }
+
+    /// Notices whether an administrator has asked the session to end
+    /// (see `sudo_pair_admin`), logging who asked and why the first time
+    /// it's noticed.
+    fn check_killed(&mut self) -> bool {
+        if self.killed.is_some() {
+            return true;
+        }
+
+        self.killed = self.slot.as_ref().and_then(Slot::kill_request);
+
+        if let Some(kill) = self.killed.as_ref() {
+            slog::warn!(self.slog, "pair session killed by administrator";
+                "requested_by" => &kill.requested_by,
+                "reason"       => &kill.reason,
+            );
+        }
+
+        self.killed.is_some()
+    }
+
+    /// Checks whether an active session has outlived its limits, or is
+    /// overdue for re-approval, after asking for it if it's due. Also
+    /// makes progress on handing the session off, if another approver
+    /// has offered to take it over, and passes along messages between
+    /// the approver and the user.
+    fn check_active(&mut self, active: &mut Active) -> std::result::Result<(), Termination> {
+        self.check_expiry(active)?;
+
+        // both wait on an answer from the approver, so they're never
+        // asked for both at once
+        if active.transfer.is_none() {
+            self.check_reapproval(active)?;
+        }
+
+        if !matches!(active.reapproval, Some(Reapproval::Pending(_))) {
+            self.check_transfer(active);
+        }
+
+        Ok(self.check_chats(active)?)
+    }
+
+    /// Shows the user the messages the approver has sent them since the
+    /// last callback, ahead of the output it was called with, and asks
+    /// them to reply to those that ask for one.
+    fn check_chats(&mut self, active: &mut Active) -> Result<()> {
+        for chat in active.take_chats() {
+            slog::info!(self.slog, "pair chat message";
+                "from" => "approver",
+                "text" => &chat.text,
+                "ask"  => chat.ask,
+            );
+
+            let notice = chat::notice(&chat, self.at_line_start);
+            let _      = delivery::deliver(self.plugin, &self.options.prompt_delivery, &notice);
+
+            self.at_line_start = true;
+
+            // there's nobody to ask when sudo was told not to prompt
+            if !chat.ask || self.plugin.settings.noninteractive {
+                continue;
+            }
+
+            let reply = self.plugin.conversation()
+                .prompt_timeout(chat::REPLY_PROMPT, true, chat::REPLY_TIMEOUT)
+                .ok()
+                .and_then(|input| chat::reply(&input, active.protocol().limits.chat));
+
+            if let Some(reply) = reply {
+                slog::info!(self.slog, "pair chat message";
+                    "from" => "user",
+                    "text" => &reply,
+                );
+
+                active.send_chat(&reply)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How long it's been since the session began.
+    fn elapsed(&self) -> Duration {
+        self.clock.instant().saturating_duration_since(self.started)
+    }
+
+    /// Ends the session if it's outlived `soft_max_session_duration`.
+    fn check_expiry(&mut self, active: &mut Active) -> std::result::Result<(), Termination> {
+        let expiry = active.check_expiry(self.clock.as_ref());
+
+        if expiry.is_err() {
+            slog::warn!(self.slog, "pair session exceeded maximum duration";
+                "soft_max_session_duration" => format!("{:?}", self.options.soft_max_session_duration),
+            );
+        }
+
+        expiry
+    }
+
+    /// Asks the approver to re-approve the session once
+    /// `reapproval_interval` has passed since they last did, and ends
+    /// the session if they decline or don't answer within
+    /// `reapproval_grace`.
+    fn check_reapproval(&mut self, active: &mut Active) -> std::result::Result<(), Termination> {
+        match active.reapproval {
+            Some(Reapproval::Pending(deadline))                             => self.await_reapproval(active, deadline),
+            Some(reapproval) if reapproval.is_due_on(self.clock.as_ref()) => Ok(self.request_reapproval(active)?),
+            _                                                               => Ok(()),
+        }
+    }
+
+    fn request_reapproval(&mut self, active: &mut Active) -> Result<()> {
+        let deadline = Deadline::after_on(self.clock.as_ref(), self.options.reapproval_grace);
+        let protocol = active.protocol();
+
+        // anything typed before the request can't be an answer to it;
+        // if the pair's gone, that's noticed while awaiting one
+        active.discard_input();
+
+        if protocol.streams(Capabilities::REAPPROVAL) {
+            active.send_reapprove(deadline.unix_secs())?;
+        }
+
+        if !protocol.handles(Capabilities::REAPPROVAL) {
+            let prompt = reapproval::prompt(self.options.reapproval_grace, deadline.time(), protocol.utc_offset);
+
+            active.send_output(&mut self.transcript, &self.slog, prompt.as_bytes())?;
+        }
+
+        slog::info!(self.slog, "pair session re-approval requested";
+            "deadline" => deadline.unix_secs(),
+        );
+
+        active.reapproval = Some(Reapproval::Pending(deadline));
+
+        Ok(())
+    }
+
+    fn await_reapproval(&mut self, active: &mut Active, deadline: Deadline) -> std::result::Result<(), Termination> {
+        // answers are looked for before the deadline is checked, since
+        // one that arrived while the session was idle still came in time
+        match active.pair_answer() {
+            Some(true) => {
+                slog::info!(self.slog, "remote pair re-approved session");
+
+                active.reapproval = Some(Reapproval::after_on(self.clock.as_ref(), self.options.reapproval_interval));
+
+                Ok(())
+            },
+
+            Some(false) => self.end_unapproved(
+                ErrorKind::SessionTerminated,
+                "session ended: the pair declined to re-approve it",
+            ),
+
+            None if deadline.has_passed_on(self.clock.as_ref()) => self.end_unapproved(
+                ErrorKind::ReapprovalTimedOut,
+                "session ended: it wasn't re-approved in time",
+            ),
+
+            None => Ok(()),
+        }
+    }
+
+    /// Ends a session that wasn't re-approved, for the reason `kind`,
+    /// telling the approver with `notice`.
+    fn end_unapproved(&self, kind: ErrorKind, notice: &'static str) -> std::result::Result<(), Termination> {
+        slog::warn!(self.slog, "pair session not re-approved"; "reason" => kind.to_string());
+
+        Err(Termination::new(kind, notice))
+    }
+
+    /// Reopens the session's socket so that another approver can offer
+    /// to take the session over. Sessions carry on without one if it
+    /// can't be opened.
+    fn listen_for_transfers(&self) -> Option<Listener> {
+        let listener = Listener::bind(
+            self.socket_path(),
+            self.socket_uid(),
+            self.socket_gid(),
+            self.socket_mode(),
+            self.options.socket_backlog,
+        );
+
+        match listener {
+            Ok(listener) => {
+                slog::debug!(self.slog, "socket waiting for transfers";
+                    "socket_path" => self.socket_path().to_string_lossy().into_owned(),
+                );
+
+                Some(listener)
+            },
+
+            Err(e) => {
+                slog::warn!(self.slog, "unable to accept transfers";
+                    "error" => e.to_string(),
+                );
+
+                None
+            },
+        }
+    }
+
+    /// Offers the session to another approver who's connected, or makes
+    /// progress on handing it off to one who already has. Handoffs that
+    /// fail for any reason leave the session with its current approver,
+    /// so this never ends the session.
+    fn check_transfer(&mut self, active: &mut Active) {
+        match active.transfer.take() {
+            Some(offer) => self.await_transfer(active, offer),
+            None        => self.offer_transfer(active),
+        }
+    }
+
+    fn offer_transfer(&mut self, active: &mut Active) {
+        let accepted = match active.listener.as_ref() {
+            Some(listener) => self.admit(|admission| listener.try_accept(admission)),
+            None           => return,
+        };
+
+        let mut candidate = match accepted {
+            Ok(Some(candidate)) => candidate,
+            Ok(None)            => return,
+            Err(e)              => {
+                slog::warn!(self.slog, "unable to accept transfer"; "error" => e.to_string());
+
+                return;
+            },
+        };
+
+        let challenge = match self.challenge() {
+            Ok(challenge) => challenge,
+            Err(e)        => {
+                slog::warn!(self.slog, "unable to challenge transfer candidate"; "error" => e.to_string());
+
+                return;
+            },
+        };
+
+        let deadline = Deadline::after_on(self.clock.as_ref(), self.options.transfer_timeout);
+        let hello    = self.hello(Some(deadline));
+        let prompt   = self.pair_prompt(&self.template_spec(), &hello, None, challenge.as_deref());
+
+        if let Err(e) = candidate.write_all(&prompt) {
+            slog::debug!(self.slog, "transfer candidate disconnected"; "error" => e.to_string());
+
+            return;
+        }
+
+        slog::info!(self.slog, "pair session transfer offered";
+            "deadline" => deadline.unix_secs(),
+        );
+
+        active.discard_input();
+
+        let _ = active.send_output(
+            &mut self.transcript,
+            &self.slog,
+            transfer::prompt(self.options.transfer_timeout).as_bytes(),
+        );
+
+        active.transfer = Some(Offer {
+            candidate,
+            input:    Vec::new(),
+            hello,
+            challenge,
+            released: false,
+            deadline,
+        });
+    }
+
+    fn await_transfer(&mut self, active: &mut Active, mut offer: Offer) {
+        let mut input = [0; 256];
+
+        let response = match offer.candidate.try_read(&mut input) {
+            Ok(Some(0)) | Err(_) => Response::Declined,
+            Ok(None)             => transfer::response(&offer.input),
+            Ok(Some(n))          => {
+                offer.input.extend_from_slice(&input[..n]);
+                transfer::response(&offer.input)
+            },
+        };
+
+        if !offer.released {
+            match active.pair_answer() {
+                Some(true)  => offer.released = true,
+                Some(false) => return self.abandon_transfer(active, offer, "the current approver declined it"),
+                None        => (),
+            }
+        }
+
+        match response {
+            Response::Declined => self.abandon_transfer(active, offer, "the new approver declined it"),
+
+            Response::Approved(client, credentials) if offer.released => {
+                self.complete_transfer(active, offer, client, &credentials);
+            },
+
+            _ if offer.deadline.has_passed_on(self.clock.as_ref()) => {
+                self.abandon_transfer(active, offer, "it wasn't completed in time");
+            },
+
+            _ => active.transfer = Some(offer),
+        }
+    }
+
+    fn abandon_transfer(&mut self, active: &mut Active, mut offer: Offer, reason: &str) {
+        slog::info!(self.slog, "pair session transfer abandoned"; "reason" => reason);
+
+        let notice = format!("\nhandoff abandoned: {}\n", reason);
+
+        let _ = offer.candidate.write_all(notice.as_bytes());
+        let _ = active.send_output(&mut self.transcript, &self.slog, notice.as_bytes());
+    }
+
+    /// Hands the session off to the new approver, who's negotiated the
+    /// protocol with `client` if their client supports it, once they've
+    /// authenticated with `credentials` if they have to.
+    fn complete_transfer(&mut self, active: &mut Active, mut offer: Offer, client: Option<Hello>, credentials: &Credentials) {
+        if let Err(reason) = self.authenticate_approver(offer.challenge.as_deref(), credentials) {
+            return self.abandon_transfer(active, offer, &format!("the new approver couldn't be authenticated ({})", reason));
+        }
+
+        // echo back out the response, as it was to the original approver
+        if offer.candidate.write_all(b"y\n").is_err() {
+            return self.abandon_transfer(active, offer, "the new approver disconnected");
+        }
+
+        let _ = active.send_output(
+            &mut self.transcript,
+            &self.slog,
+            b"\nsession handed off to another approver\n",
+        );
+
+        let hello    = offer.hello;
+        let protocol = client.map_or_else(Hello::legacy, |client| hello.negotiate(client));
+
+        active.hand_off(offer.candidate, protocol);
+
+        slog::info!(self.slog, "pair session transferred";
+            "protocol"  => protocol,
+            "transfers" => active.transfers,
+        );
+
+        let (rows, cols) = self.winsize;
+        let _            = active.send_winsize(rows, cols);
+
+        // the new approver's first re-approval is a full interval away
+        if active.reapproval.is_some() {
+            active.reapproval = Some(Reapproval::after_on(self.clock.as_ref(), self.options.reapproval_interval));
+        }
+    }
+
+    #[cfg(feature = "change_winsize")]
+    fn change_winsize(&mut self, rows: u32, cols: u32) -> Result<()> {
+        self.check_session()?;
+
+        slog::debug!(self.slog, "terminal resized";
+            "rows" => rows,
+            "cols" => cols,
+        );
+
+        self.winsize = (rows, cols);
+
+        self.write_recording(|recording| recording.winsize(rows, cols))?;
+
+        match self.session {
+            Session::Active(ref mut active) => active.send_winsize(rows, cols),
+            _                               => Ok(()),
+        }
+    }
+
+    /// Tells the approver about a command the session's command ran
+    /// (e.g., from a shell), and notes it for the session's manifest.
+    fn subcommand(&mut self, subcommand: Subcommand) -> Result<()> {
+        self.check_session()?;
+
+        let command = subcommand.to_string();
+
+        slog::info!(self.slog, "pair session ran a subcommand";
+            "subcommand" => &command,
+        );
+
+        if self.subcommands.len() < MAX_SUBCOMMANDS {
+            self.subcommands.push(command);
+        } else {
+            self.subcommands_omitted += 1;
+        }
+
+        let active = match self.session {
+            Session::Active(ref mut active) => active,
+            _                               => return Ok(()),
+        };
+
+        // arguments can hold anything, including what looks like the
+        // end of this line and the start of another
+        let args : Vec<_> = Some(subcommand.command.as_os_str()).into_iter()
+            .chain(subcommand.argv.iter().skip(1).map(OsString::as_os_str))
+            .map(OsStrExt::as_bytes)
+            .collect();
+
+        let shown : String = String::from_utf8_lossy(&command_limits(&self.options).apply(&args).join())
+            .chars()
+            .map(|c| if c.is_control() { '?' } else { c })
+            .collect();
+
+        let notice = format!("\n\u{2192} ran {}\n", shown);
+
+        active.send_output(&mut self.transcript, &self.slog, notice.as_bytes())
+    }
+
+    /// Starts recording the session, if it's configured to be recorded.
+    fn start_recording(&mut self) -> Result<()> {
+        let ownership = self.recording_ownership()?;
+
+        let iolog_path = self.plugin.command_info.iolog_path.as_ref().map(Path::new);
+        let name       = recording::name(&self.options, iolog_path, &self.session_id);
+
+        let sink = match recording::sink(&self.options, ownership, &self.session_id, &name) {
+            Ok(Some(sink)) => sink,
+            Ok(None)       => return Ok(()),
+            Err(e)         => {
+                slog::error!(self.slog, "unable to open recording"; "error" => e.to_string());
+
+                return Err(ErrorKind::RecordingFailed.into());
+            },
+        };
+
+        let mut recording = Recording::start(sink, self.clock.clone(), Durability::configured(&self.options))
+            .context(ErrorKind::RecordingFailed)?;
+
+        // replay needs to know the size the session began with, since
+        // it's only recorded again if it changes
+        recording.winsize(
+            self.plugin.user_info.lines as _,
+            self.plugin.user_info.cols  as _,
+        ).context(ErrorKind::RecordingFailed)?;
+
+        // if sudo is killed before the session ends, the recording can
+        // still be completed with what was known when it began
+        let manifest = self.manifest(&recording, Outcome::Incomplete);
+
+        recording.pending(&manifest)
+            .context(ErrorKind::RecordingFailed)?;
+
+        slog::info!(self.slog, "pair session recording";
+            "session_id" => &self.session_id,
+            "recording"  => recording.describe(),
+            "fsync"      => self.options.recording_fsync.name(),
+        );
+
+        self.recording = Some(recording);
+
+        Ok(())
+    }
+
+    /// The owner and permissions given to recordings and their
+    /// manifests, restricted by the umask `recording_umask` chooses. A
+    /// session can't be recorded as configured if the owner or group
+    /// can't be resolved.
+    fn recording_ownership(&self) -> Result<Ownership> {
+        let uid = match self.options.recording_owner {
+            Some(ref user) => Some(self.uid(user).ok_or(ErrorKind::RecordingFailed)?),
+            None           => None,
+        };
+
+        let gid = match self.options.recording_group {
+            Some(ref group) => Some(self.gid(group).ok_or(ErrorKind::RecordingFailed)?),
+            None            => None,
+        };
+
+        let umask = match self.options.recording_umask {
+            UmaskPolicy::Ignore => None,
+
+            // sudo always gives the umask (or the process's is filled in
+            // for versions that don't), but if it were somehow missing,
+            // nothing but root should be able to read recordings
+            UmaskPolicy::Inherit => Some(self.plugin.user_info.umask.unwrap_or(0o077)),
+
+            UmaskPolicy::Fixed(Mode(umask)) => Some(umask),
+        };
+
+        Ok(Ownership {
+            uid,
+            gid,
+            mode:  self.options.recording_mode.0 & !umask.unwrap_or(0),
+            umask: umask.unwrap_or(Ownership::default().umask),
+        })
+    }
+
+    /// What of the session is captured, and where it goes, now that
+    /// it's been opened.
+    fn disclosure(&self) -> Disclosure {
+        let info     = &self.plugin.command_info;
+        let sudo_log = |logged: bool| info.iolog_path.clone().filter(|_| logged);
+
+        Disclosure {
+            watched:          self.session.is_paired(),
+            recording:        self.recording.as_ref().map(Recording::describe),
+            keystroke_timing: self.latency.is_some(),
+            subcommands:      info.intercept,
+            sudo_input:       sudo_log(info.iolog_ttyin || info.iolog_stdin),
+            sudo_output:      sudo_log(!policy::is_exempted_from_logging(self.plugin)),
+        }
+    }
+
+    /// Shows the user what of the session is captured, with the
+    /// template at `monitoring_notice_path`, if `monitoring_notice` is
+    /// enabled and anything is.
+    fn disclose(&self) {
+        if !self.options.monitoring_notice {
+            return;
+        }
+
+        let disclosure = self.disclosure();
+
+        if disclosure.is_empty() {
+            return;
+        }
+
+        let mut template_spec = self.template_spec();
+
+        // what of the session is _M_onitored, one item per line
+        template_spec.replace(b'M', disclosure.to_list());
+
+        let notice = template_spec.render_file(
+            &self.options.monitoring_notice_path,
+            DEFAULT_NOTICE,
+        );
+
+        slog::info!(self.slog, "pair session monitoring disclosed";
+            "captured" => disclosure.items().join("; "),
+        );
+
+        let _ = self.plugin.print(Level::Info, &notice);
+    }
+
+    /// Records output the command wrote to `stream`, exactly as it was
+    /// written.
+    fn record(&mut self, stream: Frame, log: &[u8]) -> Result<()> {
+        self.output_bytes += log.len() as u64;
+
+        if self.recording_to_syslog {
+            slog::info!(self.slog, "pair session output";
+                "frame"  => (stream.tag() as char).to_string(),
+                "output" => String::from_utf8_lossy(log).into_owned(),
+            );
+
+            return Ok(());
+        }
+
+        self.write_recording(|recording| recording.output(stream, log))
+    }
+
+    /// Marks output that was kept from the approver in the log and in
+    /// the recording, so the gap in what they saw can be accounted for.
+    fn mark(&mut self, suppression: &Suppression) -> Result<()> {
+        slog::info!(self.slog, "pair session output suppressed";
+            "reason" => suppression.reason.name(),
+            "bytes"  => suppression.bytes,
+        );
+
+        self.write_recording(|recording| recording.suppressed(&suppression.describe()))
+    }
+
+    /// Writes to the session's recording, if it has one, following
+    /// `on_record_error` if it can't be written to.
+    fn write_recording<F>(&mut self, write: F) -> Result<()>
+        where F: FnOnce(&mut Recording) -> io::Result<()>
+    {
+        let written = match self.recording.as_mut() {
+            Some(recording) => write(recording),
+            None            => return Ok(()),
+        };
+
+        match written {
+            Ok(()) => Ok(()),
+            Err(e) => self.recording_failed(&e),
+        }
+    }
+
+    /// Follows `on_record_error` once the recording has failed with
+    /// `error`, telling the approver what's become of the session.
+    fn recording_failed(&mut self, error: &io::Error) -> Result<()> {
+        let policy = self.options.on_record_error;
+
+        slog::error!(self.slog, "unable to record output";
+            "error"           => error.to_string(),
+            "on_record_error" => policy.name(),
+        );
+
+        let notice : &[u8] = match policy {
+            // the recording is kept, so its manifest can still be
+            // attempted once the session closes
+            RecordErrorPolicy::Terminate => return Err(self.terminate(Termination::new(
+                ErrorKind::RecordingFailed,
+                "session ended: it couldn't be recorded",
+            ))),
+
+            RecordErrorPolicy::Continue => b"\nrecording failed: the session continues unrecorded\n",
+            RecordErrorPolicy::Syslog   => b"\nrecording failed: the rest of the session is logged to syslog\n",
+        };
+
+        if let Session::Active(ref mut active) = self.session {
+            let _ = active.send_output(&mut self.transcript, &self.slog, notice);
+        }
+
+        self.recording           = None;
+        self.recording_to_syslog = matches!(policy, RecordErrorPolicy::Syslog);
+
+        Ok(())
+    }
+
+    /// Runs the configured close hook, if any, to report how the
+    /// session ended with `outcome`. The session is already over, so a
+    /// hook that fails is only logged.
+    fn run_close_hook(&self, outcome: Outcome) {
+        let command = match self.options.close_hook.as_ref() {
+            Some(command) => command,
+            None          => return,
+        };
+
+        let checkpoint = self.transcript.checkpoint();
+
+        let event = CloseEvent {
+            session_id: self.session_id.clone(),
+            user:       self.plugin.user_name(),
+            runas_user: self.plugin.runas_user_name(),
+            executable: self.plugin.executable().to_string_lossy().into_owned(),
+            paired:     self.session.is_paired(),
+
+            ticket:            self.ticket.clone(),
+            approver_comments: self.approver_comments.clone(),
+            labels:            self.labels.clone(),
+            correlation_id:    self.correlation_id.clone(),
+
+            outcome,
+            duration: self.elapsed(),
+
+            output_bytes:    self.output_bytes,
+            approver_bytes:  checkpoint.bytes,
+            approver_sha256: checkpoint.digest,
+        };
+
+        match hook::run(command, &event, self.options.close_hook_timeout) {
+            Ok(status) if status.success() => slog::debug!(self.slog, "close hook succeeded"),
+
+            Ok(status) => slog::warn!(self.slog, "close hook failed";
+                "hook"   => command.to_string_lossy().into_owned(),
+                "status" => status.to_string(),
+            ),
+
+            Err(e) => slog::warn!(self.slog, "close hook failed";
+                "hook"  => command.to_string_lossy().into_owned(),
+                "error" => e.to_string(),
+            ),
+        }
+    }
+
+    /// Summarizes the session that was recorded by `recording`, once it
+    /// has ended with `outcome`.
+    fn manifest(&self, recording: &Recording, outcome: Outcome) -> Manifest {
+        let plugin   = self.plugin;
+        let context  = self.context.as_ref();
+        let approved = Some(self.transcript.checkpoint())
+            .filter(|_| self.session.is_paired());
+
+        Manifest {
+            session_id: self.session_id.clone(),
+            host:       plugin.user_info.host.clone(),
+            host_tags:  self.options.host_tags.iter()
+                .map(|tag| (tag.key.clone(), tag.value.clone()))
+                .collect(),
+
+            user:        plugin.user_name(),
+            uid:         plugin.user_info.uid,
+            runas_user:  plugin.runas_user_name(),
+            runas_uid:   plugin.command_info.runas_euid,
+            runas_group: plugin.runas_group_name(),
+            runas_gid:   plugin.command_info.runas_egid,
+
+            cwd:        plugin.cwd().to_string_lossy().into_owned(),
+            argv:       plugin.argv().iter().map(|arg| arg.to_string_lossy().into_owned()).collect(),
+            executable: plugin.executable().to_string_lossy().into_owned(),
+
+            command_path:   context.map(|c| c.path.to_string_lossy().into_owned()),
+            command_sha256: context.map(|c| c.sha256.clone()),
+
+            paired:    self.session.is_paired(),
+            transfers: self.session.transfers(),
+
+            ticket:            self.ticket.clone(),
+            approver_comments: self.approver_comments.clone(),
+            correlation_id:    self.correlation_id.clone(),
+
+            colocated_approver: self.colocated_approver.clone(),
+            web_approver:       self.web_approver.clone(),
+
+            subcommands:         self.subcommands.clone(),
+            subcommands_omitted: self.subcommands_omitted,
+
+            maintenance_window: self.maintenance.as_ref().map(|window| window.id.clone()),
+            killed_by:          self.killed.as_ref().map(|kill| kill.requested_by.clone()),
+            kill_reason:        self.killed.as_ref().map(|kill| kill.reason.clone()),
+            labels:             self.labels.clone(),
+
+            multiplexer: self.terminal.multiplexer.clone().filter(|_| self.options.record_multiplexer),
+
+            started_at: recording.started(),
+            ended_at:   recording.now(),
+
+            output_bytes: recording.bytes(),
+
+            approver_bytes:  approved.as_ref().map(|checkpoint| checkpoint.bytes),
+            approver_sha256: approved.map(|checkpoint| checkpoint.digest),
+
+            keystroke_latency: self.latency.as_ref().and_then(KeystrokeLatency::summary),
+
+            // filled in once the edited files have been written back
+            edits: Vec::new(),
+
+            outcome,
+        }
+    }
+
+    fn log_disabled(&mut self, _: &[u8]) -> Result<()> {
+        // if there's no pair watching the session (because we're exempt
+        // or were allowed to proceed without one), don't disable stdin
+        if !self.session.is_paired() {
+            return Ok(());
+        }
+
+        Err(ErrorKind::StdinRedirected.into())
+    }
+
+    fn local_pair_prompt(&self, template_spec: &Spec) {
+        // render the template from the file (up to a bounded size); if
+        // there's an error, use the default template instead
+        let prompt = if self.options.quiet {
+            template_spec.expand(QUIET_USER_PROMPT)
+        } else {
+            template_spec.render_file(
+                &self.options.user_prompt_path,
+                DEFAULT_USER_PROMPT,
+            )
+        };
+
+        // NOTE: I don't think it's adviseable to log the evaluated
+        // template here since it likely contains ANSI escape sequences
+        // that clear the terminal, adjust color/width, etc.
+        slog::trace!(self.slog, "local prompt template evaluated");
+
+        // we ignore any failure to deliver the prompt locally, because
+        // we can't really do anything productive other than die, and
+        // that could render `sudo` inoperable given an unanticipated
+        // bug
+        let delivery = delivery::deliver(
+            self.plugin,
+            &self.options.prompt_delivery,
+            &prompt,
+        );
+
+        for (channel, error) in &delivery.failures {
+            slog::debug!(self.slog, "local prompt delivery failed";
+                "channel" => ?channel,
+                "error"   => error.to_string(),
+            );
+        }
+
+        match delivery.channel {
+            Some(channel) => slog::trace!(self.slog, "local prompt delivered"; "channel" => ?channel),
+            None          => slog::warn!(self.slog, "local prompt couldn't be delivered"),
+        }
+
+        if self.options.verbose {
+            let _ = self.plugin.print(Level::Info, format!(
+                "session {} is waiting on {}",
+                self.session_id,
+                self.socket_path.display(),
+            ).as_bytes());
+        }
+
+        slog::trace!(self.slog, "local prompt rendered");
+    }
+
+    /// Waits for pairs to connect and respond to the prompt, one after
+    /// another, until the session's quorum has approved it. Signals
+    /// that would otherwise kill `sudo` partway through (e.g., Ctrl-C)
+    /// cancel the session instead, so the socket is cleaned up and the
+    /// user is told why.
+    fn remote_pair(&mut self, template_spec: &Spec) -> Result<Active> {
+        let signals = SignalGuard::install()
+            .context(ErrorKind::CommunicationError)?;
+
+        let deadline = Some(self.options.approval_timeout)
+            .filter(|timeout| *timeout > Duration::from_secs(0))
+            .map(|timeout| Deadline::after_on(self.clock.as_ref(), timeout));
+
+        // the countdown is erased (by dropping it) once the wait is
+        // over, whichever way it ends; without a deadline, there's only
+        // something to show if there are hints
+        let hints     = self.wait_hints();
+        let countdown = self.plugin.tty()
+            .filter(|_| deadline.is_some() || !hints.is_empty())
+            .and_then(|tty| Countdown::start(&self.threads, tty, deadline, hints, template_spec.clone(), self.clock.clone()).ok());
+
+        let approvers    = self.quorum.approvers;
+        let mut awaiting = AwaitingApproval::new();
+        let mut result   = Ok(());
+
+        for approval in 1..=approvers {
+            result = loop {
+                let answer = self.remote_pair_connect(&mut awaiting, &signals, deadline)
+                    .and_then(|_| self.remote_pair_prompt(&mut awaiting, template_spec, deadline, approval));
+
+                match answer {
+                    // someone else can answer in place of an approver
+                    // who let the preview lapse
+                    Err(ref e) if e.kind() == ErrorKind::PreviewTimedOut => {
+                        slog::warn!(self.slog, "remote pair didn't answer in time";
+                            "preview_timeout" => format!("{:?}", self.options.preview_timeout),
+                        );
+
+                        awaiting.release(b"\nyou didn't answer in time; another approver can answer instead\n");
+                    },
+
+                    // as can someone in place of an approver whose
+                    // terminal is too narrow, once they've been told why
+                    Err(ref e) if e.kind() == ErrorKind::ApproverTooNarrow => {
+                        awaiting.release(b"another approver can answer instead\n");
+                    },
+
+                    answer => break answer,
+                }
+            }.map(|comment| self.approver_comments.extend(comment));
+
+            if result.is_err() || approval == approvers {
+                break;
+            }
+
+            // set the approver aside while the rest of the quorum is
+            // waited on
+            awaiting.set_aside(approvers - approval);
+        }
+
+        drop(countdown);
+
+        if let Some(signal) = signals.caught() {
+            slog::warn!(self.slog, "session cancelled while waiting for pair";
+                "signal" => signals::name(signal),
+            );
+
+            // the approver may already be connected, so let them know
+            // what happened
+            awaiting.decline(b"\nsession cancelled by the user\n");
+
+            return Err(ErrorKind::Cancelled.into());
+        }
+
+        if let Err(e) = result {
+            if e.kind() == ErrorKind::ApprovalTimedOut {
+                slog::warn!(self.slog, "session not approved in time";
+                    "approval_timeout" => format!("{:?}", self.options.approval_timeout),
+                );
+
+                awaiting.decline(b"\nsession declined: it wasn't approved in time\n");
+            } else if awaiting.approvals() > 0 {
+                awaiting.decline(b"\nsession declined by another approver\n");
+            }
+
+            return Err(e);
+        }
+
+        let active = awaiting.approve()
+            .ok_or(ErrorKind::CommunicationError)?;
+
+        if approvers > 1 {
+            slog::info!(self.slog, "remote pair quorum approved session"; "approvers" => approvers);
+        }
+
+        Ok(active)
+    }
+
+    fn remote_pair_connect(
+        &self,
+        awaiting: &mut AwaitingApproval,
+        signals:  &SignalGuard,
+        deadline: Option<Deadline>,
+    ) -> Result<()> {
+        let slog = slog::Logger::new(&self.slog, slog::o!(
+            "socket_path" => self.socket_path().to_string_lossy().into_owned(),
+        ));
+
+        slog::debug!(slog, "socket initializing";
+            "socket_uid"  => self.socket_uid(),
+            "socket_gid"  => self.socket_gid(),
+            "socket_mode" => format!("{:#06o}", self.socket_mode()),
+        );
+
+        if awaiting.pair().is_some() {
+            slog::warn!(slog, "socket unexpectedly already initialized");
+
+            // TODO: this is probably an error, since we should never
+            // expect the socket to have already been created
+            return Ok(());
+        }
+
+        if let Some(control) = self.options.control_socket.as_ref() {
+            let announcement = protocol::announce(
+                &self.session_id,
+                self.plugin.user_info.uid,
+                self.plugin.user_info.pid,
+            );
+
+            let announced = self.admit(|admission| Socket::announce(
+                control,
+                self.socket_uid(),
+                self.socket_gid(),
+                self.socket_mode(),
+                admission,
+                &announcement,
+            ));
+
+            match announced {
+                Ok(socket) => {
+                    awaiting.connect(socket);
+
+                    slog::info!(slog, "socket announced to approver daemon";
+                        "control_socket" => control.to_string_lossy().into_owned(),
+                    );
+
+                    return Ok(());
+                },
+
+                // without the daemon, the session waits for a pair on
+                // its own socket as usual
+                Err(e) => slog::warn!(slog, "approver daemon unavailable";
+                    "control_socket" => control.to_string_lossy().into_owned(),
+                    "error"          => e.to_string(),
+                ),
+            }
+        }
+
+        slog::info!(slog, "socket waiting for pair to connect...");
+
+        // TODO: clearly indicate when the socket path is missing
+        // this is currently being hidden by the `context` method which
+        // ironically hides the extra context instead of providing extra
+        // context
+        let socket = self.admit(|admission| Socket::open(
+            self.socket_path(),
+            self.socket_uid(),
+            self.socket_gid(),
+            self.socket_mode(),
+            admission,
+            signals.fd(),
+            deadline.map(|deadline| deadline.instant()),
+        ));
+
+        let socket = before_deadline(socket, ErrorKind::CommunicationError)?;
+
+        awaiting.connect(socket);
+
+        slog::info!(slog, "socket connected");
+
+        Ok(())
+    }
+
+    fn remote_pair_prompt(
+        &self,
+        awaiting:      &mut AwaitingApproval,
+        template_spec: &Spec,
+        deadline:      Option<Deadline>,
+        approval:      u32,
+    ) -> Result<Option<String>> {
+        let challenge = self.challenge()
+            .context(ErrorKind::CommunicationError)?;
+
+        let hello  = self.hello(deadline);
+        let prompt = self.pair_prompt(template_spec, &hello, Some(approval), challenge.as_deref());
+
+        // the approver has until the session's deadline to answer, or
+        // until their preview lapses, if that's sooner
+        let preview = Some(self.options.preview_timeout)
+            .filter(|timeout| *timeout > Duration::from_secs(0))
+            .map(|timeout| Deadline::after_on(self.clock.as_ref(), timeout))
+            .filter(|preview| deadline.is_none_or(|deadline| preview.instant() < deadline.instant()));
+
+        let answer = awaiting.prompt(
+            &self.slog,
+            &prompt,
+            hello,
+            preview.or(deadline).map(|deadline| deadline.instant()),
+        );
+
+        let answer = match answer {
+            Err(ref e) if e.kind() == ErrorKind::ApprovalTimedOut && preview.is_some() =>
+                return Err(ErrorKind::PreviewTimedOut.into()),
+
+            answer => answer?,
+        };
+
+        let comment = answer.comment;
+
+        if !answer.approved {
+            slog::warn!(self.slog, "remote pair declined session";
+                "comment" => comment.as_deref(),
+            );
+
+            if let Some(comment) = comment {
+                let _ = self.plugin.print(Level::Warn, format!(
+                    "session declined: {}",
+                    comment,
+                ).as_bytes());
+            }
+
+            return Err(ErrorKind::SessionDeclined.into());
+        }
+
+        if let Err(reason) = self.authenticate_approver(challenge.as_deref(), &answer.credentials) {
+            if let Some(socket) = awaiting.pair() {
+                let _ = socket.write_all(format!("\nsession declined: {}\n", reason).as_bytes());
+            }
+
+            return Err(ErrorKind::ApproverUnauthenticated.into());
+        }
+
+        self.check_approver_width(awaiting)?;
+
+        slog::info!(self.slog, "remote pair approved session";
+            "comment" => comment.as_deref(),
+        );
+
+        if let Some(comment) = comment.as_ref() {
+            let _ = self.plugin.print(Level::Info, format!(
+                "session approved: {}",
+                comment,
+            ).as_bytes());
+        }
+
+        Ok(comment)
+    }
+
+    /// Holds the approver who's just approved the session to
+    /// `approver_width`, comparing the width of their terminal (as
+    /// their client reported it) to the user's.
+    fn check_approver_width(&self, awaiting: &mut AwaitingApproval) -> Result<()> {
+        let policy   = self.options.approver_width;
+        let user     = self.plugin.user_info.cols;
+        let approver = awaiting.protocol().winsize.map(|(_, cols)| u64::from(cols));
+
+        // there's nothing to compare against if sudo doesn't know the
+        // user's width
+        if policy == WidthPolicy::Ignore || user == 0 || approver.is_some_and(|cols| cols >= user) {
+            return Ok(());
+        }
+
+        slog::warn!(self.slog, "remote pair terminal narrower than user's";
+            "approver_cols"  => approver,
+            "user_cols"      => user,
+            "approver_width" => format!("{:?}", policy),
+        );
+
+        let notice = match (policy, approver) {
+            (WidthPolicy::Require, Some(cols)) => format!(
+                "\napproval refused: your terminal is {} columns wide, narrower than the user's {}; widen it to approve\n",
+                cols, user,
+            ),
+
+            (WidthPolicy::Require, None) => format!(
+                "\napproval refused: your client didn't report how wide your terminal is, which has to be at least the user's {} columns\n",
+                user,
+            ),
+
+            (_, Some(cols)) => format!(
+                "\nwarning: your terminal is {} columns wide, narrower than the user's {}, so their output will wrap\n",
+                cols, user,
+            ),
+
+            // there's nothing to warn an approver about without knowing
+            // how wide their terminal is
+            (_, None) => return Ok(()),
+        };
+
+        if let Some(socket) = awaiting.pair() {
+            let _ = socket.write_all(notice.as_bytes());
+        }
+
+        match policy {
+            WidthPolicy::Require => Err(ErrorKind::ApproverTooNarrow.into()),
+            _                    => Ok(()),
+        }
+    }
+
+    /// The hints shown to the user as they wait for approval, or none if
+    /// `wait_hints` isn't configured or can't be loaded, in which case
+    /// they're just shown the countdown.
+    fn wait_hints(&self) -> Hints {
+        let path = match self.options.wait_hints.as_ref() {
+            Some(path) => path,
+            None       => return Hints::default(),
+        };
+
+        Hints::load(path).unwrap_or_else(|e| {
+            slog::error!(self.slog, "unable to load wait hints";
+                "wait_hints" => path.to_string_lossy().into_owned(),
+                "error"      => e,
+            );
+
+            Hints::default()
+        })
+    }
+
+    /// Passes `accept` the rules for admitting connections to the
+    /// session's socket: who, beyond anyone who could write to it, is
+    /// allowed to connect (see `approver_uids`), how many may try, and
+    /// how often each user may (see `connection_attempt_limit`).
+    /// Connections that are dropped are logged, as are bans, but not
+    /// the connections of those who are banned.
+    fn admit<T, F: FnOnce(&Admission<'_>) -> T>(&self, accept: F) -> T {
+        // users who can't be found can't connect, rather than letting
+        // anyone connect once none of them can be found
+        let uids : Option<Vec<_>> = Some(&self.options.approver_uids)
+            .filter(|users| !users.is_empty())
+            .map(|users| users.iter().filter_map(|user| self.uid(user)).collect());
+
+        let groups  = |uid| self.approver_gids(uid);
+        let dropped = |uid, gid| slog::warn!(self.slog, "pair connection dropped";
+            "peer_uid" => uid,
+            "peer_gid" => gid,
+        );
+
+        // without a limit, nobody's banned, and connections to sockets
+        // on the filesystem can be admitted without asking who made them
+        let limited = self.options.connection_attempt_limit > 0;
+        let banned  = |uid| match self.attempts.borrow_mut().attempt(uid, self.clock.instant()) {
+            Verdict::Allowed => false,
+            Verdict::Banned  => true,
+
+            Verdict::NewlyBanned => {
+                slog::warn!(self.slog, "pair connection peer banned";
+                    "peer_uid" => uid,
+                    "attempts" => self.options.connection_attempt_limit,
+                    "window"   => deadline::format(self.options.connection_attempt_window),
+                    "ban"      => deadline::format(self.options.connection_ban),
+                );
+
+                true
+            },
+        };
+
+        accept(&Admission {
+            backlog:     self.options.socket_backlog,
+            max_pending: self.options.max_pending_connections,
+            uids:        uids.as_deref(),
+            groups:      &groups,
+            banned:      if limited { Some(&banned) } else { None },
+            dropped:     &dropped,
+        })
+    }
+
+    /// The groups the approver with `uid` is a member of, for checking
+    /// their connection to an abstract socket, if
+    /// `resolve_approver_groups` is set and they can be found.
+    fn approver_gids(&self, uid: uid_t) -> Option<HashSet<gid_t>> {
+        if !self.options.resolve_approver_groups {
+            return None;
+        }
+
+        let gids = self.plugin.gids_of(uid);
+
+        if gids.is_none() {
+            slog::debug!(self.slog, "approver groups couldn't be resolved";
+                "approver_uid" => uid,
+            );
+        }
+
+        gids
+    }
+
+    /// A new challenge for an approver to sign, if approvers have to
+    /// authenticate.
+    fn challenge(&self) -> io::Result<Option<String>> {
+        match self.approver_keys {
+            Some(_) => sshsig::challenge().map(Some),
+            None    => Ok(None),
+        }
+    }
+
+    /// Verifies the `credentials` the approver sent with their approval
+    /// (a signature of the `challenge` they were sent, and their
+    /// one-time code), as far as they have to authenticate, logging who
+    /// they proved themselves to be. Returns why they couldn't be
+    /// authenticated if they couldn't.
+    fn authenticate_approver(&self, challenge: Option<&str>, credentials: &Credentials) -> std::result::Result<(), String> {
+        self.verify_signature(challenge, credentials)?;
+
+        if let Some(keys) = self.approver_totp.as_ref() {
+            let ledger = Ledger::new(self.options.socket_dir.join("totp"));
+
+            match keys.verify(credentials.totp.as_ref(), self.clock.wall(), &ledger) {
+                Ok(()) => {
+                    slog::info!(self.slog, "approver entered one-time code";
+                        "approver" => credentials.totp.as_ref().map(|code| code.approver.as_str()),
+                    );
+                },
+
+                Err(rejection) => {
+                    slog::warn!(self.slog, "approver failed to enter one-time code";
+                        "approver" => credentials.totp.as_ref().map(|code| code.approver.as_str()),
+                        "reason"   => rejection.to_string(),
+                    );
+
+                    return Err(rejection.to_string());
+                },
+            }
+
+            // their code proved who they are, so their account can be
+            // checked too
+            if let Some(code) = credentials.totp.as_ref() {
+                self.check_approver_account(&code.approver)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `approver` through the PAM account stage if
+    /// `approver_account_check` is set, so the lockouts and access
+    /// rules the host enforces apply to approving sessions too. Returns
+    /// why their account can't approve sessions if it can't.
+    #[cfg(feature = "pam")]
+    fn check_approver_account(&self, approver: &str) -> std::result::Result<(), String> {
+        if !self.options.approver_account_check {
+            return Ok(());
+        }
+
+        match pam::account(&self.options.pam_service, approver) {
+            Ok(()) => {
+                slog::info!(self.slog, "approver account permitted";
+                    "approver" => approver,
+                );
+
+                Ok(())
+            },
+
+            Err(reason) => {
+                slog::warn!(self.slog, "approver account refused";
+                    "approver" => approver,
+                    "reason"   => &reason,
+                );
+
+                Err(format!("{} may not approve sessions: {}", approver, reason))
+            },
+        }
+    }
+
+    /// Without the `pam` feature, there's no account stage to run
+    /// approvers through (and `approver_account_check` is rejected as
+    /// invalid).
+    #[cfg(not(feature = "pam"))]
+    fn check_approver_account(&self, _: &str) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Everything an approver is sent when they connect, ending with the
+    /// prompt they're to answer. Those joining the session's quorum are
+    /// told which `approval` theirs is, those who have to authenticate
+    /// are sent the `challenge` to sign, and those who have to enter a
+    /// one-time code are asked for it.
+    fn pair_prompt(&self, template_spec: &Spec, hello: &Hello, approval: Option<u32>, challenge: Option<&str>) -> Vec<u8> {
+        // render the template from the file (up to a bounded size); if
+        // there's an error, use the default template instead
+        let prompt = template_spec.render_file(
+            &self.options.pair_prompt_path,
+            DEFAULT_PAIR_PROMPT,
+        );
+
+        slog::trace!(self.slog, "remote prompt evaluated");
+
+        // announce what we support; clients that don't understand this
+        // will (harmlessly) print it to the approver's terminal
+        let mut message = hello.encode();
+
+        if !self.options.host_tags.is_empty() {
+            message.extend_from_slice(&protocol::tags(&self.options.host_tags));
+        }
+
+        let warnings : Vec<_> = self.shell_warning().into_iter()
+            .chain(self.command_warnings())
+            .collect();
+
+        let env = self.env_highlights();
+
+        message.extend_from_slice(&self.preview(&warnings, &env));
+
+        if let Some(challenge) = challenge {
+            message.extend_from_slice(&protocol::authenticate(challenge));
+        }
+
+        if self.approver_totp.is_some() {
+            message.extend_from_slice(&protocol::request_totp());
+        }
+
+        if let Some(approval) = approval.filter(|_| self.quorum.approvers > 1) {
+            message.extend_from_slice(format!(
+                "approval {} of {}\n",
+                approval,
+                self.quorum.approvers,
+            ).as_bytes());
+        }
+
+        let files = self.plugin.edited_files();
+
+        if !files.is_empty() {
+            let files : Vec<_> = files.iter().map(|file| file.to_string_lossy()).collect();
+
+            message.extend_from_slice(format!("EDITING {}\n", files.join(" ")).as_bytes());
+        }
+
+        if let Some(resolution) = self.command_resolution() {
+            message.extend_from_slice(format!("command: {}\n", resolution).as_bytes());
+        }
+
+        if let Some(ticket) = self.ticket.as_ref() {
+            message.extend_from_slice(format!("ticket: {}\n", ticket).as_bytes());
+        }
+
+        if !self.labels.is_empty() {
+            message.extend_from_slice(format!("labels: {}\n", self.labels.join(", ")).as_bytes());
+        }
+
+        if let Some(id) = self.correlation_id.as_ref() {
+            message.extend_from_slice(format!("correlation id: {}\n", id).as_bytes());
+        }
+
+        if let Some(multiplexer) = self.terminal.multiplexer.as_ref() {
+            message.extend_from_slice(format!("tty: {} (detachable)\n", multiplexer.name).as_bytes());
+        }
+
+        let restrictions = Restrictions::of(&self.plugin.command_info).describe();
+
+        if !restrictions.is_empty() {
+            message.extend_from_slice(format!("policy: {}\n", restrictions.join(", ")).as_bytes());
+        }
+
+        for warning in &warnings {
+            message.extend_from_slice(format!("warning: {}\n", warning).as_bytes());
+        }
+
+        if !env.is_empty() {
+            message.extend_from_slice(b"environment:\n");
+        }
+
+        for line in &env {
+            message.extend_from_slice(format!("  {}\n", line).as_bytes());
+        }
+
+        message.extend_from_slice(&prompt[..]);
+        message
+    }
+
+    /// A preview of the session for approval clients to show the
+    /// approver ahead of the prompt, with the `warnings` and highlighted
+    /// `env` they're shown in it.
+    fn preview(&self, warnings: &[String], env: &[String]) -> Vec<u8> {
+        let mut limits = command_limits(&self.options);
+
+        limits.max_length = match limits.max_length {
+            0   => protocol::MAX_PREVIEW_COMMAND_LEN,
+            len => len.min(protocol::MAX_PREVIEW_COMMAND_LEN),
+        };
+
+        let user        = self.plugin.user_info.user.as_bytes();
+        let host        = self.plugin.user_info.host.as_bytes();
+        let runas_user  = self.plugin.runas_user_name();
+        let runas_group = self.plugin.runas_group_name();
+        let cwd         = self.plugin.cwd().as_os_str().as_bytes();
+        let command     = self.displayed_command(limits);
+
+        let mut fields : Vec<(&str, &[u8])> = vec![
+            ("user",        user),
+            ("host",        host),
+            ("runas_user",  runas_user.as_bytes()),
+            ("runas_group", runas_group.as_bytes()),
+            ("cwd",         cwd),
+            ("command",     &command),
+        ];
+
+        fields.extend(warnings.iter().map(|warning| ("warning", warning.as_bytes())));
+        fields.extend(env     .iter().map(|line|    ("env",     line   .as_bytes())));
+
+        protocol::preview(&fields)
+    }
+
+    /// What's actually run, if the name the command was invoked by
+    /// doesn't match the binary sudo executes (e.g., busybox applets).
+    fn command_resolution(&self) -> Option<String> {
+        // sudoedit's command is named `sudoedit`, but always runs an
+        // editor on copies of the files, which is shown instead
+        if self.plugin.invocation_kind() == InvocationKind::Edit {
+            return None;
+        }
+
+        let argv0      = self.plugin.argv().first()?;
+        let resolution = self.context.as_ref()?.resolution(argv0.as_bytes())?;
+
+        slog::info!(self.slog, "command resolution"; "resolution" => &resolution);
+
+        Some(resolution)
+    }
+
+    /// Whether the user is being granted a shell rather than a single
+    /// command, if the approver is to be warned about it. A command
+    /// given alongside `-s` is only run through the shell, so isn't
+    /// warned about, but one given alongside `-i` still runs with the
+    /// runas user's login scripts and environment.
+    fn shell_warning(&self) -> Option<String> {
+        if !self.options.warn_shell {
+            return None;
+        }
+
+        let shell = self.plugin.runas_shell()?;
+
+        // sudo runs any command given with `-i` or `-s` as `shell -c
+        // command`; without one, the shell is interactive
+        let interactive = !self.plugin.argv().iter()
+            .any(|arg| arg.as_bytes() == b"-c");
+
+        let mut warning = match (self.plugin.is_login_shell(), interactive) {
+            (true, true)  => format!(
+                "a login shell ({}) is being granted, not a single command",
+                shell.display(),
+            ),
+            (true, false) => format!(
+                "the command runs through a login shell ({}), with the target user's login scripts",
+                shell.display(),
+            ),
+            (false, true) => format!(
+                "a shell ({}) is being granted, not a single command",
+                shell.display(),
+            ),
+            (false, false) => return None,
+        };
+
+        if let Some(class) = self.plugin.login_class() {
+            warning.push_str(&format!(" (login class {})", class));
+        }
+
+        slog::warn!(self.slog, "shell granted"; "warning" => &warning);
+
+        Some(warning)
+    }
+
+    /// Reasons the approver may not be able to trust that the command
+    /// is what it claims to be, if they're to be warned about them.
+    fn command_warnings(&self) -> Vec<String> {
+        if !self.options.warn_unsafe_command {
+            return Vec::new();
+        }
+
+        let warnings = match self.context.as_ref() {
+            Some(context) => context.warnings(),
+            None          => vec![format!(
+                "{} could not be inspected",
+                self.plugin.command_info.command.display(),
+            )],
+        };
+
+        for warning in &warnings {
+            slog::warn!(self.slog, "unsafe command"; "warning" => warning);
+        }
+
+        warnings
+    }
+
+    /// Variables in the command's environment that can change what it
+    /// does, if they're to be highlighted to the approver.
+    fn env_highlights(&self) -> Vec<String> {
+        if !self.options.highlight_env {
+            return Vec::new();
+        }
+
+        let highlights = environment::highlights(
+            self.plugin.command_env(),
+            &self.options.risky_env,
+        );
+
+        for highlight in &highlights {
+            slog::info!(self.slog, "risky environment"; "highlight" => highlight);
+        }
+
+        highlights
+    }
+
+    /// The protocol version and capabilities this session offers to
+    /// approval clients.
+    fn hello(&self, deadline: Option<Deadline>) -> Hello {
+        let mut capabilities = Capabilities::empty();
+
+        capabilities.insert(Capabilities::WINSIZE);
+        capabilities.insert(Capabilities::COMMENTS);
+        capabilities.insert(Capabilities::STREAMS);
+        capabilities.insert(Capabilities::MARKERS);
+
+        if self.transcript.is_streamed() {
+            capabilities.insert(Capabilities::DIGESTS);
+        }
+
+        if self.options.reapproval_interval > Duration::from_secs(0) {
+            capabilities.insert(Capabilities::REAPPROVAL);
+        }
+
+        if self.options.chat {
+            capabilities.insert(Capabilities::CHAT);
+        }
+
+        Hello {
+            version:    PROTOCOL_VERSION,
+            capabilities,
+            deadline:   deadline.map(|deadline| deadline.unix_secs()),
+            encoding:   Encoding::Text,
+            utc_offset: None,
+            winsize:    None,
+            limits:     LIMITS,
+        }
+    }
+
+    fn is_exempt(&self) -> bool {
+        let exemption = policy::exemption(
+            self.invocation(),
+            self,
+            &self.quorum,
+            self.coverage,
+            self.recording.is_some(),
+        );
+
+        let plugin = self.plugin;
+
+        match exemption {
+            Some(Exemption::NotRunningCommand) => slog::debug!(self.slog, "sudo not running a command";
+                "invocation" => format!("{:?}", plugin.invocation_kind()),
+            ),
+
+            Some(Exemption::Root) => slog::debug!(self.slog, "sudo initiated by root";
+                "user_info.uid" => plugin.user_info.uid,
+            ),
+
+            // this is logged at a higher level than other exemptions
+            // since it's configurable and should be visible in the
+            // audit trail
+            Some(Exemption::SudoToSelf) => slog::info!(self.slog, "sudo to current user exempted";
+                "user_info.uid"          => plugin.user_info.uid,
+                "command_info.runas_uid" => plugin.command_info.runas_uid,
+            ),
+
+            Some(Exemption::ApprovalCommand) => slog::debug!(self.slog, "sudo running approval command";
+                "command_info.command"       => plugin.command_info.command.to_string_lossy().into_owned(),
+                "plugin_options.binary_path" => self.options.binary_path.to_string_lossy().into_owned(),
+            ),
+
+            Some(Exemption::NotLogged)         => slog::debug!(self.slog, "sudo command exempted from logging"),
+            Some(Exemption::ExemptNetwork)     => slog::debug!(self.slog, "sudo on exempt network"),
+            Some(Exemption::UnenforcedNetwork) => slog::debug!(self.slog, "sudo on unenforced network"),
+            Some(Exemption::ExemptGroup)       => slog::debug!(self.slog, "sudo from exempt group id"),
+            Some(Exemption::UnenforcedGroup)   => slog::debug!(self.slog, "sudo to unenforced group id"),
+
+            // the pair rules have already logged that none matched
+            Some(Exemption::NoPairRule) => {},
+
+            Some(Exemption::RecordOnly) => slog::info!(self.slog, "sudo command recorded rather than paired, as pair rules allow"),
+
+            // only exempts sessions as they're approved, once their
+            // unattended policies are applied
+            Some(Exemption::RecordedBySudo(_)) => {},
+
+            None => {
+                if policy::is_sudoing_to_themselves(plugin) {
+                    slog::info!(self.slog, "sudo to current user not exempted";
+                        "user_info.uid"          => plugin.user_info.uid,
+                        "command_info.runas_uid" => plugin.command_info.runas_uid,
+                    );
+                }
+
+                if self.coverage == Coverage::RecordOnly {
+                    slog::warn!(self.slog, "pair session required despite record-only pair rule, session not recorded");
+                }
+
+                slog::debug!(self.slog, "sudo session requires a pair");
+            },
+        }
+
+        exemption.is_some()
+    }
+
+    /// Everything the session's policy is decided by (see `policy`).
+    fn invocation(&self) -> Invocation<'_> {
+        Invocation {
+            plugin:   self.plugin,
+            options:  &self.options,
+            labels:   &self.labels,
+            terminal: &self.terminal,
+        }
+    }
+
+    ///
+    /// Returns the quorum needed by the tier of the rule the command
+    /// matches, or no approvers at all if `pair_rules` is configured and
+    /// the command matches none of them, along with whether the rule
+    /// says it's paired, recorded, or both. Without any rules, or if
+    /// they can't be loaded, a single approver is needed and the
+    /// session is recorded, so a broken file never exempts anyone. The
+    /// outcome is logged at a higher level than other exemptions, since
+    /// which rule required a pair (or that none did) belongs in the
+    /// audit trail.
+    ///
+    fn match_pair_rules(&self) -> (Quorum, Coverage) {
+        let path = match self.options.pair_rules.as_ref() {
+            Some(path) => path,
+            None       => return (Quorum::default(), Coverage::Full),
+        };
+
+        let rules = match Rules::load(path) {
+            Ok(rules) => rules,
+            Err(e)    => {
+                slog::error!(self.slog, "unable to load pair rules";
+                    "pair_rules" => path.to_string_lossy().into_owned(),
+                    "error"      => e,
+                );
+
+                return (Quorum::default(), Coverage::Full);
+            },
+        };
+
+        match policy::pair_rule(self.invocation(), &rules) {
+            Some(rule) => {
+                let quorum = rules.quorum(rule.tier());
+
+                slog::info!(self.slog, "sudo command matches pair rule";
+                    "pair_rule"      => rule.to_string(),
+                    "tier"           => rule.tier(),
+                    "approvers"      => quorum.approvers,
+                    "approver_group" => quorum.group.as_ref().map(Group::to_string),
+                    "coverage"       => rule.coverage().name(),
+                );
+
+                (quorum, rule.coverage())
+            },
+
+            None => {
+                slog::info!(self.slog, "sudo command exempted by pair rules");
+
+                (Quorum { approvers: 0, group: None }, Coverage::Full)
+            },
+        }
+    }
+
+    ///
+    /// Applies the configured policies for sessions that can't be
+    /// paired in the usual way: automation accounts' sessions and other
+    /// non-interactive (`-n`) invocations, invocations from the
+    /// background, and invocations without a TTY. Returns `Ok(true)` if
+    /// the session may proceed without a pair.
+    ///
+    fn is_unattended(&self) -> Result<bool> {
+        for (policy, reason) in policy::unattended(self.invocation(), self.automation) {
+            if self.apply_unattended_policy(policy, reason)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn apply_unattended_policy(
+        &self,
+        policy: UnattendedPolicy,
+        reason: ErrorKind,
+    ) -> Result<bool> {
+        let slog = slog::Logger::new(&self.slog, slog::o!(
+            "reason" => reason.to_string(),
+        ));
+
+        match policy {
+            UnattendedPolicy::Pair => {
+                slog::info!(slog, "pair session required despite unattended sudo");
+
+                Ok(false)
+            },
+
+            UnattendedPolicy::Deny => {
+                slog::warn!(slog, "unattended sudo denied");
+
+                Err(reason.into())
+            },
+
+            UnattendedPolicy::Record if self.is_recorded_by_sudo() => {
+                slog::warn!(slog, "pair session skipped, session recorded by sudo";
+                    "command_info.iolog_path" => &self.plugin.command_info.iolog_path,
+                );
+
+                Ok(true)
+            },
+
+            UnattendedPolicy::Record => {
+                slog::warn!(slog, "unattended sudo denied, session not recorded by sudo");
+
+                Err(ErrorKind::Unrecorded.into())
+            },
+        }
+    }
+
+    ///
+    /// Returns true if the plugin is disabled with `disable_mode=bypass`,
+    /// in which case the session is neither paired nor recorded.
+    ///
+    fn is_bypassed(&self) -> bool {
+        let disabled = match self.disabled.as_ref() {
+            Some(disabled) if self.options.disable_mode == DisableMode::Bypass => disabled,
+            _                                                                  => return false,
+        };
+
+        slog::crit!(self.slog, "pair session bypassed, plugin disabled";
+            "disable_reason" => disabled.reason.as_deref().unwrap_or(""),
+        );
+
+        let _ = self.plugin.print(Level::Warn, format!(
+            "sudo_pair is disabled{}; this session isn't paired or recorded, and that's been logged",
+            disabled.describe(),
+        ).as_bytes());
+
+        true
+    }
+
+    ///
+    /// Returns true if the plugin is disabled with
+    /// `disable_mode=record-only` and the session is recorded, in which
+    /// case it doesn't need a pair. Sessions that aren't recorded are
+    /// paired as usual, as they are in a maintenance window.
+    ///
+    fn is_disabled(&self) -> bool {
+        let disabled = match self.disabled.as_ref() {
+            Some(disabled) => disabled,
+            None           => return false,
+        };
+
+        if self.recording.is_none() && !self.is_recorded_by_sudo() {
+            slog::crit!(self.slog, "pair session required despite disable file, session not recorded");
+
+            return false;
+        }
+
+        slog::crit!(self.slog, "pair session recorded rather than paired, plugin disabled";
+            "disable_reason" => disabled.reason.as_deref().unwrap_or(""),
+        );
+
+        let _ = self.plugin.print(Level::Warn, format!(
+            "sudo_pair is disabled{}; this session is recorded rather than paired, and that's been logged",
+            disabled.describe(),
+        ).as_bytes());
+
+        true
+    }
+
+    ///
+    /// Returns true if a maintenance window is open and the session is
+    /// recorded, in which case it doesn't need a pair, since planned
+    /// mass changes would otherwise need an approver for every host
+    /// they touch. Sessions that aren't recorded are paired as usual,
+    /// since the recording is what stands in for the approver.
+    ///
+    fn is_in_maintenance(&self) -> bool {
+        let window = match self.maintenance.as_ref() {
+            Some(window) => window,
+            None         => return false,
+        };
+
+        if self.recording.is_none() && !self.is_recorded_by_sudo() {
+            slog::warn!(self.slog, "pair session required despite maintenance window, session not recorded");
+
+            return false;
+        }
+
+        slog::warn!(self.slog, "pair session skipped during maintenance window";
+            "maintenance_reason" => window.reason.as_deref().unwrap_or(""),
+        );
+
+        let _ = self.plugin.print(Level::Info, format!(
+            "maintenance window {} is open; this session is recorded rather than paired",
+            window.id,
+        ).as_bytes());
+
+        true
+    }
+
+    ///
+    /// Returns true if the user presented a valid pre-approval token
+    /// for exactly this command. A token that's rejected (or a key that
+    /// can't be used) is logged and reported to the user, who then
+    /// waits for a live pair as usual.
+    ///
+    fn is_preapproved(&self) -> bool {
+        let path = match self.options.preapproval_key.as_ref() {
+            Some(path) => path,
+            None       => return false,
+        };
+
+        let token = match self.preapproval_token() {
+            Some(token) => token,
+            None        => return false,
+        };
+
+        let key = match preapproval_key(path) {
+            Ok(key) => key,
+            Err(e)  => {
+                slog::error!(self.slog, "unable to read pre-approval key";
+                    "preapproval_key" => path.to_string_lossy().into_owned(),
+                    "error"           => e.to_string(),
+                );
+
+                return false;
+            },
+        };
+
+        let runas_user = self.plugin.runas_user_name();
+        let command : Vec<&[u8]> = std::iter::once(self.plugin.executable().as_os_str().as_bytes())
+            .chain(self.plugin.argv().iter().skip(1).map(|arg| arg.as_bytes()))
+            .collect();
+
+        let claims = Claims {
+            host:       self.plugin.user_info.host.as_bytes(),
+            user:       self.plugin.user_info.user.as_bytes(),
+            runas_user: runas_user.as_bytes(),
+            command:    &command,
+        };
+
+        let now = self.clock.wall()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+
+        match token::verify(&key, &claims, &token, now, self.options.preapproval_max_window.as_secs()) {
+            Ok(window) => {
+                slog::info!(self.slog, "pair session pre-approved";
+                    "token_not_before" => window.not_before,
+                    "token_not_after"  => window.not_after,
+                );
+
+                true
+            },
+
+            Err(e) => {
+                slog::warn!(self.slog, "pre-approval token rejected"; "reason" => e.to_string());
+
+                let _ = self.plugin.print(
+                    Level::Warn,
+                    format!("{}; a pair is required instead", e).as_bytes(),
+                );
+
+                false
+            },
+        }
+    }
+
+    /// The pre-approval token the user provided, if any.
+    fn preapproval_token(&self) -> Option<Zeroizing<String>> {
+        self.user_input(
+            self.options.preapproval_source,
+            PREAPPROVAL_ENV,
+            b"pre-approval token (leave empty to wait for a pair): ",
+        )
+    }
+
+    /// Requires the user to cite a ticket for the session that matches
+    /// `ticket_pattern` (and that the issue tracker confirms, if
+    /// `ticket_url` is set), returning it. Sessions are refused before
+    /// any pair is asked to approve them if the ticket is missing or
+    /// can't be verified.
+    fn verify_ticket(&self) -> Result<Option<String>> {
+        let pattern = match self.options.ticket_pattern.as_ref() {
+            Some(pattern) => pattern,
+            None          => return Ok(None),
+        };
 
-        Ok(pair)
-    }
+        // tickets aren't secret (they're logged), so there's no need to
+        // wipe them
+        let ticket = self.user_input(
+            self.options.ticket_source,
+            TICKET_ENV,
+            b"ticket or change ID for this session: ",
+        ).map(|ticket| ticket.as_str().to_owned());
+
+        let problem = match ticket.as_ref() {
+            None                                     => Some("a ticket is required for paired sessions".into()),
+            Some(ticket) if !pattern.matches(ticket) => Some(format!("{} isn't a valid ticket", ticket)),
+            Some(ticket)                             => self.confirm_ticket(ticket).err(),
+        };
 
-    fn close(&mut self, _: i64, _: i64) {
-        slog::trace!(self.slog, "pair session ending");
+        if let Some(problem) = problem {
+            slog::warn!(self.slog, "ticket rejected";
+                "ticket" => ticket.as_deref(),
+                "reason" => &problem,
+            );
+
+            let _ = self.plugin.print(Level::Error, problem.as_bytes());
+
+            return Err(ErrorKind::TicketRejected.into());
+        }
 
-        // if we have a socket, close it
-        let _ = self.socket.as_mut().map(Socket::close);
+        slog::info!(self.slog, "ticket verified"; "ticket" => ticket.as_deref());
 
-        slog::info!(self.slog, "pair session ended");
+        Ok(ticket)
     }
 
-    fn log_ttyout(&mut self, log: &[u8]) -> Result<()> {
-        if !self.plugin.command_info.iolog_ttyout {
-            return Ok(())
+    /// Loads the keys approvers have to sign their challenges with, if
+    /// `approver_keys` is set.
+    #[cfg(feature = "signatures")]
+    fn load_approver_keys(&mut self) -> Result<()> {
+        if let Some(path) = self.options.approver_keys.as_ref() {
+            let keys = AuthorizedKeys::load(path).map_err(|e| {
+                slog::error!(self.slog, "unable to load approver keys";
+                    "approver_keys" => path.to_string_lossy().into_owned(),
+                    "error"         => e,
+                );
+
+                ErrorKind::UnreadableApproverKeys
+            })?;
+
+            slog::debug!(self.slog, "approvers must authenticate"; "keys" => keys.len());
+
+            self.approver_keys = Some(keys);
         }
 
-        self.log_output(log)
+        Ok(())
     }
 
-    fn log_stdout(&mut self, log: &[u8]) -> Result<()> {
-        if !self.plugin.command_info.iolog_stdout {
-            return Ok(())
+    /// Without the `signatures` feature, approvers aren't sent
+    /// challenges (and `approver_keys` is rejected as invalid).
+    #[cfg(not(feature = "signatures"))]
+    fn load_approver_keys(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Verifies the approver's signature of the `challenge` they were
+    /// sent, if they were sent one.
+    #[cfg(feature = "signatures")]
+    fn verify_signature(&self, challenge: Option<&str>, credentials: &Credentials) -> std::result::Result<(), String> {
+        if let (Some(keys), Some(challenge)) = (self.approver_keys.as_ref(), challenge) {
+            match keys.verify(challenge, credentials.signature.as_deref()) {
+                Ok(key) => {
+                    slog::info!(self.slog, "approver authenticated";
+                        "approver_key"         => key.fingerprint(),
+                        "approver_key_comment" => &key.comment,
+                    );
+                },
+
+                Err(rejection) => {
+                    slog::warn!(self.slog, "approver failed to authenticate";
+                        "reason" => rejection.to_string(),
+                    );
+
+                    return Err(rejection.to_string());
+                },
+            }
         }
 
-        self.log_output(log)
+        Ok(())
     }
 
-    fn log_stderr(&mut self, log: &[u8]) -> Result<()> {
-        if !self.plugin.command_info.iolog_stderr {
-            return Ok(())
+    /// Without the `signatures` feature, approvers are never sent a
+    /// challenge to sign.
+    #[cfg(not(feature = "signatures"))]
+    fn verify_signature(&self, _: Option<&str>, _: &Credentials) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Takes the session's correlation ID from its ticket with
+    /// `correlation_pattern`, if the user didn't provide one. Events
+    /// logged from here on carry it, as do the session's manifest and
+    /// index entry.
+    fn correlate_with_ticket(&mut self) {
+        if self.correlation_id.is_some() {
+            return;
         }
 
-        self.log_output(log)
-    }
+        let id = match (self.options.correlation_pattern.as_ref(), self.ticket.as_ref()) {
+            (Some(pattern), Some(ticket)) => pattern.extract(ticket),
+            _                             => None,
+        };
+
+        if let Some(id) = id {
+            slog::info!(self.slog, "correlation id taken from ticket"; "correlation_id" => &id);
 
-    fn log_output(&mut self, log: &[u8]) -> Result<()> {
-        // if we have a socket, write to it
-        self.socket.as_mut().map_or(Ok(()), |socket| {
-            socket.write_all(log)
-        }).context(ErrorKind::SessionTerminated)?;
+            self.slog           = slog::Logger::new(&self.slog, slog::o!("correlation_id" => id.clone()));
+            self.correlation_id = Some(id);
+        }
+    }
 
-        slog::trace!(self.slog, "{{{} bytes sent}}", log.len());
+    /// Confirms `ticket` with the issue tracker, if one is configured.
+    #[cfg(feature = "http")]
+    fn confirm_ticket(&self, ticket: &str) -> std::result::Result<(), String> {
+        match self.options.ticket_url.as_ref() {
+            Some(url) => ticket::confirm(url, ticket, self.options.ticket_timeout),
+            None      => Ok(()),
+        }
+    }
 
+    /// Without the `http` feature, there's no issue tracker to confirm
+    /// tickets with (and `ticket_url` is rejected as invalid).
+    #[cfg(not(feature = "http"))]
+    fn confirm_ticket(&self, _: &str) -> std::result::Result<(), String> {
         Ok(())
     }
 
-    fn log_disabled(&mut self, _: &[u8]) -> Result<()> {
-        // if we're exempt, don't disable stdin/stdout/stderr
-        if self.is_exempt() {
-            return Ok(());
+    /// Lets an approver beside the user approve the session from the
+    /// user's own terminal, for emergencies with nobody else around to
+    /// pair with. They name themselves, and authenticate through PAM
+    /// as the host requires them to. Returns whether they approved it:
+    /// naming nobody waits for a pair as usual, but an approver who
+    /// isn't allowed to, or who fails to authenticate, refuses the
+    /// session.
+    #[cfg(feature = "pam")]
+    fn is_colocated_approved(&mut self) -> Result<bool> {
+        if self.options.colocated_approvers.is_empty() {
+            return Ok(false);
         }
 
-        Err(ErrorKind::StdinRedirected.into())
-    }
+        // a single approver can't stand in for a quorum, and nobody
+        // watches sessions approved this way, so only recorded ones are
+        // offered it
+        if self.quorum.approvers > 1 || self.plugin.settings.noninteractive {
+            return Ok(false);
+        }
 
-    fn local_pair_prompt(&self, template_spec: &Spec) {
-        // read the template from the file; if there's an error, use the
-        // default template instead
-        let template : Vec<u8> = File::open(&self.options.user_prompt_path)
-            .and_then(|file| file.bytes().collect() )
-            .unwrap_or_else(|_| DEFAULT_USER_PROMPT.into() );
+        if self.recording.is_none() && !self.is_recorded_by_sudo() {
+            slog::debug!(self.slog, "co-located approval unavailable, session not recorded");
 
-        slog::trace!(self.slog, "local prompt template loaded");
+            return Ok(false);
+        }
 
-        let prompt = template_spec.expand(&template[..]);
+        let conversation = self.plugin.conversation();
 
-        // NOTE: I don't think it's adviseable to log the evaluated
-        // template here since it likely contains ANSI escape sequences
-        // that clear the terminal, adjust color/width, etc.
-        slog::trace!(self.slog, "local prompt template evaluated");
+        let approver = match conversation.prompt(b"approver at this terminal (leave empty to wait for a pair): ", true) {
+            Ok(approver) => String::from_utf8_lossy(&approver).trim().to_owned(),
+            Err(_)       => return Ok(false),
+        };
 
-        // If sudo has detected the user's TTY, we try to print to it
-        // directly. If we don't have a TTY or fail to open/write to
-        // it, we fall back to writing with the plugin's printf
-        // function. This allows `sudo_pair` to be used in situations
-        // where stdout/stderr are redirected to pipes.
-        //
-        // we ignore any errors about printing the prompt locally,
-        // because we can't really do anything productive other than
-        // die, and that could render `sudo` inoperable given an
-        // unanticipated bug (however, if we fail to print to the TTY)
-        // we do try to print directly to stderr
-        //
-        // TODO: the stderr write is returning an error (EINVAL) even
-        // though it prints successfully; I'm not entirely sure why. It
-        // started failing when I added some new operators for the
-        // templating code, but nothing in that commit seems like it
-        // should have obviously started causing writes to fail.
-        //
-        // EINVAL is raised by the underlying libc vfprintf call, which
-        // appears to only be problematic if the underlying write fails.
-        // As far as I can tell, this only happens if something isn't
-        // aligned correctly and the `fd` is opened with `O_DIRECT`. But
-        // it seems unlikely that STDIN is opened that way or that
-        // anything Rust allocates is misaligned. The other possibility
-        // is that STDIN is "unsuitable for writing" which also seems
-        // improbable. For now, I'm ignoring the situation but hopefully
-        // there's enough information here for someone (probably me) to
-        // pick up where I left off.
-        let _ = self.plugin.tty().as_mut()
-            .and_then(|tty| tty.write_all(&prompt).ok() )
-            .ok_or_else(|| self.plugin.stderr().write_all(&prompt));
+        if approver.is_empty() {
+            return Ok(false);
+        }
 
-        slog::trace!(self.slog, "local prompt rendered");
-    }
+        let uid = self.plugin.user_id(&approver);
 
-    fn remote_pair_connect(&mut self) -> Result<()> {
-        let slog = slog::Logger::new(&self.slog, slog::o!(
-            "socket_path" => self.socket_path().to_string_lossy().into_owned(),
-        ));
+        let allowed = self.options.colocated_approvers.iter().any(|user| match *user {
+            User::Id(id)         => uid == Some(id),
+            User::Name(ref name) => *name == approver,
+        });
 
-        slog::debug!(slog, "socket initializing";
-            "socket_uid"  => self.socket_uid(),
-            "socket_gid"  => self.socket_gid(),
-            "socket_mode" => format!("{:#06o}", self.socket_mode()),
-        );
+        let refusal = if approver == self.plugin.user_info.user || uid == Some(self.plugin.user_info.uid) {
+            Some("users may not approve their own sessions".into())
+        } else if !allowed {
+            Some(format!("{} may not approve sessions at this terminal", approver))
+        } else {
+            let _ = conversation.info(format!("{} must authenticate to approve this session\n", approver).as_bytes());
 
-        if self.socket.is_some() {
-            slog::warn!(slog, "socket unexpectedly already initialized");
+            let tty = self.plugin.user_info.tty.as_ref()
+                .map(|tty| tty.to_string_lossy().into_owned());
 
-            // TODO: this is probably an error, since we should never
-            // expect the socket to have already been created
-            return Ok(());
-        }
+            pam::authenticate(&self.options.pam_service, &approver, tty.as_deref(), &mut |style, message| match style {
+                pam::Style::PromptEchoOff => conversation.prompt(message, false).ok(),
+                pam::Style::PromptEchoOn  => conversation.prompt(message, true).ok(),
+                pam::Style::ErrorMsg      => conversation.error(message).ok().and(None),
+                pam::Style::TextInfo      => conversation.info(message).ok().and(None),
+            }).and_then(|()| self.check_approver_account(&approver)).err()
+        };
 
-        slog::info!(slog, "socket waiting for pair to connect...");
+        if let Some(reason) = refusal {
+            slog::warn!(self.slog, "co-located approval refused";
+                "approver" => &approver,
+                "reason"   => &reason,
+            );
 
-        // TODO: clearly indicate when the socket path is missing
-        // this is currently being hidden by the `context` method which
-        // ironically hides the extra context instead of providing extra
-        // context
-        let socket = Socket::open(
-            self.socket_path(),
-            self.socket_uid(),
-            self.socket_gid(),
-            self.socket_mode(),
-        ).context(ErrorKind::CommunicationError)?;
+            let _ = self.plugin.print(Level::Error, format!("session declined: {}", reason).as_bytes());
 
-        self.socket = Some(socket);
+            return Err(ErrorKind::ApproverUnauthenticated.into());
+        }
 
-        slog::info!(slog, "socket connected");
+        slog::warn!(self.slog, "pair session approved by co-located approver";
+            "approval" => "co-located",
+            "approver" => &approver,
+        );
 
-        Ok(())
+        self.colocated_approver = Some(approver);
+
+        Ok(true)
     }
 
-    fn remote_pair_prompt(&mut self, template_spec: &Spec) -> Result<()> {
-        // read the template from the file; if there's an error, use the
-        // default template instead
-        let template : Vec<u8> = File::open(&self.options.pair_prompt_path)
-            .and_then(|file| file.bytes().collect() )
-            .unwrap_or_else(|_| DEFAULT_PAIR_PROMPT.into() );
+    /// Without the `pam` feature, co-located approvers can't
+    /// authenticate (and `colocated_approvers` is rejected as invalid).
+    #[cfg(not(feature = "pam"))]
+    fn is_colocated_approved(&mut self) -> Result<bool> {
+        Ok(false)
+    }
 
-        slog::trace!(self.slog, "remote prompt loaded");
+    /// Lets an approver approve the session from a web page, through
+    /// the approval service at `approval_url`, rather than by
+    /// connecting to its socket. Returns whether they approved it: a
+    /// service that can't be reached waits for a pair as usual, but a
+    /// session that's declined, or isn't approved before its URL
+    /// expires, is refused.
+    #[cfg(feature = "http")]
+    fn is_web_approved(&mut self) -> Result<bool> {
+        let template = match self.options.approval_url.as_ref() {
+            Some(template) => template,
+            None           => return Ok(false),
+        };
 
-        let prompt = template_spec.expand(&template[..]);
+        // the service stands in for a single approver, who can't make
+        // up a quorum alone
+        if self.quorum.approvers > 1 {
+            slog::debug!(self.slog, "web approval unavailable, session requires a quorum");
 
-        slog::trace!(self.slog, "remote prompt evaluated");
+            return Ok(false);
+        }
 
-        let socket = self.socket
-            .as_mut()
-            .ok_or(ErrorKind::CommunicationError)?;
+        let ttl = match self.options.approval_timeout {
+            timeout if timeout > Duration::from_secs(0) => timeout.min(self.options.approval_url_ttl),
+            _                                           => self.options.approval_url_ttl,
+        };
 
-        socket.write_all(&prompt[..])
-            .context(ErrorKind::CommunicationError)?;
+        let plugin     = self.plugin;
+        let user       = plugin.user_name();
+        let runas_user = plugin.runas_user_name();
+        let cwd        = plugin.cwd().to_string_lossy().into_owned();
+        let executable = plugin.executable().to_string_lossy().into_owned();
+        let argv : Vec<_> = plugin.argv().iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
 
-        socket.flush()
-            .context(ErrorKind::CommunicationError)?;
+        let session = web_approval::Session {
+            session_id: &self.session_id,
+            host:       &plugin.user_info.host,
+            user:       &user,
+            runas_user: &runas_user,
+            cwd:        &cwd,
+            argv:       &argv,
+            executable: &executable,
+            ticket:     self.ticket.as_deref(),
+            correlation_id: self.correlation_id.as_deref(),
+            expires_at: self.clock.wall() + ttl,
+        };
+
+        let approval = match ApprovalUrl::register(template, &session) {
+            Ok(approval) => approval,
+            Err(e)       => {
+                slog::error!(self.slog, "unable to register session for web approval"; "error" => &e);
 
+                let _ = self.plugin.print(Level::Warn, format!("{}; a pair is required instead", e).as_bytes());
 
-        slog::trace!(self.slog, "remote prompt rendered");
+                return Ok(false);
+            },
+        };
 
-        // default `response` to something other than success, since
-        // `read` might return without actually having written anything;
-        // this prevents us from being required to check the number of
-        // bytes actually read from `read`
-        let mut response : [u8; 1] = [b'n'];
+        // the URL isn't logged, since anyone with it could try to
+        // approve the session
+        slog::info!(self.slog, "session waiting for web approval";
+            "approval_url_ttl" => format!("{:?}", ttl),
+        );
 
-        slog::debug!(self.slog, "remote prompt awaiting response...");
+        let _ = self.plugin.print(Level::Info, format!(
+            "approve this session at {} within {}",
+            approval.url(),
+            humanize::duration(ttl),
+        ).as_bytes());
 
-        // read exactly one byte back from the socket for the
-        // response (`read_exact` isn't used because it will capture
-        // Ctrl-C and retry the read); we don't need to check the return
-        // value because if the read was successful, we're guaranteed to
-        // have read at least one byte
-        let _ = socket.read(&mut response)
-            .context(ErrorKind::SessionDeclined)?;
+        let decision = self.await_web_approval(&approval, ttl);
 
-        slog::debug!(self.slog, "remote pair responded";
-            "response" => String::from_utf8_lossy(&response[..]).into_owned(),
-        );
+        approval.withdraw();
 
-        // echo back out the response, since the client is anticipated
-        // to be noecho
-        let _ = socket.write_all(&response[..]);
-        let _ = socket.write_all(b"\n");
+        let (approver, comment) = match decision? {
+            Decision::Approved { approver, comment } => (approver, comment),
 
-        match &response {
-            b"y" | b"Y" => (),
-            _           => {
-                slog::warn!(self.slog, "remote pair declined session");
-                return Err(ErrorKind::SessionDeclined.into());
-            }
-        };
+            Decision::Declined { approver, comment } => {
+                slog::warn!(self.slog, "web approver declined session";
+                    "approver" => &approver,
+                    "comment"  => comment.as_deref(),
+                );
 
-        slog::info!(self.slog, "remote pair approved session");
+                let _ = self.plugin.print(Level::Warn, match comment {
+                    Some(comment) => format!("session declined by {}: {}", approver, comment),
+                    None          => format!("session declined by {}", approver),
+                }.as_bytes());
 
-        Ok(())
-    }
+                return Err(ErrorKind::SessionDeclined.into());
+            },
 
-    fn is_exempt(&self) -> bool {
-        // root is always exempt
-        if self.is_sudoing_from_root() {
-            slog::debug!(self.slog, "sudo initiated by root";
-                "user_info.uid" => self.plugin.user_info.uid,
-            );
+            Decision::Pending | Decision::Expired => {
+                slog::warn!(self.slog, "session not approved in time";
+                    "approval_url_ttl" => format!("{:?}", ttl),
+                );
 
-            return true;
-        }
+                return Err(ErrorKind::ApprovalTimedOut.into());
+            },
+        };
 
-        // a user sudoing entirely to themselves is weird, but I can't
-        // see any reason not to let them do it without approval since
-        // they can already do everything as themselves anyway
-        if self.is_sudoing_to_themselves() {
-            slog::debug!(self.slog, "sudo to current user";
-                "user_info.uid"          => self.plugin.user_info.uid,
-                "command_info.runas_uid" => self.plugin.command_info.runas_uid,
+        if approver == user {
+            slog::warn!(self.slog, "web approval refused";
+                "approver" => &approver,
+                "reason"   => "users may not approve their own sessions",
             );
 
-            return true;
+            let _ = self.plugin.print(Level::Error, b"session declined: users may not approve their own sessions");
+
+            return Err(ErrorKind::ApproverUnauthenticated.into());
         }
 
-        // exempt if the approval command is the command being invoked
-        if self.is_sudoing_approval_command() {
-            slog::debug!(self.slog, "sudo running approval command";
-                "command_info.command"       => self.plugin.command_info.command.to_string_lossy().into_owned(),
-                "plugin_options.binary_path" => self.options.binary_path.to_string_lossy().into_owned(),
-            );
+        slog::warn!(self.slog, "pair session approved through the approval service";
+            "approval" => "web",
+            "approver" => &approver,
+            "comment"  => comment.as_deref(),
+        );
 
-            return true;
+        if let Some(comment) = comment {
+            let _ = self.plugin.print(Level::Info, format!("session approved: {}", comment).as_bytes());
+
+            self.approver_comments.push(comment);
         }
 
-        // policy plugins can inform us that logging is unnecessary
-        if self.is_exempted_from_logging() {
-            slog::debug!(self.slog, "sudo command exempted from logging");
+        self.web_approver = Some(approver);
 
-            return true;
-        }
+        Ok(true)
+    }
 
-        // exempt if the user who's sudoing is in a group that's exempt
-        // from having to pair
-        if self.is_sudoing_from_exempted_gid() {
-            slog::debug!(self.slog, "sudo from exempt group id");
+    /// Asks the approval service whether the session has been decided
+    /// every `approval_url_poll`, until it has been, or `ttl` has
+    /// passed (which leaves it `Pending`). Failures to ask are retried
+    /// until then, and the user can cancel the wait as they could one
+    /// for a pair.
+    #[cfg(feature = "http")]
+    fn await_web_approval(&self, approval: &ApprovalUrl, ttl: Duration) -> Result<Decision> {
+        let signals = SignalGuard::install()
+            .context(ErrorKind::CommunicationError)?;
 
-            return true;
-        }
+        let deadline  = Deadline::after_on(self.clock.as_ref(), ttl);
+        let countdown = self.plugin.tty()
+            .and_then(|tty| Countdown::start(&self.threads, tty, Some(deadline), self.wait_hints(), self.template_spec(), self.clock.clone()).ok());
 
-        // exempt if none of the gids of the user we're sudoing into are
-        // in the set of gids we enforce pairing for
-        if !self.is_sudoing_to_enforced_gid() {
-            slog::debug!(self.slog, "sudo to unenforced group id");
+        let decision = loop {
+            match approval.poll() {
+                Ok(Decision::Pending) => {},
+                Ok(decision)          => break decision,
+                Err(e)                => slog::warn!(self.slog, "unable to poll the approval service"; "error" => e),
+            }
 
-            return true;
-        }
+            if deadline.has_passed_on(self.clock.as_ref()) {
+                break Decision::Pending;
+            }
 
-        slog::debug!(self.slog, "sudo session requires a pair");
+            let wait = deadline.remaining_on(self.clock.as_ref())
+                .min(self.options.approval_url_poll);
 
-        false
-    }
+            if let Some(signal) = signals.wait(wait) {
+                slog::warn!(self.slog, "session cancelled while waiting for web approval";
+                    "signal" => signals::name(signal),
+                );
 
-    fn is_sudoing_from_root(&self) -> bool {
-        // theoretically, root's `uid` should be 0, but it's probably
-        // safest to check whatever user `sudo` is running as since sudo
-        // is pretty much by definition going to be running setuid;
-        // hypothetically with selinux someone could have sudo owned by
-        // some non-root user that has the caps needed for sudoing around
-        //
-        // note that the `euid` will always be the owner of the `sudo`
-        // binary
-        self.plugin.user_info.uid == self.plugin.user_info.euid
-    }
-
-    fn is_sudoing_to_themselves(&self) -> bool {
-        // if they're not sudoing to a new uid or to a new gid, they're
-        // just becoming themselves... right?
-        if !self.is_sudoing_to_user() && !self.is_sudoing_to_group() {
-            debug_assert_eq!(
-                self.plugin.runas_gids(),
-                self.plugin.user_info.groups.iter().cloned().collect()
-            );
+                return Err(ErrorKind::Cancelled.into());
+            }
+        };
 
-            return true;
-        }
+        drop(countdown);
 
-        false
+        Ok(decision)
     }
 
-    fn is_sudoing_approval_command(&self) -> bool {
-        self.plugin.command_info.command == self.options.binary_path
+    /// Without the `http` feature, there's no approval service to
+    /// approve sessions through (and `approval_url` is rejected as
+    /// invalid).
+    #[cfg(not(feature = "http"))]
+    fn is_web_approved(&mut self) -> Result<bool> {
+        Ok(false)
     }
 
-    ///
-    /// Returns true if the policy plugin has not given us any
-    /// facilities to log output for.
-    ///
-    fn is_exempted_from_logging(&self) -> bool {
-        if
-            !self.plugin.command_info.iolog_ttyout &&
-            !self.plugin.command_info.iolog_stdout &&
-            !self.plugin.command_info.iolog_stderr
-        {
-            return true;
-        }
+    /// Something the user was asked to provide from `source`: either
+    /// the environment variable `env`, or their answer to `prompt`.
+    /// Since it may be a secret (e.g., a pre-approval token), every copy
+    /// made along the way is wiped once it's dropped.
+    fn user_input(&self, source: InputSource, env: &str, prompt: &[u8]) -> Option<Zeroizing<String>> {
+        let input = match source {
+            InputSource::Env => self.plugin.user_env
+                .get_str(env)
+                .map(|input| Zeroizing::new(input.to_owned())),
+
+            // there's nobody to ask when sudo was told not to prompt
+            InputSource::Prompt if self.plugin.settings.noninteractive => None,
+
+            InputSource::Prompt => self.plugin.conversation()
+                .prompt(prompt, true)
+                .ok()
+                .map(|input| Zeroizing::new(String::from_utf8_lossy(&input).into_owned())),
+        };
 
-        false
+        input
+            .map(|input| Zeroizing::new(input.trim().to_owned()))
+            .filter(|input| !input.is_empty())
     }
 
-    fn is_sudoing_to_user_and_group(&self) -> bool {
-        // if a user is doing `sudo -u ${u} -g ${g}`, we don't have a
-        // way to ensure that the pair can act with permissions of both
-        // the new user and the new group; ignoring this would allow
-        // someone to gain a group privilege through a pair who doesn't
-        // also have that group privilege
-        //
-        // note that we don't use `is_sudoing_to_group` because sudoing
-        // to a new user typically implicitly comes along with sudoing
-        // to a new group which is fine, what we want to avoid is the
-        // user explicitly providing a *different* group
-        if self.is_sudoing_to_user() && self.is_sudoing_to_explicit_group() {
-            return true
-        }
-
-        false
+    ///
+    /// Returns true if sudo itself is recording the session's I/O (e.g.,
+    /// the `log_output` option in sudoers).
+    ///
+    fn is_recorded_by_sudo(&self) -> bool {
+        policy::is_recorded_by_sudo(self.plugin)
     }
 
-    fn is_sudoing_from_exempted_gid(&self) -> bool {
-        !self.options.gids_exempted.is_disjoint(
-            &self.plugin.user_info.groups.iter().cloned().collect()
-        )
+    fn is_sudoing_to_user_and_group(&self) -> bool {
+        policy::is_sudoing_to_user_and_group(self.plugin)
     }
 
-    fn is_sudoing_to_enforced_gid(&self) -> bool {
-        !self.options.gids_enforced.is_disjoint(
-            &self.plugin.runas_gids()
-        )
+    fn is_automation_account(&self) -> bool {
+        policy::is_automation_account(self.invocation(), self)
     }
 
     fn is_sudoing_to_user(&self) -> bool {
-        // `plugin.settings.runas_user` tells us the value of `-u`, but
-        // by checking the change in uid, we can exclude cases where
-        // they're sudoing to themselves
-        self.plugin.user_info.uid != self.plugin.command_info.runas_euid
-    }
-
-    fn is_sudoing_to_group(&self) -> bool {
-        self.plugin.user_info.gid != self.plugin.command_info.runas_egid
+        policy::is_sudoing_to_user(self.plugin)
     }
 
     // returns true if `-g` was specified
     fn is_sudoing_to_explicit_group(&self) -> bool {
-        self.plugin.settings.runas_group.is_some()
+        policy::is_sudoing_to_explicit_group(self.plugin)
     }
 
     fn socket_path(&self) -> PathBuf {
-        // we encode the originating `uid` into the pathname since
-        // there's no other (easy) way for the approval command to probe
-        // for this information
+        self.socket_path.clone()
+    }
+
+    /// The path to create the session's socket at, named by the
+    /// `socket_name` template. Since the name is unique to this
+    /// process, a socket already there was left behind by one that's
+    /// gone and is replaced; anything else is left alone, and a name
+    /// unique to this session is used instead.
+    fn free_socket_path(&self) -> PathBuf {
+        // the default name encodes the originating `uid` since there's
+        // no other (easy) way for the approval command to probe for
+        // this information
         //
         // note that we want the *`uid`* and not the `euid` here since
         // we want to know who the real user is and not the `uid` of the
         // owner of `sudo`
-        self.options.socket_dir.join(
-            format!(
-                "{}.{}.sock",
-                self.plugin.user_info.uid,
-                self.plugin.user_info.pid,
-            )
-        )
+        let name = self.options.socket_name.expand(&Vars {
+            uid:        self.plugin.user_info.uid,
+            pid:        self.plugin.user_info.pid,
+            sid:        self.plugin.user_info.sid,
+            session_id: &self.session_id,
+        });
+
+        let path = self.options.socket_dir.join(&name);
+
+        // abstract sockets can't be left behind by sessions that have
+        // gone, and their names are unique to this process
+        if self.options.in_memory_only {
+            let mut name = OsString::from("@");
+            name.push(path);
+
+            return name.into();
+        }
+
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                let unique = self.options.socket_dir.join(
+                    socket_name::unique(&name, &self.session_id)
+                );
+
+                slog::warn!(self.slog, "socket path already taken";
+                    "socket_path" => path.to_string_lossy().into_owned(),
+                    "instead"     => unique.to_string_lossy().into_owned(),
+                );
+
+                unique
+            },
+
+            _ => path,
+        }
     }
 
     fn socket_uid(&self) -> uid_t {
+        if let Some(uid) = self.socket_owner {
+            return uid;
+        }
+
+        // approvers restricted to a group connect through a socket only
+        // that group can write to, so root keeps owning it
+        if self.approver_gid.is_some() {
+            return uid_t::max_value();
+        }
+
         // we explicitly want to have the socket owned by the root user
         // if we're doing `sudo -g`, so that the sudoing user can't
         // silently self-approve by manually connecting to the socket
         // without needing to invoke sudo
-        if self.is_sudoing_to_user() {
+        //
+        // users sudoing to themselves (when that isn't exempt) are
+        // treated as though they're sudoing to a user
+        if self.is_sudoing_to_user() || !self.is_sudoing_to_explicit_group() {
             self.plugin.command_info.runas_euid
         } else {
             // don't change the owner; chown accepts a uid of -1
@@ -585,6 +3767,14 @@ impl SudoPair {
     }
 
     fn socket_gid(&self) -> gid_t {
+        if let Some(gid) = self.socket_group {
+            return gid;
+        }
+
+        if let Some(gid) = self.approver_gid {
+            return gid;
+        }
+
         // this should only be changed if the user is sudoing to a group
         // explicitly, not only if they're gaining a new primary `gid`
         if self.is_sudoing_to_explicit_group() {
@@ -598,6 +3788,17 @@ impl SudoPair {
     }
 
     fn socket_mode(&self) -> mode_t {
+        if let Some(Mode(mode)) = self.options.socket_mode {
+            return mode;
+        }
+
+        // approvers restricted to a group have to be able to act as
+        // that group, in place of the user or group the command is run
+        // as
+        if self.approver_gid.is_some() {
+            return libc::S_IWGRP;
+        }
+
         // if the user is sudoing to a new `euid`, we require the
         // approver to also be able to act as the same `euid`; this is
         // the first check, because if euid changes egid is also likely
@@ -617,15 +3818,48 @@ impl SudoPair {
             return libc::S_IWGRP; // from <sys/stat.h>, writable by the group
         }
 
-        // elsewhere, we exempt sessions for users who are sudoing to
-        // themselves, so this line should never be reached; if it is,
-        // it's a bug
-        unreachable!("cannot determine if we're sudoing to a user or group")
+        // the user is sudoing to themselves, which is only reached when
+        // `exempt_sudo_to_self` is disabled; anyone who can act as the
+        // user (including the user) may approve the session
+        libc::S_IWUSR
+    }
+
+    /// The command `sudo` was invoked as (as `Plugin::invocation`
+    /// reconstructs it), with the command itself shortened to the
+    /// configured limits. For `sudoedit`, the files being edited take
+    /// the command's place.
+    fn displayed_invocation(&self) -> Vec<u8> {
+        let mut invocation = self.plugin.settings.progname.as_bytes().to_vec();
+
+        for flag in self.plugin.settings.flags() {
+            invocation.push(b' ');
+            invocation.extend_from_slice(&flag);
+        }
+
+        let command = self.displayed_command(command_limits(&self.options));
+
+        if !command.is_empty() {
+            invocation.push(b' ');
+            invocation.extend_from_slice(&command);
+        }
+
+        invocation
+    }
+
+    /// The command being run, shortened to `limits`. For `sudoedit`,
+    /// the files being edited take the command's place.
+    fn displayed_command(&self, limits: CommandLimits) -> Vec<u8> {
+        let files = self.plugin.edited_files();
+
+        let command : Vec<_> = match self.plugin.invocation_kind() {
+            InvocationKind::Edit => files.iter().map(|file| file.as_os_str().as_bytes()).collect(),
+            _                    => self.plugin.command.iter().map(|arg| arg.as_bytes()).collect(),
+        };
+
+        limits.apply(&command).join()
     }
 
     fn template_spec(&self) -> Spec {
-        // TODO: provide username of runas_euid?
-        // TODO: provide groupname of runas_egid?
         let mut spec = Spec::with_escape(b'%');
 
         // the name of the appoval _b_inary
@@ -635,12 +3869,27 @@ impl SudoPair {
         spec.replace(b'B', self.options.binary_path.as_os_str().as_bytes());
 
         // the full _C_ommand `sudo` was invoked as (recreated as
-        // best-effort for now)
-        spec.replace(b'C', self.plugin.invocation());
+        // best-effort for now), shortened if it's unreasonably long
+        spec.replace(b'C', self.displayed_invocation());
 
         // the cw_d_ of the command being run under `sudo`
         spec.replace(b'd', self.plugin.cwd().as_os_str().as_bytes());
 
+        // the _e_ffective gid the command will be run as
+        spec.replace(b'e', self.plugin.command_info.runas_egid.to_string());
+
+        // the name of the _E_ffective group the command will be run as
+        spec.replace(b'E', self.plugin.runas_group_name());
+
+        // the _F_iles being edited with `sudoedit`, if any
+        spec.replace(b'F', {
+            let files : Vec<_> = self.plugin.edited_files().iter()
+                .map(|file| file.as_os_str().as_bytes().to_vec())
+                .collect();
+
+            files.join(&b' ')
+        });
+
         // the _h_ostname of the machine `sudo` is being executed on
         spec.replace(b'h', self.plugin.user_info.host.as_bytes());
 
@@ -650,9 +3899,24 @@ impl SudoPair {
         // the real _g_id of the user invoking `sudo`
         spec.replace(b'g', self.plugin.user_info.gid.to_string());
 
+        // the name of the primary _G_roup of the user invoking `sudo`
+        spec.replace(b'G', self.plugin.group_name());
+
+        // the _L_abels the policy plugin attached to the session
+        spec.replace(b'L', self.labels.join(","));
+
         // the _p_id of this `sudo` process
         spec.replace(b'p', self.plugin.user_info.pid.to_string());
 
+        // the path to the session's _S_ocket
+        spec.replace(b'S', self.socket_path.as_os_str().as_bytes());
+
+        // the uid of the user the command will be _r_un as
+        spec.replace(b'r', self.plugin.command_info.runas_euid.to_string());
+
+        // the name of the user the command will be _R_un as
+        spec.replace(b'R', self.plugin.runas_user_name());
+
         // the real _u_id of the user invoking `sudo`
         spec.replace(b'u', self.plugin.user_info.uid.to_string());
 
@@ -662,106 +3926,243 @@ impl SudoPair {
         // the _W_idth of the invoking user's terminal, in columns
         spec.replace(b'W', self.plugin.user_info.cols.to_string());
 
+        let restrictions = Restrictions::of(&self.plugin.command_info);
+
+        // the restrictions the sudo _P_olicy places on the command
+        spec.replace(b'P', restrictions.summary());
+
+        // the session _T_imeout set by the policy
+        spec.replace(b'T', restrictions.timeout.map_or_else(|| "none".into(), restrictions::format));
+
+        // whether the policy prevents the command from e_X_ecuting others
+        spec.replace(b'X', if restrictions.noexec { "yes" } else { "no" });
+
+        // whether the policy _I_ntercepts commands the command executes
+        spec.replace(b'I', if restrictions.intercept { "yes" } else { "no" });
+
         spec
     }
 }
 
+impl Names for SudoPair {
+    fn uid(&self, user: &User) -> Option<uid_t> {
+        match *user {
+            User::Id(uid) => Some(uid),
+            User::Name(ref name) => {
+                let uid = self.plugin.user_id(name);
+
+                if uid.is_none() {
+                    slog::warn!(self.slog, "couldn't resolve user name"; "user" => name);
+                }
+
+                uid
+            },
+        }
+    }
+
+    fn gid(&self, group: &Group) -> Option<gid_t> {
+        match *group {
+            Group::Id(gid) => Some(gid),
+            Group::Name(ref name) => {
+                let gid = self.plugin.group_id(name);
+
+                if gid.is_none() {
+                    slog::warn!(self.slog, "couldn't resolve group name"; "group" => name);
+                }
+
+                gid
+            },
+        }
+    }
+}
+
 impl Drop for SudoPair {
     fn drop(&mut self) {
         slog::debug!(self.slog, "plugin exiting");
     }
 }
 
-#[derive(Debug)]
-struct PluginOptions {
-    /// `binary_path` is the location of the approval binary, so that we
-    /// can bypass the approval process for invoking it.
-    ///
-    /// Default: `"/usr/bin/sudo_approve"`
-    binary_path: PathBuf,
+/// Reads the key pre-approval tokens are signed with, refusing one that
+/// anyone but root could have read (and used to approve their own
+/// commands) or replaced.
+fn preapproval_key(path: &Path) -> io::Result<Zeroizing<Vec<u8>>> {
+    let metadata = std::fs::metadata(path)?;
 
-    /// `user_prompt_path` is the location of the prompt template to
-    /// display to the user invoking sudo; if no template is found at
-    /// this location, an extremely minimal default will be printed.
-    ///
-    /// Default: `"/etc/sudo_pair.prompt.user"`
-    user_prompt_path: PathBuf,
+    if metadata.uid() != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the key isn't owned by root"));
+    }
 
-    /// `pair_prompt_path` is the location of the prompt template to
-    /// display to the user being asked to approve the sudo session; if
-    /// no template is found at this location, an extremely minimal
-    /// default will be printed.
-    ///
-    /// Default: `"/etc/sudo_pair.prompt.pair"`
-    pair_prompt_path: PathBuf,
+    if metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the key is accessible to users other than root"));
+    }
 
-    /// `socket_dir` is the path where this plugin will store sockets for
-    /// sessions that are pending approval.
-    ///
-    /// Default: `"/var/run/sudo_pair"`
-    socket_dir: PathBuf,
+    token::read_key(path)
+}
 
-    /// `gids_enforced` is a comma-separated list of gids that sudo_pair
-    /// will gate access to. If a user is `sudo`ing to a user that is a
-    /// member of one of these groups, they will be required to have a
-    /// pair approve their session.
-    ///
-    /// Default: `[0]` (e.g., root)
-    gids_enforced: HashSet<gid_t>,
-
-    /// `gids_exempted` is a comma-separated list of gids whose users
-    /// will be exempted from the requirements of sudo_pair. Note that
-    /// this is not the opposite of the `gids_enforced` flag. Whereas
-    /// `gids_enforced` gates access *to* groups, `gids_exempted`
-    /// exempts users sudoing *from* groups. For instance, this setting
-    /// can be used to ensure that oncall sysadmins can respond to
-    /// outages without needing to find a pair.
-    ///
-    /// Default: `[]` (however, root is *always* exempt)
-    gids_exempted: HashSet<gid_t>,
+/// The correlation ID the user provided in the environment, if any. An
+/// invalid one is logged and ignored rather than refusing the session,
+/// since it only groups sessions after the fact.
+fn session_correlation_id(plugin: &Plugin, slog: &slog::Logger) -> Option<String> {
+    let value = plugin.user_env.get_lossy(CORRELATION_ENV)?;
+
+    match correlation::parse(&value) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            slog::warn!(slog, "invalid correlation id ignored"; "error" => e);
+            None
+        },
+    }
+}
+
+/// The labels the policy plugin attached to the session, if any.
+/// Invalid labels are logged and left out, since they'd be shown to
+/// approvers and logged as they are.
+fn session_labels(plugin: &Plugin, slog: &slog::Logger) -> Vec<String> {
+    let value = match plugin.command_info.raw.get_lossy(LABEL_KEY) {
+        Some(value) => value,
+        None        => return Vec::new(),
+    };
+
+    let (labels, invalid) = labels::parse(&value);
+
+    if !invalid.is_empty() {
+        slog::warn!(slog, "invalid session labels ignored"; "invalid_labels" => invalid.join(","));
+    }
+
+    labels
 }
 
-impl PluginOptions {
-    fn binary_name(&self) -> &[u8] {
-        self.binary_path.file_name().unwrap_or_else(||
-            self.binary_path.as_os_str()
-        ).as_bytes()
+/// The maintenance window described at `path`, if one is open. A
+/// window that can't be loaded is logged and ignored, so sessions are
+/// paired as usual.
+fn maintenance_window(slog: &slog::Logger, path: &Path, now: SystemTime) -> Option<Window> {
+    match Window::load(path) {
+        Ok(window) => window.filter(|window| window.is_active(now)),
+
+        Err(e) => {
+            slog::error!(slog, "unable to load maintenance window";
+                "maintenance_window" => path.to_string_lossy().into_owned(),
+                "error"              => e,
+            );
+
+            None
+        },
     }
 }
 
-// TODO: single_use_lifetimes was committed, but I'm not sure there's
-// actually a way to satisfy the linter for the time being
-#[allow(single_use_lifetimes)]
-impl<'a> From<&'a OptionMap> for PluginOptions {
-    fn from(map: &'a OptionMap) -> Self {
-        Self {
-            binary_path: map.get("binary_path")
-                .unwrap_or_else(|_| DEFAULT_BINARY_PATH.into()),
+/// The plugin's disable file at `path`, if it's present. A file that
+/// can't be trusted is logged and ignored, so sessions are paired as
+/// usual.
+fn disabled(slog: &slog::Logger, path: &Path) -> Option<Disabled> {
+    match Disabled::load(path) {
+        Ok(disabled) => disabled,
+
+        Err(e) => {
+            slog::error!(slog, "unable to load disable file";
+                "disable_file" => path.to_string_lossy().into_owned(),
+                "error"        => e,
+            );
+
+            None
+        },
+    }
+}
 
-            user_prompt_path: map.get("user_prompt_path")
-                .unwrap_or_else(|_| DEFAULT_USER_PROMPT_PATH.into()),
+/// Completes `recording` with its `manifest`, logging whether it was
+/// recorded in full and adding it to the session `index`, if there is
+/// one, or discards it if the command never ran.
+fn finish_recording(
+    slog:      &slog::Logger,
+    recording: Recording,
+    manifest:  &Manifest,
+    index:     Option<(PathBuf, Ownership)>,
+) {
+    let description = recording.describe();
+
+    // a command that never ran leaves nothing worth keeping, and a
+    // recording of it would only look like a session that did
+    if let Outcome::NotRun(_) = manifest.outcome {
+        match recording.discard(manifest) {
+            Ok(())  => slog::info!(slog, "pair session recording discarded";
+                "recording" => description,
+            ),
+
+            Err(e) => slog::error!(slog, "unable to discard pair session recording";
+                "recording" => description,
+                "error"     => e.to_string(),
+            ),
+        }
 
-            pair_prompt_path: map.get("pair_prompt_path")
-                .unwrap_or_else(|_| DEFAULT_PAIR_PROMPT_PATH.into()),
+        return;
+    }
 
-            socket_dir: map.get("socket_dir")
-                .unwrap_or_else(|_| DEFAULT_SOCKET_DIR.into()),
+    match recording.finish(manifest) {
+        Ok(())  => slog::info!(slog, "pair session recorded";
+            "recording" => &description,
+        ),
 
-            gids_enforced: map.get("gids_enforced")
-                .unwrap_or_else(|_| DEFAULT_GIDS_ENFORCED.iter().cloned().collect()),
+        Err(e) => slog::error!(slog, "pair session recording incomplete";
+            "recording" => &description,
+            "error"     => e.to_string(),
+        ),
+    }
 
-            gids_exempted: map.get("gids_exempted")
-                .unwrap_or_default(),
+    if let Some((path, ownership)) = index {
+        if let Err(e) = index::append(&path, ownership, &index::Entry::new(manifest, description)) {
+            slog::error!(slog, "unable to index pair session";
+                "index" => path.display().to_string(),
+                "error" => e.to_string(),
+            );
         }
     }
 }
 
-impl slog::Value for PluginOptions {
-    fn serialize(&self, _: &slog::Record<'_>, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
-        serializer.emit_str(key, &format!("{:?}", self))
+/// Checks what every session depends on: the directories it writes to,
+/// and syslog.
+fn check_dependencies(options: &PluginOptions, health: &mut Health) {
+    // sockets aren't created there when nothing's written to disk
+    if !options.in_memory_only {
+        health.check_writable("socket_dir", &options.socket_dir);
+    }
+
+    // recordings uploaded over HTTP are spooled there first
+    if options.recording_sink == RecordingSinkKind::File || options.recording_sink == RecordingSinkKind::Http {
+        health.check_writable("recording_dir", &options.recording_dir);
+    }
+
+    #[cfg(feature = "syslog")]
+    health.check_syslog(Path::new(SYSLOG_PATH));
+}
+
+/// Logs the results of the self-check, and warns the user of any
+/// failures; otherwise, they're only noted in sudo's debug log.
+fn report_health(plugin: &Plugin, slog: &slog::Logger, health: &Health) {
+    let summary = health.summary();
+
+    if health.is_healthy() {
+        slog::info!(slog, "plugin self-check passed"; "checks" => &summary);
+
+        let _ = plugin.print(Level::Debug, format!("self-check: {}", summary).as_bytes());
+    } else {
+        slog::warn!(slog, "plugin self-check failed"; "checks" => &summary);
+
+        let _ = plugin.print(Level::Warn, format!("self-check: {}", summary).as_bytes());
+    }
+}
+
+/// The limits on how much of the command is displayed and logged.
+fn command_limits(options: &PluginOptions) -> CommandLimits {
+    CommandLimits {
+        max_args:   options.max_command_args   as usize,
+        max_length: options.max_command_length as usize,
+        max_width:  options.max_command_width  as usize,
     }
 }
 
+/// The most subcommands listed in a session's manifest; a loop in a
+/// shell can run any number of them, and the rest are only counted.
+const MAX_SUBCOMMANDS : usize = 1024;
+
 #[cfg(all(target_os = "macos", feature = "syslog"))]
 const SYSLOG_PATH: &str = "/private/var/run/syslog";
 
@@ -775,13 +4176,20 @@ fn slog(name: &str, version: &str) -> slog::Logger {
     #[cfg(not(any(feature = "syslog", feature = "journald")))]
     let drain = slog::Drain::Discard;
 
+    // an unreachable syslog is reported by the self-check, rather than
+    // taking sudo down with it
     #[cfg(feature = "syslog")]
-    let drain = slog_syslog::SyslogBuilder::new()
+    let drain = match slog_syslog::SyslogBuilder::new()
         .unix(SYSLOG_PATH)
         .facility(slog_syslog::Facility::LOG_AUTH)
         .start()
-        .unwrap() // TODO: remove unwrap
-        .ignore_res(); // TODO: handle errors
+    {
+        Ok(drain) => drain.ignore_res(), // TODO: handle errors
+        Err(_)    => return slog::Logger::root(slog::Discard, slog::o!(
+            "plugin_name"    => name   .to_owned(),
+            "plugin_version" => version.to_owned()
+        )),
+    };
 
     #[cfg(feature = "journald")]
     let drain = slog_journald::JournaldDrain
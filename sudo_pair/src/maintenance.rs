@@ -0,0 +1,185 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Planned maintenance windows, during which sessions are recorded
+//! rather than paired, so a mass change doesn't need an approver for
+//! every host it touches.
+//!
+//! A window is described by a small JSON file on the host (e.g.,
+//! dropped there by configuration management ahead of the change):
+//!
+//! ```text
+//! {"id": "CHG-1234", "start": "2020-09-13T02:00:00Z", "end": "2020-09-13T06:00:00Z", "reason": "kernel upgrades"}
+//! ```
+//!
+//! `start` and `end` are UTC timestamps (or seconds since the epoch),
+//! and `reason` is optional. Since anyone who could write the file could
+//! skip pairing, it has to be owned by root and writable by nobody else.
+
+use crate::flat_json::{self, Value};
+
+use sudo_pair_types::manifest::parse_utc;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A planned maintenance window.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Window {
+    /// identifies the change the window was planned for, and is
+    /// attached to every session during it
+    pub(crate) id: String,
+
+    /// when the window opens, and when it closes
+    pub(crate) start: SystemTime,
+    pub(crate) end:   SystemTime,
+
+    pub(crate) reason: Option<String>,
+}
+
+impl Window {
+    /// Loads the window described at `path`, if there is one.
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, String> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e)       => return Err(e.to_string()),
+        };
+
+        if metadata.uid() != 0 {
+            return Err("the window isn't owned by root".into());
+        }
+
+        if metadata.mode() & 0o022 != 0 {
+            return Err("the window is writable by users other than root".into());
+        }
+
+        Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?).map(Some)
+    }
+
+    /// Parses a window from a flat JSON object. Fields other than those
+    /// of a window are ignored.
+    pub(crate) fn parse(s: &str) -> Result<Self, String> {
+        let fields = flat_json::parse(s)
+            .ok_or("the window isn't a flat JSON object")?;
+
+        let field = |key: &str| fields.iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value);
+
+        let id = match field("id") {
+            Some(Value::Str(id)) if !id.is_empty() => id.clone(),
+            _                                      => return Err("id is missing".into()),
+        };
+
+        let time = |key: &str| match field(key) {
+            Some(Value::Uint(secs)) => Ok(UNIX_EPOCH + Duration::from_secs(*secs)),
+            Some(Value::Str(time))  => parse_utc(time)
+                .ok_or_else(|| format!("{} isn't a UTC timestamp, got {}", key, time)),
+            None                    => Err(format!("{} is missing", key)),
+        };
+
+        let start = time("start")?;
+        let end   = time("end")?;
+
+        if end <= start {
+            return Err("end isn't after start".into());
+        }
+
+        let reason = match field("reason") {
+            Some(Value::Str(reason)) => Some(reason.clone()),
+            Some(Value::Uint(_))     => return Err("reason isn't a string".into()),
+            None                     => None,
+        };
+
+        Ok(Self { id, start, end, reason })
+    }
+
+    /// Returns true if the window is open at `now`.
+    pub(crate) fn is_active(&self, now: SystemTime) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn parses_windows() {
+        let window = Window::parse(r#"
+            {
+                "id":     "CHG-1234",
+                "start":  "2020-09-13T02:00:00Z",
+                "end":    1600000000,
+                "reason": "kernel upgrades \u2014 \"all\" hosts",
+                "owner":  "sre"
+            }
+        "#).unwrap();
+
+        assert_eq!(Window {
+            id:     "CHG-1234".into(),
+            start:  UNIX_EPOCH + Duration::from_secs(1_599_962_400),
+            end:    UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+            reason: Some("kernel upgrades \u{2014} \"all\" hosts".into()),
+        }, window);
+
+        let window = Window::parse(r#"{"id":"CHG-1","start":0,"end":1}"#).unwrap();
+
+        assert_eq!(None, window.reason);
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        let invalid = [
+            ("",                                                     "the window isn't a flat JSON object"),
+            (r#"{"id":"CHG-1","start":0,"end":1} {}"#,               "the window isn't a flat JSON object"),
+            (r#"{"id":"CHG-1","start":0,"end":1,"n":{}}"#,           "the window isn't a flat JSON object"),
+            (r#"{"start":0,"end":1}"#,                               "id is missing"),
+            (r#"{"id":"","start":0,"end":1}"#,                       "id is missing"),
+            (r#"{"id":"CHG-1","end":1}"#,                            "start is missing"),
+            (r#"{"id":"CHG-1","start":"tomorrow","end":1}"#,         "start isn't a UTC timestamp, got tomorrow"),
+            (r#"{"id":"CHG-1","start":1,"end":1}"#,                  "end isn't after start"),
+            (r#"{"id":"CHG-1","start":0,"end":1,"reason":7}"#,       "reason isn't a string"),
+        ];
+
+        for (json, problem) in invalid.iter() {
+            assert_eq!(Err(problem.to_string()), Window::parse(json), "{}", json);
+        }
+    }
+
+    #[test]
+    fn is_active_between_start_and_end() {
+        let window = Window::parse(r#"{"id":"CHG-1","start":100,"end":200}"#).unwrap();
+
+        assert!(!window.is_active(UNIX_EPOCH + Duration::from_secs(99)));
+        assert!(window.is_active(UNIX_EPOCH  + Duration::from_secs(100)));
+        assert!(window.is_active(UNIX_EPOCH  + Duration::from_secs(199)));
+        assert!(!window.is_active(UNIX_EPOCH + Duration::from_secs(200)));
+    }
+
+    #[test]
+    fn loads_nothing_without_a_file() {
+        let path = env::temp_dir().join(format!("sudo_pair-maintenance-{}", process::id()));
+
+        assert_eq!(Ok(None), Window::load(&path));
+    }
+}
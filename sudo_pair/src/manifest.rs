@@ -0,0 +1,47 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! How a recorded session's command ended, in the terms of its
+//! manifest. The manifest itself, and how it's serialized, is in
+//! `sudo_pair_types::manifest`, which is shared with everything that
+//! reads them.
+
+use sudo_plugin::CommandStatus;
+use sudo_pair_types::manifest::Outcome;
+
+/// How the command ended, from what sudo reported when the session
+/// closed.
+pub fn outcome(status: CommandStatus) -> Outcome {
+    match status {
+        CommandStatus::Exited(code)         => Outcome::Exited(code),
+        CommandStatus::Signaled(signal)     => Outcome::Signaled(signal),
+        CommandStatus::CommandNotRun(errno) => Outcome::NotRun(errno),
+        CommandStatus::Unknown              => Outcome::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interprets_close_arguments() {
+        let outcome = |exit_status, error| outcome(CommandStatus::from_close(exit_status, error));
+
+        assert_eq!(Outcome::Exited(0),   outcome(0, 0));
+        assert_eq!(Outcome::Exited(1),   outcome(1 << 8, 0));
+        assert_eq!(Outcome::Signaled(9), outcome(9, 0));
+        assert_eq!(Outcome::NotRun(2),   outcome(0, 2));
+    }
+}
@@ -0,0 +1,2941 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Parsing and validation of the options provided to the plugin on
+//! its `Plugin` line in `sudo.conf`.
+
+use crate::correlation::CorrelationPattern;
+use crate::disclosure::DEFAULT_NOTICE_PATH;
+use crate::environment::EnvPattern;
+use crate::socket_name::SocketName;
+use crate::ticket::TicketPattern;
+use crate::template::{DEFAULT_PAIR_PROMPT_PATH, DEFAULT_USER_PROMPT_PATH};
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use libc::{gid_t, mode_t, uid_t};
+
+use sudo_plugin::{
+    ByteSize,
+    Cidr,
+    FileResolver,
+    FromSudoOption,
+    FromSudoOptionList,
+    NssResolver,
+    OptionMap,
+    Plugin,
+    StaticResolver,
+    TimeoutResolver,
+    split_list,
+};
+
+const DEFAULT_BINARY_PATH       : &str             = "/usr/bin/sudo_approve";
+const DEFAULT_SOCKET_DIR        : &str             = "/var/run/sudo_pair";
+const DEFAULT_MAX_SESSIONS      : u32              = 0;
+const DEFAULT_SOCKET_BACKLOG    : u32              = 128;
+const DEFAULT_MAX_PENDING       : u32              = 0;
+const DEFAULT_ATTEMPT_LIMIT     : u32              = 0;
+const DEFAULT_ATTEMPT_WINDOW    : Duration         = Duration::from_secs(60);
+const DEFAULT_CONNECTION_BAN    : Duration         = Duration::from_secs(5 * 60);
+const DEFAULT_RECORDING_SINK    : RecordingSinkKind = RecordingSinkKind::None;
+const DEFAULT_RECORDING_DIR     : &str             = "/var/log/sudo_pair";
+const DEFAULT_RECORDING_LAYOUT  : RecordingLayout  = RecordingLayout::SessionId;
+const DEFAULT_SUDO_IOLOG_DIR    : &str             = "/var/log/sudo-io";
+const DEFAULT_RECORDING_MODE    : Mode             = Mode(0o600);
+const DEFAULT_RECORDING_UMASK   : UmaskPolicy      = UmaskPolicy::Ignore;
+const DEFAULT_UPLOAD_ATTEMPTS   : u32              = 3;
+const DEFAULT_UPLOAD_TIMEOUT    : Duration         = Duration::from_secs(10);
+const DEFAULT_OPEN_BUDGET       : Duration         = Duration::from_secs(1);
+const DEFAULT_CLOSE_BUDGET      : Duration         = Duration::from_secs(10);
+const DEFAULT_QUEUE_LIMIT       : ByteSize         = ByteSize(1024 * 1024);
+const DEFAULT_ON_RECORD_ERROR   : RecordErrorPolicy = RecordErrorPolicy::Terminate;
+const DEFAULT_FLUSH_INTERVAL    : Duration         = Duration::from_secs(0);
+const DEFAULT_RECORDING_FSYNC   : FsyncPolicy      = FsyncPolicy::Never;
+const DEFAULT_CLOSE_HOOK_TIMEOUT: Duration         = Duration::from_secs(5);
+const DEFAULT_APPROVAL_TIMEOUT  : Duration         = Duration::from_secs(0);
+const DEFAULT_PREVIEW_TIMEOUT   : Duration         = Duration::from_secs(0);
+const DEFAULT_APPROVER_WIDTH    : WidthPolicy      = WidthPolicy::Ignore;
+const DEFAULT_SOFT_MAX_SESSION  : Duration         = Duration::from_secs(0);
+const DEFAULT_REAPPROVAL        : Duration         = Duration::from_secs(0);
+const DEFAULT_REAPPROVAL_GRACE  : Duration         = Duration::from_secs(60);
+const DEFAULT_TRANSFER_TIMEOUT  : Duration         = Duration::from_secs(0);
+const DEFAULT_CHAT              : bool             = true;
+const DEFAULT_PREAPPROVAL_SOURCE: InputSource      = InputSource::Env;
+const DEFAULT_PREAPPROVAL_WINDOW: Duration         = Duration::from_secs(60 * 60);
+const DEFAULT_DISABLE_MODE      : DisableMode      = DisableMode::RecordOnly;
+const DEFAULT_TICKET_SOURCE     : InputSource      = InputSource::Env;
+const DEFAULT_TICKET_TIMEOUT    : Duration         = Duration::from_secs(5);
+const DEFAULT_APPROVAL_URL_TTL  : Duration         = Duration::from_secs(600);
+const DEFAULT_APPROVAL_URL_POLL : Duration         = Duration::from_secs(2);
+const DEFAULT_GIDS_ENFORCED     : [Group; 1]       = [Group::Id(0)];
+const DEFAULT_EXEMPT_SELF       : bool             = true;
+const DEFAULT_UNATTENDED_POLICY : UnattendedPolicy = UnattendedPolicy::Deny;
+const DEFAULT_IDENTITY_SOURCE   : IdentitySource   = IdentitySource::Nss;
+const DEFAULT_IDENTITY_TIMEOUT  : Duration         = Duration::from_secs(2);
+const DEFAULT_DIGEST_INTERVAL   : Duration         = Duration::from_secs(0);
+const DEFAULT_UTF8_CHUNKING     : bool             = false;
+const DEFAULT_BINARY_OUTPUT     : BinaryOutput     = BinaryOutput::Pass;
+const DEFAULT_OUTPUT_RATE_LIMIT : ByteSize         = ByteSize(0);
+const DEFAULT_WARN_UNSAFE_CMD   : bool             = true;
+const DEFAULT_WARN_SHELL        : bool             = true;
+const DEFAULT_MAX_COMMAND_ARGS  : u32              = 256;
+const DEFAULT_MAX_COMMAND_LEN   : u32              = 4096;
+const DEFAULT_MAX_COMMAND_WIDTH : u32              = 0;
+const DEFAULT_SUDOEDIT_DIFF     : u32              = 0;
+const DEFAULT_RECORD_MUX        : bool             = false;
+const DEFAULT_MONITORING_NOTICE : bool             = false;
+const DEFAULT_PROMPT_DELIVERY   : [DeliveryChannel; 3] = [
+    DeliveryChannel::Tty,
+    DeliveryChannel::Conversation,
+    DeliveryChannel::Stderr,
+];
+const DEFAULT_HIGHLIGHT_ENV     : bool             = true;
+const DEFAULT_QUIET             : bool             = false;
+const DEFAULT_VERBOSE           : bool             = false;
+const DEFAULT_STRICT_HEALTH     : bool             = false;
+const DEFAULT_ON_INTERNAL_ERROR : InternalErrorPolicy = InternalErrorPolicy::Deny;
+const DEFAULT_PAIR_AND_RECORDING: bool             = false;
+const DEFAULT_IN_MEMORY_ONLY    : bool             = false;
+const DEFAULT_RESOLVE_GROUPS    : bool             = false;
+const DEFAULT_ACCOUNT_CHECK     : bool             = false;
+const DEFAULT_PAM_SERVICE       : &str             = "sudo";
+const DEFAULT_RISKY_ENV         : [&str; 14]       = [
+    "LD_*",
+    "DYLD_*",
+    "BASH_ENV",
+    "ENV",
+    "IFS",
+    "SHELLOPTS",
+    "PS4",
+    "PERL5LIB",
+    "PERL5OPT",
+    "PYTHONPATH",
+    "PYTHONSTARTUP",
+    "RUBYLIB",
+    "RUBYOPT",
+    "NODE_OPTIONS",
+];
+const DEFAULT_SANITIZE_ESCAPES  : [EscapeClass; 7] = [
+    EscapeClass::Title,
+    EscapeClass::Clipboard,
+    EscapeClass::Dcs,
+    EscapeClass::Apc,
+    EscapeClass::Reports,
+    EscapeClass::Paste,
+    EscapeClass::Protocol,
+];
+
+#[derive(Debug)]
+pub struct PluginOptions {
+    /// `binary_path` is the location of the approval binary, so that we
+    /// can bypass the approval process for invoking it.
+    ///
+    /// Default: `"/usr/bin/sudo_approve"`
+    pub binary_path: PathBuf,
+
+    /// `user_prompt_path` is the location of the prompt template to
+    /// display to the user invoking sudo; if no template is found at
+    /// this location, an extremely minimal default will be printed.
+    ///
+    /// Default: `"/etc/sudo_pair.prompt.user"`
+    pub user_prompt_path: PathBuf,
+
+    /// `pair_prompt_path` is the location of the prompt template to
+    /// display to the user being asked to approve the sudo session; if
+    /// no template is found at this location, an extremely minimal
+    /// default will be printed.
+    ///
+    /// Default: `"/etc/sudo_pair.prompt.pair"`
+    pub pair_prompt_path: PathBuf,
+
+    /// `quiet` replaces the prompt at `user_prompt_path` with a single
+    /// line saying how to approve the session, and suppresses
+    /// informational messages.
+    ///
+    /// Default: `false`
+    pub quiet: bool,
+
+    /// `verbose` additionally shows the user the session's id and
+    /// socket while they wait, and how long it took to be approved and
+    /// how long it ran.
+    ///
+    /// Default: `false`
+    pub verbose: bool,
+
+    /// `strict_health` refuses every session when any part of the
+    /// self-check run as sessions open fails (e.g., `socket_dir` isn't
+    /// writable or syslog is unreachable). The results are logged, and
+    /// the user warned of any failures, regardless.
+    ///
+    /// Default: `false`
+    pub strict_health: bool,
+
+    /// `on_internal_error` is what's done when a session can't be
+    /// paired because the plugin itself failed (e.g., the session's
+    /// socket couldn't be created, its recording couldn't be started,
+    /// or the approver keys couldn't be read), as opposed to the
+    /// session being declined. `deny` refuses the session, and
+    /// `allow_with_audit` lets the command run unpaired, logging it as
+    /// a critical error and warning the user, for hosts where an
+    /// outage of sudo_pair mustn't keep anyone from working. Sessions
+    /// refused because of `strict_health` are refused regardless.
+    ///
+    /// Default: `deny`
+    pub on_internal_error: InternalErrorPolicy,
+
+    /// `require_pair_and_recording` only lets a session run once an
+    /// approver has accepted it *and* its recording has been confirmed
+    /// open, for the most sensitive hosts. Sessions approved without
+    /// an approver (e.g., by a maintenance window or pre-approval), or
+    /// that pair rules keep from being recorded, are refused, with the
+    /// half that's missing reported; recordings delivered elsewhere
+    /// must open within `recording_open_budget`. It requires a
+    /// `recording_sink`, and can't be combined with any
+    /// `on_internal_error` or `on_record_error` that would let a
+    /// session run unpaired or unrecorded.
+    ///
+    /// Default: `false`
+    pub require_pair_and_recording: bool,
+
+    /// `in_memory_only` keeps the plugin from writing anything to local
+    /// disk, for hosts where that's forbidden. Session sockets are
+    /// created in Linux's abstract namespace rather than `socket_dir`
+    /// (named as they would have been, but starting with `@`), running
+    /// sessions aren't tracked, and sessions are refused outright if
+    /// any option would have something written to disk (e.g., a
+    /// `recording_sink` of `file` or `http`). Recordings can still be
+    /// streamed elsewhere with the `socket` or `command` sinks.
+    ///
+    /// Default: `false`
+    pub in_memory_only: bool,
+
+    /// `resolve_approver_groups` looks up the groups of approvers
+    /// connecting to sessions' abstract sockets (with `in_memory_only`)
+    /// by name, through `identity_source`, since the kernel only says
+    /// which group their process is running as. Approvers whose group
+    /// memberships come from a directory service (e.g., SSSD or LDAP)
+    /// can then connect without `sudo -g`. Memberships are cached once
+    /// they're found, and lookups are bounded by `identity_timeout`.
+    ///
+    /// Default: `false`
+    pub resolve_approver_groups: bool,
+
+    /// `host_tags` is a comma-separated list of `key=value` tags
+    /// describing the host (e.g.,
+    /// `datacenter=sfo1,environment=production,team=payments`). They're
+    /// attached to everything the plugin logs, to session manifests,
+    /// and to what approval clients are sent when they connect, so
+    /// sessions can be routed and prioritized without matching on
+    /// hostnames. Keys are lowercase letters, digits, and underscores;
+    /// values are letters, digits, `.`, `_`, `-`, and `:`.
+    ///
+    /// Default: none
+    pub host_tags: Vec<HostTag>,
+
+    /// `socket_dir` is the path where this plugin will store sockets for
+    /// sessions that are pending approval.
+    ///
+    /// Default: `"/var/run/sudo_pair"`
+    pub socket_dir: PathBuf,
+
+    /// `max_sessions` is the most sessions that may run on the host at
+    /// once. Every session (exempt or not) is tracked in its own
+    /// subdirectory of `socket_dir/sessions`, and sessions opened while
+    /// the limit is reached are refused. `0` allows any number.
+    ///
+    /// Default: `0`
+    pub max_sessions: u32,
+
+    /// `socket_name` is the template sockets in `socket_dir` are named
+    /// by. It expands `%{uid}`, `%{pid}`, `%{sid}`, and `%{session_id}`
+    /// (and `%%` to a literal `%`), and has to include `%{pid}` or
+    /// `%{session_id}` so that no two sessions can share a name.
+    ///
+    /// Default: `"%{uid}.%{pid}.sock"`
+    pub socket_name: SocketName,
+
+    /// `socket_owner` is the user (name or uid) that owns session
+    /// sockets, in place of the one chosen from how the user is
+    /// `sudo`ing. Anyone who can write to a socket can approve its
+    /// session, so this should never be a user who might need a pair.
+    ///
+    /// Default: none
+    pub socket_owner: Option<User>,
+
+    /// `socket_group` is the group (name or gid) that owns session
+    /// sockets, in place of the one chosen from how the user is
+    /// `sudo`ing.
+    ///
+    /// Default: none
+    pub socket_group: Option<Group>,
+
+    /// `socket_mode` is the permissions (in octal) given to session
+    /// sockets, in place of the ones chosen from how the user is
+    /// `sudo`ing.
+    ///
+    /// Default: none
+    pub socket_mode: Option<Mode>,
+
+    /// `socket_backlog` is how many connections to a session's socket
+    /// the kernel holds until they're accepted, beyond which more are
+    /// refused before they ever reach the plugin.
+    ///
+    /// Default: `128`
+    pub socket_backlog: u32,
+
+    /// `max_pending_connections` is the most connections accepted while
+    /// waiting for a pair, counting those that are dropped, before the
+    /// wait is given up on. It bounds the work someone connecting over
+    /// and over can cause. `0` allows any number.
+    ///
+    /// Default: `0`
+    pub max_pending_connections: u32,
+
+    /// `control_socket` is the control socket of an approver daemon,
+    /// which sessions connect to and announce themselves on rather than
+    /// each waiting on a socket of its own, so that one daemon can
+    /// receive every session on the host. The daemon is held to what a
+    /// pair connecting to the session's own socket would be (who runs
+    /// it has to be able to write to that socket, and be admitted by
+    /// `approver_uids`). Sessions fall back to their own socket when
+    /// the daemon can't be reached or isn't admitted. A path starting
+    /// with `@` names a socket in Linux's abstract namespace.
+    ///
+    /// Default: none
+    pub control_socket: Option<PathBuf>,
+
+    /// `approver_uids` is a comma-separated list of the users (names or
+    /// uids) whose connections to session sockets are accepted. The
+    /// connections of anyone else who could write to a socket are
+    /// logged and dropped as soon as they're accepted, before they're
+    /// sent anything. When empty, anyone who can write to a socket may
+    /// connect to it.
+    ///
+    /// Default: none
+    pub approver_uids: Vec<User>,
+
+    /// `connection_attempt_limit` is how many times each user may
+    /// connect to a session's socket within `connection_attempt_window`
+    /// before they're banned from connecting for `connection_ban`. Their
+    /// connections are dropped without being logged while they're
+    /// banned, and the ban itself is logged once. `0` allows any number.
+    ///
+    /// Default: `0`
+    pub connection_attempt_limit: u32,
+
+    /// `connection_attempt_window` is how long connection attempts are
+    /// counted for against `connection_attempt_limit`.
+    ///
+    /// Default: `60s`
+    pub connection_attempt_window: Duration,
+
+    /// `connection_ban` is how long users who exceed
+    /// `connection_attempt_limit` are banned from connecting.
+    ///
+    /// Default: `5m`
+    pub connection_ban: Duration,
+
+    /// `gids_enforced` is a comma-separated list of gids (or group
+    /// names) that sudo_pair will gate access to. If a user is `sudo`ing
+    /// to a user that is a member of one of these groups, they will be
+    /// required to have a pair approve their session.
+    ///
+    /// Default: `[0]` (e.g., root)
+    pub gids_enforced: HashSet<Group>,
+
+    /// `gids_exempted` is a comma-separated list of gids (or group
+    /// names) whose users will be exempted from the requirements of sudo_pair. Note that
+    /// this is not the opposite of the `gids_enforced` flag. Whereas
+    /// `gids_enforced` gates access *to* groups, `gids_exempted`
+    /// exempts users sudoing *from* groups. For instance, this setting
+    /// can be used to ensure that oncall sysadmins can respond to
+    /// outages without needing to find a pair.
+    ///
+    /// Default: `[]` (however, root is *always* exempt)
+    pub gids_exempted: HashSet<Group>,
+
+    /// `networks_enforced` is a comma-separated list of CIDR blocks
+    /// (e.g., `10.20.0.0/16`). If provided, pairing is only required on
+    /// hosts with an address in one of these blocks. Hosts whose
+    /// addresses couldn't be determined always require pairing.
+    ///
+    /// Default: `[]` (e.g., every host)
+    pub networks_enforced: Vec<Cidr>,
+
+    /// `networks_exempted` is a comma-separated list of CIDR blocks.
+    /// Hosts with an address in one of these blocks are exempted from
+    /// the requirements of sudo_pair.
+    ///
+    /// Default: `[]`
+    pub networks_exempted: Vec<Cidr>,
+
+    /// `exempt_sudo_to_self` controls whether users who `sudo` to
+    /// themselves (the same uid, gid, and groups) are exempted from
+    /// pairing. When disabled, the user is able to approve their own
+    /// session, so pairing only adds a deliberate confirmation step.
+    ///
+    /// Default: `true`
+    pub exempt_sudo_to_self: bool,
+
+    /// `pair_rules` is a file of rules matching commands by their
+    /// arguments (e.g., any with `--force`). If provided, sessions that
+    /// would otherwise require a pair only do so when their command
+    /// matches one of these rules. If the file can't be read or parsed,
+    /// every session requires a pair.
+    ///
+    /// Default: none (e.g., every command requires a pair)
+    pub pair_rules: Option<PathBuf>,
+
+    /// `approver_keys` is a file of SSH public keys, in the format of
+    /// `authorized_keys`. If provided, approvers are sent a challenge
+    /// they have to sign with one of these keys before their approval
+    /// is accepted. Only Ed25519 keys are supported. If the file can't
+    /// be read, or lists no usable keys, no session can be approved.
+    /// Requires the `signatures` feature.
+    ///
+    /// Default: none
+    pub approver_keys: Option<PathBuf>,
+
+    /// `approver_totp_keys` is a file of approvers' names and their
+    /// base32-encoded TOTP secrets, one approver per line. If provided,
+    /// approvers have to send their name and current one-time code
+    /// before their approval is accepted, and each code can only be
+    /// used once. It has to be readable only by root. If it can't be
+    /// read, or lists no approvers, no session can be approved.
+    ///
+    /// Default: none
+    pub approver_totp_keys: Option<PathBuf>,
+
+    /// `colocated_approvers` is a comma-separated list of the users
+    /// (names or uids) who may approve sessions from the invoking
+    /// user's own terminal, for emergencies with nobody else around to
+    /// pair with. Users who need a pair are first asked whether someone
+    /// beside them will approve; if so, that approver names themselves
+    /// and authenticates through PAM (with `pam_service`), and the
+    /// session runs without a pair, logged and recorded as a co-located
+    /// approval. It's only offered for sessions that need a single
+    /// approval and are recorded. Requires the `pam` feature.
+    ///
+    /// Default: none
+    pub colocated_approvers: Vec<User>,
+
+    /// `approver_account_check` runs approvers through the PAM account
+    /// stage (with `pam_service`) before accepting their approval, so
+    /// the lockouts, expirations, and access times the host enforces
+    /// apply to approving sessions too. Only approvers whose names are
+    /// known can be checked: those who sent a one-time code (with
+    /// `approver_totp_keys`) and co-located approvers. Requires the
+    /// `pam` feature.
+    ///
+    /// Default: `false`
+    pub approver_account_check: bool,
+
+    /// `pam_service` is the PAM service co-located approvers
+    /// authenticate with, and that approvers' accounts are checked
+    /// against.
+    ///
+    /// Default: `sudo`
+    pub pam_service: String,
+
+    /// `noninteractive_policy` controls what happens when `sudo` is
+    /// invoked with `-n` (`--non-interactive`). `deny` rejects the
+    /// session outright, `pair` displays the pairing instructions and
+    /// waits for a pair as usual, and `record` allows the session
+    /// without a pair as long as sudo itself is recording the session's
+    /// I/O.
+    ///
+    /// Default: `deny`
+    pub noninteractive_policy: UnattendedPolicy,
+
+    /// `automation_users` is a comma-separated list of the users (names
+    /// or uids) of service accounts, which can't answer prompts. Their
+    /// sessions are treated as if sudo had been invoked with `-n`, going
+    /// straight to `noninteractive_policy` without waiting on a pair,
+    /// and nothing is printed to them but errors, so a failure is a
+    /// single `session rejected: reason=<reason>` line rather than a
+    /// prompt that's never answered.
+    ///
+    /// Default: none
+    pub automation_users: Vec<User>,
+
+    /// `automation_groups` is a comma-separated list of gids (or group
+    /// names) whose users are treated like those in
+    /// `automation_users`.
+    ///
+    /// Default: none
+    pub automation_groups: HashSet<Group>,
+
+    /// `background_policy` controls what happens when `sudo` is invoked
+    /// from a process group that isn't in the foreground of its
+    /// terminal (e.g., `sudo command &`), where the user can't interact
+    /// with the terminal. Accepts the same values as
+    /// `noninteractive_policy`.
+    ///
+    /// Note that sudo's own `-b` flag isn't communicated to plugins and
+    /// can't be detected.
+    ///
+    /// Default: `deny`
+    pub background_policy: UnattendedPolicy,
+
+    /// `no_tty_policy` controls what happens when `sudo` is invoked
+    /// without a TTY (e.g., from cron or a script), where there may be
+    /// nobody to read the pairing instructions. Accepts the same values
+    /// as `noninteractive_policy`; with `pair`, the instructions are
+    /// delivered through the first channel in `prompt_delivery` that
+    /// doesn't need one.
+    ///
+    /// Default: `deny`
+    pub no_tty_policy: UnattendedPolicy,
+
+    /// `prompt_delivery` is a comma-separated list of the ways to try
+    /// delivering the pairing instructions to the user invoking `sudo`,
+    /// in order of preference. `tty` writes directly to the user's
+    /// terminal (and is skipped if sudo didn't detect one),
+    /// `conversation` uses sudo's conversation function (which can
+    /// reach users without a TTY), and `stderr` writes to sudo's
+    /// standard error. Each is tried until one succeeds.
+    ///
+    /// Default: `tty,conversation,stderr`
+    pub prompt_delivery: Vec<DeliveryChannel>,
+
+    /// `identity_source` controls how user and group names are resolved
+    /// for display in prompts. `nss` uses the system's usual lookups
+    /// through libc, `files` reads `/etc/passwd` and `/etc/group`
+    /// directly, and `static` only uses the names provided through
+    /// `identity_users` and `identity_groups`. Ids without a name are
+    /// displayed numerically.
+    ///
+    /// Default: `nss`
+    pub identity_source: IdentitySource,
+
+    /// `identity_users` is a comma-separated list of `uid:name` pairs
+    /// used when `identity_source` is `static`.
+    ///
+    /// Default: `[]`
+    pub identity_users: HashMap<uid_t, String>,
+
+    /// `identity_groups` is a comma-separated list of `gid:name` pairs
+    /// used when `identity_source` is `static`.
+    ///
+    /// Default: `[]`
+    pub identity_groups: HashMap<gid_t, String>,
+
+    /// `identity_timeout` is the longest that resolving any one name
+    /// may take before giving up and displaying the id numerically.
+    ///
+    /// Default: `2s`
+    pub identity_timeout: Duration,
+
+    /// `transcript_digest_interval` is how often a SHA-256 digest of the
+    /// output sent so far is sent to the approver, as an `OSC` escape
+    /// sequence that terminals ignore. A final digest is always logged
+    /// when the session ends, regardless of this setting. `0` disables
+    /// sending digests to the approver.
+    ///
+    /// Default: `0`
+    pub transcript_digest_interval: Duration,
+
+    /// `utf8_chunking` controls whether output sent to the approver is
+    /// re-chunked so that multibyte UTF-8 characters split across
+    /// writes by the command arrive whole, rather than being garbled by
+    /// clients that render each chunk as it's received. Incomplete
+    /// characters are held back until the rest of them is written.
+    ///
+    /// Default: `false`
+    pub utf8_chunking: bool,
+
+    /// `binary_output` controls what the approver is sent when the
+    /// session's output looks binary (it contains `NUL` bytes or is
+    /// dominated by unusual control characters), which could otherwise
+    /// wreck the approver's terminal. `pass` sends it unchanged,
+    /// `summarize` replaces it with a note of how many bytes were
+    /// suppressed, and `hexdump` sends a hex dump of it instead.
+    ///
+    /// Default: `pass`
+    pub binary_output: BinaryOutput,
+
+    /// `sanitize_escapes` is a comma-separated list of the classes of
+    /// terminal escape sequences to remove from output before it's sent
+    /// to the approver, since a malicious command could otherwise use
+    /// them to attack the approver's terminal. `title` (window title
+    /// changes), `clipboard` (`OSC 52` clipboard writes), `osc` (any
+    /// other operating system command), `dcs` (device control strings),
+    /// `apc` (application program commands, privacy messages, and
+    /// `SOS` strings), `reports` (requests for the terminal to report
+    /// its status, attributes, or title), `paste` (toggling bracketed
+    /// paste mode), and `protocol` (imitations of sudo_pair's own
+    /// protocol messages) are recognized. `none` disables sanitization.
+    ///
+    /// Default: `title,clipboard,dcs,apc,reports,paste,protocol`
+    pub sanitize_escapes: HashSet<EscapeClass>,
+
+    /// `output_rate_limit` is the most output relayed to the approver
+    /// each second (e.g., `64KiB`), so a command that floods its
+    /// terminal can't overwhelm the approver's connection. Output over
+    /// the limit is withheld, and the approver told how much once
+    /// output is relayed again. `0` doesn't limit the output.
+    ///
+    /// Default: `0`
+    pub output_rate_limit: ByteSize,
+
+    /// `warn_unsafe_command` controls whether the approver is warned
+    /// when the command's binary isn't owned by root, is writable by
+    /// anyone but its owner, or is inside a world-writable directory,
+    /// any of which would let someone other than root change what's
+    /// actually run. The binary's location, inode, and digest are
+    /// logged regardless.
+    ///
+    /// Default: `true`
+    pub warn_unsafe_command: bool,
+
+    /// `warn_shell` controls whether the approver is warned when the
+    /// user is being granted a shell (`sudo -i` or `sudo -s`) rather
+    /// than a single command, along with which shell the policy
+    /// resolved and the login class it will run under. The shell is
+    /// logged regardless.
+    ///
+    /// Default: `true`
+    pub warn_shell: bool,
+
+    /// `max_command_args` is the most arguments of the command shown
+    /// to the approver (through `%C`) and logged; any more are replaced
+    /// by a marker saying how many were left out (e.g., `… (+1024
+    /// args)`). Recordings and their manifests always have the whole
+    /// command. `0` doesn't limit the arguments.
+    ///
+    /// Default: `256`
+    pub max_command_args: u32,
+
+    /// `max_command_length` is the most bytes of the command shown to
+    /// the approver and logged, beyond which the command is cut short
+    /// the same way. `0` doesn't limit the length.
+    ///
+    /// Default: `4096`
+    pub max_command_length: u32,
+
+    /// `max_command_width` is the most columns of the command shown to
+    /// the approver and logged, as a terminal displays it: wide
+    /// characters (e.g., CJK) take up two columns, and combining
+    /// characters (e.g., accents) none. Beyond it, the command is cut
+    /// short the same way. `0` doesn't limit the width.
+    ///
+    /// Default: `0`
+    pub max_command_width: u32,
+
+    /// `highlight_env` controls whether the approver is shown the
+    /// variables in the command's environment that can change what it
+    /// does without changing the command itself: those matching
+    /// `risky_env`, and entries in `PATH` that are relative or
+    /// world-writable.
+    ///
+    /// Default: `true`
+    pub highlight_env: bool,
+
+    /// `risky_env` is a comma-separated list of the environment
+    /// variables to highlight to the approver. A name ending in `*`
+    /// matches every variable beginning with the rest of it.
+    ///
+    /// Default: `LD_*,DYLD_*,BASH_ENV,ENV,IFS,SHELLOPTS,PS4,PERL5LIB,PERL5OPT,PYTHONPATH,PYTHONSTARTUP,RUBYLIB,RUBYOPT,NODE_OPTIONS`
+    pub risky_env: Vec<EnvPattern>,
+
+    /// `recording_sink` is where each session's output is recorded.
+    /// `none` disables recording, `file` writes a recording for each
+    /// session into `recording_dir`, `command` pipes it to the standard
+    /// input of `recording_command`, `socket` streams it to the UNIX
+    /// socket at `recording_socket`, and `http` uploads it to
+    /// `recording_url` once the session ends (which requires the `http`
+    /// feature). Sessions are recorded whether or not they require a
+    /// pair, and a session whose recording can't be started or written
+    /// to is ended.
+    ///
+    /// Default: `none`
+    pub recording_sink: RecordingSinkKind,
+
+    /// `recording_dir` is the directory recordings are written to when
+    /// `recording_sink` is `file`, and where they're kept until they're
+    /// uploaded (or if they can't be) when it's `http`. It's created
+    /// (readable only by root) if it doesn't exist.
+    ///
+    /// Default: `"/var/log/sudo_pair"`
+    pub recording_dir: PathBuf,
+
+    /// `recording_layout` is how recordings (and their manifests) are
+    /// named. `session_id` names them after the session's id, while
+    /// `iolog` names them after the path of sudo's own I/O log of the
+    /// session relative to `sudo_iolog_dir` (e.g., `00/00/01.rec` in
+    /// `recording_dir` for sudo's `/var/log/sudo-io/00/00/01`), so the
+    /// two can be found from one another. Sessions sudo isn't logging
+    /// under `sudo_iolog_dir` are named after their id regardless.
+    ///
+    /// Default: `session_id`
+    pub recording_layout: RecordingLayout,
+
+    /// `sudo_iolog_dir` is the directory sudo writes its own I/O logs
+    /// into (its `iolog_dir`), for the `iolog` `recording_layout`.
+    ///
+    /// Default: `"/var/log/sudo-io"`
+    pub sudo_iolog_dir: PathBuf,
+
+    /// `recording_owner` is the user (name or uid) that owns
+    /// recordings and their manifests, e.g., so that something other
+    /// than root can collect them.
+    ///
+    /// Default: none (root)
+    pub recording_owner: Option<User>,
+
+    /// `recording_group` is the group (name or gid) that owns
+    /// recordings and their manifests.
+    ///
+    /// Default: none (root's primary group)
+    pub recording_group: Option<Group>,
+
+    /// `recording_mode` is the permissions (in octal) given to
+    /// recordings and their manifests. A `recording_dir` that has to be
+    /// created is searchable by whoever this lets read or write them.
+    ///
+    /// Default: `0600`
+    pub recording_mode: Mode,
+
+    /// `recording_umask` is whether the umask `sudo` was run with has
+    /// any say in the permissions of recordings, their manifests, and
+    /// the session index. With `ignore`, they're given `recording_mode`
+    /// exactly, and `recording_command` is run with a umask of `077`.
+    /// With `inherit`, `recording_mode` is further restricted by the
+    /// invoking user's umask, and `recording_command` is run with it.
+    /// Otherwise, it's a umask (in octal, e.g., `027`) used in its
+    /// place.
+    ///
+    /// Default: `ignore`
+    pub recording_umask: UmaskPolicy,
+
+    /// `session_index` is a file that a line describing each recorded
+    /// session (its id, user, command, when it ran, how it ended, and
+    /// where it was recorded) is appended to as it closes, so that a
+    /// session's recording can be found without reading every one
+    /// (e.g., with `sudo_pair_check --find-session`). It's given the
+    /// same owner and permissions as recordings.
+    ///
+    /// Default: none
+    pub session_index: Option<PathBuf>,
+
+    /// `recording_command` is the command recordings are piped to when
+    /// `recording_sink` is `command`. It's run as root with the
+    /// session's id as its only argument, and should exit successfully
+    /// once it has received the whole recording.
+    ///
+    /// Default: none
+    pub recording_command: Option<PathBuf>,
+
+    /// `recording_socket` is the UNIX socket recordings are streamed to
+    /// when `recording_sink` is `socket`. It must be owned by root.
+    ///
+    /// Default: none
+    pub recording_socket: Option<PathBuf>,
+
+    /// `recording_url` is where recordings and their manifests are
+    /// uploaded with `PUT` requests when `recording_sink` is `http`.
+    /// `%f` is replaced by the name of the file being uploaded (e.g.,
+    /// `1600000000-31337.rec` or `1600000000-31337.json`, or with the
+    /// `iolog` `recording_layout`, `00/00/01.rec`), so it must appear
+    /// somewhere in the URL.
+    ///
+    /// Default: none
+    pub recording_url: Option<String>,
+
+    /// `recording_upload_attempts` is how many times each upload is
+    /// attempted before giving up and leaving the files in
+    /// `recording_dir`.
+    ///
+    /// Default: `3`
+    pub recording_upload_attempts: u32,
+
+    /// `recording_upload_timeout` is how long each upload attempt may
+    /// take.
+    ///
+    /// Default: `10s`
+    pub recording_upload_timeout: Duration,
+
+    /// `recording_open_budget` is how long a session waits for a
+    /// `command` or `socket` recording sink to open before
+    /// going ahead without it, queueing its output until the sink is
+    /// ready; a sink that then fails to open is treated as one that
+    /// failed partway through (see `on_record_error`). `0` waits for
+    /// as long as opening takes.
+    ///
+    /// Default: `1s`
+    pub recording_open_budget: Duration,
+
+    /// `recording_close_budget` is how long a session waits, once it's
+    /// over, for a `command`, `socket`, or `http` recording sink to
+    /// receive the rest of the recording (including, for `http`, every
+    /// upload attempt), after which the recording is reported as
+    /// having failed. `0` waits for as long as it takes.
+    ///
+    /// Default: `10s`
+    pub recording_close_budget: Duration,
+
+    /// `recording_queue_limit` is the most output that's queued for a
+    /// `command` or `socket` recording sink that's opening, or slow to
+    /// read what it's sent, before the sink is treated as having
+    /// failed (see `on_record_error`). `0` queues without limit.
+    ///
+    /// Default: `1MiB`
+    pub recording_queue_limit: ByteSize,
+
+    /// `on_record_error` is what's done when a recording can't be
+    /// written to partway through a session (e.g., its disk is full):
+    /// `terminate` ends the session, `continue` lets it carry on
+    /// unrecorded, and `degrade-to-syslog` logs the rest of its output
+    /// to syslog instead. Either way, the approver is told. A
+    /// recording that can't be started always ends the session.
+    ///
+    /// Default: `terminate`
+    pub on_record_error: RecordErrorPolicy,
+
+    /// `recording_flush_interval` is how long recorded output may be
+    /// held in memory before it's written to the recording sink, so
+    /// bursts of output are written together rather than a frame at a
+    /// time. Held output is written along with the first output after
+    /// the interval has passed, and when the session ends. `0` writes
+    /// each frame as it's recorded.
+    ///
+    /// Default: `0`
+    pub recording_flush_interval: Duration,
+
+    /// `recording_fsync` is when recordings written locally are synced
+    /// to disk while the session runs: `never` leaves it to the kernel,
+    /// `interval` syncs whenever held output is written (see
+    /// `recording_flush_interval`), and `every-chunk` writes and syncs
+    /// each frame as it's recorded. Recordings are always synced once
+    /// they're complete.
+    ///
+    /// Default: `never`
+    pub recording_fsync: FsyncPolicy,
+
+    /// `sudoedit_diff_limit` is the most bytes of a file edited with
+    /// `sudoedit` that's diffed, and of the diff recorded in the
+    /// session's manifest. Edited files are always hashed before and
+    /// after they're edited; this additionally records what changed,
+    /// so be wary of enabling it where files holding secrets (like
+    /// `/etc/shadow`) can be edited, since their contents will end up
+    /// in recordings. `0` disables diffs.
+    ///
+    /// Default: `0`
+    pub sudoedit_diff_limit: u32,
+
+    /// `record_multiplexer` records which terminal multiplexer session
+    /// (e.g., tmux's or screen's) a session was run in, if it was run in
+    /// one, in the session's manifest. Sessions run in a multiplexer can
+    /// be detached and outlive their approver's attention, and this
+    /// lets them be traced back to the multiplexer they were left in.
+    /// Multiplexers are always pointed out to approvers, and can be
+    /// matched by `tty` pair rules, whether or not this is enabled.
+    ///
+    /// Default: `false`
+    pub record_multiplexer: bool,
+
+    /// `monitoring_notice` shows users, as their session starts, what
+    /// of it is captured and where it goes: whether an approver is
+    /// watching, where it's recorded, and what sudo itself logs. The
+    /// list is generated from how the session was actually opened, and
+    /// is shown with the template at `monitoring_notice_path`.
+    ///
+    /// Default: `false`
+    pub monitoring_notice: bool,
+
+    /// `monitoring_notice_path` is the location of the template the
+    /// monitoring notice is shown with, in which `%M` expands to the
+    /// list of what's captured; if no template is found at this
+    /// location, a minimal default will be printed.
+    ///
+    /// Default: `"/etc/sudo_pair.notice"`
+    pub monitoring_notice_path: PathBuf,
+
+    /// `close_hook` is a command run when each session ends, with
+    /// details of how it ended (the command's exit status or signal,
+    /// how long it ran, and how much output it wrote) in its
+    /// environment. It's run as root, whether or not the session
+    /// required a pair.
+    ///
+    /// Default: none
+    pub close_hook: Option<PathBuf>,
+
+    /// `close_hook_timeout` is how long `close_hook` may run before
+    /// it's killed, since `sudo` can't exit until it's done.
+    ///
+    /// Default: `5s`
+    pub close_hook_timeout: Duration,
+
+    /// `approval_timeout` is how long a pair has to connect and approve
+    /// the session before it's declined. While waiting, the time left
+    /// is counted down on the user's TTY and sent to the approval
+    /// client in its `hello`. `0` waits indefinitely.
+    ///
+    /// Default: `0`
+    pub approval_timeout: Duration,
+
+    /// `preview_timeout` is how long each approver who connects has to
+    /// answer the session's preview (and prompt). An approver who
+    /// doesn't is disconnected, and the session waits for another,
+    /// rather than being held by someone who's walked away. It doesn't
+    /// extend `approval_timeout`. `0` gives approvers as long as the
+    /// session waits.
+    ///
+    /// Default: `0`
+    pub preview_timeout: Duration,
+
+    /// `approver_width` is what's done when an approver's terminal is
+    /// narrower than the user's, so the session's output would wrap on
+    /// their screen: `ignore` does nothing, `warn` warns them (and logs
+    /// it) once they've approved, and `require` turns their approval
+    /// away and disconnects them so someone with a wide enough
+    /// terminal can approve instead. Under `require`, approvers whose
+    /// clients don't report their terminal's size can't approve.
+    ///
+    /// Default: `ignore`
+    pub approver_width: WidthPolicy,
+
+    /// `wait_hints` is a file of hints shown to the user in place of
+    /// the countdown as they wait for a pair, each once they've waited
+    /// a given time (e.g., who to ask after 30 seconds, and that the
+    /// session's about to be cancelled after two minutes). If the file
+    /// can't be read or parsed, the countdown is shown as usual.
+    ///
+    /// Default: none
+    pub wait_hints: Option<PathBuf>,
+
+    /// `soft_max_session_duration` is how long a paired session may run
+    /// once it's approved. It's a soft limit: the plugin can only end a
+    /// session when sudo calls it, so the session is ended by the first
+    /// output, keystroke, or resize after the limit passes, and a
+    /// command doing none of those runs on past it. Both the user and
+    /// the approver are told why it ended. `0` doesn't limit sessions.
+    ///
+    /// Default: `0`
+    pub soft_max_session_duration: Duration,
+
+    /// `reapproval_interval` is how often the approver of a paired
+    /// session is asked to re-approve it, counted from when they last
+    /// did. The session ends if they decline, or don't answer within
+    /// `reapproval_grace`. `0` never asks.
+    ///
+    /// Default: `0`
+    pub reapproval_interval: Duration,
+
+    /// `reapproval_grace` is how long the approver has to answer a
+    /// request to re-approve the session.
+    ///
+    /// Default: `1m`
+    pub reapproval_grace: Duration,
+
+    /// `transfer_timeout` is how long a paired session can take to be
+    /// handed off to another approver (e.g., at a shift change). When
+    /// it's set, the session's socket is reopened once it's approved,
+    /// and anyone who can connect to it may offer to take over. The
+    /// handoff happens only if both the current approver and the new
+    /// one approve it within the timeout; otherwise the session carries
+    /// on with the current approver. `0` doesn't allow handoffs.
+    ///
+    /// Default: `0`
+    pub transfer_timeout: Duration,
+
+    /// `chat` is whether the approver of a paired session can send the
+    /// user short messages while it runs, if their client supports it.
+    /// Each is shown on a line of its own on the user's terminal (or
+    /// through the next of `prompt_delivery` that reaches them) the next
+    /// time the command writes output or the user types. An approver
+    /// can also ask the user to reply, in which case the user is
+    /// prompted for one, and has a minute to give it before the command
+    /// carries on.
+    ///
+    /// Default: `true`
+    pub chat: bool,
+
+    /// `preapproval_key` is a file holding the key pre-approval tokens
+    /// are signed with. When it's set, a session with a valid token for
+    /// its exact command doesn't need a live pair. The file must be
+    /// owned by root and unreadable by anyone else, since anyone who
+    /// can read it can approve their own commands.
+    ///
+    /// Default: none
+    pub preapproval_key: Option<PathBuf>,
+
+    /// `preapproval_source` is where a pre-approval token is looked
+    /// for: `env` reads it from the `SUDO_PAIR_TOKEN` environment
+    /// variable (which sudoers must be configured to keep), and
+    /// `prompt` asks the user for one.
+    ///
+    /// Default: `env`
+    pub preapproval_source: InputSource,
+
+    /// `preapproval_max_window` is the longest a pre-approval token may
+    /// be valid for. Tokens can be used any number of times within
+    /// their window, so this bounds how long a leaked one is useful.
+    ///
+    /// Default: `1h`
+    pub preapproval_max_window: Duration,
+
+    /// `maintenance_window` is a JSON file describing a planned
+    /// maintenance window (its `id`, `start`, `end`, and optionally a
+    /// `reason`). While the window is open, sessions that are recorded
+    /// (by the plugin or by sudo) proceed without a pair, and every
+    /// session is logged with the window's ID; sessions that aren't
+    /// recorded still need a pair. The file may be missing outside of
+    /// windows, but must be owned by root and writable by nobody else,
+    /// since anyone who can write it can skip pairing.
+    ///
+    /// Default: none
+    pub maintenance_window: Option<PathBuf>,
+
+    /// `disable_file` is a file whose presence disables pairing, as an
+    /// escape hatch when approvers (or the recording sink) can't be
+    /// reached, without editing sudo.conf. Its first line, if any, is
+    /// logged as the reason. While it exists, every session proceeds
+    /// as `disable_mode` says, logged as a critical error. The file,
+    /// and the directory it's in, must be owned by root and writable by
+    /// nobody else, and it can't be a symlink; one that isn't is
+    /// ignored.
+    ///
+    /// Default: none
+    pub disable_file: Option<PathBuf>,
+
+    /// `disable_mode` is what sessions do while `disable_file` exists.
+    /// `record-only` records them rather than pairing them (sessions
+    /// that can't be recorded are still paired), and `bypass` neither
+    /// pairs nor records them.
+    ///
+    /// Default: `record-only`
+    pub disable_mode: DisableMode,
+
+    /// `ticket_pattern` is a regular expression the ticket (or change
+    /// ID) a user cites for a paired session has to match in its
+    /// entirety. When it's set, sessions that need a pair are refused
+    /// before anyone is asked to approve them unless the user provides
+    /// a matching ticket, which is shown to the approver and recorded
+    /// with the session.
+    ///
+    /// Default: none
+    pub ticket_pattern: Option<TicketPattern>,
+
+    /// `ticket_source` is where a ticket is looked for: `env` reads it
+    /// from the `SUDO_PAIR_TICKET` environment variable (which sudoers
+    /// must be configured to keep), and `prompt` asks the user for one.
+    ///
+    /// Default: `env`
+    pub ticket_source: InputSource,
+
+    /// `ticket_url` is where tickets are confirmed with an issue
+    /// tracker, with `%t` replaced by the ticket. A ticket is only
+    /// accepted if a `GET` of the URL succeeds, so the tracker (or a
+    /// service in front of it) should only answer successfully for
+    /// tickets that are open or approved. Requires the `http` feature.
+    ///
+    /// Default: none
+    pub ticket_url: Option<String>,
+
+    /// `ticket_timeout` is how long the issue tracker has to confirm a
+    /// ticket before it's rejected.
+    ///
+    /// Default: `5s`
+    pub ticket_timeout: Duration,
+
+    /// `correlation_pattern` is a regular expression that takes the
+    /// correlation ID grouping the sessions of a change across hosts
+    /// from the session's ticket, when the user didn't provide one in
+    /// the `SUDO_PAIR_CORRELATION_ID` environment variable (which
+    /// sudoers must be configured to keep). The first group it captures
+    /// is taken if it has one, or otherwise all it matched (e.g.,
+    /// `^(CHG-[0-9]+)` takes `CHG-42` from the ticket `CHG-42/db01`).
+    /// Correlation IDs are logged with every event of the session, and
+    /// recorded in its manifest and `session_index` entry.
+    ///
+    /// Default: none
+    pub correlation_pattern: Option<CorrelationPattern>,
+
+    /// `approval_url` is a remote approval service that sessions are
+    /// approved through, from a web page, instead of by an approver
+    /// connecting to the session's socket. `%t` is replaced by a
+    /// one-time token generated for each session, and the resulting
+    /// URL is printed to the user to pass on to an approver. The
+    /// session is registered with a `PUT` of the URL, and approved (or
+    /// declined) once a `GET` of it says so. Sessions approved this way
+    /// aren't watched live. Requires the `http` feature.
+    ///
+    /// Default: none
+    pub approval_url: Option<String>,
+
+    /// `approval_url_ttl` is how long the URL printed for a session is
+    /// valid for, after which the session is declined. The session is
+    /// declined sooner if `approval_timeout` is shorter.
+    ///
+    /// Default: `10m`
+    pub approval_url_ttl: Duration,
+
+    /// `approval_url_poll` is how often the approval service is asked
+    /// whether a session has been approved yet.
+    ///
+    /// Default: `2s`
+    pub approval_url_poll: Duration,
+
+    /// `debug_capture_dir` is a directory that a copy of everything
+    /// sudo provides the plugin (its settings, the user's info, the
+    /// command's info and environment, and the plugin's options) is
+    /// written to for each session, so that parsing bugs seen in
+    /// production can be replayed offline with `sudo_pair_replay`. The
+    /// command's arguments and most of its environment are redacted.
+    /// Requires the `debug_capture` feature.
+    ///
+    /// Default: none
+    pub debug_capture_dir: Option<PathBuf>,
+}
+
+/// The action to take for sessions that can't be paired in the usual
+/// way because nobody is able to interact with them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnattendedPolicy {
+    Deny,
+    Pair,
+    Record,
+}
+
+impl FromSudoOption for UnattendedPolicy {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny"   => Ok(UnattendedPolicy::Deny),
+            "pair"   => Ok(UnattendedPolicy::Pair),
+            "record" => Ok(UnattendedPolicy::Record),
+            _        => Err(format!("unknown policy {}", s)),
+        }
+    }
+}
+
+/// What's done when a session can't be paired because the plugin
+/// itself failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InternalErrorPolicy {
+    Deny,
+    AllowWithAudit,
+}
+
+impl InternalErrorPolicy {
+    /// The policy's name, as it's configured.
+    pub fn name(self) -> &'static str {
+        match self {
+            InternalErrorPolicy::Deny           => "deny",
+            InternalErrorPolicy::AllowWithAudit => "allow_with_audit",
+        }
+    }
+}
+
+impl FromSudoOption for InternalErrorPolicy {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deny"             => Ok(InternalErrorPolicy::Deny),
+            "allow_with_audit" => Ok(InternalErrorPolicy::AllowWithAudit),
+            _                  => Err(format!("unknown internal error policy {}", s)),
+        }
+    }
+}
+
+/// What sessions do while the plugin is disabled by its disable file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DisableMode {
+    RecordOnly,
+    Bypass,
+}
+
+impl DisableMode {
+    /// The mode's name, as it's configured.
+    pub fn name(self) -> &'static str {
+        match self {
+            DisableMode::RecordOnly => "record-only",
+            DisableMode::Bypass     => "bypass",
+        }
+    }
+}
+
+impl FromSudoOption for DisableMode {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "record-only" => Ok(DisableMode::RecordOnly),
+            "bypass"      => Ok(DisableMode::Bypass),
+            _             => Err(format!("unknown disable mode {}", s)),
+        }
+    }
+}
+
+/// What's done when an approver's terminal is narrower than the user's.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WidthPolicy {
+    Ignore,
+    Warn,
+    Require,
+}
+
+impl FromSudoOption for WidthPolicy {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore"  => Ok(WidthPolicy::Ignore),
+            "warn"    => Ok(WidthPolicy::Warn),
+            "require" => Ok(WidthPolicy::Require),
+            _         => Err(format!("unknown width policy {}", s)),
+        }
+    }
+}
+
+/// A way of delivering the pairing instructions to the user invoking
+/// `sudo`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeliveryChannel {
+    Tty,
+    Conversation,
+    Stderr,
+}
+
+impl FromSudoOption for DeliveryChannel {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tty"          => Ok(DeliveryChannel::Tty),
+            "conversation" => Ok(DeliveryChannel::Conversation),
+            "stderr"       => Ok(DeliveryChannel::Stderr),
+            _              => Err(format!("unknown prompt delivery channel {}", s)),
+        }
+    }
+}
+
+impl FromSudoOptionList for DeliveryChannel {}
+
+/// How binary output is displayed to the approver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryOutput {
+    Pass,
+    Summarize,
+    Hexdump,
+}
+
+impl FromSudoOption for BinaryOutput {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass"      => Ok(BinaryOutput::Pass),
+            "summarize" => Ok(BinaryOutput::Summarize),
+            "hexdump"   => Ok(BinaryOutput::Hexdump),
+            _           => Err(format!("unknown binary output mode {}", s)),
+        }
+    }
+}
+
+/// Where something the user provides (e.g., a pre-approval token) is
+/// looked for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InputSource {
+    Env,
+    Prompt,
+}
+
+impl FromSudoOption for InputSource {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "env"    => Ok(InputSource::Env),
+            "prompt" => Ok(InputSource::Prompt),
+            _        => Err(format!("unknown input source {}", s)),
+        }
+    }
+}
+
+/// Where session recordings are written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordingSinkKind {
+    None,
+    File,
+    Command,
+    Socket,
+    Http,
+}
+
+impl FromSudoOption for RecordingSinkKind {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none"    => Ok(RecordingSinkKind::None),
+            "file"    => Ok(RecordingSinkKind::File),
+            "command" => Ok(RecordingSinkKind::Command),
+            "socket"  => Ok(RecordingSinkKind::Socket),
+            "http"    => Ok(RecordingSinkKind::Http),
+            _         => Err(format!("unknown recording sink {}", s)),
+        }
+    }
+}
+
+/// How recordings are named.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordingLayout {
+    SessionId,
+    Iolog,
+}
+
+impl FromSudoOption for RecordingLayout {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session_id" => Ok(RecordingLayout::SessionId),
+            "iolog"      => Ok(RecordingLayout::Iolog),
+            _            => Err(format!("unknown recording layout {}", s)),
+        }
+    }
+}
+
+/// What's done when a session's recording can't be written to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordErrorPolicy {
+    Terminate,
+    Continue,
+    Syslog,
+}
+
+impl RecordErrorPolicy {
+    /// The policy's name, as it's configured.
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordErrorPolicy::Terminate => "terminate",
+            RecordErrorPolicy::Continue  => "continue",
+            RecordErrorPolicy::Syslog    => "degrade-to-syslog",
+        }
+    }
+}
+
+impl FromSudoOption for RecordErrorPolicy {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminate"         => Ok(RecordErrorPolicy::Terminate),
+            "continue"          => Ok(RecordErrorPolicy::Continue),
+            "degrade-to-syslog" => Ok(RecordErrorPolicy::Syslog),
+            _                   => Err(format!("unknown recording error policy {}", s)),
+        }
+    }
+}
+
+/// When a session's recording is synced to disk while it runs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FsyncPolicy {
+    Never,
+    Interval,
+    EveryChunk,
+}
+
+impl FsyncPolicy {
+    /// The policy's name, as it's configured.
+    pub fn name(self) -> &'static str {
+        match self {
+            FsyncPolicy::Never      => "never",
+            FsyncPolicy::Interval   => "interval",
+            FsyncPolicy::EveryChunk => "every-chunk",
+        }
+    }
+}
+
+impl FromSudoOption for FsyncPolicy {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never"       => Ok(FsyncPolicy::Never),
+            "interval"    => Ok(FsyncPolicy::Interval),
+            "every-chunk" => Ok(FsyncPolicy::EveryChunk),
+            _             => Err(format!("unknown fsync policy {}", s)),
+        }
+    }
+}
+
+/// A class of terminal escape sequences that may be removed from the
+/// output sent to the approver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum EscapeClass {
+    Title,
+    Clipboard,
+    Osc,
+    Dcs,
+    Apc,
+    Reports,
+    Paste,
+    Protocol,
+}
+
+impl FromSudoOption for EscapeClass {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title"     => Ok(EscapeClass::Title),
+            "clipboard" => Ok(EscapeClass::Clipboard),
+            "osc"       => Ok(EscapeClass::Osc),
+            "dcs"       => Ok(EscapeClass::Dcs),
+            "apc"       => Ok(EscapeClass::Apc),
+            "reports"   => Ok(EscapeClass::Reports),
+            "paste"     => Ok(EscapeClass::Paste),
+            "protocol"  => Ok(EscapeClass::Protocol),
+            _           => Err(format!("unknown escape sequence class {}", s)),
+        }
+    }
+}
+
+impl FromSudoOptionList for EscapeClass {
+    fn from_sudo_option_list(s: &str) -> Vec<Cow<'_, str>> {
+        if s == "none" {
+            return Vec::new();
+        }
+
+        split_list(s, Self::SEPARATOR)
+    }
+}
+
+/// A group in `gids_enforced` or `gids_exempted`, given either by its
+/// gid or by a name to be resolved when the plugin is opened.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Group {
+    Id(gid_t),
+    Name(String),
+}
+
+impl FromSudoOption for Group {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty group".into());
+        }
+
+        Ok(s.parse().map_or_else(|_| Group::Name(s.into()), Group::Id))
+    }
+}
+
+impl FromSudoOptionList for Group {}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Group::Id(gid)    => gid.fmt(f),
+            Group::Name(name) => name.fmt(f),
+        }
+    }
+}
+
+/// The owner of sockets or recordings, given either by its uid or by a
+/// name to be resolved when the plugin is opened.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum User {
+    Id(uid_t),
+    Name(String),
+}
+
+impl FromSudoOption for User {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty user".into());
+        }
+
+        Ok(s.parse().map_or_else(|_| User::Name(s.into()), User::Id))
+    }
+}
+
+impl FromSudoOptionList for User {}
+
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            User::Id(uid)    => uid.fmt(f),
+            User::Name(name) => name.fmt(f),
+        }
+    }
+}
+
+/// Permissions for the files and sockets the plugin creates, given in
+/// octal (e.g., `0640`). Only the permission bits may be set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Mode(pub mode_t);
+
+impl FromSudoOption for Mode {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        mode_t::from_str_radix(s, 8).ok()
+            .filter(|mode| mode & !0o777 == 0)
+            .map(Mode)
+            .ok_or_else(|| format!("{} isn't a mode between 0 and 0777", s))
+    }
+}
+
+/// Whether the umask `sudo` was run with restricts what the plugin
+/// creates, or another in its place.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UmaskPolicy {
+    Ignore,
+    Inherit,
+    Fixed(Mode),
+}
+
+impl FromSudoOption for UmaskPolicy {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore"  => Ok(UmaskPolicy::Ignore),
+            "inherit" => Ok(UmaskPolicy::Inherit),
+            _         => Mode::from_sudo_option(s)
+                .map(UmaskPolicy::Fixed)
+                .map_err(|_| format!("{} isn't ignore, inherit, or a umask between 0 and 0777", s)),
+        }
+    }
+}
+
+/// Where user and group names are resolved from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IdentitySource {
+    Nss,
+    Files,
+    Static,
+}
+
+impl FromSudoOption for IdentitySource {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nss"    => Ok(IdentitySource::Nss),
+            "files"  => Ok(IdentitySource::Files),
+            "static" => Ok(IdentitySource::Static),
+            _        => Err(format!("unknown identity source {}", s)),
+        }
+    }
+}
+
+/// An `id:name` pair from `identity_users` or `identity_groups`.
+#[derive(Clone, Debug)]
+struct IdName(u32, String);
+
+impl FromSudoOption for IdName {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+
+        let id   = parts.next().and_then(|id| id.parse().ok());
+        let name = parts.next().filter(|name| !name.is_empty());
+
+        match (id, name) {
+            (Some(id), Some(name)) => Ok(IdName(id, name.into())),
+            _                      => Err(format!("expected id:name, got {}", s)),
+        }
+    }
+}
+
+impl FromSudoOptionList for IdName {}
+
+/// A `key=value` tag from `host_tags`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostTag {
+    pub key:   String,
+    pub value: String,
+}
+
+impl FromSudoOption for HostTag {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+
+        let key = parts.next().filter(|key| !key.is_empty() && key.bytes().all(|b|
+            b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_'
+        ));
+
+        let value = parts.next().filter(|value| !value.is_empty() && value.bytes().all(|b|
+            b.is_ascii_alphanumeric() || b"._-:".contains(&b)
+        ));
+
+        match (key, value) {
+            (Some(key), Some(value)) => Ok(Self { key: key.into(), value: value.into() }),
+            _                        => Err(format!("expected key=value, got {}", s)),
+        }
+    }
+}
+
+impl FromSudoOptionList for HostTag {}
+
+impl fmt::Display for HostTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+impl PluginOptions {
+    /// Configures how `plugin` resolves user and group names.
+    pub fn apply_identity_source(&self, plugin: &Plugin) {
+        let timeout = self.identity_timeout;
+
+        match self.identity_source {
+            IdentitySource::Nss => plugin.set_identity_resolver(
+                TimeoutResolver::new(NssResolver, timeout)
+            ),
+
+            IdentitySource::Files => plugin.set_identity_resolver(
+                TimeoutResolver::new(FileResolver::default(), timeout)
+            ),
+
+            // nothing to wait on when every name is already in memory
+            IdentitySource::Static => plugin.set_identity_resolver(
+                StaticResolver::new(
+                    self.identity_users.clone(),
+                    self.identity_groups.clone(),
+                )
+            ),
+        }
+    }
+
+    pub fn binary_name(&self) -> &[u8] {
+        self.binary_path.file_name().unwrap_or_else(||
+            self.binary_path.as_os_str()
+        ).as_bytes()
+    }
+
+    /// Checks for problems that only become apparent when looking at
+    /// the options as a whole, rather than at any one individual value.
+    fn validate(&self, problems: &mut Vec<String>) {
+        let paths = [
+            ("binary_path",            &self.binary_path),
+            ("user_prompt_path",       &self.user_prompt_path),
+            ("pair_prompt_path",       &self.pair_prompt_path),
+            ("monitoring_notice_path", &self.monitoring_notice_path),
+            ("socket_dir",             &self.socket_dir),
+            ("recording_dir",          &self.recording_dir),
+            ("sudo_iolog_dir",         &self.sudo_iolog_dir),
+        ];
+
+        // the control socket may instead be named in Linux's abstract
+        // namespace
+        let control_socket = self.control_socket.clone()
+            .filter(|path| !path.as_os_str().as_bytes().starts_with(b"@"));
+
+        let optional_paths = [
+            ("recording_command",  &self.recording_command),
+            ("recording_socket",   &self.recording_socket),
+            ("close_hook",         &self.close_hook),
+            ("preapproval_key",    &self.preapproval_key),
+            ("pair_rules",         &self.pair_rules),
+            ("maintenance_window", &self.maintenance_window),
+            ("disable_file",       &self.disable_file),
+            ("wait_hints",         &self.wait_hints),
+            ("debug_capture_dir",  &self.debug_capture_dir),
+            ("control_socket",     &control_socket),
+            ("session_index",      &self.session_index),
+        ];
+
+        let provided = optional_paths.iter()
+            .filter_map(|(key, path)| path.as_ref().map(|path| (*key, path)));
+
+        for (key, path) in paths.iter().cloned().chain(provided) {
+            if !path.is_absolute() {
+                problems.push(format!(
+                    "{} must be an absolute path, got {}",
+                    key,
+                    path.to_string_lossy(),
+                ));
+            }
+        }
+
+        let required = match self.recording_sink {
+            RecordingSinkKind::Command => Some(("recording_command", &self.recording_command)),
+            RecordingSinkKind::Socket  => Some(("recording_socket",  &self.recording_socket)),
+            _                          => None,
+        };
+
+        if let Some((key, None)) = required {
+            problems.push(format!("{} is required by recording_sink", key));
+        }
+
+        if self.recording_sink == RecordingSinkKind::Http {
+            if !cfg!(feature = "http") {
+                problems.push("recording_sink=http requires the http feature".into());
+            }
+
+            match self.recording_url.as_ref() {
+                None => problems.push(
+                    "recording_url is required by recording_sink".into()
+                ),
+
+                Some(url) if !url.starts_with("https://") && !url.starts_with("http://") => problems.push(
+                    format!("recording_url must be an http or https URL, got {}", url)
+                ),
+
+                Some(url) if !url.contains("%f") => problems.push(
+                    format!("recording_url must include %f, got {}", url)
+                ),
+
+                Some(_) => {},
+            }
+        }
+
+        if let Some(url) = self.ticket_url.as_ref() {
+            if !cfg!(feature = "http") {
+                problems.push("ticket_url requires the http feature".into());
+            }
+
+            if self.ticket_pattern.is_none() {
+                problems.push("ticket_url requires ticket_pattern".into());
+            }
+
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                problems.push(format!("ticket_url must be an http or https URL, got {}", url));
+            } else if !url.contains("%t") {
+                problems.push(format!("ticket_url must include %t, got {}", url));
+            }
+        }
+
+        // correlation IDs are only ever taken from tickets users had to
+        // cite
+        if self.correlation_pattern.is_some() && self.ticket_pattern.is_none() {
+            problems.push("correlation_pattern requires ticket_pattern".into());
+        }
+
+        if let Some(url) = self.approval_url.as_ref() {
+            if !cfg!(feature = "http") {
+                problems.push("approval_url requires the http feature".into());
+            }
+
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                problems.push(format!("approval_url must be an http or https URL, got {}", url));
+            } else if !url.contains("%t") {
+                problems.push(format!("approval_url must include %t, got {}", url));
+            }
+
+            if self.approval_url_ttl == Duration::from_secs(0) {
+                problems.push("approval_url_ttl must be greater than 0".into());
+            }
+
+            if self.approval_url_poll == Duration::from_secs(0) {
+                problems.push("approval_url_poll must be greater than 0".into());
+            }
+        }
+
+        // a backlog of 0 is taken by some kernels to mean their own
+        // default, and by others to mean one connection
+        if self.socket_backlog == 0 {
+            problems.push("socket_backlog must be at least 1".into());
+        }
+
+        if !self.colocated_approvers.is_empty() && !cfg!(feature = "pam") {
+            problems.push("colocated_approvers requires the pam feature".into());
+        }
+
+        if self.approver_keys.is_some() && !cfg!(feature = "signatures") {
+            problems.push("approver_keys requires the signatures feature".into());
+        }
+
+        if self.debug_capture_dir.is_some() && !cfg!(feature = "debug_capture") {
+            problems.push("debug_capture_dir requires the debug_capture feature".into());
+        }
+
+        if self.approver_account_check {
+            if !cfg!(feature = "pam") {
+                problems.push("approver_account_check requires the pam feature".into());
+            }
+
+            if self.approver_totp_keys.is_none() && self.colocated_approvers.is_empty() {
+                problems.push("approver_account_check requires approver_totp_keys or colocated_approvers".into());
+            }
+        }
+
+        if self.quiet && self.verbose {
+            problems.push("quiet and verbose can't both be enabled".into());
+        }
+
+        // automation accounts have nobody to pair with, so they can't be
+        // left waiting for one
+        let automated = !self.automation_users.is_empty() || !self.automation_groups.is_empty();
+
+        if automated && self.noninteractive_policy == UnattendedPolicy::Pair {
+            problems.push("automation_users and automation_groups require noninteractive_policy=deny or record".into());
+        }
+
+        if self.require_pair_and_recording {
+            if self.recording_sink == RecordingSinkKind::None {
+                problems.push("require_pair_and_recording requires recording_sink".into());
+            }
+
+            if self.on_internal_error != InternalErrorPolicy::Deny {
+                problems.push(format!(
+                    "require_pair_and_recording forbids on_internal_error={}",
+                    self.on_internal_error.name(),
+                ));
+            }
+
+            if self.on_record_error != RecordErrorPolicy::Terminate {
+                problems.push(format!(
+                    "require_pair_and_recording forbids on_record_error={}",
+                    self.on_record_error.name(),
+                ));
+            }
+
+            if self.disable_file.is_some() {
+                problems.push("require_pair_and_recording forbids disable_file".into());
+            }
+        }
+
+        for (i, tag) in self.host_tags.iter().enumerate() {
+            if self.host_tags[.. i].iter().any(|other| other.key == tag.key) {
+                problems.push(format!("host_tags has more than one {} tag", tag.key));
+            }
+        }
+
+        if self.reapproval_grace == Duration::from_secs(0) {
+            problems.push("reapproval_grace must be greater than 0".into());
+        }
+
+        if self.recording_upload_attempts == 0 {
+            problems.push("recording_upload_attempts must be at least 1".into());
+        }
+
+        let has_static_names =
+            !self.identity_users.is_empty() || !self.identity_groups.is_empty();
+
+        if has_static_names && self.identity_source != IdentitySource::Static {
+            problems.push(
+                "identity_users and identity_groups require identity_source=static".into()
+            );
+        }
+
+        if self.in_memory_only {
+            if !cfg!(target_os = "linux") {
+                problems.push("in_memory_only requires Linux's abstract sockets".into());
+            }
+
+            let writers = [
+                (self.recording_sink == RecordingSinkKind::File, "recording_sink=file"),
+                (self.recording_sink == RecordingSinkKind::Http, "recording_sink=http"),
+                (self.max_sessions != 0,                         "max_sessions"),
+                (self.approver_totp_keys.is_some(),              "approver_totp_keys"),
+                (self.debug_capture_dir.is_some(),               "debug_capture_dir"),
+            ];
+
+            for (_, option) in writers.iter().filter(|(writes, _)| *writes) {
+                problems.push(format!("in_memory_only forbids {}, which writes to disk", option));
+            }
+        }
+
+        // the kernel already checks every group of approvers connecting
+        // to sockets on the filesystem
+        if self.resolve_approver_groups && !self.in_memory_only {
+            problems.push("resolve_approver_groups requires in_memory_only".into());
+        }
+    }
+}
+
+// TODO: single_use_lifetimes was committed, but I'm not sure there's
+// actually a way to satisfy the linter for the time being
+#[allow(single_use_lifetimes)]
+impl<'a> TryFrom<&'a OptionMap> for PluginOptions {
+    type Error = Vec<String>;
+
+    fn try_from(map: &'a OptionMap) -> Result<Self, Self::Error> {
+        let mut parser = Parser::new(map);
+
+        let options = Self {
+            binary_path: parser.get("binary_path",
+                DEFAULT_BINARY_PATH.into()),
+
+            user_prompt_path: parser.get("user_prompt_path",
+                DEFAULT_USER_PROMPT_PATH.into()),
+
+            pair_prompt_path: parser.get("pair_prompt_path",
+                DEFAULT_PAIR_PROMPT_PATH.into()),
+
+            quiet: parser.get("quiet",
+                DEFAULT_QUIET),
+
+            verbose: parser.get("verbose",
+                DEFAULT_VERBOSE),
+
+            strict_health: parser.get("strict_health",
+                DEFAULT_STRICT_HEALTH),
+
+            on_internal_error: parser.get("on_internal_error",
+                DEFAULT_ON_INTERNAL_ERROR),
+
+            require_pair_and_recording: parser.get("require_pair_and_recording",
+                DEFAULT_PAIR_AND_RECORDING),
+
+            in_memory_only: parser.get("in_memory_only",
+                DEFAULT_IN_MEMORY_ONLY),
+
+            resolve_approver_groups: parser.get("resolve_approver_groups",
+                DEFAULT_RESOLVE_GROUPS),
+
+            host_tags: parser.get("host_tags",
+                Vec::new()),
+
+            socket_dir: parser.get("socket_dir",
+                DEFAULT_SOCKET_DIR.into()),
+
+            max_sessions: parser.get("max_sessions",
+                DEFAULT_MAX_SESSIONS),
+
+            socket_name: parser.get("socket_name",
+                SocketName::default()),
+
+            socket_owner: parser.get_optional("socket_owner"),
+
+            socket_group: parser.get_optional("socket_group"),
+
+            socket_mode: parser.get_optional("socket_mode"),
+
+            socket_backlog: parser.get("socket_backlog",
+                DEFAULT_SOCKET_BACKLOG),
+
+            max_pending_connections: parser.get("max_pending_connections",
+                DEFAULT_MAX_PENDING),
+
+            control_socket: parser.get_optional("control_socket"),
+
+            approver_uids: parser.get("approver_uids",
+                Vec::new()),
+
+            connection_attempt_limit: parser.get("connection_attempt_limit",
+                DEFAULT_ATTEMPT_LIMIT),
+
+            connection_attempt_window: parser.get("connection_attempt_window",
+                DEFAULT_ATTEMPT_WINDOW),
+
+            connection_ban: parser.get("connection_ban",
+                DEFAULT_CONNECTION_BAN),
+
+            gids_enforced: parser.get("gids_enforced",
+                DEFAULT_GIDS_ENFORCED.iter().cloned().collect()),
+
+            gids_exempted: parser.get("gids_exempted",
+                HashSet::new()),
+
+            networks_enforced: parser.get("networks_enforced",
+                Vec::new()),
+
+            networks_exempted: parser.get("networks_exempted",
+                Vec::new()),
+
+            exempt_sudo_to_self: parser.get("exempt_sudo_to_self",
+                DEFAULT_EXEMPT_SELF),
+
+            pair_rules: parser.get_optional("pair_rules"),
+
+            approver_keys: parser.get_optional("approver_keys"),
+
+            approver_totp_keys: parser.get_optional("approver_totp_keys"),
+
+            colocated_approvers: parser.get("colocated_approvers",
+                Vec::new()),
+
+            approver_account_check: parser.get("approver_account_check",
+                DEFAULT_ACCOUNT_CHECK),
+
+            pam_service: parser.get("pam_service",
+                DEFAULT_PAM_SERVICE.into()),
+
+            noninteractive_policy: parser.get("noninteractive_policy",
+                DEFAULT_UNATTENDED_POLICY),
+
+            automation_users: parser.get("automation_users",
+                Vec::new()),
+
+            automation_groups: parser.get("automation_groups",
+                HashSet::new()),
+
+            background_policy: parser.get("background_policy",
+                DEFAULT_UNATTENDED_POLICY),
+
+            no_tty_policy: parser.get("no_tty_policy",
+                DEFAULT_UNATTENDED_POLICY),
+
+            prompt_delivery: parser.get("prompt_delivery",
+                DEFAULT_PROMPT_DELIVERY.to_vec()),
+
+            identity_source: parser.get("identity_source",
+                DEFAULT_IDENTITY_SOURCE),
+
+            identity_users: parser.get("identity_users", Vec::new())
+                .into_iter().map(|IdName(id, name)| (id, name)).collect(),
+
+            identity_groups: parser.get("identity_groups", Vec::new())
+                .into_iter().map(|IdName(id, name)| (id, name)).collect(),
+
+            identity_timeout: parser.get("identity_timeout",
+                DEFAULT_IDENTITY_TIMEOUT),
+
+            transcript_digest_interval: parser.get("transcript_digest_interval",
+                DEFAULT_DIGEST_INTERVAL),
+
+            utf8_chunking: parser.get("utf8_chunking",
+                DEFAULT_UTF8_CHUNKING),
+
+            binary_output: parser.get("binary_output",
+                DEFAULT_BINARY_OUTPUT),
+
+            sanitize_escapes: parser.get("sanitize_escapes",
+                DEFAULT_SANITIZE_ESCAPES.iter().cloned().collect()),
+
+            output_rate_limit: parser.get("output_rate_limit",
+                DEFAULT_OUTPUT_RATE_LIMIT),
+
+            warn_unsafe_command: parser.get("warn_unsafe_command",
+                DEFAULT_WARN_UNSAFE_CMD),
+
+            warn_shell: parser.get("warn_shell",
+                DEFAULT_WARN_SHELL),
+
+            max_command_args: parser.get("max_command_args",
+                DEFAULT_MAX_COMMAND_ARGS),
+
+            max_command_length: parser.get("max_command_length",
+                DEFAULT_MAX_COMMAND_LEN),
+
+            max_command_width: parser.get("max_command_width",
+                DEFAULT_MAX_COMMAND_WIDTH),
+
+            highlight_env: parser.get("highlight_env",
+                DEFAULT_HIGHLIGHT_ENV),
+
+            risky_env: parser.get("risky_env",
+                DEFAULT_RISKY_ENV.iter().map(|&name| name.into()).collect()),
+
+            recording_sink: parser.get("recording_sink",
+                DEFAULT_RECORDING_SINK),
+
+            recording_dir: parser.get("recording_dir",
+                DEFAULT_RECORDING_DIR.into()),
+
+            recording_layout: parser.get("recording_layout",
+                DEFAULT_RECORDING_LAYOUT),
+
+            sudo_iolog_dir: parser.get("sudo_iolog_dir",
+                DEFAULT_SUDO_IOLOG_DIR.into()),
+
+            recording_owner: parser.get_optional("recording_owner"),
+
+            recording_group: parser.get_optional("recording_group"),
+
+            recording_mode: parser.get("recording_mode",
+                DEFAULT_RECORDING_MODE),
+
+            recording_umask: parser.get("recording_umask",
+                DEFAULT_RECORDING_UMASK),
+
+            session_index: parser.get_optional("session_index"),
+
+            recording_command: parser.get_optional("recording_command"),
+
+            recording_socket: parser.get_optional("recording_socket"),
+
+            recording_url: parser.get_optional("recording_url"),
+
+            recording_upload_attempts: parser.get("recording_upload_attempts",
+                DEFAULT_UPLOAD_ATTEMPTS),
+
+            recording_upload_timeout: parser.get("recording_upload_timeout",
+                DEFAULT_UPLOAD_TIMEOUT),
+
+            recording_open_budget: parser.get("recording_open_budget",
+                DEFAULT_OPEN_BUDGET),
+
+            recording_close_budget: parser.get("recording_close_budget",
+                DEFAULT_CLOSE_BUDGET),
+
+            recording_queue_limit: parser.get("recording_queue_limit",
+                DEFAULT_QUEUE_LIMIT),
+
+            on_record_error: parser.get("on_record_error",
+                DEFAULT_ON_RECORD_ERROR),
+
+            recording_flush_interval: parser.get("recording_flush_interval",
+                DEFAULT_FLUSH_INTERVAL),
+
+            recording_fsync: parser.get("recording_fsync",
+                DEFAULT_RECORDING_FSYNC),
+
+            sudoedit_diff_limit: parser.get("sudoedit_diff_limit",
+                DEFAULT_SUDOEDIT_DIFF),
+
+            record_multiplexer: parser.get("record_multiplexer",
+                DEFAULT_RECORD_MUX),
+
+            monitoring_notice: parser.get("monitoring_notice",
+                DEFAULT_MONITORING_NOTICE),
+
+            monitoring_notice_path: parser.get("monitoring_notice_path",
+                DEFAULT_NOTICE_PATH.into()),
+
+            close_hook: parser.get_optional("close_hook"),
+
+            close_hook_timeout: parser.get("close_hook_timeout",
+                DEFAULT_CLOSE_HOOK_TIMEOUT),
+
+            approval_timeout: parser.get("approval_timeout",
+                DEFAULT_APPROVAL_TIMEOUT),
+
+            preview_timeout: parser.get("preview_timeout",
+                DEFAULT_PREVIEW_TIMEOUT),
+
+            approver_width: parser.get("approver_width",
+                DEFAULT_APPROVER_WIDTH),
+
+            wait_hints: parser.get_optional("wait_hints"),
+
+            soft_max_session_duration: parser.get("soft_max_session_duration",
+                DEFAULT_SOFT_MAX_SESSION),
+
+            reapproval_interval: parser.get("reapproval_interval",
+                DEFAULT_REAPPROVAL),
+
+            reapproval_grace: parser.get("reapproval_grace",
+                DEFAULT_REAPPROVAL_GRACE),
+
+            transfer_timeout: parser.get("transfer_timeout",
+                DEFAULT_TRANSFER_TIMEOUT),
+
+            chat: parser.get("chat",
+                DEFAULT_CHAT),
+
+            preapproval_key: parser.get_optional("preapproval_key"),
+
+            preapproval_source: parser.get("preapproval_source",
+                DEFAULT_PREAPPROVAL_SOURCE),
+
+            preapproval_max_window: parser.get("preapproval_max_window",
+                DEFAULT_PREAPPROVAL_WINDOW),
+
+            maintenance_window: parser.get_optional("maintenance_window"),
+
+            disable_file: parser.get_optional("disable_file"),
+
+            disable_mode: parser.get("disable_mode",
+                DEFAULT_DISABLE_MODE),
+
+            ticket_pattern: parser.get_optional("ticket_pattern"),
+
+            ticket_source: parser.get("ticket_source",
+                DEFAULT_TICKET_SOURCE),
+
+            ticket_url: parser.get_optional("ticket_url"),
+
+            ticket_timeout: parser.get("ticket_timeout",
+                DEFAULT_TICKET_TIMEOUT),
+
+            correlation_pattern: parser.get_optional("correlation_pattern"),
+
+            approval_url: parser.get_optional("approval_url"),
+
+            approval_url_ttl: parser.get("approval_url_ttl",
+                DEFAULT_APPROVAL_URL_TTL),
+
+            approval_url_poll: parser.get("approval_url_poll",
+                DEFAULT_APPROVAL_URL_POLL),
+
+            debug_capture_dir: parser.get_optional("debug_capture_dir"),
+        };
+
+        let mut problems = parser.finish();
+
+        options.validate(&mut problems);
+
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        Ok(options)
+    }
+}
+
+impl slog::Value for PluginOptions {
+    fn serialize(&self, _: &slog::Record<'_>, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_str(key, &format!("{:?}", self))
+    }
+}
+
+/// Retrieves options from an `OptionMap`, keeping track of every key
+/// that's been asked for along with any problems parsing their values,
+/// so that all problems can be reported at once.
+struct Parser<'a> {
+    map:      &'a OptionMap,
+    known:    HashSet<&'static str>,
+    problems: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(map: &'a OptionMap) -> Self {
+        Self {
+            map,
+            known:    HashSet::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    /// Returns the parsed value of `key`, or `default` if it wasn't
+    /// provided. If the value couldn't be parsed, the problem is noted
+    /// and `default` is returned so parsing can continue.
+    fn get<T: FromSudoOption>(&mut self, key: &'static str, default: T) -> T {
+        self.get_optional(key).unwrap_or(default)
+    }
+
+    /// Returns the parsed value of `key`, or `None` if it wasn't
+    /// provided. If the value couldn't be parsed, the problem is noted
+    /// and `None` is returned so parsing can continue.
+    fn get_optional<T: FromSudoOption>(&mut self, key: &'static str) -> Option<T> {
+        let _ = self.known.insert(key);
+
+        let map = self.map;
+        let raw = map.get_bytes(key.as_bytes())?;
+
+        map.get(key).map_err(|_| {
+            self.problems.push(format!(
+                "{} has an invalid value: {}",
+                key,
+                String::from_utf8_lossy(raw),
+            ));
+        }).ok()
+    }
+
+    /// Consumes the parser, returning every problem encountered so far
+    /// along with any keys that were provided but never asked for.
+    fn finish(mut self) -> Vec<String> {
+        // the map's keys come sorted, so the output is stable across
+        // invocations
+        let mut unknown : Vec<_> = self.map.keys()
+            .map(String::from_utf8_lossy)
+            .filter(|key| !self.known.contains(key.as_ref()))
+            .map(|key| format!("unknown option {}", key))
+            .collect();
+
+        self.problems.append(&mut unknown);
+        self.problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    #[test]
+    fn defaults() {
+        let map     = unsafe { OptionMap::from_raw(ptr::null()) };
+        let options = PluginOptions::try_from(&map).unwrap();
+
+        assert_eq!(PathBuf::from(DEFAULT_SOCKET_DIR), options.socket_dir);
+        assert_eq!(DEFAULT_MAX_SESSIONS, options.max_sessions);
+        assert_eq!(SocketName::default(),             options.socket_name);
+        assert_eq!(None, options.socket_owner);
+        assert_eq!(None, options.socket_group);
+        assert_eq!(None, options.socket_mode);
+        assert_eq!(DEFAULT_SOCKET_BACKLOG, options.socket_backlog);
+        assert_eq!(DEFAULT_MAX_PENDING, options.max_pending_connections);
+        assert_eq!(None, options.control_socket);
+        assert!(options.approver_uids.is_empty());
+        assert!(options.automation_users.is_empty());
+        assert!(options.automation_groups.is_empty());
+        assert_eq!(DEFAULT_ATTEMPT_LIMIT, options.connection_attempt_limit);
+        assert_eq!(DEFAULT_ATTEMPT_WINDOW, options.connection_attempt_window);
+        assert_eq!(DEFAULT_CONNECTION_BAN, options.connection_ban);
+        assert_eq!(UnattendedPolicy::Deny,            options.no_tty_policy);
+        assert!(options.exempt_sudo_to_self);
+        assert!(!options.utf8_chunking);
+        assert_eq!(BinaryOutput::Pass, options.binary_output);
+        assert!(options.sanitize_escapes.contains(&EscapeClass::Clipboard));
+        assert!(!options.sanitize_escapes.contains(&EscapeClass::Osc));
+        assert!(options.highlight_env);
+        assert!(options.warn_shell);
+        assert_eq!(ByteSize(0), options.output_rate_limit);
+        assert_eq!(DEFAULT_MAX_COMMAND_ARGS, options.max_command_args);
+        assert_eq!(DEFAULT_MAX_COMMAND_LEN,  options.max_command_length);
+        assert_eq!(DEFAULT_MAX_COMMAND_WIDTH, options.max_command_width);
+        assert!(!options.quiet);
+        assert!(!options.verbose);
+        assert!(!options.strict_health);
+        assert_eq!(InternalErrorPolicy::Deny, options.on_internal_error);
+        assert!(!options.require_pair_and_recording);
+        assert!(!options.in_memory_only);
+        assert!(!options.resolve_approver_groups);
+        assert!(options.host_tags.is_empty());
+        assert_eq!(DEFAULT_PROMPT_DELIVERY.to_vec(), options.prompt_delivery);
+        assert!(options.risky_env.contains(&"LD_*".into()));
+        assert!(options.gids_enforced.contains(&Group::Id(0)));
+        assert!(options.gids_exempted.is_empty());
+        assert_eq!(RecordingSinkKind::None, options.recording_sink);
+        assert_eq!(PathBuf::from(DEFAULT_RECORDING_DIR), options.recording_dir);
+        assert_eq!(RecordingLayout::SessionId, options.recording_layout);
+        assert_eq!(PathBuf::from("/var/log/sudo-io"), options.sudo_iolog_dir);
+        assert_eq!(None, options.recording_owner);
+        assert_eq!(None, options.recording_group);
+        assert_eq!(Mode(0o600), options.recording_mode);
+        assert_eq!(UmaskPolicy::Ignore, options.recording_umask);
+        assert_eq!(None, options.session_index);
+        assert_eq!(None, options.recording_command);
+        assert_eq!(None, options.recording_url);
+        assert_eq!(DEFAULT_UPLOAD_ATTEMPTS, options.recording_upload_attempts);
+        assert_eq!(DEFAULT_UPLOAD_TIMEOUT,  options.recording_upload_timeout);
+        assert_eq!(DEFAULT_OPEN_BUDGET,     options.recording_open_budget);
+        assert_eq!(DEFAULT_CLOSE_BUDGET,    options.recording_close_budget);
+        assert_eq!(ByteSize(1024 * 1024),   options.recording_queue_limit);
+        assert_eq!(RecordErrorPolicy::Terminate, options.on_record_error);
+        assert_eq!(Duration::from_secs(0),  options.recording_flush_interval);
+        assert_eq!(FsyncPolicy::Never,      options.recording_fsync);
+        assert_eq!(DEFAULT_SUDOEDIT_DIFF,   options.sudoedit_diff_limit);
+        assert_eq!(DEFAULT_RECORD_MUX,      options.record_multiplexer);
+        assert_eq!(DEFAULT_MONITORING_NOTICE, options.monitoring_notice);
+        assert_eq!(PathBuf::from(DEFAULT_NOTICE_PATH), options.monitoring_notice_path);
+        assert_eq!(None, options.close_hook);
+        assert_eq!(DEFAULT_CLOSE_HOOK_TIMEOUT, options.close_hook_timeout);
+        assert_eq!(DEFAULT_APPROVAL_TIMEOUT,   options.approval_timeout);
+        assert_eq!(DEFAULT_PREVIEW_TIMEOUT,    options.preview_timeout);
+        assert_eq!(WidthPolicy::Ignore,        options.approver_width);
+        assert_eq!(None,                       options.wait_hints);
+        assert_eq!(DEFAULT_SOFT_MAX_SESSION,   options.soft_max_session_duration);
+        assert_eq!(DEFAULT_REAPPROVAL,         options.reapproval_interval);
+        assert_eq!(DEFAULT_REAPPROVAL_GRACE,   options.reapproval_grace);
+        assert_eq!(DEFAULT_TRANSFER_TIMEOUT,   options.transfer_timeout);
+        assert_eq!(DEFAULT_CHAT,               options.chat);
+        assert_eq!(None, options.preapproval_key);
+        assert_eq!(DEFAULT_PREAPPROVAL_SOURCE, options.preapproval_source);
+        assert_eq!(DEFAULT_PREAPPROVAL_WINDOW, options.preapproval_max_window);
+        assert_eq!(None, options.maintenance_window);
+        assert_eq!(None, options.disable_file);
+        assert_eq!(DisableMode::RecordOnly, options.disable_mode);
+        assert_eq!(None, options.pair_rules);
+        assert_eq!(None, options.approver_keys);
+        assert_eq!(None, options.approver_totp_keys);
+        assert!(options.colocated_approvers.is_empty());
+        assert!(!options.approver_account_check);
+        assert_eq!("sudo", options.pam_service);
+        assert!(options.ticket_pattern.is_none());
+        assert_eq!(DEFAULT_TICKET_SOURCE,  options.ticket_source);
+        assert_eq!(None,                   options.ticket_url);
+        assert_eq!(DEFAULT_TICKET_TIMEOUT, options.ticket_timeout);
+        assert!(options.correlation_pattern.is_none());
+        assert_eq!(None,                   options.approval_url);
+        assert_eq!(Duration::from_secs(600), options.approval_url_ttl);
+        assert_eq!(Duration::from_secs(2),   options.approval_url_poll);
+        assert_eq!(None,                   options.debug_capture_dir);
+    }
+
+    #[test]
+    fn parses_provided_options() {
+        let map = unsafe { OptionMap::from_raw([
+            b"socket_dir=/tmp/pair\0"   .as_ptr() as _,
+            b"max_sessions=32\0"        .as_ptr() as _,
+            b"socket_name=%{uid}.%{session_id}.sock\0".as_ptr() as _,
+            b"socket_group=approvers\0".as_ptr() as _,
+            b"socket_mode=0020\0"      .as_ptr() as _,
+            b"socket_backlog=4\0"      .as_ptr() as _,
+            b"max_pending_connections=16\0".as_ptr() as _,
+            b"control_socket=/var/run/sudo_pair.control\0".as_ptr() as _,
+            b"approver_uids=bob,1001\0".as_ptr() as _,
+            b"connection_attempt_limit=5\0".as_ptr() as _,
+            b"connection_attempt_window=10s\0".as_ptr() as _,
+            b"connection_ban=1h\0"     .as_ptr() as _,
+            b"gids_exempted=42,wheel\0" .as_ptr() as _,
+            b"automation_users=deploy,998\0".as_ptr() as _,
+            b"automation_groups=ci\0"   .as_ptr() as _,
+            b"no_tty_policy=record\0"   .as_ptr() as _,
+            b"exempt_sudo_to_self=false\0".as_ptr() as _,
+            b"utf8_chunking=true\0"    .as_ptr() as _,
+            b"verbose=true\0"          .as_ptr() as _,
+            b"strict_health=true\0"    .as_ptr() as _,
+            b"on_internal_error=allow_with_audit\0".as_ptr() as _,
+            b"binary_output=hexdump\0" .as_ptr() as _,
+            b"sanitize_escapes=none\0" .as_ptr() as _,
+            b"warn_shell=false\0"     .as_ptr() as _,
+            b"output_rate_limit=64KiB\0".as_ptr() as _,
+            b"risky_env=LD_PRELOAD,GIT_*\0".as_ptr() as _,
+            b"max_command_args=16\0"  .as_ptr() as _,
+            b"max_command_length=0\0" .as_ptr() as _,
+            b"max_command_width=80\0" .as_ptr() as _,
+            b"prompt_delivery=conversation,stderr\0".as_ptr() as _,
+            b"recording_sink=socket\0" .as_ptr() as _,
+            b"recording_socket=/run/recorder.sock\0".as_ptr() as _,
+            b"recording_owner=archiver\0".as_ptr() as _,
+            b"recording_group=0\0"      .as_ptr() as _,
+            b"recording_mode=640\0"     .as_ptr() as _,
+            b"recording_umask=027\0"    .as_ptr() as _,
+            b"session_index=/var/log/sudo_pair/index.jsonl\0".as_ptr() as _,
+            b"recording_open_budget=0\0".as_ptr() as _,
+            b"recording_close_budget=30s\0".as_ptr() as _,
+            b"recording_queue_limit=64KiB\0".as_ptr() as _,
+            b"on_record_error=degrade-to-syslog\0".as_ptr() as _,
+            b"recording_flush_interval=5s\0".as_ptr() as _,
+            b"recording_fsync=every-chunk\0".as_ptr() as _,
+            b"sudoedit_diff_limit=65536\0".as_ptr() as _,
+            b"record_multiplexer=true\0".as_ptr() as _,
+            b"monitoring_notice=true\0".as_ptr() as _,
+            b"monitoring_notice_path=/etc/sudo_pair.notice.pci\0".as_ptr() as _,
+            b"close_hook=/usr/local/bin/session-ended\0".as_ptr() as _,
+            b"close_hook_timeout=1s\0".as_ptr() as _,
+            b"approval_timeout=5m\0"  .as_ptr() as _,
+            b"preview_timeout=90s\0"  .as_ptr() as _,
+            b"approver_width=require\0".as_ptr() as _,
+            b"wait_hints=/etc/sudo_pair/hints\0".as_ptr() as _,
+            b"soft_max_session_duration=1h\0".as_ptr() as _,
+            b"reapproval_interval=15m\0".as_ptr() as _,
+            b"reapproval_grace=2m\0"   .as_ptr() as _,
+            b"transfer_timeout=90s\0"  .as_ptr() as _,
+            b"chat=false\0"            .as_ptr() as _,
+            b"preapproval_key=/etc/sudo_pair.key\0".as_ptr() as _,
+            b"preapproval_source=prompt\0".as_ptr() as _,
+            b"pair_rules=/etc/sudo_pair.rules\0".as_ptr() as _,
+            b"maintenance_window=/etc/sudo_pair/maintenance.json\0".as_ptr() as _,
+            b"disable_file=/etc/sudo_pair/disabled\0".as_ptr() as _,
+            b"disable_mode=bypass\0".as_ptr() as _,
+            b"approver_totp_keys=/etc/sudo_pair/approver_totp_keys\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let options = PluginOptions::try_from(&map).unwrap();
+
+        assert_eq!(PathBuf::from("/tmp/pair"), options.socket_dir);
+        assert_eq!(32,                         options.max_sessions);
+        assert_ne!(SocketName::default(),      options.socket_name);
+        assert_eq!(None,                       options.socket_owner);
+        assert_eq!(Some(Group::Name("approvers".into())), options.socket_group);
+        assert_eq!(Some(Mode(0o020)),          options.socket_mode);
+        assert_eq!(4,                          options.socket_backlog);
+        assert_eq!(16,                         options.max_pending_connections);
+        assert_eq!(Some(PathBuf::from("/var/run/sudo_pair.control")), options.control_socket);
+        assert_eq!(vec![User::Name("bob".into()), User::Id(1001)], options.approver_uids);
+        assert_eq!(5,                          options.connection_attempt_limit);
+        assert_eq!(Duration::from_secs(10),    options.connection_attempt_window);
+        assert_eq!(Duration::from_secs(3600),  options.connection_ban);
+        assert_eq!(UnattendedPolicy::Record,   options.no_tty_policy);
+        assert!(!options.exempt_sudo_to_self);
+        assert!(options.utf8_chunking);
+        assert!(options.verbose);
+        assert!(options.strict_health);
+        assert_eq!(InternalErrorPolicy::AllowWithAudit, options.on_internal_error);
+        assert_eq!(BinaryOutput::Hexdump, options.binary_output);
+        assert!(options.sanitize_escapes.is_empty());
+        assert!(!options.warn_shell);
+        assert_eq!(ByteSize(64 * 1024), options.output_rate_limit);
+        assert_eq!(vec![EnvPattern::from("LD_PRELOAD"), "GIT_*".into()], options.risky_env);
+        assert_eq!(16, options.max_command_args);
+        assert_eq!(0,  options.max_command_length);
+        assert_eq!(80, options.max_command_width);
+        assert_eq!(
+            vec![DeliveryChannel::Conversation, DeliveryChannel::Stderr],
+            options.prompt_delivery,
+        );
+        assert!(options.gids_exempted.contains(&Group::Id(42)));
+        assert!(options.gids_exempted.contains(&Group::Name("wheel".into())));
+        assert_eq!(vec![User::Name("deploy".into()), User::Id(998)], options.automation_users);
+        assert!(options.automation_groups.contains(&Group::Name("ci".into())));
+        assert_eq!(RecordingSinkKind::Socket, options.recording_sink);
+        assert_eq!(Some(PathBuf::from("/run/recorder.sock")), options.recording_socket);
+        assert_eq!(Some(User::Name("archiver".into())), options.recording_owner);
+        assert_eq!(Some(Group::Id(0)), options.recording_group);
+        assert_eq!(Mode(0o640),        options.recording_mode);
+        assert_eq!(UmaskPolicy::Fixed(Mode(0o027)), options.recording_umask);
+        assert_eq!(Some(PathBuf::from("/var/log/sudo_pair/index.jsonl")), options.session_index);
+        assert_eq!(Duration::from_secs(0),  options.recording_open_budget);
+        assert_eq!(Duration::from_secs(30), options.recording_close_budget);
+        assert_eq!(ByteSize(64 * 1024),     options.recording_queue_limit);
+        assert_eq!(RecordErrorPolicy::Syslog, options.on_record_error);
+        assert_eq!(Duration::from_secs(5),  options.recording_flush_interval);
+        assert_eq!(FsyncPolicy::EveryChunk, options.recording_fsync);
+        assert_eq!(65536,              options.sudoedit_diff_limit);
+        assert!(options.record_multiplexer);
+        assert!(options.monitoring_notice);
+        assert_eq!(PathBuf::from("/etc/sudo_pair.notice.pci"), options.monitoring_notice_path);
+        assert_eq!(Some(PathBuf::from("/usr/local/bin/session-ended")), options.close_hook);
+        assert_eq!(Duration::from_secs(1),   options.close_hook_timeout);
+        assert_eq!(Duration::from_secs(300), options.approval_timeout);
+        assert_eq!(Duration::from_secs(90),  options.preview_timeout);
+        assert_eq!(WidthPolicy::Require,     options.approver_width);
+        assert_eq!(Some(PathBuf::from("/etc/sudo_pair/hints")), options.wait_hints);
+        assert_eq!(Duration::from_secs(3600), options.soft_max_session_duration);
+        assert_eq!(Duration::from_secs(900),  options.reapproval_interval);
+        assert_eq!(Duration::from_secs(120),  options.reapproval_grace);
+        assert_eq!(Duration::from_secs(90),   options.transfer_timeout);
+        assert!(!options.chat);
+        assert_eq!(Some(PathBuf::from("/etc/sudo_pair.key")), options.preapproval_key);
+        assert_eq!(InputSource::Prompt, options.preapproval_source);
+        assert_eq!(Some(PathBuf::from("/etc/sudo_pair.rules")), options.pair_rules);
+        assert_eq!(Some(PathBuf::from("/etc/sudo_pair/maintenance.json")), options.maintenance_window);
+        assert_eq!(Some(PathBuf::from("/etc/sudo_pair/disabled")), options.disable_file);
+        assert_eq!(DisableMode::Bypass, options.disable_mode);
+        assert_eq!(Some(PathBuf::from("/etc/sudo_pair/approver_totp_keys")), options.approver_totp_keys);
+    }
+
+    #[test]
+    fn requires_recording_destinations() {
+        let map = unsafe { OptionMap::from_raw([
+            b"recording_sink=command\0"          .as_ptr() as _,
+            b"recording_socket=recorder.sock\0"  .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert_eq!(2, problems.len());
+        assert!(problems.contains(&"recording_command is required by recording_sink".into()));
+        assert!(problems.contains(&"recording_socket must be an absolute path, got recorder.sock".into()));
+    }
+
+    #[test]
+    fn validates_recording_uploads() {
+        let map = unsafe { OptionMap::from_raw([
+            b"recording_sink=http\0"                           .as_ptr() as _,
+            b"recording_url=ftp://archive.example.com/logs\0"  .as_ptr() as _,
+            b"recording_upload_attempts=0\0"                   .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert!(problems.contains(&"recording_url must be an http or https URL, got ftp://archive.example.com/logs".into()));
+        assert!(problems.contains(&"recording_upload_attempts must be at least 1".into()));
+        assert_eq!(cfg!(not(feature = "http")), problems.contains(&"recording_sink=http requires the http feature".into()));
+    }
+
+    #[test]
+    fn validates_ticket_verification() {
+        let map = unsafe { OptionMap::from_raw([
+            b"ticket_pattern=(INC|CHG)-[0-9]+\0"                   .as_ptr() as _,
+            b"ticket_source=prompt\0"                             .as_ptr() as _,
+            b"ticket_url=https://tracker.example.com/tickets/%t\0" .as_ptr() as _,
+            b"ticket_timeout=2s\0"                                .as_ptr() as _,
+            b"correlation_pattern=^(CHG-[0-9]+)\0"                .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let result = PluginOptions::try_from(&map);
+
+        if cfg!(feature = "http") {
+            let options = result.unwrap();
+
+            assert!(options.ticket_pattern.unwrap().matches("CHG-42"));
+            assert_eq!(InputSource::Prompt,    options.ticket_source);
+            assert_eq!(Duration::from_secs(2), options.ticket_timeout);
+            assert_eq!(Some("CHG-42".into()),  options.correlation_pattern.unwrap().extract("CHG-42"));
+        } else {
+            assert_eq!(vec![String::from("ticket_url requires the http feature")], result.unwrap_err());
+        }
+
+        let map = unsafe { OptionMap::from_raw([
+            b"ticket_pattern=(INC\0"                             .as_ptr() as _,
+            b"ticket_url=https://tracker.example.com/tickets\0"  .as_ptr() as _,
+            b"correlation_pattern=CHG-[0-9]+\0"                  .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert!(problems.contains(&"ticket_pattern has an invalid value: (INC".into()));
+        assert!(problems.contains(&"ticket_url requires ticket_pattern".into()));
+        assert!(problems.contains(&"correlation_pattern requires ticket_pattern".into()));
+        assert!(problems.contains(&"ticket_url must include %t, got https://tracker.example.com/tickets".into()));
+    }
+
+    #[test]
+    fn validates_approval_urls() {
+        let map = unsafe { OptionMap::from_raw([
+            b"approval_url=https://approve.example.com/s/%t\0" .as_ptr() as _,
+            b"approval_url_ttl=5m\0"                           .as_ptr() as _,
+            b"approval_url_poll=1s\0"                          .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let result = PluginOptions::try_from(&map);
+
+        if cfg!(feature = "http") {
+            let options = result.unwrap();
+
+            assert_eq!(Some("https://approve.example.com/s/%t".into()), options.approval_url);
+            assert_eq!(Duration::from_secs(300), options.approval_url_ttl);
+            assert_eq!(Duration::from_secs(1),   options.approval_url_poll);
+        } else {
+            assert_eq!(vec![String::from("approval_url requires the http feature")], result.unwrap_err());
+        }
+
+        let map = unsafe { OptionMap::from_raw([
+            b"approval_url=https://approve.example.com/s\0" .as_ptr() as _,
+            b"approval_url_ttl=0\0"                         .as_ptr() as _,
+            b"approval_url_poll=0\0"                        .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert!(problems.contains(&"approval_url must include %t, got https://approve.example.com/s".into()));
+        assert!(problems.contains(&"approval_url_ttl must be greater than 0".into()));
+        assert!(problems.contains(&"approval_url_poll must be greater than 0".into()));
+    }
+
+    #[test]
+    fn validates_approver_keys() {
+        let map = unsafe { OptionMap::from_raw([
+            b"approver_keys=/etc/sudo_pair/approver_keys\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let result = PluginOptions::try_from(&map);
+
+        if cfg!(feature = "signatures") {
+            assert_eq!(Some(PathBuf::from("/etc/sudo_pair/approver_keys")), result.unwrap().approver_keys);
+        } else {
+            assert_eq!(vec![String::from("approver_keys requires the signatures feature")], result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn validates_colocated_approval() {
+        let map = unsafe { OptionMap::from_raw([
+            b"colocated_approvers=bob,carol\0" .as_ptr() as _,
+            b"pam_service=sudo_pair\0"         .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let result = PluginOptions::try_from(&map);
+
+        if cfg!(feature = "pam") {
+            let options = result.unwrap();
+
+            assert_eq!(vec![User::Name("bob".into()), User::Name("carol".into())], options.colocated_approvers);
+            assert_eq!("sudo_pair", options.pam_service);
+        } else {
+            assert_eq!(vec![String::from("colocated_approvers requires the pam feature")], result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn validates_pair_and_recording_requirement() {
+        let map = unsafe { OptionMap::from_raw([
+            b"require_pair_and_recording=true\0"     .as_ptr() as _,
+            b"on_internal_error=allow_with_audit\0"  .as_ptr() as _,
+            b"on_record_error=continue\0"            .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(vec![
+            String::from("require_pair_and_recording requires recording_sink"),
+            String::from("require_pair_and_recording forbids on_internal_error=allow_with_audit"),
+            String::from("require_pair_and_recording forbids on_record_error=continue"),
+        ], PluginOptions::try_from(&map).unwrap_err());
+
+        let map = unsafe { OptionMap::from_raw([
+            b"require_pair_and_recording=true\0" .as_ptr() as _,
+            b"recording_sink=file\0"             .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert!(PluginOptions::try_from(&map).unwrap().require_pair_and_recording);
+    }
+
+    #[test]
+    fn validates_socket_backlog() {
+        let map = unsafe { OptionMap::from_raw([
+            b"socket_backlog=0\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(
+            vec![String::from("socket_backlog must be at least 1")],
+            PluginOptions::try_from(&map).unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn validates_control_sockets() {
+        let map = unsafe { OptionMap::from_raw([
+            b"control_socket=approver.sock\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(
+            vec![String::from("control_socket must be an absolute path, got approver.sock")],
+            PluginOptions::try_from(&map).unwrap_err(),
+        );
+
+        // abstract sockets aren't paths at all
+        let map = unsafe { OptionMap::from_raw([
+            b"control_socket=@sudo_pair.control\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert!(PluginOptions::try_from(&map).is_ok());
+    }
+
+    #[test]
+    fn validates_approver_account_check() {
+        let map = unsafe { OptionMap::from_raw([
+            b"approver_account_check=true\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert!(problems.contains(&"approver_account_check requires approver_totp_keys or colocated_approvers".into()));
+        assert_eq!(!cfg!(feature = "pam"), problems.contains(&"approver_account_check requires the pam feature".into()));
+
+        let map = unsafe { OptionMap::from_raw([
+            b"approver_account_check=true\0"             .as_ptr() as _,
+            b"approver_totp_keys=/etc/sudo_pair.totp\0"  .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let result = PluginOptions::try_from(&map);
+
+        if cfg!(feature = "pam") {
+            assert!(result.unwrap().approver_account_check);
+        } else {
+            assert_eq!(vec![String::from("approver_account_check requires the pam feature")], result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn rejects_quiet_and_verbose() {
+        let map = unsafe { OptionMap::from_raw([
+            b"quiet=true\0"  .as_ptr() as _,
+            b"verbose=true\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert_eq!(vec![String::from("quiet and verbose can't both be enabled")], problems);
+    }
+
+    #[test]
+    fn automation_accounts_cant_wait_for_a_pair() {
+        let map = unsafe { OptionMap::from_raw([
+            b"automation_groups=ci\0"      .as_ptr() as _,
+            b"noninteractive_policy=pair\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert_eq!(
+            vec![String::from("automation_users and automation_groups require noninteractive_policy=deny or record")],
+            problems,
+        );
+    }
+
+    #[test]
+    fn in_memory_only_forbids_writing_to_disk() {
+        let map = unsafe { OptionMap::from_raw([
+            b"in_memory_only=true\0"                 .as_ptr() as _,
+            b"recording_sink=socket\0"               .as_ptr() as _,
+            b"recording_socket=/run/recorder.sock\0" .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert!(PluginOptions::try_from(&map).unwrap().in_memory_only);
+
+        let map = unsafe { OptionMap::from_raw([
+            b"in_memory_only=true\0"                 .as_ptr() as _,
+            b"recording_sink=file\0"                 .as_ptr() as _,
+            b"max_sessions=32\0"                     .as_ptr() as _,
+            b"debug_capture_dir=/var/tmp/captures\0" .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+        let mut expected = Vec::new();
+
+        if !cfg!(feature = "debug_capture") {
+            expected.push(String::from("debug_capture_dir requires the debug_capture feature"));
+        }
+
+        expected.extend(vec![
+            String::from("in_memory_only forbids recording_sink=file, which writes to disk"),
+            String::from("in_memory_only forbids max_sessions, which writes to disk"),
+            String::from("in_memory_only forbids debug_capture_dir, which writes to disk"),
+        ]);
+
+        assert_eq!(expected, problems);
+    }
+
+    #[test]
+    fn resolving_approver_groups_requires_in_memory_only() {
+        let map = unsafe { OptionMap::from_raw([
+            b"resolve_approver_groups=true\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(
+            vec![String::from("resolve_approver_groups requires in_memory_only")],
+            PluginOptions::try_from(&map).unwrap_err(),
+        );
+
+        let map = unsafe { OptionMap::from_raw([
+            b"resolve_approver_groups=true\0".as_ptr() as _,
+            b"in_memory_only=true\0"         .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let result = PluginOptions::try_from(&map);
+
+        if cfg!(target_os = "linux") {
+            assert!(result.unwrap().resolve_approver_groups);
+        }
+    }
+
+    #[test]
+    fn validates_reapproval_grace() {
+        let map = unsafe { OptionMap::from_raw([
+            b"reapproval_interval=10m\0".as_ptr() as _,
+            b"reapproval_grace=0\0"     .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert_eq!(vec![String::from("reapproval_grace must be greater than 0")], problems);
+    }
+
+    #[test]
+    fn parses_static_identities() {
+        let map = unsafe { OptionMap::from_raw([
+            b"identity_source=static\0"                 .as_ptr() as _,
+            b"identity_users=0:root,109:postgres\0"     .as_ptr() as _,
+            b"identity_timeout=500ms\0"                 .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let options = PluginOptions::try_from(&map).unwrap();
+
+        assert_eq!(IdentitySource::Static,       options.identity_source);
+        assert_eq!(Some(&"postgres".into()),     options.identity_users.get(&109));
+        assert_eq!(Duration::from_millis(500),   options.identity_timeout);
+        assert!(options.identity_groups.is_empty());
+    }
+
+    #[test]
+    fn rejects_static_identities_without_static_source() {
+        let map = unsafe { OptionMap::from_raw([
+            b"identity_groups=0:root\0"   .as_ptr() as _,
+            b"identity_users=root\0"      .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert_eq!(2, problems.len());
+        assert!(problems.contains(&"identity_users has an invalid value: root".into()));
+        assert!(problems.contains(&"identity_users and identity_groups require identity_source=static".into()));
+    }
+
+    #[test]
+    fn parses_host_tags() {
+        let map = unsafe { OptionMap::from_raw([
+            b"host_tags=datacenter=sfo1,environment=production\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let options = PluginOptions::try_from(&map).unwrap();
+
+        assert_eq!(vec![
+            HostTag { key: "datacenter".into(),  value: "sfo1".into() },
+            HostTag { key: "environment".into(), value: "production".into() },
+        ], options.host_tags);
+
+        assert_eq!("datacenter=sfo1", options.host_tags[0].to_string());
+    }
+
+    #[test]
+    fn rejects_invalid_host_tags() {
+        for tags in &[&b"host_tags=team\0"[..], b"host_tags=Team=ops\0", b"host_tags=team=a;b\0"] {
+            let map = unsafe { OptionMap::from_raw([
+                tags.as_ptr() as _,
+                ptr::null(),
+            ].as_ptr()) };
+
+            assert!(PluginOptions::try_from(&map).is_err());
+        }
+
+        let map = unsafe { OptionMap::from_raw([
+            b"host_tags=team=ops,team=dba\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(
+            vec!["host_tags has more than one team tag".to_string()],
+            PluginOptions::try_from(&map).unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn parses_networks() {
+        let map = unsafe { OptionMap::from_raw([
+            b"networks_enforced=10.20.0.0/16,2001:db8::/32\0" .as_ptr() as _,
+            b"networks_exempted=10.20.99.1\0"                 .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let options = PluginOptions::try_from(&map).unwrap();
+
+        assert_eq!(2, options.networks_enforced.len());
+        assert_eq!(1, options.networks_exempted.len());
+        assert!(options.networks_enforced[0].contains(&"10.20.1.1".parse().unwrap()));
+        assert!(options.networks_exempted[0].contains(&"10.20.99.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let map = unsafe { OptionMap::from_raw([
+            b"socket_dir=relative\0"     .as_ptr() as _,
+            b"gids_exempted=42,\0"       .as_ptr() as _,
+            b"no_tty_policy=maybe\0"     .as_ptr() as _,
+            b"recording_mode=4755\0"    .as_ptr() as _,
+            b"frobnicate\0"              .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let problems = PluginOptions::try_from(&map).unwrap_err();
+
+        assert_eq!(5, problems.len());
+        assert!(problems.contains(&"gids_exempted has an invalid value: 42,".into()));
+        assert!(problems.contains(&"no_tty_policy has an invalid value: maybe".into()));
+        assert!(problems.contains(&"recording_mode has an invalid value: 4755".into()));
+        assert!(problems.contains(&"socket_dir must be an absolute path, got relative".into()));
+        assert!(problems.contains(&"unknown option frobnicate".into()));
+    }
+}
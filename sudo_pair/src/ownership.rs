@@ -0,0 +1,138 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The owner and permissions given to the files the plugin creates.
+//!
+//! Files are created readable only by root, and then given their final
+//! owner and permissions through the open descriptor before anything is
+//! written to them. Nothing is left to the umask `sudo` happened to be
+//! run with (unless `recording_umask` says to restrict the permissions
+//! by it), and the path can't be swapped out in between.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use libc::{gid_t, mode_t, uid_t};
+
+/// Who owns the files the plugin creates, and who else can use them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ownership {
+    /// the owner, or `None` to leave them owned by root
+    pub uid: Option<uid_t>,
+
+    /// the group, or `None` to leave them owned by root's group
+    pub gid: Option<gid_t>,
+
+    pub mode: mode_t,
+
+    /// the umask processes that create them on the plugin's behalf
+    /// (e.g., `recording_command`) are run with
+    pub umask: mode_t,
+}
+
+impl Default for Ownership {
+    /// Readable only by root.
+    fn default() -> Self {
+        Self { uid: None, gid: None, mode: 0o600, umask: 0o077 }
+    }
+}
+
+impl Ownership {
+    /// Gives `file` this owner and these permissions.
+    pub fn apply(&self, file: &File) -> Result<()> {
+        let fd = file.as_raw_fd();
+
+        if self.uid.is_some() || self.gid.is_some() {
+            // fchown accepts an id of -1 (unsigned) to indicate that it
+            // shouldn't be changed
+            let uid = self.uid.unwrap_or(uid_t::MAX);
+            let gid = self.gid.unwrap_or(gid_t::MAX);
+
+            if unsafe { libc::fchown(fd, uid, gid) } == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        // the mode is set after the owner, since changing the owner may
+        // clear bits from it
+        if unsafe { libc::fchmod(fd, self.mode) } == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// The permissions for a directory holding files with this
+    /// ownership, searchable by anyone they let read or write them.
+    pub fn dir_mode(&self) -> mode_t {
+        let mut mode = 0o700;
+
+        if self.mode & 0o060 != 0 {
+            mode |= 0o050;
+        }
+
+        if self.mode & 0o006 != 0 {
+            mode |= 0o005;
+        }
+
+        mode
+    }
+
+    /// Creates `dir` (and any missing parents, readable only by root)
+    /// if it doesn't exist. A newly-created `dir` is given this
+    /// ownership, with its permissions from `dir_mode`; an existing one
+    /// is left as it is.
+    pub fn create_dir(&self, dir: &Path) -> Result<()> {
+        if let Some(parent) = dir.parent() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)?;
+        }
+
+        match fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(())                                             => {},
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => return Ok(()),
+            Err(e)                                             => return Err(e),
+        }
+
+        // never follow a symlink someone else may have planted in its
+        // place
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+            .open(dir)?;
+
+        Self { mode: self.dir_mode(), ..*self }.apply(&file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_directory_modes() {
+        let mode = |mode| Ownership { mode, ..Ownership::default() }.dir_mode();
+
+        assert_eq!(0o700, mode(0o600));
+        assert_eq!(0o750, mode(0o640));
+        assert_eq!(0o750, mode(0o620));
+        assert_eq!(0o755, mode(0o644));
+        assert_eq!(0o705, mode(0o604));
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Authenticates approvers through PAM, so one at the user's own
+//! terminal proves who they are however the host is configured to have
+//! them do so (e.g., a password, or a password and a one-time code),
+//! without the plugin ever checking their secrets itself.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::mem::size_of;
+use std::ptr;
+
+use libc::{c_char, c_int, c_void};
+
+use sudo_plugin::Zeroizing;
+
+const PAM_SUCCESS  : c_int = 0;
+const PAM_BUF_ERR  : c_int = 5;
+const PAM_CONV_ERR : c_int = 19;
+
+const PAM_TTY : c_int = 3;
+
+const PAM_DISALLOW_NULL_AUTHTOK : c_int = 0x0001;
+
+const PAM_PROMPT_ECHO_OFF : c_int = 1;
+const PAM_PROMPT_ECHO_ON  : c_int = 2;
+const PAM_ERROR_MSG       : c_int = 3;
+const PAM_TEXT_INFO       : c_int = 4;
+
+/// The most messages Linux-PAM sends in a single conversation.
+const PAM_MAX_NUM_MSG : usize = 32;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg:       *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp:         *mut c_char,
+    resp_retcode: c_int,
+}
+
+type PamConvFn = extern "C" fn(
+    num_msg:     c_int,
+    msg:         *mut *const PamMessage,
+    resp:        *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int;
+
+#[repr(C)]
+struct PamConv {
+    conv:        PamConvFn,
+    appdata_ptr: *mut c_void,
+}
+
+#[repr(C)]
+struct PamHandle {
+    _private: [u8; 0],
+}
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_start(
+        service_name:     *const c_char,
+        user:             *const c_char,
+        pam_conversation: *const PamConv,
+        pamh:             *mut *mut PamHandle,
+    ) -> c_int;
+
+    fn pam_end(pamh: *mut PamHandle, pam_status: c_int) -> c_int;
+
+    fn pam_set_item(pamh: *mut PamHandle, item_type: c_int, item: *const c_void) -> c_int;
+
+    fn pam_authenticate(pamh: *mut PamHandle, flags: c_int) -> c_int;
+
+    fn pam_acct_mgmt(pamh: *mut PamHandle, flags: c_int) -> c_int;
+
+    fn pam_strerror(pamh: *mut PamHandle, errnum: c_int) -> *const c_char;
+}
+
+/// What PAM is asking of the person being authenticated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Style {
+    /// a prompt whose answer shouldn't be echoed (e.g., a password)
+    PromptEchoOff,
+
+    /// a prompt whose answer can be echoed
+    PromptEchoOn,
+
+    /// an error, which is only shown
+    ErrorMsg,
+
+    /// information, which is only shown
+    TextInfo,
+}
+
+impl Style {
+    fn from_raw(style: c_int) -> Option<Self> {
+        match style {
+            PAM_PROMPT_ECHO_OFF => Some(Style::PromptEchoOff),
+            PAM_PROMPT_ECHO_ON  => Some(Style::PromptEchoOn),
+            PAM_ERROR_MSG       => Some(Style::ErrorMsg),
+            PAM_TEXT_INFO       => Some(Style::TextInfo),
+            _                   => None,
+        }
+    }
+
+    fn is_prompt(self) -> bool {
+        self == Style::PromptEchoOff || self == Style::PromptEchoOn
+    }
+}
+
+/// Carries PAM's messages to the person being authenticated, returning
+/// their answer to prompts (or `None` if they couldn't be asked).
+/// Whatever's returned for messages that are only shown is ignored.
+pub(crate) type Converse<'a> = dyn FnMut(Style, &[u8]) -> Option<Zeroizing<Vec<u8>>> + 'a;
+
+/// Authenticates `user` through the PAM `service`, on `tty` if they're
+/// at one, conversing with them through `converse`. Returns why they
+/// couldn't be authenticated if they couldn't.
+pub(crate) fn authenticate(
+    service:  &str,
+    user:     &str,
+    tty:      Option<&str>,
+    mut converse: &mut Converse<'_>,
+) -> Result<(), String> {
+    // PAM hands this back to `conversation`, which it only calls while
+    // the transaction lasts (and the transaction ends before this does)
+    let appdata : *mut &mut Converse<'_> = &mut converse;
+
+    let mut transaction = Transaction::start(service, user, appdata.cast())?;
+
+    if let Some(tty) = tty {
+        transaction.set_item(PAM_TTY, tty)?;
+    }
+
+    let status = unsafe { pam_authenticate(transaction.pamh, PAM_DISALLOW_NULL_AUTHTOK) };
+
+    transaction.check(status)
+}
+
+/// Runs `user` through the account stage of the PAM `service`, which
+/// checks whether they may use it right now (e.g., that their account
+/// isn't locked or expired, and that it's a time they're allowed in),
+/// without authenticating them. There's nobody to converse with, so a
+/// module that prompts fails the check. Returns why they aren't allowed
+/// if they aren't.
+pub(crate) fn account(service: &str, user: &str) -> Result<(), String> {
+    let mut converse : &mut Converse<'_> = &mut |_, _| None;
+
+    // as in `authenticate`, the transaction ends before this does
+    let appdata : *mut &mut Converse<'_> = &mut converse;
+
+    let mut transaction = Transaction::start(service, user, appdata.cast())?;
+
+    let status = unsafe { pam_acct_mgmt(transaction.pamh, PAM_DISALLOW_NULL_AUTHTOK) };
+
+    transaction.check(status)
+}
+
+/// A PAM transaction, ended when it's dropped.
+struct Transaction {
+    pamh:   *mut PamHandle,
+    status: c_int,
+
+    // PAM may hold onto the conversation for as long as the transaction
+    // lasts, so it's kept where it won't move until it's ended
+    _conv: Box<PamConv>,
+}
+
+impl Transaction {
+    /// Starts a transaction for `user` with the PAM `service`, whose
+    /// conversations are handed `appdata`, a `*mut &mut Converse`.
+    fn start(service: &str, user: &str, appdata: *mut c_void) -> Result<Self, String> {
+        let service = CString::new(service).map_err(|e| e.to_string())?;
+        let user    = CString::new(user)   .map_err(|e| e.to_string())?;
+
+        let conv = Box::new(PamConv {
+            conv:        conversation,
+            appdata_ptr: appdata,
+        });
+
+        let mut pamh = ptr::null_mut();
+        let status   = unsafe { pam_start(service.as_ptr(), user.as_ptr(), &*conv, &mut pamh) };
+
+        if status != PAM_SUCCESS || pamh.is_null() {
+            return Err(format!("PAM couldn't be started ({})", status));
+        }
+
+        Ok(Self { pamh, status, _conv: conv })
+    }
+
+    fn set_item(&mut self, item: c_int, value: &str) -> Result<(), String> {
+        let value  = CString::new(value).map_err(|e| e.to_string())?;
+        let status = unsafe { pam_set_item(self.pamh, item, value.as_ptr().cast()) };
+
+        self.check(status)
+    }
+
+    /// Records `status` as the transaction's latest, converting it into
+    /// PAM's description of it if it's a failure.
+    fn check(&mut self, status: c_int) -> Result<(), String> {
+        self.status = status;
+
+        if status == PAM_SUCCESS {
+            return Ok(());
+        }
+
+        let description = unsafe { pam_strerror(self.pamh, status) };
+
+        if description.is_null() {
+            return Err(format!("PAM error {}", status));
+        }
+
+        Err(unsafe { CStr::from_ptr(description) }.to_string_lossy().into_owned())
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        let _ = unsafe { pam_end(self.pamh, self.status) };
+    }
+}
+
+/// The conversation function PAM is given, which hands each of its
+/// messages to the `Converse` it was started with.
+extern "C" fn conversation(
+    num_msg:     c_int,
+    msg:         *mut *const PamMessage,
+    resp:        *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    let count = match usize::try_from(num_msg) {
+        Ok(count) if count > 0 && count <= PAM_MAX_NUM_MSG => count,
+        _                                                  => return PAM_CONV_ERR,
+    };
+
+    if msg.is_null() || resp.is_null() || appdata_ptr.is_null() {
+        return PAM_CONV_ERR;
+    }
+
+    let converse = unsafe { &mut *appdata_ptr.cast::<&mut Converse<'_>>() };
+
+    // PAM frees the responses (and each answer in them) with `free(3)`
+    let responses : *mut PamResponse = unsafe {
+        libc::calloc(count, size_of::<PamResponse>()).cast()
+    };
+
+    if responses.is_null() {
+        return PAM_BUF_ERR;
+    }
+
+    for i in 0 .. count {
+        // Linux-PAM and OpenPAM pass an array of pointers to messages
+        let message = unsafe { *msg.add(i) };
+
+        let (style, text) = match unsafe { message.as_ref() } {
+            Some(message) => (
+                Style::from_raw(message.msg_style),
+                if message.msg.is_null() { &[][..] } else { unsafe { CStr::from_ptr(message.msg) }.to_bytes() },
+            ),
+
+            None => (None, &[][..]),
+        };
+
+        let style = match style {
+            Some(style) => style,
+            None        => return abandon(responses, count),
+        };
+
+        let answer = converse(style, text);
+
+        if !style.is_prompt() {
+            continue;
+        }
+
+        let answer = match answer.as_ref().and_then(|answer| copy(answer)) {
+            Some(answer) => answer,
+            None         => return abandon(responses, count),
+        };
+
+        unsafe { (*responses.add(i)).resp = answer };
+    }
+
+    unsafe { *resp = responses };
+
+    PAM_SUCCESS
+}
+
+/// Copies `answer` into memory PAM can free, or `None` if it can't be
+/// passed to PAM.
+fn copy(answer: &[u8]) -> Option<*mut c_char> {
+    if answer.contains(&0) {
+        return None;
+    }
+
+    let copy : *mut u8 = unsafe { libc::calloc(answer.len() + 1, 1).cast() };
+
+    if copy.is_null() {
+        return None;
+    }
+
+    unsafe { ptr::copy_nonoverlapping(answer.as_ptr(), copy, answer.len()) };
+
+    Some(copy.cast())
+}
+
+/// Wipes and frees the answers given so far, and the `responses` they
+/// were given in, failing the conversation.
+fn abandon(responses: *mut PamResponse, count: usize) -> c_int {
+    for i in 0 .. count {
+        let answer = unsafe { (*responses.add(i)).resp };
+
+        if answer.is_null() {
+            continue;
+        }
+
+        unsafe {
+            let len = libc::strlen(answer);
+
+            ptr::write_bytes(answer, 0, len);
+            libc::free(answer.cast());
+        }
+    }
+
+    unsafe { libc::free(responses.cast()) };
+
+    PAM_CONV_ERR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `conversation` with `messages`, as PAM would, returning the
+    /// status and any answers it gave.
+    fn converse(messages: &[(c_int, &str)], converse: &mut Converse<'_>) -> (c_int, Vec<Option<Vec<u8>>>) {
+        let texts : Vec<_> = messages.iter()
+            .map(|(_, text)| CString::new(*text).unwrap())
+            .collect();
+
+        let messages : Vec<_> = messages.iter().zip(&texts)
+            .map(|((style, _), text)| PamMessage { msg_style: *style, msg: text.as_ptr() })
+            .collect();
+
+        let mut pointers : Vec<_> = messages.iter()
+            .map(|message| -> *const PamMessage { message })
+            .collect();
+
+        let mut responses = ptr::null_mut();
+        let mut converse  = converse;
+        let appdata : *mut &mut Converse<'_> = &mut converse;
+
+        let status = conversation(
+            pointers.len() as _,
+            pointers.as_mut_ptr(),
+            &mut responses,
+            appdata.cast(),
+        );
+
+        if responses.is_null() {
+            return (status, Vec::new());
+        }
+
+        let answers = (0 .. messages.len()).map(|i| unsafe {
+            let answer = (*responses.add(i)).resp;
+
+            if answer.is_null() {
+                return None;
+            }
+
+            let copy = CStr::from_ptr(answer).to_bytes().to_vec();
+
+            libc::free(answer.cast());
+
+            Some(copy)
+        }).collect();
+
+        unsafe { libc::free(responses.cast()) };
+
+        (status, answers)
+    }
+
+    #[test]
+    fn answers_prompts() {
+        let mut shown = Vec::new();
+
+        let (status, answers) = converse(
+            &[(PAM_TEXT_INFO, "hello"), (PAM_PROMPT_ECHO_OFF, "Password: ")],
+            &mut |style, text| {
+                shown.push((style, text.to_vec()));
+
+                Some(Zeroizing::new(b"hunter2".to_vec()))
+            },
+        );
+
+        assert_eq!(PAM_SUCCESS, status);
+        assert_eq!(vec![None, Some(b"hunter2".to_vec())], answers);
+        assert_eq!(vec![
+            (Style::TextInfo,      b"hello".to_vec()),
+            (Style::PromptEchoOff, b"Password: ".to_vec()),
+        ], shown);
+    }
+
+    #[test]
+    fn fails_unanswered_prompts() {
+        let (status, answers) = converse(
+            &[(PAM_PROMPT_ECHO_ON, "Name: "), (PAM_PROMPT_ECHO_OFF, "Password: ")],
+            &mut |style, _| Some(style)
+                .filter(|style| *style == Style::PromptEchoOn)
+                .map(|_| Zeroizing::new(b"bob".to_vec())),
+        );
+
+        assert_eq!(PAM_CONV_ERR, status);
+        assert!(answers.is_empty());
+
+        let (status, _) = converse(&[(99, "?")], &mut |_, _| None);
+
+        assert_eq!(PAM_CONV_ERR, status);
+    }
+}
@@ -0,0 +1,621 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Whether a session needs a pair, decided from nothing but what sudo
+//! provided the plugin, the plugin's options, and its pair rules.
+//!
+//! The plugin consults this as each session opens, logging why it
+//! decided what it did. Nothing here logs, prints, or touches the disk,
+//! so `sudo_pair_check --decide` runs the same decision over captured
+//! invocations (see `capture`) to show how a configuration would treat
+//! them before it's deployed.
+//!
+//! What can only be known as a session opens is left out: whether the
+//! plugin is disabled or in a maintenance window, pre-approval tokens,
+//! tickets, approvals at the user's terminal or over the web, and
+//! whether the session's recording can actually be started (`decide`
+//! assumes that it can).
+
+use crate::errors::ErrorKind;
+use crate::options::{Group, PluginOptions, RecordingSinkKind, UnattendedPolicy, User};
+use crate::rules::{Coverage, Quorum, Rule, Rules};
+use crate::terminal::Terminal;
+
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+
+use libc::{gid_t, uid_t};
+use sudo_plugin::Plugin;
+
+/// What's decided about a session before anyone's asked to approve it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// the session waits for a quorum of approvers, and is recorded as
+    /// its coverage says
+    Require(Quorum, Coverage),
+
+    /// the session goes ahead without a pair
+    Exempt(Exemption),
+
+    /// the session is refused before anyone's asked to approve it
+    Deny(ErrorKind),
+}
+
+/// Why a session goes ahead without a pair.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exemption {
+    /// sudo isn't running a command
+    NotRunningCommand,
+
+    /// sudo was run by root
+    Root,
+
+    /// the user is sudoing to themselves, and `exempt_sudo_to_self` is
+    /// set
+    SudoToSelf,
+
+    /// the command is the approval command
+    ApprovalCommand,
+
+    /// the policy plugin asked for none of the session's output to be
+    /// logged
+    NotLogged,
+
+    /// the host is on one of `networks_exempted`
+    ExemptNetwork,
+
+    /// the host isn't on any of `networks_enforced`
+    UnenforcedNetwork,
+
+    /// the user is in one of `gids_exempted`
+    ExemptGroup,
+
+    /// the command isn't run as any of `gids_enforced`
+    UnenforcedGroup,
+
+    /// `pair_rules` is configured, and no rule needing approval matched
+    /// the command
+    NoPairRule,
+
+    /// the command matched a `record-only` rule, and is recorded
+    RecordOnly,
+
+    /// nobody can interact with the session, and it's recorded by sudo
+    /// as the unattended policy for the reason allows
+    RecordedBySudo(ErrorKind),
+}
+
+/// Resolves the users and groups named in the plugin's options. The
+/// plugin logs names it can't resolve, so it resolves them itself; a
+/// `Plugin` resolves them through whatever identity source it's using.
+pub trait Names {
+    /// The uid of `user`, or `None` if it can't be resolved.
+    fn uid(&self, user: &User) -> Option<uid_t>;
+
+    /// The gid of `group`, or `None` if it can't be resolved.
+    fn gid(&self, group: &Group) -> Option<gid_t>;
+}
+
+impl Names for Plugin {
+    fn uid(&self, user: &User) -> Option<uid_t> {
+        match *user {
+            User::Id(uid)        => Some(uid),
+            User::Name(ref name) => self.user_id(name),
+        }
+    }
+
+    fn gid(&self, group: &Group) -> Option<gid_t> {
+        match *group {
+            Group::Id(gid)        => Some(gid),
+            Group::Name(ref name) => self.group_id(name),
+        }
+    }
+}
+
+/// Everything a session is decided by.
+#[derive(Clone, Copy)]
+pub struct Invocation<'a> {
+    pub plugin:  &'a Plugin,
+    pub options: &'a PluginOptions,
+
+    /// the labels the policy plugin gave the session
+    pub labels: &'a [String],
+
+    /// the terminal the session was run from, which `tty` pair rules
+    /// match
+    pub terminal: &'a Terminal,
+}
+
+impl fmt::Debug for Invocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Invocation")
+            .field("command",   &self.plugin.command_info.command)
+            .field("labels",    &self.labels)
+            .field("terminal",  &self.terminal)
+            .finish()
+    }
+}
+
+/// Decides what's done with the session `invocation` describes, given
+/// the pair `rules` (if `pair_rules` is configured and they could be
+/// loaded; otherwise, every command needs a single approver, as it
+/// does for the plugin). The plugin makes the same decision a step at
+/// a time, as it logs each one; only `sudo_pair_check` makes it whole.
+pub fn decide(invocation: Invocation<'_>, names: &dyn Names, rules: Option<&Rules>) -> Decision {
+    let plugin  = invocation.plugin;
+    let options = invocation.options;
+
+    let (quorum, coverage) = match rules {
+        Some(rules) => match pair_rule(invocation, rules) {
+            Some(rule) => (rules.quorum(rule.tier()), rule.coverage()),
+            None       => (Quorum { approvers: 0, group: None }, Coverage::Full),
+        },
+
+        None => (Quorum::default(), Coverage::Full),
+    };
+
+    let recorded = coverage != Coverage::PairOnly &&
+        options.recording_sink != RecordingSinkKind::None;
+
+    if let Some(exemption) = exemption(invocation, names, &quorum, coverage, recorded) {
+        return Decision::Exempt(exemption);
+    }
+
+    if options.require_pair_and_recording && !recorded {
+        return Decision::Deny(ErrorKind::RecordingRequired);
+    }
+
+    if is_sudoing_to_user_and_group(plugin) {
+        return Decision::Deny(ErrorKind::SudoToUserAndGroup);
+    }
+
+    let automation = is_automation_account(invocation, names);
+
+    for (policy, reason) in unattended(invocation, automation) {
+        match policy {
+            UnattendedPolicy::Pair => {},
+            UnattendedPolicy::Deny => return Decision::Deny(reason),
+
+            UnattendedPolicy::Record if !is_recorded_by_sudo(plugin) => {
+                return Decision::Deny(ErrorKind::Unrecorded);
+            },
+
+            // sessions let go ahead without a pair are refused where
+            // one is required anyway
+            UnattendedPolicy::Record if options.require_pair_and_recording => {
+                return Decision::Deny(ErrorKind::PairRequired);
+            },
+
+            UnattendedPolicy::Record => return Decision::Exempt(Exemption::RecordedBySudo(reason)),
+        }
+    }
+
+    Decision::Require(quorum, coverage)
+}
+
+/// The pair rule the command matches, if any.
+pub fn pair_rule<'r>(invocation: Invocation<'_>, rules: &'r Rules) -> Option<&'r Rule> {
+    let args : Vec<&[u8]> = invocation.plugin.argv().iter()
+        .skip(1)
+        .map(|arg| arg.as_bytes())
+        .collect();
+
+    let executable = invocation.plugin.executable().as_os_str().as_bytes();
+
+    rules.matching(executable, &args, invocation.labels, &invocation.terminal.names())
+}
+
+/// Why the session goes ahead without a pair, if it does, given the
+/// `quorum` and `coverage` of the pair rule it matched, and whether the
+/// plugin is recording it.
+pub fn exemption(
+    invocation: Invocation<'_>,
+    names:      &dyn Names,
+    quorum:     &Quorum,
+    coverage:   Coverage,
+    recorded:   bool,
+) -> Option<Exemption> {
+    let plugin  = invocation.plugin;
+    let options = invocation.options;
+
+    // there's nothing to pair if sudo isn't running a command; sudo
+    // handles `-l`, `-v`, and `-k` without opening I/O plugins, and
+    // `-V` doesn't get this far, but any other mode that does
+    // shouldn't leave someone waiting on a pair
+    if !plugin.invocation_kind().runs_command() {
+        return Some(Exemption::NotRunningCommand);
+    }
+
+    // root is always exempt
+    if is_sudoing_from_root(plugin) {
+        return Some(Exemption::Root);
+    }
+
+    // a user sudoing entirely to themselves is weird, but I can't see
+    // any reason not to let them do it without approval since they can
+    // already do everything as themselves anyway
+    if is_sudoing_to_themselves(plugin) && options.exempt_sudo_to_self {
+        return Some(Exemption::SudoToSelf);
+    }
+
+    // exempt if the approval command is the command being invoked
+    if plugin.command_info.command == options.binary_path {
+        return Some(Exemption::ApprovalCommand);
+    }
+
+    // policy plugins can inform us that logging is unnecessary
+    if is_exempted_from_logging(plugin) {
+        return Some(Exemption::NotLogged);
+    }
+
+    let addrs = plugin.host_addrs();
+
+    // exempt if this host is on a network that's exempt from having to
+    // pair
+    if options.networks_exempted.iter().any(|network| addrs.iter().any(|addr| network.contains(addr))) {
+        return Some(Exemption::ExemptNetwork);
+    }
+
+    // exempt if this host isn't on any of the networks we enforce
+    // pairing for; with no networks configured, every host is
+    // enforced, and if sudo couldn't tell us our addresses, err on the
+    // side of requiring a pair
+    if !options.networks_enforced.is_empty() && !addrs.is_empty() &&
+        !options.networks_enforced.iter().any(|network| addrs.iter().any(|addr| network.contains(addr)))
+    {
+        return Some(Exemption::UnenforcedNetwork);
+    }
+
+    // exempt if the user who's sudoing is in a group that's exempt from
+    // having to pair; a group that can't be resolved can't exempt
+    // anyone
+    if options.gids_exempted.iter()
+        .filter_map(|group| names.gid(group))
+        .any(|gid| plugin.is_user_in_group(gid))
+    {
+        return Some(Exemption::ExemptGroup);
+    }
+
+    // exempt if none of the gids of the user we're sudoing into are in
+    // the set of gids we enforce pairing for; a group that can't be
+    // resolved might be one the user is sudoing to, so err on the side
+    // of requiring a pair
+    if !options.gids_enforced.iter().any(|group| match names.gid(group) {
+        Some(gid) => plugin.is_runas_in_group(gid),
+        None      => true,
+    }) {
+        return Some(Exemption::UnenforcedGroup);
+    }
+
+    // exempt if pairing is narrowed to commands matching specific
+    // rules, and this command doesn't match any that need approval
+    if quorum.approvers == 0 {
+        return Some(Exemption::NoPairRule);
+    }
+
+    // exempt if the command matched a rule saying it only needs to be
+    // recorded, as long as it is; otherwise it's paired as usual, since
+    // the recording is what stands in for the approver
+    if coverage == Coverage::RecordOnly && (recorded || is_recorded_by_sudo(plugin)) {
+        return Some(Exemption::RecordOnly);
+    }
+
+    None
+}
+
+/// The policies that apply to a session nobody may be able to interact
+/// with, in the order they're applied, each with the reason it applies:
+/// automation accounts' sessions and other non-interactive (`-n`)
+/// invocations, invocations from the background, and invocations
+/// without a TTY.
+pub fn unattended(invocation: Invocation<'_>, automation: bool) -> Vec<(UnattendedPolicy, ErrorKind)> {
+    let plugin  = invocation.plugin;
+    let options = invocation.options;
+
+    let mut policies = Vec::new();
+
+    if automation {
+        policies.push((options.noninteractive_policy, ErrorKind::AutomationAccount));
+    }
+
+    if plugin.settings.noninteractive {
+        policies.push((options.noninteractive_policy, ErrorKind::NonInteractive));
+    }
+
+    if plugin.is_background() {
+        policies.push((options.background_policy, ErrorKind::Background));
+    }
+
+    if plugin.user_info.tty.is_none() {
+        policies.push((options.no_tty_policy, ErrorKind::NoTty));
+    }
+
+    policies
+}
+
+/// Whether the user is one of `automation_users`, or in one of
+/// `automation_groups`. As with exempted groups, names that can't be
+/// resolved don't match anyone.
+pub fn is_automation_account(invocation: Invocation<'_>, names: &dyn Names) -> bool {
+    let plugin  = invocation.plugin;
+    let options = invocation.options;
+
+    options.automation_users.iter()
+        .filter_map(|user| names.uid(user))
+        .any(|uid| uid == plugin.user_info.uid) ||
+    options.automation_groups.iter()
+        .filter_map(|group| names.gid(group))
+        .any(|gid| plugin.is_user_in_group(gid))
+}
+
+pub fn is_sudoing_from_root(plugin: &Plugin) -> bool {
+    // theoretically, root's `uid` should be 0, but it's probably safest
+    // to check whatever user `sudo` is running as since sudo is pretty
+    // much by definition going to be running setuid; hypothetically
+    // with selinux someone could have sudo owned by some non-root user
+    // that has the caps needed for sudoing around
+    //
+    // note that the `euid` will always be the owner of the `sudo`
+    // binary
+    plugin.user_info.uid == plugin.user_info.euid
+}
+
+pub fn is_sudoing_to_themselves(plugin: &Plugin) -> bool {
+    // if they're not sudoing to a new uid, a new gid, or a new set of
+    // supplementary groups, they're just becoming themselves
+    !is_sudoing_to_user(plugin) &&
+        !is_sudoing_to_group(plugin) &&
+        plugin.runas_gids() == plugin.user_gids()
+}
+
+pub fn is_sudoing_to_user(plugin: &Plugin) -> bool {
+    // `plugin.settings.runas_user` tells us the value of `-u`, but by
+    // checking the change in uid, we can exclude cases where they're
+    // sudoing to themselves
+    plugin.user_info.uid != plugin.command_info.runas_euid
+}
+
+pub fn is_sudoing_to_group(plugin: &Plugin) -> bool {
+    plugin.user_info.gid != plugin.command_info.runas_egid
+}
+
+/// Returns true if `-g` was specified.
+pub fn is_sudoing_to_explicit_group(plugin: &Plugin) -> bool {
+    plugin.settings.runas_group.is_some()
+}
+
+pub fn is_sudoing_to_user_and_group(plugin: &Plugin) -> bool {
+    // if a user is doing `sudo -u ${u} -g ${g}`, we don't have a way to
+    // ensure that the pair can act with permissions of both the new
+    // user and the new group; ignoring this would allow someone to gain
+    // a group privilege through a pair who doesn't also have that group
+    // privilege
+    //
+    // note that we don't use `is_sudoing_to_group` because sudoing to a
+    // new user typically implicitly comes along with sudoing to a new
+    // group which is fine, what we want to avoid is the user explicitly
+    // providing a *different* group
+    is_sudoing_to_user(plugin) && is_sudoing_to_explicit_group(plugin)
+}
+
+/// Returns true if the policy plugin has not given us any facilities
+/// to log output for.
+pub fn is_exempted_from_logging(plugin: &Plugin) -> bool {
+    !plugin.command_info.iolog_ttyout &&
+        !plugin.command_info.iolog_stdout &&
+        !plugin.command_info.iolog_stderr
+}
+
+/// Returns true if sudo itself is recording the session's I/O (e.g.,
+/// the `log_output` option in sudoers).
+pub fn is_recorded_by_sudo(plugin: &Plugin) -> bool {
+    plugin.command_info.iolog_path.is_some() && !is_exempted_from_logging(plugin)
+}
+
+impl Exemption {
+    /// The name of the exemption, as it's reported.
+    pub fn name(self) -> &'static str {
+        match self {
+            Exemption::NotRunningCommand => "not_running_command",
+            Exemption::Root              => "root",
+            Exemption::SudoToSelf        => "sudo_to_self",
+            Exemption::ApprovalCommand   => "approval_command",
+            Exemption::NotLogged         => "not_logged",
+            Exemption::ExemptNetwork     => "exempt_network",
+            Exemption::UnenforcedNetwork => "unenforced_network",
+            Exemption::ExemptGroup       => "exempt_group",
+            Exemption::UnenforcedGroup   => "unenforced_group",
+            Exemption::NoPairRule        => "no_pair_rule",
+            Exemption::RecordOnly        => "record_only",
+            Exemption::RecordedBySudo(_) => "recorded_by_sudo",
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Require(quorum, coverage) => {
+                write!(f, "require {} approver{}", quorum.approvers, if quorum.approvers == 1 { "" } else { "s" })?;
+
+                if let Some(group) = quorum.group.as_ref() {
+                    write!(f, " from {}", group)?;
+                }
+
+                write!(f, ", {}", coverage.name())
+            },
+
+            Decision::Exempt(Exemption::RecordedBySudo(reason)) => {
+                write!(f, "exempt ({}, {})", Exemption::RecordedBySudo(*reason).name(), reason.code())
+            },
+
+            Decision::Exempt(exemption) => write!(f, "exempt ({})", exemption.name()),
+            Decision::Deny(reason)      => write!(f, "deny ({})", reason.code()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::fixture;
+
+    use sudo_plugin::{OptionMap, PluginBuilder};
+
+    use std::convert::TryFrom;
+
+    /// A plugin for alice (uid 1000) running `/bin/ls -la` as root,
+    /// with sudo logging its output, but without a terminal.
+    fn unattended() -> PluginBuilder {
+        let user_info = &[
+            ("cwd", "/home/alice"), ("egid", "1000"), ("euid", "0"),
+            ("gid", "1000"), ("groups", "1000"), ("host", "example"),
+            ("pgid", "4242"), ("pid", "4242"), ("ppid", "4241"),
+            ("uid", "1000"), ("user", "alice"),
+        ];
+
+        user_info.iter().fold(
+            PluginBuilder::new("sudo_pair")
+                .command(&["/bin/ls", "-la"])
+                .setting("plugin_dir", "/usr/libexec/sudo")
+                .setting("plugin_path", "/usr/libexec/sudo/sudo_pair.so")
+                .setting("progname", "sudo")
+                .command_info("command", "/bin/ls")
+                .command_info("runas_uid", "0")
+                .command_info("runas_gid", "0")
+                .command_info("runas_groups", "0")
+                .command_info("iolog_ttyout", "true"),
+            |builder, (k, v)| builder.user_info(k, v),
+        )
+    }
+
+    /// The same, run from a terminal.
+    fn builder() -> PluginBuilder {
+        unattended().user_info("tty", "/dev/pts/0")
+    }
+
+    fn options(options: &[(&str, &str)]) -> PluginOptions {
+        let map : OptionMap = options.iter().copied().collect();
+
+        PluginOptions::try_from(&map).unwrap()
+    }
+
+    fn decide_for(plugin: &Plugin, options: &PluginOptions, rules: Option<&Rules>) -> Decision {
+        let terminal   = Terminal::detect(plugin.user_info.tty.as_deref(), |_| None, &[]);
+        let invocation = Invocation { plugin, options, labels: &[], terminal: &terminal };
+
+        decide(invocation, plugin, rules)
+    }
+
+    #[test]
+    fn requires_a_pair_by_default() {
+        let plugin = builder().build().unwrap();
+
+        assert_eq!(
+            Decision::Require(Quorum::default(), Coverage::Full),
+            decide_for(&plugin, &options(&[]), None),
+        );
+
+        assert_eq!("require 1 approver, full", decide_for(&plugin, &options(&[]), None).to_string());
+    }
+
+    #[test]
+    fn exempts_root_and_unlogged_commands() {
+        let root = builder().user_info("euid", "1000").build().unwrap();
+
+        assert_eq!(Decision::Exempt(Exemption::Root), decide_for(&root, &options(&[]), None));
+
+        let unlogged = builder().command_info("iolog_ttyout", "false").build().unwrap();
+
+        assert_eq!(Decision::Exempt(Exemption::NotLogged), decide_for(&unlogged, &options(&[]), None));
+    }
+
+    #[test]
+    fn follows_pair_rules() {
+        let plugin = builder().build().unwrap();
+        let rules  = Rules::parse("quorum 2 2\ntier 2 arg -la\n").unwrap();
+
+        assert_eq!(
+            "require 2 approvers, full",
+            decide_for(&plugin, &options(&[]), Some(&rules)).to_string(),
+        );
+
+        let rules = Rules::parse("arg --force\n").unwrap();
+
+        assert_eq!(Decision::Exempt(Exemption::NoPairRule), decide_for(&plugin, &options(&[]), Some(&rules)));
+    }
+
+    #[test]
+    fn applies_unattended_policies() {
+        let plugin = unattended().build().unwrap();
+
+        assert_eq!(
+            Decision::Deny(ErrorKind::NoTty),
+            decide_for(&plugin, &options(&[("no_tty_policy", "deny")]), None),
+        );
+
+        assert_eq!(
+            Decision::Require(Quorum::default(), Coverage::Full),
+            decide_for(&plugin, &options(&[("no_tty_policy", "pair")]), None),
+        );
+
+        assert_eq!(
+            "deny (unrecorded)",
+            decide_for(&plugin, &options(&[("no_tty_policy", "record")]), None).to_string(),
+        );
+
+        let recorded = unattended()
+            .command_info("iolog_path", "/var/log/sudo-io/00/00/01")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            "exempt (recorded_by_sudo, no_tty)",
+            decide_for(&recorded, &options(&[("no_tty_policy", "record")]), None).to_string(),
+        );
+    }
+
+    #[test]
+    fn decides_fixtures() {
+        for (name, options, decision) in &[
+            ("sudo-1.8.16",           &[][..],                                 "exempt (unenforced_group)"),
+            ("sudo-1.8.31",           &[],                                     "exempt (unenforced_group)"),
+            ("sudo-1.9.5",            &[],                                     "exempt (unenforced_group)"),
+            ("sudo-1.9.5",            &[("gids_enforced", "109")],             "require 1 approver, full"),
+            ("sudo-1.8.31-sudoedit",  &[],                                     "require 1 approver, full"),
+            ("sudo-1.9.5-sudoedit",   &[],                                     "require 1 approver, full"),
+            ("sudo-1.9.5-login",      &[],                                     "require 1 approver, full"),
+            ("sudo-1.9.5-shell",      &[],                                     "require 1 approver, full"),
+            ("sudo-1.9.5-shell",      &[("gids_exempted", "27")],              "exempt (exempt_group)"),
+            ("sudo-1.9.5-background", &[],                                     "require 1 approver, full"),
+            ("sudo-1.9.5-nopasswd",   &[],                                     "deny (non_interactive)"),
+            ("sudo-1.9.5-nopasswd",   &[("noninteractive_policy", "record")],  "exempt (recorded_by_sudo, non_interactive)"),
+            ("sudo-1.9.5-nopasswd",   &[
+                ("automation_users", "1001"), ("noninteractive_policy", "record"),
+            ],                                                                 "exempt (recorded_by_sudo, automation_account)"),
+        ] {
+            let plugin = fixture(name).plugin();
+
+            assert_eq!(*decision, decide_for(&plugin, &self::options(options), None).to_string(), "{} {:?}", name, options);
+        }
+    }
+
+    #[test]
+    fn refuses_sudoing_to_a_user_and_group() {
+        let plugin = builder().setting("runas_group", "wheel").build().unwrap();
+
+        assert_eq!(Decision::Deny(ErrorKind::SudoToUserAndGroup), decide_for(&plugin, &options(&[]), None));
+    }
+}
@@ -0,0 +1,900 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Versioning and capability negotiation between the plugin and the
+//! approval client.
+//!
+//! The original protocol is nothing more than raw session output sent
+//! to the client and a single `y`/`n` byte sent back, which is what the
+//! shipped `sudo_approve` script (and `socat`) speak. To remain
+//! compatible with such clients, everything else is exchanged in-band as
+//! `OSC` (operating system command) escape sequences, which terminals
+//! ignore when they don't recognize them:
+//!
+//! ```text
+//! ESC ] 5379 ; sudo_pair;<message>;<key>=<value>;... BEL
+//! ```
+//!
+//! Upon connecting, the plugin sends a `hello` announcing its protocol
+//! version and capabilities. A client that understands it replies with
+//! its own `hello` before the `y`/`n` response, and the session proceeds
+//! with the lower of the two versions and the capabilities both sides
+//! share. A client that doesn't is treated as speaking version 0 with no
+//! capabilities.
+//!
+//! A client that negotiated `COMMENTS` may also send a `comment` ahead
+//! of its response, explaining why the session was approved or declined.
+//! Its text is percent-encoded, so it can't be mistaken for the end of
+//! the message.
+//!
+//! Once the session's approved, a client that negotiated `CHAT` may
+//! send the user a `chat;text=<text>` message at any time, encoded like
+//! a comment, adding `ask=1` to ask them to reply. Their replies (and
+//! only their replies) are sent back as `chat` messages of their own
+//! (see `chat`).
+//!
+//! The plugin's `hello` also lists the `encodings` it can frame the
+//! session in once it's approved, and a client may choose one of them
+//! as the `encoding` in its own (see `wire`).
+//!
+//! A client's `hello` may also give the approver's timezone, as the
+//! `utc_offset` in seconds their local time is ahead of UTC. Times the
+//! plugin shows the approver are then in their own timezone rather
+//! than UTC; manifests and logs stay in UTC regardless.
+//!
+//! Both `hello`s also give the largest of everything their side
+//! accepts, as `max_message`, `max_frame`, `max_prompt`, `max_comment`,
+//! `max_reason`, and `max_chat` (see `Limits`). The plugin reads nothing from a
+//! client beyond its own limits, and holds what it sends once the
+//! session's approved to the smaller of its own and the client's: output
+//! is split across as many frames as it takes, and notices and reasons
+//! and chat messages are cut short, ending with `…`. A client that doesn't give them is
+//! held to the defaults every client has always accepted.
+//!
+//! It may also give the size of the approver's terminal, as `rows` and
+//! `cols`, so that an approver whose terminal is narrower than the
+//! user's (and who would see the session's output wrapped) can be
+//! warned, or kept from approving (see `approver_width`).
+//!
+//! A client that negotiated `STREAMS` is sent a `stream;name=<stream>`
+//! message whenever the output that follows it comes from a different
+//! stream than the output before it: `ttyout`, `stdout`, or `stderr`
+//! for the command's output (as in sudo's own I/O logs), or `plugin`
+//! for the plugin's own messages, which is where every session starts.
+//!
+//! A client that negotiated `MARKERS` is sent a
+//! `suppressed;reason=<reason>;bytes=<bytes>` message whenever output is
+//! kept from the approver: `binary` output that was summarized,
+//! `escapes` that were sanitized away, or output over the `rate_limit`.
+//! Binary and rate-limited output are also marked in the output itself,
+//! for every client.
+//!
+//! When the host is tagged (see `host_tags`), the plugin follows its
+//! `hello` with a `tags;<key>=<value>;...` message listing them, so
+//! clients can route and prioritize the session.
+//!
+//! The plugin also follows its `hello` with a `preview` of the session,
+//! before the prompt: who's running what, where, and as whom, and the
+//! warnings the approver is given about it, as `user`, `host`,
+//! `runas_user`, `runas_group`, `cwd`, and `command` fields, then a
+//! `warning` field for each warning and an `env` field for each
+//! highlighted variable. Values are percent-encoded, like comments. A
+//! client can show the approver everything they're approving from it,
+//! without picking it out of the prompt; nothing from the command itself
+//! is sent until they've approved it.
+//!
+//! When approvers have to authenticate, the plugin follows its `hello`
+//! with an `authenticate` challenge, and the client sends a `signature`
+//! of it ahead of its response (see `sshsig`). When they have to enter
+//! a one-time code, the plugin asks for one with another `authenticate`
+//! message, and the client sends a `totp` message with the approver's
+//! name and code (see `totp`).
+//!
+//! A session handed to an approver daemon through its control socket
+//! (see `control_socket`) first sends an `announce` message identifying
+//! it with its `session_id`, and the `uid` and `pid` its own socket
+//! would have been named for. Everything after it is exactly what a
+//! client connecting to the session's socket would have been sent.
+
+use crate::chat::Chat;
+use crate::options::HostTag;
+use crate::sshsig::NAMESPACE;
+use crate::totp::{self, Code};
+
+use sudo_pair_types::base64;
+use sudo_pair_types::protocol::{message, percent_decode, percent_encode, Capabilities, Encoding, Limits, BEL, OSC};
+
+use std::io::{self, Read};
+
+use libc::{pid_t, uid_t};
+
+/// The largest of everything the plugin accepts from a client, which it
+/// announces in its `hello`. Messages are kept short, to keep a
+/// misbehaving client from making us buffer indefinitely; signatures
+/// are the longest a client sends.
+pub const LIMITS : Limits = Limits { message: 512, ..Limits::DEFAULT };
+
+/// The longest `preview` sent to a client, which is sent before the
+/// client's `hello` and so has to be short enough for any client to
+/// take as a single message. Fields that don't fit are left out.
+pub const MAX_PREVIEW_LEN : usize = 4000;
+
+/// The most bytes of the command sent in a `preview`, so that (even
+/// percent-encoded) it leaves room in the message for the rest of the
+/// session's context.
+pub const MAX_PREVIEW_COMMAND_LEN : usize = 1024;
+
+/// The version and capabilities announced by one side of the session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Hello {
+    pub version:      u16,
+    pub capabilities: Capabilities,
+
+    /// when the plugin will decline the session if it hasn't been
+    /// approved, in seconds since the epoch
+    pub deadline: Option<u64>,
+
+    /// the encoding the client asked for the session to be sent in;
+    /// the plugin's own `hello` offers every encoding instead
+    pub encoding: Encoding,
+
+    /// the approver's timezone, as the seconds their local time is
+    /// ahead of UTC, for showing them times in it; the plugin's own
+    /// `hello` has none
+    pub utc_offset: Option<i32>,
+
+    /// the size of the approver's terminal, as rows and columns; the
+    /// plugin's own `hello` has none
+    pub winsize: Option<(u32, u32)>,
+
+    /// the largest of everything the side accepts, or once negotiated,
+    /// what both sides are held to
+    pub limits: Limits,
+}
+
+impl Hello {
+    /// The hello spoken by clients that predate negotiation.
+    pub fn legacy() -> Self {
+        Self {
+            version:      0,
+            capabilities: Capabilities::empty(),
+            deadline:     None,
+            encoding:     Encoding::Text,
+            utc_offset:   None,
+            winsize:      None,
+            limits:       Limits::DEFAULT,
+        }
+    }
+
+    /// Returns true if the messages enabled by `capability` should be
+    /// sent. Clients that predate negotiation are sent them anyway, since
+    /// they'll ignore them, but clients that negotiated without them
+    /// aren't.
+    pub fn streams(&self, capability: Capabilities) -> bool {
+        self.version == 0 || self.capabilities.contains(capability)
+    }
+
+    /// Returns true if the client negotiated `capability`, and so
+    /// handles it itself instead of needing a plain-text fallback shown
+    /// to the approver.
+    pub fn handles(&self, capability: Capabilities) -> bool {
+        self.version > 0 && self.capabilities.contains(capability)
+    }
+
+    /// Returns what both sides of the session have agreed upon.
+    pub fn negotiate(self, other: Self) -> Self {
+        Self {
+            version:      self.version.min(other.version),
+            capabilities: self.capabilities & other.capabilities,
+            deadline:     self.deadline,
+            encoding:     other.encoding,
+            utc_offset:   other.utc_offset,
+            winsize:      other.winsize,
+            limits:       self.limits.negotiate(other.limits),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = format!(
+            "hello;version={};capabilities={}",
+            self.version,
+            self.capabilities,
+        );
+
+        if let Some(deadline) = self.deadline {
+            payload.push_str(&format!(";deadline={}", deadline));
+        }
+
+        let encodings : Vec<_> = Encoding::FRAMED.iter()
+            .map(|encoding| encoding.name())
+            .collect();
+
+        payload.push_str(&format!(";encodings={}", encodings.join(",")));
+        payload.push_str(&self.limits.fields());
+
+        if self.encoding != Encoding::Text {
+            payload.push_str(&format!(";encoding={}", self.encoding.name()));
+        }
+
+        if let Some(utc_offset) = self.utc_offset {
+            payload.push_str(&format!(";utc_offset={}", utc_offset));
+        }
+
+        if let Some((rows, cols)) = self.winsize {
+            payload.push_str(&format!(";rows={};cols={}", rows, cols));
+        }
+
+        message(&payload)
+    }
+
+    /// Parses the payload of a `hello` message (everything between the
+    /// `OSC` introducer and the terminating `BEL`).
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        let payload = std::str::from_utf8(payload).ok()?;
+        let prefix  = format!("]{};sudo_pair;hello", OSC);
+
+        let mut version      = None;
+        let mut capabilities = None;
+        let mut deadline     = None;
+        let mut encoding     = Encoding::Text;
+        let mut utc_offset   = None;
+        let mut rows         = None;
+        let mut cols         = None;
+        let mut limits       = Limits::DEFAULT;
+
+        for field in payload.strip_prefix(prefix.as_str())?.split(';').skip(1) {
+            let mut kv = field.splitn(2, '=');
+
+            match (kv.next(), kv.next()) {
+                (Some("version"), Some(v)) => {
+                    version = v.parse().ok();
+                },
+
+                (Some("capabilities"), Some(c)) => {
+                    capabilities = u32::from_str_radix(c.trim_start_matches("0x"), 16).ok();
+                },
+
+                (Some("deadline"), Some(d)) => {
+                    deadline = d.parse().ok();
+                },
+
+                // an encoding we don't offer leaves the session as text
+                (Some("encoding"), Some(e)) => {
+                    encoding = Encoding::from_name(e).unwrap_or(Encoding::Text);
+                },
+
+                // no timezone is more than a day from UTC, so anything
+                // further is ignored and times are shown in UTC
+                (Some("utc_offset"), Some(o)) => {
+                    utc_offset = o.parse().ok().filter(|o: &i32| o.abs() < 86_400);
+                },
+
+                (Some("rows"), Some(r)) => {
+                    rows = r.parse().ok();
+                },
+
+                (Some("cols"), Some(c)) => {
+                    cols = c.parse().ok();
+                },
+
+                // a limit that isn't a size leaves the default in place
+                (Some(key), Some(value)) if key.starts_with("max_") => {
+                    let _ = limits.set(key, value);
+                },
+
+                // unknown fields are reserved for future versions
+                _ => (),
+            }
+        }
+
+        Some(Self {
+            version:      version?,
+            capabilities: Capabilities(capabilities?),
+            deadline,
+            encoding,
+            utc_offset,
+
+            // a terminal with no rows or columns is one whose size isn't
+            // actually known
+            winsize: rows.zip(cols).filter(|&(rows, cols)| rows > 0 && cols > 0),
+
+            limits,
+        })
+    }
+}
+
+/// A message a client sends ahead of its `y`/`n` response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reply {
+    /// the client's version and capabilities
+    Hello(Hello),
+
+    /// the approver's comment on their response
+    Comment(String),
+
+    /// the approver's signature of the challenge they were sent, in the
+    /// `SSHSIG` format
+    Signature(Vec<u8>),
+
+    /// the approver's one-time code, and who they say they are
+    Totp(Code),
+
+    /// a message from the approver to the user
+    Chat(Chat),
+}
+
+/// What an approver sends to prove who they are, when they have to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Credentials {
+    /// their signature of the challenge they were sent, in the
+    /// `SSHSIG` format
+    pub signature: Option<Vec<u8>>,
+
+    /// their one-time code
+    pub totp: Option<Code>,
+}
+
+impl Credentials {
+    /// Whether nothing's been sent.
+    pub fn is_empty(&self) -> bool {
+        self.signature.is_none() && self.totp.is_none()
+    }
+}
+
+impl Reply {
+    /// Reads the remainder of a message from the client, whose leading
+    /// `ESC` has already been consumed. Returns `None` if the client
+    /// sent something that isn't a message it's allowed to send.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let payload = match read_payload(reader)? {
+            Some(payload) => payload,
+            None          => return Ok(None),
+        };
+
+        Ok(Self::decode(&payload))
+    }
+
+    /// Parses the payload of a message from the client (everything
+    /// between the `OSC` introducer and the terminating `BEL`).
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        Hello::decode(payload).map(Reply::Hello)
+            .or_else(|| decode_comment(payload).map(Reply::Comment))
+            .or_else(|| decode_signature(payload).map(Reply::Signature))
+            .or_else(|| decode_totp(payload).map(Reply::Totp))
+            .or_else(|| decode_chat(payload).map(Reply::Chat))
+    }
+}
+
+/// Reads a message's payload up to (but not including) its terminating
+/// `BEL`, giving up on messages longer than the plugin accepts.
+fn read_payload<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut payload = Vec::new();
+    let mut byte    = [0; 1];
+
+    while payload.len() < LIMITS.message {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+
+        if byte[0] == BEL {
+            return Ok(Some(payload));
+        }
+
+        payload.push(byte[0]);
+    }
+
+    Ok(None)
+}
+
+/// Parses the payload of a `comment` message. Comments are shown to
+/// the user on their terminal, so any that decode to something other
+/// than a single line of printable text (or that are too long) are
+/// rejected.
+fn decode_comment(payload: &[u8]) -> Option<String> {
+    let payload = std::str::from_utf8(payload).ok()?;
+    let prefix  = format!("]{};sudo_pair;comment", OSC);
+
+    let text = payload.strip_prefix(prefix.as_str())?
+        .split(';')
+        .skip(1)
+        .find_map(|field| field.strip_prefix("text="))?;
+
+    decode_text(text, LIMITS.comment)
+}
+
+/// Parses the payload of a `chat` message, whose text is held to the
+/// same rules as a comment's.
+fn decode_chat(payload: &[u8]) -> Option<Chat> {
+    let payload = std::str::from_utf8(payload).ok()?;
+    let prefix  = format!("]{};sudo_pair;chat", OSC);
+    let fields  = payload.strip_prefix(prefix.as_str())?;
+    let field   = |name: &str| fields.split(';')
+        .skip(1)
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='));
+
+    let text = decode_text(field("text")?, LIMITS.chat)?;
+    let ask  = field("ask") == Some("1");
+
+    Some(Chat { text, ask })
+}
+
+/// Percent-decodes `text`, which has to be a single line of printable
+/// text of at most `limit` characters once leading and trailing
+/// whitespace is trimmed.
+fn decode_text(text: &str, limit: usize) -> Option<String> {
+    let text = String::from_utf8(percent_decode(text)?).ok()?;
+    let text = text.trim();
+
+    if text.is_empty()
+        || text.chars().count() > limit
+        || text.chars().any(char::is_control)
+    {
+        return None;
+    }
+
+    Some(text.into())
+}
+
+/// Parses the payload of a `signature` message, whose signature is
+/// base64-encoded.
+fn decode_signature(payload: &[u8]) -> Option<Vec<u8>> {
+    let payload = std::str::from_utf8(payload).ok()?;
+    let prefix  = format!("]{};sudo_pair;signature", OSC);
+
+    payload.strip_prefix(prefix.as_str())?
+        .split(';')
+        .skip(1)
+        .find_map(|field| field.strip_prefix("sshsig="))
+        .and_then(base64::decode)
+}
+
+/// Parses the payload of a `totp` message, rejecting names that can't
+/// be provisioned and codes that aren't six digits.
+fn decode_totp(payload: &[u8]) -> Option<Code> {
+    let payload = std::str::from_utf8(payload).ok()?;
+    let prefix  = format!("]{};sudo_pair;totp", OSC);
+    let fields  = payload.strip_prefix(prefix.as_str())?;
+    let field   = |name: &str| fields.split(';')
+        .skip(1)
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='));
+
+    let approver = field("approver").filter(|approver| totp::valid_approver(approver))?;
+    let code     = field("code").filter(|code| totp::valid_code(code))?;
+
+    Some(Code { approver: approver.into(), code: code.into() })
+}
+
+/// Encodes the announcement of a session to an approver daemon, sent
+/// ahead of anything else on its control socket.
+pub fn announce(session_id: &str, uid: uid_t, pid: pid_t) -> Vec<u8> {
+    message(&format!("announce;session_id={};uid={};pid={}", session_id, uid, pid))
+}
+
+/// Encodes a request for the approver to re-approve the session by
+/// `deadline`, in seconds since the epoch.
+pub fn reapprove(deadline: u64) -> Vec<u8> {
+    message(&format!("reapprove;deadline={}", deadline))
+}
+
+/// Encodes a `challenge` the approver has to sign before their
+/// approval is accepted.
+pub fn authenticate(challenge: &str) -> Vec<u8> {
+    message(&format!("authenticate;method=sshsig;namespace={};challenge={}", NAMESPACE, challenge))
+}
+
+/// Encodes a request for the approver's one-time code, which has to be
+/// sent before their approval is accepted.
+pub fn request_totp() -> Vec<u8> {
+    message("authenticate;method=totp")
+}
+
+/// Encodes the tags describing the host.
+pub fn tags(tags: &[HostTag]) -> Vec<u8> {
+    let fields : Vec<_> = tags.iter().map(HostTag::to_string).collect();
+
+    message(&format!("tags;{}", fields.join(";")))
+}
+
+/// Encodes a preview of the session from its `fields`, in order, as
+/// many as fit in `MAX_PREVIEW_LEN`.
+pub fn preview(fields: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut payload = String::from("preview");
+
+    for (key, value) in fields {
+        let field = format!(";{}={}", key, percent_encode(value));
+
+        if payload.len() + field.len() <= MAX_PREVIEW_LEN {
+            payload.push_str(&field);
+        }
+    }
+
+    message(&payload)
+}
+
+/// Encodes a message from the user to the approver.
+pub fn chat(text: &str) -> Vec<u8> {
+    message(&format!("chat;text={}", percent_encode(text.as_bytes())))
+}
+
+/// Encodes a change in the size of the user's terminal.
+pub fn winsize(rows: u32, cols: u32) -> Vec<u8> {
+    message(&format!("winsize;rows={};cols={}", rows, cols))
+}
+
+/// Encodes a change in the stream the output that follows comes from.
+pub fn stream(name: &str) -> Vec<u8> {
+    message(&format!("stream;name={}", name))
+}
+
+/// Encodes a message telling the approver that `bytes` of output were
+/// kept from them, and why.
+pub fn suppressed(reason: &str, bytes: u64) -> Vec<u8> {
+    message(&format!("suppressed;reason={};bytes={}", reason, bytes))
+}
+
+impl slog::Value for Hello {
+    fn serialize(&self, _: &slog::Record<'_>, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_str(key, &format!("v{} ({})", self.version, self.capabilities))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sudo_pair_types::protocol::PROTOCOL_VERSION;
+
+    #[test]
+    fn round_trips_hello() {
+        for &(deadline, utc_offset, winsize) in &[
+            (None,                None,          None),
+            (Some(1_600_000_300), Some(-16_200), Some((24, 80))),
+        ] {
+            let hello = Hello {
+                version:      PROTOCOL_VERSION,
+                capabilities: Capabilities::KILL,
+                deadline,
+                encoding:     Encoding::Text,
+                utc_offset,
+                winsize,
+                limits:       Limits { frame: 65_536, ..Limits::DEFAULT },
+            };
+
+            // strip the leading ESC, which is consumed before decoding,
+            // and the trailing BEL
+            let encoded = hello.encode();
+            let payload = &encoded[1 .. encoded.len() - 1];
+
+            assert_eq!(Some(hello), Hello::decode(payload));
+        }
+    }
+
+    #[test]
+    fn announces_deadlines() {
+        let hello = Hello {
+            version:      1,
+            capabilities: Capabilities::DIGESTS,
+            deadline:     Some(1_600_000_300),
+            encoding:     Encoding::Text,
+            utc_offset:   None,
+            winsize:      None,
+            limits:       LIMITS,
+        };
+
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x10;deadline=1600000300;encodings=json,msgpack,cbor,binary;max_message=512;max_frame=1048576;max_prompt=65536;max_comment=128;max_reason=256;max_chat=256\x07".to_vec(),
+            hello.encode(),
+        );
+    }
+
+    #[test]
+    fn reads_hello_from_client() {
+        let mut client = &b"]5379;sudo_pair;hello;version=3;capabilities=0x9;future=1\x07y"[..];
+
+        let hello = match Reply::read_from(&mut client).unwrap() {
+            Some(Reply::Hello(hello)) => hello,
+            reply                     => panic!("unexpected reply: {:?}", reply),
+        };
+
+        assert_eq!(3, hello.version);
+        assert!(hello.capabilities.contains(Capabilities::KILL));
+        assert!(hello.capabilities.contains(Capabilities::TIMESTAMPS));
+        assert!(!hello.capabilities.contains(Capabilities::PAUSE));
+        assert_eq!(Encoding::Text, hello.encoding);
+
+        // the response is left unread
+        assert_eq!(b"y", client);
+    }
+
+    #[test]
+    fn reads_encodings_from_client() {
+        for (field, encoding) in &[
+            ("encoding=msgpack", Encoding::MessagePack),
+            ("encoding=json",    Encoding::Json),
+            ("encoding=yaml",    Encoding::Text),
+        ] {
+            let payload = format!("]5379;sudo_pair;hello;version=1;capabilities=0x0;{}\x07", field);
+
+            match Reply::read_from(&mut payload.as_bytes()).unwrap() {
+                Some(Reply::Hello(hello)) => assert_eq!(*encoding, hello.encoding),
+                reply                     => panic!("unexpected reply: {:?}", reply),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_timezones_from_client() {
+        for (field, utc_offset) in &[
+            ("utc_offset=7200",   Some(7_200)),
+            ("utc_offset=-16200", Some(-16_200)),
+            ("utc_offset=90000",  None),
+            ("utc_offset=CEST",   None),
+        ] {
+            let payload = format!("]5379;sudo_pair;hello;version=1;capabilities=0x0;{}\x07", field);
+
+            match Reply::read_from(&mut payload.as_bytes()).unwrap() {
+                Some(Reply::Hello(hello)) => assert_eq!(*utc_offset, hello.utc_offset),
+                reply                     => panic!("unexpected reply: {:?}", reply),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_terminal_sizes_from_client() {
+        for (fields, winsize) in &[
+            ("rows=50;cols=200", Some((50, 200))),
+            ("cols=200;rows=50", Some((50, 200))),
+            ("cols=200",         None),
+            ("rows=0;cols=0",    None),
+            ("rows=50;cols=-1",  None),
+        ] {
+            let payload = format!("]5379;sudo_pair;hello;version=1;capabilities=0x0;{}\x07", fields);
+
+            match Reply::read_from(&mut payload.as_bytes()).unwrap() {
+                Some(Reply::Hello(hello)) => assert_eq!(*winsize, hello.winsize),
+                reply                     => panic!("unexpected reply: {:?}", reply),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_limits_from_client() {
+        let payload = b"]5379;sudo_pair;hello;version=1;capabilities=0x0;max_frame=8192;max_reason=lots\x07";
+
+        match Reply::read_from(&mut &payload[..]).unwrap() {
+            Some(Reply::Hello(hello)) => assert_eq!(
+                Limits { frame: 8192, ..Limits::DEFAULT },
+                hello.limits,
+            ),
+
+            reply => panic!("unexpected reply: {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        assert_eq!(None, Reply::read_from(&mut &b"[A"[..]).unwrap());
+        assert_eq!(None, Reply::read_from(&mut &b"]5379;sudo_pair;bye\x07"[..]).unwrap());
+        assert_eq!(None, Reply::read_from(&mut &b"]5379;sudo_pair;hello;version=1\x07"[..]).unwrap());
+        assert_eq!(None, Reply::read_from(&mut &[b'x'; 1024][..]).unwrap());
+    }
+
+    #[test]
+    fn negotiates_common_ground() {
+        let mut ours = Capabilities::empty();
+        ours.insert(Capabilities::KILL);
+        ours.insert(Capabilities::DIGESTS);
+
+        let plugin = Hello { version: 2, capabilities: ours, deadline: None, encoding: Encoding::Text, utc_offset: None, winsize: None, limits: Limits::DEFAULT };
+        let client = Hello { version: 1, capabilities: Capabilities::DIGESTS, deadline: None, encoding: Encoding::Cbor, utc_offset: Some(7_200), winsize: Some((50, 200)), limits: Limits { frame: 4096, ..Limits::DEFAULT } };
+
+        let session = plugin.negotiate(client);
+
+        assert_eq!(1, session.version);
+        assert_eq!(Encoding::Cbor, session.encoding);
+        assert_eq!(Some(7_200),    session.utc_offset);
+        assert_eq!(Some((50, 200)), session.winsize);
+        assert_eq!(4096,           session.limits.frame);
+        assert!(session.capabilities.contains(Capabilities::DIGESTS));
+        assert!(!session.capabilities.contains(Capabilities::KILL));
+
+        assert_eq!(Hello::legacy(), plugin.negotiate(Hello::legacy()));
+    }
+
+    #[test]
+    fn streams_only_negotiated_messages() {
+        let client = Hello { version: 1, capabilities: Capabilities::WINSIZE, deadline: None, encoding: Encoding::Text, utc_offset: None, winsize: None, limits: Limits::DEFAULT };
+
+        assert!(client.streams(Capabilities::WINSIZE));
+        assert!(!client.streams(Capabilities::DIGESTS));
+
+        // clients that predate negotiation ignore every message
+        assert!(Hello::legacy().streams(Capabilities::WINSIZE));
+        assert!(Hello::legacy().streams(Capabilities::DIGESTS));
+
+        // but they can't handle them, so they need fallbacks
+        assert!(!Hello::legacy().handles(Capabilities::REAPPROVAL));
+    }
+
+    #[test]
+    fn reads_comments_from_client() {
+        let mut client = &b"]5379;sudo_pair;comment;text=denied%3B%20wrong host\x07n"[..];
+
+        assert_eq!(
+            Some(Reply::Comment("denied; wrong host".into())),
+            Reply::read_from(&mut client).unwrap(),
+        );
+
+        assert_eq!(b"n", client);
+    }
+
+    #[test]
+    fn rejects_unprintable_comments() {
+        for payload in &[
+            &b"]5379;sudo_pair;comment;text=%1b[2J\x07"[..],
+            &b"]5379;sudo_pair;comment;text=line%0Aline\x07"[..],
+            &b"]5379;sudo_pair;comment;text=%zz\x07"[..],
+            &b"]5379;sudo_pair;comment;text=%e2%28\x07"[..],
+            &b"]5379;sudo_pair;comment;text=%20\x07"[..],
+            &b"]5379;sudo_pair;comment\x07"[..],
+        ] {
+            assert_eq!(None, Reply::read_from(&mut &payload[..]).unwrap());
+        }
+
+        let long = format!("]5379;sudo_pair;comment;text={}\x07", "x".repeat(LIMITS.comment + 1));
+
+        assert_eq!(None, Reply::read_from(&mut long.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn reads_chats_from_client() {
+        assert_eq!(
+            Some(Reply::Chat(Chat { text: "hold on; checking the ticket".into(), ask: false })),
+            Reply::read_from(&mut &b"]5379;sudo_pair;chat;text=hold on%3b checking the ticket\x07"[..]).unwrap(),
+        );
+
+        assert_eq!(
+            Some(Reply::Chat(Chat { text: "which ticket?".into(), ask: true })),
+            Reply::read_from(&mut &b"]5379;sudo_pair;chat;text=which ticket?;ask=1\x07"[..]).unwrap(),
+        );
+
+        for payload in &[
+            &b"]5379;sudo_pair;chat;text=%1b]0;pwned%07\x07"[..],
+            &b"]5379;sudo_pair;chat;ask=1\x07"[..],
+        ] {
+            assert_eq!(None, Reply::read_from(&mut &payload[..]).unwrap());
+        }
+
+        let long = format!("]5379;sudo_pair;chat;text={}\x07", "x".repeat(LIMITS.chat + 1));
+
+        assert_eq!(None, Reply::read_from(&mut long.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn reads_signatures_from_client() {
+        let mut client = &b"]5379;sudo_pair;signature;sshsig=U1NIU0lH\x07y"[..];
+
+        assert_eq!(
+            Some(Reply::Signature(b"SSHSIG".to_vec())),
+            Reply::read_from(&mut client).unwrap(),
+        );
+
+        assert_eq!(b"y", client);
+        assert_eq!(None, Reply::read_from(&mut &b"]5379;sudo_pair;signature;sshsig=U1N\x07"[..]).unwrap());
+    }
+
+    #[test]
+    fn reads_one_time_codes_from_client() {
+        let mut client = &b"]5379;sudo_pair;totp;approver=alice;code=005924\x07y"[..];
+
+        assert_eq!(
+            Some(Reply::Totp(Code { approver: "alice".into(), code: "005924".into() })),
+            Reply::read_from(&mut client).unwrap(),
+        );
+
+        assert_eq!(b"y", client);
+
+        for payload in &[
+            &b"]5379;sudo_pair;totp;approver=../alice;code=005924\x07"[..],
+            &b"]5379;sudo_pair;totp;approver=alice;code=5924\x07"[..],
+            &b"]5379;sudo_pair;totp;code=005924\x07"[..],
+        ] {
+            assert_eq!(None, Reply::read_from(&mut &payload[..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn encodes_challenges() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;authenticate;method=sshsig;namespace=sudo_pair;challenge=00ff\x07".to_vec(),
+            authenticate("00ff"),
+        );
+
+        assert_eq!(b"\x1b]5379;sudo_pair;authenticate;method=totp\x07".to_vec(), request_totp());
+    }
+
+    #[test]
+    fn encodes_tags() {
+        let host_tags = [
+            HostTag { key: "datacenter".into(), value: "sfo1".into() },
+            HostTag { key: "team".into(),       value: "payments".into() },
+        ];
+
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;tags;datacenter=sfo1;team=payments\x07".to_vec(),
+            tags(&host_tags),
+        );
+    }
+
+    #[test]
+    fn encodes_previews() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;preview;user=alice;cwd=/home/alice;command=echo 100%25%3b \
+              %e6%97%a5;warning=a shell\x07".to_vec(),
+            preview(&[
+                ("user",    b"alice"),
+                ("cwd",     b"/home/alice"),
+                ("command", "echo 100%; 日".as_bytes()),
+                ("warning", b"a shell"),
+            ]),
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_doesnt_fit_in_previews() {
+        let huge    = vec![b'x'; MAX_PREVIEW_LEN];
+        let preview = preview(&[("user", b"alice"), ("cwd", &huge), ("warning", b"a shell")]);
+
+        assert_eq!(b"\x1b]5379;sudo_pair;preview;user=alice;warning=a shell\x07".to_vec(), preview);
+    }
+
+    #[test]
+    fn encodes_winsize() {
+        assert_eq!(b"\x1b]5379;sudo_pair;winsize;rows=24;cols=80\x07".to_vec(), winsize(24, 80));
+    }
+
+    #[test]
+    fn encodes_streams() {
+        assert_eq!(b"\x1b]5379;sudo_pair;stream;name=stderr\x07".to_vec(), stream("stderr"));
+    }
+
+    #[test]
+    fn encodes_suppressions() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;suppressed;reason=binary;bytes=4096\x07".to_vec(),
+            suppressed("binary", 4096),
+        );
+    }
+
+    #[test]
+    fn encodes_chats() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;chat;text=ticket 42%3b looks fine%07\x07".to_vec(),
+            chat("ticket 42; looks fine\x07"),
+        );
+    }
+
+    #[test]
+    fn encodes_announcements() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;announce;session_id=1600000000-4242;uid=1000;pid=4242\x07".to_vec(),
+            announce("1600000000-4242", 1000, 4242),
+        );
+    }
+
+    #[test]
+    fn encodes_reapproval_requests() {
+        assert_eq!(b"\x1b]5379;sudo_pair;reapprove;deadline=1600000060\x07".to_vec(), reapprove(1_600_000_060));
+    }
+}
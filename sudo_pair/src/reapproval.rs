@@ -0,0 +1,123 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Periodic re-approval of long sessions, so an approver who's walked
+//! away can't leave a privileged session running unwatched.
+
+use crate::clock::Clock;
+use crate::deadline::Deadline;
+
+use sudo_pair_types::humanize;
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// Where a session is in its cycle of re-approvals.
+#[derive(Clone, Copy, Debug)]
+pub enum Reapproval {
+    /// the approver is next asked to re-approve the session then
+    Due(Instant),
+
+    /// the approver has been asked, and has to answer by the deadline
+    Pending(Deadline),
+}
+
+impl Reapproval {
+    /// The next request for re-approval, `interval` from now on
+    /// `clock`.
+    pub fn after_on(clock: &dyn Clock, interval: Duration) -> Self {
+        Reapproval::Due(clock.instant() + interval)
+    }
+
+    /// Whether the approver has to be asked to re-approve the session
+    /// now, as measured by `clock`.
+    pub fn is_due_on(&self, clock: &dyn Clock) -> bool {
+        match *self {
+            Reapproval::Due(at)     => clock.instant() >= at,
+            Reapproval::Pending(..) => false,
+        }
+    }
+}
+
+/// The approver's answer, if `input` contains one: `true` for `y` and
+/// `false` for `n`, whichever comes first. Anything else they typed is
+/// ignored.
+pub fn answer(input: &[u8]) -> Option<bool> {
+    input.iter().find_map(|b| match b {
+        b'y' | b'Y' => Some(true),
+        b'n' | b'N' => Some(false),
+        _           => None,
+    })
+}
+
+/// The request shown to approvers whose clients can't render their own,
+/// who have `grace` to answer it, until `by` in their timezone (see
+/// `Hello::utc_offset`).
+pub fn prompt(grace: Duration, by: SystemTime, utc_offset: Option<i32>) -> String {
+    format!(
+        "\nre-approve this session? [y/n] ({} to answer, by {})\n",
+        humanize::duration(grace),
+        humanize::clock(by, utc_offset),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn comes_due_after_the_interval() {
+        let clock      = ManualClock::at(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let reapproval = Reapproval::after_on(&clock, Duration::from_secs(3_600));
+
+        clock.advance(Duration::from_secs(3_599));
+        assert!(!reapproval.is_due_on(&clock));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(reapproval.is_due_on(&clock));
+
+        // once asked, it's the approver's answer (or the grace period's
+        // deadline) that's waited on instead
+        let pending = Reapproval::Pending(Deadline::after_on(&clock, Duration::from_secs(60)));
+
+        clock.advance(Duration::from_secs(3_600));
+        assert!(!pending.is_due_on(&clock));
+    }
+
+    #[test]
+    fn finds_answers_among_stray_input() {
+        assert_eq!(Some(true),  answer(b"y"));
+        assert_eq!(Some(false), answer(b"N"));
+        assert_eq!(Some(true),  answer(b"\x1b[A Yn"));
+        assert_eq!(None,        answer(b"\r\n"));
+        assert_eq!(None,        answer(b""));
+    }
+
+    #[test]
+    fn prompts_with_the_time_to_answer() {
+        let by = UNIX_EPOCH + Duration::from_secs(1_600_000_090);
+
+        assert_eq!(
+            "\nre-approve this session? [y/n] (1 minute 30 seconds to answer, by 12:28:10 UTC)\n",
+            prompt(Duration::from_secs(90), by, None),
+        );
+
+        assert_eq!(
+            "\nre-approve this session? [y/n] (1 minute 30 seconds to answer, by 14:28:10 +02:00)\n",
+            prompt(Duration::from_secs(90), by, Some(7_200)),
+        );
+    }
+}
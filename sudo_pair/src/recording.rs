@@ -0,0 +1,1392 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Recordings of each session's output, written to whichever
+//! `RecordingSink` is configured.
+//!
+//! A recording begins with `HEADER`, followed by a frame for each
+//! chunk of output and each change to the terminal's size, and ends
+//! with a frame holding the session's manifest:
+//!
+//! * the kind of frame (see `Frame`), as one byte
+//! * the time since the session began, in microseconds, as a big-endian
+//!   `u64`
+//! * the length of the frame's data, as a big-endian `u32`
+//! * the data itself: output exactly as the command wrote it, the
+//!   terminal's rows and columns as big-endian `u32`s, or the manifest
+//!   as JSON
+
+use crate::clock::{Anchor, Clock};
+use crate::options::{FsyncPolicy, PluginOptions, RecordingLayout, RecordingSinkKind};
+use crate::ownership::Ownership;
+use crate::recovery;
+
+use sudo_pair_types::manifest::Manifest;
+
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use libc::mode_t;
+
+/// The first bytes of every recording.
+pub const HEADER : &[u8] = b"sudo_pair recording v1\n";
+
+/// The kinds of frames in a recording.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Frame {
+    TtyOut,
+    StdOut,
+    StdErr,
+    Winsize,
+    Manifest,
+    Suppressed,
+}
+
+impl Frame {
+    /// The byte that identifies the frame in a recording.
+    pub fn tag(self) -> u8 {
+        match self {
+            Frame::TtyOut     => b'o',
+            Frame::StdOut     => b'1',
+            Frame::StdErr     => b'2',
+            Frame::Winsize    => b'w',
+            Frame::Manifest   => b'm',
+            Frame::Suppressed => b's',
+        }
+    }
+}
+
+/// Somewhere a recording can be written to.
+pub trait RecordingSink: Write + Debug + Send {
+    /// Where the recording is being written, for logging.
+    fn describe(&self) -> String;
+
+    /// Completes the recording once the session has ended, reporting
+    /// whether everything written was received. The manifest has
+    /// already been written as the recording's last frame, but is also
+    /// provided (as JSON) to sinks that store it separately.
+    fn finish(self: Box<Self>, manifest: &[u8]) -> Result<()>;
+
+    /// Abandons the recording of a session whose command never ran.
+    /// Sinks that keep recordings locally remove them; by default, the
+    /// recording has already been sent elsewhere, so it's completed as
+    /// usual, with a manifest saying the command never ran.
+    fn discard(self: Box<Self>, manifest: &[u8]) -> Result<()> {
+        self.finish(manifest)
+    }
+
+    /// Leaves the session's `manifest` (as JSON), as it stands when
+    /// the session begins, beside a recording kept locally, so the
+    /// recording can still be completed if sudo is killed before the
+    /// session ends (see `recovery`). Recordings sent elsewhere can't
+    /// be recovered from here, so by default nothing is left.
+    fn pending(&mut self, _manifest: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes sure everything written so far would survive the host
+    /// going down (see `recording_fsync`). Recordings sent elsewhere
+    /// are out of this host's hands once they've been sent, so by
+    /// default they're only flushed.
+    fn sync(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// When a recording's frames are written to its sink, and when they're
+/// synced (see `recording_flush_interval` and `recording_fsync`).
+#[derive(Clone, Copy, Debug)]
+pub struct Durability {
+    pub flush_interval: Duration,
+    pub fsync:          FsyncPolicy,
+}
+
+impl Durability {
+    /// Writes each frame as it's recorded, and leaves syncing it to the
+    /// kernel.
+    pub const IMMEDIATE : Self = Self {
+        flush_interval: Duration::from_secs(0),
+        fsync:          FsyncPolicy::Never,
+    };
+
+    /// The durability `options` configure.
+    pub fn configured(options: &PluginOptions) -> Self {
+        Self {
+            flush_interval: options.recording_flush_interval,
+            fsync:          options.recording_fsync,
+        }
+    }
+}
+
+/// Opens the sink configured by `options` for the session `id`, or
+/// returns `None` if sessions aren't being recorded. Any files written
+/// locally are given `ownership`, and named `name` (see `name`). Sinks
+/// that deliver recordings elsewhere are opened and written to on a
+/// thread of their own (see `QueuedSink`), so they can't hold up the
+/// session for longer than `options` budgets for them.
+pub fn sink(options: &PluginOptions, ownership: Ownership, id: &str, name: &str) -> Result<Option<Box<dyn RecordingSink>>> {
+    let path = |path: &Option<PathBuf>, key: &str| path.clone().ok_or_else(||
+        Error::new(ErrorKind::InvalidInput, format!("{} isn't set", key))
+    );
+
+    let budget = Budget {
+        open:  options.recording_open_budget,
+        close: options.recording_close_budget,
+        queue: options.recording_queue_limit.as_u64() as usize,
+
+        confirmed: options.require_pair_and_recording,
+    };
+
+    let sink : Box<dyn RecordingSink> = match options.recording_sink {
+        RecordingSinkKind::None    => return Ok(None),
+        RecordingSinkKind::File    => Box::new(FileSink::create(&options.recording_dir, ownership, name)?),
+
+        RecordingSinkKind::Command => {
+            let command = path(&options.recording_command, "recording_command")?;
+            let id      = id.to_owned();
+
+            Box::new(QueuedSink::open(command.display().to_string(), budget, move || {
+                Ok(Box::new(CommandSink::spawn(&command, &id, ownership.umask)?))
+            })?)
+        },
+
+        RecordingSinkKind::Socket => {
+            let socket = path(&options.recording_socket, "recording_socket")?;
+
+            Box::new(QueuedSink::open(socket.display().to_string(), budget, move || {
+                Ok(Box::new(SocketSink::connect(&socket)?))
+            })?)
+        },
+
+        #[cfg(feature = "http")]
+        RecordingSinkKind::Http => Box::new(crate::upload::HttpSink::create(options, ownership, name)?),
+
+        #[cfg(not(feature = "http"))]
+        RecordingSinkKind::Http => return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "sudo_pair was built without the http feature",
+        )),
+    };
+
+    Ok(Some(sink))
+}
+
+/// Returns an identifier for the session being run by this process,
+/// unique on this host.
+pub fn session_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    format!("{}-{}", now.as_secs(), std::process::id())
+}
+
+/// The name the session `id`'s recording (and manifest) is given in
+/// `recording_dir`, less its extension: its id, or with the `iolog`
+/// `recording_layout`, the path of sudo's own I/O log of the session
+/// (`iolog_path`) relative to `sudo_iolog_dir` (e.g., `00/00/01`), if
+/// sudo is logging it there.
+pub fn name(options: &PluginOptions, iolog_path: Option<&Path>, id: &str) -> String {
+    if options.recording_layout != RecordingLayout::Iolog {
+        return id.into();
+    }
+
+    iolog_path
+        .and_then(|path| iolog_name(&options.sudo_iolog_dir, path))
+        .unwrap_or_else(|| id.into())
+}
+
+/// The path of sudo's I/O log at `path` relative to `dir`, if it's
+/// somewhere beneath it. Paths that would climb back out (so that a
+/// recording named after them would end up outside `recording_dir`)
+/// aren't named after.
+fn iolog_name(dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(dir).ok()?;
+
+    let components = relative.components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _                       => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if components.is_empty() {
+        return None;
+    }
+
+    Some(components.join("/"))
+}
+
+/// A recording of a session in progress.
+#[derive(Debug)]
+pub struct Recording {
+    sink:  Box<dyn RecordingSink>,
+    clock: Arc<dyn Clock>,
+
+    /// when the recording began, which every timestamp in it is
+    /// measured from on the monotonic clock
+    start: Anchor,
+
+    /// the number of bytes of output recorded so far
+    bytes: u64,
+
+    /// frames recorded but not yet written to the sink, and when they
+    /// last were
+    held:       Vec<u8>,
+    durability: Durability,
+    written_at: Instant,
+}
+
+impl Recording {
+    /// Begins a recording timed by `clock`, writing its header to
+    /// `sink`, and then its frames as `durability` has it.
+    pub fn start(mut sink: Box<dyn RecordingSink>, clock: Arc<dyn Clock>, durability: Durability) -> Result<Self> {
+        sink.write_all(HEADER)?;
+
+        let start = Anchor::now(clock.as_ref());
+
+        Ok(Self {
+            sink,
+            start,
+            bytes:      0,
+            held:       Vec::new(),
+            durability,
+            written_at: clock.instant(),
+            clock,
+        })
+    }
+
+    /// Where the recording is being written.
+    pub fn describe(&self) -> String {
+        self.sink.describe()
+    }
+
+    /// When the recording began.
+    pub fn started(&self) -> SystemTime {
+        self.start.time()
+    }
+
+    /// The current time, as measured from when the recording began, so
+    /// that it agrees with the recording's timestamps even if the wall
+    /// clock has been changed since.
+    pub fn now(&self) -> SystemTime {
+        self.start.time_at(self.clock.instant())
+    }
+
+    /// The number of bytes of output recorded so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Records a chunk of output written to `stream`.
+    pub fn output(&mut self, stream: Frame, data: &[u8]) -> Result<()> {
+        self.frame(stream, data)?;
+        self.bytes += data.len() as u64;
+
+        Ok(())
+    }
+
+    /// Records the size of the user's terminal.
+    pub fn winsize(&mut self, rows: u32, cols: u32) -> Result<()> {
+        let mut data = rows.to_be_bytes().to_vec();
+
+        data.extend_from_slice(&cols.to_be_bytes());
+
+        self.frame(Frame::Winsize, &data)
+    }
+
+    /// Records a description of output that was kept from the
+    /// approver. It isn't counted as output.
+    pub fn suppressed(&mut self, description: &str) -> Result<()> {
+        self.frame(Frame::Suppressed, description.as_bytes())
+    }
+
+    /// Completes the recording with the session's `manifest`.
+    pub fn finish(mut self, manifest: &Manifest) -> Result<()> {
+        let manifest = manifest.to_json();
+
+        self.frame(Frame::Manifest, manifest.as_bytes())?;
+        self.write_held()?;
+        self.sink.finish(manifest.as_bytes())
+    }
+
+    /// Leaves the session's `manifest`, as it stands now, to complete
+    /// the recording with if the session never ends (see
+    /// `RecordingSink::pending`).
+    pub fn pending(&mut self, manifest: &Manifest) -> Result<()> {
+        self.write_held()?;
+        self.sink.pending(manifest.to_json().as_bytes())
+    }
+
+    /// Abandons the recording, since the session's command never ran
+    /// (see `RecordingSink::discard`).
+    pub fn discard(mut self, manifest: &Manifest) -> Result<()> {
+        let manifest = manifest.to_json();
+
+        self.frame(Frame::Manifest, manifest.as_bytes())?;
+        self.write_held()?;
+        self.sink.discard(manifest.as_bytes())
+    }
+
+    fn frame(&mut self, frame: Frame, data: &[u8]) -> Result<()> {
+        let now     = self.clock.instant();
+        let elapsed = micros(now.saturating_duration_since(self.start.instant()));
+
+        // sudo never hands plugins more than a page or so at a time, but
+        // the length still needs to fit in its field
+        for chunk in data.chunks(u32::MAX as usize) {
+            self.held.extend_from_slice(&encode(frame, elapsed, chunk));
+        }
+
+        // since there's no timer, held frames only become due with the
+        // first frame after the interval passes
+        let due = self.durability.fsync == FsyncPolicy::EveryChunk
+            || now.saturating_duration_since(self.written_at) >= self.durability.flush_interval;
+
+        if !due {
+            return Ok(());
+        }
+
+        self.written_at = now;
+
+        self.write_held()?;
+
+        match self.durability.fsync {
+            FsyncPolicy::Never => Ok(()),
+            _                  => self.sink.sync(),
+        }
+    }
+
+    /// Writes every frame being held to the sink. Frames are only ever
+    /// held whole, so a sink that fails partway through a session holds
+    /// only whole frames.
+    fn write_held(&mut self) -> Result<()> {
+        if self.held.is_empty() {
+            return Ok(());
+        }
+
+        let held = mem::take(&mut self.held);
+
+        self.sink.write_all(&held)
+    }
+}
+
+/// Encodes a single frame of `data` (which has to fit in a frame's
+/// length), `elapsed` microseconds into the session.
+pub fn encode(frame: Frame, elapsed: u64, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(13 + data.len());
+
+    buf.push(frame.tag());
+    buf.extend_from_slice(&elapsed.to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Reads the frames of `recording` in order, or returns `None` if it
+/// doesn't begin with `HEADER`.
+pub fn frames(recording: &[u8]) -> Option<Frames<'_>> {
+    if !recording.starts_with(HEADER) {
+        return None;
+    }
+
+    Some(Frames { recording, len: HEADER.len() })
+}
+
+/// The whole frames of a recording (see `frames`), each as its tag, the
+/// time since the session began, and its data. A frame that was cut
+/// short (e.g., by a session that was killed) ends them.
+#[derive(Clone, Debug)]
+pub struct Frames<'a> {
+    recording: &'a [u8],
+
+    /// the length of the recording up to the end of the last frame read
+    pub len: usize,
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = (u8, Duration, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.recording.get(self.len .. self.len + 13)?;
+
+        let mut elapsed = [0; 8];
+        let mut len     = [0; 4];
+
+        elapsed.copy_from_slice(&frame[1..9]);
+        len.copy_from_slice(&frame[9..13]);
+
+        let start = self.len + 13;
+        let data  = self.recording.get(start .. start + u32::from_be_bytes(len) as usize)?;
+
+        self.len = start + data.len();
+
+        Some((frame[0], Duration::from_micros(u64::from_be_bytes(elapsed)), data))
+    }
+}
+
+pub fn micros(duration: Duration) -> u64 {
+    duration.as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(u64::from(duration.subsec_micros()))
+}
+
+/// Writes recordings to files in a local directory. Each recording is
+/// locked for as long as it's open, so that recovery can tell it from
+/// one abandoned by a session that died.
+#[derive(Debug)]
+pub struct FileSink {
+    file:      File,
+    ownership: Ownership,
+
+    /// where the recording is being written
+    pub path: PathBuf,
+
+    /// the recording's name, relative to the directory it's in and
+    /// without its extension
+    pub name: String,
+}
+
+impl FileSink {
+    /// Creates a new recording named `name` in `dir`, creating the
+    /// directory if needed, and gives it `ownership`. Names with
+    /// several components (e.g., `00/00/01`) are created in
+    /// subdirectories, which are made just as `dir` is.
+    pub fn create(dir: &Path, ownership: Ownership, name: &str) -> Result<Self> {
+        ownership.create_dir(dir)?;
+
+        let mut parent = dir.to_path_buf();
+
+        for component in Path::new(name).parent().iter().flat_map(|path| path.components()) {
+            parent.push(component);
+            ownership.create_dir(&parent)?;
+        }
+
+        let path = dir.join(format!("{}.rec", name));
+
+        // never follow a symlink someone else may have planted, or
+        // append to someone else's recording
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        ownership.apply(&file)?;
+        recovery::lock(&file)?;
+
+        Ok(Self { file, ownership, path, name: name.into() })
+    }
+}
+
+impl Write for FileSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+impl RecordingSink for FileSink {
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn finish(self: Box<Self>, _: &[u8]) -> Result<()> {
+        self.file.sync_all()?;
+
+        recovery::remove_marker(&self.path)
+    }
+
+    fn discard(self: Box<Self>, _: &[u8]) -> Result<()> {
+        fs::remove_file(&self.path)?;
+
+        recovery::remove_marker(&self.path)
+    }
+
+    fn pending(&mut self, manifest: &[u8]) -> Result<()> {
+        // the header has to be on disk before the marker, or recovery
+        // may find a marker beside an empty recording
+        self.file.sync_all()?;
+
+        recovery::write_marker(&self.path, self.ownership, manifest)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()
+    }
+}
+
+/// Pipes recordings to the standard input of a command (e.g., one that
+/// uploads them somewhere central).
+#[derive(Debug)]
+pub struct CommandSink {
+    command: PathBuf,
+    child:   Child,
+    stdin:   ChildStdin,
+}
+
+impl CommandSink {
+    /// Runs `command` with the session `id` as its only argument. It
+    /// runs as root, so it's given a minimal environment rather than
+    /// inheriting one the invoking user could have influenced, and
+    /// `umask` rather than the one `sudo` was run with.
+    fn spawn(command: &Path, id: &str, umask: mode_t) -> Result<Self> {
+        let mut process = Command::new(command);
+
+        let _ = process
+            .arg(id)
+            .env_clear()
+            .env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+            .current_dir("/")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        // umask is async-signal-safe, and can't fail
+        unsafe {
+            let _ = process.pre_exec(move || {
+                let _ = libc::umask(umask);
+                Ok(())
+            });
+        }
+
+        let mut child = process.spawn()?;
+
+        let stdin = child.stdin.take()
+            .ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "recording command has no stdin"))?;
+
+        Ok(Self { command: command.to_path_buf(), child, stdin })
+    }
+}
+
+impl Write for CommandSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl RecordingSink for CommandSink {
+    fn describe(&self) -> String {
+        format!("{} (pid {})", self.command.display(), self.child.id())
+    }
+
+    fn finish(self: Box<Self>, _: &[u8]) -> Result<()> {
+        let Self { command, mut child, stdin } = *self;
+
+        // closing its input tells the command the recording is complete
+        drop(stdin);
+
+        let status = child.wait()?;
+
+        // the command may not have read (or kept) all of it
+        if !status.success() {
+            return Err(Error::new(
+                ErrorKind::BrokenPipe,
+                format!("{} exited with {}", command.display(), status),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Streams recordings to a UNIX socket (e.g., one a local collector is
+/// listening on).
+#[derive(Debug)]
+pub struct SocketSink {
+    stream: UnixStream,
+    path:   PathBuf,
+}
+
+impl SocketSink {
+    fn connect(path: &Path) -> Result<Self> {
+        // only a socket root controls should receive recordings, since
+        // they may contain anything the command printed
+        let metadata = fs::symlink_metadata(path)?;
+
+        if metadata.uid() != 0 {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} isn't owned by root", path.display()),
+            ));
+        }
+
+        Ok(Self { stream: UnixStream::connect(path)?, path: path.to_path_buf() })
+    }
+}
+
+impl Write for SocketSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl RecordingSink for SocketSink {
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn finish(self: Box<Self>, _: &[u8]) -> Result<()> {
+        self.stream.shutdown(Shutdown::Write)
+    }
+}
+
+/// How long a `QueuedSink` may take to open and to complete its
+/// recording, and how far behind it may fall in between. A budget of
+/// zero waits for as long as it takes.
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    pub open:  Duration,
+    pub close: Duration,
+
+    /// the most bytes queued and not yet written, or zero if there's
+    /// no limit
+    pub queue: usize,
+
+    /// whether the sink must open within `open`, rather than the
+    /// session going ahead while it's still opening
+    pub confirmed: bool,
+}
+
+/// What the thread delivering a `QueuedSink`'s recording is asked to do.
+#[derive(Debug)]
+enum Request {
+    Write(Vec<u8>),
+    Finish(Vec<u8>),
+}
+
+/// What a `QueuedSink` and the thread delivering its recording share.
+#[derive(Debug, Default)]
+struct Delivery {
+    /// the number of bytes queued and not yet written
+    queued: usize,
+
+    /// why the sink failed, if it has
+    error: Option<(ErrorKind, String)>,
+
+    /// what the sink describes itself as, once it's open
+    description: Option<String>,
+}
+
+/// A sink that's opened, written to, and completed on a thread of its
+/// own, so that one slow to open or to accept what's written to it
+/// (e.g., a collector that's stopped reading) can't stall the session.
+///
+/// Opening the sink is waited on for at most the budget, after which
+/// the session goes ahead without it (unless the budget requires it be
+/// confirmed open, in which case that's an error too); a sink that
+/// fails to open within the budget is an error, and one that fails
+/// after it fails the next write. Output is queued in memory until it can be written, and a
+/// sink that falls further behind than the budget allows fails the
+/// write that would have put it there.
+#[derive(Debug)]
+pub struct QueuedSink {
+    /// what the recording is being delivered to, until the sink can
+    /// describe itself
+    target: String,
+    budget: Budget,
+
+    requests: Sender<Request>,
+    finished: Receiver<Result<()>>,
+    delivery: Arc<Mutex<Delivery>>,
+}
+
+impl QueuedSink {
+    /// Starts opening a sink with `open` on a thread of its own,
+    /// delivering the recording to `target`, and waits for it within
+    /// `budget`.
+    pub fn open<F>(target: String, budget: Budget, open: F) -> Result<Self>
+        where F: FnOnce() -> Result<Box<dyn RecordingSink>> + Send + 'static
+    {
+        let (requests, received) = mpsc::channel();
+        let (opened,   opening)  = mpsc::channel();
+        let (finish,   finished) = mpsc::channel();
+
+        let delivery = Arc::new(Mutex::new(Delivery::default()));
+        let shared   = Arc::clone(&delivery);
+
+        // unlike the session's other threads, this one isn't joined
+        // when the session closes: one still delivering the recording
+        // once the budget has run out is abandoned, and ends with sudo
+        let _ = thread::Builder::new()
+            .name("recording".into())
+            .spawn(move || deliver(open, &received, &opened, &finish, &shared))?;
+
+        match within(&opening, budget.open) {
+            Some(Err(e))             => return Err(e),
+            None if budget.confirmed => return Err(Error::new(ErrorKind::TimedOut, format!(
+                "{} couldn't be confirmed open within {:?}",
+                target,
+                budget.open,
+            ))),
+            _                        => {},
+        }
+
+        Ok(Self { target, budget, requests, finished, delivery })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Delivery> {
+        self.delivery.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The error the sink failed with, if it has.
+    fn error(&self) -> Option<Error> {
+        self.lock().error.as_ref().map(|(kind, message)| Error::new(*kind, message.clone()))
+    }
+
+    fn send(&self, request: Request) -> Result<()> {
+        self.requests.send(request).map_err(|_| self.error().unwrap_or_else(||
+            Error::new(ErrorKind::BrokenPipe, format!("delivery to {} ended unexpectedly", self.target))
+        ))
+    }
+}
+
+/// Opens a sink with `open` and carries out what's `requested` of it,
+/// until it's finished or fails.
+fn deliver<F>(
+    open:      F,
+    requested: &Receiver<Request>,
+    opened:    &Sender<Result<()>>,
+    finish:    &Sender<Result<()>>,
+    delivery:  &Mutex<Delivery>,
+) where F: FnOnce() -> Result<Box<dyn RecordingSink>> {
+    let lock = || delivery.lock().unwrap_or_else(|e| e.into_inner());
+    let fail = |e: &Error| lock().error = Some((e.kind(), e.to_string()));
+
+    let mut sink = match open() {
+        Ok(sink) => sink,
+        Err(e)   => {
+            fail(&e);
+            let _ = opened.send(Err(e));
+            return;
+        },
+    };
+
+    lock().description = Some(sink.describe());
+    let _ = opened.send(Ok(()));
+
+    while let Ok(request) = requested.recv() {
+        match request {
+            Request::Write(data) => {
+                let written = sink.write_all(&data);
+
+                lock().queued -= data.len();
+
+                if let Err(e) = written {
+                    return fail(&e);
+                }
+            },
+
+            Request::Finish(manifest) => {
+                let _ = finish.send(sink.finish(&manifest));
+                return;
+            },
+        }
+    }
+}
+
+/// Waits for a result from `receiver` for at most `budget` (or for as
+/// long as it takes, if it's zero), returning `None` if it runs out.
+pub fn within<T>(receiver: &Receiver<Result<T>>, budget: Duration) -> Option<Result<T>> {
+    let received = match budget {
+        Duration::ZERO => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        budget         => receiver.recv_timeout(budget),
+    };
+
+    match received {
+        Ok(result)                          => Some(result),
+        Err(RecvTimeoutError::Timeout)      => None,
+        Err(RecvTimeoutError::Disconnected) => Some(Err(Error::new(
+            ErrorKind::BrokenPipe,
+            "the thread doing it ended unexpectedly",
+        ))),
+    }
+}
+
+impl Write for QueuedSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if let Some(e) = self.error() {
+            return Err(e);
+        }
+
+        {
+            let mut delivery = self.lock();
+
+            if self.budget.queue != 0 && delivery.queued + buf.len() > self.budget.queue {
+                return Err(Error::new(ErrorKind::WouldBlock, format!(
+                    "{} fell more than {} bytes behind",
+                    delivery.description.as_ref().unwrap_or(&self.target),
+                    self.budget.queue,
+                )));
+            }
+
+            delivery.queued += buf.len();
+        }
+
+        self.send(Request::Write(buf.to_vec()))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl RecordingSink for QueuedSink {
+    fn describe(&self) -> String {
+        self.lock().description.clone().unwrap_or_else(|| self.target.clone())
+    }
+
+    fn finish(self: Box<Self>, manifest: &[u8]) -> Result<()> {
+        self.send(Request::Finish(manifest.to_vec()))?;
+
+        match within(&self.finished, self.budget.close) {
+            Some(Ok(()))  => Ok(()),
+            Some(Err(e))  => Err(self.error().unwrap_or(e)),
+            None          => Err(Error::new(ErrorKind::TimedOut, format!(
+                "{} didn't receive the whole recording within {:?}",
+                self.describe(),
+                self.budget.close,
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::scratch;
+    use sudo_pair_types::manifest::Outcome;
+
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// Splits a recording back into its frames.
+    fn frames(mut recording: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        assert!(recording.starts_with(HEADER));
+
+        recording = &recording[HEADER.len()..];
+
+        let mut frames = Vec::new();
+
+        while !recording.is_empty() {
+            let mut len = [0; 4];
+
+            len.copy_from_slice(&recording[9..13]);
+
+            let len = u32::from_be_bytes(len) as usize;
+
+            frames.push((recording[0], recording[13 .. 13 + len].to_vec()));
+            recording = &recording[13 + len ..];
+        }
+
+        frames
+    }
+
+    fn record(sink: Box<dyn RecordingSink>) {
+        recorded(sink).finish(&Manifest::example()).unwrap();
+    }
+
+    /// A recording of a short session, which is yet to be finished.
+    fn recorded(sink: Box<dyn RecordingSink>) -> Recording {
+        let mut recording = Recording::start(sink, Arc::new(SystemClock), Durability::IMMEDIATE).unwrap();
+
+        recording.winsize(24, 80).unwrap();
+        recording.output(Frame::TtyOut, b"\x1b[1mhello\x1b[0m\r\n").unwrap();
+        recording.output(Frame::StdErr, b"\xff\x00").unwrap();
+        recording.suppressed("2 bytes of binary output suppressed").unwrap();
+
+        assert_eq!(17, recording.bytes());
+
+        recording
+    }
+
+    /// A budget that waits `open` for sinks to open, and `close` for
+    /// them to finish.
+    fn budget(open: u64, close: u64, queue: usize) -> Budget {
+        Budget {
+            open:  Duration::from_millis(open),
+            close: Duration::from_millis(close),
+            queue,
+
+            confirmed: false,
+        }
+    }
+
+    /// A sink that accepts everything written to it, but never finishes
+    /// until it's `released`.
+    #[derive(Debug)]
+    struct Stalled {
+        released: Receiver<()>,
+    }
+
+    impl Write for Stalled {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RecordingSink for Stalled {
+        fn describe(&self) -> String {
+            "stalled".into()
+        }
+
+        fn finish(self: Box<Self>, _manifest: &[u8]) -> Result<()> {
+            let _ = self.released.recv();
+            Ok(())
+        }
+    }
+
+    /// A sink that keeps what's written to it, and counts how many
+    /// times it's synced.
+    #[derive(Clone, Debug, Default)]
+    struct Memory(Arc<Mutex<(Vec<u8>, usize)>>);
+
+    impl Memory {
+        fn written(&self) -> Vec<u8> {
+            self.0.lock().unwrap().0.clone()
+        }
+
+        fn syncs(&self) -> usize {
+            self.0.lock().unwrap().1
+        }
+    }
+
+    impl Write for Memory {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.lock().unwrap().0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RecordingSink for Memory {
+        fn describe(&self) -> String {
+            "memory".into()
+        }
+
+        fn finish(self: Box<Self>, _manifest: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            self.0.lock().unwrap().1 += 1;
+            Ok(())
+        }
+    }
+
+    fn assert_recorded(recording: &[u8]) {
+        assert_eq!(vec![
+            (b'w', vec![0, 0, 0, 24, 0, 0, 0, 80]),
+            (b'o', b"\x1b[1mhello\x1b[0m\r\n".to_vec()),
+            (b'2', b"\xff\x00".to_vec()),
+            (b's', b"2 bytes of binary output suppressed".to_vec()),
+            (b'm', Manifest::example().to_json().into_bytes()),
+        ], frames(recording));
+    }
+
+    #[test]
+    fn records_to_files() {
+        let dir  = scratch::dir("recording", "file", false);
+        let sink = FileSink::create(&dir, Ownership::default(), "1-2").unwrap();
+        let path = sink.path.clone();
+
+        record(Box::new(sink));
+
+        assert_recorded(&fs::read(&path).unwrap());
+        assert_eq!(0o700, fs::metadata(&dir) .unwrap().permissions().mode() & 0o777);
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        // recordings are never reopened or overwritten
+        assert!(FileSink::create(&dir, Ownership::default(), "1-2").is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn records_to_nested_files() {
+        let dir  = scratch::dir("recording", "nested", false);
+        let sink = FileSink::create(&dir, Ownership::default(), "00/00/01").unwrap();
+        let path = sink.path.clone();
+
+        record(Box::new(sink));
+
+        assert_eq!(dir.join("00/00/01.rec"), path);
+        assert_recorded(&fs::read(&path).unwrap());
+        assert_eq!(0o700, fs::metadata(dir.join("00/00")).unwrap().permissions().mode() & 0o777);
+
+        // a sibling shares its parents
+        assert!(FileSink::create(&dir, Ownership::default(), "00/00/02").is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn discards_files() {
+        let dir  = scratch::dir("recording", "discarded", false);
+        let sink = FileSink::create(&dir, Ownership::default(), "unrun").unwrap();
+        let path = sink.path.clone();
+
+        let mut manifest = Manifest::example();
+
+        manifest.outcome = Outcome::NotRun(2);
+
+        Recording::start(Box::new(sink), Arc::new(SystemClock), Durability::IMMEDIATE).unwrap().discard(&manifest).unwrap();
+
+        assert!(!path.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn names_recordings_after_sudos_io_logs() {
+        let dir = Path::new("/var/log/sudo-io");
+
+        assert_eq!(Some("00/00/01".into()),    iolog_name(dir, Path::new("/var/log/sudo-io/00/00/01")));
+        assert_eq!(Some("alice/vim-1".into()), iolog_name(dir, Path::new("/var/log/sudo-io/alice/vim-1")));
+        assert_eq!(None,                       iolog_name(dir, Path::new("/var/log/sudo-iox/00/00/01")));
+        assert_eq!(None,                       iolog_name(dir, Path::new("/var/log/sudo-io/../secret")));
+        assert_eq!(None,                       iolog_name(dir, Path::new("/var/log/sudo-io")));
+        assert_eq!(None,                       iolog_name(dir, Path::new("/tmp/00/00/01")));
+    }
+
+    #[test]
+    fn records_with_configured_ownership() {
+        let dir  = scratch::dir("recording", "ownership", false);
+        let gid  = unsafe { libc::getegid() };
+        let sink = FileSink::create(&dir, Ownership { gid: Some(gid), mode: 0o640, ..Ownership::default() }, "1-2").unwrap();
+        let path = sink.path.clone();
+
+        record(Box::new(sink));
+
+        assert_eq!(0o750, fs::metadata(&dir) .unwrap().permissions().mode() & 0o777);
+        assert_eq!(0o640, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        assert_eq!(gid,   fs::metadata(&path).unwrap().gid());
+
+        // an existing directory is left as it is
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o711)).unwrap();
+
+        let _ = FileSink::create(&dir, Ownership::default(), "3-4").unwrap();
+
+        assert_eq!(0o711, fs::metadata(&dir).unwrap().permissions().mode() & 0o777);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn times_frames_with_the_monotonic_clock() {
+        let dir   = scratch::dir("recording", "clock", false);
+        let sink  = FileSink::create(&dir, Ownership::default(), "1-2").unwrap();
+        let path  = sink.path.clone();
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let clock = ManualClock::at(start);
+
+        let mut recording = Recording::start(Box::new(sink), Arc::new(clock.clone()), Durability::IMMEDIATE).unwrap();
+
+        clock.advance(Duration::from_millis(1500));
+        recording.output(Frame::TtyOut, b"a").unwrap();
+
+        // the wall clock is stepped back a day, as NTP might
+        clock.set_wall(start - Duration::from_secs(86_400));
+        clock.advance(Duration::from_secs(2));
+        recording.output(Frame::TtyOut, b"b").unwrap();
+
+        assert_eq!(start,                                recording.started());
+        assert_eq!(start + Duration::from_millis(3500), recording.now());
+
+        recording.finish(&Manifest::example()).unwrap();
+
+        let recorded = fs::read(&path).unwrap();
+        let elapsed  = |at: usize| {
+            let mut micros = [0; 8];
+
+            micros.copy_from_slice(&recorded[at + 1 .. at + 9]);
+            u64::from_be_bytes(micros)
+        };
+
+        assert_eq!(1_500_000, elapsed(HEADER.len()));
+        assert_eq!(3_500_000, elapsed(HEADER.len() + 14));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn holds_frames_for_the_flush_interval() {
+        let clock = ManualClock::at(UNIX_EPOCH);
+        let sink  = Memory::default();
+
+        let mut recording = Recording::start(Box::new(sink.clone()), Arc::new(clock.clone()), Durability {
+            flush_interval: Duration::from_secs(1),
+            fsync:          FsyncPolicy::Interval,
+        }).unwrap();
+
+        recording.output(Frame::TtyOut, b"a").unwrap();
+
+        assert_eq!(HEADER, &sink.written()[..]);
+
+        // the frame held back is written with the first one once the
+        // interval has passed, and they're synced together
+        clock.advance(Duration::from_secs(1));
+        recording.output(Frame::TtyOut, b"b").unwrap();
+
+        assert_eq!(vec![(b'o', b"a".to_vec()), (b'o', b"b".to_vec())], frames(&sink.written()));
+        assert_eq!(1, sink.syncs());
+
+        recording.output(Frame::TtyOut, b"c").unwrap();
+
+        assert_eq!(2, frames(&sink.written()).len());
+
+        // nothing is held back once the recording is complete
+        recording.finish(&Manifest::example()).unwrap();
+
+        assert_eq!(4, frames(&sink.written()).len());
+    }
+
+    #[test]
+    fn syncs_as_configured() {
+        let recorded = |fsync| {
+            let sink = Memory::default();
+
+            let mut recording = Recording::start(Box::new(sink.clone()), Arc::new(SystemClock), Durability {
+                flush_interval: Duration::from_secs(60),
+                fsync,
+            }).unwrap();
+
+            recording.output(Frame::TtyOut, b"a").unwrap();
+            recording.output(Frame::TtyOut, b"b").unwrap();
+
+            (frames(&sink.written()).len(), sink.syncs())
+        };
+
+        // each frame is written and synced at once, whatever the
+        // interval
+        assert_eq!((2, 2), recorded(FsyncPolicy::EveryChunk));
+        assert_eq!((0, 0), recorded(FsyncPolicy::Interval));
+        assert_eq!((0, 0), recorded(FsyncPolicy::Never));
+
+        let sink = Memory::default();
+
+        let mut recording = Recording::start(Box::new(sink.clone()), Arc::new(SystemClock), Durability::IMMEDIATE).unwrap();
+
+        recording.output(Frame::TtyOut, b"a").unwrap();
+
+        assert_eq!((1, 0), (frames(&sink.written()).len(), sink.syncs()));
+    }
+
+    #[test]
+    fn records_to_commands() {
+        let dir  = scratch::dir("recording", "command", false);
+        let out  = dir.join("out");
+        let cmd  = dir.join("upload");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&cmd, format!("#!/bin/sh\ncat > {}.$1\n", out.display())).unwrap();
+        fs::set_permissions(&cmd, fs::Permissions::from_mode(0o755)).unwrap();
+
+        record(Box::new(CommandSink::spawn(&cmd, "1-2", 0o077).unwrap()));
+
+        assert_recorded(&fs::read(dir.join("out.1-2")).unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn runs_commands_with_the_recording_umask() {
+        let dir  = scratch::dir("recording", "command-umask", false);
+        let out  = dir.join("out");
+        let cmd  = dir.join("upload");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&cmd, format!("#!/bin/sh
+umask > {}.$1
+cat > /dev/null
+", out.display())).unwrap();
+        fs::set_permissions(&cmd, fs::Permissions::from_mode(0o755)).unwrap();
+
+        record(Box::new(CommandSink::spawn(&cmd, "1-2", 0o027).unwrap()));
+
+        assert_eq!("0027", fs::read_to_string(dir.join("out.1-2")).unwrap().trim());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reports_failed_commands() {
+        let sink = CommandSink::spawn(Path::new("/bin/false"), "1-2", 0o077).unwrap();
+
+        assert!(Box::new(sink).finish(b"{}").is_err());
+    }
+
+    #[test]
+    fn records_to_sockets() {
+        let dir  = scratch::dir("recording", "socket", false);
+        let path = dir.join("recorder.sock");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let receiver = thread::spawn(move || {
+            let mut recording = Vec::new();
+            let _ = listener.accept().unwrap().0.read_to_end(&mut recording).unwrap();
+            recording
+        });
+
+        // only sockets owned by root are trusted with recordings
+        if unsafe { libc::geteuid() } != 0 {
+            assert!(SocketSink::connect(&path).is_err());
+            drop(UnixStream::connect(&path).unwrap());
+            let _ = receiver.join().unwrap();
+            let _ = fs::remove_dir_all(&dir);
+            return;
+        }
+
+        record(Box::new(SocketSink::connect(&path).unwrap()));
+
+        assert_recorded(&receiver.join().unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn queues_output_for_sinks_slow_to_open() {
+        let dir  = scratch::dir("recording", "queued", false);
+        let out  = dir.join("out");
+        let cmd  = dir.join("upload");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&cmd, format!("#!/bin/sh\ncat > {}.$1\n", out.display())).unwrap();
+        fs::set_permissions(&cmd, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let (release, released) = mpsc::channel();
+
+        let sink = QueuedSink::open("upload".into(), budget(10, 10_000, 1024), move || {
+            released.recv().unwrap();
+            Ok(Box::new(CommandSink::spawn(&cmd, "1-2", 0o077)?))
+        }).unwrap();
+
+        // the session goes ahead without waiting for the sink
+        assert_eq!("upload", sink.describe());
+
+        let recording = recorded(Box::new(sink));
+
+        release.send(()).unwrap();
+        recording.finish(&Manifest::example()).unwrap();
+
+        assert_recorded(&fs::read(dir.join("out.1-2")).unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn requires_confirmed_sinks_to_open_within_budget() {
+        let (_release, released) = mpsc::channel::<()>();
+
+        let error = QueuedSink::open("upload".into(), Budget { confirmed: true, ..budget(10, 10, 0) }, move || {
+            let _ = released.recv();
+            Err(Error::other("never opened"))
+        }).unwrap_err();
+
+        assert_eq!(ErrorKind::TimedOut, error.kind());
+        assert_eq!("upload couldn't be confirmed open within 10ms", error.to_string());
+    }
+
+    #[test]
+    fn fails_sinks_that_fall_behind() {
+        let (_release, released) = mpsc::channel::<()>();
+
+        let mut sink = QueuedSink::open("upload".into(), budget(10, 10, 16), move || {
+            let _ = released.recv();
+            Err(Error::other("never opened"))
+        }).unwrap();
+
+        sink.write_all(&[0; 10]).unwrap();
+
+        assert_eq!(ErrorKind::WouldBlock, sink.write_all(&[0; 10]).unwrap_err().kind());
+    }
+
+    #[test]
+    fn reports_sinks_that_fail_to_open() {
+        // within the budget, failing to open fails the recording's start
+        let error = QueuedSink::open("upload".into(), budget(10_000, 10, 0), || {
+            Err(Error::new(ErrorKind::ConnectionRefused, "refused"))
+        }).unwrap_err();
+
+        assert_eq!(ErrorKind::ConnectionRefused, error.kind());
+
+        // after it, it fails the recording when it's finished
+        let (release, released) = mpsc::channel();
+
+        let sink = QueuedSink::open("upload".into(), budget(10, 10_000, 0), move || {
+            released.recv().unwrap();
+            Err(Error::new(ErrorKind::ConnectionRefused, "refused"))
+        }).unwrap();
+
+        release.send(()).unwrap();
+
+        let error = Box::new(sink).finish(b"{}").unwrap_err();
+
+        assert_eq!(ErrorKind::ConnectionRefused, error.kind());
+        assert_eq!("refused", error.to_string());
+    }
+
+    #[test]
+    fn abandons_sinks_that_never_finish() {
+        let (release, released) = mpsc::channel();
+
+        let sink = QueuedSink::open("stalled".into(), budget(10_000, 10, 0), move || {
+            Ok(Box::new(Stalled { released }))
+        }).unwrap();
+
+        let error = Box::new(sink).finish(b"{}").unwrap_err();
+
+        assert_eq!(ErrorKind::TimedOut, error.kind());
+        assert!(error.to_string().starts_with("stalled didn't receive"));
+
+        drop(release);
+    }
+}
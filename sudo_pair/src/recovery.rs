@@ -0,0 +1,402 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Completing the recordings of sessions that never finished.
+//!
+//! A recording only gets its manifest when sudo closes the plugin, so if
+//! sudo is killed outright (e.g., by the OOM killer), the recording just
+//! stops, with nothing in it to say who ran what or that it was cut
+//! short. To keep that from going unnoticed, each recording kept in
+//! `recording_dir` has a marker written ahead of it (`<name>.pending`),
+//! holding the session's manifest as it stood when the session began,
+//! and the recording is locked for as long as the session runs. The
+//! marker is only removed once the recording is complete.
+//!
+//! A marker beside a recording nobody holds the lock on was left by a
+//! session that died. `sudo_pair_check` reports them, and with
+//! `--recover`, completes each recording with the marker's manifest,
+//! flagged `incomplete` and brought up to date with how far the
+//! recording got.
+
+use crate::ownership::Ownership;
+use crate::recording::{self, Frame, HEADER};
+
+use sudo_pair_types::manifest::{parse_utc, utc};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The extension of the marker written beside each recording.
+pub const EXTENSION : &str = "pending";
+
+/// The marker for the recording at `recording`.
+pub fn marker(recording: &Path) -> PathBuf {
+    recording.with_extension(EXTENSION)
+}
+
+/// Locks `file` (a recording) until it's closed, which happens at the
+/// latest when the process holding it dies, so that a recording still
+/// being written isn't mistaken for one that was abandoned.
+pub fn lock(file: &File) -> Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Writes the marker for the recording at `recording`, holding the
+/// session's `manifest` as it stands, and gives it `ownership`.
+pub fn write_marker(recording: &Path, ownership: Ownership, manifest: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(marker(recording))?;
+
+    ownership.apply(&file)?;
+
+    file.write_all(manifest)?;
+    file.sync_all()
+}
+
+/// Removes the marker for the recording at `recording`, if it has one.
+pub fn remove_marker(recording: &Path) -> Result<()> {
+    match fs::remove_file(marker(recording)) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        result                                        => result,
+    }
+}
+
+/// A recording left incomplete by a session that never finished.
+#[derive(Debug)]
+pub struct Incomplete {
+    pub recording: PathBuf,
+}
+
+/// Finds every recording in `dir` (or in the directories beneath it)
+/// whose marker was left behind by a session that's no longer running.
+/// Symlinks aren't followed. Only `sudo_pair_check` looks for them; the
+/// plugin just leaves the markers.
+pub fn find(dir: &Path) -> Result<Vec<Incomplete>> {
+    let mut incomplete = Vec::new();
+    let mut dirs       = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+            entries                                       => entries?,
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let path  = entry.path();
+
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+
+            if path.extension().is_none_or(|extension| extension != EXTENSION) {
+                continue;
+            }
+
+            let recording = path.with_extension("rec");
+
+            if !is_locked(&recording)? {
+                incomplete.push(Incomplete { recording });
+            }
+        }
+    }
+
+    incomplete.sort_by(|a, b| a.recording.cmp(&b.recording));
+
+    Ok(incomplete)
+}
+
+/// Whether someone (i.e., the session recording it) holds the lock on
+/// the recording at `path`.
+fn is_locked(path: &Path) -> Result<bool> {
+    let file = match File::open(path) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        file                                          => file?,
+    };
+
+    match lock(&file) {
+        Ok(())                                          => Ok(false),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(true),
+        Err(e)                                          => Err(e),
+    }
+}
+
+impl Incomplete {
+    /// Completes the recording with the manifest in its marker, then
+    /// removes the marker. Anything after the last whole frame (i.e., a
+    /// frame sudo was killed partway through writing) is cut off first.
+    /// A recording that was already complete (because sudo was killed
+    /// just before it could remove the marker) is left as it is, and a
+    /// marker without a recording (because one was being discarded) is
+    /// simply removed.
+    pub fn recover(&self) -> Result<()> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(&self.recording) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return remove_marker(&self.recording),
+            file                                          => file?,
+        };
+
+        // another recovery may be underway, or the session may have
+        // just started
+        lock(&file)?;
+
+        let mut contents = Vec::new();
+        let _ = file.read_to_end(&mut contents)?;
+
+        let summary = summarize(&contents).ok_or_else(|| Error::new(
+            ErrorKind::InvalidData,
+            "it isn't a sudo_pair recording",
+        ))?;
+
+        if !summary.complete {
+            let manifest = fs::read_to_string(marker(&self.recording))?;
+            let manifest = complete(&manifest, summary.elapsed, summary.output_bytes)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "its marker isn't a manifest"))?;
+
+            file.set_len(summary.len as u64)?;
+            let _ = file.seek(SeekFrom::Start(summary.len as u64))?;
+
+            file.write_all(&recording::encode(
+                Frame::Manifest,
+                recording::micros(summary.elapsed),
+                manifest.as_bytes(),
+            ))?;
+
+            file.sync_all()?;
+        }
+
+        remove_marker(&self.recording)
+    }
+}
+
+/// What recovery needs to know about a recording.
+#[derive(Debug, Eq, PartialEq)]
+struct Summary {
+    /// the length of the recording up to the end of its last whole frame
+    len: usize,
+
+    /// the time of its last whole frame
+    elapsed: Duration,
+
+    /// the number of bytes of output recorded in its whole frames
+    output_bytes: u64,
+
+    /// whether its last whole frame is its manifest
+    complete: bool,
+}
+
+/// Reads through the frames of `recording`, or returns `None` if it
+/// doesn't begin with `HEADER`.
+fn summarize(recording: &[u8]) -> Option<Summary> {
+    let mut frames = recording::frames(recording)?;
+
+    let mut summary = Summary {
+        len:          HEADER.len(),
+        elapsed:      Duration::default(),
+        output_bytes: 0,
+        complete:     false,
+    };
+
+    let output = [Frame::TtyOut.tag(), Frame::StdOut.tag(), Frame::StdErr.tag()];
+
+    while let Some((tag, elapsed, data)) = frames.next() {
+        if output.contains(&tag) {
+            summary.output_bytes += data.len() as u64;
+        }
+
+        summary.len      = frames.len;
+        summary.elapsed  = elapsed;
+        summary.complete = tag == Frame::Manifest.tag();
+    }
+
+    Some(summary)
+}
+
+/// Brings the `manifest` (as JSON) written when a session began up to
+/// date with how long it ran (`elapsed`) and how much output it wrote
+/// (`output_bytes`) before it was cut short.
+///
+/// The manifest is serialized by the plugin itself, so it's amended in
+/// place rather than parsed. Its `started_at`, `ended_at`, and
+/// `output_bytes` are the last keys by those names in it (only
+/// `host_tags` can repeat them, and it comes first), and any quote in a
+/// string is escaped, so the last occurrence of `"<key>":` is always the
+/// key itself.
+fn complete(manifest: &str, elapsed: Duration, output_bytes: u64) -> Option<String> {
+    let started_at = parse_utc(&manifest[value(manifest, "started_at")?])?;
+    let ended_at   = utc(started_at + elapsed);
+
+    let mut manifest = manifest.to_string();
+
+    let range = value(&manifest, "ended_at")?;
+    manifest.replace_range(range, &ended_at);
+
+    let range = value(&manifest, "output_bytes")?;
+    manifest.replace_range(range, &output_bytes.to_string());
+
+    Some(manifest)
+}
+
+/// Where the value of the last `key` in `json` is: a number, or a string
+/// (without its quotes) that doesn't itself contain quotes.
+pub fn value(json: &str, key: &str) -> Option<std::ops::Range<usize>> {
+    let start = json.rfind(&format!("\"{}\":", key))? + key.len() + 3;
+    let rest  = &json[start..];
+
+    if let Some(string) = rest.strip_prefix('"') {
+        let len = string.find('"')?;
+
+        return Some(start + 1 .. start + 1 + len);
+    }
+
+    let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+
+    Some(start .. start + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::SystemClock;
+    use crate::recording::{Durability, FileSink, Recording};
+    use crate::scratch;
+
+    use sudo_pair_types::manifest::{Manifest, Outcome};
+
+    use std::sync::Arc;
+
+    fn pending() -> Manifest {
+        Manifest { outcome: Outcome::Incomplete, ..Manifest::example() }
+    }
+
+    #[test]
+    fn completes_manifests() {
+        let manifest = pending().to_json();
+        let started  = parse_utc(&manifest[value(&manifest, "started_at").unwrap()]).unwrap();
+
+        let completed = complete(&manifest, Duration::from_secs(90), 1234).unwrap();
+
+        assert!(completed.contains(&format!("\"ended_at\":\"{}\"", utc(started + Duration::from_secs(90)))));
+        assert!(completed.contains("\"output_bytes\":1234"));
+        assert!(completed.contains("\"incomplete\":true"));
+
+        assert_eq!(None, complete("{}", Duration::default(), 0));
+    }
+
+    #[test]
+    fn leaves_running_sessions_alone() {
+        let dir = scratch::dir("recovery", "running", false);
+
+        let mut recording = Recording::start(
+            Box::new(FileSink::create(&dir, Ownership::default(), "1-2").unwrap()),
+            Arc::new(SystemClock),
+            Durability::IMMEDIATE,
+        ).unwrap();
+
+        recording.pending(&pending()).unwrap();
+
+        assert!(dir.join("1-2.pending").exists());
+        assert!(find(&dir).unwrap().is_empty());
+
+        recording.finish(&Manifest::example()).unwrap();
+
+        assert!(!dir.join("1-2.pending").exists());
+        assert!(find(&dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recovers_abandoned_recordings() {
+        let dir = scratch::dir("recovery", "abandoned", false);
+
+        let mut recording = Recording::start(
+            Box::new(FileSink::create(&dir.join("00"), Ownership::default(), "01").unwrap()),
+            Arc::new(SystemClock),
+            Durability::IMMEDIATE,
+        ).unwrap();
+
+        recording.pending(&pending()).unwrap();
+        recording.output(Frame::TtyOut, b"hello\r\n").unwrap();
+        recording.output(Frame::StdOut, b"bye\n").unwrap();
+
+        // sudo is killed partway through writing a frame, which closes
+        // the recording (releasing its lock) without finishing it
+        drop(recording);
+
+        let path = dir.join("00/01.rec");
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&recording::encode(Frame::TtyOut, 5, b"cut off")[..10]).unwrap();
+        drop(file);
+
+        let incomplete = find(&dir).unwrap();
+
+        assert_eq!(1, incomplete.len());
+        assert_eq!(path, incomplete[0].recording);
+
+        incomplete[0].recover().unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        let summary  = summarize(&contents).unwrap();
+
+        assert!(summary.complete);
+        assert_eq!(contents.len(), summary.len);
+        assert_eq!(11, summary.output_bytes);
+
+        let manifest = String::from_utf8_lossy(&contents).into_owned();
+
+        assert!(manifest.contains("\"incomplete\":true"));
+        assert!(manifest.contains("\"output_bytes\":11"));
+
+        assert!(!dir.join("00/01.pending").exists());
+        assert!(find(&dir).unwrap().is_empty());
+
+        // recovering again, or a recording that was already completed,
+        // changes nothing
+        fs::write(dir.join("00/01.pending"), pending().to_json()).unwrap();
+
+        find(&dir).unwrap()[0].recover().unwrap();
+
+        assert_eq!(contents, fs::read(&path).unwrap());
+        assert!(!dir.join("00/01.pending").exists());
+    }
+
+    #[test]
+    fn removes_markers_of_discarded_recordings() {
+        let dir = scratch::dir("recovery", "discarded", false);
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("1-2.pending"), pending().to_json()).unwrap();
+
+        let incomplete = find(&dir).unwrap();
+
+        assert_eq!(1, incomplete.len());
+
+        incomplete[0].recover().unwrap();
+
+        assert!(!dir.join("1-2.pending").exists());
+        assert!(find(&dir).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,571 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The sessions running on this host, tracked so that any number of
+//! them can run at once without colliding, and so how many run at once
+//! can be bounded.
+//!
+//! Each session has its own subdirectory of the registry, named for its
+//! ID, holding a `lock` file that the session keeps locked (with
+//! `flock(2)`) until it closes. A subdirectory whose lock can be taken
+//! belonged to a session that ended without cleaning up after itself
+//! (e.g., `sudo` was killed), and is removed the next time sessions are
+//! counted.
+//!
+//! Sessions are counted, claimed, and released while holding an
+//! exclusive lock on the registry's `.lock` file, so two sessions opening
+//! at once can't both take the last slot. Anything else that cleans up
+//! after sessions (e.g., a job pruning leftover recordings or sockets)
+//! should take the same lock, and treat a session as running for as long
+//! as its own lock is held.
+//!
+//! Alongside its lock, each session describes itself (who's running
+//! what, since when) in a `session` file, so administrators can list
+//! what's running on the host (see `sudo_pair_admin`). An administrator
+//! asks a session to end by leaving a `kill` file beside them, naming
+//! who asked and why, which the session notices the next time it's
+//! checked and records before it ends.
+
+use crate::flat_json::{self, Value};
+
+use sudo_pair_types::json::quote;
+
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A session's place in the registry, which it holds until this is
+/// dropped.
+#[derive(Debug)]
+pub struct Slot {
+    registry: PathBuf,
+    dir:      PathBuf,
+
+    /// the session's lock, held for as long as the file is open
+    lock: Option<File>,
+
+    /// the number of other sessions that were running when the slot
+    /// was claimed
+    pub running: u64,
+}
+
+/// What a session describes itself with, for administrators listing
+/// the sessions running on the host.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct About {
+    pub user:       String,
+    pub runas_user: String,
+
+    /// the command's arguments, separated by spaces
+    pub command: String,
+
+    pub started_at: SystemTime,
+}
+
+/// An administrator's request that a session end.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KillRequest {
+    /// who asked, as they were logged in
+    pub requested_by: String,
+    pub reason:       String,
+    pub requested_at: SystemTime,
+}
+
+/// A session that's still running.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Running {
+    pub id: String,
+
+    /// the process that opened the session (i.e., `sudo` itself), if
+    /// it could be read
+    pub pid: Option<libc::pid_t>,
+
+    /// what the session said about itself, if it has yet
+    pub about: Option<About>,
+
+    /// the request that the session end, if one's been made
+    pub kill: Option<KillRequest>,
+}
+
+/// Why a slot couldn't be claimed.
+#[derive(Debug)]
+pub enum ClaimError {
+    /// `running` sessions were already running, which is as many as
+    /// are allowed
+    Full { running: u64 },
+
+    /// the registry couldn't be used
+    Io(io::Error),
+}
+
+impl Slot {
+    /// Claims a slot in `registry` (creating it if it doesn't exist) for
+    /// the session `id`, unless `limit` sessions are already running. A
+    /// `limit` of `0` allows any number.
+    pub fn claim(registry: &Path, id: &str, limit: u64) -> Result<Self, ClaimError> {
+        create_registry(registry)?;
+
+        let _guard = lock(&registry.join(".lock"), libc::LOCK_EX)?;
+
+        let running = running(registry)?.len() as u64;
+
+        if limit != 0 && running >= limit {
+            return Err(ClaimError::Full { running });
+        }
+
+        let dir = registry.join(id);
+
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(dir.join("lock"))?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        // for whoever finds it, since the session ID only identifies the
+        // process that opened the session
+        writeln!(file, "{}", process::id())?;
+
+        Ok(Self {
+            registry: registry.to_path_buf(),
+            dir,
+            lock: Some(file),
+            running,
+        })
+    }
+
+    /// Describes the session for administrators listing the sessions
+    /// running on the host.
+    pub fn describe(&self, about: &About) -> io::Result<()> {
+        let json = format!(
+            "{{{}:{},{}:{},{}:{},{}:{}}}\n",
+            quote("user"),       quote(&about.user),
+            quote("runas_user"), quote(&about.runas_user),
+            quote("command"),    quote(&about.command),
+            quote("started_at"), unix_secs(about.started_at),
+        );
+
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(self.dir.join("session"))?
+            .write_all(json.as_bytes())
+    }
+
+    /// The request that the session end, if an administrator has made
+    /// one.
+    pub fn kill_request(&self) -> Option<KillRequest> {
+        read_kill_request(&self.dir)
+    }
+}
+
+/// Lists the sessions in `registry` that are still running, removing
+/// what's left of those that aren't. A registry that doesn't exist has
+/// nothing running in it.
+pub fn list(registry: &Path) -> io::Result<Vec<Running>> {
+    let _guard = match lock(&registry.join(".lock"), libc::LOCK_EX) {
+        Ok(guard)                                         => guard,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e)                                            => return Err(e),
+    };
+
+    let mut sessions : Vec<_> = running(registry)?.iter()
+        .map(|dir| describe(dir))
+        .collect();
+
+    sessions.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(sessions)
+}
+
+/// Asks the session `id` in `registry` to end, returning it as it was
+/// running when asked. A later request replaces an earlier one.
+pub fn request_kill(registry: &Path, id: &str, request: &KillRequest) -> io::Result<Running> {
+    let missing = || io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no session {} is running", id),
+    );
+
+    if id.is_empty() || id.starts_with('.') || id.contains('/') {
+        return Err(missing());
+    }
+
+    let _guard = match lock(&registry.join(".lock"), libc::LOCK_EX) {
+        Ok(guard)                                         => guard,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(missing()),
+        Err(e)                                            => return Err(e),
+    };
+
+    let dir = registry.join(id);
+
+    if !running(registry)?.contains(&dir) {
+        return Err(missing());
+    }
+
+    let json = format!(
+        "{{{}:{},{}:{},{}:{}}}\n",
+        quote("requested_by"), quote(&request.requested_by),
+        quote("reason"),       quote(&request.reason),
+        quote("requested_at"), unix_secs(request.requested_at),
+    );
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(dir.join("kill"))?
+        .write_all(json.as_bytes())?;
+
+    Ok(describe(&dir))
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        // the subdirectory is removed before the lock is released, so
+        // it's never counted as abandoned while it's being removed
+        let _guard = lock(&self.registry.join(".lock"), libc::LOCK_EX);
+        let _      = fs::remove_dir_all(&self.dir);
+
+        drop(self.lock.take());
+    }
+}
+
+impl Display for ClaimError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ClaimError::Full { running } => write!(f, "{} sessions are already running", running),
+            ClaimError::Io(e)            => e.fmt(f),
+        }
+    }
+}
+
+impl From<io::Error> for ClaimError {
+    fn from(e: io::Error) -> Self {
+        ClaimError::Io(e)
+    }
+}
+
+/// Creates the registry if it doesn't exist, and checks that only root
+/// can change what's in it. Its parent (typically `socket_dir`) is never
+/// created.
+fn create_registry(registry: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(registry) {
+        Ok(())                                                 => {},
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+        Err(e)                                                 => return Err(e),
+    }
+
+    let metadata = fs::symlink_metadata(registry)?;
+
+    // the plugin runs as root, but checking against whoever it's
+    // running as lets the registry be exercised without it
+    let owner = unsafe { libc::geteuid() };
+
+    if !metadata.is_dir() || metadata.uid() != owner || metadata.mode() & 0o022 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} isn't a directory only root can write to", registry.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Opens (creating if needed) the file at `path` and locks it with
+/// `operation`, which is released when the file is closed.
+fn lock(path: &Path, operation: libc::c_int) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(file)
+}
+
+/// Returns the subdirectory of each session in `registry` that's still
+/// running, removing those of sessions that aren't. Must be called with
+/// the registry locked.
+fn running(registry: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut running = Vec::new();
+
+    for entry in fs::read_dir(registry)? {
+        let entry = entry?;
+
+        if entry.file_name().as_bytes().starts_with(b".") || !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        match lock(&entry.path().join("lock"), libc::LOCK_EX | libc::LOCK_NB) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => running.push(entry.path()),
+
+            // slots are only claimed with the registry locked, so a
+            // subdirectory without a lock was left partway through
+            // being claimed or removed
+            _ => { let _ = fs::remove_dir_all(entry.path()); },
+        }
+    }
+
+    Ok(running)
+}
+
+/// Reads what's known about the running session whose subdirectory is
+/// `dir`. Anything that can't be read is left out, since a session only
+/// describes itself once it's been claimed.
+fn describe(dir: &Path) -> Running {
+    let fields = |name| fs::read_to_string(dir.join(name)).ok()
+        .and_then(|json| flat_json::parse(&json));
+
+    let pid = fs::read_to_string(dir.join("lock")).ok()
+        .and_then(|pid| pid.trim().parse().ok());
+
+    let about = fields("session").and_then(|fields| Some(About {
+        user:       string(&fields, "user")?,
+        runas_user: string(&fields, "runas_user")?,
+        command:    string(&fields, "command")?,
+        started_at: time(&fields, "started_at")?,
+    }));
+
+    Running {
+        id:   dir.file_name().map_or_else(String::new, |id| id.to_string_lossy().into_owned()),
+        pid,
+        about,
+        kill: read_kill_request(dir),
+    }
+}
+
+fn read_kill_request(dir: &Path) -> Option<KillRequest> {
+    let fields = flat_json::parse(&fs::read_to_string(dir.join("kill")).ok()?)?;
+
+    Some(KillRequest {
+        requested_by: string(&fields, "requested_by")?,
+        reason:       string(&fields, "reason")?,
+        requested_at: time(&fields, "requested_at")?,
+    })
+}
+
+fn string(fields: &[(String, Value)], key: &str) -> Option<String> {
+    fields.iter().find_map(|(k, value)| match value {
+        Value::Str(s) if k == key => Some(s.clone()),
+        _                         => None,
+    })
+}
+
+fn time(fields: &[(String, Value)], key: &str) -> Option<SystemTime> {
+    fields.iter().find_map(|(k, value)| match value {
+        Value::Uint(secs) if k == key => Some(UNIX_EPOCH + Duration::from_secs(*secs)),
+        _                             => None,
+    })
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+impl Running {
+    /// Serializes the session as a single JSON object.
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![format!("{}:{}", quote("id"), quote(&self.id))];
+
+        if let Some(pid) = self.pid {
+            fields.push(format!("{}:{}", quote("pid"), pid));
+        }
+
+        if let Some(ref about) = self.about {
+            fields.push(format!("{}:{}", quote("user"),       quote(&about.user)));
+            fields.push(format!("{}:{}", quote("runas_user"), quote(&about.runas_user)));
+            fields.push(format!("{}:{}", quote("command"),    quote(&about.command)));
+            fields.push(format!("{}:{}", quote("started_at"), unix_secs(about.started_at)));
+        }
+
+        if let Some(ref kill) = self.kill {
+            fields.push(format!("{}:{}", quote("kill_requested_by"), quote(&kill.requested_by)));
+            fields.push(format!("{}:{}", quote("kill_reason"),       quote(&kill.reason)));
+        }
+
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    fn registry(name: &str) -> PathBuf {
+        scratch::dir("registry", name, false)
+    }
+
+    #[test]
+    fn claims_slots_up_to_the_limit() {
+        let registry = registry("limit");
+
+        let first  = Slot::claim(&registry, "1600000000-1", 2).unwrap();
+        let second = Slot::claim(&registry, "1600000000-2", 2).unwrap();
+
+        assert_eq!(0, first.running);
+        assert_eq!(1, second.running);
+
+        assert!(registry.join("1600000000-1").join("lock").exists());
+
+        match Slot::claim(&registry, "1600000000-3", 2) {
+            Err(ClaimError::Full { running }) => assert_eq!(2, running),
+            other                             => panic!("unexpected claim: {:?}", other),
+        }
+
+        // a slot is freed once its session closes
+        drop(first);
+
+        assert!(!registry.join("1600000000-1").exists());
+
+        let third = Slot::claim(&registry, "1600000000-3", 2).unwrap();
+
+        assert_eq!(1, third.running);
+
+        drop((second, third));
+        fs::remove_dir_all(&registry).unwrap();
+    }
+
+    #[test]
+    fn allows_any_number_without_a_limit() {
+        let registry = registry("unlimited");
+
+        let slots : Vec<_> = (0 .. 5)
+            .map(|i| Slot::claim(&registry, &format!("1600000000-{}", i), 0).unwrap())
+            .collect();
+
+        assert_eq!(4, slots[4].running);
+
+        drop(slots);
+        fs::remove_dir_all(&registry).unwrap();
+    }
+
+    #[test]
+    fn removes_abandoned_slots() {
+        let registry = registry("abandoned");
+
+        // a session that went away without releasing its slot leaves
+        // its subdirectory behind, but nothing holding its lock
+        fs::create_dir_all(registry.join("1600000000-1")).unwrap();
+        fs::write(registry.join("1600000000-1").join("lock"), b"1\n").unwrap();
+
+        let slot = Slot::claim(&registry, "1600000000-2", 1).unwrap();
+
+        assert_eq!(0, slot.running);
+        assert!(!registry.join("1600000000-1").exists());
+
+        drop(slot);
+        fs::remove_dir_all(&registry).unwrap();
+    }
+
+    #[test]
+    fn lists_running_sessions() {
+        let registry = registry("list");
+
+        assert_eq!(Vec::<Running>::new(), list(&registry).unwrap());
+
+        let about = About {
+            user:       "alice".into(),
+            runas_user: "root".into(),
+            command:    "/bin/sh -c \"uptime\"".into(),
+            started_at: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        };
+
+        let first  = Slot::claim(&registry, "1600000000-1", 0).unwrap();
+        let second = Slot::claim(&registry, "1600000000-2", 0).unwrap();
+
+        first.describe(&about).unwrap();
+
+        let running = list(&registry).unwrap();
+
+        assert_eq!(2, running.len());
+        assert_eq!("1600000000-1", running[0].id);
+        assert_eq!(Some(process::id() as libc::pid_t), running[0].pid);
+        assert_eq!(Some(about), running[0].about);
+        assert_eq!(None, running[1].about);
+
+        assert_eq!(
+            format!(
+                "{{\"id\":\"1600000000-1\",\"pid\":{},\"user\":\"alice\",\"runas_user\":\"root\",\"command\":\"/bin/sh -c \\\"uptime\\\"\",\"started_at\":1600000000}}",
+                process::id(),
+            ),
+            running[0].to_json(),
+        );
+
+        drop((first, second));
+
+        assert_eq!(Vec::<Running>::new(), list(&registry).unwrap());
+
+        fs::remove_dir_all(&registry).unwrap();
+    }
+
+    #[test]
+    fn delivers_kill_requests() {
+        let registry = registry("kill");
+        let slot     = Slot::claim(&registry, "1600000000-1", 0).unwrap();
+
+        let request = KillRequest {
+            requested_by: "bob".into(),
+            reason:       "INC-1234".into(),
+            requested_at: UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+        };
+
+        assert_eq!(None, slot.kill_request());
+
+        let killed = request_kill(&registry, "1600000000-1", &request).unwrap();
+
+        assert_eq!("1600000000-1", killed.id);
+        assert_eq!(Some(request.clone()), killed.kill);
+        assert_eq!(Some(request), slot.kill_request());
+
+        // only sessions that are running can be asked to end
+        for id in &["1600000000-2", "..", ".lock", "1600000000-1/lock", ""] {
+            let e = request_kill(&registry, id, &slot.kill_request().unwrap()).unwrap_err();
+
+            assert_eq!(io::ErrorKind::NotFound, e.kind());
+        }
+
+        drop(slot);
+
+        assert!(request_kill(&registry, "1600000000-1", &KillRequest {
+            requested_by: "bob".into(),
+            reason:       "INC-1234".into(),
+            requested_at: UNIX_EPOCH,
+        }).is_err());
+
+        fs::remove_dir_all(&registry).unwrap();
+    }
+}
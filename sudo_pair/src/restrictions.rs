@@ -0,0 +1,140 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Restrictions the policy plugin places on the command, which the
+//! approver should know about since they change how much a session can
+//! do: whether it can run other programs, and for how long.
+
+use sudo_plugin::CommandInfo;
+
+use std::time::Duration;
+
+/// The restrictions sudo's policy placed on the command.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Restrictions {
+    /// the command is prevented from executing other programs
+    pub(crate) noexec: bool,
+
+    /// programs the command executes are checked against the policy
+    pub(crate) intercept: bool,
+
+    /// the command is killed once it has run this long
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl Restrictions {
+    /// The restrictions described by `command_info`.
+    pub(crate) fn of(command_info: &CommandInfo) -> Self {
+        Self {
+            noexec:    command_info.noexec,
+            intercept: command_info.intercept,
+
+            // sudo treats a timeout of zero as no timeout at all
+            timeout: command_info.timeout.filter(|timeout| *timeout != Duration::default()),
+        }
+    }
+
+    /// Describes each restriction in effect (e.g., `noexec enforced`).
+    pub(crate) fn describe(&self) -> Vec<String> {
+        let mut descriptions = Vec::new();
+
+        if self.noexec {
+            descriptions.push("noexec enforced".into());
+        }
+
+        if self.intercept {
+            descriptions.push("subcommands intercepted".into());
+        }
+
+        if let Some(timeout) = self.timeout {
+            descriptions.push(format!("session timeout {}", format(timeout)));
+        }
+
+        descriptions
+    }
+
+    /// Describes every restriction in effect on one line, or `none`.
+    pub(crate) fn summary(&self) -> String {
+        let descriptions = self.describe();
+
+        if descriptions.is_empty() {
+            return "none".into();
+        }
+
+        descriptions.join(", ")
+    }
+}
+
+/// Formats `duration` the way durations are written in options, to the
+/// second (e.g., `1h30m`).
+pub(crate) fn format(duration: Duration) -> String {
+    const UNITS : [(&str, u64); 4] = [
+        ("d", 60 * 60 * 24),
+        ("h", 60 * 60),
+        ("m", 60),
+        ("s", 1),
+    ];
+
+    let mut secs      = duration.as_secs();
+    let mut formatted = String::new();
+
+    for &(unit, length) in &UNITS {
+        if secs >= length {
+            formatted.push_str(&format!("{}{}", secs / length, unit));
+            secs %= length;
+        }
+    }
+
+    if formatted.is_empty() {
+        formatted.push_str("0s");
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations_compactly() {
+        assert_eq!("0s",     format(Duration::from_millis(500)));
+        assert_eq!("45s",    format(Duration::from_secs(45)));
+        assert_eq!("10m",    format(Duration::from_secs(600)));
+        assert_eq!("1h30m",  format(Duration::from_secs(5400)));
+        assert_eq!("1d1h1s", format(Duration::from_secs(90001)));
+    }
+
+    #[test]
+    fn describes_each_restriction() {
+        let restrictions = Restrictions {
+            noexec:    true,
+            intercept: false,
+            timeout:   Some(Duration::from_secs(600)),
+        };
+
+        assert_eq!(
+            vec!["noexec enforced".to_owned(), "session timeout 10m".into()],
+            restrictions.describe(),
+        );
+
+        assert_eq!("noexec enforced, session timeout 10m", restrictions.summary());
+    }
+
+    #[test]
+    fn summarizes_no_restrictions() {
+        assert!(Restrictions::default().describe().is_empty());
+        assert_eq!("none", Restrictions::default().summary());
+    }
+}
@@ -0,0 +1,618 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Rules narrowing which commands require a pair, so routine commands
+//! stay low-friction while dangerous arguments still trigger review.
+//!
+//! Rules are read from a file, one per line, with blank lines and lines
+//! starting with `#` ignored:
+//!
+//! ```text
+//! # any invocation with --force
+//! arg --force
+//!
+//! # `systemctl stop prod-*`, even with other arguments interspersed
+//! command systemctl stop prod-*
+//!
+//! # the whole command line, space-separated
+//! regex ^/usr/bin/ssh .*\bprod[0-9]+\b
+//!
+//! # any session the policy plugin labeled as in PCI scope
+//! label pci-*
+//!
+//! # any session run inside tmux, screen, or zellij
+//! tty multiplexer
+//! ```
+//!
+//! Globs support `*` (any run of bytes), `?` (any one byte), and `\` to
+//! match the next character literally. `tty` rules match the kind of
+//! terminal the session was run from (`none`, `console`, `pty`, or
+//! `multiplexer`), or the multiplexer it was run in (e.g., `tmux`), as
+//! described by `terminal`.
+//!
+//! Rules can also sort commands into risk tiers, each needing its own
+//! quorum of approvers. A rule prefixed with `tier <n>` puts the commands
+//! it matches in that tier (rules without one are in tier 1), and a
+//! session is in the highest tier of any rule its command matches:
+//!
+//! ```text
+//! # two approvers, both in the security group
+//! quorum 2 2 security
+//!
+//! # no approvers at all
+//! tier 0 command systemctl status *
+//! tier 2 command systemctl stop prod-*
+//! ```
+//!
+//! Unless a `quorum <tier> <approvers> [<group>]` line says otherwise,
+//! tier 0 needs no approvers, and every other tier needs one.
+//!
+//! Some commands need a second person present but must never be
+//! recorded (e.g., password managers), while others only need to be
+//! recorded. A rule prefixed with `pair-only` or `record-only` (after
+//! its tier, if it has one) covers the commands it matches with just
+//! that, and the rule that decides a session's tier decides this too:
+//!
+//! ```text
+//! tier 2 pair-only command pass *
+//! record-only command journalctl *
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use regex_lite::Regex;
+
+use sudo_plugin::FromSudoOption;
+
+use crate::options::Group;
+
+/// The rules loaded from a file.
+#[derive(Clone, Debug)]
+pub struct Rules {
+    rules: Vec<Rule>,
+
+    /// the quorums configured for each tier, where they differ from the
+    /// default
+    quorums: HashMap<u8, Quorum>,
+}
+
+/// The approvals a session needs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Quorum {
+    /// how many approvers have to approve the session
+    pub approvers: u32,
+
+    /// the group they have to be able to act as, if not the user (or
+    /// group) the command is run as
+    pub group: Option<Group>,
+}
+
+/// A single rule, which requires a pair for the commands it matches.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    /// the line of the file the rule was read from
+    line: usize,
+
+    /// the rule as written
+    text: String,
+
+    /// the risk tier of the commands it matches
+    tier: u8,
+
+    /// whether the commands it matches are paired, recorded, or both
+    coverage: Coverage,
+
+    kind: RuleKind,
+}
+
+/// Whether the sessions a rule matches are paired, recorded, or both.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Coverage {
+    /// paired and recorded, as sessions are without rules
+    Full,
+
+    /// paired, but never recorded
+    PairOnly,
+
+    /// recorded, but not paired
+    RecordOnly,
+}
+
+#[derive(Clone, Debug)]
+enum RuleKind {
+    /// matches if any argument matches the glob
+    Arg(Glob),
+
+    /// matches if the executable matches the first glob, and the
+    /// arguments contain matches for the rest, in order
+    Command(Glob, Vec<Glob>),
+
+    /// matches the executable and its arguments joined by spaces
+    Regex(Regex),
+
+    /// matches if any of the labels the policy plugin attached to the
+    /// session matches the glob
+    Label(Glob),
+
+    /// matches if any of the names of the session's terminal matches
+    /// the glob
+    Tty(Glob),
+}
+
+impl Rules {
+    /// Reads rules from `path`, which must be owned by root and not be
+    /// writable by anyone else, since whoever can change the rules can
+    /// exempt their commands from pairing.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+
+        if metadata.uid() != 0 {
+            return Err("the rules aren't owned by root".into());
+        }
+
+        if metadata.mode() & 0o022 != 0 {
+            return Err("the rules are writable by users other than root".into());
+        }
+
+        Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut rules   = Vec::new();
+        let mut quorums = HashMap::new();
+
+        for (i, text) in s.lines().enumerate() {
+            let text = text.trim();
+
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let (mut keyword, mut rest) = split_keyword(text);
+
+            if keyword == "quorum" {
+                let (tier, quorum) = Quorum::parse(rest)
+                    .map_err(|e| format!("line {}: {}", i + 1, e))?;
+
+                if quorums.insert(tier, quorum).is_some() {
+                    return Err(format!("line {}: tier {} already has a quorum", i + 1, tier));
+                }
+
+                continue;
+            }
+
+            let mut tier = 1;
+
+            if keyword == "tier" {
+                let (n, rule) = split_keyword(rest);
+
+                tier = parse_tier(n).map_err(|e| format!("line {}: {}", i + 1, e))?;
+
+                if rule.is_empty() {
+                    return Err(format!("line {}: tier needs a rule", i + 1));
+                }
+
+                let (k, r) = split_keyword(rule);
+
+                keyword = k;
+                rest    = r;
+            }
+
+            let coverage = match keyword {
+                "pair-only"   => Coverage::PairOnly,
+                "record-only" => Coverage::RecordOnly,
+                _             => Coverage::Full,
+            };
+
+            if coverage != Coverage::Full {
+                let (k, r) = split_keyword(rest);
+
+                if k.is_empty() {
+                    return Err(format!("line {}: {} needs a rule", i + 1, keyword));
+                }
+
+                keyword = k;
+                rest    = r;
+            }
+
+            if !["arg", "command", "regex", "label", "tty"].contains(&keyword) {
+                return Err(format!("line {}: unknown rule {}", i + 1, keyword));
+            }
+
+            if rest.is_empty() {
+                return Err(format!("line {}: {} needs a pattern", i + 1, keyword));
+            }
+
+            let kind = match keyword {
+                "arg"     => RuleKind::Arg(Glob::new(rest)),
+                "command" => {
+                    let mut globs = rest.split_whitespace().map(Glob::new);
+                    let command   = globs.next().expect("rest isn't empty");
+
+                    RuleKind::Command(command, globs.collect())
+                },
+                "regex"   => RuleKind::Regex(
+                    Regex::new(rest).map_err(|e| format!("line {}: {}", i + 1, e))?
+                ),
+                "label"   => RuleKind::Label(Glob::new(rest)),
+                "tty"     => RuleKind::Tty(Glob::new(rest)),
+
+                _ => unreachable!("keywords are checked above"),
+            };
+
+            rules.push(Rule { line: i + 1, text: text.into(), tier, coverage, kind });
+        }
+
+        Ok(Self { rules, quorums })
+    }
+
+    /// The rule matching the `executable` run with `args` (not including
+    /// the name it was invoked by), in a session with `labels` run from
+    /// a terminal going by the names `tty` (see `Terminal::names`), that
+    /// puts it in the highest tier, or the first such rule if several
+    /// do, if any.
+    pub fn matching(&self, executable: &[u8], args: &[&[u8]], labels: &[String], tty: &[&str]) -> Option<&Rule> {
+        self.rules.iter()
+            .filter(|rule| rule.matches(executable, args, labels, tty))
+            .fold(None, |highest: Option<&Rule>, rule| match highest {
+                Some(highest) if highest.tier >= rule.tier => Some(highest),
+                _                                          => Some(rule),
+            })
+    }
+
+    /// The quorum needed to approve sessions in `tier`.
+    pub fn quorum(&self, tier: u8) -> Quorum {
+        self.quorums.get(&tier).cloned().unwrap_or(Quorum {
+            approvers: if tier == 0 { 0 } else { 1 },
+            group:     None,
+        })
+    }
+}
+
+impl Rule {
+    pub fn tier(&self) -> u8 {
+        self.tier
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        self.coverage
+    }
+
+    fn matches(&self, executable: &[u8], args: &[&[u8]], labels: &[String], tty: &[&str]) -> bool {
+        match &self.kind {
+            RuleKind::Arg(glob) => args.iter().any(|arg| glob.matches(arg)),
+
+            RuleKind::Command(command, globs) => {
+                // a bare name matches the executable wherever it lives
+                let name = if command.0.contains(&Token::Literal(b'/')) {
+                    executable
+                } else {
+                    executable.rsplit(|&b| b == b'/').next().unwrap_or(executable)
+                };
+
+                if !command.matches(name) {
+                    return false;
+                }
+
+                // each glob must match some argument after the one the
+                // previous glob matched, so interspersed arguments
+                // (e.g., `--no-pager`) can't dodge the rule
+                let mut args = args.iter();
+
+                globs.iter().all(|glob| args.any(|arg| glob.matches(arg)))
+            },
+
+            RuleKind::Regex(regex) => {
+                let mut line = String::from_utf8_lossy(executable).into_owned();
+
+                for arg in args {
+                    line.push(' ');
+                    line.push_str(&String::from_utf8_lossy(arg));
+                }
+
+                regex.is_match(&line)
+            },
+
+            RuleKind::Label(glob) => labels.iter().any(|label| glob.matches(label.as_bytes())),
+
+            RuleKind::Tty(glob) => tty.iter().any(|name| glob.matches(name.as_bytes())),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.text)
+    }
+}
+
+impl Coverage {
+    /// The name of the coverage, as it's logged.
+    pub fn name(self) -> &'static str {
+        match self {
+            Coverage::Full       => "full",
+            Coverage::PairOnly   => "pair-only",
+            Coverage::RecordOnly => "record-only",
+        }
+    }
+}
+
+impl Default for Quorum {
+    /// A single approver, as sessions have always needed.
+    fn default() -> Self {
+        Self { approvers: 1, group: None }
+    }
+}
+
+impl Quorum {
+    /// Parses the tier and quorum of a `quorum` line, after the keyword.
+    fn parse(s: &str) -> Result<(u8, Self), String> {
+        let mut fields = s.split_whitespace();
+
+        let (tier, approvers, group) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(tier), Some(approvers), group, None) => (tier, approvers, group),
+            _ => return Err("quorum needs a tier, a number of approvers, and optionally a group".into()),
+        };
+
+        let tier      = parse_tier(tier)?;
+        let approvers = approvers.parse()
+            .map_err(|_| format!("{} isn't a number of approvers", approvers))?;
+        let group     = group.map(Group::from_sudo_option).transpose()?;
+
+        Ok((tier, Self { approvers, group }))
+    }
+}
+
+/// Splits the first word off `s`.
+fn split_keyword(s: &str) -> (&str, &str) {
+    match s.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None                  => (s, ""),
+    }
+}
+
+fn parse_tier(s: &str) -> Result<u8, String> {
+    s.parse().map_err(|_| format!("{} isn't a tier", s))
+}
+
+/// A shell-style glob over bytes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Glob(Vec<Token>);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Token {
+    Literal(u8),
+    Any,
+    Star,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::with_capacity(pattern.len());
+        let mut bytes  = pattern.bytes();
+
+        while let Some(b) = bytes.next() {
+            tokens.push(match b {
+                b'*'  => Token::Star,
+                b'?'  => Token::Any,
+                b'\\' => Token::Literal(bytes.next().unwrap_or(b'\\')),
+                _     => Token::Literal(b),
+            });
+        }
+
+        Self(tokens)
+    }
+
+    /// Matches `s` against the glob, backtracking only to the most
+    /// recent `*`, so no pattern can take more than quadratic time.
+    fn matches(&self, s: &[u8]) -> bool {
+        let tokens = &self.0;
+
+        let (mut t, mut i) = (0, 0);
+        let mut star       = None;
+
+        while i < s.len() {
+            match tokens.get(t) {
+                Some(Token::Star) => {
+                    star = Some((t, i));
+                    t   += 1;
+                },
+
+                Some(Token::Any) => {
+                    t += 1;
+                    i += 1;
+                },
+
+                Some(Token::Literal(b)) if *b == s[i] => {
+                    t += 1;
+                    i += 1;
+                },
+
+                _ => match star {
+                    // let the last `*` swallow one more byte and retry
+                    Some((st, si)) => {
+                        star = Some((st, si + 1));
+                        t    = st + 1;
+                        i    = si + 1;
+                    },
+
+                    None => return false,
+                },
+            }
+        }
+
+        tokens[t..].iter().all(|token| *token == Token::Star)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(s: &str) -> Rules {
+        Rules::parse(s).unwrap()
+    }
+
+    fn matching<'a>(rules: &'a Rules, executable: &str, args: &[&str]) -> Option<&'a Rule> {
+        let args : Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+
+        rules.matching(executable.as_bytes(), &args, &[], &["pty"])
+    }
+
+    #[test]
+    fn matches_globs() {
+        let glob = |pattern| Glob::new(pattern);
+
+        assert!(glob("prod-*").matches(b"prod-db01"));
+        assert!(glob("prod-*").matches(b"prod-"));
+        assert!(glob("*.service").matches(b"nginx.service"));
+        assert!(glob("db??").matches(b"db01"));
+        assert!(glob("*a*b*").matches(b"xxaxxbxx"));
+        assert!(glob("\\*").matches(b"*"));
+
+        assert!(!glob("prod-*").matches(b"staging-db01"));
+        assert!(!glob("db??").matches(b"db1"));
+        assert!(!glob("\\*").matches(b"x"));
+        assert!(!glob("*a*b").matches(b"xxaxxbxx"));
+    }
+
+    #[test]
+    fn matches_arguments_anywhere() {
+        let rules = rules("# forced anything\narg --force\n");
+
+        assert_eq!(2, matching(&rules, "/usr/bin/git", &["push", "--force"]).unwrap().line);
+        assert!(matching(&rules, "/usr/bin/git", &["push"]).is_none());
+
+        // the executable itself isn't an argument
+        assert!(matching(&rules, "--force", &[]).is_none());
+    }
+
+    #[test]
+    fn matches_commands_with_interspersed_arguments() {
+        let rules = rules("command systemctl stop prod-*");
+
+        assert!(matching(&rules, "/bin/systemctl", &["stop", "prod-db"]).is_some());
+        assert!(matching(&rules, "/bin/systemctl", &["--no-pager", "stop", "-q", "prod-db"]).is_some());
+
+        assert!(matching(&rules, "/bin/systemctl", &["stop", "staging-db"]).is_none());
+        assert!(matching(&rules, "/bin/systemctl", &["prod-db", "stop"]).is_none());
+        assert!(matching(&rules, "/bin/journalctl", &["stop", "prod-db"]).is_none());
+
+        // a path must match the executable's full path
+        let rules = self::rules("command /usr/bin/* --force");
+
+        assert!(matching(&rules, "/usr/bin/git", &["--force"]).is_some());
+        assert!(matching(&rules, "/usr/local/bin/git", &["--force"]).is_none());
+    }
+
+    #[test]
+    fn matches_regexes_against_command_lines() {
+        let rules = rules(r"regex ^/usr/bin/ssh .*\bprod[0-9]+\b");
+
+        assert!(matching(&rules, "/usr/bin/ssh", &["-v", "prod12.example.com"]).is_some());
+        assert!(matching(&rules, "/usr/bin/ssh", &["staging12.example.com"]).is_none());
+    }
+
+    #[test]
+    fn matches_session_labels() {
+        let rules  = rules("tier 2 label pci-*");
+        let labels = |labels: &[&str]| labels.iter().map(|label| label.to_string()).collect::<Vec<_>>();
+
+        assert!(rules.matching(b"/bin/ls", &[], &labels(&["prod", "pci-scope"]), &[]).is_some());
+        assert!(rules.matching(b"/bin/ls", &[], &labels(&["prod"]), &[]).is_none());
+
+        // labels aren't arguments, nor arguments labels
+        assert!(matching(&rules, "/bin/echo", &["pci-scope"]).is_none());
+    }
+
+    #[test]
+    fn matches_kinds_of_terminal() {
+        let rules = rules("tier 2 tty multiplexer\ntier 3 tty screen");
+
+        assert_eq!(Some(2), rules.matching(b"/bin/ls", &[], &[], &["multiplexer", "tmux"]).map(Rule::tier));
+        assert_eq!(Some(3), rules.matching(b"/bin/ls", &[], &[], &["multiplexer", "screen"]).map(Rule::tier));
+        assert!(rules.matching(b"/bin/ls", &[], &[], &["pty"]).is_none());
+        assert!(matching(&rules, "/bin/echo", &["multiplexer"]).is_none());
+    }
+
+    #[test]
+    fn assigns_the_highest_matching_tier() {
+        let rules = rules("\
+            quorum 2 2 security\n\
+            arg --force\n\
+            tier 0 command systemctl status *\n\
+            tier 2 command systemctl * prod-*\n\
+            tier 2 arg --now\n\
+        ");
+
+        let tier = |executable, args| matching(&rules, executable, args).map(Rule::tier);
+
+        assert_eq!(Some(0), tier("/bin/systemctl", &["status", "nginx"]));
+        assert_eq!(Some(2), tier("/bin/systemctl", &["status", "prod-db"]));
+        assert_eq!(Some(1), tier("/bin/systemctl", &["restart", "--force", "nginx"]));
+        assert_eq!(None,    tier("/bin/systemctl", &["restart", "nginx"]));
+
+        // ties go to the first rule in the file
+        assert_eq!(4, matching(&rules, "/bin/systemctl", &["stop", "--now", "prod-db"]).unwrap().line);
+
+        assert_eq!(Quorum { approvers: 0, group: None }, rules.quorum(0));
+        assert_eq!(Quorum::default(),                   rules.quorum(1));
+        assert_eq!(
+            Quorum { approvers: 2, group: Some(Group::Name("security".into())) },
+            rules.quorum(2),
+        );
+    }
+
+    #[test]
+    fn covers_sessions_as_rules_say() {
+        let rules = rules("\
+            tier 2 pair-only command pass *\n\
+            record-only command journalctl *\n\
+            command systemctl *\n\
+            tier 0 pair-only arg --quiet\n\
+        ");
+
+        let coverage = |executable, args| matching(&rules, executable, args).map(Rule::coverage);
+
+        assert_eq!(Some(Coverage::PairOnly),   coverage("/usr/bin/pass", &["show", "prod/db"]));
+        assert_eq!(Some(Coverage::RecordOnly), coverage("/bin/journalctl", &["-u", "nginx"]));
+        assert_eq!(Some(Coverage::Full),       coverage("/bin/systemctl", &["restart", "nginx"]));
+
+        // the rule deciding the tier decides the coverage too
+        assert_eq!(Some(Coverage::Full),       coverage("/bin/systemctl", &["--quiet", "restart"]));
+        assert_eq!(Some(Coverage::RecordOnly), coverage("/bin/journalctl", &["--quiet"]));
+
+        assert_eq!(2, matching(&rules, "/usr/bin/pass", &["show"]).unwrap().tier());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert_eq!("line 2: unknown rule args", Rules::parse("arg -f\nargs -f").unwrap_err());
+        assert_eq!("line 1: command needs a pattern", Rules::parse("command").unwrap_err());
+        assert!(Rules::parse("regex (").unwrap_err().starts_with("line 1: "));
+        assert_eq!("line 1: high isn't a tier", Rules::parse("tier high arg -f").unwrap_err());
+        assert_eq!("line 1: command needs a pattern", Rules::parse("tier 2 command").unwrap_err());
+        assert_eq!("line 1: pair-only needs a rule", Rules::parse("tier 2 pair-only").unwrap_err());
+        assert_eq!("line 1: unknown rule tier", Rules::parse("record-only tier 2 arg -f").unwrap_err());
+        assert_eq!("line 2: tier 2 already has a quorum", Rules::parse("quorum 2 2\nquorum 2 3").unwrap_err());
+        assert_eq!(
+            "line 1: quorum needs a tier, a number of approvers, and optionally a group",
+            Rules::parse("quorum 2").unwrap_err(),
+        );
+    }
+}
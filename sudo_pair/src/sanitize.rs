@@ -0,0 +1,453 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Removal of dangerous terminal escape sequences from session output.
+//!
+//! The command being run under `sudo` controls every byte of its output,
+//! and that output is relayed to the approver's terminal. Left alone, a
+//! malicious command could change the approver's window title, write to
+//! their clipboard, ask their terminal to report on itself (injecting the
+//! reply as input), or spoof `sudo_pair`'s own protocol messages. This
+//! module strips such sequences, by class, before they're sent.
+
+use crate::options::EscapeClass;
+
+use std::collections::HashSet;
+
+const ESC : u8 = 0x1b;
+const BEL : u8 = 0x07;
+const CAN : u8 = 0x18;
+const SUB : u8 = 0x1a;
+
+/// The longest escape sequence that's buffered in order to classify it.
+/// Anything longer is classified by what's been seen so far.
+const MAX_SEQUENCE_LEN : usize = 4096;
+
+/// Where the sanitizer is within the output stream.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    /// ordinary output
+    Ground,
+
+    /// after an `ESC`, possibly with intermediate bytes
+    Escape,
+
+    /// within a control sequence (`ESC [`)
+    Csi,
+
+    /// within a control string (`OSC`, `DCS`, `APC`, `PM`, or `SOS`)
+    String,
+
+    /// after an `ESC` within a control string, which may be the start
+    /// of its terminator (`ESC \`)
+    StringEscape,
+
+    /// within a control string too long to buffer, which is being
+    /// passed through (or discarded, if `true`) until it ends
+    Overflow(bool),
+}
+
+/// Strips escape sequences belonging to any of a set of classes from a
+/// stream of output. Sequences may be split across calls, so incomplete
+/// ones are held back until the rest of them arrives.
+#[derive(Debug)]
+pub struct Sanitizer {
+    filters:  HashSet<EscapeClass>,
+    state:    State,
+    sequence: Vec<u8>,
+
+    /// the bytes removed since `take_removed` was last called
+    removed: u64,
+}
+
+impl Sanitizer {
+    /// Creates a sanitizer removing sequences in any of `filters`.
+    pub fn new(filters: HashSet<EscapeClass>) -> Self {
+        Self {
+            filters,
+            state:    State::Ground,
+            sequence: Vec::new(),
+            removed:  0,
+        }
+    }
+
+    /// Returns true if no sequences are removed at all.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Returns `data` with any unwanted sequences removed, holding back
+    /// a trailing sequence that isn't yet complete.
+    pub fn process(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(data.len());
+        let mut rest   = data;
+
+        while let Some((byte, tail)) = rest.split_first() {
+            // most output isn't part of any sequence, and a runaway
+            // control string can go on for gigabytes, so skip straight
+            // to the next byte that could change the state
+            let (skip, keep) = match self.state {
+                State::Ground => (
+                    rest.iter().position(|b| *b == ESC).unwrap_or(rest.len()),
+                    true,
+                ),
+
+                State::Overflow(strip) if self.sequence.is_empty() => (
+                    rest.iter().position(|b| *b == ESC || *b == BEL).unwrap_or(rest.len()),
+                    !strip,
+                ),
+
+                _ => (0, false),
+            };
+
+            if skip == 0 {
+                self.step(*byte, &mut output);
+                rest = tail;
+
+                continue;
+            }
+
+            let (skipped, tail) = rest.split_at(skip);
+
+            if keep {
+                output.extend_from_slice(skipped);
+            } else {
+                self.removed += skipped.len() as u64;
+            }
+
+            rest = tail;
+        }
+
+        output
+    }
+
+    /// Returns any sequence still being held back, for when the session
+    /// ends. Incomplete sequences are kept or removed as if they'd been
+    /// terminated.
+    pub fn flush(&mut self) -> Vec<u8> {
+        let sequence = std::mem::take(&mut self.sequence);
+        let state    = std::mem::replace(&mut self.state, State::Ground);
+
+        let strip = match state {
+            State::String | State::StringEscape => self.strips(string_class(&sequence)),
+            _                                   => false,
+        };
+
+        if !strip {
+            return sequence;
+        }
+
+        self.removed += sequence.len() as u64;
+
+        Vec::new()
+    }
+
+    /// Returns the number of bytes removed from the output since this
+    /// was last called.
+    pub fn take_removed(&mut self) -> u64 {
+        std::mem::take(&mut self.removed)
+    }
+
+    fn step(&mut self, byte: u8, output: &mut Vec<u8>) {
+        // terminals abandon an unfinished escape or control sequence
+        // when another `ESC` arrives, so we must too, or the sequence
+        // that follows would slip through unexamined
+        if byte == ESC && (self.state == State::Escape || self.state == State::Csi) {
+            self.emit(false, output);
+        }
+
+        match self.state {
+            State::Ground => {
+                if byte == ESC {
+                    self.sequence.push(byte);
+                    self.state = State::Escape;
+                } else {
+                    output.push(byte);
+                }
+            },
+
+            State::Escape => {
+                self.sequence.push(byte);
+
+                match byte {
+                    b'['                                 => self.state = State::Csi,
+                    b']' | b'P' | b'_' | b'^' | b'X'     => self.state = State::String,
+
+                    // intermediate bytes (e.g., `ESC ( B`) precede the
+                    // final byte of the sequence
+                    0x20 ..= 0x2f if self.sequence.len() < MAX_SEQUENCE_LEN => (),
+
+                    _ => self.emit(false, output),
+                }
+            },
+
+            State::Csi => {
+                self.sequence.push(byte);
+
+                match byte {
+                    0x40 ..= 0x7e => {
+                        let strip = self.strips(csi_class(&self.sequence));
+                        self.emit(strip, output);
+                    },
+
+                    // cancelled sequences, and those too long to be
+                    // legitimate, are passed through as they are
+                    CAN | SUB                                  => self.emit(false, output),
+                    _ if self.sequence.len() >= MAX_SEQUENCE_LEN => self.emit(false, output),
+                    _                                          => (),
+                }
+            },
+
+            State::String => {
+                self.sequence.push(byte);
+
+                match byte {
+                    BEL => {
+                        let strip = self.strips(string_class(&self.sequence));
+                        self.emit(strip, output);
+                    },
+
+                    ESC => self.state = State::StringEscape,
+
+                    _ if self.sequence.len() >= MAX_SEQUENCE_LEN => {
+                        let strip = self.strips(string_class(&self.sequence));
+
+                        if strip {
+                            self.removed += self.sequence.len() as u64;
+                        } else {
+                            output.append(&mut self.sequence);
+                        }
+
+                        self.sequence.clear();
+                        self.state = State::Overflow(strip);
+                    },
+
+                    _ => (),
+                }
+            },
+
+            State::StringEscape => {
+                if byte == b'\\' {
+                    self.sequence.push(byte);
+
+                    let strip = self.strips(string_class(&self.sequence));
+                    self.emit(strip, output);
+
+                    return;
+                }
+
+                // any other `ESC` ends the string and begins a new
+                // sequence
+                let _     = self.sequence.pop();
+                let strip = self.strips(string_class(&self.sequence));
+
+                self.emit(strip, output);
+                self.step(ESC,  output);
+                self.step(byte, output);
+            },
+
+            State::Overflow(strip) => {
+                if strip {
+                    self.removed += 1;
+                } else {
+                    output.push(byte);
+                }
+
+                let terminated = byte == BEL
+                    || (byte == b'\\' && self.sequence.last() == Some(&ESC));
+
+                // only the last byte is kept, to recognize `ESC \`
+                self.sequence.clear();
+
+                if terminated {
+                    self.state = State::Ground;
+                } else if byte == ESC {
+                    self.sequence.push(byte);
+                }
+            },
+        }
+    }
+
+    /// Ends the current sequence, sending it unless it's to be stripped.
+    fn emit(&mut self, strip: bool, output: &mut Vec<u8>) {
+        if strip {
+            self.removed += self.sequence.len() as u64;
+        } else {
+            output.extend_from_slice(&self.sequence);
+        }
+
+        self.sequence.clear();
+        self.state = State::Ground;
+    }
+
+    fn strips(&self, class: Option<EscapeClass>) -> bool {
+        match class {
+            Some(class) => self.filters.contains(&class),
+            None        => false,
+        }
+    }
+}
+
+/// Classifies a complete control sequence (`ESC [ ... final`).
+fn csi_class(sequence: &[u8]) -> Option<EscapeClass> {
+    let (last, params) = sequence[2..].split_last()?;
+
+    match last {
+        // device status, device attributes, and window operations
+        // (which include reporting the window title) all elicit replies
+        b'n' | b'c' | b't'                       => Some(EscapeClass::Reports),
+        b'p' if params.ends_with(b"$")          => Some(EscapeClass::Reports),
+
+        b'h' | b'l' if params.starts_with(b"?") => {
+            let bracketed_paste = params[1..]
+                .split(|b| *b == b';')
+                .any(|param| param == b"2004");
+
+            if bracketed_paste { Some(EscapeClass::Paste) } else { None }
+        },
+
+        _ => None,
+    }
+}
+
+/// Classifies a control string from its introducer (and, for `OSC`, its
+/// leading numeric parameter).
+fn string_class(sequence: &[u8]) -> Option<EscapeClass> {
+    match sequence.get(1)? {
+        b']' => {
+            let number = sequence[2..]
+                .split(|b| *b == b';' || *b == BEL || *b == ESC)
+                .next()?;
+
+            match number {
+                b"0" | b"1" | b"2" => Some(EscapeClass::Title),
+                b"52"              => Some(EscapeClass::Clipboard),
+                b"5379"            => Some(EscapeClass::Protocol),
+                _                  => Some(EscapeClass::Osc),
+            }
+        },
+
+        b'P'               => Some(EscapeClass::Dcs),
+        b'_' | b'^' | b'X' => Some(EscapeClass::Apc),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitizer(filters: &[EscapeClass]) -> Sanitizer {
+        Sanitizer::new(filters.iter().cloned().collect())
+    }
+
+    fn all() -> Sanitizer {
+        sanitizer(&[
+            EscapeClass::Title,
+            EscapeClass::Clipboard,
+            EscapeClass::Osc,
+            EscapeClass::Dcs,
+            EscapeClass::Apc,
+            EscapeClass::Reports,
+            EscapeClass::Paste,
+            EscapeClass::Protocol,
+        ])
+    }
+
+    #[test]
+    fn passes_through_ordinary_output() {
+        let mut sanitizer = all();
+        let     output    = b"\x1b[1;31mred\x1b[0m \x1b(B\x1b7\x1b[2J\r\n";
+
+        assert_eq!(&output[..], &sanitizer.process(output)[..]);
+        assert!(sanitizer.flush().is_empty());
+        assert_eq!(0, sanitizer.take_removed());
+    }
+
+    #[test]
+    fn strips_dangerous_sequences() {
+        let mut sanitizer = all();
+
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1b]0;pwned\x07b")[..]);
+        assert_eq!(10,    sanitizer.take_removed());
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1b]52;c;ZWNobyBoaQ==\x1b\\b")[..]);
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1bP$q\"p\x1b\\b")[..]);
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1b_payload\x1b\\b")[..]);
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1b[6n\x1b[c\x1b[21tb")[..]);
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1b[?2004hb")[..]);
+        assert_eq!(b"ab", &sanitizer.process(b"a\x1b]5379;sudo_pair;checkpoint;bytes=0\x07b")[..]);
+    }
+
+    #[test]
+    fn strips_only_configured_classes() {
+        let mut sanitizer = sanitizer(&[EscapeClass::Clipboard]);
+
+        let title = b"\x1b]2;title\x07";
+
+        assert_eq!(&title[..], &sanitizer.process(title)[..]);
+        assert!(sanitizer.process(b"\x1b]52;c;ZWNobw==\x07").is_empty());
+        assert_eq!(b"\x1b[?1049h", &sanitizer.process(b"\x1b[?1049h")[..]);
+    }
+
+    #[test]
+    fn handles_sequences_split_across_writes() {
+        let mut sanitizer = all();
+
+        assert_eq!(b"a", &sanitizer.process(b"a\x1b")[..]);
+        assert!(sanitizer.process(b"]0;ti").is_empty());
+        assert!(sanitizer.process(b"tle\x1b").is_empty());
+        assert_eq!(b"c", &sanitizer.process(b"\\c\x1b[0")[..]);
+        assert_eq!(b"\x1b[0m", &sanitizer.process(b"m")[..]);
+    }
+
+    #[test]
+    fn ends_strings_at_unexpected_escapes() {
+        let mut sanitizer = all();
+
+        assert_eq!(b"\x1b[0mb", &sanitizer.process(b"\x1b]0;title\x1b[0mb")[..]);
+    }
+
+    #[test]
+    fn abandons_sequences_at_unexpected_escapes() {
+        let mut sanitizer = all();
+
+        assert_eq!(b"\x1b(a", &sanitizer.process(b"\x1b(\x1b]52;c;ZWNobw==\x07a")[..]);
+        assert_eq!(b"\x1b[1a", &sanitizer.process(b"\x1b[1\x1b]0;title\x07a")[..]);
+    }
+
+    #[test]
+    fn discards_overlong_strings() {
+        let mut sanitizer = all();
+        let mut output    = b"\x1b]52;c;".to_vec();
+
+        output.extend_from_slice(&[b'A'; MAX_SEQUENCE_LEN * 2]);
+        output.extend_from_slice(b"\x07ok");
+
+        assert_eq!(b"ok", &sanitizer.process(&output)[..]);
+        assert_eq!(output.len() as u64 - 2, sanitizer.take_removed());
+    }
+
+    #[test]
+    fn flushes_incomplete_sequences() {
+        let mut sanitizer = all();
+
+        assert!(sanitizer.process(b"\x1b]0;unterminated").is_empty());
+        assert!(sanitizer.flush().is_empty());
+        assert_eq!(16, sanitizer.take_removed());
+
+        assert!(sanitizer.process(b"\x1b[1").is_empty());
+        assert_eq!(b"\x1b[1", &sanitizer.flush()[..]);
+    }
+}
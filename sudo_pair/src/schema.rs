@@ -0,0 +1,214 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A description of every option the plugin accepts, for generating
+//! configuration and documentation from. Rather than being maintained
+//! alongside `PluginOptions`, it's read from the fields of the struct
+//! itself (as they appear in its source): each field's name is the
+//! option's, its type determines the option's, and its doc comment
+//! describes the option, ending with the option's default.
+
+use sudo_pair_types::json::quote;
+
+/// The source of the `options` module, which `PluginOptions` is defined
+/// in. It isn't entirely UTF-8 (some of its tests have invalid bytes),
+/// but the struct itself is.
+const SOURCE : &[u8] = include_bytes!("options.rs");
+
+/// How a field's doc comment gives the option's default.
+const DEFAULT : &str = "Default: ";
+
+/// An option the plugin accepts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionSchema {
+    /// the option's name, as it's given in `sudo.conf`
+    pub name: String,
+
+    /// what kind of value the option takes (e.g., `duration`)
+    pub kind: &'static str,
+
+    /// the value the option takes when it isn't given, if it has one,
+    /// as it would be given
+    pub default: Option<String>,
+
+    /// what the option does, as paragraphs separated by blank lines
+    pub description: String,
+}
+
+impl OptionSchema {
+    /// Renders the option as a JSON object.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{{}:{},{}:{},{}:{},{}:{}}}",
+            quote("name"),        quote(&self.name),
+            quote("type"),        quote(self.kind),
+            quote("default"),     self.default.as_ref().map_or_else(|| "null".into(), |d| quote(d)),
+            quote("description"), quote(&self.description),
+        )
+    }
+}
+
+/// Describes every option the plugin accepts, in the order they're
+/// declared.
+pub fn options() -> Vec<OptionSchema> {
+    let source = String::from_utf8_lossy(SOURCE);
+
+    let mut options = Vec::new();
+    let mut docs    = Vec::new();
+
+    let fields = source.lines()
+        .skip_while(|line| !line.starts_with("pub struct PluginOptions "))
+        .skip(1)
+        .take_while(|line| *line != "}");
+
+    for line in fields.map(str::trim) {
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(doc.trim());
+            continue;
+        }
+
+        let field = match line.strip_prefix("pub ") {
+            Some(field) => field,
+            None        => continue,
+        };
+
+        if let Some((name, ty)) = field.trim_end_matches(',').split_once(':') {
+            options.push(describe(name.trim(), ty.trim(), &docs));
+        }
+
+        docs.clear();
+    }
+
+    options
+}
+
+/// Renders `options` as a JSON array.
+pub fn to_json(options: &[OptionSchema]) -> String {
+    let options : Vec<_> = options.iter().map(OptionSchema::to_json).collect();
+
+    format!("[{}]", options.join(","))
+}
+
+/// Describes the option `name`, whose field is of the type `ty` and
+/// documented by the lines of `docs`.
+fn describe(name: &str, ty: &str, docs: &[&str]) -> OptionSchema {
+    let mut paragraphs = vec![Vec::new()];
+    let mut default    = None;
+
+    for doc in docs {
+        if let Some(value) = doc.strip_prefix(DEFAULT) {
+            default = parse_default(value);
+        } else if doc.is_empty() {
+            paragraphs.push(Vec::new());
+        } else if let Some(paragraph) = paragraphs.last_mut() {
+            paragraph.push(*doc);
+        }
+    }
+
+    let paragraphs : Vec<_> = paragraphs.iter()
+        .filter(|lines| !lines.is_empty())
+        .map(|lines| lines.join(" "))
+        .collect();
+
+    OptionSchema {
+        name:        name.into(),
+        kind:        kind(ty),
+        default,
+        description: paragraphs.join("\n\n"),
+    }
+}
+
+/// The default given by a doc comment's `Default:` line (e.g.,
+/// `` `"/var/run/sudo_pair"` ``, or `` `[0]` (e.g., root) ``), as it
+/// would be given in `sudo.conf`, or `None` if there isn't one.
+fn parse_default(value: &str) -> Option<String> {
+    let value = value.strip_prefix('`')?;
+    let value = &value[.. value.find('`')?];
+
+    let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('[').and_then(|v| v.strip_suffix(']')))
+        .unwrap_or(value);
+
+    Some(value.into())
+}
+
+/// The kind of value taken by an option whose field is of the type `ty`.
+fn kind(ty: &str) -> &'static str {
+    let ty = ty.strip_prefix("Option<").and_then(|ty| ty.strip_suffix('>')).unwrap_or(ty);
+
+    match ty {
+        "bool"                     => "boolean",
+        "u32" | "u64" | "usize"    => "integer",
+        "Duration"                 => "duration",
+        "ByteSize"                 => "size",
+        "PathBuf"                  => "path",
+        "Mode"                     => "mode",
+        "User"                     => "user",
+        "Group"                    => "group",
+        _ if ty.starts_with("Vec<")
+          || ty.starts_with("HashSet<")
+          || ty.starts_with("HashMap<") => "list",
+        _                          => "string",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str) -> OptionSchema {
+        options().into_iter().find(|option| option.name == name).unwrap()
+    }
+
+    #[test]
+    fn describes_options() {
+        assert_eq!(OptionSchema {
+            name:        "binary_path".into(),
+            kind:        "path",
+            default:     Some("/usr/bin/sudo_approve".into()),
+            description: "`binary_path` is the location of the approval binary, so \
+                          that we can bypass the approval process for invoking it.".into(),
+        }, options()[0]);
+
+        assert_eq!("duration",    option("approval_timeout").kind);
+        assert_eq!(Some("0".into()), option("gids_enforced").default);
+        assert_eq!("list",        option("gids_enforced").kind);
+        assert_eq!("group",       option("socket_group").kind);
+        assert_eq!(None,          option("socket_group").default);
+        assert_eq!(Some("1MiB".into()), option("recording_queue_limit").default);
+        assert_eq!(Some("".into()),     option("identity_users").default);
+    }
+
+    #[test]
+    fn keeps_paragraphs() {
+        let option = describe("x", "bool", &[
+            "`x` does", "something.", "", "Really.", "", "Default: `true`",
+        ]);
+
+        assert_eq!("`x` does something.\n\nReally.", option.description);
+        assert_eq!(Some("true".into()), option.default);
+    }
+
+    #[test]
+    fn renders_json() {
+        let option = describe("x", "Option<PathBuf>", &[
+            "`x` is \"quoted\".", "", "Default: none",
+        ]);
+
+        assert_eq!(
+            r#"[{"name":"x","type":"path","default":null,"description":"`x` is \"quoted\"."}]"#,
+            to_json(&[option]),
+        );
+    }
+}
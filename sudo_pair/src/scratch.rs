@@ -0,0 +1,40 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Scratch directories for tests that need the filesystem, under the
+//! system's temporary directory and named for the module and the test
+//! using them (and the process running it), so tests running at once
+//! never share one.
+
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::process;
+
+/// The scratch directory for the test `name` in `module`, with anything
+/// an earlier run left in it removed. It's created, readable only by
+/// its owner, if `create` is set, and otherwise left for the test to
+/// create (e.g., to see that what's under test creates it).
+pub fn dir(module: &str, name: &str, create: bool) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sudo_pair-{}-{}-{}", module, name, process::id()));
+
+    let _ = fs::remove_dir_all(&dir);
+
+    if create {
+        DirBuilder::new().mode(0o700).create(&dir)
+            .unwrap_or_else(|e| panic!("{} couldn't be created: {}", dir.display(), e));
+    }
+
+    dir
+}
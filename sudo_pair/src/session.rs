@@ -0,0 +1,1120 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The states a paired session moves through, each its own type.
+//!
+//! A session waits on its quorum as an `AwaitingApproval`, which is
+//! either declined or approved. Only approval produces an `Active`
+//! session, and only an `Active` session can stream output to its
+//! approver, so nothing can be sent to one before they've approved it.
+//! An `Active` session can be `Terminated` early (e.g., for outliving
+//! its limits), after which sudo's callbacks are rejected, and every
+//! session is eventually `Closed`. Every rule that ends sessions early
+//! does so through `Session::terminate`, with a `Termination` giving
+//! the reason.
+
+use crate::chat::{Chat, Inbox};
+use crate::clock::Clock;
+use crate::errors::*;
+use crate::protocol::{Credentials, Hello, Reply};
+use crate::reapproval::{self, Reapproval};
+use crate::socket::{Listener, Socket};
+use crate::suppression::Suppression;
+use crate::transcript::{Checkpoint, Transcript};
+use crate::transfer::Offer;
+use crate::wire;
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::time::Instant;
+
+use failure::ResultExt;
+
+use sudo_pair_types::frame::Message;
+use sudo_pair_types::protocol::{Capabilities, Stream, ESC};
+
+/// A session waiting on its quorum, with the approver currently being
+/// prompted (if one has connected) and those who've already approved.
+#[derive(Debug)]
+pub struct AwaitingApproval {
+    pair:     Option<Socket>,
+    protocol: Hello,
+    approved: Vec<(Socket, Hello)>,
+}
+
+impl Default for AwaitingApproval {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AwaitingApproval {
+    pub fn new() -> Self {
+        Self {
+            pair:     None,
+            protocol: Hello::legacy(),
+            approved: Vec::new(),
+        }
+    }
+
+    /// What was negotiated with the approver currently being prompted,
+    /// once they've answered.
+    pub fn protocol(&self) -> Hello {
+        self.protocol
+    }
+
+    /// The approver currently being prompted, if one has connected.
+    pub fn pair(&mut self) -> Option<&mut Socket> {
+        self.pair.as_mut()
+    }
+
+    pub fn connect(&mut self, socket: Socket) {
+        self.pair = Some(socket);
+    }
+
+    /// Sends `prompt` (which announces `hello`) to the approver
+    /// currently connected, and waits until `deadline` for their
+    /// answer. Clients that support negotiation reply with their own
+    /// hello ahead of the answer, and those that negotiated comments may
+    /// follow it with the approver's comment. Any client may also send
+    /// a signature of the challenge it was sent, if it was sent one, and
+    /// the approver's one-time code; an
+    /// `ESC` that doesn't begin one of these is treated as a declined
+    /// session, just as it always was.
+    pub fn prompt(
+        &mut self,
+        slog:     &slog::Logger,
+        prompt:   &[u8],
+        hello:    Hello,
+        deadline: Option<Instant>,
+    ) -> Result<Answer> {
+        let socket = self.pair.as_mut()
+            .ok_or(ErrorKind::CommunicationError)?;
+
+        // the pair's response (and anything they send ahead of it) has
+        // to arrive before the deadline
+        socket.set_deadline(deadline)
+            .context(ErrorKind::CommunicationError)?;
+
+        socket.write_all(prompt)
+            .context(ErrorKind::CommunicationError)?;
+
+        socket.flush()
+            .context(ErrorKind::CommunicationError)?;
+
+        slog::trace!(slog, "remote prompt rendered");
+
+        // default `response` to something other than success, since
+        // `read` might return without actually having written anything;
+        // this prevents us from being required to check the number of
+        // bytes actually read from `read`
+        let mut response : [u8; 1] = [b'n'];
+
+        slog::debug!(slog, "remote prompt awaiting response...");
+
+        // read exactly one byte back from the socket for the
+        // response (`read_exact` isn't used because it will capture
+        // Ctrl-C and retry the read); we don't need to check the return
+        // value because if the read was successful, we're guaranteed to
+        // have read at least one byte
+        let _ = before_deadline(socket.read(&mut response), ErrorKind::SessionDeclined)?;
+
+        let mut protocol    = None;
+        let mut comment     = None;
+        let mut credentials = Credentials::default();
+
+        while response[0] == ESC {
+            let reply = before_deadline(Reply::read_from(socket), ErrorKind::SessionDeclined)?
+                .ok_or(ErrorKind::SessionDeclined)?;
+
+            match reply {
+                Reply::Hello(client) if protocol.is_none() && comment.is_none() => {
+                    protocol = Some(hello.negotiate(client));
+
+                    slog::debug!(slog, "protocol negotiated";
+                        "client"   => client,
+                        "protocol" => protocol,
+                    );
+                },
+
+                Reply::Comment(text) if comment.is_none()
+                    && protocol.unwrap_or_else(Hello::legacy).handles(Capabilities::COMMENTS) =>
+                {
+                    comment = Some(text);
+                },
+
+                Reply::Signature(sshsig) if credentials.signature.is_none() => {
+                    credentials.signature = Some(sshsig);
+                },
+
+                Reply::Totp(code) if credentials.totp.is_none() => {
+                    credentials.totp = Some(code);
+                },
+
+                _ => return Err(ErrorKind::SessionDeclined.into()),
+            }
+
+            response = [b'n'];
+
+            let _ = before_deadline(socket.read(&mut response), ErrorKind::SessionDeclined)?;
+        }
+
+        // nothing else read from the pair is subject to the deadline
+        let _ = socket.set_deadline(None);
+
+        slog::debug!(slog, "remote pair responded";
+            "response" => String::from_utf8_lossy(&response[..]).into_owned(),
+        );
+
+        // echo back out the response, since the client is anticipated
+        // to be noecho
+        let _ = socket.write_all(&response[..]);
+        let _ = socket.write_all(b"\n");
+
+        if let Some(protocol) = protocol {
+            self.protocol = protocol;
+        }
+
+        Ok(Answer {
+            approved: matches!(&response, b"y" | b"Y"),
+            comment,
+            credentials,
+        })
+    }
+
+    /// Disconnects the approver currently being prompted, telling them
+    /// why with `notice`, so another can connect in their place.
+    pub fn release(&mut self, notice: &[u8]) {
+        if let Some(mut socket) = self.pair.take() {
+            let _ = socket.write_all(notice);
+        }
+
+        // whoever connects next negotiates for themselves
+        self.protocol = Hello::legacy();
+    }
+
+    /// How many approvers have approved the session so far, not
+    /// counting the one currently being prompted.
+    pub fn approvals(&self) -> usize {
+        self.approved.len()
+    }
+
+    /// Sets aside the approver who's just approved while the session
+    /// waits on the `remaining` approvers in its quorum.
+    pub fn set_aside(&mut self, remaining: u32) {
+        if let Some(mut socket) = self.pair.take() {
+            let _ = socket.write_all(format!(
+                "waiting for {} more approver{}\n",
+                remaining,
+                if remaining == 1 { "" } else { "s" },
+            ).as_bytes());
+
+            self.approved.push((socket, mem::replace(&mut self.protocol, Hello::legacy())));
+        }
+    }
+
+    /// Declines the session, telling every approver who's connected
+    /// why with `notice`.
+    pub fn decline(mut self, notice: &[u8]) {
+        let approved = self.approved.iter_mut().map(|(socket, _)| socket);
+
+        for socket in self.pair.iter_mut().chain(approved) {
+            let _ = socket.write_all(notice);
+        }
+    }
+
+    /// Approves the session once its whole quorum has, returning
+    /// `None` if nobody's approved it at all. The first to approve
+    /// watches the session, and the rest of the quorum is released.
+    pub fn approve(mut self) -> Option<Active> {
+        if self.approved.is_empty() {
+            return Some(Active::new(self.pair?, self.protocol));
+        }
+
+        let approvers           = self.approved.len() + usize::from(self.pair.is_some());
+        let (watcher, protocol) = self.approved.remove(0);
+        let released            = self.approved.into_iter()
+            .map(|(socket, _)| socket)
+            .chain(self.pair);
+
+        for mut socket in released {
+            let _ = socket.write_all(b"session approved; the first approver is watching it\n");
+        }
+
+        let mut active = Active::new(watcher, protocol);
+
+        let _ = active.send(&Message::Notice(&format!(
+            "session approved by all {} approvers; you're watching it\n",
+            approvers,
+        )));
+
+        Some(active)
+    }
+}
+
+/// An approver's answer to the prompt they were sent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Answer {
+    pub approved: bool,
+
+    /// the comment they left with it, if their client negotiated
+    /// comments
+    pub comment: Option<String>,
+
+    /// whatever they sent to prove who they are
+    pub credentials: Credentials,
+}
+
+/// A session that's been approved, and is watched by its approver.
+#[derive(Debug)]
+pub struct Active {
+    /// the approver watching the session, and the protocol agreed upon
+    /// with their client
+    socket:   Socket,
+    protocol: Hello,
+
+    /// the stream the output most recently sent to the approver came
+    /// from
+    stream: Stream,
+
+    /// what the approver's sent that hasn't yet been looked at
+    inbox: Inbox,
+
+    /// when the session has to end, if its duration is limited
+    pub expires: Option<Instant>,
+
+    /// when the approver is next asked to re-approve the session, if
+    /// they're periodically asked to
+    pub reapproval: Option<Reapproval>,
+
+    /// the socket other approvers connect to in order to take over the
+    /// session, if it can be handed off; the handoff they've offered,
+    /// if one is underway; and how many have been completed
+    pub listener:  Option<Listener>,
+    pub transfer:  Option<Offer>,
+    pub transfers: u32,
+}
+
+impl Active {
+    fn new(socket: Socket, protocol: Hello) -> Self {
+        let mut active = Self {
+            socket,
+            protocol,
+            stream:     Stream::Plugin,
+            inbox:      Inbox::default(),
+            expires:    None,
+            reapproval: None,
+            listener:   None,
+            transfer:   None,
+            transfers:  0,
+        };
+
+        active.start_framing();
+        active
+    }
+
+    /// Tells the approver that everything after this is framed, if
+    /// their client asked for it to be.
+    fn start_framing(&mut self) {
+        let _ = self.socket.write_all(&wire::framing(self.protocol.encoding));
+    }
+
+    /// Sends `message` to the approver in the encoding their client
+    /// asked for, within the limits it was negotiated with.
+    fn send(&mut self, message: &Message<'_>) -> io::Result<()> {
+        self.socket.write_all(&wire::encode_within(self.protocol.encoding, self.protocol.limits, message))
+    }
+
+    /// The approver's socket, for writing to them directly. Only
+    /// `sudo_pair_sim` does; the plugin reads and writes through the
+    /// session.
+    pub fn socket(&mut self) -> &mut Socket {
+        &mut self.socket
+    }
+
+    pub fn protocol(&self) -> Hello {
+        self.protocol
+    }
+
+    /// Ends the session if, as of `clock`, it's outlived
+    /// `soft_max_session_duration`. This is only checked when sudo calls
+    /// the plugin, so it's not a hard limit.
+    pub fn check_expiry(&self, clock: &dyn Clock) -> std::result::Result<(), Termination> {
+        match self.expires {
+            Some(expires) if clock.instant() >= expires => Err(Termination::new(
+                ErrorKind::SessionExpired,
+                "session ended: it exceeded its maximum duration",
+            )),
+
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the plugin's own output to the approver (e.g., notices
+    /// about the session), as `send_stream` does.
+    pub fn send_output(
+        &mut self,
+        transcript: &mut Transcript,
+        slog:       &slog::Logger,
+        output:     &[u8],
+    ) -> Result<()> {
+        self.send_stream(transcript, slog, Stream::Plugin, output)
+    }
+
+    /// Sends output from `stream` to the approver, digesting it into
+    /// `transcript` along the way. The digest covers exactly what the
+    /// approver was sent. Clients that negotiated `STREAMS` are told
+    /// whenever the output's stream changes.
+    pub fn send_stream(
+        &mut self,
+        transcript: &mut Transcript,
+        slog:       &slog::Logger,
+        stream:     Stream,
+        output:     &[u8],
+    ) -> Result<()> {
+        if output.is_empty() {
+            return Ok(());
+        }
+
+        if stream != self.stream && self.protocol.handles(Capabilities::STREAMS) {
+            self.send(&Message::Stream(stream))
+                .context(ErrorKind::SessionTerminated)?;
+        }
+
+        self.stream = stream;
+
+        self.send(&Message::Output(output))
+            .context(ErrorKind::SessionTerminated)?;
+
+        slog::trace!(slog, "{{{} bytes sent}}", output.len());
+
+        let streams_digests = self.protocol.streams(Capabilities::DIGESTS);
+        let checkpoint      = transcript.update(output)
+            .filter(|_| streams_digests);
+
+        if let Some(checkpoint) = checkpoint {
+            self.send_checkpoint(&checkpoint)?;
+
+            slog::debug!(slog, "transcript checkpoint sent";
+                "bytes"  => checkpoint.bytes,
+                "sha256" => checkpoint.digest,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Tells the approver about output that was kept from them, if
+    /// they negotiated `MARKERS`. It isn't part of the transcript.
+    pub fn send_suppressed(&mut self, suppression: &Suppression) -> Result<()> {
+        if !self.protocol.handles(Capabilities::MARKERS) {
+            return Ok(());
+        }
+
+        self.send(&Message::Suppressed {
+            reason: suppression.reason.name(),
+            bytes:  suppression.bytes,
+        }).context(ErrorKind::SessionTerminated)?;
+
+        Ok(())
+    }
+
+    /// Tells the approver the size of the user's terminal, so their
+    /// rendering of the session wraps the same way.
+    pub fn send_winsize(&mut self, rows: u32, cols: u32) -> Result<()> {
+        if !self.protocol.streams(Capabilities::WINSIZE) {
+            return Ok(());
+        }
+
+        self.send(&Message::Winsize { rows, cols })
+            .context(ErrorKind::SessionTerminated)?;
+
+        Ok(())
+    }
+
+    /// Sends the approver a checkpoint of the transcript.
+    pub fn send_checkpoint(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        self.send(&Message::Checkpoint { bytes: checkpoint.bytes, sha256: &checkpoint.digest })
+            .context(ErrorKind::SessionTerminated)?;
+
+        Ok(())
+    }
+
+    /// Asks the approver to re-approve the session by `deadline`, in
+    /// seconds since the epoch.
+    pub fn send_reapprove(&mut self, deadline: u64) -> Result<()> {
+        self.send(&Message::Reapprove { deadline })
+            .context(ErrorKind::SessionTerminated)?;
+
+        Ok(())
+    }
+
+    /// Sends the user's reply to a message from the approver, if their
+    /// client negotiated `CHAT`.
+    pub fn send_chat(&mut self, text: &str) -> Result<()> {
+        if !self.protocol.handles(Capabilities::CHAT) {
+            return Ok(());
+        }
+
+        self.send(&Message::Chat(text))
+            .context(ErrorKind::SessionTerminated)?;
+
+        Ok(())
+    }
+
+    /// Reads whatever the approver has sent since this was last
+    /// called into the inbox. Returns how much was read, as
+    /// `Socket::try_read` does.
+    fn receive(&mut self) -> io::Result<Option<usize>> {
+        let mut input = [0; 256];
+        let read      = self.socket.try_read(&mut input)?;
+
+        if let Some(n) = read {
+            self.inbox.receive(&input[..n], self.protocol.handles(Capabilities::CHAT));
+        }
+
+        Ok(read)
+    }
+
+    /// Reads everything the approver has sent so far into the inbox.
+    fn receive_all(&mut self) {
+        while let Ok(Some(n)) = self.receive() {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    /// The approver's answer to whatever they were last asked, if
+    /// they've given one. Hanging up counts as `n`.
+    pub fn pair_answer(&mut self) -> Option<bool> {
+        let received = self.receive();
+
+        if let Some(answer) = reapproval::answer(&self.inbox.take_keys()) {
+            return Some(answer);
+        }
+
+        match received {
+            Ok(Some(0)) | Err(_) => Some(false),
+            _                    => None,
+        }
+    }
+
+    /// Discards the keys the approver has already typed, so that they
+    /// can't be mistaken for an answer to a question they haven't yet
+    /// been asked. Messages they've sent are kept.
+    pub fn discard_input(&mut self) {
+        self.receive_all();
+        self.inbox.discard_keys();
+    }
+
+    /// The messages the approver has sent the user since this was last
+    /// called, if their client negotiated `CHAT`.
+    pub fn take_chats(&mut self) -> Vec<Chat> {
+        if !self.protocol.handles(Capabilities::CHAT) {
+            return Vec::new();
+        }
+
+        self.receive_all();
+        self.inbox.take_chats()
+    }
+
+    /// Hands the session off to the approver connected to `socket`,
+    /// who's agreed upon `protocol`. The old approver's connection is
+    /// closed when it's dropped.
+    pub fn hand_off(&mut self, socket: Socket, protocol: Hello) {
+        self.socket    = socket;
+        self.protocol  = protocol;
+        self.stream    = Stream::Plugin;
+        self.inbox     = Inbox::default();
+        self.transfers += 1;
+
+        self.start_framing();
+    }
+
+    /// Ends the session early for the reason `kind`. The approver stays
+    /// connected until the session closes.
+    pub fn terminate(self, kind: ErrorKind) -> Terminated {
+        Terminated { active: self, kind }
+    }
+
+    fn close(mut self) -> Closed {
+        // nobody else can take over a session that's over
+        self.listener = None;
+
+        if let Some(mut offer) = self.transfer.take() {
+            let _ = offer.candidate.write_all(b"\nhandoff abandoned: the session ended\n");
+        }
+
+        let _ = self.socket.close();
+
+        Closed { paired: true, transfers: self.transfers }
+    }
+}
+
+/// Why a session is ended early: the error sudo's callbacks are
+/// rejected with, and the notice its approver is sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Termination {
+    pub kind: ErrorKind,
+
+    /// what the approver is told, if they can be told anything
+    pub notice: Option<&'static str>,
+}
+
+impl Termination {
+    pub fn new(kind: ErrorKind, notice: &'static str) -> Self {
+        Self { kind, notice: Some(notice) }
+    }
+}
+
+/// A session ended by an error (e.g., the approver hanging up) can't
+/// tell the approver anything.
+impl From<Error> for Termination {
+    fn from(error: Error) -> Self {
+        Self { kind: error.kind(), notice: None }
+    }
+}
+
+/// A session that was ended early for the reason `kind`, while the
+/// command may still be running.
+#[derive(Debug)]
+pub struct Terminated {
+    active: Active,
+    kind:   ErrorKind,
+}
+
+impl Terminated {
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+/// A session that's over.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Closed {
+    /// whether it was watched by an approver
+    pub paired: bool,
+
+    /// how many times it was handed off to another approver
+    pub transfers: u32,
+}
+
+/// Where the session is, once the plugin has been opened.
+#[derive(Debug)]
+pub enum Session {
+    /// no pair was needed (e.g., the session was exempt or pre-approved)
+    Unpaired,
+
+    Active(Active),
+    Terminated(Terminated),
+    Closed(Closed),
+}
+
+impl Session {
+    /// Whether the session is (or was) watched by an approver.
+    pub fn is_paired(&self) -> bool {
+        match self {
+            Session::Unpaired       => false,
+            Session::Active(_)      => true,
+            Session::Terminated(_)  => true,
+            Session::Closed(closed) => closed.paired,
+        }
+    }
+
+    /// How many times the session has been handed off to another
+    /// approver.
+    pub fn transfers(&self) -> u32 {
+        match self {
+            Session::Unpaired          => 0,
+            Session::Active(active)    => active.transfers,
+            Session::Terminated(ended) => ended.active.transfers,
+            Session::Closed(closed)    => closed.transfers,
+        }
+    }
+
+    /// Why the session was terminated, if it was and hasn't yet been
+    /// closed.
+    pub fn termination(&self) -> Option<ErrorKind> {
+        match self {
+            Session::Terminated(ended) => Some(ended.kind()),
+            _                          => None,
+        }
+    }
+
+    /// The approver watching the session, whether or not it's been
+    /// terminated, so they can be sent whatever's left as it closes.
+    pub fn watched(&mut self) -> Option<&mut Active> {
+        match self {
+            Session::Active(active)    => Some(active),
+            Session::Terminated(ended) => Some(&mut ended.active),
+            _                          => None,
+        }
+    }
+
+    /// Ends an active session early for `reason`, telling its approver
+    /// why. Sessions that aren't active have nobody to tell, and are
+    /// left as they are.
+    pub fn terminate(
+        &mut self,
+        transcript: &mut Transcript,
+        slog:       &slog::Logger,
+        reason:     Termination,
+    ) {
+        *self = match mem::replace(self, Session::Unpaired) {
+            Session::Active(mut active) => {
+                if let Some(notice) = reason.notice {
+                    let _ = active.send_output(transcript, slog, format!("\n{}\n", notice).as_bytes());
+                }
+
+                Session::Terminated(active.terminate(reason.kind))
+            },
+
+            session => session,
+        };
+    }
+
+    /// Closes the session, disconnecting its approver (and anyone
+    /// offering to take it over).
+    pub fn close(&mut self) {
+        let closed = match mem::replace(self, Session::Unpaired) {
+            Session::Unpaired          => Closed { paired: false, transfers: 0 },
+            Session::Active(active)    => active.close(),
+            Session::Terminated(ended) => ended.active.close(),
+            Session::Closed(closed)    => closed,
+        };
+
+        *self = Session::Closed(closed);
+    }
+}
+
+/// Attributes the failure of a wait on the pair to the approval
+/// deadline passing, if that's what ended it, or else to `kind`.
+pub fn before_deadline<T>(result: io::Result<T>, kind: ErrorKind) -> Result<T> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock =>
+            Err(ErrorKind::ApprovalTimedOut.into()),
+
+        result => Ok(result.context(kind)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::protocol;
+    use crate::suppression::Reason;
+    use crate::totp::Code;
+
+    use sudo_pair_types::protocol::{Encoding, PROTOCOL_VERSION};
+
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn connect(awaiting: &mut AwaitingApproval) -> UnixStream {
+        let (socket, approver) = Socket::pair().unwrap();
+
+        awaiting.connect(socket);
+        approver
+    }
+
+    /// Everything the approver has been sent, once the session's side
+    /// of the connection is gone.
+    fn received(mut approver: UnixStream) -> String {
+        let mut received = String::new();
+        let _            = approver.read_to_string(&mut received).unwrap();
+
+        received
+    }
+
+    fn slog() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    fn hello(capabilities: Capabilities) -> Hello {
+        Hello {
+            version: PROTOCOL_VERSION,
+            capabilities,
+            deadline: None,
+            encoding: Encoding::Text,
+            utc_offset: None,
+            winsize: None,
+            limits: protocol::LIMITS,
+        }
+    }
+
+    #[test]
+    fn approves_with_a_single_approver() {
+        let mut awaiting = AwaitingApproval::new();
+        let mut approver = connect(&mut awaiting);
+
+        let mut capabilities = Capabilities::WINSIZE;
+        capabilities.insert(Capabilities::COMMENTS);
+
+        let client = hello(Capabilities::WINSIZE);
+
+        approver.write_all(&client.encode()).unwrap();
+        approver.write_all(b"y").unwrap();
+
+        let answer = awaiting.prompt(&slog(), b"approve? ", hello(capabilities), None).unwrap();
+
+        assert_eq!(Answer { approved: true, comment: None, credentials: Credentials::default() }, answer);
+
+        let mut session = Session::Active(awaiting.approve().unwrap());
+
+        if let Session::Active(ref mut active) = session {
+            assert_eq!(client, active.protocol());
+            active.send_winsize(24, 80).unwrap();
+        }
+
+        session.close();
+
+        let mut expected = b"approve? y\n".to_vec();
+        expected.extend_from_slice(&protocol::winsize(24, 80));
+
+        assert_eq!(expected, received(approver).into_bytes());
+    }
+
+    #[test]
+    fn reads_comments_with_the_answer() {
+        let mut awaiting = AwaitingApproval::new();
+        let mut approver = connect(&mut awaiting);
+
+        let mut capabilities = Capabilities::WINSIZE;
+        capabilities.insert(Capabilities::COMMENTS);
+
+        approver.write_all(&hello(capabilities).encode()).unwrap();
+        approver.write_all(b"\x1b]5379;sudo_pair;comment;text=wrong%20host\x07n").unwrap();
+
+        assert_eq!(
+            Answer { approved: false, comment: Some("wrong host".into()), credentials: Credentials::default() },
+            awaiting.prompt(&slog(), b"approve? ", hello(capabilities), None).unwrap(),
+        );
+
+        // clients that didn't negotiate comments can't send them
+        let mut approver = connect(&mut awaiting);
+
+        approver.write_all(b"\x1b]5379;sudo_pair;comment;text=hi\x07y").unwrap();
+
+        assert_eq!(
+            ErrorKind::SessionDeclined,
+            awaiting.prompt(&slog(), b"approve? ", hello(capabilities), None).unwrap_err().kind(),
+        );
+    }
+
+    #[test]
+    fn reads_credentials_with_the_answer() {
+        let mut awaiting = AwaitingApproval::new();
+        let mut approver = connect(&mut awaiting);
+
+        approver.write_all(b"\x1b]5379;sudo_pair;signature;sshsig=U1NIU0lH\x07").unwrap();
+        approver.write_all(b"\x1b]5379;sudo_pair;totp;approver=alice;code=005924\x07y").unwrap();
+
+        assert_eq!(
+            Answer {
+                approved:    true,
+                comment:     None,
+                credentials: Credentials {
+                    signature: Some(b"SSHSIG".to_vec()),
+                    totp:      Some(Code { approver: "alice".into(), code: "005924".into() }),
+                },
+            },
+            awaiting.prompt(&slog(), b"approve? ", hello(Capabilities::WINSIZE), None).unwrap(),
+        );
+    }
+
+    #[test]
+    fn releases_all_but_the_first_of_a_quorum() {
+        let mut awaiting = AwaitingApproval::new();
+
+        let first = connect(&mut awaiting);
+        awaiting.set_aside(1);
+
+        let second = connect(&mut awaiting);
+
+        assert_eq!(1, awaiting.approvals());
+
+        let mut session = Session::Active(awaiting.approve().unwrap());
+        session.close();
+
+        assert_eq!(
+            "waiting for 1 more approver\nsession approved by all 2 approvers; you're watching it\n",
+            received(first),
+        );
+
+        assert_eq!(
+            "session approved; the first approver is watching it\n",
+            received(second),
+        );
+    }
+
+    #[test]
+    fn releases_approvers_who_dont_answer() {
+        let mut awaiting = AwaitingApproval::new();
+
+        let idle = connect(&mut awaiting);
+
+        let error = awaiting.prompt(&slog(), b"approve? ", hello(Capabilities::empty()), Some(Instant::now())).unwrap_err();
+        assert_eq!(ErrorKind::ApprovalTimedOut, error.kind());
+
+        awaiting.release(b"too slow\n");
+        assert!(awaiting.pair().is_none());
+
+        let mut next = connect(&mut awaiting);
+        next.write_all(b"y").unwrap();
+
+        assert!(awaiting.prompt(&slog(), b"approve? ", hello(Capabilities::empty()), None).unwrap().approved);
+        assert_eq!("approve? too slow\n", received(idle));
+    }
+
+    #[test]
+    fn can_only_be_approved_by_someone() {
+        assert!(AwaitingApproval::new().approve().is_none());
+    }
+
+    #[test]
+    fn tells_every_approver_when_declined() {
+        let mut awaiting = AwaitingApproval::new();
+
+        let first = connect(&mut awaiting);
+        awaiting.set_aside(1);
+
+        let second = connect(&mut awaiting);
+
+        awaiting.decline(b"declined\n");
+
+        assert_eq!("waiting for 1 more approver\ndeclined\n", received(first));
+        assert_eq!("declined\n",                              received(second));
+    }
+
+    #[test]
+    fn streams_output_only_once_approved() {
+        let slog           = slog::Logger::root(slog::Discard, slog::o!());
+        let mut transcript = Transcript::new(Default::default(), Arc::new(SystemClock));
+        let mut awaiting   = AwaitingApproval::new();
+        let approver       = connect(&mut awaiting);
+
+        let mut active = awaiting.approve().unwrap();
+
+        active.send_output(&mut transcript, &slog, b"output").unwrap();
+
+        let mut session = Session::Active(active);
+        session.close();
+
+        assert_eq!("output", received(approver));
+        assert_eq!(6,        transcript.checkpoint().bytes);
+    }
+
+    #[test]
+    fn frames_everything_once_watched_if_asked() {
+        let slog           = slog();
+        let mut transcript = Transcript::new(Default::default(), Arc::new(SystemClock));
+        let mut awaiting   = AwaitingApproval::new();
+        let mut approver   = connect(&mut awaiting);
+
+        let client = Hello { encoding: Encoding::MessagePack, ..hello(Capabilities::WINSIZE) };
+
+        approver.write_all(&client.encode()).unwrap();
+        approver.write_all(b"y").unwrap();
+
+        let _ = awaiting.prompt(&slog, b"approve? ", hello(Capabilities::WINSIZE), None).unwrap();
+
+        let mut active = awaiting.approve().unwrap();
+
+        active.send_output(&mut transcript, &slog, b"ok").unwrap();
+        active.send_winsize(24, 80).unwrap();
+
+        let mut session = Session::Active(active);
+        session.close();
+
+        let mut expected = b"approve? y\n\x1b]5379;sudo_pair;framing;encoding=msgpack\x07".to_vec();
+        expected.extend_from_slice(&wire::encode(Encoding::MessagePack, &Message::Output(b"ok")));
+        expected.extend_from_slice(&wire::encode(Encoding::MessagePack, &Message::Winsize { rows: 24, cols: 80 }));
+
+        let mut received = Vec::new();
+        let _ = approver.read_to_end(&mut received).unwrap();
+
+        assert_eq!(expected, received);
+    }
+
+    #[test]
+    fn labels_streams_if_asked() {
+        let slog           = slog();
+        let mut transcript = Transcript::new(Default::default(), Arc::new(SystemClock));
+        let mut awaiting   = AwaitingApproval::new();
+        let mut approver   = connect(&mut awaiting);
+
+        approver.write_all(&hello(Capabilities::STREAMS).encode()).unwrap();
+        approver.write_all(b"y").unwrap();
+
+        let _ = awaiting.prompt(&slog, b"approve? ", hello(Capabilities::STREAMS), None).unwrap();
+
+        let mut active = awaiting.approve().unwrap();
+
+        active.send_stream(&mut transcript, &slog, Stream::StdOut, b"out ").unwrap();
+        active.send_stream(&mut transcript, &slog, Stream::StdOut, b"more ").unwrap();
+        active.send_stream(&mut transcript, &slog, Stream::StdErr, b"err ").unwrap();
+        active.send_output(&mut transcript, &slog, b"ended").unwrap();
+
+        let mut session = Session::Active(active);
+        session.close();
+
+        assert_eq!(
+            "approve? y\n\
+             \x1b]5379;sudo_pair;stream;name=stdout\x07out more \
+             \x1b]5379;sudo_pair;stream;name=stderr\x07err \
+             \x1b]5379;sudo_pair;stream;name=plugin\x07ended",
+            received(approver),
+        );
+
+        // the labels aren't part of the transcript
+        assert_eq!(18, transcript.checkpoint().bytes);
+    }
+
+    #[test]
+    fn labels_no_streams_unless_asked() {
+        let slog           = slog();
+        let mut transcript = Transcript::new(Default::default(), Arc::new(SystemClock));
+        let mut awaiting   = AwaitingApproval::new();
+        let approver       = connect(&mut awaiting);
+
+        let mut active = awaiting.approve().unwrap();
+
+        active.send_stream(&mut transcript, &slog, Stream::StdErr, b"err").unwrap();
+
+        let mut session = Session::Active(active);
+        session.close();
+
+        assert_eq!("err", received(approver));
+    }
+
+    #[test]
+    fn marks_suppressions_if_asked() {
+        let slog           = slog();
+        let mut transcript = Transcript::new(Default::default(), Arc::new(SystemClock));
+        let mut awaiting   = AwaitingApproval::new();
+        let mut approver   = connect(&mut awaiting);
+
+        approver.write_all(&hello(Capabilities::MARKERS).encode()).unwrap();
+        approver.write_all(b"y").unwrap();
+
+        let _ = awaiting.prompt(&slog, b"approve? ", hello(Capabilities::MARKERS), None).unwrap();
+
+        let mut active = awaiting.approve().unwrap();
+
+        active.send_output(&mut transcript, &slog, b"ls").unwrap();
+        active.send_suppressed(&Suppression { reason: Reason::Escapes, bytes: 10 }).unwrap();
+
+        let mut session = Session::Active(active);
+        session.close();
+
+        assert_eq!(
+            "approve? y\nls\x1b]5379;sudo_pair;suppressed;reason=escapes;bytes=10\x07",
+            received(approver),
+        );
+
+        assert_eq!(2, transcript.checkpoint().bytes);
+    }
+
+    #[test]
+    fn expires_once_its_duration_has_passed() {
+        let clock        = ManualClock::at(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        let mut awaiting = AwaitingApproval::new();
+        let _approver    = connect(&mut awaiting);
+        let mut active   = awaiting.approve().unwrap();
+
+        assert!(active.check_expiry(&clock).is_ok());
+
+        active.expires = Some(clock.instant() + Duration::from_secs(3_600));
+
+        clock.advance(Duration::from_secs(3_599));
+        assert!(active.check_expiry(&clock).is_ok());
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(ErrorKind::SessionExpired, active.check_expiry(&clock).unwrap_err().kind);
+    }
+
+    #[test]
+    fn stays_paired_once_terminated() {
+        let mut awaiting = AwaitingApproval::new();
+        let approver     = connect(&mut awaiting);
+
+        let mut active  = awaiting.approve().unwrap();
+        let (socket, _) = Socket::pair().unwrap();
+
+        active.hand_off(socket, Hello::legacy());
+
+        let terminated = active.terminate(ErrorKind::SessionExpired);
+
+        assert_eq!(ErrorKind::SessionExpired, terminated.kind());
+
+        let mut session = Session::Terminated(terminated);
+
+        assert_eq!(Some(ErrorKind::SessionExpired), session.termination());
+
+        assert!(session.is_paired());
+        assert!(session.watched().is_some());
+
+        session.close();
+
+        assert!(session.is_paired());
+        assert!(session.watched().is_none());
+        assert_eq!(1, session.transfers());
+
+        // the original approver was disconnected by the handoff
+        assert_eq!("", received(approver));
+    }
+
+    #[test]
+    fn tells_approvers_why_sessions_are_terminated() {
+        let slog           = slog();
+        let mut transcript = Transcript::new(Default::default(), Arc::new(SystemClock));
+        let mut awaiting   = AwaitingApproval::new();
+        let approver       = connect(&mut awaiting);
+
+        let mut session = Session::Active(awaiting.approve().unwrap());
+        let reason      = Termination::new(ErrorKind::SessionExpired, "session ended: it ran too long");
+
+        session.terminate(&mut transcript, &slog, reason);
+
+        match session {
+            Session::Terminated(ref terminated) => assert_eq!(ErrorKind::SessionExpired, terminated.kind()),
+            _                                   => panic!("the session should be terminated"),
+        }
+
+        // it's already over, so there's nothing more to say
+        session.terminate(&mut transcript, &slog, Termination::from(Error::from(ErrorKind::SessionTerminated)));
+        session.close();
+
+        assert_eq!("\nsession ended: it ran too long\n", received(approver));
+
+        let mut unpaired = Session::Unpaired;
+
+        unpaired.terminate(&mut transcript, &slog, reason);
+
+        assert!(!unpaired.is_paired());
+    }
+
+    #[test]
+    fn closes_unpaired_sessions() {
+        let mut session = Session::Unpaired;
+
+        session.close();
+
+        assert!(!session.is_paired());
+        assert_eq!(0, session.transfers());
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Catching the signals that should cancel a session while it's waiting
+//! on the approver, so the wait can be abandoned cleanly instead of
+//! `sudo` being killed with the socket left behind.
+
+use std::io::{Error, Result};
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+
+use libc::c_int;
+
+/// The signals that cancel a session waiting on its approver.
+const CANCEL_SIGNALS : [c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// The most recent signal caught by `record_signal`, or zero.
+static CAUGHT : AtomicI32 = AtomicI32::new(0);
+
+/// The write end of the self-pipe `record_signal` wakes waiters
+/// through, or -1 when no `SignalGuard` is installed.
+static PIPE : AtomicI32 = AtomicI32::new(-1);
+
+/// Replaces the handlers for `CANCEL_SIGNALS` for as long as it's
+/// alive. Caught signals interrupt blocking system calls (they're
+/// installed without `SA_RESTART`) and make `fd` readable, so they can
+/// be waited on alongside other file descriptors with `poll(2)`.
+#[derive(Debug)]
+pub struct SignalGuard {
+    previous: Vec<(c_int, libc::sigaction)>,
+    pipe:     [RawFd; 2],
+}
+
+impl SignalGuard {
+    pub fn install() -> Result<Self> {
+        let mut pipe = [-1; 2];
+
+        unsafe {
+            if libc::pipe(pipe.as_mut_ptr()) == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+
+        // constructed before anything else can fail, so `Drop` cleans
+        // up whatever was done
+        let mut guard = Self { previous: Vec::new(), pipe };
+
+        for fd in &pipe {
+            set_flags(*fd)?;
+        }
+
+        CAUGHT.store(0, Ordering::SeqCst);
+        PIPE  .store(pipe[1], Ordering::SeqCst);
+
+        let handler : extern "C" fn(c_int) = record_signal;
+
+        for signal in &CANCEL_SIGNALS {
+            unsafe {
+                let mut action = mem::zeroed::<libc::sigaction>();
+                let mut old    = mem::MaybeUninit::<libc::sigaction>::uninit();
+
+                action.sa_sigaction = handler as libc::sighandler_t;
+                action.sa_flags     = 0;
+
+                let _ = libc::sigemptyset(&mut action.sa_mask);
+
+                if libc::sigaction(*signal, &action, old.as_mut_ptr()) == -1 {
+                    return Err(Error::last_os_error());
+                }
+
+                guard.previous.push((*signal, old.assume_init()));
+            }
+        }
+
+        Ok(guard)
+    }
+
+    /// A file descriptor that becomes readable once a signal has been
+    /// caught.
+    pub fn fd(&self) -> RawFd {
+        self.pipe[0]
+    }
+
+    /// The signal that was caught, if any.
+    pub fn caught(&self) -> Option<c_int> {
+        match CAUGHT.load(Ordering::SeqCst) {
+            0      => None,
+            signal => Some(signal),
+        }
+    }
+
+    /// Waits for at most `timeout` for a signal to be caught, returning
+    /// it if one was.
+    pub fn wait(&self, timeout: Duration) -> Option<c_int> {
+        let mut fd = libc::pollfd { fd: self.fd(), events: libc::POLLIN, revents: 0 };
+        let millis = timeout.as_millis().min(c_int::MAX as u128) as c_int;
+
+        if self.caught().is_none() {
+            let _ = unsafe { libc::poll(&mut fd, 1, millis) };
+        }
+
+        self.caught()
+    }
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        for (signal, action) in self.previous.drain(..) {
+            let _ = unsafe { libc::sigaction(signal, &action, ptr::null_mut()) };
+        }
+
+        PIPE.store(-1, Ordering::SeqCst);
+
+        for fd in &self.pipe {
+            let _ = unsafe { libc::close(*fd) };
+        }
+    }
+}
+
+/// Returns the conventional name of `signal`.
+pub fn name(signal: c_int) -> String {
+    match signal {
+        libc::SIGINT  => "SIGINT".into(),
+        libc::SIGTERM => "SIGTERM".into(),
+        libc::SIGHUP  => "SIGHUP".into(),
+        _             => format!("signal {}", signal),
+    }
+}
+
+/// Records that `signal` was received and wakes anything polling the
+/// self-pipe. Only async-signal-safe operations are allowed here.
+extern "C" fn record_signal(signal: c_int) {
+    CAUGHT.store(signal, Ordering::SeqCst);
+
+    let fd = PIPE.load(Ordering::SeqCst);
+
+    if fd != -1 {
+        // if the pipe is full, it's already readable
+        let _ = unsafe { libc::write(fd, [0_u8].as_ptr() as _, 1) };
+    }
+}
+
+/// Makes `fd` non-blocking and close-on-exec, so the handler can never
+/// block and the command doesn't inherit the pipe.
+fn set_flags(fd: RawFd) -> Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catches_cancel_signals() {
+        let guard = SignalGuard::install().unwrap();
+
+        assert_eq!(None, guard.caught());
+
+        let _ = unsafe { libc::raise(libc::SIGINT) };
+
+        assert_eq!(Some(libc::SIGINT), guard.caught());
+
+        let mut byte = [0_u8; 1];
+        let     read = unsafe { libc::read(guard.fd(), byte.as_mut_ptr() as _, 1) };
+
+        assert_eq!(1, read);
+    }
+
+    #[test]
+    fn names_signals() {
+        assert_eq!("SIGTERM",   name(libc::SIGTERM));
+        assert_eq!("signal 10", name(10));
+    }
+}
@@ -17,32 +17,230 @@
 // be avoided
 #![cfg_attr(feature="cargo-clippy", allow(clippy::similar_names))]
 
+use std::collections::HashSet;
 use std::ffi::CString;
+use std::fmt;
 use std::fs;
 use std::io::{Read, Write, Result, Error, ErrorKind};
 use std::net::Shutdown;
 use std::os::unix::prelude::*;
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::mem;
-use std::path::Path;
-use std::ptr;
+use std::mem::{self, size_of};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use libc::{self, gid_t, mode_t, uid_t};
 
+/// Looks up the groups the user with a uid is a member of, if they can
+/// be found, for checking connections to abstract sockets against more
+/// than the connecting process' primary group.
+pub type Groups<'a> = dyn Fn(uid_t) -> Option<HashSet<gid_t>> + 'a;
+
+/// How connections to a socket are queued, and which of those from
+/// someone who could write to it are admitted.
+pub struct Admission<'a> {
+    /// how many connections the kernel holds until they're accepted
+    pub backlog: u32,
+
+    /// the most connections accepted while waiting for one that's
+    /// admitted, or `0` for any number
+    pub max_pending: u32,
+
+    /// the only uids connections are admitted from, if they're limited
+    /// to more than those who could write to the socket
+    pub uids: Option<&'a [uid_t]>,
+
+    /// looks up the groups of those connecting to abstract sockets
+    pub groups: &'a Groups<'a>,
+
+    /// counts a connection attempt by a uid, returning true if they're
+    /// banned from connecting, if connection attempts are limited
+    pub banned: Option<&'a dyn Fn(uid_t) -> bool>,
+
+    /// told the uid and gid of each connection that's dropped, other
+    /// than those from banned uids
+    pub dropped: &'a dyn Fn(uid_t, gid_t),
+}
+
+impl fmt::Debug for Admission<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Admission")
+            .field("backlog",     &self.backlog)
+            .field("max_pending", &self.max_pending)
+            .field("uids",        &self.uids)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug)]
-pub(crate) struct Socket {
+pub struct Socket {
     socket: UnixStream,
 }
 
 impl Socket {
-    pub(crate) fn open<P: AsRef<Path>>(
-        path: P,
-        uid:  uid_t,
-        gid:  gid_t,
-        mode: mode_t,
+    /// Creates a socket at `path` and waits for a connection to it that
+    /// `admission` admits, giving up early if `cancel` becomes readable,
+    /// `deadline` passes, or too many connections have been dropped.
+    ///
+    /// A `path` starting with `@` names a socket in Linux's abstract
+    /// namespace instead (e.g., `@/var/run/sudo_pair/1000.4242.sock`),
+    /// which is never on the filesystem. Such sockets don't have
+    /// permissions of their own, so connections from processes that
+    /// couldn't have written to a socket file owned by `uid` and `gid`
+    /// with permissions `mode` are closed as soon as they're accepted,
+    /// with the connecting user's `groups` standing in for the process'
+    /// supplementary groups when they can be found.
+    pub fn open<P: AsRef<Path>>(
+        path:      P,
+        uid:       uid_t,
+        gid:       gid_t,
+        mode:      mode_t,
+        admission: &Admission<'_>,
+        cancel:    RawFd,
+        deadline:  Option<Instant>,
     ) -> Result<Self> {
         let path = path.as_ref();
 
+        let mut accepted = 0;
+
+        let socket = Self::bind(path, uid, gid, mode, admission.backlog).and_then(|listener| loop {
+            unsafe {
+                // rust automatically wraps the `accept()` function in a
+                // loop that retries on EINTR, so we have to get creative
+                // here and `poll(2)` ourselves if we want signals (e.g.,
+                // Ctrl-C) to interrupt the wait; `cancel` becomes
+                // readable when one has been caught
+                let mut fds = [
+                    libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                    libc::pollfd { fd: cancel,               events: libc::POLLIN, revents: 0 },
+                ];
+
+                let timeout = deadline.map_or(-1, |deadline| {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+
+                    remaining.as_millis().min(libc::c_int::MAX as _) as libc::c_int
+                });
+
+                let ready = libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout);
+
+                if ready == -1 {
+                    return Err(Error::last_os_error());
+                }
+
+                if ready == 0 {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "no pair connected before the deadline",
+                    ));
+                }
+
+                if fds[1].revents != 0 {
+                    return Err(Error::new(
+                        ErrorKind::Interrupted,
+                        "interrupted while waiting for a connection",
+                    ));
+                }
+
+                // as a sanity check, confirm that the fd we're going to
+                // `accept` is the one that `poll` says is ready
+                if fds[0].revents & libc::POLLIN == 0 {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        "`poll` returned without a connection to accept",
+                    ));
+                }
+            }
+
+            let (connection, _) = listener.accept()?;
+
+            if Self::permits(path, &connection, uid, gid, mode, admission)? {
+                return Ok(Self { socket: connection });
+            }
+
+            accepted += 1;
+
+            if admission.max_pending != 0 && accepted >= admission.max_pending {
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("gave up waiting for a pair after dropping {} connections", accepted),
+                ));
+            }
+        });
+
+        // once the connection has been made (or aborted due to a signal),
+        // we don't need the socket to remain on the filesystem
+        //
+        // we ignore the result of this operation (instead of returning
+        // the error because a) any error from the previous operation is
+        // of higher importance (we didn't return the error immediately
+        // because we want to unlink the socket regardless) and b) it's
+        // more important to continue the sudo session than to worry
+        // about filesystem janitorial work
+        let _ = Self::unlink(&path);
+
+        socket
+    }
+
+    /// Connects to the control socket of an approver daemon at
+    /// `control` and announces the session on it with `announcement`,
+    /// so the connection can stand in for the session's own socket.
+    ///
+    /// The daemon is held to what a pair connecting to the session's
+    /// socket would be: it has to be run by someone who could have
+    /// written to a socket owned by `uid` and `gid` with permissions
+    /// `mode`, and be admitted by `admission`. As with `open`, a
+    /// `control` starting with `@` names an abstract socket.
+    pub fn announce<P: AsRef<Path>>(
+        control:      P,
+        uid:          uid_t,
+        gid:          gid_t,
+        mode:         mode_t,
+        admission:    &Admission<'_>,
+        announcement: &[u8],
+    ) -> Result<Self> {
+        let control = control.as_ref();
+
+        let mut connection = match abstract_name(control) {
+            Some(name) => connect_abstract(name),
+            None       => UnixStream::connect(control),
+        }?;
+
+        if !Self::admits(&connection, uid, gid, mode, admission, false)? {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "the approver daemon isn't run by anyone who could approve the session",
+            ));
+        }
+
+        connection.write_all(announcement)?;
+
+        Ok(Self { socket: connection })
+    }
+
+    /// Creates a socket at `path`, owned by `uid` and `gid` and with
+    /// permissions `mode`, replacing any socket already there, and has
+    /// the kernel hold up to `backlog` connections to it.
+    fn bind(path: &Path, uid: uid_t, gid: gid_t, mode: mode_t, backlog: u32) -> Result<UnixListener> {
+        let listener = match abstract_name(path) {
+            Some(name) => bind_abstract(name),
+            None       => Self::bind_path(path, uid, gid, mode),
+        }?;
+
+        // the standard library listens with a backlog of its own
+        // choosing, and listening again is how it's changed
+        let backlog = backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+
+        if unsafe { libc::listen(listener.as_raw_fd(), backlog) } == -1 {
+            let error = Error::last_os_error();
+            let _     = Self::unlink(path);
+
+            return Err(error);
+        }
+
+        Ok(listener)
+    }
+
+    fn bind_path(path: &Path, uid: uid_t, gid: gid_t, mode: mode_t) -> Result<UnixListener> {
         Self::enforce_ownership(&path)?;
 
         // if the path already exists as a socket, make a best-effort
@@ -55,7 +253,7 @@ impl Socket {
             libc::umask(libc::S_IRWXU | libc::S_IRWXG | libc::S_IRWXO)
         };
 
-        let socket = UnixListener::bind(&path).and_then(|listener| {
+        let listener = UnixListener::bind(&path).and_then(|listener| {
             let cpath = CString::new(
                 path.as_os_str().as_bytes()
             )?;
@@ -68,67 +266,114 @@ impl Socket {
                 if libc::chmod(cpath.as_ptr(), mode) == -1 {
                     return Err(Error::last_os_error());
                 }
+            }
 
-                let     fd      = listener.as_raw_fd();
-                let mut readfds = mem::MaybeUninit::<libc::fd_set>::uninit();
+            Ok(listener)
+        });
 
-                libc::FD_ZERO(readfds.as_mut_ptr());
+        // restore the process' original umask
+        let _ = unsafe { libc::umask(umask) };
 
-                let mut readfds = readfds.assume_init();
+        listener
+    }
 
-                libc::FD_SET(fd, &mut readfds);
+    /// Returns true if `connection`, accepted on the socket at `path`,
+    /// is from a process that could write to it and whose uid
+    /// `admission` admits (and hasn't banned), telling `admission` about
+    /// it if not.
+    /// Connections to sockets on the filesystem are always from a
+    /// process that could write to it, since the filesystem already
+    /// checked. Connections to abstract sockets are checked the same
+    /// way, as though the socket were a file owned by `uid` and `gid`
+    /// with permissions `mode`, except that the kernel only reports the
+    /// process' primary group (which is the one `sudo -g` changes), so
+    /// its user's other groups are only considered if `admission` can
+    /// find them.
+    fn permits(path: &Path, connection: &UnixStream, uid: uid_t, gid: gid_t, mode: mode_t, admission: &Admission<'_>) -> Result<bool> {
+        Self::admits(connection, uid, gid, mode, admission, abstract_name(path).is_none())
+    }
 
-                // rust automatically wraps the `accept()` function in a
-                // loop that retries on SIGINT, so we have to get
-                // creative here and `select(2)` ourselves if we want
-                // Ctrl-C to interrupt the process
-                match libc::select(
-                    fd + 1, // this must be greater than the fd's int value
-                    &mut readfds,
-                    ptr::null_mut(),
-                    ptr::null_mut(),
-                    ptr::null_mut(),
-                ) {
-                    1  => (),
-                    -1 => return Err(Error::last_os_error()),
-                    0  => unreachable!("`select` returned 0 even though no timeout was set"),
-                    _  => unreachable!("`select` indicated that more than 1 fd is ready"),
-                };
+    /// Returns true if the process on the other end of `connection` is
+    /// admitted, as `permits` decides, where `on_filesystem` says
+    /// whether the filesystem has already checked that it could write
+    /// to the socket.
+    fn admits(connection: &UnixStream, uid: uid_t, gid: gid_t, mode: mode_t, admission: &Admission<'_>, on_filesystem: bool) -> Result<bool> {
+        // nothing left to check needs to know who's connecting
+        if on_filesystem && admission.uids.is_none() && admission.banned.is_none() {
+            return Ok(true);
+        }
 
-                // as a sanity check, confirm that the fd we're going to
-                // `accept` is the one that `select` says is ready
-                if !libc::FD_ISSET(fd, &mut readfds) {
-                    unreachable!("`select` returned an unexpected file descriptor");
-                }
-            }
+        // an owner or group of -1 leaves the one the socket would have
+        // been created with
+        let owner = if uid == uid_t::MAX { unsafe { libc::geteuid() } } else { uid };
+        let group = if gid == gid_t::MAX { unsafe { libc::getegid() } } else { gid };
 
-            listener.accept().map(|connection| {
-                Self { socket: connection.0 }
-            })
-        });
+        let (peer_uid, peer_gid) = peer_credentials(connection)?;
 
-        // once the connection has been made (or aborted due to ctrl-c),
-        // we don't need the socket to remain on the filesystem
-        //
-        // we ignore the result of this operation (instead of returning
-        // the error because a) any error from the previous operation is
-        // of higher importance (we didn't return the error immediately
-        // because we want to unlink the socket regardless) and b) it's
-        // more important to continue the sudo session than to worry
-        // about filesystem janitorial work
-        let _ = Self::unlink(&path);
+        // connections from banned uids are dropped without a word, so
+        // that they can't flood the log either
+        if admission.banned.is_some_and(|banned| banned(peer_uid)) {
+            return Ok(false);
+        }
 
-        // restore the process' original umask
-        let _ = unsafe { libc::umask(umask) };
+        let admitted = admission.uids.is_none_or(|uids| uids.contains(&peer_uid))
+            && (on_filesystem || is_writable_by_member(owner, group, mode, peer_uid, peer_gid, admission.groups));
 
-        socket
+        if !admitted {
+            (admission.dropped)(peer_uid, peer_gid);
+        }
+
+        Ok(admitted)
+    }
+
+    /// Makes reads that would block past `deadline` fail with
+    /// `ErrorKind::WouldBlock` instead, or removes any such limit.
+    pub fn set_deadline(&self, deadline: Option<Instant>) -> Result<()> {
+        // a zero timeout is rejected, so one that's already passed is
+        // rounded up to the smallest one allowed
+        let timeout = deadline.map(|deadline| deadline
+            .saturating_duration_since(Instant::now())
+            .max(Duration::from_millis(1))
+        );
+
+        self.socket.set_read_timeout(timeout)
+    }
+
+    /// Reads whatever the pair has already sent, without waiting for
+    /// more. Returns `None` if they haven't sent anything.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        self.socket.set_nonblocking(true)?;
+
+        let result = self.socket.read(buf);
+
+        self.socket.set_nonblocking(false)?;
+
+        match result {
+            Ok(n)                                       => Ok(Some(n)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e)                                      => Err(e),
+        }
     }
 
-    pub(crate) fn close(&mut self) -> Result<()> {
+    pub fn close(&mut self) -> Result<()> {
         self.socket.shutdown(Shutdown::Both)
     }
 
+    /// A socket connected to the returned stream, which stands in for
+    /// the pair in tests.
+    #[cfg(test)]
+    pub fn pair() -> Result<(Self, UnixStream)> {
+        let (socket, pair) = UnixStream::pair()?;
+
+        Ok((Self { socket }, pair))
+    }
+
     fn unlink(path: &Path) -> Result<()> {
+        // abstract sockets disappear on their own once they're closed
+        if abstract_name(path).is_some() {
+            return Ok(());
+        }
+
         match fs::metadata(&path).map(|md| md.file_type().is_socket()) {
             // file exists, is a socket; delete it
             Ok(true) => fs::remove_file(path),
@@ -226,6 +471,82 @@ impl Socket {
     }
 }
 
+/// A socket left on the filesystem so that connections to it can be
+/// accepted later, without waiting for them. It's removed from the
+/// filesystem when dropped.
+#[derive(Debug)]
+pub struct Listener {
+    listener: UnixListener,
+    path:     PathBuf,
+
+    /// who could write to the socket, for checking connections to an
+    /// abstract one
+    uid:  uid_t,
+    gid:  gid_t,
+    mode: mode_t,
+}
+
+impl Listener {
+    /// Creates a socket at `path`, with the same ownership,
+    /// permissions, and `backlog` `Socket::open` would give it.
+    pub fn bind<P: AsRef<Path>>(
+        path:    P,
+        uid:     uid_t,
+        gid:     gid_t,
+        mode:    mode_t,
+        backlog: u32,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let listener = Socket::bind(&path, uid, gid, mode, backlog).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+
+            Ok(listener)
+        });
+
+        match listener {
+            Ok(listener) => Ok(Self { listener, path, uid, gid, mode }),
+            Err(e)       => {
+                let _ = Socket::unlink(&path);
+
+                Err(e)
+            },
+        }
+    }
+
+    /// Accepts a connection that's already waiting, if there is one,
+    /// checking it as `Socket::open` does. `admission`'s backlog was set
+    /// when the socket was bound, and there's no wait for its limit on
+    /// connections to end.
+    pub fn try_accept(&self, admission: &Admission<'_>) -> Result<Option<Socket>> {
+        match self.listener.accept() {
+            Ok((socket, _)) => {
+                // a connection from someone who couldn't have written
+                // to the socket, or who isn't admitted, is dropped as
+                // though it were never made
+                if !Socket::permits(&self.path, &socket, self.uid, self.gid, self.mode, admission)? {
+                    return Ok(None);
+                }
+
+                // whether an accepted socket inherits the listener's
+                // `O_NONBLOCK` varies by platform
+                socket.set_nonblocking(false)?;
+
+                Ok(Some(Socket { socket }))
+            },
+
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e)                                      => Err(e),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = Socket::unlink(&self.path);
+    }
+}
+
 impl Drop for Socket {
     fn drop(&mut self) {
         let _ = self.close();
@@ -252,6 +573,115 @@ impl Write for Socket {
     }
 }
 
+/// The name of the abstract socket `path` refers to, if it starts with
+/// an `@` (as `ss(8)` and `socat(1)` show them).
+fn abstract_name(path: &Path) -> Option<&[u8]> {
+    let path = path.as_os_str().as_bytes();
+
+    if path.first() == Some(&b'@') { path.get(1..) } else { None }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &[u8]) -> Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    UnixListener::bind_addr(&SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_: &[u8]) -> Result<UnixListener> {
+    Err(Error::new(ErrorKind::Other, "abstract sockets are only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &[u8]) -> Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    UnixStream::connect_addr(&SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_: &[u8]) -> Result<UnixStream> {
+    Err(Error::new(ErrorKind::Other, "abstract sockets are only supported on Linux"))
+}
+
+/// The effective uid and gid of the process on the other end of
+/// `connection`, as of when it connected.
+#[cfg(target_os = "linux")]
+fn peer_credentials(connection: &UnixStream) -> Result<(uid_t, gid_t)> {
+    let mut credentials = mem::MaybeUninit::<libc::ucred>::zeroed();
+    let mut len         = size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe { libc::getsockopt(
+        connection.as_raw_fd(),
+        libc::SOL_SOCKET,
+        libc::SO_PEERCRED,
+        credentials.as_mut_ptr().cast(),
+        &mut len,
+    ) };
+
+    if result == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    let credentials = unsafe { credentials.assume_init() };
+
+    Ok((credentials.uid, credentials.gid))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_credentials(connection: &UnixStream) -> Result<(uid_t, gid_t)> {
+    let mut uid = 0;
+    let mut gid = 0;
+
+    if unsafe { libc::getpeereid(connection.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok((uid, gid))
+}
+
+/// Returns true if a process running as `uid` and `gid` could write to
+/// a file owned by `owner` and `group` with permissions `mode`.
+fn is_writable_by(owner: uid_t, group: gid_t, mode: mode_t, uid: uid_t, gid: gid_t) -> bool {
+    if uid == 0 {
+        return true;
+    }
+
+    // as with files, the owner's permissions apply to the owner even
+    // when the group's or everyone else's would allow more
+    if uid == owner {
+        return mode & libc::S_IWUSR != 0;
+    }
+
+    if gid == group {
+        return mode & libc::S_IWGRP != 0;
+    }
+
+    mode & libc::S_IWOTH != 0
+}
+
+/// Returns true if a process running as `uid` and `gid` could write to
+/// a file owned by `owner` and `group` with permissions `mode`, counting
+/// every group `groups` finds `uid` to be a member of as though it were
+/// one of the process' supplementary groups.
+fn is_writable_by_member(owner: uid_t, group: gid_t, mode: mode_t, uid: uid_t, gid: gid_t, groups: &Groups<'_>) -> bool {
+    if is_writable_by(owner, group, mode, uid, gid) {
+        return true;
+    }
+
+    // only the group's permissions can be widened by other groups, and
+    // those never apply to the owner (so there's no point looking them
+    // up)
+    if uid == owner || mode & libc::S_IWGRP == 0 {
+        return false;
+    }
+
+    groups(uid).is_some_and(|gids| gids.contains(&group))
+}
+
 /// Sets up a handler for Ctrl-C (SIGINT) that's a no-op, but with the
 /// `SA_RESTART` flag disabled, for the duration of the passed function
 /// call.
@@ -301,3 +731,141 @@ unsafe fn sigaction(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_writability_like_the_filesystem() {
+        // root can write to anything
+        assert!(is_writable_by(1000, 1000, 0, 0, 0));
+
+        assert!(is_writable_by(1000, 1000, libc::S_IWUSR, 1000, 2000));
+        assert!(!is_writable_by(1000, 1000, libc::S_IWUSR, 2000, 1000));
+
+        assert!(is_writable_by(0, 27, libc::S_IWGRP, 1000, 27));
+        assert!(!is_writable_by(0, 27, libc::S_IWGRP, 1000, 1000));
+
+        // the owner is held to the owner's permissions alone
+        assert!(!is_writable_by(1000, 1000, libc::S_IWGRP | libc::S_IWOTH, 1000, 1000));
+
+        assert!(is_writable_by(0, 0, libc::S_IWOTH, 1000, 1000));
+    }
+
+    #[test]
+    fn checks_writability_by_group_members() {
+        let groups = |uid| match uid {
+            1000 => Some(vec![1000, 27].into_iter().collect()),
+            _    => None,
+        };
+
+        assert!(is_writable_by_member(0, 27, libc::S_IWGRP, 1000, 1000, &groups));
+        assert!(!is_writable_by_member(0, 28, libc::S_IWGRP, 1000, 1000, &groups));
+        assert!(!is_writable_by_member(0, 27, libc::S_IWGRP, 1001, 1001, &groups));
+
+        // other groups only stand in for the group's permissions
+        assert!(!is_writable_by_member(0, 27, libc::S_IWUSR, 1000, 1000, &groups));
+        assert!(!is_writable_by_member(1000, 27, libc::S_IWGRP, 1000, 1000, &groups));
+    }
+
+    #[test]
+    fn recognizes_abstract_names() {
+        assert_eq!(Some(&b"/run/sudo_pair/1.2.sock"[..]), abstract_name(Path::new("@/run/sudo_pair/1.2.sock")));
+        assert_eq!(None,                                  abstract_name(Path::new("/run/sudo_pair/1.2.sock")));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn listens_on_abstract_sockets() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("sudo_pair-test-{}", std::process::id());
+        let path = PathBuf::from(format!("@{}", name));
+
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        let listener = Listener::bind(&path, uid, gid, libc::S_IWUSR, 1).unwrap();
+
+        assert!(!path.exists());
+        assert!(listener.try_accept(&admission(None)).unwrap().is_none());
+
+        let _client = UnixStream::connect_addr(
+            &SocketAddr::from_abstract_name(name.as_bytes()).unwrap()
+        ).unwrap();
+
+        assert!(listener.try_accept(&admission(None)).unwrap().is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drops_connections_from_unlisted_uids() {
+        use std::cell::Cell;
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("sudo_pair-test-uids-{}", std::process::id());
+        let path = PathBuf::from(format!("@{}", name));
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        let listener = Listener::bind(&path, uid, gid, libc::S_IWUSR, 4).unwrap();
+        let dropped  = Cell::new(None);
+        let others   = [uid.wrapping_add(1)];
+
+        let unlisted = Admission {
+            uids:    Some(&others),
+            dropped: &|uid, gid| dropped.set(Some((uid, gid))),
+            ..admission(None)
+        };
+
+        let _client = UnixStream::connect_addr(&addr).unwrap();
+
+        assert!(listener.try_accept(&unlisted).unwrap().is_none());
+        assert_eq!(Some((uid, gid)), dropped.get());
+
+        let _client = UnixStream::connect_addr(&addr).unwrap();
+
+        assert!(listener.try_accept(&admission(Some(&[uid]))).unwrap().is_some());
+    }
+
+    #[test]
+    fn announces_sessions_to_daemons() {
+        let dir     = std::env::temp_dir().join(format!("sudo_pair-test-control-{}", std::process::id()));
+        let control = dir.join("control.sock");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let daemon     = UnixListener::bind(&control).unwrap();
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        let _socket = Socket::announce(&control, uid, gid, libc::S_IWUSR, &admission(None), b"announce").unwrap();
+
+        let mut announcement = [0; 8];
+        daemon.accept().unwrap().0.read_exact(&mut announcement).unwrap();
+
+        assert_eq!(b"announce", &announcement);
+
+        // the daemon is held to the same uids as any other pair
+        let others = [uid.wrapping_add(1)];
+        let error  = Socket::announce(&control, uid, gid, libc::S_IWUSR, &admission(Some(&others)), b"announce").unwrap_err();
+
+        assert_eq!(ErrorKind::PermissionDenied, error.kind());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn admission(uids: Option<&[uid_t]>) -> Admission<'_> {
+        Admission {
+            backlog:     1,
+            max_pending: 0,
+            uids,
+            groups:      &|_| None,
+            banned:      None,
+            dropped:     &|_, _| {},
+        }
+    }
+}
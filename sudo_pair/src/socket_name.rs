@@ -0,0 +1,184 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The names given to session sockets, configured by a template like
+//! `%{uid}.%{pid}.sock`.
+
+use sudo_plugin::FromSudoOption;
+
+/// The template sockets have always been named by, which the sample
+/// approval script and the client library expect.
+const DEFAULT_SOCKET_NAME : &str = "%{uid}.%{pid}.sock";
+
+/// A template for the names of session sockets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SocketName(Vec<Part>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Literal(String),
+    Var(Var),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Var {
+    Uid,
+    Pid,
+    Sid,
+    SessionId,
+}
+
+/// The values substituted into a socket name.
+#[derive(Clone, Copy, Debug)]
+pub struct Vars<'a> {
+    /// the real uid of the user invoking `sudo`
+    pub uid: libc::uid_t,
+
+    /// the pid of this `sudo` process
+    pub pid: libc::pid_t,
+
+    /// the id of the terminal session `sudo` was invoked from
+    pub sid: libc::pid_t,
+
+    pub session_id: &'a str,
+}
+
+impl SocketName {
+    /// The name of the socket for a session described by `vars`.
+    pub fn expand(&self, vars: &Vars<'_>) -> String {
+        self.0.iter().map(|part| match part {
+            Part::Literal(s)          => s.clone(),
+            Part::Var(Var::Uid)       => vars.uid.to_string(),
+            Part::Var(Var::Pid)       => vars.pid.to_string(),
+            Part::Var(Var::Sid)       => vars.sid.to_string(),
+            Part::Var(Var::SessionId) => vars.session_id.to_owned(),
+        }).collect()
+    }
+}
+
+impl Default for SocketName {
+    fn default() -> Self {
+        Self::from_sudo_option(DEFAULT_SOCKET_NAME)
+            .expect("the default socket name is valid")
+    }
+}
+
+impl FromSudoOption for SocketName {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        let mut parts   = Vec::new();
+        let mut literal = String::new();
+        let mut rest    = s;
+
+        while let Some(i) = rest.find('%') {
+            literal.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            if let Some(after) = rest.strip_prefix('%') {
+                literal.push('%');
+                rest = after;
+
+                continue;
+            }
+
+            let (name, after) = rest.strip_prefix('{')
+                .and_then(|rest| rest.split_once('}'))
+                .ok_or_else(|| format!("{} has a % that isn't followed by {{name}}", s))?;
+
+            let var = match name {
+                "uid"        => Var::Uid,
+                "pid"        => Var::Pid,
+                "sid"        => Var::Sid,
+                "session_id" => Var::SessionId,
+                _            => return Err(format!("unknown socket name placeholder %{{{}}}", name)),
+            };
+
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+
+            parts.push(Part::Var(var));
+            rest = after;
+        }
+
+        literal.push_str(rest);
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        let has = |var| parts.contains(&Part::Var(var));
+
+        if parts.iter().any(|part| matches!(part, Part::Literal(s) if s.contains('/'))) {
+            return Err(format!("{} can't contain a /", s));
+        }
+
+        // a name that's unique to the process means any socket already
+        // there was left behind by one that's gone, and can be replaced
+        if !has(Var::Pid) && !has(Var::SessionId) {
+            return Err(format!("{} has to include %{{pid}} or %{{session_id}}", s));
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+/// The name to fall back to when `name` is already taken by something
+/// that can't be replaced, made unique with the session's id.
+pub fn unique(name: &str, session_id: &str) -> String {
+    match name.strip_suffix(".sock") {
+        Some(stem) => format!("{}.{}.sock", stem, session_id),
+        None       => format!("{}.{}", name, session_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARS : Vars<'static> = Vars {
+        uid:        1000,
+        pid:        4242,
+        sid:        4000,
+        session_id: "1600000000-4242",
+    };
+
+    fn name(s: &str) -> Result<String, String> {
+        SocketName::from_sudo_option(s).map(|name| name.expand(&VARS))
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!("1000.4242.sock", SocketName::default().expand(&VARS));
+
+        assert_eq!(Ok("1000.4000.1600000000-4242.sock".into()), name("%{uid}.%{sid}.%{session_id}.sock"));
+        assert_eq!(Ok("100%.4242".into()),                       name("100%%.%{pid}"));
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert_eq!(Err("unknown socket name placeholder %{user}".into()), name("%{user}.%{pid}.sock"));
+        assert_eq!(Err("%{pid}.%u has a % that isn't followed by {name}".into()), name("%{pid}.%u"));
+        assert_eq!(Err("%{pid has a % that isn't followed by {name}".into()),     name("%{pid"));
+        assert_eq!(Err("../%{pid}.sock can't contain a /".into()), name("../%{pid}.sock"));
+        assert_eq!(Err("%{uid}.sock has to include %{pid} or %{session_id}".into()), name("%{uid}.sock"));
+    }
+
+    #[test]
+    fn falls_back_to_unique_names() {
+        assert_eq!("1000.4242.1600000000-4242.sock", unique("1000.4242.sock", VARS.session_id));
+        assert_eq!("1000.4242.1600000000-4242",      unique("1000.4242", VARS.session_id));
+    }
+}
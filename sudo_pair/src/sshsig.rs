@@ -0,0 +1,372 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Authentication of approvers by SSH signature.
+//!
+//! Anyone able to connect to a session's socket can answer its prompt,
+//! and the socket's permissions only say who that is in terms of uids.
+//! When `approver_keys` is set, each approver is also sent a random
+//! challenge, which they have to sign with one of the keys listed there
+//! before their approval is accepted.
+//!
+//! Signatures are in OpenSSH's `SSHSIG` format (what `ssh-keygen -Y
+//! sign -n sudo_pair` produces, without its armor), and the namespace
+//! they're made in keeps them from being usable as signatures for
+//! anything else (like logging in over SSH), whether they're made by
+//! `ssh-keygen` or through an SSH agent. Only Ed25519 keys are
+//! supported.
+//!
+//! Without the `signatures` feature, `approver_keys` is rejected, and
+//! the plugin only uses `challenge` (for web approval's tokens) and
+//! `NAMESPACE`.
+
+use crate::crypto::{self, to_hex, Ed25519Key};
+
+use sudo_pair_types::base64;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// The namespace approvers' signatures are made in.
+pub const NAMESPACE : &str = "sudo_pair";
+
+/// The first bytes of every `SSHSIG` signature, and of the data signed.
+const MAGIC : &[u8] = b"SSHSIG";
+
+/// The only version of the `SSHSIG` format.
+const VERSION : u32 = 1;
+
+/// The only type of key supported.
+const ED25519 : &[u8] = b"ssh-ed25519";
+
+/// The keys approvers may sign challenges with.
+#[derive(Clone, Debug, Default)]
+pub struct AuthorizedKeys {
+    keys: Vec<AuthorizedKey>,
+}
+
+/// A key an approver may sign challenges with.
+#[derive(Clone, Debug)]
+pub struct AuthorizedKey {
+    /// the key in SSH's wire format, as it appears in signatures
+    blob: Vec<u8>,
+
+    key: Ed25519Key,
+
+    /// the key's comment, which conventionally names its owner
+    pub comment: String,
+}
+
+impl AuthorizedKey {
+    /// The key's fingerprint, as `ssh-keygen -l` shows it (e.g.,
+    /// `SHA256:4264Haoh...`).
+    pub fn fingerprint(&self) -> String {
+        let digest = crypto::sha256(&self.blob);
+
+        format!("SHA256:{}", base64::encode(&digest).trim_end_matches('='))
+    }
+}
+
+impl AuthorizedKeys {
+    /// Loads the keys listed at `path`, which has to be owned by root
+    /// and writable by nobody else, since anyone who could add a key to
+    /// it could approve any session.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+
+        if metadata.uid() != 0 {
+            return Err("the keys aren't owned by root".into());
+        }
+
+        if metadata.mode() & 0o022 != 0 {
+            return Err("the keys are writable by users other than root".into());
+        }
+
+        let keys = Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?);
+
+        if keys.keys.is_empty() {
+            return Err("no Ed25519 keys are listed".into());
+        }
+
+        Ok(keys)
+    }
+
+    /// Parses keys in the format of OpenSSH's `authorized_keys`: one per
+    /// line, as its type, its base64-encoded blob, and an optional
+    /// comment, optionally preceded by options (which are ignored).
+    /// Lines that don't hold an Ed25519 key are skipped.
+    pub fn parse(s: &str) -> Self {
+        let keys = s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let fields : Vec<_> = line.split_whitespace().collect();
+                let at              = fields.iter().position(|&f| f.as_bytes() == ED25519)?;
+                let blob            = base64::decode(fields.get(at + 1)?)?;
+                let key             = parse_key(&blob)?;
+
+                Some(AuthorizedKey {
+                    blob,
+                    key,
+                    comment: fields[at + 2 ..].join(" "),
+                })
+            })
+            .collect();
+
+        Self { keys }
+    }
+
+    /// How many keys are listed.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no keys are listed.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Verifies that `sshsig` is a signature of `challenge` by one of
+    /// these keys, returning the key that made it.
+    pub fn verify(&self, challenge: &str, sshsig: Option<&[u8]>) -> Result<&AuthorizedKey, Rejection> {
+        let mut reader = Reader(sshsig.ok_or(Rejection::Missing)?);
+
+        if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+            return Err(Rejection::Malformed);
+        }
+
+        let public    = reader.string()?;
+        let namespace = reader.string()?;
+        let reserved  = reader.string()?;
+        let hash      = reader.string()?;
+        let signature = reader.string()?;
+
+        if !reader.0.is_empty() {
+            return Err(Rejection::Malformed);
+        }
+
+        if namespace != NAMESPACE.as_bytes() {
+            return Err(Rejection::WrongNamespace);
+        }
+
+        let authorized = self.keys.iter()
+            .find(|key| key.blob == public)
+            .ok_or(Rejection::UnknownKey)?;
+
+        let digest = match hash {
+            b"sha256" => crypto::sha256(challenge.as_bytes()),
+            b"sha512" => crypto::sha512(challenge.as_bytes()),
+            _         => return Err(Rejection::Malformed),
+        };
+
+        let mut reader = Reader(signature);
+
+        if reader.string()? != ED25519 {
+            return Err(Rejection::Malformed);
+        }
+
+        let signature = reader.string()?;
+
+        if signature.len() != 64 {
+            return Err(Rejection::Malformed);
+        }
+
+        // what's actually signed binds the signature to its namespace
+        let mut signed = MAGIC.to_vec();
+
+        for field in &[namespace, reserved, hash, &digest[..]] {
+            signed.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            signed.extend_from_slice(field);
+        }
+
+        if !authorized.key.verify(&signed, signature) {
+            return Err(Rejection::BadSignature);
+        }
+
+        Ok(authorized)
+    }
+}
+
+/// Why a signature wasn't accepted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// the approver didn't send one
+    Missing,
+
+    /// it isn't an Ed25519 signature in the `SSHSIG` format
+    Malformed,
+
+    /// it was made for something other than approving sessions
+    WrongNamespace,
+
+    /// it was made with a key that isn't authorized
+    UnknownKey,
+
+    /// it isn't a valid signature of the challenge
+    BadSignature,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::Missing        => "the challenge wasn't signed",
+            Rejection::Malformed      => "the signature is malformed",
+            Rejection::WrongNamespace => "the signature wasn't made for sudo_pair",
+            Rejection::UnknownKey     => "the signing key isn't authorized",
+            Rejection::BadSignature   => "the signature doesn't match the challenge",
+        })
+    }
+}
+
+/// Returns a new random challenge, as hex.
+pub fn challenge() -> io::Result<String> {
+    let mut bytes = [0; 32];
+
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    Ok(to_hex(&bytes))
+}
+
+/// Parses an Ed25519 key in SSH's wire format.
+fn parse_key(blob: &[u8]) -> Option<Ed25519Key> {
+    let mut reader = Reader(blob);
+
+    if reader.string().ok()? != ED25519 {
+        return None;
+    }
+
+    let key = reader.string().ok()?;
+
+    if !reader.0.is_empty() {
+        return None;
+    }
+
+    Ed25519Key::from_bytes(key)
+}
+
+/// Reads the fields of SSH's wire format.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Rejection> {
+        if self.0.len() < n {
+            return Err(Rejection::Malformed);
+        }
+
+        let (taken, rest) = self.0.split_at(n);
+
+        self.0 = rest;
+
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, Rejection> {
+        let bytes = self.take(4)?;
+
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], Rejection> {
+        let len = self.u32()? as usize;
+
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // generated with `ssh-keygen -t ed25519`, and the signature with
+    // `ssh-keygen -Y sign -n sudo_pair -f id challenge`
+    const ALICE   : &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPtdau6cGX6Z/BlppzFZ2zJZb6xksz96jtz0FHZmduei alice@bastion";
+    const MALLORY : &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPcHGcUfveg+Gj8NZVf1mL4ZyYqMaHW3gj7kuWVAhwJX mallory";
+
+    const CHALLENGE : &str = "4f2b6c0d9e8a7b1c3d5e7f90a1b2c3d4e5f60718293a4b5c6d7e8f9012345678";
+    const SIGNATURE : &str = "U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAg+11q7pwZfpn8GWmnMVnbMllvrG\
+                              SzP3qO3PQUdmZ256IAAAAJc3Vkb19wYWlyAAAAAAAAAAZzaGE1MTIAAABTAAAAC3NzaC1l\
+                              ZDI1NTE5AAAAQDu//JAsbSXRGssDi87ogZcU12MF+EmLkDIVtsmEuCHT0u4xbitwAGu4VB\
+                              GnsI3ZpetNGa8xppvXK+J1Choy7AE=";
+
+    fn signature() -> Vec<u8> {
+        base64::decode(SIGNATURE).unwrap()
+    }
+
+    #[test]
+    fn parses_authorized_keys() {
+        let keys = AuthorizedKeys::parse(&format!(
+            "# approvers\n\n{}\nfrom=\"10.0.0.0/8\" {}\nssh-rsa AAAAB3NzaC1yc2E= bob\nssh-ed25519 !!!\n",
+            ALICE,
+            MALLORY,
+        ));
+
+        assert_eq!(2, keys.len());
+        assert_eq!("alice@bastion", keys.keys[0].comment);
+        assert_eq!("mallory",       keys.keys[1].comment);
+        assert_eq!("SHA256:4264HaohW4dFCPYf178Kog9lVH+S4SO8OYntpQJ07Mw", keys.keys[0].fingerprint());
+    }
+
+    #[test]
+    fn verifies_signatures_by_authorized_keys() {
+        let keys = AuthorizedKeys::parse(&format!("{}\n{}\n", MALLORY, ALICE));
+        let key  = keys.verify(CHALLENGE, Some(&signature())).unwrap();
+
+        assert_eq!("alice@bastion", key.comment);
+    }
+
+    #[test]
+    fn rejects_signatures_by_other_keys() {
+        let keys = AuthorizedKeys::parse(MALLORY);
+
+        assert_eq!(Rejection::UnknownKey, keys.verify(CHALLENGE, Some(&signature())).unwrap_err());
+        assert_eq!(Rejection::Missing,    keys.verify(CHALLENGE, None).unwrap_err());
+    }
+
+    #[test]
+    fn rejects_signatures_of_other_challenges() {
+        let keys = AuthorizedKeys::parse(ALICE);
+
+        assert_eq!(
+            Rejection::BadSignature,
+            keys.verify(&CHALLENGE.replace('4', "5"), Some(&signature())).unwrap_err(),
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_signatures() {
+        let keys          = AuthorizedKeys::parse(ALICE);
+        let mut signature = signature();
+
+        // the namespace is `sudo_pair`, just after the public key
+        let at = signature.windows(9).position(|w| w == b"sudo_pair").unwrap();
+        signature[at] = b'S';
+
+        assert_eq!(Rejection::WrongNamespace, keys.verify(CHALLENGE, Some(&signature)).unwrap_err());
+
+        let truncated = &self::signature()[..100];
+
+        assert_eq!(Rejection::Malformed, keys.verify(CHALLENGE, Some(truncated)).unwrap_err());
+    }
+
+    #[test]
+    fn generates_unique_challenges() {
+        let challenge = challenge().unwrap();
+
+        assert_eq!(64, challenge.len());
+        assert_ne!(challenge, self::challenge().unwrap());
+    }
+}
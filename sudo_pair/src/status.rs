@@ -0,0 +1,207 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The exit statuses shared by the helper binaries, and the status they
+//! print instead of their usual output when given `--json`, so they can
+//! be driven by automation without scraping what they print for people.
+//!
+//! Every helper exits with one of three statuses:
+//!
+//! * `0`: it did what it was asked, and found nothing wrong
+//! * `1`: it did what it was asked, and found problems (e.g., with a
+//!   configuration or a capture), each of which it reported
+//! * `2`: it couldn't do what it was asked at all (e.g., its arguments
+//!   were invalid, or a file it needed couldn't be read)
+
+use sudo_pair_types::json::quote;
+
+use std::process;
+
+/// The exit status when nothing was wrong.
+pub const SUCCESS : i32 = 0;
+
+/// The exit status when problems were found and reported.
+pub const PROBLEMS : i32 = 1;
+
+/// The exit status when nothing could be done at all.
+pub const ERROR : i32 = 2;
+
+/// What a helper did, as it's reported with `--json`: an object with
+/// the `status` (`success`, `problems`, or `error`), the `exit_code`
+/// it's paired with, and whatever else the helper adds.
+#[derive(Debug)]
+pub struct Report {
+    code:   i32,
+    error:  Option<String>,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Report {
+    /// A report of success.
+    pub fn success() -> Self {
+        Self { code: SUCCESS, error: None, fields: Vec::new() }
+    }
+
+    /// A report listing `problems` (as `problems`), which is successful
+    /// if there weren't any.
+    pub fn problems(problems: &[String]) -> Self {
+        let code   = if problems.is_empty() { SUCCESS } else { PROBLEMS };
+        let report = Self { code, error: None, fields: Vec::new() };
+
+        report.strings("problems", problems)
+    }
+
+    /// A report of having failed outright, for the reason given (as
+    /// `error`).
+    pub fn error(error: &str) -> Self {
+        Self { code: ERROR, error: Some(error.into()), fields: Vec::new() }
+    }
+
+    /// Adds `value` to the report as the string `key`.
+    pub fn string(mut self, key: &'static str, value: &str) -> Self {
+        self.fields.push((key, quote(value)));
+        self
+    }
+
+    /// Adds `values` to the report as the array of strings `key`.
+    pub fn strings(mut self, key: &'static str, values: &[String]) -> Self {
+        let values : Vec<_> = values.iter().map(|v| quote(v)).collect();
+
+        self.fields.push((key, format!("[{}]", values.join(","))));
+        self
+    }
+
+    /// Adds `objects`, each already serialized as JSON, to the report
+    /// as the array `key`.
+    pub fn objects(mut self, key: &'static str, objects: &[String]) -> Self {
+        self.fields.push((key, format!("[{}]", objects.join(","))));
+        self
+    }
+
+    /// The exit status the report is paired with.
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    /// Renders the report as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let status = match self.code {
+            SUCCESS  => "success",
+            PROBLEMS => "problems",
+            _        => "error",
+        };
+
+        let mut fields = vec![
+            format!("{}:{}", quote("status"),    quote(status)),
+            format!("{}:{}", quote("exit_code"), self.code),
+        ];
+
+        if let Some(ref error) = self.error {
+            fields.push(format!("{}:{}", quote("error"), quote(error)));
+        }
+
+        fields.extend(self.fields.iter().map(|(key, value)| format!("{}:{}", quote(key), value)));
+
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// Prints the report to stdout if `json` was asked for, or else any
+    /// error to stderr, prefixed by the `program`'s name, then exits
+    /// with the report's status.
+    pub fn exit(&self, program: &str, json: bool) -> ! {
+        if json {
+            println!("{}", self.to_json());
+        } else if let Some(ref error) = self.error {
+            eprintln!("{}: {}", program, error);
+        }
+
+        process::exit(self.code)
+    }
+}
+
+/// Removes `--json` from `args` (only before any `--`, after which
+/// arguments are a command's rather than the helper's), returning
+/// whether it was there.
+pub fn json_requested(args: &mut Vec<String>) -> bool {
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    let len = args.len();
+
+    let mut index = 0;
+
+    args.retain(|arg| {
+        index += 1;
+        index > end || arg != "--json"
+    });
+
+    args.len() != len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn reports_problems() {
+        assert_eq!(
+            r#"{"status":"success","exit_code":0,"problems":[]}"#,
+            Report::problems(&[]).to_json(),
+        );
+
+        let report = Report::problems(&["socket_dir must be an absolute path, got \"x\"".into()]);
+
+        assert_eq!(PROBLEMS, report.code());
+        assert_eq!(
+            r#"{"status":"problems","exit_code":1,"problems":["socket_dir must be an absolute path, got \"x\""]}"#,
+            report.to_json(),
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let report = Report::error("couldn't read /etc/sudo.conf").string("output", "");
+
+        assert_eq!(ERROR, report.code());
+        assert_eq!(
+            r#"{"status":"error","exit_code":2,"error":"couldn't read /etc/sudo.conf","output":""}"#,
+            report.to_json(),
+        );
+    }
+
+    #[test]
+    fn reports_objects() {
+        let report = Report::success().objects("sessions", &[r#"{"id":"1600000000-1"}"#.into()]);
+
+        assert_eq!(
+            r#"{"status":"success","exit_code":0,"sessions":[{"id":"1600000000-1"}]}"#,
+            report.to_json(),
+        );
+    }
+
+    #[test]
+    fn finds_the_json_flag() {
+        let mut given = args(&["--json", "--key", "k", "--", "/bin/echo", "--json"]);
+
+        assert!(json_requested(&mut given));
+        assert_eq!(args(&["--key", "k", "--", "/bin/echo", "--json"]), given);
+
+        let mut given = args(&["capture"]);
+
+        assert!(!json_requested(&mut given));
+        assert_eq!(args(&["capture"]), given);
+    }
+}
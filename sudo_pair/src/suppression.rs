@@ -0,0 +1,97 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Output that was kept from the approver, and why.
+//!
+//! Every suppression is marked where it happened, both in the stream
+//! sent to the approver and in the session's recording, so a gap in
+//! what the approver saw can be accounted for later rather than being
+//! silent.
+
+/// Why output was kept from the approver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// it looked binary, and `binary_output` summarizes binary output
+    Binary,
+
+    /// it was made up of escape sequences in `sanitize_escapes`
+    Escapes,
+
+    /// it was over `output_rate_limit`
+    RateLimit,
+}
+
+impl Reason {
+    /// The reason's name in protocol messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::Binary    => "binary",
+            Reason::Escapes   => "escapes",
+            Reason::RateLimit => "rate_limit",
+        }
+    }
+}
+
+/// A stretch of output kept from the approver.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Suppression {
+    pub reason: Reason,
+    pub bytes:  u64,
+}
+
+impl Suppression {
+    /// Describes what was suppressed and why, as it's recorded.
+    pub fn describe(&self) -> String {
+        let bytes = format!(
+            "{} byte{}",
+            self.bytes,
+            if self.bytes == 1 { "" } else { "s" },
+        );
+
+        match self.reason {
+            Reason::Binary    => format!("{} of binary output suppressed", bytes),
+            Reason::Escapes   => format!("{} of escape sequences removed", bytes),
+            Reason::RateLimit => format!("{} of output withheld: over the output rate limit", bytes),
+        }
+    }
+
+    /// The description, set apart on its own line, for showing the
+    /// approver in place of the output.
+    pub fn marker(&self) -> Vec<u8> {
+        format!("\r\n[{}]\r\n", self.describe()).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_suppressions() {
+        assert_eq!(
+            "1 byte of binary output suppressed",
+            Suppression { reason: Reason::Binary, bytes: 1 }.describe(),
+        );
+
+        assert_eq!(
+            "12 bytes of escape sequences removed",
+            Suppression { reason: Reason::Escapes, bytes: 12 }.describe(),
+        );
+
+        assert_eq!(
+            b"\r\n[4096 bytes of output withheld: over the output rate limit]\r\n".to_vec(),
+            Suppression { reason: Reason::RateLimit, bytes: 4096 }.marker(),
+        );
+    }
+}
@@ -13,64 +13,181 @@
 // permissions and limitations under the License.
 
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use sudo_pair_types::protocol::Limits;
 
 const DEFAULT_ESCAPE_BYTE : u8 = b'%';
 
-pub(crate) struct Spec {
+/// Where the prompt templates are read from, unless configured
+/// otherwise.
+pub const DEFAULT_USER_PROMPT_PATH : &str = "/etc/sudo_pair.prompt.user";
+pub const DEFAULT_PAIR_PROMPT_PATH : &str = "/etc/sudo_pair.prompt.pair";
+
+/// The templates used when none can be read from the filesystem.
+pub const DEFAULT_USER_PROMPT : &[u8] = b"%B '%p %u'\n";
+pub const DEFAULT_PAIR_PROMPT : &[u8] = b"%U@%h:%d$ %C\ny/n? [n]: ";
+
+/// The single line shown to the user in place of their prompt in quiet
+/// mode.
+pub const QUIET_USER_PROMPT : &[u8] = b"waiting for a pair to run `%b %u %p` on %h\n";
+
+/// The most a rendered prompt may be, so that an enormous (or endless,
+/// like `/dev/zero`) template can't balloon sudo's memory. Even with
+/// `TRUNCATION_MARKER`, it's within the prompt limit the plugin
+/// announces to clients.
+pub const MAX_PROMPT_LEN : usize = Limits::DEFAULT.prompt - TRUNCATION_MARKER.len();
+
+/// Appended to prompts cut short by `MAX_PROMPT_LEN`.
+pub const TRUNCATION_MARKER : &[u8] = b"\n[prompt truncated]\n";
+
+#[derive(Clone, Debug)]
+pub struct Spec {
     expansions: HashMap<u8, Vec<u8>>,
     escape:     u8,
 }
 
 impl Spec {
-    pub(crate) fn new() -> Self {
+    pub fn new() -> Self {
         Self::default()
     }
 
-    pub(crate) fn with_escape(escape: u8) -> Self {
+    pub fn with_escape(escape: u8) -> Self {
         Self { escape, .. Self::new() }
     }
 
-    pub(crate) fn replace<T: Into<Vec<u8>>>(&mut self, literal: u8, replacement: T) {
+    pub fn replace<T: Into<Vec<u8>>>(&mut self, literal: u8, replacement: T) {
         let _ = self.expansions.insert(literal, replacement.into());
     }
 
-    pub(crate) fn expand(&self, template: &[u8]) -> Vec<u8> {
+    /// Expands an in-memory template without any limit on its size.
+    pub fn expand(&self, template: &[u8]) -> Vec<u8> {
         // the expanded result is likely to be at least as long as the
         // template; if we go a little over, it's not a big deal
         let mut result = Vec::with_capacity(template.len());
-        let mut iter   = template.iter().cloned();
-
-        while iter.len() != 0 {
-            // copy literally everything up to the next escape character
-            result.extend(
-                iter.by_ref().take_while(|b| *b != self.escape )
-            );
-
-            // TODO: The above take_while consumes an extra byte in
-            // the event that it finds the escape character; this is
-            // *mostly* okay, but we can't distinguish between the case
-            // where a '%' was consumed and where one wasn't (for
-            // instance at EOF). This matters because of the following
-            // line which terminates if there's nothing left in the
-            // template to evaluate, because the template may have ended
-            // in a '%'! This isn't a huge deal, but it's at least worth
-            // documenting this limitation: if your template ends in a
-            // line '%' character, we will silently eat it.
-            let byte = match iter.next() {
-                Some(b) => b,
-                None    => break,
-            };
 
-            // if the spec contains an expansion for the escaped
-            // character, use it; otherwise, emit the character as a
-            // literal
-            match self.expansions.get(&byte) {
-                Some(expansion) => result.extend_from_slice(expansion),
-                None            => result.push(byte),
+        // neither reading from a slice nor writing to a `Vec` can fail
+        let _ = self.render(template, &mut result, usize::MAX);
+
+        result
+    }
+
+    /// Expands the template read from the file at `path` (or `default`,
+    /// if it can't be read), to at most `MAX_PROMPT_LEN` bytes.
+    pub fn render_file(&self, path: &Path, default: &[u8]) -> Vec<u8> {
+        let mut prompt = Vec::new();
+
+        let rendered = File::open(path).and_then(|file|
+            self.render(file, &mut prompt, MAX_PROMPT_LEN)
+        );
+
+        if rendered.is_err() {
+            prompt.clear();
+
+            let _ = self.render(default, &mut prompt, MAX_PROMPT_LEN);
+        }
+
+        prompt
+    }
+
+    /// Streams the expansion of `template` into `out`, writing at most
+    /// `limit` bytes. If the expansion would exceed `limit`, it's cut
+    /// short with `TRUNCATION_MARKER` and the rest of `template` is
+    /// never read. Returns whether the expansion was truncated.
+    ///
+    /// If the template ends in a lone escape character, it's silently
+    /// dropped, since there's nothing for it to escape.
+    pub fn render<R: Read, W: Write>(
+        &self,
+        template: R,
+        out:      W,
+        limit:    usize,
+    ) -> io::Result<bool> {
+        let mut reader  = BufReader::new(template);
+        let mut out     = Limited { inner: out, remaining: limit, full: false };
+        let mut escaped = false;
+
+        while !out.full {
+            let buf = match reader.fill_buf() {
+                Ok([])  => break,
+                Ok(buf) => buf,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e)  => return Err(e),
             };
+
+            let len   = buf.len();
+            let mut i = 0;
+
+            while i < len && !out.full {
+                if escaped {
+                    // if the spec contains an expansion for the escaped
+                    // character, use it; otherwise, emit the character
+                    // as a literal
+                    match self.expansions.get(&buf[i]) {
+                        Some(expansion) => out.write(expansion)?,
+                        None            => out.write(&buf[i ..= i])?,
+                    };
+
+                    escaped = false;
+                    i      += 1;
+
+                    continue;
+                }
+
+                // copy literally everything up to the next escape
+                // character
+                let end = buf[i..].iter()
+                    .position(|b| *b == self.escape)
+                    .map_or(len, |offset| i + offset);
+
+                out.write(&buf[i..end])?;
+
+                escaped = end < len;
+                i       = end + usize::from(escaped);
+            }
+
+            reader.consume(len);
         }
 
-        result
+        if out.full {
+            out.inner.write_all(TRUNCATION_MARKER)?;
+        }
+
+        Ok(out.full)
+    }
+}
+
+/// A writer that passes through at most `remaining` bytes, noting when
+/// it's been asked to write more. What's cut off is cut between UTF-8
+/// characters, so usernames, hostnames, and commands in other scripts
+/// aren't left ending in a broken character.
+struct Limited<W> {
+    inner:     W,
+    remaining: usize,
+    full:      bool,
+}
+
+impl<W: Write> Limited<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut len = data.len().min(self.remaining);
+
+        // no character is encoded in more than four bytes, so at most
+        // three continuation bytes have to be given up
+        for _ in 0 .. 3 {
+            if len == 0 || len == data.len() || data[len] & 0b1100_0000 != 0b1000_0000 {
+                break;
+            }
+
+            len -= 1;
+        }
+
+        self.inner.write_all(&data[..len])?;
+        self.remaining -= len;
+        self.full       = len < data.len();
+
+        Ok(())
     }
 }
 
@@ -93,6 +210,10 @@ impl From<HashMap<u8, Vec<u8>>> for Spec {
 mod tests {
     use super::*;
 
+    /// A template large enough that it could never fit in memory on
+    /// most machines.
+    const HUGE : u64 = 16 << 30;
+
     #[test]
     fn new() {
         let spec = Spec::new();
@@ -133,8 +254,8 @@ mod tests {
         let mut spec     = Spec::new();
         let     template = b"a: %a, b: %b";
 
-        let _ = spec.replace(b'a', &b"foo"[..]);
-        let _ = spec.replace(b'b', &b"bar"[..]);
+        spec.replace(b'a', &b"foo"[..]);
+        spec.replace(b'b', &b"bar"[..]);
 
         assert_eq!(
             b"a: foo, b: bar"[..],
@@ -147,8 +268,8 @@ mod tests {
         let mut spec     = Spec::new();
         let     template = b"%a%a%a%b%a%a%b";
 
-        let _ = spec.replace(b'a', &b"x"[..]);
-        let _ = spec.replace(b'b', &b"y"[..]);
+        spec.replace(b'a', &b"x"[..]);
+        spec.replace(b'b', &b"y"[..]);
 
         assert_eq!(
             b"xxxyxxy"[..],
@@ -250,11 +371,9 @@ mod tests {
         );
     }
 
-    // `take_while` silently eats one extra character off of the `Iter`,
-    // since it needs to call `Iter::next` before it can check the value,
-    // and this leads us to not being able to detect the difference
-    // between a normal EOF and an EOF immediately after a lone escape
-    // character; this isn't worth fixing
+    // a lone escape character at the end of a template has nothing to
+    // escape, so it's silently dropped rather than emitted literally;
+    // this isn't worth fixing
     #[test]
     fn bug_wontfix_swallow_trailing_escape_character() {
         let spec     = Spec::new();
@@ -265,4 +384,102 @@ mod tests {
             spec.expand(template)[..]
         );
     }
+
+    /// A reader that returns a single byte per read, so that escapes
+    /// are split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((byte, rest)), Some(out)) => {
+                    *out   = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                },
+
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn render_handles_escapes_split_across_reads() {
+        let mut spec = Spec::new();
+        let mut out  = Vec::new();
+
+        spec.replace(b'a', &b"foo"[..]);
+
+        assert!(!spec.render(Trickle(b"a: %a, %%"), &mut out, usize::MAX).unwrap());
+        assert_eq!(b"a: foo, %"[..], out[..]);
+    }
+
+    #[test]
+    fn render_allows_exactly_the_limit() {
+        let spec    = Spec::new();
+        let mut out = Vec::new();
+
+        assert!(!spec.render(&b"12345"[..], &mut out, 5).unwrap());
+        assert_eq!(b"12345"[..], out[..]);
+    }
+
+    #[test]
+    fn render_never_splits_characters() {
+        let mut spec = Spec::new();
+        let mut out  = Vec::new();
+
+        spec.replace(b'u', "山田太郎");
+        spec.replace(b'h', "cafe\u{301}");
+
+        // `山` is three bytes, and `é` is an `e` and a two-byte accent
+        assert!(spec.render(&b"%u"[..], &mut out, 5).unwrap());
+        assert_eq!(["山".as_bytes(), TRUNCATION_MARKER].concat(), out);
+
+        out.clear();
+
+        assert!(spec.render(&b"%h"[..], &mut out, 5).unwrap());
+        assert_eq!([b"cafe", TRUNCATION_MARKER].concat(), out);
+    }
+
+    #[test]
+    fn render_truncates_huge_templates() {
+        let spec    = Spec::new();
+        let mut out = Vec::new();
+
+        assert!(spec.render(io::repeat(b'x').take(HUGE), &mut out, MAX_PROMPT_LEN).unwrap());
+        assert_eq!(MAX_PROMPT_LEN + TRUNCATION_MARKER.len(), out.len());
+        assert!(out.ends_with(TRUNCATION_MARKER));
+    }
+
+    #[test]
+    fn render_truncates_huge_expansions() {
+        let mut spec = Spec::new();
+        let mut out  = Vec::new();
+
+        // each pair of escape characters expands to a mebibyte
+        spec.replace(b'%', vec![b'y'; 1 << 20]);
+
+        assert!(spec.render(io::repeat(b'%').take(HUGE), &mut out, MAX_PROMPT_LEN).unwrap());
+        assert_eq!(MAX_PROMPT_LEN + TRUNCATION_MARKER.len(), out.len());
+    }
+
+    #[test]
+    fn render_file_falls_back_to_default() {
+        let spec = Spec::new();
+
+        assert_eq!(
+            b"default"[..],
+            spec.render_file(Path::new("/nonexistent/template"), b"default")[..],
+        );
+    }
+
+    #[test]
+    fn render_file_bounds_endless_files() {
+        let spec = Spec::new();
+
+        assert_eq!(
+            MAX_PROMPT_LEN + TRUNCATION_MARKER.len(),
+            spec.render_file(Path::new("/dev/zero"), b"default").len(),
+        );
+    }
 }
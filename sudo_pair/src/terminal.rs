@@ -0,0 +1,269 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The kind of terminal a session is run from. Sessions run inside a
+//! terminal multiplexer (tmux, screen, or zellij) can be detached and
+//! left running long after their approver has stopped paying attention,
+//! so they're pointed out to approvers, can be singled out by `tty` pair
+//! rules, and with `record_multiplexer`, have the multiplexer session
+//! they were run in recorded in their manifest.
+//!
+//! Multiplexers are found by the variables they set in the environment,
+//! or failing that, among the processes the session was started from.
+//! Users control both, so a multiplexer can be hidden by anyone who
+//! goes to the trouble; this is for handling ordinary sessions more
+//! strictly, not for enforcing anything against a determined user.
+
+use sudo_pair_types::manifest::Multiplexer;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use libc::pid_t;
+
+/// The most processes walked up through looking for a multiplexer.
+const MAX_ANCESTORS : usize = 64;
+
+/// The longest a multiplexer session is recorded as; the rest is cut
+/// off, since it comes from the user's environment.
+const MAX_SESSION_LEN : usize = 256;
+
+/// The multiplexers that can be found, with the environment variable
+/// each sets to identify its session, and the prefix of the name its
+/// processes run as.
+const MULTIPLEXERS : &[(&str, &str, &str)] = &[
+    ("tmux",   "TMUX",                "tmux"),
+    ("screen", "STY",                 "screen"),
+    ("zellij", "ZELLIJ_SESSION_NAME", "zellij"),
+];
+
+/// What kind of terminal a session is run from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TtyKind {
+    /// no terminal at all (e.g., from cron or over `ssh` without `-t`)
+    None,
+
+    /// a console or serial line (e.g., `/dev/tty1` or `/dev/ttyS0`)
+    Console,
+
+    /// a pseudo-terminal (e.g., from a terminal emulator or `ssh`)
+    Pty,
+
+    /// a pseudo-terminal belonging to a terminal multiplexer
+    Multiplexer,
+}
+
+impl TtyKind {
+    /// The name `tty` pair rules match the kind by.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TtyKind::None        => "none",
+            TtyKind::Console     => "console",
+            TtyKind::Pty         => "pty",
+            TtyKind::Multiplexer => "multiplexer",
+        }
+    }
+}
+
+impl fmt::Display for TtyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The terminal a session is run from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Terminal {
+    pub kind:        TtyKind,
+    pub multiplexer: Option<Multiplexer>,
+}
+
+impl Terminal {
+    /// Works out the terminal of a session run on `tty`, by a user with
+    /// the environment `env`, from the processes named `ancestors` (the
+    /// nearest first).
+    pub fn detect<F>(tty: Option<&Path>, env: F, ancestors: &[String]) -> Self
+        where F: Fn(&str) -> Option<String>
+    {
+        let tty = match tty {
+            Some(tty) => tty,
+            None      => return Self { kind: TtyKind::None, multiplexer: None },
+        };
+
+        if !is_pty(tty) {
+            return Self { kind: TtyKind::Console, multiplexer: None };
+        }
+
+        let from_env = MULTIPLEXERS.iter().find_map(|&(name, var, _)| {
+            let session = env(var).filter(|session| !session.is_empty())?;
+
+            Some(Multiplexer { name, session: Some(truncate(session)) })
+        });
+
+        let multiplexer = from_env.or_else(|| ancestors.iter().find_map(|process| {
+            let process = process.to_ascii_lowercase();
+
+            MULTIPLEXERS.iter()
+                .find(|(_, _, prefix)| process.starts_with(prefix))
+                .map(|&(name, _, _)| Multiplexer { name, session: None })
+        }));
+
+        Self {
+            kind: if multiplexer.is_some() { TtyKind::Multiplexer } else { TtyKind::Pty },
+            multiplexer,
+        }
+    }
+
+    /// The names `tty` pair rules match the terminal by: its kind, and
+    /// the multiplexer's name if it's run in one.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = vec![self.kind.as_str()];
+
+        names.extend(self.multiplexer.as_ref().map(|multiplexer| multiplexer.name));
+        names
+    }
+}
+
+/// The names of the processes `pid` was started from, the nearest
+/// first, as far as they can be read from `/proc`. Elsewhere, there are
+/// none.
+pub fn ancestors(mut pid: pid_t) -> Vec<String> {
+    let mut ancestors = Vec::new();
+
+    while pid > 1 && ancestors.len() < MAX_ANCESTORS {
+        let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => stat,
+            Err(_)   => break,
+        };
+
+        match parse_stat(&stat) {
+            Some((name, parent)) => {
+                ancestors.push(name.into());
+                pid = parent;
+            },
+
+            None => break,
+        }
+    }
+
+    ancestors
+}
+
+/// The name and parent of the process described by the contents of its
+/// `/proc/<pid>/stat`, whose name is in parentheses and may contain
+/// them itself.
+fn parse_stat(stat: &str) -> Option<(&str, pid_t)> {
+    let start = stat.find('(')? + 1;
+    let end   = stat.rfind(')')?;
+
+    let name   = stat.get(start..end)?;
+    let parent = stat.get(end + 1..)?.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some((name, parent))
+}
+
+/// Whether `tty` is a pseudo-terminal, on Linux (`/dev/pts/N`) or BSD
+/// (`/dev/ttypN`, and the like).
+fn is_pty(tty: &Path) -> bool {
+    if tty.starts_with("/dev/pts") {
+        return true;
+    }
+
+    tty.strip_prefix("/dev")
+        .ok()
+        .and_then(Path::to_str)
+        .is_some_and(|name| name.len() == 5 && name.starts_with("tty") && name.as_bytes()[3].is_ascii_lowercase())
+}
+
+fn truncate(mut session: String) -> String {
+    if session.len() > MAX_SESSION_LEN {
+        let mut end = MAX_SESSION_LEN;
+
+        while !session.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        session.truncate(end);
+    }
+
+    session
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |name| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn detects_kinds_of_terminal() {
+        let none = Terminal::detect(None, env(&[("TMUX", "/tmp/tmux-1000/default,42,0")]), &[]);
+
+        assert_eq!(TtyKind::None, none.kind);
+        assert_eq!(None,          none.multiplexer);
+
+        assert_eq!(TtyKind::Console, Terminal::detect(Some(Path::new("/dev/tty1")),  env(&[]), &[]).kind);
+        assert_eq!(TtyKind::Console, Terminal::detect(Some(Path::new("/dev/ttyS0")), env(&[]), &[]).kind);
+        assert_eq!(TtyKind::Pty,     Terminal::detect(Some(Path::new("/dev/pts/3")), env(&[]), &[]).kind);
+        assert_eq!(TtyKind::Pty,     Terminal::detect(Some(Path::new("/dev/ttyp3")), env(&[]), &[]).kind);
+    }
+
+    #[test]
+    fn detects_multiplexers() {
+        let tty = Some(Path::new("/dev/pts/3"));
+
+        assert_eq!(
+            Terminal {
+                kind:        TtyKind::Multiplexer,
+                multiplexer: Some(Multiplexer { name: "screen", session: Some("4242.pts-0.db01".into()) }),
+            },
+            Terminal::detect(tty, env(&[("STY", "4242.pts-0.db01")]), &[]),
+        );
+
+        // without the environment, the multiplexer is found by name
+        let terminal = Terminal::detect(tty, env(&[]), &["bash".into(), "tmux: server".into()]);
+
+        assert_eq!(Some(Multiplexer { name: "tmux", session: None }), terminal.multiplexer);
+        assert_eq!(vec!["multiplexer", "tmux"], terminal.names());
+
+        // but consoles aren't multiplexed
+        assert_eq!(None, Terminal::detect(Some(Path::new("/dev/tty1")), env(&[("STY", "1.x")]), &[]).multiplexer);
+    }
+
+    #[test]
+    fn truncates_sessions() {
+        let session = format!("{}é", "x".repeat(MAX_SESSION_LEN - 1));
+
+        assert_eq!(MAX_SESSION_LEN - 1, truncate(session).len());
+    }
+
+    #[test]
+    fn parses_process_stats() {
+        assert_eq!(Some(("tmux: server", 1)),  parse_stat("4242 (tmux: server) S 1 4242 4242 0 -1"));
+        assert_eq!(Some(("a) (b", 17)),        parse_stat("99 (a) (b) R 17 99 99 0 -1"));
+        assert_eq!(None,                       parse_stat("garbage"));
+    }
+
+    #[test]
+    fn finds_this_process_ancestors() {
+        let ancestors = ancestors(std::process::id() as pid_t);
+
+        if cfg!(target_os = "linux") {
+            assert!(!ancestors.is_empty());
+        }
+    }
+}
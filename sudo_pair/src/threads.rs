@@ -0,0 +1,202 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The threads the plugin starts during a session.
+//!
+//! Each is named, so it can be told apart in a debugger or `ps -T`,
+//! and is joined when whatever started it is done with it, or at the
+//! latest when the session closes, so none outlive the session. A
+//! thread that panics doesn't take sudo down with it; the panic is
+//! kept until the session closes, where it's reported.
+//!
+//! The only exceptions are the threads delivering recordings to sinks
+//! elsewhere (see `recording::QueuedSink` and `upload::HttpSink`),
+//! which a session waits on for no longer than its budget, and which
+//! are otherwise abandoned to end along with sudo.
+
+use std::any::Any;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+/// The threads started during a session, and the panics of any that
+/// have been joined.
+#[derive(Clone, Debug, Default)]
+pub struct Threads {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next:    usize,
+    running: Vec<(usize, &'static str, JoinHandle<()>)>,
+    panics:  Vec<Panic>,
+}
+
+/// A thread that panicked.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Panic {
+    pub thread:  &'static str,
+    pub message: String,
+}
+
+/// A running thread, which is joined when this is dropped.
+#[derive(Debug)]
+pub struct Thread {
+    id:      usize,
+    threads: Threads,
+}
+
+impl Threads {
+    /// Starts running `f` on a thread called `name`.
+    pub fn spawn<F>(&self, name: &'static str, f: F) -> io::Result<Thread>
+        where F: FnOnce() + Send + 'static
+    {
+        let handle = thread::Builder::new()
+            .name(name.into())
+            .spawn(f)?;
+
+        let mut inner = self.lock();
+        let id        = inner.next;
+
+        inner.next += 1;
+        inner.running.push((id, name, handle));
+
+        Ok(Thread { id, threads: self.clone() })
+    }
+
+    /// Joins every thread that's still running, and returns the panics
+    /// of every thread that's been joined since this was last called.
+    pub fn join_all(&self) -> Vec<Panic> {
+        let running = mem::take(&mut self.lock().running);
+
+        for (_, name, handle) in running {
+            self.join(name, handle);
+        }
+
+        mem::take(&mut self.lock().panics)
+    }
+
+    fn join_one(&self, id: usize) {
+        let thread = {
+            let mut inner = self.lock();
+            let index     = inner.running.iter().position(|(i, ..)| *i == id);
+
+            index.map(|index| inner.running.remove(index))
+        };
+
+        if let Some((_, name, handle)) = thread {
+            self.join(name, handle);
+        }
+    }
+
+    // never called with the lock held, since the thread being joined
+    // may be waiting on it
+    fn join(&self, name: &'static str, handle: JoinHandle<()>) {
+        if let Err(payload) = handle.join() {
+            self.lock().panics.push(Panic {
+                thread:  name,
+                message: message(&*payload),
+            });
+        }
+    }
+
+    // a thread can only panic outside the lock, so it's never poisoned
+    // in a way that matters
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        self.threads.join_one(self.id);
+    }
+}
+
+/// The message a thread panicked with, if it was given one.
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return (*message).into();
+    }
+
+    match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None          => "<unknown>".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn names_threads() {
+        let threads  = Threads::default();
+        let (tx, rx) = mpsc::channel();
+
+        let thread = threads.spawn("namer", move || {
+            tx.send(thread::current().name().map(String::from)).unwrap();
+        }).unwrap();
+
+        drop(thread);
+
+        assert_eq!(Some("namer".into()), rx.recv().unwrap());
+        assert!(threads.join_all().is_empty());
+    }
+
+    #[test]
+    fn joins_threads_when_dropped() {
+        let threads  = Threads::default();
+        let finished = Arc::new(Mutex::new(false));
+        let flag     = Arc::clone(&finished);
+
+        let thread = threads.spawn("sleeper", move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            *flag.lock().unwrap() = true;
+        }).unwrap();
+
+        drop(thread);
+
+        assert!(*finished.lock().unwrap());
+    }
+
+    #[test]
+    fn reports_panics_on_close() {
+        let threads = Threads::default();
+
+        // a thread that's still running when the session closes is
+        // joined then
+        let (tx, rx) = mpsc::channel::<()>();
+        let thread   = threads.spawn("waiter", move || {
+            let _ = rx.recv();
+            panic!("gave up waiting");
+        }).unwrap();
+
+        mem::forget(thread);
+        drop(tx);
+
+        drop(threads.spawn("formatter", || panic!("{} went wrong", 42)).unwrap());
+
+        assert_eq!(vec![
+            Panic { thread: "formatter", message: "42 went wrong".into() },
+            Panic { thread: "waiter",    message: "gave up waiting".into() },
+        ], threads.join_all());
+
+        assert!(threads.join_all().is_empty());
+    }
+}
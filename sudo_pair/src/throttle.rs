@@ -0,0 +1,180 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A limit on how quickly session output is relayed to the approver, so
+//! a command that floods its terminal (e.g., `cat`ing a large log) can't
+//! saturate the approver's connection and leave their session too far
+//! behind to follow. Output over the limit is withheld from the
+//! approver, who's told how much they missed once output is relayed
+//! again. Recordings and sudo's own I/O logs are unaffected.
+
+use crate::suppression::{Reason, Suppression};
+
+use std::time::Instant;
+
+/// A token bucket refilled at a fixed number of bytes per second, up to
+/// one second's worth.
+///
+/// Output is admitted or withheld a whole write at a time, since cutting
+/// a write short could split an escape sequence or a multibyte
+/// character. A write is admitted as long as the bucket isn't empty,
+/// and may leave it in debt, which is paid back before anything else is
+/// admitted; on average, output is still relayed no faster than the
+/// limit.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    rate:   u64,
+    tokens: i64,
+    filled: Instant,
+
+    /// the bytes withheld since output was last admitted
+    pending: u64,
+
+    /// the bytes withheld over the whole session
+    withheld: u64,
+}
+
+impl Throttle {
+    /// Creates a bucket, full as of `now`, relaying up to `rate` bytes
+    /// each second.
+    pub(crate) fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens:   Self::clamp(rate),
+            filled:   now,
+            pending:  0,
+            withheld: 0,
+        }
+    }
+
+    /// Returns true if `len` bytes of output, written at `now`, are to
+    /// be relayed to the approver. Once they are, anything withheld
+    /// before them should be summarized with `flush`.
+    pub(crate) fn admit(&mut self, len: usize, now: Instant) -> bool {
+        if len == 0 {
+            return false;
+        }
+
+        self.refill(now);
+
+        if self.tokens <= 0 {
+            self.pending  += len as u64;
+            self.withheld += len as u64;
+
+            return false;
+        }
+
+        self.tokens = self.tokens.saturating_sub(Self::clamp(len as u64));
+
+        true
+    }
+
+    /// Returns the output withheld since this was last called, if
+    /// there was any.
+    pub(crate) fn flush(&mut self) -> Option<Suppression> {
+        if self.pending == 0 {
+            return None;
+        }
+
+        Some(Suppression {
+            reason: Reason::RateLimit,
+            bytes:  std::mem::take(&mut self.pending),
+        })
+    }
+
+    /// The number of bytes withheld from the approver over the session.
+    pub(crate) fn withheld(&self) -> u64 {
+        self.withheld
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.filled);
+        let earned  = u128::from(self.rate) * elapsed.as_millis() / 1000;
+
+        // only the time that's earned whole bytes is spent, so slow
+        // rates still refill when written to in quick succession
+        if earned == 0 {
+            return;
+        }
+
+        self.tokens = self.tokens
+            .saturating_add(Self::clamp(earned as u64))
+            .min(Self::clamp(self.rate));
+
+        self.filled = now;
+    }
+
+    fn clamp(bytes: u64) -> i64 {
+        if bytes > i64::MAX as u64 { i64::MAX } else { bytes as i64 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn admits_output_within_the_limit() {
+        let start        = Instant::now();
+        let mut throttle = Throttle::new(1024, start);
+
+        assert!(throttle.admit(5, start));
+        assert!(throttle.admit(5, start));
+        assert!(!throttle.admit(0, start));
+
+        assert_eq!(0,    throttle.withheld());
+        assert_eq!(None, throttle.flush());
+    }
+
+    #[test]
+    fn withholds_output_over_the_limit() {
+        let start        = Instant::now();
+        let mut throttle = Throttle::new(4, start);
+
+        // the first write drains the bucket (and then some)
+        assert!(throttle.admit(5, start));
+        assert!(!throttle.admit(4, start));
+        assert!(!throttle.admit(8, start + Duration::from_millis(250)));
+
+        assert_eq!(12, throttle.withheld());
+
+        // two seconds pay off the debt, and the approver is told what
+        // they missed
+        assert!(throttle.admit(2, start + Duration::from_secs(2)));
+
+        assert_eq!(
+            Some(Suppression { reason: Reason::RateLimit, bytes: 12 }),
+            throttle.flush(),
+        );
+
+        assert_eq!(None, throttle.flush());
+        assert_eq!(12,   throttle.withheld());
+    }
+
+    #[test]
+    fn refills_no_more_than_a_seconds_worth() {
+        let start        = Instant::now();
+        let mut throttle = Throttle::new(8, start);
+
+        assert!(throttle.admit(1, start));
+
+        // a long idle stretch doesn't bank an unlimited burst
+        let later = start + Duration::from_secs(60);
+
+        assert!(throttle.admit(8, later));
+        assert!(!throttle.admit(1, later));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Tickets (or change IDs) that users cite as the reason for a paired
+//! session. A ticket can be required to match a pattern before anyone
+//! is asked to approve the session, and with the `http` feature, to be
+//! confirmed by the issue tracker it belongs to.
+
+#[cfg(any(test, feature = "http"))]
+use std::fmt::Write;
+
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+use regex_lite::Regex;
+use sudo_plugin::FromSudoOption;
+
+/// The environment variable tickets are passed in, when they're read
+/// from the environment.
+pub const TICKET_ENV : &str = "SUDO_PAIR_TICKET";
+
+/// The pattern a ticket has to match in its entirety (e.g.,
+/// `(INC|CHG)-[0-9]+`).
+#[derive(Clone, Debug)]
+pub struct TicketPattern(Regex);
+
+impl TicketPattern {
+    /// Returns true if `ticket` matches the pattern. Tickets are shown
+    /// to the approver, so those with control characters never match,
+    /// however permissive the pattern.
+    pub fn matches(&self, ticket: &str) -> bool {
+        !ticket.chars().any(char::is_control) && self.0.is_match(ticket)
+    }
+}
+
+impl FromSudoOption for TicketPattern {
+    type Err = String;
+
+    fn from_sudo_option(s: &str) -> Result<Self, Self::Err> {
+        // anchored, so a ticket can't match by merely containing
+        // something that looks like one
+        Regex::new(&format!("^(?:{})$", s))
+            .map(Self)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Asks the issue tracker at `url` whether `ticket` is one that
+/// sessions may be run under, replacing `%t` in the URL with the
+/// ticket. The tracker is expected to answer a `GET` with a successful
+/// status only for tickets that are open (or approved), and anything
+/// else (including no answer within `timeout`) rejects the ticket.
+#[cfg(feature = "http")]
+pub fn confirm(url: &str, ticket: &str, timeout: Duration) -> Result<(), String> {
+    let url = url.replace("%t", &encode(ticket));
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .redirects(0)
+        .build();
+
+    match agent.get(&url).call() {
+        Ok(_) => Ok(()),
+
+        Err(ureq::Error::Status(status, _)) => Err(format!(
+            "ticket {} was rejected by the issue tracker ({})",
+            ticket,
+            status,
+        )),
+
+        Err(ureq::Error::Transport(transport)) => Err(format!(
+            "ticket {} couldn't be checked: {}",
+            ticket,
+            transport,
+        )),
+    }
+}
+
+/// Percent-encodes everything in `ticket` but unreserved characters,
+/// so it can be placed anywhere in a URL.
+#[cfg(any(test, feature = "http"))]
+fn encode(ticket: &str) -> String {
+    let mut encoded = String::with_capacity(ticket.len());
+
+    for &byte in ticket.as_bytes() {
+        match byte {
+            b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char);
+            },
+
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            },
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> TicketPattern {
+        TicketPattern::from_sudo_option(s).unwrap()
+    }
+
+    #[test]
+    fn matches_whole_tickets() {
+        let pattern = pattern("(INC|CHG)-[0-9]+");
+
+        assert!(pattern.matches("INC-1234"));
+        assert!(pattern.matches("CHG-1"));
+        assert!(!pattern.matches("INC-"));
+        assert!(!pattern.matches("see INC-1234"));
+        assert!(!pattern.matches("INC-1234; rm -rf /"));
+        assert!(!self::pattern(".+").matches("INC-1\x1b[2J"));
+
+        assert!(TicketPattern::from_sudo_option("(INC").is_err());
+    }
+
+    #[test]
+    fn encodes_tickets_for_urls() {
+        assert_eq!("INC-1234",          encode("INC-1234"));
+        assert_eq!("a%2Fb%3Fc%26d%20e", encode("a/b?c&d e"));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn confirms_tickets_with_the_tracker() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url      = format!("http://{}/tickets/%t/open", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let mut paths = Vec::new();
+
+            for status in &[200, 404] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader  = BufReader::new(stream);
+                let mut line    = String::new();
+
+                let _ = reader.read_line(&mut line).unwrap();
+                paths.push(line.split(' ').nth(1).unwrap().to_string());
+
+                loop {
+                    let mut header = String::new();
+                    let _ = reader.read_line(&mut header).unwrap();
+
+                    if header == "\r\n" {
+                        break;
+                    }
+                }
+
+                let _ = write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status,
+                );
+            }
+
+            paths
+        });
+
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(Ok(()), confirm(&url, "INC-1234", timeout));
+        assert_eq!(
+            Err("ticket INC 9 was rejected by the issue tracker (404)".into()),
+            confirm(&url, "INC 9", timeout),
+        );
+
+        assert_eq!(
+            vec!["/tickets/INC-1234/open".to_string(), "/tickets/INC%209/open".into()],
+            server.join().unwrap(),
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Pre-approval tokens, which let an approver approve an exact command
+//! ahead of time instead of watching it live.
+//!
+//! A token is an HMAC-SHA256, under a key shared by the approvers and
+//! the plugin, over the host, the invoking user, the user the command
+//! runs as, the command and its arguments, and the window of time the
+//! token is valid for. It's written as
+//!
+//! ```text
+//! v1.<not before>.<not after>.<hex-encoded MAC>
+//! ```
+//!
+//! where the window's bounds are in seconds since the epoch.
+
+use crate::constant_time;
+use crate::crypto::{self, from_hex, to_hex};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use sudo_plugin::Zeroizing;
+
+/// The version of the token format, and the prefix of every token.
+const VERSION : &str = "v1";
+
+/// The environment variable tokens are passed in, when they're read
+/// from the environment.
+pub const PREAPPROVAL_ENV : &str = "SUDO_PAIR_TOKEN";
+
+/// Domain separation, so a MAC computed under the same key for some
+/// other purpose can never be mistaken for a token.
+const CONTEXT : &[u8] = b"sudo_pair preapproval v1";
+
+/// What a token approves.
+#[derive(Clone, Copy, Debug)]
+pub struct Claims<'a> {
+    pub host:       &'a [u8],
+    pub user:       &'a [u8],
+    pub runas_user: &'a [u8],
+
+    /// the absolute path of the executable, followed by its arguments
+    /// (not including the name it was invoked by)
+    pub command: &'a [&'a [u8]],
+}
+
+/// When a token is valid, in seconds since the epoch (inclusive).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Window {
+    pub not_before: u64,
+    pub not_after:  u64,
+}
+
+impl Window {
+    fn len(self) -> u64 {
+        self.not_after.saturating_sub(self.not_before)
+    }
+}
+
+/// Why a token was rejected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TokenError {
+    Malformed,
+    NotYetValid,
+    Expired,
+    WindowTooLong,
+    BadSignature,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TokenError::Malformed     => "the token is malformed",
+            TokenError::NotYetValid   => "the token isn't valid yet",
+            TokenError::Expired       => "the token has expired",
+            TokenError::WindowTooLong => "the token is valid for longer than allowed",
+            TokenError::BadSignature  => "the token doesn't approve this command",
+        })
+    }
+}
+
+/// Creates a token approving `claims` during `window`. Only the
+/// `sudo_pair_token` binary creates tokens; the plugin just checks them.
+pub fn sign(key: &[u8], claims: &Claims<'_>, window: Window) -> String {
+    let mac = mac(key, claims, window);
+
+    format!(
+        "{}.{}.{}.{}",
+        VERSION,
+        window.not_before,
+        window.not_after,
+        to_hex(&mac),
+    )
+}
+
+/// Checks that `token` approves `claims` at time `now`, and isn't valid
+/// for longer than `max_window` seconds. The MAC is compared in
+/// constant time.
+pub fn verify(
+    key:        &[u8],
+    claims:     &Claims<'_>,
+    token:      &str,
+    now:        u64,
+    max_window: u64,
+) -> Result<Window, TokenError> {
+    let mut parts = token.trim().split('.');
+
+    let (version, not_before, not_after, signature) = match (
+        parts.next(), parts.next(), parts.next(), parts.next(), parts.next(),
+    ) {
+        (Some(v), Some(b), Some(a), Some(s), None) => (v, b, a, s),
+        _                                          => return Err(TokenError::Malformed),
+    };
+
+    if version != VERSION {
+        return Err(TokenError::Malformed);
+    }
+
+    let window = Window {
+        not_before: not_before.parse().map_err(|_| TokenError::Malformed)?,
+        not_after:  not_after .parse().map_err(|_| TokenError::Malformed)?,
+    };
+
+    let signature = from_hex(signature).ok_or(TokenError::Malformed)?;
+
+    // the signature is checked first, so that nothing is revealed about
+    // forged tokens beyond their being rejected
+    let expected = mac(key, claims, window);
+
+    if !constant_time::eq(&expected, &signature) {
+        return Err(TokenError::BadSignature);
+    }
+
+    if window.len() > max_window {
+        return Err(TokenError::WindowTooLong);
+    }
+
+    if now < window.not_before {
+        return Err(TokenError::NotYetValid);
+    }
+
+    if now > window.not_after {
+        return Err(TokenError::Expired);
+    }
+
+    Ok(window)
+}
+
+/// Reads the key tokens are signed with from `path`, ignoring trailing
+/// whitespace (e.g., the newline an editor may have added). The key is
+/// wiped from memory once it's dropped.
+pub fn read_key(path: &Path) -> io::Result<Zeroizing<Vec<u8>>> {
+    let mut key = Zeroizing::new(fs::read(path)?);
+    let len     = key.iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+
+    key.truncate(len);
+
+    if key.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the key is empty"));
+    }
+
+    Ok(key)
+}
+
+fn mac(key: &[u8], claims: &Claims<'_>, window: Window) -> Vec<u8> {
+    let mut message = Vec::new();
+
+    let not_before = window.not_before.to_string();
+    let not_after  = window.not_after .to_string();
+
+    // every field is NUL-terminated; none can contain a NUL, since they
+    // all come from C strings, so the encoding is unambiguous
+    let fields = [
+        CONTEXT,
+        claims.host,
+        claims.user,
+        claims.runas_user,
+        not_before.as_bytes(),
+        not_after .as_bytes(),
+    ];
+
+    for field in fields.iter().chain(claims.command) {
+        message.extend_from_slice(field);
+        message.push(b'\0');
+    }
+
+    crypto::hmac_sha256(key, &message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY : &[u8] = b"correct horse battery staple";
+    const NOW : u64   = 1_600_000_000;
+    const MAX : u64   = 3_600;
+
+    fn claims<'a>(command: &'a [&'a [u8]]) -> Claims<'a> {
+        Claims {
+            host:       b"db01",
+            user:       b"alice",
+            runas_user: b"postgres",
+            command,
+        }
+    }
+
+    fn window(not_before: u64, not_after: u64) -> Window {
+        Window { not_before, not_after }
+    }
+
+    #[test]
+    fn verifies_signed_tokens() {
+        let command : &[&[u8]] = &[b"/usr/bin/psql", b"-c", b"select 1"];
+        let token              = sign(KEY, &claims(command), window(NOW - 60, NOW + 60));
+
+        assert!(token.starts_with("v1.1599999940.1600000060."));
+        assert_eq!(Ok(window(NOW - 60, NOW + 60)), verify(KEY, &claims(command), &token, NOW, MAX));
+    }
+
+    #[test]
+    fn rejects_tokens_for_other_commands() {
+        let command : &[&[u8]] = &[b"/usr/bin/psql", b"-c", b"select 1"];
+        let token              = sign(KEY, &claims(command), window(NOW, NOW + 60));
+
+        // the boundaries between arguments are part of what's signed
+        let joined : &[&[u8]] = &[b"/usr/bin/psql", b"-c select 1"];
+        let other  : &[&[u8]] = &[b"/usr/bin/psql", b"-c", b"drop table users"];
+
+        assert_eq!(Err(TokenError::BadSignature), verify(KEY, &claims(joined), &token, NOW, MAX));
+        assert_eq!(Err(TokenError::BadSignature), verify(KEY, &claims(other),  &token, NOW, MAX));
+
+        let mut elsewhere = claims(command);
+        elsewhere.host = b"db02";
+
+        assert_eq!(Err(TokenError::BadSignature), verify(KEY,          &elsewhere,      &token, NOW, MAX));
+        assert_eq!(Err(TokenError::BadSignature), verify(b"other key", &claims(command), &token, NOW, MAX));
+    }
+
+    #[test]
+    fn rejects_tokens_outside_their_window() {
+        let command : &[&[u8]] = &[b"/bin/true"];
+        let token              = sign(KEY, &claims(command), window(NOW, NOW + 60));
+
+        assert_eq!(Err(TokenError::NotYetValid), verify(KEY, &claims(command), &token, NOW - 1,  MAX));
+        assert_eq!(Err(TokenError::Expired),     verify(KEY, &claims(command), &token, NOW + 61, MAX));
+        assert!(verify(KEY, &claims(command), &token, NOW + 60, MAX).is_ok());
+
+        let forever = sign(KEY, &claims(command), window(NOW, NOW + MAX + 1));
+
+        assert_eq!(Err(TokenError::WindowTooLong), verify(KEY, &claims(command), &forever, NOW, MAX));
+    }
+
+    #[test]
+    fn rejects_tampered_windows() {
+        let command : &[&[u8]] = &[b"/bin/true"];
+        let token              = sign(KEY, &claims(command), window(NOW, NOW + 60));
+        let extended           = token.replacen(".1600000060.", ".1600000600.", 1);
+
+        assert_eq!(Err(TokenError::BadSignature), verify(KEY, &claims(command), &extended, NOW, MAX));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let command : &[&[u8]] = &[b"/bin/true"];
+
+        for token in &["", "v1", "v1.1.2", "v2.1.2.00", "v1.a.2.00", "v1.1.2.0", "v1.1.2.zz", "v1.1.2.00.00"] {
+            assert_eq!(Err(TokenError::Malformed), verify(KEY, &claims(command), token, NOW, MAX));
+        }
+    }
+
+    #[test]
+    fn trims_keys() {
+        let path = std::env::temp_dir().join(format!("sudo_pair-token-key-{}", std::process::id()));
+
+        fs::write(&path, b"secret\n").unwrap();
+        assert_eq!(b"secret".to_vec(), *read_key(&path).unwrap());
+
+        fs::write(&path, b"\n").unwrap();
+        assert!(read_key(&path).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
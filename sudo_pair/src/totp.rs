@@ -0,0 +1,407 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Authentication of approvers by one-time code.
+//!
+//! When `approver_totp_keys` is set, each approver has to send the name
+//! they're provisioned under there and the current code from their
+//! authenticator (RFC 6238: HMAC-SHA1, six digits, thirty-second steps)
+//! before their approval is accepted. Codes from the step before or
+//! after the current one are accepted too, to allow for clock drift.
+//!
+//! A code can only be used once. The last step each approver used is
+//! kept in a ledger shared by every session on the host, so a code
+//! that's been seen (on the approver's screen, or in transit) can't be
+//! used to approve another session within the time it's valid.
+
+use crate::constant_time;
+use crate::crypto;
+use crate::ownership::Ownership;
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sudo_plugin::Zeroizing;
+
+/// How long each code is valid for, in seconds.
+const STEP : u64 = 30;
+
+/// How many digits codes have.
+const DIGITS : usize = 6;
+
+/// The longest name an approver may be provisioned under.
+const MAX_APPROVER_LEN : usize = 64;
+
+/// The approvers who have to enter one-time codes, and their secrets,
+/// which are wiped from memory once they're dropped.
+#[derive(Clone, Default)]
+pub struct TotpKeys {
+    keys: Vec<(String, Zeroizing<Vec<u8>>)>,
+}
+
+// secrets are never printed, even when debugging
+impl fmt::Debug for TotpKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.keys.iter().map(|(approver, _)| approver))
+            .finish()
+    }
+}
+
+/// A one-time code an approver sent, and who they say they are.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Code {
+    pub approver: String,
+    pub code:     String,
+}
+
+impl TotpKeys {
+    /// Loads the secrets listed at `path`, which has to be owned by
+    /// root and accessible to nobody else, since anyone who could read
+    /// a secret could generate its codes.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+
+        if metadata.uid() != 0 {
+            return Err("the secrets aren't owned by root".into());
+        }
+
+        if metadata.mode() & 0o077 != 0 {
+            return Err("the secrets are accessible to users other than root".into());
+        }
+
+        let listing = Zeroizing::new(fs::read_to_string(path).map_err(|e| e.to_string())?);
+        let keys    = Self::parse(&listing);
+
+        if keys.keys.is_empty() {
+            return Err("no approvers are listed".into());
+        }
+
+        Ok(keys)
+    }
+
+    /// Parses secrets listed one approver per line, as the approver's
+    /// name and their base32-encoded secret (as authenticators are
+    /// provisioned with). Lines that aren't in this format are skipped.
+    pub fn parse(s: &str) -> Self {
+        let keys = s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let approver   = fields.next().filter(|approver| valid_approver(approver))?;
+                let encoded    = Zeroizing::new(fields.collect::<String>());
+                let secret     = Zeroizing::new(unbase32(&encoded)?);
+
+                Some((approver.into(), secret))
+            })
+            .collect();
+
+        Self { keys }
+    }
+
+    /// How many approvers are listed.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no approvers are listed.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Verifies that `code` is the current code of the approver it
+    /// names as of `now`, and that it hasn't been used before according
+    /// to `ledger`.
+    pub fn verify(&self, code: Option<&Code>, now: SystemTime, ledger: &Ledger) -> Result<(), Rejection> {
+        let code = code.ok_or(Rejection::Missing)?;
+
+        let secret = self.keys.iter()
+            .find(|(approver, _)| *approver == code.approver)
+            .map(|(_, secret)| secret)
+            .ok_or(Rejection::UnknownApprover)?;
+
+        let current = now.duration_since(UNIX_EPOCH)
+            .map_err(|_| Rejection::BadCode)?
+            .as_secs() / STEP;
+
+        // every step in the window is checked, whether or not an
+        // earlier one matched, so how long this takes doesn't depend on
+        // which (if any) did
+        let mut matched = None;
+
+        for step in current.saturating_sub(1) ..= current + 1 {
+            if constant_time::eq(generate(secret, step).as_bytes(), code.code.as_bytes()) {
+                matched = Some(step);
+            }
+        }
+
+        let step = matched.ok_or(Rejection::BadCode)?;
+
+        match ledger.claim(&code.approver, step) {
+            Ok(true)  => Ok(()),
+            Ok(false) => Err(Rejection::Replayed),
+            Err(_)    => Err(Rejection::Unrecorded),
+        }
+    }
+}
+
+/// Why a one-time code wasn't accepted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// the approver didn't send one
+    Missing,
+
+    /// it names an approver who isn't provisioned
+    UnknownApprover,
+
+    /// it isn't the approver's current code
+    BadCode,
+
+    /// it (or a later one) has already been used
+    Replayed,
+
+    /// its use couldn't be recorded, so it can't be known not to have
+    /// been used already
+    Unrecorded,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::Missing         => "no one-time code was entered",
+            Rejection::UnknownApprover => "the approver isn't provisioned for one-time codes",
+            Rejection::BadCode         => "the one-time code is incorrect",
+            Rejection::Replayed        => "the one-time code has already been used",
+            Rejection::Unrecorded      => "the one-time code couldn't be checked for reuse",
+        })
+    }
+}
+
+/// The last step each approver used a code from, shared by every
+/// session on the host.
+#[derive(Clone, Debug)]
+pub struct Ledger {
+    dir: PathBuf,
+}
+
+impl Ledger {
+    /// A ledger kept in `dir`, which is created (readable only by root)
+    /// when it's first needed.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Records that `approver` has used the code from `step`, unless
+    /// they've already used that step's code (or a later one), in
+    /// which case this returns `false`.
+    pub fn claim(&self, approver: &str, step: u64) -> io::Result<bool> {
+        Ownership::default().create_dir(&self.dir)?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(self.dir.join(approver))?;
+
+        // concurrent sessions approved with the same code would
+        // otherwise both read the ledger before either updated it
+        lock(&file)?;
+
+        let mut contents = String::new();
+        let _            = file.read_to_string(&mut contents)?;
+
+        if matches!(contents.trim().parse::<u64>(), Ok(last) if last >= step) {
+            return Ok(false);
+        }
+
+        let _ = file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(step.to_string().as_bytes())?;
+
+        Ok(true)
+    }
+}
+
+/// Takes an exclusive lock on `file`, which is released when it's
+/// closed.
+fn lock(file: &File) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Whether `approver` can be provisioned, which keeps it to a name that
+/// can also name a file in the ledger.
+pub fn valid_approver(approver: &str) -> bool {
+    !approver.is_empty()
+        && approver.len() <= MAX_APPROVER_LEN
+        && !approver.starts_with('.')
+        && approver.bytes().all(|b| b.is_ascii_alphanumeric() || b"._-@".contains(&b))
+}
+
+/// Whether `code` looks like a one-time code at all.
+pub fn valid_code(code: &str) -> bool {
+    code.len() == DIGITS && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The code for `step` under `secret`.
+fn generate(secret: &[u8], step: u64) -> String {
+    let digest = crypto::hmac_sha1(secret, &step.to_be_bytes());
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!("{:0width$}", binary % 10_u32.pow(DIGITS as u32), width = DIGITS)
+}
+
+/// Decodes base32 (RFC 4648), ignoring case and padding.
+fn unbase32(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer  = 0_u32;
+    let mut bits    = 0;
+
+    for b in encoded.trim_end_matches('=').bytes() {
+        let value = match b.to_ascii_uppercase() {
+            c @ b'A' ..= b'Z' => c - b'A',
+            c @ b'2' ..= b'7' => c - b'2' + 26,
+            _                 => return None,
+        };
+
+        buffer = buffer << 5 | u32::from(value);
+        bits  += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    if decoded.is_empty() {
+        return None;
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    use std::time::Duration;
+
+    // the secret from RFC 6238's test vectors, base32-encoded
+    const SECRET : &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn code(approver: &str, code: &str) -> Code {
+        Code { approver: approver.into(), code: code.into() }
+    }
+
+    fn ledger(name: &str) -> Ledger {
+        Ledger::new(scratch::dir("totp", name, false))
+    }
+
+    #[test]
+    fn generates_rfc_6238_codes() {
+        let secret = b"12345678901234567890";
+
+        assert_eq!("287082", generate(secret, 59 / STEP));
+        assert_eq!("081804", generate(secret, 1_111_111_109 / STEP));
+        assert_eq!("005924", generate(secret, 1_234_567_890 / STEP));
+        assert_eq!("279037", generate(secret, 2_000_000_000 / STEP));
+    }
+
+    #[test]
+    fn parses_secrets() {
+        let keys = TotpKeys::parse(&format!(
+            "# approvers\n\nalice {}\nbob GEZD GNBV GY3T QOJQ\n../root {}\ncarol not-base32\n",
+            SECRET,
+            SECRET,
+        ));
+
+        assert_eq!(2, keys.len());
+        assert_eq!(("alice".into(), Zeroizing::new(b"12345678901234567890".to_vec())), keys.keys[0]);
+        assert_eq!(("bob".into(),   Zeroizing::new(b"1234567890".to_vec())),           keys.keys[1]);
+    }
+
+    #[test]
+    fn verifies_codes_within_the_window() {
+        let keys   = TotpKeys::parse(&format!("alice {}", SECRET));
+        let ledger = ledger("window");
+
+        assert_eq!(Ok(()), keys.verify(Some(&code("alice", "081804")), at(1_111_111_109), &ledger));
+        assert_eq!(Ok(()), keys.verify(Some(&code("alice", "005924")), at(1_234_567_890 + STEP), &ledger));
+
+        assert_eq!(Err(Rejection::BadCode), keys.verify(Some(&code("alice", "005924")), at(1_234_567_890 + 2 * STEP), &ledger));
+        assert_eq!(Err(Rejection::BadCode), keys.verify(Some(&code("alice", "000000")), at(1_234_567_890), &ledger));
+
+        assert_eq!(Err(Rejection::UnknownApprover), keys.verify(Some(&code("bob", "005924")), at(1_234_567_890), &ledger));
+        assert_eq!(Err(Rejection::Missing),         keys.verify(None,                         at(1_234_567_890), &ledger));
+
+        let _ = fs::remove_dir_all(&ledger.dir);
+    }
+
+    #[test]
+    fn rejects_reused_codes() {
+        let keys   = TotpKeys::parse(&format!("alice {}\nbob {}", SECRET, SECRET));
+        let ledger = ledger("replay");
+
+        assert_eq!(Ok(()),                   keys.verify(Some(&code("alice", "005924")), at(1_234_567_890), &ledger));
+        assert_eq!(Err(Rejection::Replayed), keys.verify(Some(&code("alice", "005924")), at(1_234_567_890), &ledger));
+
+        // nor can an earlier step's code be used once a later one has
+        let earlier = generate(b"12345678901234567890", 1_234_567_890 / STEP - 1);
+
+        assert_eq!(Err(Rejection::Replayed), keys.verify(Some(&code("alice", &earlier)), at(1_234_567_890), &ledger));
+
+        // each approver's codes are tracked separately
+        assert_eq!(Ok(()), keys.verify(Some(&code("bob", "005924")), at(1_234_567_890), &ledger));
+
+        let _ = fs::remove_dir_all(&ledger.dir);
+    }
+
+    #[test]
+    fn validates_approvers_and_codes() {
+        assert!(valid_approver("alice"));
+        assert!(valid_approver("alice.smith@example.com"));
+        assert!(!valid_approver(""));
+        assert!(!valid_approver(".."));
+        assert!(!valid_approver("../alice"));
+        assert!(!valid_approver(&"a".repeat(MAX_APPROVER_LEN + 1)));
+
+        assert!(valid_code("005924"));
+        assert!(!valid_code("5924"));
+        assert!(!valid_code("00592a"));
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A running digest of the session output sent to the approver, so that
+//! what the approver saw can be verified against what was recorded.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::crypto::{to_hex, Sha256};
+
+use sudo_pair_types::protocol;
+
+#[derive(Debug)]
+pub struct Transcript {
+    hasher: Sha256,
+    bytes:  u64,
+
+    /// how often checkpoints are produced; `None` if they never are
+    interval:        Option<Duration>,
+    last_checkpoint: Instant,
+
+    /// what the interval is measured on
+    clock: Arc<dyn Clock>,
+}
+
+/// The digest of every byte of output sent to the approver so far.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub bytes:  u64,
+    pub digest: String,
+}
+
+impl Transcript {
+    /// Creates a transcript producing checkpoints every `interval`, as
+    /// measured by `clock`. An interval of zero disables checkpoints,
+    /// though the digest is still maintained.
+    pub fn new(interval: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            hasher:          Sha256::new(),
+            bytes:           0,
+            interval:        Some(interval).filter(|i| *i != Duration::default()),
+            last_checkpoint: clock.instant(),
+            clock,
+        }
+    }
+
+    /// Adds `data` to the transcript, returning a checkpoint if one is
+    /// due. Since there's no timer, a checkpoint only becomes due on the
+    /// first write after the interval elapses.
+    pub fn update(&mut self, data: &[u8]) -> Option<Checkpoint> {
+        self.hasher.update(data);
+        self.bytes += data.len() as u64;
+
+        let interval = self.interval?;
+
+        let now = self.clock.instant();
+
+        if now.saturating_duration_since(self.last_checkpoint) < interval {
+            return None;
+        }
+
+        self.last_checkpoint = now;
+
+        Some(self.checkpoint())
+    }
+
+    /// Returns a checkpoint of the transcript so far.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            bytes:  self.bytes,
+            digest: to_hex(&self.hasher.clone().finish()),
+        }
+    }
+
+    /// Returns true if checkpoints are sent to the approver.
+    pub fn is_streamed(&self) -> bool {
+        self.interval.is_some()
+    }
+}
+
+impl Checkpoint {
+    /// Encodes the checkpoint as a protocol message to be sent in-band
+    /// to the approver. The checkpoint itself is not part of the
+    /// digested output.
+    pub fn to_escape_sequence(&self) -> Vec<u8> {
+        protocol::message(&format!(
+            "checkpoint;bytes={};sha256={}",
+            self.bytes,
+            self.digest,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+
+    use std::time::UNIX_EPOCH;
+
+    const ABC_SHA256 : &str =
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn digests_output_across_writes() {
+        let mut transcript = Transcript::new(Duration::default(), Arc::new(SystemClock));
+
+        assert_eq!(None, transcript.update(b"a"));
+        assert_eq!(None, transcript.update(b"bc"));
+
+        assert_eq!(Checkpoint { bytes: 3, digest: ABC_SHA256.into() }, transcript.checkpoint());
+    }
+
+    #[test]
+    fn produces_checkpoints_when_due() {
+        let clock          = ManualClock::at(UNIX_EPOCH);
+        let mut transcript = Transcript::new(Duration::from_secs(30), Arc::new(clock.clone()));
+
+        assert_eq!(None, transcript.update(b"a"));
+
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(None, transcript.update(b"b"));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Some(3), transcript.update(b"c").map(|c| c.bytes));
+
+        // the next one is due a full interval later
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(None, transcript.update(b"d"));
+
+        assert!(transcript.is_streamed());
+    }
+
+    #[test]
+    fn encodes_checkpoints() {
+        let checkpoint = Checkpoint { bytes: 3, digest: ABC_SHA256.into() };
+
+        assert_eq!(
+            format!("\x1b]5379;sudo_pair;checkpoint;bytes=3;sha256={}\x07", ABC_SHA256).into_bytes(),
+            checkpoint.to_escape_sequence(),
+        );
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Handing a paired session off to another approver (e.g., at a shift
+//! change) without interrupting it.
+//!
+//! Once a session is approved, its socket is reopened. Whoever connects
+//! is prompted just as the original approver was, while the current
+//! approver is asked whether to hand the session off. It's handed off
+//! only once both have approved, and nothing is waited on in between,
+//! so the user's session carries on throughout.
+
+use crate::deadline::Deadline;
+use crate::protocol::{self, Credentials, Hello, Reply};
+use crate::socket::Socket;
+
+use std::time::Duration;
+
+use sudo_pair_types::humanize;
+use sudo_pair_types::protocol::{BEL, ESC};
+
+/// A handoff that's been offered, but not yet completed or abandoned.
+#[derive(Debug)]
+pub struct Offer {
+    /// the approver offering to take over the session
+    pub candidate: Socket,
+
+    /// what they've sent so far, since their answer can arrive
+    /// piecemeal
+    pub input: Vec<u8>,
+
+    /// the hello they were sent
+    pub hello: Hello,
+
+    /// the challenge they were sent to sign, if approvers have to
+    /// authenticate
+    pub challenge: Option<String>,
+
+    /// whether the current approver has agreed to hand the session off
+    pub released: bool,
+
+    pub deadline: Deadline,
+}
+
+/// The new approver's answer to the pair prompt.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Response {
+    /// they haven't finished answering
+    Pending,
+
+    /// they approved, after negotiating the protocol if their client
+    /// supports it and sending whatever credentials they sent
+    Approved(Option<Hello>, Credentials),
+
+    /// they declined, or sent something that isn't an answer
+    Declined,
+}
+
+/// Interprets what the new approver has sent so far, just as the
+/// original approver's answer was: an optional hello and optional
+/// credentials, followed by `y` to approve.
+pub fn response(input: &[u8]) -> Response {
+    let mut client      = None;
+    let mut credentials = Credentials::default();
+    let mut rest        = input;
+
+    loop {
+        match rest.split_first() {
+            None => return Response::Pending,
+
+            Some((&ESC, message)) => match message.iter().position(|&b| b == BEL) {
+                Some(end) => {
+                    match Reply::decode(&message[..end]) {
+                        Some(Reply::Hello(hello)) if client.is_none() && credentials.is_empty() => {
+                            client = Some(hello);
+                        },
+
+                        Some(Reply::Signature(sshsig)) if credentials.signature.is_none() => {
+                            credentials.signature = Some(sshsig);
+                        },
+
+                        Some(Reply::Totp(code)) if credentials.totp.is_none() => {
+                            credentials.totp = Some(code);
+                        },
+
+                        _ => return Response::Declined,
+                    }
+
+                    rest = &message[end + 1..];
+                },
+
+                None if message.len() < protocol::LIMITS.message => return Response::Pending,
+                None                                             => return Response::Declined,
+            },
+
+            Some((b'y' | b'Y', _)) => return Response::Approved(client, credentials),
+            Some(_)                => return Response::Declined,
+        }
+    }
+}
+
+/// The question put to the current approver.
+pub fn prompt(timeout: Duration) -> String {
+    format!(
+        "\nanother approver wants to take over this session; hand it off? [y/n] ({} to answer)\n",
+        humanize::duration(timeout),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::totp::Code;
+
+    use sudo_pair_types::protocol::{Capabilities, Encoding, PROTOCOL_VERSION};
+
+    #[test]
+    fn interprets_responses_as_they_arrive() {
+        let hello = Hello {
+            version:      PROTOCOL_VERSION,
+            capabilities: Capabilities::WINSIZE,
+            deadline:     None,
+            encoding:     Encoding::Text,
+            utc_offset:   None,
+            winsize:      None,
+            limits:       protocol::LIMITS,
+        };
+
+        let encoded = hello.encode();
+        let (partial, _) = encoded.split_at(encoded.len() - 1);
+
+        assert_eq!(Response::Pending,  response(b""));
+        assert_eq!(Response::Pending,  response(partial));
+        assert_eq!(Response::Pending,  response(&encoded));
+
+        assert_eq!(Response::Approved(None, Credentials::default()),        response(b"y"));
+        assert_eq!(Response::Approved(Some(hello), Credentials::default()), response(&[&encoded[..], b"Y"].concat()));
+
+        assert_eq!(Response::Declined, response(b"n"));
+        assert_eq!(Response::Declined, response(&[&encoded[..], b"n"].concat()));
+        assert_eq!(Response::Declined, response(b"\x1b]0;title\x07y"));
+        assert_eq!(Response::Declined, response(&[b'\x1b'; 1024]));
+    }
+
+    #[test]
+    fn reads_credentials_with_responses() {
+        let signature = b"\x1b]5379;sudo_pair;signature;sshsig=U1NIU0lH\x07";
+        let totp      = b"\x1b]5379;sudo_pair;totp;approver=alice;code=005924\x07";
+
+        assert_eq!(Response::Pending, response(signature));
+
+        assert_eq!(
+            Response::Approved(None, Credentials {
+                signature: Some(b"SSHSIG".to_vec()),
+                totp:      Some(Code { approver: "alice".into(), code: "005924".into() }),
+            }),
+            response(&[&signature[..], &totp[..], b"y"].concat()),
+        );
+
+        assert_eq!(Response::Declined, response(&[&signature[..], &signature[..], b"y"].concat()));
+        assert_eq!(Response::Declined, response(&[&totp[..], &totp[..], b"y"].concat()));
+        assert_eq!(Response::Declined, response(b"\x1b]5379;sudo_pair;comment;text=hi\x07y"));
+    }
+
+    #[test]
+    fn prompts_with_the_time_to_answer() {
+        assert_eq!(
+            "\nanother approver wants to take over this session; hand it off? [y/n] (2 minutes to answer)\n",
+            prompt(Duration::from_secs(120)),
+        );
+    }
+}
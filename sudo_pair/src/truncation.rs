@@ -0,0 +1,321 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Shortening of commands for display. Commands built by tools like
+//! `xargs` can have thousands of arguments, which would otherwise bury
+//! the prompt and bloat every log line; recordings and manifests keep
+//! the command in full.
+//!
+//! Commands are only ever cut between characters as a terminal displays
+//! them: never in the middle of a UTF-8 sequence, and never between a
+//! character and the combining characters (e.g., accents) that follow
+//! it. Their width is measured in the columns a terminal gives them, so
+//! wide characters (e.g., CJK) count twice and combining ones not at
+//! all.
+
+use std::borrow::Cow;
+
+use unicode_width::UnicodeWidthChar;
+
+/// Limits on how much of a command is displayed. A limit of `0` doesn't
+/// limit anything.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct CommandLimits {
+    /// the most arguments displayed after the command's name
+    pub(crate) max_args: usize,
+
+    /// the most bytes of the command displayed, including the spaces
+    /// between its arguments
+    pub(crate) max_length: usize,
+
+    /// the most columns of the command displayed, including the spaces
+    /// between its arguments
+    pub(crate) max_width: usize,
+}
+
+impl CommandLimits {
+    /// Returns the part of `command` (its name followed by its
+    /// arguments) that's within these limits.
+    pub(crate) fn apply<'a>(&self, command: &[&'a [u8]]) -> Truncated<'a> {
+        let mut truncated = Truncated {
+            shown:   Vec::new(),
+            cut:     false,
+            omitted: 0,
+        };
+
+        let max_length = limit(self.max_length);
+        let max_width  = limit(self.max_width);
+
+        let mut length = 0;
+        let mut width  = 0;
+
+        for (i, arg) in command.iter().enumerate() {
+            if self.max_args != 0 && i > self.max_args {
+                break;
+            }
+
+            let separator = usize::from(i != 0);
+            let arg_width = self::width(arg);
+
+            if length + separator + arg.len() > max_length || width + separator + arg_width > max_width {
+                let end = fit(
+                    arg,
+                    max_length.saturating_sub(length + separator),
+                    max_width .saturating_sub(width  + separator),
+                );
+
+                if end > 0 {
+                    truncated.shown.push(&arg[..end]);
+                    truncated.cut = true;
+                }
+
+                break;
+            }
+
+            truncated.shown.push(arg);
+            length += separator + arg.len();
+            width  += separator + arg_width;
+        }
+
+        truncated.omitted = command.len() - truncated.shown.len();
+        truncated
+    }
+}
+
+/// The part of a command that's displayed.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Truncated<'a> {
+    /// the command's name and arguments that are displayed, the last of
+    /// which may have been cut short
+    pub(crate) shown: Vec<&'a [u8]>,
+
+    /// whether the last entry in `shown` was cut short
+    pub(crate) cut: bool,
+
+    /// how many arguments aren't displayed at all
+    pub(crate) omitted: usize,
+}
+
+impl Truncated<'_> {
+    /// Returns whether any of the command isn't displayed.
+    pub(crate) fn is_truncated(&self) -> bool {
+        self.cut || self.omitted > 0
+    }
+
+    /// Returns the marker noting what isn't displayed: `…` if an
+    /// argument was cut short, followed by how many arguments were left
+    /// out (e.g., `… (+1024 args)`). It's empty if nothing was.
+    pub(crate) fn marker(&self) -> String {
+        match self.omitted {
+            0 if self.cut => "…".into(),
+            0             => String::new(),
+            1             => "… (+1 arg)".into(),
+            n             => format!("… (+{} args)", n),
+        }
+    }
+
+    /// Returns the displayed command, its entries separated by spaces
+    /// and followed by the marker.
+    pub(crate) fn join(&self) -> Vec<u8> {
+        let mut joined = self.shown.join(&b' ');
+        let     marker = self.marker();
+
+        if !marker.is_empty() {
+            if !self.cut && !joined.is_empty() {
+                joined.push(b' ');
+            }
+
+            joined.extend_from_slice(marker.as_bytes());
+        }
+
+        joined
+    }
+
+    /// Returns the displayed arguments (not including the command's
+    /// name) for logging, with the marker as a final entry if anything
+    /// isn't displayed.
+    pub(crate) fn args(&self) -> Vec<Cow<'_, str>> {
+        let mut args : Vec<_> = self.shown.iter()
+            .skip(1)
+            .map(|arg| String::from_utf8_lossy(arg))
+            .collect();
+
+        if self.is_truncated() {
+            args.push(self.marker().into());
+        }
+
+        args
+    }
+}
+
+/// Returns `max`, or no limit at all if it's `0`.
+fn limit(max: usize) -> usize {
+    if max == 0 { usize::MAX } else { max }
+}
+
+/// Returns the characters of `bytes` with their offsets and the columns
+/// they take up. Bytes that aren't valid UTF-8 are each given a column
+/// of their own, as they're displayed escaped (or replaced) one by one.
+fn columns(bytes: &[u8]) -> impl Iterator<Item = Column> + '_ {
+    let mut offset = 0;
+
+    bytes.utf8_chunks().flat_map(move |chunk| {
+        let start = offset;
+        offset   += chunk.valid().len() + chunk.invalid().len();
+
+        let valid = chunk.valid().char_indices().map(move |(i, c)| Column {
+            offset:    start + i,
+            len:       c.len_utf8(),
+            width:     c.width().unwrap_or(0),
+            combining: c.width() == Some(0),
+        });
+
+        let invalid_start = start + chunk.valid().len();
+        let invalid       = (0 .. chunk.invalid().len()).map(move |i| Column {
+            offset:    invalid_start + i,
+            len:       1,
+            width:     1,
+            combining: false,
+        });
+
+        valid.chain(invalid)
+    })
+}
+
+/// A character, as `columns` describes it.
+#[derive(Clone, Copy, Debug)]
+struct Column {
+    offset:    usize,
+    len:       usize,
+    width:     usize,
+
+    /// whether it's displayed on top of the character before it
+    combining: bool,
+}
+
+/// Returns the number of columns `bytes` takes up when displayed.
+fn width(bytes: &[u8]) -> usize {
+    columns(bytes).map(|column| column.width).sum()
+}
+
+/// Returns the length of the longest prefix of `bytes` that's at most
+/// `max_length` bytes and `max_width` columns, without separating any
+/// character from the combining characters that follow it.
+fn fit(bytes: &[u8], max_length: usize, max_width: usize) -> usize {
+    let mut width = 0;
+    let mut start = 0;
+
+    for column in columns(bytes) {
+        if !column.combining {
+            start = column.offset;
+        }
+
+        if column.offset + column.len > max_length || width + column.width > max_width {
+            return if column.combining { start } else { column.offset };
+        }
+
+        width += column.width;
+    }
+
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_args: usize, max_length: usize) -> CommandLimits {
+        CommandLimits { max_args, max_length, max_width: 0 }
+    }
+
+    fn widths(max_width: usize) -> CommandLimits {
+        CommandLimits { max_args: 0, max_length: 0, max_width }
+    }
+
+    fn display(limits: CommandLimits, command: &[&str]) -> String {
+        let command : Vec<&[u8]> = command.iter().map(|arg| arg.as_bytes()).collect();
+
+        String::from_utf8(limits.apply(&command).join()).unwrap()
+    }
+
+    #[test]
+    fn leaves_short_commands_alone() {
+        let command = ["ls", "-la", "/tmp"];
+
+        assert_eq!("ls -la /tmp", display(limits(0, 0),  &command));
+        assert_eq!("ls -la /tmp", display(limits(2, 11), &command));
+    }
+
+    #[test]
+    fn limits_the_number_of_arguments() {
+        let command = ["rm", "a", "b", "c", "d"];
+
+        assert_eq!("rm a b … (+2 args)", display(limits(2, 0), &command));
+        assert_eq!("rm a b c … (+1 arg)", display(limits(3, 0), &command));
+    }
+
+    #[test]
+    fn limits_the_length() {
+        let command = ["echo", "hello", "world"];
+
+        assert_eq!("echo hel… (+1 arg)", display(limits(0, 8), &command));
+        assert_eq!("echo hello … (+1 arg)", display(limits(0, 11), &command));
+        assert_eq!("echo hello wor…", display(limits(0, 14), &command));
+    }
+
+    #[test]
+    fn never_splits_characters() {
+        assert_eq!("echo h… (+1 arg)", display(limits(0, 7), &["echo", "hé", "x"]));
+        assert_eq!("echo hé…",         display(limits(0, 8), &["echo", "héé"]));
+    }
+
+    #[test]
+    fn never_splits_combining_characters() {
+        // `e` followed by a combining acute accent
+        assert_eq!("echo e\u{301}…",          display(limits(0, 8), &["echo", "e\u{301}e\u{301}"]));
+        assert_eq!("echo … (+1 arg)",          display(limits(0, 7), &["echo", "e\u{301}e\u{301}"]));
+        assert_eq!("echo e\u{301}e\u{301}", display(widths(7),    &["echo", "e\u{301}e\u{301}"]));
+        assert_eq!("echo e\u{301}…",          display(widths(6),    &["echo", "e\u{301}e\u{301}"]));
+    }
+
+    #[test]
+    fn limits_the_width() {
+        let command = ["cat", "日本語.txt", "x"];
+
+        assert_eq!("cat 日本語.txt x", display(widths(16), &command));
+        assert_eq!("cat 日本… (+1 arg)", display(widths(8), &command));
+        assert_eq!("cat 日本… (+1 arg)", display(widths(9), &command));
+        assert_eq!("cat 日… (+1 arg)",   display(widths(7), &command));
+
+        // each of these characters is three bytes, but only two columns
+        assert_eq!("cat 日本語… (+1 arg)", display(widths(10),    &command));
+        assert_eq!("cat 日本… (+1 arg)",   display(limits(0, 10), &command));
+    }
+
+    #[test]
+    fn measures_invalid_utf8_by_the_byte() {
+        let command : Vec<&[u8]> = vec![b"echo", b"\xff\xfe\xfd"];
+
+        assert_eq!(b"echo \xff\xfe\xe2\x80\xa6".to_vec(), widths(7).apply(&command).join());
+    }
+
+    #[test]
+    fn logs_the_displayed_arguments() {
+        let command : Vec<&[u8]> = vec![b"rm", b"a", b"b", b"c"];
+        let truncated = limits(1, 0).apply(&command);
+
+        assert_eq!(vec!["a", "… (+2 args)"], truncated.args());
+        assert!(limits(0, 0).apply(&command).args().iter().all(|arg| arg.len() == 1));
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Uploading finished recordings and their manifests over HTTP, for
+//! archiving sessions centrally without shipping files after the fact.
+
+use crate::options::PluginOptions;
+use crate::ownership::Ownership;
+use crate::recording::{self, FileSink, RecordingSink};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before the second attempt at an upload; each later
+/// attempt waits twice as long as the one before it.
+const INITIAL_BACKOFF : Duration = Duration::from_millis(500);
+
+/// Spools a recording to local disk while the session runs, and
+/// uploads it (and its manifest) with `PUT` requests once the session
+/// ends. The local copies are removed once both are uploaded, and left
+/// in place if either can't be, or if uploading them takes longer than
+/// the session's budget for closing.
+#[derive(Debug)]
+pub struct HttpSink {
+    spool:     FileSink,
+    ownership: Ownership,
+    uploader:  Uploader,
+    budget:    Duration,
+}
+
+impl HttpSink {
+    pub fn create(options: &PluginOptions, ownership: Ownership, name: &str) -> Result<Self> {
+        let url = options.recording_url.clone()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "recording_url isn't set"))?;
+
+        Ok(Self {
+            spool:     FileSink::create(&options.recording_dir, ownership, name)?,
+            ownership,
+            uploader:  Uploader {
+                url,
+                attempts: options.recording_upload_attempts,
+                timeout:  options.recording_upload_timeout,
+                backoff:  INITIAL_BACKOFF,
+            },
+            budget:    options.recording_close_budget,
+        })
+    }
+}
+
+/// Uploads files to a URL, with bounded retries.
+#[derive(Debug)]
+struct Uploader {
+    url:      String,
+    attempts: u32,
+    timeout:  Duration,
+    backoff:  Duration,
+}
+
+impl Uploader {
+    /// Uploads the file at `path` as `name`, retrying failures that
+    /// might not happen again (timeouts, refused connections, and
+    /// server errors).
+    fn upload(&self, path: &Path, name: &str, content_type: &str) -> Result<()> {
+        let url = self.url.replace("%f", name);
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout(self.timeout)
+            .redirects(0)
+            .build();
+
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+
+        loop {
+            let file = File::open(path)?;
+            let len  = file.metadata()?.len();
+
+            // an explicit length keeps the body from being chunked,
+            // which object stores generally don't accept for `PUT`s
+            let result = agent.put(&url)
+                .set("Content-Type",   content_type)
+                .set("Content-Length", &len.to_string())
+                .send(file);
+
+            let (error, retryable) = match result {
+                Ok(_) => return Ok(()),
+
+                Err(ureq::Error::Status(status, _)) => (
+                    Error::new(ErrorKind::InvalidData, format!("upload of {} was rejected with {}", name, status)),
+                    status == 429 || status >= 500,
+                ),
+
+                Err(ureq::Error::Transport(transport)) => (
+                    Error::new(ErrorKind::ConnectionAborted, format!("upload of {} failed: {}", name, transport)),
+                    true,
+                ),
+            };
+
+            if !retryable || attempt >= self.attempts {
+                return Err(error);
+            }
+
+            thread::sleep(backoff);
+
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+impl Write for HttpSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.spool.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.spool.flush()
+    }
+}
+
+impl RecordingSink for HttpSink {
+    fn describe(&self) -> String {
+        // the URL isn't included, since it may carry credentials (e.g.,
+        // a pre-signed signature)
+        format!("{} (to be uploaded)", self.spool.describe())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.spool.sync()
+    }
+
+    fn finish(self: Box<Self>, manifest: &[u8]) -> Result<()> {
+        let Self { spool, ownership, uploader, budget } = *self;
+
+        let recording = spool.path.clone();
+        let json      = recording.with_extension("json");
+        let name      = spool.name.clone();
+
+        Box::new(spool).finish(manifest)?;
+
+        write_manifest(&json, ownership, manifest)?;
+
+        let (done, uploaded) = mpsc::channel();
+        let files            = (recording.clone(), json.clone());
+
+        // like a `QueuedSink`'s thread, this one is abandoned (and
+        // ends with sudo) if it's still uploading once the budget has
+        // run out
+        let _ = thread::Builder::new()
+            .name("upload".into())
+            .spawn(move || {
+                let _ = done.send(upload(&uploader, &files.0, &files.1, &name));
+            })?;
+
+        match recording::within(&uploaded, budget) {
+            Some(result) => result,
+            None         => Err(Error::new(ErrorKind::TimedOut, format!(
+                "uploads didn't finish within {:?}; left {} and {}",
+                budget,
+                recording.display(),
+                json.display(),
+            ))),
+        }
+    }
+
+    fn discard(self: Box<Self>, manifest: &[u8]) -> Result<()> {
+        Box::new(self.spool).discard(manifest)
+    }
+
+    fn pending(&mut self, manifest: &[u8]) -> Result<()> {
+        self.spool.pending(manifest)
+    }
+}
+
+/// Uploads the `recording` named `name` and its manifest (at `json`)
+/// with `uploader`, removing both once they're uploaded.
+fn upload(uploader: &Uploader, recording: &Path, json: &Path, name: &str) -> Result<()> {
+    let uploaded = uploader.upload(recording, &format!("{}.rec", name), "application/octet-stream")
+        .and_then(|_| uploader.upload(json, &format!("{}.json", name), "application/json"));
+
+    if let Err(e) = uploaded {
+        return Err(Error::new(e.kind(), format!(
+            "{}; kept {} and {}",
+            e,
+            recording.display(),
+            json.display(),
+        )));
+    }
+
+    fs::remove_file(recording)?;
+    fs::remove_file(json)
+}
+
+/// Writes `manifest` beside the recording, so it's uploaded (or kept)
+/// along with it, and gives it the same `ownership`.
+fn write_manifest(path: &Path, ownership: Ownership, manifest: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+
+    ownership.apply(&file)?;
+    file.write_all(manifest)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch;
+
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+
+    /// Serves one request per status in `statuses`, reporting the path
+    /// and body of each.
+    fn server(statuses: Vec<u16>) -> (String, mpsc::Receiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url      = format!("http://{}/sessions/%f?sig=secret", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        let _ = thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader  = BufReader::new(stream);
+                let mut line    = String::new();
+
+                let _    = reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap().to_string();
+                let mut len = 0;
+
+                loop {
+                    let mut header = String::new();
+                    let _ = reader.read_line(&mut header).unwrap();
+
+                    if header == "\r\n" {
+                        break;
+                    }
+
+                    if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+
+                let _ = write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status,
+                );
+
+                tx.send((path, body)).unwrap();
+            }
+        });
+
+        (url, rx)
+    }
+
+    fn sink(name: &str, url: String) -> (PathBuf, HttpSink) {
+        let dir  = scratch::dir("upload", name, false);
+        let sink = HttpSink {
+            spool:     FileSink::create(&dir, Ownership::default(), "1-2").unwrap(),
+            ownership: Ownership::default(),
+            uploader:  Uploader {
+                url,
+                attempts: 3,
+                timeout:  Duration::from_secs(5),
+                backoff:  Duration::from_millis(1),
+            },
+            budget:    Duration::from_secs(10),
+        };
+
+        (dir, sink)
+    }
+
+    #[test]
+    fn uploads_recordings_and_manifests() {
+        let (url, requests) = server(vec![503, 200, 200]);
+        let (dir, mut sink) = sink("success", url);
+
+        sink.write_all(b"recording").unwrap();
+        Box::new(sink).finish(b"{}").unwrap();
+
+        // the first attempt failed and was retried
+        assert_eq!(("/sessions/1-2.rec?sig=secret".into(), b"recording".to_vec()), requests.recv().unwrap());
+        assert_eq!(("/sessions/1-2.rec?sig=secret".into(), b"recording".to_vec()), requests.recv().unwrap());
+        assert_eq!(("/sessions/1-2.json?sig=secret".into(), b"{}".to_vec()),       requests.recv().unwrap());
+
+        // nothing is left behind once everything is uploaded
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keeps_recordings_that_cant_be_uploaded() {
+        let (url, requests) = server(vec![403]);
+        let (dir, mut sink) = sink("failure", url);
+
+        sink.write_all(b"recording").unwrap();
+
+        let error = Box::new(sink).finish(b"{}").unwrap_err();
+
+        // client errors aren't retried
+        assert_eq!("/sessions/1-2.rec?sig=secret", requests.recv().unwrap().0);
+        assert!(error.to_string().contains("rejected with 403"));
+
+        assert_eq!(b"recording".to_vec(), fs::read(dir.join("1-2.rec")).unwrap());
+        assert_eq!(b"{}".to_vec(),        fs::read(dir.join("1-2.json")).unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn gives_up_on_slow_uploads() {
+        // connections are accepted by the kernel, but never answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url      = format!("http://{}/sessions/%f", listener.local_addr().unwrap());
+
+        let (dir, mut sink) = sink("slow", url);
+
+        sink.budget = Duration::from_millis(50);
+        sink.write_all(b"recording").unwrap();
+
+        let error = Box::new(sink).finish(b"{}").unwrap_err();
+
+        assert_eq!(ErrorKind::TimedOut, error.kind());
+        assert!(error.to_string().contains("1-2.rec"));
+
+        assert_eq!(b"recording".to_vec(), fs::read(dir.join("1-2.rec")).unwrap());
+        assert_eq!(b"{}".to_vec(),        fs::read(dir.join("1-2.json")).unwrap());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,342 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Approving sessions from a web page, through a remote approval
+//! service, so approvers don't have to be on the host to approve them.
+//!
+//! Each session is given a random one-time token, and `approval_url`
+//! with `%t` replaced by it is printed to the user to pass on. The
+//! session is registered with a `PUT` of the URL, whose body is a JSON
+//! object describing it (see `Session`), including when the URL
+//! expires. The service answers each `GET` of the URL with a body whose
+//! first line is `pending`, or `approved` or `declined` followed by the
+//! approver's name, and whose remaining lines are any comment the
+//! approver left; a `404` or `410` means it no longer knows of the
+//! session. Once the session has been decided (or given up on), it's
+//! withdrawn with a `DELETE`, so the URL can't be used again.
+
+use crate::sshsig;
+
+use sudo_pair_types::json::quote;
+use sudo_pair_types::manifest::utc;
+
+use std::time::{Duration, SystemTime};
+
+/// How long each request to the approval service may take.
+const REQUEST_TIMEOUT : Duration = Duration::from_secs(10);
+
+/// What the approval service is told about a session awaiting approval.
+#[derive(Debug)]
+pub(crate) struct Session<'a> {
+    pub(crate) session_id: &'a str,
+    pub(crate) host:       &'a str,
+    pub(crate) user:       &'a str,
+    pub(crate) runas_user: &'a str,
+    pub(crate) cwd:        &'a str,
+    pub(crate) argv:       &'a [String],
+    pub(crate) executable: &'a str,
+    pub(crate) ticket:     Option<&'a str>,
+
+    /// the ID grouping the session with the others of the same change,
+    /// if it has one
+    pub(crate) correlation_id: Option<&'a str>,
+
+    /// when the URL stops being accepted
+    pub(crate) expires_at: SystemTime,
+}
+
+impl Session<'_> {
+    /// Serializes the session as a single line of JSON.
+    fn to_json(&self) -> String {
+        let argv : Vec<_> = self.argv.iter().map(|arg| quote(arg)).collect();
+
+        let mut fields = vec![
+            format!("{}:{}", quote("session_id"), quote(self.session_id)),
+            format!("{}:{}", quote("host"),       quote(self.host)),
+            format!("{}:{}", quote("user"),       quote(self.user)),
+            format!("{}:{}", quote("runas_user"), quote(self.runas_user)),
+            format!("{}:{}", quote("cwd"),        quote(self.cwd)),
+            format!("{}:[{}]", quote("argv"),     argv.join(",")),
+            format!("{}:{}", quote("executable"), quote(self.executable)),
+        ];
+
+        if let Some(ticket) = self.ticket {
+            fields.push(format!("{}:{}", quote("ticket"), quote(ticket)));
+        }
+
+        if let Some(id) = self.correlation_id {
+            fields.push(format!("{}:{}", quote("correlation_id"), quote(id)));
+        }
+
+        fields.push(format!("{}:{}", quote("expires_at"), quote(&utc(self.expires_at))));
+
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// What the approval service says about a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Decision {
+    /// nobody has approved or declined it yet
+    Pending,
+
+    /// an approver approved (or declined) it, with an optional comment
+    Approved { approver: String, comment: Option<String> },
+    Declined { approver: String, comment: Option<String> },
+
+    /// the service no longer knows of it (e.g., its URL expired)
+    Expired,
+}
+
+/// A session registered with the approval service.
+#[derive(Debug)]
+pub(crate) struct ApprovalUrl {
+    url:   String,
+    agent: ureq::Agent,
+}
+
+impl ApprovalUrl {
+    /// Registers `session` with the approval service, at `template`
+    /// with `%t` replaced by a new one-time token.
+    pub(crate) fn register(template: &str, session: &Session<'_>) -> Result<Self, String> {
+        let token = sshsig::challenge()
+            .map_err(|e| format!("couldn't generate an approval token: {}", e))?;
+
+        let url   = template.replace("%t", &token);
+        let agent = ureq::AgentBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .redirects(0)
+            .build();
+
+        match agent.put(&url).set("Content-Type", "application/json").send_string(&session.to_json()) {
+            Ok(_) => Ok(Self { url, agent }),
+
+            Err(ureq::Error::Status(status, _)) => Err(format!(
+                "the approval service rejected the session ({})",
+                status,
+            )),
+
+            Err(ureq::Error::Transport(transport)) => Err(format!(
+                "the approval service couldn't be reached: {}",
+                transport,
+            )),
+        }
+    }
+
+    /// The URL the session can be approved at.
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Asks the approval service whether the session has been decided.
+    pub(crate) fn poll(&self) -> Result<Decision, String> {
+        let response = match self.agent.get(&self.url).call() {
+            Ok(response) => response,
+
+            Err(ureq::Error::Status(404 | 410, _)) => return Ok(Decision::Expired),
+
+            Err(ureq::Error::Status(status, _)) => return Err(format!(
+                "the approval service failed ({})",
+                status,
+            )),
+
+            Err(ureq::Error::Transport(transport)) => return Err(format!(
+                "the approval service couldn't be reached: {}",
+                transport,
+            )),
+        };
+
+        let body = response.into_string()
+            .map_err(|e| format!("the approval service's answer couldn't be read: {}", e))?;
+
+        parse(&body).ok_or_else(|| format!(
+            "the approval service's answer wasn't understood: {:?}",
+            body.lines().next().unwrap_or_default(),
+        ))
+    }
+
+    /// Withdraws the session from the approval service, so its URL
+    /// can't be used again. Failures are ignored, since the URL expires
+    /// regardless.
+    pub(crate) fn withdraw(self) {
+        let _ = self.agent.delete(&self.url).call();
+    }
+}
+
+/// Parses the approval service's answer to a `GET`.
+fn parse(body: &str) -> Option<Decision> {
+    let (first, rest) = body.split_once('\n').unwrap_or((body, ""));
+
+    let mut words = first.trim().splitn(2, ' ');
+
+    let decision = words.next()?;
+    let approver = words.next().map(str::trim).filter(|approver| !approver.is_empty());
+    let comment  = Some(rest.trim()).filter(|comment| !comment.is_empty()).map(String::from);
+
+    match (decision, approver) {
+        ("pending",  None)           => Some(Decision::Pending),
+        ("approved", Some(approver)) => Some(Decision::Approved { approver: approver.into(), comment }),
+        ("declined", Some(approver)) => Some(Decision::Declined { approver: approver.into(), comment }),
+        _                            => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+
+    /// Serves one request per `(status, body)` in `answers`, reporting
+    /// the method, path, and body of each.
+    fn server(answers: Vec<(u16, &'static str)>) -> (String, mpsc::Receiver<(String, String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url      = format!("http://{}/sessions/%t", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        let _ = thread::spawn(move || {
+            for (status, answer) in answers {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader  = BufReader::new(stream);
+                let mut line    = String::new();
+
+                let _ = reader.read_line(&mut line).unwrap();
+
+                let mut parts = line.split(' ');
+                let method    = parts.next().unwrap().to_string();
+                let path      = parts.next().unwrap().to_string();
+                let mut len   = 0;
+
+                loop {
+                    let mut header = String::new();
+                    let _ = reader.read_line(&mut header).unwrap();
+
+                    if header == "\r\n" {
+                        break;
+                    }
+
+                    if let Some(value) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+
+                let _ = write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    answer.len(),
+                    answer,
+                );
+
+                tx.send((method, path, String::from_utf8(body).unwrap())).unwrap();
+            }
+        });
+
+        (url, rx)
+    }
+
+    fn session(argv: &[String]) -> Session<'_> {
+        Session {
+            session_id: "1600000000-31337",
+            host:       "db01",
+            user:       "alice",
+            runas_user: "postgres",
+            cwd:        "/home/alice",
+            argv,
+            executable: "/usr/bin/psql",
+            ticket:     Some("INC-1234"),
+            correlation_id: Some("CHG-42"),
+            expires_at: UNIX_EPOCH + Duration::from_secs(1_600_000_600),
+        }
+    }
+
+    #[test]
+    fn describes_sessions() {
+        let argv = vec!["psql".into(), "-c".into(), "select 1".into()];
+
+        assert_eq!(
+            "{\"session_id\":\"1600000000-31337\",\"host\":\"db01\",\"user\":\"alice\",\
+             \"runas_user\":\"postgres\",\"cwd\":\"/home/alice\",\"argv\":[\"psql\",\"-c\",\"select 1\"],\
+             \"executable\":\"/usr/bin/psql\",\"ticket\":\"INC-1234\",\"correlation_id\":\"CHG-42\",\"expires_at\":\"2020-09-13T12:36:40Z\"}",
+            session(&argv).to_json(),
+        );
+    }
+
+    #[test]
+    fn parses_decisions() {
+        assert_eq!(Some(Decision::Pending), parse("pending\n"));
+        assert_eq!(
+            Some(Decision::Approved { approver: "bob".into(), comment: None }),
+            parse("approved bob"),
+        );
+        assert_eq!(
+            Some(Decision::Declined { approver: "bob".into(), comment: Some("not during the freeze".into()) }),
+            parse("declined bob\nnot during the freeze\n"),
+        );
+
+        // decisions have to say who made them
+        assert_eq!(None, parse("approved\n"));
+        assert_eq!(None, parse("<html>"));
+    }
+
+    #[test]
+    fn registers_and_polls_sessions() {
+        let (template, requests) = server(vec![
+            (201, ""),
+            (200, "pending\n"),
+            (200, "approved bob\nlooks fine\n"),
+            (204, ""),
+        ]);
+
+        let argv     = vec!["psql".into()];
+        let approval = ApprovalUrl::register(&template, &session(&argv)).unwrap();
+
+        let (method, path, body) = requests.recv().unwrap();
+        let token                = path.trim_start_matches("/sessions/");
+
+        assert_eq!("PUT", method);
+        assert_eq!(64, token.len());
+        assert!(approval.url().ends_with(&path));
+        assert!(body.starts_with("{\"session_id\":\"1600000000-31337\""));
+
+        assert_eq!(Ok(Decision::Pending), approval.poll());
+        assert_eq!(
+            Ok(Decision::Approved { approver: "bob".into(), comment: Some("looks fine".into()) }),
+            approval.poll(),
+        );
+
+        approval.withdraw();
+
+        assert_eq!("GET",    requests.recv().unwrap().0);
+        assert_eq!("GET",    requests.recv().unwrap().0);
+        assert_eq!("DELETE", requests.recv().unwrap().0);
+    }
+
+    #[test]
+    fn expires_sessions_the_service_forgot() {
+        let (template, _requests) = server(vec![(201, ""), (410, "")]);
+
+        let argv     = Vec::new();
+        let approval = ApprovalUrl::register(&template, &session(&argv)).unwrap();
+
+        assert_eq!(Ok(Decision::Expired), approval.poll());
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Encodings of what's sent to the approver watching a session.
+//!
+//! By default (the `text` encoding), the session's output is sent as-is
+//! and messages are sent in-band as `OSC` escape sequences, as
+//! described in `protocol`. A client may instead ask, through the
+//! `encoding` field of its `hello`, for everything it's sent while
+//! watching the session to be framed: as `json`, which is easy to
+//! debug, as `msgpack` or `cbor`, which are compact, or as `binary`,
+//! which sends output with two bytes of overhead and nothing to decode.
+//!
+//! Once the approver is watching a framed session, the plugin sends
+//! one last `OSC` message, `framing;encoding=<encoding>`, after which
+//! everything it sends is a frame, encoded as described in
+//! `sudo_pair_types::frame` (which the client decodes them with).
+//!
+//! Frames are held to the `Limits` negotiated with the client: output
+//! and notices too long for a single frame are split across as many
+//! as it takes (which the client can't tell from having been sent them
+//! in pieces to begin with), and reasons and chat messages are cut
+//! short.
+
+use crate::protocol;
+use crate::transcript::Checkpoint;
+
+use sudo_pair_types::frame::{self, Message};
+use sudo_pair_types::protocol::{message, truncate, Encoding, Limits};
+
+use std::iter;
+
+/// The message telling the client that everything after it is framed
+/// in `encoding`, or nothing if it isn't framed.
+pub fn framing(encoding: Encoding) -> Vec<u8> {
+    if encoding == Encoding::Text {
+        return Vec::new();
+    }
+
+    message(&format!("framing;encoding={}", encoding.name()))
+}
+
+/// Encodes `message` in `encoding`.
+pub fn encode(encoding: Encoding, message: &Message<'_>) -> Vec<u8> {
+    frame::encode(encoding, message).unwrap_or_else(|| text(message))
+}
+
+/// Encodes `message` in `encoding`, held to the negotiated `limits`.
+pub fn encode_within(encoding: Encoding, limits: Limits, message: &Message<'_>) -> Vec<u8> {
+    if let Message::Suppressed { reason, bytes } = *message {
+        let reason = truncate(reason, limits.reason);
+
+        return encode(encoding, &Message::Suppressed { reason: &reason, bytes });
+    }
+
+    if let Message::Chat(text) = *message {
+        return encode(encoding, &Message::Chat(&truncate(text, limits.chat)));
+    }
+
+    let encoded = encode(encoding, message);
+
+    // the text encoding isn't framed, and its messages are all far
+    // shorter than any client accepts
+    if encoding == Encoding::Text || encoded.len() - 4 <= limits.frame {
+        return encoded;
+    }
+
+    // what the frame can hold besides the message's other fields, less
+    // a little for the length of the data itself, which grows with it
+    let empty = match *message {
+        Message::Notice(_) => Message::Notice(""),
+        _                  => Message::Output(&[]),
+    };
+
+    let room = limits.frame.saturating_sub(encode(encoding, &empty).len() - 4 + 8);
+
+    match *message {
+        // base64 makes output a third longer in JSON
+        Message::Output(data) => {
+            let size = if encoding == Encoding::Json { room / 4 * 3 } else { room };
+
+            data.chunks(size.max(1))
+                .flat_map(|chunk| encode(encoding, &Message::Output(chunk)))
+                .collect()
+        },
+
+        // an escaped control character is six times as long in JSON
+        Message::Notice(text) => {
+            let size = if encoding == Encoding::Json { room / 6 } else { room };
+
+            split(text, size)
+                .flat_map(|piece| encode(encoding, &Message::Notice(piece)))
+                .collect()
+        },
+
+        // no other message comes close to the smallest frame allowed
+        _ => encoded,
+    }
+}
+
+/// Splits `text` into pieces of at most `size` bytes, without splitting
+/// any character (so a piece is longer than `size` only if it's a
+/// single character that is).
+fn split(mut text: &str, size: usize) -> impl Iterator<Item = &str> {
+    iter::from_fn(move || {
+        if text.is_empty() {
+            return None;
+        }
+
+        let mut end = size.min(text.len());
+
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        if end == 0 {
+            end = text.chars().next().map_or(text.len(), char::len_utf8);
+        }
+
+        let (piece, rest) = text.split_at(end);
+
+        text = rest;
+
+        Some(piece)
+    })
+}
+
+/// `message` as it's sent in the `text` encoding: output and notices
+/// as they are, and everything else as an `OSC` message.
+fn text(message: &Message<'_>) -> Vec<u8> {
+    match *message {
+        Message::Output(data)                 => data.to_vec(),
+        Message::Stream(stream)               => protocol::stream(stream.name()),
+        Message::Suppressed { reason, bytes } => protocol::suppressed(reason, bytes),
+        Message::Winsize { rows, cols }       => protocol::winsize(rows, cols),
+        Message::Reapprove { deadline }       => protocol::reapprove(deadline),
+        Message::Notice(text)                 => text.as_bytes().to_vec(),
+        Message::Chat(text)                   => protocol::chat(text),
+
+        Message::Checkpoint { bytes, sha256 } => {
+            Checkpoint { bytes, digest: sha256.into() }.to_escape_sequence()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sudo_pair_types::protocol::Stream;
+
+    #[test]
+    fn sends_text_as_it_always_was() {
+        let checkpoint = Checkpoint { bytes: 300, digest: "abcd".into() };
+
+        assert_eq!(b"ls\n".to_vec(),                encode(Encoding::Text, &Message::Output(b"ls\n")));
+        assert_eq!(protocol::winsize(24, 80),       encode(Encoding::Text, &Message::Winsize { rows: 24, cols: 80 }));
+        assert_eq!(b"handed off\n".to_vec(),        encode(Encoding::Text, &Message::Notice("handed off\n")));
+        assert_eq!(protocol::stream("stdout"),      encode(Encoding::Text, &Message::Stream(Stream::StdOut)));
+        assert_eq!(protocol::chat("one sec"),       encode(Encoding::Text, &Message::Chat("one sec")));
+
+        assert_eq!(
+            checkpoint.to_escape_sequence(),
+            encode(Encoding::Text, &Message::Checkpoint { bytes: 300, sha256: "abcd" }),
+        );
+
+        assert_eq!(
+            protocol::suppressed("escapes", 12),
+            encode(Encoding::Text, &Message::Suppressed { reason: "escapes", bytes: 12 }),
+        );
+
+        assert!(framing(Encoding::Text).is_empty());
+    }
+
+    #[test]
+    fn announces_framing() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;framing;encoding=cbor\x07".to_vec(),
+            framing(Encoding::Cbor),
+        );
+
+        for encoding in &Encoding::FRAMED {
+            assert_eq!(Some(*encoding), Encoding::from_name(encoding.name()));
+        }
+
+        assert_eq!(None, Encoding::from_name("yaml"));
+    }
+
+    #[test]
+    fn frames_everything_else() {
+        for encoding in &Encoding::FRAMED {
+            assert_eq!(
+                frame::encode(*encoding, &Message::Output(b"ls")),
+                Some(encode(*encoding, &Message::Output(b"ls"))),
+            );
+        }
+    }
+
+    #[test]
+    fn splits_output_across_frames() {
+        let limits = Limits { frame: Limits::MINIMUM.frame, ..Limits::DEFAULT };
+        let output : Vec<u8> = (0 .. 20_000).map(|i| i as u8).collect();
+
+        for encoding in &Encoding::FRAMED {
+            let encoded = encode_within(*encoding, limits, &Message::Output(&output));
+            let frames  = frames(&encoded);
+
+            assert!(frames.len() > 1);
+            assert!(frames.iter().all(|frame| frame.len() <= limits.frame), "{:?}", encoding);
+        }
+
+        let binary : Vec<u8> = frames(&encode_within(Encoding::Binary, limits, &Message::Output(&output)))
+            .into_iter()
+            .flat_map(|frame| frame[2..].to_vec())
+            .collect();
+
+        assert_eq!(output, binary);
+
+        // output that fits is sent as it always was
+        assert_eq!(
+            encode(Encoding::Cbor, &Message::Output(b"ls")),
+            encode_within(Encoding::Cbor, limits, &Message::Output(b"ls")),
+        );
+
+        // and text isn't framed at all
+        assert_eq!(output, encode_within(Encoding::Text, limits, &Message::Output(&output)));
+    }
+
+    #[test]
+    fn splits_notices_between_characters() {
+        assert_eq!(0, split("", 4).count());
+
+        assert_eq!(vec!["ab", "c"],               split("abc", 2).collect::<Vec<_>>());
+        assert_eq!(vec!["\u{5c71}", "\u{5c71}"], split("\u{5c71}\u{5c71}", 4).collect::<Vec<_>>());
+
+        // a character longer than a piece is sent on its own
+        assert_eq!(vec!["\u{5c71}", "x"], split("\u{5c71}x", 2).collect::<Vec<_>>());
+
+        let limits = Limits { frame: Limits::MINIMUM.frame, ..Limits::DEFAULT };
+        let notice = "\x01".repeat(10_000);
+
+        let encoded = encode_within(Encoding::Json, limits, &Message::Notice(&notice));
+
+        assert!(frames(&encoded).iter().all(|frame| frame.len() <= limits.frame));
+    }
+
+    #[test]
+    fn cuts_reasons_and_chats_short() {
+        let limits = Limits { reason: 16, ..Limits::DEFAULT };
+        let reason = "x".repeat(100);
+
+        assert_eq!(
+            encode(Encoding::Binary, &Message::Suppressed { reason: "xxxxxxxxxxxxxxx\u{2026}", bytes: 1 }),
+            encode_within(Encoding::Binary, limits, &Message::Suppressed { reason: &reason, bytes: 1 }),
+        );
+
+        let limits = Limits { chat: 4, ..Limits::DEFAULT };
+
+        assert_eq!(
+            encode(Encoding::Json, &Message::Chat("wai\u{2026}")),
+            encode_within(Encoding::Json, limits, &Message::Chat("wait for it")),
+        );
+    }
+
+    /// The frames in `encoded`, without their lengths.
+    fn frames(mut encoded: &[u8]) -> Vec<&[u8]> {
+        let mut frames = Vec::new();
+
+        while encoded.len() >= 4 {
+            let len   = u32::from_be_bytes([encoded[0], encoded[1], encoded[2], encoded[3]]) as usize;
+            let frame = &encoded[4 .. 4 + len];
+
+            frames.push(frame);
+            encoded = &encoded[4 + len ..];
+        }
+
+        frames
+    }
+}
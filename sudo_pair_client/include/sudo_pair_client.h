@@ -0,0 +1,160 @@
+/*
+ * Copyright 2018 Square Inc.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+ * implied. See the License for the specific language governing
+ * permissions and limitations under the License.
+ */
+
+/*
+ * Client library for approving sudo_pair sessions.
+ *
+ * Link against libsudo_pair_client (built from the sudo_pair_client
+ * crate). A minimal approval client looks like:
+ *
+ *     struct sudo_pair_event event;
+ *     sudo_pair_client *client = sudo_pair_client_connect(path, 0);
+ *
+ *     while (sudo_pair_client_next_event(client, &event) == 1) {
+ *         if (event.kind == SUDO_PAIR_EVENT_OUTPUT)
+ *             fwrite(event.data, 1, event.len, stdout);
+ *
+ *         ... call sudo_pair_client_respond() once the approver decides
+ *     }
+ *
+ *     sudo_pair_client_free(client);
+ */
+
+#ifndef SUDO_PAIR_CLIENT_H
+#define SUDO_PAIR_CLIENT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* capabilities, combined as a bitmap */
+#define SUDO_PAIR_CAP_KILL       0x01
+#define SUDO_PAIR_CAP_PAUSE      0x02
+#define SUDO_PAIR_CAP_OBSERVERS  0x04
+#define SUDO_PAIR_CAP_TIMESTAMPS 0x08
+#define SUDO_PAIR_CAP_DIGESTS    0x10
+#define SUDO_PAIR_CAP_WINSIZE    0x20
+#define SUDO_PAIR_CAP_REAPPROVAL 0x40
+#define SUDO_PAIR_CAP_COMMENTS   0x80
+#define SUDO_PAIR_CAP_STREAMS    0x100
+#define SUDO_PAIR_CAP_MARKERS    0x200
+#define SUDO_PAIR_CAP_CHAT       0x400
+
+/* values of `sudo_pair_event.kind` */
+#define SUDO_PAIR_EVENT_OUTPUT        1 /* output to display to the approver */
+#define SUDO_PAIR_EVENT_HELLO         2 /* the plugin's version and capabilities */
+#define SUDO_PAIR_EVENT_CHECKPOINT    3 /* a digest of the transcript so far */
+#define SUDO_PAIR_EVENT_UNKNOWN       4 /* a message this library doesn't know */
+#define SUDO_PAIR_EVENT_WINSIZE       5 /* the size of the user's terminal */
+#define SUDO_PAIR_EVENT_REAPPROVE     6 /* a request to re-approve the session */
+#define SUDO_PAIR_EVENT_AUTHENTICATE  7 /* a challenge to sign with an SSH key */
+#define SUDO_PAIR_EVENT_ONE_TIME_CODE 8 /* a request for the approver's one-time code */
+#define SUDO_PAIR_EVENT_STREAM        9 /* the stream the output that follows comes from */
+#define SUDO_PAIR_EVENT_SUPPRESSED   10 /* output kept from the approver, and why */
+#define SUDO_PAIR_EVENT_TAGS         11 /* the host's tags, as comma-separated key=value pairs */
+#define SUDO_PAIR_EVENT_PREVIEW      12 /* the session being approved, as key=value lines */
+#define SUDO_PAIR_EVENT_CHAT         13 /* the user's reply to a message from the approver */
+
+typedef struct sudo_pair_client sudo_pair_client;
+
+/*
+ * An event received from the plugin. Which fields are meaningful depends
+ * on `kind`. `data` is not NUL-terminated, and remains valid only until
+ * the next call on the same client.
+ */
+struct sudo_pair_event {
+    int            kind;
+    const uint8_t *data;         /* output, stream name, suppression reason, host tags, preview, hex-encoded digest, challenge, reply, or payload */
+    size_t         len;
+    uint16_t       version;      /* SUDO_PAIR_EVENT_HELLO */
+    uint32_t       capabilities; /* SUDO_PAIR_EVENT_HELLO */
+    uint64_t       bytes;        /* SUDO_PAIR_EVENT_CHECKPOINT or _SUPPRESSED */
+    uint32_t       rows;         /* SUDO_PAIR_EVENT_WINSIZE */
+    uint32_t       cols;         /* SUDO_PAIR_EVENT_WINSIZE */
+    uint64_t       deadline;     /* SUDO_PAIR_EVENT_HELLO or _REAPPROVE; seconds since the epoch, or 0 */
+};
+
+/*
+ * Connects to the session socket at `path`, announcing `capabilities`.
+ * Returns NULL on failure.
+ */
+sudo_pair_client *sudo_pair_client_connect(const char *path, uint32_t capabilities);
+
+/*
+ * Blocks until the next event arrives. Returns 1 if `event` was filled
+ * in, 0 once the session has ended, and -1 on error.
+ */
+int sudo_pair_client_next_event(sudo_pair_client *client, struct sudo_pair_event *event);
+
+/*
+ * Approves the session (or re-approves it, in answer to
+ * SUDO_PAIR_EVENT_REAPPROVE) if `approve` is nonzero, and declines it
+ * otherwise. Returns 0 on success and -1 on error.
+ */
+int sudo_pair_client_respond(sudo_pair_client *client, int approve);
+
+/*
+ * Approves or declines the session like sudo_pair_client_respond, with
+ * a NUL-terminated `comment` explaining why that's shown to the user.
+ * The comment is left out unless SUDO_PAIR_CAP_COMMENTS was negotiated,
+ * and when answering SUDO_PAIR_EVENT_REAPPROVE. Returns 0 on success
+ * and -1 on error.
+ */
+int sudo_pair_client_respond_with_comment(sudo_pair_client *client, int approve, const char *comment);
+
+/*
+ * Answers SUDO_PAIR_EVENT_AUTHENTICATE with the `len`-byte binary SSHSIG
+ * signature of its challenge (in the `sudo_pair` namespace), which must
+ * be sent before responding. Returns 0 on success and -1 on error.
+ */
+int sudo_pair_client_authenticate(sudo_pair_client *client, const uint8_t *sshsig, size_t len);
+
+/*
+ * Answers SUDO_PAIR_EVENT_ONE_TIME_CODE with the approver's current
+ * one-time `code` and the name they're provisioned under (`approver`),
+ * both NUL-terminated, which must be sent before responding. Returns 0
+ * on success and -1 on error.
+ */
+int sudo_pair_client_send_one_time_code(sudo_pair_client *client, const char *approver, const char *code);
+
+/*
+ * Sends the user the NUL-terminated message `text` from the approver,
+ * shown on their terminal, asking them to reply (as a
+ * SUDO_PAIR_EVENT_CHAT) if `ask` is nonzero. Messages can only be sent
+ * once the approver has responded, and only if SUDO_PAIR_CAP_CHAT was
+ * negotiated. Returns 0 on success and -1 on error.
+ */
+int sudo_pair_client_chat(sudo_pair_client *client, const char *text, int ask);
+
+/*
+ * Ends the session. Returns 0 on success and -1 on error. The client
+ * must still be freed.
+ */
+int sudo_pair_client_close(sudo_pair_client *client);
+
+/*
+ * Frees the client, closing its connection if necessary. Accepts NULL.
+ */
+void sudo_pair_client_free(sudo_pair_client *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SUDO_PAIR_CLIENT_H */
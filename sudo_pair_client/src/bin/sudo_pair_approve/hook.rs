@@ -0,0 +1,227 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The approver's `--hook`: a program run on each session as soon as the
+//! plugin previews it, which can approve or decline the session itself
+//! (e.g., by local policy) or leave it to the approver. It's given the
+//! session as a JSON object on its stdin:
+//!
+//! ```text
+//! {"title":"session of uid 1000 (pid 4242)","user":"alice","host":"db01","runas_user":"postgres","runas_group":"","cwd":"/home/alice","command":"psql","warnings":[],"env":[],"tags":{"team":"payments"}}
+//! ```
+//!
+//! and decides by how it exits: 0 approves the session, 1 declines it,
+//! and anything else leaves it to the approver, as does a hook that
+//! can't be run or doesn't exit within `TIMEOUT`. The first line it
+//! writes to stdout, if any, is sent along with its decision as the
+//! approver's comment.
+//!
+//! Sessions whose plugin doesn't send a preview are always left to the
+//! approver.
+
+use sudo_pair_client::Preview;
+use sudo_pair_types::json::quote;
+
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the hook has to decide before the session is left to the
+/// approver.
+const TIMEOUT : Duration = Duration::from_secs(10);
+
+/// How often to check whether the hook has exited.
+const POLL : Duration = Duration::from_millis(20);
+
+/// The longest comment taken from the hook; the rest is cut off.
+const MAX_COMMENT_LEN : usize = 256;
+
+/// What the hook decided.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Decision {
+    pub(crate) approve: bool,
+
+    /// why, as the hook explained it, or empty if it didn't
+    pub(crate) comment: String,
+}
+
+/// Runs the `hook` on the session called `title`, which the plugin has
+/// described with `preview` and `tags`. Returns `None` if the hook left
+/// the session to the approver.
+pub(crate) fn decide(hook: &Path, title: &str, preview: &Preview, tags: &[(String, String)]) -> Result<Option<Decision>, String> {
+    let mut child = Command::new(hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run the hook {}: {}", hook.display(), e))?;
+
+    // a hook that doesn't read its input is still free to decide
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(to_json(title, preview, tags).as_bytes());
+    }
+
+    let deadline = Instant::now() + TIMEOUT;
+
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(format!("the hook {} didn't decide within {:?}", hook.display(), TIMEOUT));
+        }
+
+        thread::sleep(POLL);
+    };
+
+    let approve = match status.code() {
+        Some(0) => true,
+        Some(1) => false,
+        _       => return Ok(None),
+    };
+
+    let mut output = String::new();
+
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_string(&mut output);
+    }
+
+    Ok(Some(Decision { approve, comment: comment(&output) }))
+}
+
+/// The comment taken from what the hook wrote: its first line, cut off
+/// at `MAX_COMMENT_LEN` bytes.
+fn comment(output: &str) -> String {
+    let mut comment = output.lines().next().unwrap_or_default().trim().to_string();
+
+    if comment.len() > MAX_COMMENT_LEN {
+        let mut end = MAX_COMMENT_LEN;
+
+        while !comment.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        comment.truncate(end);
+    }
+
+    comment
+}
+
+/// The session, as it's given to the hook.
+fn to_json(title: &str, preview: &Preview, tags: &[(String, String)]) -> String {
+    let list = |values: &[String]| values.iter()
+        .map(|value| quote(value))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let tags = tags.iter()
+        .map(|(key, value)| format!("{}:{}", quote(key), quote(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(
+        "{{\"title\":{},\"user\":{},\"host\":{},\"runas_user\":{},\"runas_group\":{},\"cwd\":{},\"command\":{},\"warnings\":[{}],\"env\":[{}],\"tags\":{{{}}}}}",
+        quote(title),
+        quote(&preview.user),
+        quote(&preview.host),
+        quote(&preview.runas_user),
+        quote(&preview.runas_group),
+        quote(&preview.cwd),
+        quote(&preview.command),
+        list(&preview.warnings),
+        list(&preview.env),
+        tags,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    fn preview() -> Preview {
+        Preview {
+            user:       "alice".into(),
+            host:       "db01".into(),
+            runas_user: "postgres".into(),
+            cwd:        "/home/alice".into(),
+            command:    "psql -c \"select 1\"".into(),
+            warnings:   vec!["the command is a shell".into()],
+            ..Preview::default()
+        }
+    }
+
+    /// A hook that runs `script` with `sh`.
+    fn hook(dir: &Path, name: &str, script: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o700)).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn describes_sessions() {
+        assert_eq!(
+            "{\"title\":\"session\",\"user\":\"alice\",\"host\":\"db01\",\"runas_user\":\"postgres\",\
+             \"runas_group\":\"\",\"cwd\":\"/home/alice\",\"command\":\"psql -c \\\"select 1\\\"\",\
+             \"warnings\":[\"the command is a shell\"],\"env\":[],\"tags\":{\"team\":\"payments\"}}",
+            to_json("session", &preview(), &[("team".into(), "payments".into())]),
+        );
+    }
+
+    #[test]
+    fn decides_by_exit_status() {
+        let dir = env::temp_dir().join(format!("sudo_pair_approve.hook.{}", process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let approve = hook(&dir, "approve", "grep -q '\"user\":\"alice\"' && echo 'on call for db01' && exit 0; exit 2");
+        let decline = hook(&dir, "decline", "cat >/dev/null; exit 1");
+        let ask     = hook(&dir, "ask",     "exit 3");
+
+        assert_eq!(
+            Ok(Some(Decision { approve: true, comment: "on call for db01".into() })),
+            decide(&approve, "session", &preview(), &[]),
+        );
+
+        assert_eq!(
+            Ok(Some(Decision { approve: false, comment: String::new() })),
+            decide(&decline, "session", &preview(), &[]),
+        );
+
+        assert_eq!(Ok(None), decide(&ask, "session", &preview(), &[]));
+        assert!(decide(&dir.join("missing"), "session", &preview(), &[]).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn takes_the_first_line_as_the_comment() {
+        assert_eq!("looks fine", comment("  looks fine \nmore\n"));
+        assert_eq!("",           comment(""));
+        assert_eq!(MAX_COMMENT_LEN - 1, comment(&format!("{}é", "x".repeat(MAX_COMMENT_LEN - 1))).len());
+    }
+}
@@ -0,0 +1,787 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Approves a `sudo_pair` session, like the sample `sudo_approve`
+//! script: the session's output is passed to the terminal, and the
+//! approver's keystrokes to the session. Unlike the script, it answers
+//! the plugin's challenges with the approver's SSH key (see
+//! `approver_keys`), and with `--via`, it approves sessions on other
+//! hosts by forwarding their sockets over SSH, so approvers don't have
+//! to log into the host (and find the socket) themselves. With
+//! `--control`, it runs as an approver daemon instead, showing each
+//! session announced on its control socket in turn (see the plugin's
+//! `control_socket`). With `--hook`, a program of the approver's can
+//! approve or decline sessions before they're shown (see `hook`).
+//!
+//! On a terminal, the session is shown in a full-screen view (see
+//! `tui`) with its details pinned above its output, unless the approver
+//! asks for it to be passed through as-is with `--plain`.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+mod hook;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+use sudo_pair_client::{protocol, Capabilities, Client, ControlSocket, Encoding, Event, Preview, Session, DEFAULT_SOCKET_DIR};
+
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{pid_t, uid_t};
+
+/// The namespace the plugin has approvers sign its challenges in.
+/// Challenges in any other are refused, so a host can't use the client
+/// to get the approver's signature for something else.
+const NAMESPACE : &str = "sudo_pair";
+
+/// The keystroke that ends the session (Ctrl-D), as it does for the
+/// sample approval script.
+const END_OF_SESSION : u8 = 0x04;
+
+/// What the approver is told when the plugin sends a challenge but they
+/// didn't give a key to sign it with.
+const NO_KEY : &str = "the session requires approvers to sign a challenge, but no --key was given";
+
+/// How long to wait for SSH to forward the session's socket, which
+/// includes however long the approver takes to authenticate to the host.
+const FORWARD_TIMEOUT : Duration = Duration::from_secs(60);
+
+/// How often to check whether SSH has forwarded the session's socket.
+const FORWARD_POLL : Duration = Duration::from_millis(100);
+
+const USAGE : &str = "\
+usage: sudo_pair_approve [options] UID PID
+       sudo_pair_approve [options] --control PATH
+
+Connects to the sudo session of the user UID whose sudo is PID and shows it
+below the session's details: y approves it, n declines it, and Ctrl-D ends
+it (press ? for the other keys).
+
+With --control, listens on the control socket at PATH (the plugin's
+control_socket) instead, and shows each session announced on it in turn.
+
+options:
+    --socket-dir DIR  find the session's socket in DIR [/var/run/sudo_pair]
+    --via URL         approve a session on another host, whose socket is
+                      forwarded over SSH; URL is ssh://[USER@]HOST[:PORT]
+    --key PATH        answer challenges from the plugin by signing them with
+                      the SSH key at PATH (or held by ssh-agent, if PATH is
+                      its public key); required with --via
+    --control PATH    act as an approver daemon listening on PATH
+    --hook PATH       run PATH on each session as it's previewed, with the
+                      session as JSON on its stdin; exiting 0 approves the
+                      session, 1 declines it, and anything else leaves it
+                      to you
+    --plain           pass the session's output to this terminal as-is, and
+                      keystrokes to the session, as the sample approval
+                      script does (and as is done when this isn't a
+                      terminal); anything but y declines the session
+    -h, --help        print this message
+";
+
+/// What the approver asked for.
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+    socket_dir: PathBuf,
+    via:        Option<Target>,
+    key:        Option<PathBuf>,
+    hook:       Option<PathBuf>,
+    plain:      bool,
+
+    /// the session to approve, as its user's uid and its `sudo`'s pid,
+    /// or the control socket to receive sessions on
+    session: Source,
+}
+
+/// Where the session (or sessions) to approve come from.
+#[derive(Debug, PartialEq, Eq)]
+enum Source {
+    /// the one session of `uid` whose `sudo` is `pid`
+    Session { uid: uid_t, pid: pid_t },
+
+    /// every session announced on the control socket at the path
+    Control(PathBuf),
+}
+
+/// A host to reach over SSH, as given by an `ssh://` URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Target {
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+}
+
+impl Target {
+    /// Parses an `ssh://[USER@]HOST[:PORT]` URL, where `HOST` may be an
+    /// IPv6 address in brackets.
+    fn parse(url: &str) -> Result<Self, String> {
+        let invalid = || format!("--via must be ssh://[USER@]HOST[:PORT], got {}", url);
+
+        let rest = url.strip_prefix("ssh://").ok_or_else(invalid)?;
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+
+        let (user, rest) = match rest.rsplit_once('@') {
+            Some((user, rest)) => (Some(user), rest),
+            None               => (None, rest),
+        };
+
+        let (host, port) = match rest.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+
+                match rest {
+                    ""   => (host, None),
+                    rest => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+                }
+            },
+
+            None => match rest.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None               => (rest, None),
+            },
+        };
+
+        let port = port.map(|port| port.parse::<u16>().map_err(|_| invalid())).transpose()?;
+
+        // neither can be empty, or start with `-` and be taken by `ssh`
+        // as an option
+        let valid = |part: &str| !part.is_empty()
+            && !part.starts_with('-')
+            && !part.contains(|c: char| c.is_whitespace() || c == '/');
+
+        if !valid(host) || !user.is_none_or(valid) {
+            return Err(invalid());
+        }
+
+        Ok(Self { user: user.map(String::from), host: host.into(), port })
+    }
+
+    /// The arguments `ssh` is given to reach the host.
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(ref user) = self.user {
+            args.extend(["-l".into(), user.clone()]);
+        }
+
+        if let Some(port) = self.port {
+            args.extend(["-p".into(), port.to_string()]);
+        }
+
+        args.push(self.host.clone());
+        args
+    }
+}
+
+/// The session's socket forwarded from another host by `ssh`, to a
+/// socket in a directory only the approver can use. SSH is stopped and
+/// the directory removed when it's dropped.
+#[derive(Debug)]
+struct Forward {
+    ssh:    Child,
+    dir:    PathBuf,
+    socket: PathBuf,
+}
+
+impl Forward {
+    /// Forwards the socket at `remote` on `target`.
+    fn open(target: &Target, remote: &Path) -> Result<Self, String> {
+        let dir = env::temp_dir().join(format!("sudo_pair_approve.{}", process::id()));
+
+        DirBuilder::new().mode(0o700).create(&dir)
+            .map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+
+        let socket = dir.join("session.sock");
+
+        let ssh = Command::new("ssh")
+            .args(["-N", "-o", "ExitOnForwardFailure=yes", "-L"])
+            .arg(format!("{}:{}", socket.display(), remote.display()))
+            .args(target.args())
+            .stdin(Stdio::null())
+            .spawn();
+
+        match ssh {
+            Ok(ssh) => Ok(Self { ssh, dir, socket }),
+            Err(e)  => {
+                let _ = fs::remove_dir(&dir);
+                Err(format!("couldn't run ssh: {}", e))
+            },
+        }
+    }
+
+    /// Connects to the forwarded socket once SSH has created it,
+    /// announcing `capabilities`.
+    fn connect(&mut self, capabilities: Capabilities) -> Result<Client, String> {
+        let deadline = Instant::now() + FORWARD_TIMEOUT;
+
+        loop {
+            if let Some(status) = self.ssh.try_wait().map_err(|e| e.to_string())? {
+                return Err(format!("ssh exited ({}) without forwarding the session's socket", status));
+            }
+
+            if self.socket.exists() {
+                return Client::connect(&self.socket, capabilities)
+                    .map_err(|e| format!("couldn't connect to the forwarded socket: {}", e));
+            }
+
+            if Instant::now() >= deadline {
+                return Err(format!("ssh didn't forward the session's socket within {:?}", FORWARD_TIMEOUT));
+            }
+
+            thread::sleep(FORWARD_POLL);
+        }
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        let _ = self.ssh.kill();
+        let _ = self.ssh.wait();
+        let _ = fs::remove_file(&self.socket);
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+/// The terminal put into the mode the sample approval script uses:
+/// keystrokes are sent as they're typed, without being echoed, and
+/// Ctrl-C is passed to the session rather than killing the client (so
+/// an approver who forgets what the terminal is watching doesn't
+/// instinctively end it). Its settings are restored when it's dropped.
+struct Terminal {
+    original: libc::termios,
+}
+
+impl Terminal {
+    /// Changes the mode of the terminal on stdin, if there is one.
+    fn raw() -> Option<Self> {
+        // SAFETY: `termios` is plain data, which `tcgetattr` fills in
+        unsafe {
+            let mut original = std::mem::zeroed::<libc::termios>();
+
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+
+            Some(Self { original })
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // SAFETY: `original` was filled in by `tcgetattr`
+        let _ = unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+impl std::fmt::Debug for Terminal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Terminal").finish()
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let result = parse(args.into_iter()).and_then(|options| match options {
+        Some(options) => approve(&options),
+        None          => {
+            print!("{}", USAGE);
+            Ok(())
+        },
+    });
+
+    if let Err(e) = result {
+        eprintln!("sudo_pair_approve: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Parses the approver's arguments, returning `None` if they asked for
+/// help.
+fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Options>, String> {
+    let mut socket_dir = PathBuf::from(DEFAULT_SOCKET_DIR);
+    let mut via        = None;
+    let mut key        = None;
+    let mut hook       = None;
+    let mut plain      = false;
+    let mut control    = None;
+    let mut session    = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next()
+            .ok_or_else(|| format!("{} requires a value", flag));
+
+        match arg.as_str() {
+            "--socket-dir"  => socket_dir = PathBuf::from(value(&arg)?),
+            "--via"         => via        = Some(Target::parse(&value(&arg)?)?),
+            "--key"         => key        = Some(PathBuf::from(value(&arg)?)),
+            "--control"     => control    = Some(PathBuf::from(value(&arg)?)),
+            "--hook"        => hook       = Some(PathBuf::from(value(&arg)?)),
+            "--plain"       => plain      = true,
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') => return Err(format!("unrecognized argument {}\n\n{}", arg, USAGE)),
+            _               => session.push(arg),
+        }
+    }
+
+    let session = match (session.as_slice(), control) {
+        ([], Some(control)) => Source::Control(control),
+
+        ([uid, pid], None) => Source::Session {
+            uid: uid.parse().map_err(|_| format!("UID must be a number, got {}", uid))?,
+            pid: pid.parse().map_err(|_| format!("PID must be a number, got {}", pid))?,
+        },
+
+        (_, Some(_)) => return Err(format!("UID and PID can't be given with --control\n\n{}", USAGE)),
+        (_, None)    => return Err(format!("UID and PID are required\n\n{}", USAGE)),
+    };
+
+    // sessions announced on a control socket are already on this host
+    if via.is_some() && matches!(session, Source::Control(_)) {
+        return Err(format!("--via can't be given with --control\n\n{}", USAGE));
+    }
+
+    // over SSH, the socket's permissions only say who logged into the
+    // host, so the approver has to be able to prove who they are
+    if via.is_some() && key.is_none() {
+        return Err(format!("--key is required with --via\n\n{}", USAGE));
+    }
+
+    Ok(Some(Options { socket_dir, via, key, hook, plain, session }))
+}
+
+/// Connects to the session (or each session announced on the control
+/// socket) and passes it between the approver and the plugin until it
+/// ends.
+fn approve(options: &Options) -> Result<(), String> {
+    let (uid, pid) = match options.session {
+        Source::Session { uid, pid } => (uid, pid),
+        Source::Control(ref path)    => return serve(options, path),
+    };
+
+    let not_found = || format!(
+        "no session of the user {} with the pid {} is awaiting approval in {}",
+        uid, pid, options.socket_dir.display(),
+    );
+
+    let full_screen  = !options.plain && full_screen_available();
+    let capabilities = capabilities(full_screen);
+
+    // kept until the session ends, so the socket stays forwarded
+    let mut forward = None;
+
+    let client = match options.via {
+        Some(ref target) => {
+            let listing = list_remote(target, &options.socket_dir)?;
+            let sessions = listing.lines()
+                .filter_map(|name| Session::from_path(options.socket_dir.join(name)));
+
+            let session = choose(sessions, uid, pid).ok_or_else(not_found)?;
+
+            forward.insert(Forward::open(target, &session.path)?).connect(capabilities)?
+        },
+
+        None => {
+            let sessions = Session::list(&options.socket_dir)
+                .map_err(|e| format!("couldn't list {}: {}", options.socket_dir.display(), e))?;
+
+            let session = choose(sessions.into_iter(), uid, pid).ok_or_else(not_found)?;
+
+            session.connect(capabilities)
+                .map_err(|e| format!("couldn't connect to {}: {}", session.path.display(), e))?
+        },
+    };
+
+    review(client, options, full_screen, format!("session of uid {} (pid {})", uid, pid))
+}
+
+/// Listens on the control socket at `path` as an approver daemon, and
+/// shows each session announced on it in turn. Sessions announced while
+/// another is being shown wait their turn.
+fn serve(options: &Options, path: &Path) -> Result<(), String> {
+    let control = ControlSocket::bind(path)
+        .map_err(|e| format!("couldn't listen on {}: {}", path.display(), e))?;
+
+    let full_screen  = !options.plain && full_screen_available();
+    let capabilities = capabilities(full_screen);
+
+    loop {
+        eprintln!("sudo_pair_approve: waiting for sessions on {}", path.display());
+
+        let (announcement, client) = control.accept(capabilities)
+            .map_err(|e| format!("couldn't accept a session on {}: {}", path.display(), e))?;
+
+        let title = format!(
+            "session {} of uid {} (pid {})",
+            announcement.session_id, announcement.uid, announcement.pid,
+        );
+
+        // one session going wrong doesn't stop the others from being
+        // shown
+        if let Err(e) = review(client, options, full_screen, title) {
+            eprintln!("sudo_pair_approve: {}", e);
+        }
+    }
+}
+
+/// The capabilities announced to the plugin. The full-screen view asks
+/// for re-approvals itself, rather than having them written into the
+/// session's output, and has somewhere to write and show chat messages.
+fn capabilities(full_screen: bool) -> Capabilities {
+    if full_screen {
+        let mut capabilities = Capabilities::REAPPROVAL;
+        capabilities.insert(Capabilities::CHAT);
+        capabilities
+    } else {
+        Capabilities::default()
+    }
+}
+
+/// Shows the session to the approver, full-screen under `title` or
+/// passed through as-is, until it ends.
+///
+/// Once it's approved, the session is asked to be sent in `binary`
+/// frames, so nothing the command writes can be mistaken for a message
+/// from the plugin. Plugins that don't offer them keep sending it as
+/// text.
+fn review(mut client: Client, options: &Options, full_screen: bool, title: String) -> Result<(), String> {
+    client.request_encoding(Encoding::Binary);
+
+    #[cfg(feature = "tui")]
+    {
+        if full_screen {
+            return tui::review(client, options.key.clone(), options.hook.clone(), title);
+        }
+    }
+
+    let _ = full_screen;
+
+    pass_through(client, options, &title)
+}
+
+/// Whether the session can be shown in a full-screen view, which needs
+/// the approver to be at a terminal.
+fn full_screen_available() -> bool {
+    #[cfg(feature = "tui")]
+    {
+        tui::is_available()
+    }
+
+    #[cfg(not(feature = "tui"))]
+    {
+        false
+    }
+}
+
+/// Passes the session's output to the terminal as-is, and the
+/// approver's keystrokes to the session, until it ends.
+fn pass_through(mut client: Client, options: &Options, title: &str) -> Result<(), String> {
+    let input     = client.input().map_err(|e| e.to_string())?;
+    let _terminal = Terminal::raw();
+
+    let _ = thread::spawn(move || pass_keystrokes(input));
+
+    let stdout   = io::stdout();
+    let mut tags = Vec::new();
+
+    while let Some(event) = client.next_event().map_err(|e| e.to_string())? {
+        match event {
+            Event::Output(output) => {
+                let mut stdout = stdout.lock();
+
+                let _ = stdout.write_all(&output);
+                let _ = stdout.flush();
+            },
+
+            Event::Authenticate { namespace, challenge } => match options.key {
+                Some(ref key) => authenticate(&mut client, key, &namespace, &challenge)?,
+                None          => eprint!("\r\nsudo_pair_approve: {}\r\n", NO_KEY),
+            },
+
+            Event::Tags(received) => tags = received,
+
+            Event::Preview(preview) => if let Some(ref hook) = options.hook {
+                match run_hook(&mut client, hook, title, &preview, &tags) {
+                    Ok(Some(true))  => eprint!("\r\nsudo_pair_approve: the hook approved the session\r\n"),
+                    Ok(Some(false)) => eprint!("\r\nsudo_pair_approve: the hook declined the session\r\n"),
+                    Ok(None)        => (),
+                    Err(e)          => eprint!("\r\nsudo_pair_approve: {}\r\n", e),
+                }
+            },
+
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the approver's `hook` on the session, answering it if the hook
+/// decides to. Returns whether the session was approved, or `None` if
+/// it was left to the approver.
+fn run_hook(client: &mut Client, hook: &Path, title: &str, preview: &Preview, tags: &[(String, String)]) -> Result<Option<bool>, String> {
+    let decision = match hook::decide(hook, title, preview, tags)? {
+        Some(decision) => decision,
+        None           => return Ok(None),
+    };
+
+    let answered = match decision.comment.as_str() {
+        ""      => client.respond(decision.approve),
+        comment => client.respond_with_comment(decision.approve, comment),
+    };
+
+    answered.map_err(|e| e.to_string())?;
+
+    Ok(Some(decision.approve))
+}
+
+/// Answers the plugin's `challenge` by signing it with the key at `key`.
+fn authenticate(client: &mut Client, key: &Path, namespace: &str, challenge: &str) -> Result<(), String> {
+    let sshsig = sign(key, namespace, challenge)?;
+
+    client.authenticate(&sshsig).map_err(|e| e.to_string())
+}
+
+/// Lists the files in `dir` on `target`.
+fn list_remote(target: &Target, dir: &Path) -> Result<String, String> {
+    let output = Command::new("ssh")
+        .args(target.args())
+        .arg(format!("ls -1 -- {}", shell_quote(&dir.to_string_lossy())))
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("couldn't run ssh: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("couldn't list {} on {} ({})", dir.display(), target.host, output.status));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The session of `uid` whose `sudo` is `pid`, preferring a socket with
+/// the usual name (`<uid>.<pid>.sock`) over one named for a session
+/// whose usual name was taken.
+fn choose<I: Iterator<Item = Session>>(sessions: I, uid: uid_t, pid: pid_t) -> Option<Session> {
+    sessions
+        .filter(|session| session.uid == uid && session.pid == pid)
+        .min_by_key(|session| session.path.as_os_str().len())
+}
+
+/// Quotes `s` as a single word for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Signs the plugin's `challenge` with the key at `key`, as the plugin
+/// expects (see `protocol::signature`).
+fn sign(key: &Path, namespace: &str, challenge: &str) -> Result<Vec<u8>, String> {
+    if namespace != NAMESPACE {
+        return Err(format!("refusing to sign a challenge in the namespace {:?}", namespace));
+    }
+
+    if challenge.is_empty() || !challenge.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("refusing to sign the challenge {:?}", challenge));
+    }
+
+    let mut ssh_keygen = Command::new("ssh-keygen")
+        .args(["-Y", "sign", "-n", namespace, "-f"])
+        .arg(key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run ssh-keygen: {}", e))?;
+
+    if let Some(mut stdin) = ssh_keygen.stdin.take() {
+        stdin.write_all(challenge.as_bytes())
+            .map_err(|e| format!("couldn't run ssh-keygen: {}", e))?;
+    }
+
+    let output = ssh_keygen.wait_with_output()
+        .map_err(|e| format!("couldn't run ssh-keygen: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("couldn't sign the challenge with {} ({})", key.display(), output.status));
+    }
+
+    protocol::dearmor(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| "ssh-keygen didn't write a signature".into())
+}
+
+/// Passes the approver's keystrokes to the session as they're typed,
+/// ending the session on Ctrl-D (or once stdin is closed).
+fn pass_keystrokes(mut input: std::os::unix::net::UnixStream) {
+    let stdin   = io::stdin();
+    let mut buf = [0; 1024];
+
+    loop {
+        let read = match stdin.lock().read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(read)       => read,
+        };
+
+        let keystrokes = &buf[.. read];
+        let end        = keystrokes.iter().position(|&b| b == END_OF_SESSION);
+
+        if input.write_all(&keystrokes[.. end.unwrap_or(read)]).is_err() || end.is_some() {
+            break;
+        }
+    }
+
+    let _ = input.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn target(user: Option<&str>, host: &str, port: Option<u16>) -> Target {
+        Target { user: user.map(String::from), host: host.into(), port }
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(Ok(target(None, "db01", None)),                Target::parse("ssh://db01"));
+        assert_eq!(Ok(target(Some("bob"), "db01", Some(2222))),   Target::parse("ssh://bob@db01:2222/"));
+        assert_eq!(Ok(target(None, "::1", Some(22))),             Target::parse("ssh://[::1]:22"));
+
+        assert_eq!(
+            vec!["-l", "bob", "-p", "2222", "db01"],
+            target(Some("bob"), "db01", Some(2222)).args(),
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        for url in &["db01", "http://db01", "ssh://", "ssh://db01:ssh", "ssh://-oProxyCommand=x", "ssh://@db01", "ssh://[::1"] {
+            assert!(Target::parse(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn parses_arguments() {
+        assert_eq!(Ok(Some(Options {
+            socket_dir: "/run/sudo_pair".into(),
+            via:        Some(target(None, "db01", None)),
+            key:        Some("/home/bob/.ssh/id_ed25519".into()),
+            hook:       None,
+            plain:      false,
+            session:    Source::Session { uid: 1000, pid: 4242 },
+        })), parse(args(&[
+            "--socket-dir", "/run/sudo_pair", "--via", "ssh://db01", "--key", "/home/bob/.ssh/id_ed25519", "1000", "4242",
+        ])));
+
+        assert!(parse(args(&["--plain", "1000", "4242"])).unwrap().unwrap().plain);
+        assert_eq!(
+            Some(PathBuf::from("/etc/sudo_pair/hook")),
+            parse(args(&["--hook", "/etc/sudo_pair/hook", "1000", "4242"])).unwrap().unwrap().hook,
+        );
+
+        assert_eq!(Ok(None), parse(args(&["--help"])));
+        assert!(parse(args(&["1000"])).is_err());
+        assert!(parse(args(&["1000", "x"])).is_err());
+    }
+
+    #[test]
+    fn parses_control_sockets() {
+        assert_eq!(
+            Source::Control("/var/run/sudo_pair.control".into()),
+            parse(args(&["--control", "/var/run/sudo_pair.control"])).unwrap().unwrap().session,
+        );
+
+        // it's one or the other
+        assert!(parse(args(&["--control", "/var/run/sudo_pair.control", "1000", "4242"])).is_err());
+        assert!(parse(args(&["--control", "/var/run/sudo_pair.control", "--via", "ssh://db01", "--key", "k"])).is_err());
+    }
+
+    #[test]
+    fn requires_keys_over_ssh() {
+        let error = parse(args(&["--via", "ssh://db01", "1000", "4242"])).unwrap_err();
+
+        assert!(error.starts_with("--key is required with --via"));
+    }
+
+    #[test]
+    fn chooses_sessions() {
+        let sessions = [
+            "/var/run/sudo_pair/1000.4242.1600000000-4242.sock",
+            "/var/run/sudo_pair/1000.4242.sock",
+            "/var/run/sudo_pair/1000.4343.sock",
+        ];
+
+        let sessions = || sessions.iter().filter_map(Session::from_path);
+
+        assert_eq!(
+            Some(PathBuf::from("/var/run/sudo_pair/1000.4242.sock")),
+            choose(sessions(), 1000, 4242).map(|session| session.path),
+        );
+
+        assert_eq!(None, choose(sessions(), 1001, 4242));
+    }
+
+    #[test]
+    fn quotes_directories() {
+        assert_eq!("'/var/run/sudo_pair'", shell_quote("/var/run/sudo_pair"));
+        assert_eq!("'/it'\\''s'",          shell_quote("/it's"));
+    }
+
+    #[test]
+    fn signs_only_sudo_pair_challenges() {
+        let key = Path::new("/nonexistent");
+
+        assert!(sign(key, "file", "00ff").unwrap_err().contains("namespace"));
+        assert!(sign(key, NAMESPACE, "not hex").unwrap_err().contains("refusing"));
+    }
+}
@@ -0,0 +1,987 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! A full-screen view of the session. Passed through as-is, the
+//! session's details scroll away with its output the moment it's
+//! approved (and are easy to miss even before then), so here they're
+//! pinned above the output instead, from the plugin's `preview`. The
+//! output can be scrolled back through, and a status bar below it says
+//! what the approver is being asked and which keys answer it.
+//!
+//! The view doesn't emulate a terminal: escape sequences are removed
+//! from the output, which is shown as plain lines of text.
+//!
+//! Once the session's approved, the approver can send the user short
+//! messages (when the plugin accepts them), which are shown with the
+//! user's replies in a few rows of their own, just above the status
+//! bar.
+
+use crate::{authenticate, run_hook, NO_KEY};
+
+use sudo_pair_client::{protocol, time, Capabilities, Client, Event, Preview};
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::Shutdown;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::IntoAlternateScreen;
+use termion::{clear, color, cursor, style};
+
+/// The most lines of output kept to scroll back through.
+const SCROLLBACK : usize = 10_000;
+
+/// The most messages between the approver and the user kept on screen.
+const CHAT_LINES : usize = 3;
+
+/// The columns tabs in the output advance to a multiple of.
+const TAB_WIDTH : usize = 8;
+
+const ESC : u8 = 0x1b;
+const BEL : u8 = 0x07;
+
+/// The keys the view responds to, shown when the approver asks.
+const HELP : &[&str] = &[
+    "y          approve the session, or re-approve it when asked",
+    "n          decline the session, or end it when asked to re-approve",
+    "Up, Down   scroll the output by a line",
+    "PgUp, PgDn scroll the output by a page",
+    "Home, End  scroll to the start or the end of the output",
+    "m          send the user a message, once the session's approved",
+    "a          ask the user something; they're prompted to reply",
+    "?          show or hide these keys",
+    "Ctrl-D     end the session and exit",
+    "q          exit, once the session has ended",
+];
+
+/// Returns true if the approver is at a terminal the view can be shown
+/// on.
+pub(crate) fn is_available() -> bool {
+    termion::is_tty(&io::stdin()) && termion::is_tty(&io::stdout())
+}
+
+/// Shows the session until it ends and the approver exits, answering
+/// the plugin's challenges with the key at `key`, and running the
+/// approver's `hook` once the session's previewed. Until the plugin
+/// describes the session, it's called `title`.
+pub(crate) fn review(client: Client, key: Option<PathBuf>, hook: Option<PathBuf>, title: String) -> Result<(), String> {
+    let mut input = client.input().map_err(|e| e.to_string())?;
+
+    let (tx, rx) = mpsc::channel();
+    let events   = tx.clone();
+    let named    = title.clone();
+
+    let _ = thread::spawn(move || read_events(client, key, hook, &named, events));
+    let _ = thread::spawn(move || read_keys(tx));
+
+    let mut screen = io::stdout()
+        .into_raw_mode()
+        .and_then(|stdout| stdout.into_alternate_screen())
+        .map_err(|e| format!("couldn't set up the terminal: {}", e))?;
+
+    let mut view = View::new(title);
+
+    let _ = write!(screen, "{}", cursor::Hide);
+
+    'session: loop {
+        // terminals that don't know their size say they're empty
+        let (cols, rows) = termion::terminal_size().ok()
+            .filter(|&(cols, rows)| cols > 0 && rows > 0)
+            .unwrap_or((80, 24));
+
+        view.render(&mut screen, cols.into(), rows.into())
+            .map_err(|e| e.to_string())?;
+
+        // everything that's arrived is applied before the view is
+        // drawn again, so it keeps up with sessions that write a lot
+        let mut message = rx.recv().ok();
+
+        while let Some(next) = message {
+            if !view.apply(next, &mut input) {
+                break 'session;
+            }
+
+            message = rx.try_recv().ok();
+        }
+    }
+
+    let _ = write!(screen, "{}", cursor::Show);
+    let _ = input.shutdown(Shutdown::Both);
+
+    Ok(())
+}
+
+/// Something for the view to show or act on.
+#[derive(Debug)]
+enum Message {
+    /// an event from the plugin
+    Event(Event),
+
+    /// a key the approver pressed
+    Key(Key),
+
+    /// something that went wrong, which the approver has to be told
+    Error(String),
+
+    /// the approver's hook is deciding on the session
+    Deciding,
+
+    /// the approver's hook approved (or declined) the session, or left
+    /// it to the approver
+    Decided(Option<bool>),
+
+    /// the session has ended
+    Ended,
+}
+
+/// Passes the plugin's events to the view, answering its challenges
+/// and running the approver's `hook` along the way.
+fn read_events(mut client: Client, key: Option<PathBuf>, hook: Option<PathBuf>, title: &str, tx: Sender<Message>) {
+    let mut tags = Vec::new();
+
+    loop {
+        let message = match client.next_event() {
+            Ok(Some(Event::Authenticate { namespace, challenge })) => match key {
+                Some(ref key) => match authenticate(&mut client, key, &namespace, &challenge) {
+                    Ok(())  => continue,
+                    Err(e)  => Message::Error(e),
+                },
+
+                None => Message::Error(NO_KEY.into()),
+            },
+
+            Ok(Some(Event::Tags(received))) => {
+                tags = received.clone();
+                Message::Event(Event::Tags(received))
+            },
+
+            Ok(Some(Event::Preview(preview))) => match hook {
+                Some(ref hook) => {
+                    if tx.send(Message::Deciding).is_err() {
+                        return;
+                    }
+
+                    let decided = run_hook(&mut client, hook, title, &preview, &tags);
+
+                    if tx.send(Message::Event(Event::Preview(preview))).is_err() {
+                        return;
+                    }
+
+                    match decided {
+                        Ok(decided) => Message::Decided(decided),
+                        Err(e)      => {
+                            let _ = tx.send(Message::Decided(None));
+                            Message::Error(e)
+                        },
+                    }
+                },
+
+                None => Message::Event(Event::Preview(preview)),
+            },
+
+            Ok(Some(event)) => Message::Event(event),
+            Ok(None)        => break,
+            Err(e)          => {
+                let _ = tx.send(Message::Error(e.to_string()));
+                break;
+            },
+        };
+
+        if tx.send(message).is_err() {
+            return;
+        }
+    }
+
+    let _ = tx.send(Message::Ended);
+}
+
+/// Passes the approver's keys to the view.
+fn read_keys(tx: Sender<Message>) {
+    for key in io::stdin().keys() {
+        match key {
+            Ok(key) if tx.send(Message::Key(key)).is_ok() => (),
+            _                                             => return,
+        }
+    }
+}
+
+/// Where the session's approval stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// the approver hasn't answered yet
+    Asked,
+
+    /// the approver's hook is deciding whether to answer for them
+    Deciding,
+
+    /// the approver approved the session
+    Approved,
+
+    /// the plugin asked the approver to re-approve the session
+    Reapproving,
+
+    /// the approver declined the session
+    Declined,
+
+    /// the session is over
+    Ended,
+}
+
+/// How a row of the view is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tone {
+    Plain,
+    Title,
+    Warning,
+    Highlight,
+    Status,
+    Error,
+}
+
+/// Everything shown to the approver.
+#[derive(Debug)]
+struct View {
+    title:   String,
+    preview: Option<Preview>,
+    tags:    Vec<(String, String)>,
+    output:  Scrollback,
+    state:   State,
+    error:   Option<String>,
+    help:    bool,
+
+    /// whether the approver's hook answered the session for them
+    decided: bool,
+
+    /// when the approver has to answer by, if the plugin said
+    deadline: Option<SystemTime>,
+
+    /// the approver's timezone, which times are shown in
+    utc_offset: Option<i32>,
+
+    /// when the session was approved, and once it's ended, how long it
+    /// ran after that
+    approved_at: Option<Instant>,
+    ran:         Option<Duration>,
+
+    /// the longest message the user can be sent, if the plugin accepts
+    /// them
+    chat: Option<usize>,
+
+    /// the message the approver is writing, and whether it asks the
+    /// user to reply
+    composing: Option<(bool, String)>,
+
+    /// the latest messages between the approver and the user
+    chats: VecDeque<String>,
+
+    /// how many rows of output the view is scrolled back by
+    scrolled: usize,
+
+    /// the columns and rows of output shown when the view was last
+    /// drawn, for scrolling by pages
+    size: (usize, usize),
+}
+
+impl View {
+    fn new(title: String) -> Self {
+        Self {
+            title,
+            preview:  None,
+            tags:     Vec::new(),
+            output:   Scrollback::default(),
+            state:    State::Asked,
+            error:    None,
+            help:     false,
+            decided:  false,
+            deadline: None,
+
+            utc_offset:  time::utc_offset(SystemTime::now()),
+            approved_at: None,
+            ran:         None,
+
+            chat:      None,
+            composing: None,
+            chats:     VecDeque::new(),
+
+            scrolled: 0,
+            size:     (80, 20),
+        }
+    }
+
+    /// Updates the view with `message`, sending the approver's answers
+    /// to `input`. Returns false once the approver asks to exit.
+    fn apply<W: Write>(&mut self, message: Message, input: &mut W) -> bool {
+        match message {
+            Message::Event(Event::Output(output)) => {
+                let (cols, _) = self.size;
+                let before    = self.output.rows(cols).len();
+
+                self.output.push(&output);
+
+                // approvers who've scrolled back stay where they are
+                if self.scrolled > 0 {
+                    self.scrolled += self.output.rows(cols).len().saturating_sub(before);
+                }
+            },
+
+            Message::Event(Event::Preview(preview)) => self.preview = Some(preview),
+            Message::Event(Event::Tags(tags))       => self.tags    = tags,
+            Message::Event(Event::Hello { deadline, capabilities, limits, .. }) => {
+                self.deadline = deadline;
+                self.chat     = Some(limits.chat).filter(|_| capabilities.contains(Capabilities::CHAT));
+            },
+
+            Message::Event(Event::Chat { text }) => self.note(format!("the user: {}", text)),
+
+            Message::Event(Event::Reapprove { deadline }) => {
+                self.state    = State::Reapproving;
+                self.deadline = Some(deadline);
+            },
+
+            Message::Event(_) => (),
+
+            Message::Error(error) => self.error = Some(error),
+
+            Message::Ended => {
+                self.state = State::Ended;
+                self.ran   = self.approved_at.map(|at| at.elapsed());
+            },
+
+            Message::Deciding if self.state == State::Asked => self.state = State::Deciding,
+
+            Message::Decided(decided) if self.state == State::Deciding => {
+                self.state = match decided {
+                    Some(true)  => State::Approved,
+                    Some(false) => State::Declined,
+                    None        => State::Asked,
+                };
+
+                self.decided = decided.is_some();
+
+                if decided == Some(true) {
+                    self.approve();
+                }
+            },
+
+            Message::Deciding | Message::Decided(_) => (),
+
+            Message::Key(key) => return self.press(key, input),
+        }
+
+        true
+    }
+
+    /// Acts on the approver pressing `key`.
+    fn press<W: Write>(&mut self, key: Key, input: &mut W) -> bool {
+        let (cols, rows) = self.size;
+        let most         = self.output.rows(cols).len().saturating_sub(rows);
+
+        if self.composing.is_some() {
+            return self.compose(key, input);
+        }
+
+        match key {
+            Key::Char(answer @ ('y' | 'n')) if matches!(self.state, State::Asked | State::Reapproving) => {
+                let answer = answer as u8;
+
+                if input.write_all(&[answer]).is_ok() {
+                    self.state = if answer == b'y' { State::Approved } else { State::Declined };
+
+                    if answer == b'y' {
+                        self.approve();
+                    }
+                }
+            },
+
+            Key::Char(key @ ('m' | 'a')) if self.chat.is_some() && matches!(self.state, State::Approved | State::Reapproving) => {
+                self.composing = Some((key == 'a', String::new()));
+            },
+
+            Key::Char('?') => self.help = !self.help,
+            Key::Ctrl('d') => return false,
+
+            Key::Char('q') if self.state == State::Ended => return false,
+
+            Key::Up       => self.scrolled = (self.scrolled + 1).min(most),
+            Key::Down     => self.scrolled = self.scrolled.saturating_sub(1),
+            Key::PageUp   => self.scrolled = (self.scrolled + rows.max(1)).min(most),
+            Key::PageDown => self.scrolled = self.scrolled.saturating_sub(rows.max(1)),
+            Key::Home     => self.scrolled = most,
+            Key::End      => self.scrolled = 0,
+
+            _ => (),
+        }
+
+        true
+    }
+
+    /// Acts on the approver pressing `key` while they write a message to
+    /// the user. Enter sends it and Esc discards it; the keys that
+    /// usually answer the plugin are part of the message instead.
+    fn compose<W: Write>(&mut self, key: Key, input: &mut W) -> bool {
+        let limit = self.chat.unwrap_or_default();
+
+        let (ask, text) = match self.composing {
+            Some(ref mut composing) => composing,
+            None                    => return true,
+        };
+
+        match key {
+            Key::Char('\n') => {
+                let (ask, text) = (*ask, text.trim().to_string());
+
+                self.composing = None;
+
+                if !text.is_empty() && input.write_all(&protocol::chat(&text, ask)).is_ok() {
+                    self.note(format!("{}: {}", if ask { "you asked" } else { "you" }, text));
+                }
+            },
+
+            Key::Char(c) if !c.is_control() && text.chars().count() < limit => text.push(c),
+
+            Key::Backspace => { let _ = text.pop(); },
+            Key::Esc       => self.composing = None,
+            Key::Ctrl('d') => return false,
+
+            _ => (),
+        }
+
+        true
+    }
+
+    /// Adds a message between the approver and the user to those shown.
+    fn note(&mut self, line: String) {
+        self.chats.push_back(line);
+
+        if self.chats.len() > CHAT_LINES {
+            let _ = self.chats.pop_front();
+        }
+    }
+
+    /// Notes when the session was first approved, which re-approving
+    /// it doesn't change.
+    fn approve(&mut self) {
+        let _ = self.approved_at.get_or_insert_with(Instant::now);
+    }
+
+    /// The session's details, as they're pinned above its output.
+    fn header(&self, cols: usize) -> Vec<(Tone, String)> {
+        let mut lines = Vec::new();
+
+        let tags : Vec<_> = self.tags.iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+
+        let tags = match tags.as_slice() {
+            []   => String::new(),
+            tags => format!("  [{}]", tags.join(" ")),
+        };
+
+        match self.preview {
+            Some(ref preview) => {
+                let runas = match preview.runas_group.as_str() {
+                    ""    => preview.runas_user.clone(),
+                    group => format!("{}:{}", preview.runas_user, group),
+                };
+
+                lines.push((Tone::Title, format!("{}@{} as {}{}", preview.user, preview.host, runas, tags)));
+                lines.push((Tone::Plain, format!("in {}", preview.cwd)));
+                lines.push((Tone::Plain, format!("$ {}", preview.command)));
+
+                lines.extend(preview.warnings.iter().map(|warning| (Tone::Warning,   format!("warning: {}", warning))));
+                lines.extend(preview.env     .iter().map(|line|    (Tone::Highlight, format!("env: {}", line))));
+            },
+
+            None => lines.push((Tone::Title, format!("{}{}", self.title, tags))),
+        }
+
+        // the command is never cut short to fit, so approvers see all of
+        // what they're approving
+        lines.into_iter()
+            .flat_map(|(tone, line)| wrap(&line, cols).into_iter().map(move |row| (tone, row)))
+            .collect()
+    }
+
+    /// The status bar, below the output.
+    fn status(&self) -> (Tone, String) {
+        if let Some(ref error) = self.error {
+            return (Tone::Error, error.clone());
+        }
+
+        if let Some((ask, ref text)) = self.composing {
+            let to = if ask { "ask the user" } else { "message the user" };
+
+            return (Tone::Status, format!("{}: {}_ (Enter sends it, Esc discards it)", to, text));
+        }
+
+        let mut status = match (self.state, self.decided) {
+            (State::Asked, _)        => "approve this session? y approves it, n declines it",
+            (State::Deciding, _)     => "your hook is deciding on this session",
+            (State::Approved, true)  => "approved by your hook; Ctrl-D ends the session",
+            (State::Approved, false) => "approved; Ctrl-D ends the session",
+            (State::Reapproving, _)  => "re-approve this session? y re-approves it, n ends it",
+            (State::Declined, true)  => "declined by your hook",
+            (State::Declined, false) => "declined",
+            (State::Ended, _)        => "the session has ended; q exits",
+        }.to_string();
+
+        // times are shown in the approver's timezone, not the plugin's
+        match (self.state, self.deadline) {
+            (State::Asked | State::Reapproving, Some(deadline)) => {
+                status.push_str(&format!(" (by {})", time::clock(deadline, self.utc_offset)));
+            },
+
+            (State::Ended, _) => if let Some(ran) = self.ran {
+                status = format!("the session has ended after {}; q exits", time::duration(ran));
+            },
+
+            _ => (),
+        }
+
+        if self.scrolled > 0 {
+            status.push_str(&format!(" (scrolled back {} lines)", self.scrolled));
+        }
+
+        (Tone::Status, status)
+    }
+
+    /// Draws the view on a terminal of `cols` by `rows`.
+    fn render<W: Write>(&mut self, out: &mut W, cols: usize, rows: usize) -> io::Result<()> {
+        let cols = cols.max(1);
+
+        // the details can't crowd the output (or status) off the screen
+        let mut header = self.header(cols);
+
+        if header.len() > rows / 2 {
+            header.truncate((rows / 2).saturating_sub(1));
+            header.push((Tone::Warning, "(the rest of the session's details don't fit)".into()));
+        }
+
+        let chats : Vec<(Tone, String)> = self.chats.iter()
+            .map(|line| (Tone::Highlight, line.clone()))
+            .collect();
+
+        let body = rows.saturating_sub(header.len() + chats.len() + 1);
+
+        self.size     = (cols, body);
+        self.scrolled = self.scrolled.min(self.output.rows(cols).len().saturating_sub(body));
+
+        let lines : Vec<(Tone, String)> = if self.help {
+            HELP.iter().map(|line| (Tone::Plain, line.to_string())).collect()
+        } else {
+            let output = self.output.rows(cols);
+            let end    = output.len() - self.scrolled;
+
+            output[end.saturating_sub(body) .. end].iter()
+                .map(|row| (Tone::Plain, row.clone()))
+                .collect()
+        };
+
+        let rows = header.into_iter()
+            .chain(lines.into_iter().chain(std::iter::repeat((Tone::Plain, String::new()))).take(body))
+            .chain(chats)
+            .chain(Some(self.status()));
+
+        for (row, (tone, text)) in rows.enumerate() {
+            let text : String = text.chars().take(cols).collect();
+
+            write!(out, "{}{}", cursor::Goto(1, row as u16 + 1), clear::CurrentLine)?;
+
+            match tone {
+                Tone::Plain     => write!(out, "{}", text)?,
+                Tone::Title     => write!(out, "{}{}{}", style::Bold, text, style::Reset)?,
+                Tone::Warning   => write!(out, "{}{}{}", color::Fg(color::Red), text, color::Fg(color::Reset))?,
+                Tone::Highlight => write!(out, "{}{}{}", color::Fg(color::Yellow), text, color::Fg(color::Reset))?,
+                Tone::Error     => write!(out, "{}{}{:<cols$}{}", style::Invert, color::Fg(color::Red), text, style::Reset, cols = cols)?,
+                Tone::Status    => write!(out, "{}{:<cols$}{}", style::Invert, format!("{}  ? keys", text), style::Reset, cols = cols)?,
+            }
+        }
+
+        out.flush()
+    }
+}
+
+/// Splits `line` into rows of at most `cols` characters.
+fn wrap(line: &str, cols: usize) -> Vec<String> {
+    let chars : Vec<_> = line.chars().collect();
+
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+
+    chars.chunks(cols.max(1)).map(|row| row.iter().collect()).collect()
+}
+
+/// Where the scrollback is in an escape sequence in the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    Esc,
+    Csi,
+    String,
+    StringEsc,
+}
+
+/// The session's output as lines of plain text, without the escape
+/// sequences (colors, cursor movement, and the like) it was written
+/// with.
+#[derive(Debug, Default)]
+struct Scrollback {
+    lines: VecDeque<String>,
+
+    /// the line being written, which may still be missing the rest of
+    /// a character
+    line: Vec<u8>,
+
+    /// whether a carriage return was written, so whatever's written
+    /// next replaces the line (as progress bars do)
+    returned: bool,
+
+    escape: Escape,
+}
+
+impl Scrollback {
+    /// Adds `output` to the scrollback.
+    fn push(&mut self, output: &[u8]) {
+        for &byte in output {
+            self.escape = match (self.escape, byte) {
+                (Escape::None, ESC) => Escape::Esc,
+                (Escape::None, _)   => {
+                    self.write(byte);
+                    continue;
+                },
+
+                // control sequences end with a byte from `@` to `~`, and
+                // strings (e.g., `OSC`) with `BEL` or `ESC \`
+                (Escape::Esc, b'[')                                 => Escape::Csi,
+                (Escape::Esc, b']' | b'P' | b'X' | b'^' | b'_')     => Escape::String,
+                (Escape::Csi, 0x40 ..= 0x7e)                        => Escape::None,
+                (Escape::Csi, _)                                    => Escape::Csi,
+                (Escape::String, BEL)                               => Escape::None,
+                (Escape::String, ESC)                               => Escape::StringEsc,
+                (Escape::String, _)                                 => Escape::String,
+                (Escape::StringEsc, b'\\')                          => Escape::None,
+                (Escape::StringEsc, _)                              => Escape::String,
+                (Escape::Esc, _)                                    => Escape::None,
+            };
+        }
+    }
+
+    /// Adds a byte of output outside any escape sequence.
+    fn write(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.returned = false;
+                self.end_line();
+                return;
+            },
+
+            b'\r' => {
+                self.returned = true;
+                return;
+            },
+
+            // other control characters don't leave anything to show
+            b'\t' | 0x08 | 0x20 ..= 0x7e | 0x80 ..= 0xff => (),
+            _                                            => return,
+        }
+
+        if self.returned {
+            self.returned = false;
+            self.line.clear();
+        }
+
+        match byte {
+            b'\t' => {
+                let width = String::from_utf8_lossy(&self.line).chars().count();
+
+                self.line.resize(self.line.len() + TAB_WIDTH - width % TAB_WIDTH, b' ');
+            },
+
+            0x08 => {
+                // back up over a whole character
+                while let Some(byte) = self.line.pop() {
+                    if byte & 0xc0 != 0x80 {
+                        break;
+                    }
+                }
+            },
+
+            _ => self.line.push(byte),
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.lines.push_back(String::from_utf8_lossy(&self.line).into_owned());
+        self.line.clear();
+
+        if self.lines.len() > SCROLLBACK {
+            let _ = self.lines.pop_front();
+        }
+    }
+
+    /// The output as rows of at most `cols` characters, including the
+    /// line still being written, if any.
+    fn rows(&self, cols: usize) -> Vec<String> {
+        let line = Some(&self.line)
+            .filter(|line| !line.is_empty())
+            .map(|line| String::from_utf8_lossy(line).into_owned());
+
+        self.lines.iter().cloned().chain(line)
+            .flat_map(|line| wrap(&line, cols))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sudo_pair_client::{Capabilities, Limits};
+
+    use std::time::UNIX_EPOCH;
+
+    fn scrollback(output: &[&[u8]]) -> Scrollback {
+        let mut scrollback = Scrollback::default();
+
+        for output in output {
+            scrollback.push(output);
+        }
+
+        scrollback
+    }
+
+    #[test]
+    fn removes_escape_sequences() {
+        let scrollback = scrollback(&[
+            b"\x1b[1;31mred\x1b[0m\n\x1b]0;title\x07plain\x1b]8;;http://x\x1b\\link\n",
+            b"split\x1b[3",
+            b"8;5;1mhere\x08\x08\x08\x08HERE\n",
+        ]);
+
+        assert_eq!(vec!["red", "plainlink", "splitHERE"], scrollback.rows(80));
+    }
+
+    #[test]
+    fn replaces_lines_after_carriage_returns() {
+        let scrollback = scrollback(&[b"10%\r50%\r100%\r\ndone\ta\xe6\x97", b"\xa5\tb"]);
+
+        assert_eq!(vec!["100%", "done    a\u{65e5}      b"], scrollback.rows(80));
+    }
+
+    #[test]
+    fn wraps_rows_to_the_width() {
+        let scrollback = scrollback(&[b"abcdefg\n\nhij"]);
+
+        assert_eq!(vec!["abc", "def", "g", "", "hij"], scrollback.rows(3));
+    }
+
+    #[test]
+    fn keeps_a_limited_scrollback() {
+        let scrollback = scrollback(&[&b"line\n".repeat(SCROLLBACK + 5)]);
+
+        assert_eq!(SCROLLBACK, scrollback.rows(80).len());
+    }
+
+    #[test]
+    fn pins_the_sessions_details() {
+        let mut view = View::new("session of uid 1000 (pid 4242)".into());
+
+        assert_eq!(vec![(Tone::Title, "session of uid 1000 (pid 4242)".to_string())], view.header(80));
+
+        let _ = view.apply(Message::Event(Event::Tags(vec![("team".into(), "payments".into())])), &mut Vec::new());
+        let _ = view.apply(Message::Event(Event::Preview(Preview {
+            user:       "alice".into(),
+            host:       "db01".into(),
+            runas_user: "root".into(),
+            cwd:        "/home/alice".into(),
+            command:    "bash -c 'rm -rf /tmp/x && touch /etc/nologin'".into(),
+            warnings:   vec!["bash is a shell".into()],
+            ..Preview::default()
+        })), &mut Vec::new());
+
+        assert_eq!(vec![
+            (Tone::Title,   "alice@db01 as root  [team=payments]".to_string()),
+            (Tone::Plain,   "in /home/alice".to_string()),
+            (Tone::Plain,   "$ bash -c 'rm -rf /tmp/x && touch /etc/n".to_string()),
+            (Tone::Plain,   "ologin'".to_string()),
+            (Tone::Warning, "warning: bash is a shell".to_string()),
+        ], view.header(40));
+    }
+
+    #[test]
+    fn answers_only_when_asked() {
+        let mut view  = View::new("session".into());
+        let mut input = Vec::new();
+
+        assert!(view.apply(Message::Key(Key::Char('x')), &mut input));
+        assert!(view.apply(Message::Key(Key::Char('y')), &mut input));
+        assert!(view.apply(Message::Key(Key::Char('n')), &mut input));
+        assert_eq!(State::Approved, view.state);
+
+        let _ = view.apply(Message::Event(Event::Reapprove { deadline: UNIX_EPOCH }), &mut input);
+        let _ = view.apply(Message::Key(Key::Char('n')), &mut input);
+
+        assert_eq!(b"yn".to_vec(), input);
+        assert_eq!(State::Declined, view.state);
+
+        let _ = view.apply(Message::Ended, &mut input);
+
+        assert!(view.apply(Message::Key(Key::Char('?')), &mut input));
+        assert!(!view.apply(Message::Key(Key::Char('q')), &mut input));
+        assert!(!view.apply(Message::Key(Key::Ctrl('d')), &mut input));
+    }
+
+    #[test]
+    fn shows_what_the_hook_decided() {
+        let mut view = View::new("session".into());
+
+        let mut input = Vec::new();
+
+        // the approver can't answer over the hook while it decides
+        let _ = view.apply(Message::Deciding, &mut input);
+        let _ = view.apply(Message::Key(Key::Char('y')), &mut input);
+        let _ = view.apply(Message::Decided(Some(false)), &mut input);
+
+        assert!(input.is_empty());
+        assert_eq!(State::Declined, view.state);
+        assert_eq!((Tone::Status, "declined by your hook".to_string()), view.status());
+
+        // but answers themselves if it leaves the session to them
+        let mut view = View::new("session".into());
+
+        let _ = view.apply(Message::Deciding, &mut input);
+        let _ = view.apply(Message::Decided(None), &mut input);
+        let _ = view.apply(Message::Key(Key::Char('y')), &mut input);
+
+        assert_eq!(b"y".to_vec(), input);
+        assert_eq!((Tone::Status, "approved; Ctrl-D ends the session".to_string()), view.status());
+    }
+
+    #[test]
+    fn shows_times_in_the_approvers_timezone() {
+        let mut view  = View::new("session".into());
+        let mut input = Vec::new();
+
+        view.utc_offset = Some(7_200);
+
+        let _ = view.apply(Message::Event(Event::Hello {
+            version:      1,
+            capabilities: Capabilities::default(),
+            deadline:     Some(UNIX_EPOCH + Duration::from_secs(1_600_000_300)),
+            encodings:    Vec::new(),
+            limits:       Limits::DEFAULT,
+        }), &mut input);
+
+        assert_eq!(
+            (Tone::Status, "approve this session? y approves it, n declines it (by 14:31:40 +02:00)".to_string()),
+            view.status(),
+        );
+
+        let _ = view.apply(Message::Key(Key::Char('y')), &mut input);
+        let _ = view.apply(Message::Event(Event::Reapprove { deadline: UNIX_EPOCH + Duration::from_secs(1_600_003_600) }), &mut input);
+
+        assert_eq!(
+            (Tone::Status, "re-approve this session? y re-approves it, n ends it (by 15:26:40 +02:00)".to_string()),
+            view.status(),
+        );
+
+        // how long the session ran is counted from when it was first
+        // approved
+        let _ = view.apply(Message::Key(Key::Char('y')), &mut input);
+
+        view.approved_at = Instant::now().checked_sub(Duration::from_secs(720));
+
+        let _ = view.apply(Message::Ended, &mut input);
+
+        assert_eq!((Tone::Status, "the session has ended after 12 minutes; q exits".to_string()), view.status());
+    }
+
+    #[test]
+    fn sends_messages_to_the_user() {
+        let mut view  = View::new("session".into());
+        let mut input = Vec::new();
+
+        let _ = view.apply(Message::Event(Event::Hello {
+            version:      1,
+            capabilities: Capabilities::CHAT,
+            deadline:     None,
+            encodings:    Vec::new(),
+            limits:       Limits { chat: 5, ..Limits::DEFAULT },
+        }), &mut input);
+
+        // there's no one to message until the session's approved
+        let _ = view.apply(Message::Key(Key::Char('m')), &mut input);
+
+        assert_eq!(None, view.composing);
+
+        let _ = view.apply(Message::Key(Key::Char('y')), &mut input);
+
+        for key in "aok?yesno\n".chars() {
+            let _ = view.apply(Message::Key(Key::Char(key)), &mut input);
+        }
+
+        assert_eq!(State::Approved, view.state);
+        assert_eq!([b"y".to_vec(), protocol::chat("ok?ye", true)].concat(), input);
+
+        let _ = view.apply(Message::Event(Event::Chat { text: "yes".into() }), &mut input);
+
+        assert_eq!(vec!["you asked: ok?ye", "the user: yes"], Vec::from(view.chats.clone()));
+
+        // messages the approver discards aren't sent
+        input.clear();
+
+        for key in [Key::Char('m'), Key::Char('x'), Key::Backspace, Key::Char('z')] {
+            let _ = view.apply(Message::Key(key), &mut input);
+        }
+
+        assert_eq!(
+            (Tone::Status, "message the user: z_ (Enter sends it, Esc discards it)".to_string()),
+            view.status(),
+        );
+
+        let _ = view.apply(Message::Key(Key::Esc), &mut input);
+
+        assert!(input.is_empty());
+        assert_eq!(None, view.composing);
+    }
+
+    #[test]
+    fn stays_scrolled_back_as_output_arrives() {
+        let mut view = View::new("session".into());
+        let mut out  = Vec::new();
+
+        let _ = view.apply(Message::Event(Event::Output(b"1\n2\n3\n4\n5\n".to_vec())), &mut Vec::new());
+
+        view.render(&mut out, 80, 4).unwrap();
+        let _ = view.apply(Message::Key(Key::PageUp), &mut Vec::new());
+
+        assert_eq!(2, view.scrolled);
+
+        let _ = view.apply(Message::Event(Event::Output(b"6\n".to_vec())), &mut Vec::new());
+
+        assert_eq!(3, view.scrolled);
+
+        let _ = view.apply(Message::Key(Key::End), &mut Vec::new());
+
+        assert_eq!(0, view.scrolled);
+    }
+}
@@ -0,0 +1,434 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::protocol::{self, truncate, Capabilities, Encoding, Event, Limits, Parser};
+use crate::time;
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::Shutdown;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The size of the buffer used for each read from the socket.
+const READ_SIZE : usize = 4096;
+
+/// A connection to a session awaiting (or under) approval.
+#[derive(Debug)]
+pub struct Client {
+    stream: UnixStream,
+    parser: Parser,
+    events: VecDeque<Event>,
+
+    capabilities: Capabilities,
+    plugin:       Option<(u16, Capabilities, Limits)>,
+
+    /// the encoding to ask for the session to be sent in, if the plugin
+    /// offers it
+    encoding: Encoding,
+
+    /// whether the session has been approved or declined yet
+    responded: bool,
+}
+
+impl Client {
+    /// Connects to the session socket at `path`. The client announces
+    /// `capabilities` to the plugin once the plugin announces its own.
+    ///
+    /// A `path` starting with `@` names a socket in Linux's abstract
+    /// namespace instead, which is where sessions create their sockets
+    /// when the plugin isn't allowed to write to disk.
+    pub fn connect<P: AsRef<Path>>(path: P, capabilities: Capabilities) -> Result<Self> {
+        connect(path.as_ref()).map(|stream| Self::from_stream(stream, capabilities))
+    }
+
+    /// A client for the session on the other end of `stream`, which is
+    /// already connected to it (e.g., by a `ControlSocket`).
+    pub(crate) fn from_stream(stream: UnixStream, capabilities: Capabilities) -> Self {
+        Self {
+            stream,
+            parser: Parser::new(),
+            events: VecDeque::new(),
+
+            capabilities,
+            plugin:    None,
+            encoding:  Encoding::Text,
+            responded: false,
+        }
+    }
+
+    /// Holds the plugin to `limits`, rather than to `Limits::DEFAULT`,
+    /// announcing them in the client's `hello`. Like `request_encoding`,
+    /// this has to be called before the plugin's `hello` arrives.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.parser.set_limits(limits);
+    }
+
+    /// Asks for the session to be sent in `encoding` once it's watched,
+    /// if the plugin offers it. This has to be called before the
+    /// plugin's `hello` arrives, and doesn't change the events returned,
+    /// only how they're sent.
+    pub fn request_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Returns the next event from the plugin, blocking until one is
+    /// available, or `None` once the session has ended.
+    ///
+    /// When the plugin's `hello` arrives, the client's own `hello` is
+    /// sent in response before the event is returned, giving the
+    /// approver's timezone (see `time::utc_offset`) so the plugin can
+    /// show them times in it, and the size of their terminal (if stdout
+    /// is one) so it can hold them to its `approver_width`.
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        let mut buf = [0; READ_SIZE];
+
+        while self.events.is_empty() {
+            let read = self.stream.read(&mut buf)?;
+
+            if read == 0 {
+                return Ok(None);
+            }
+
+            self.events.extend(self.parser.feed(&buf[..read]));
+        }
+
+        let event = self.events.pop_front();
+
+        if let Some(Event::Hello { version, capabilities, ref encodings, limits, .. }) = event {
+            let encoding = if encodings.contains(&self.encoding) {
+                self.encoding
+            } else {
+                Encoding::Text
+            };
+
+            self.plugin = Some((version, capabilities, limits));
+            let utc_offset = time::utc_offset(SystemTime::now());
+
+            self.stream.write_all(&protocol::hello(
+                self.capabilities,
+                encoding,
+                utc_offset,
+                winsize(),
+                self.parser.limits(),
+            ))?;
+        }
+
+        Ok(event)
+    }
+
+    /// Returns an iterator over the events from the plugin, which calls
+    /// `next_event` for each, and ends with the session (or the first
+    /// error). The client stays reachable through `Events::client`, so
+    /// it can respond to the events as they arrive.
+    pub fn events(&mut self) -> Events<'_> {
+        Events { client: self, failed: false }
+    }
+
+    /// Returns the protocol version and capabilities agreed upon with
+    /// the plugin, or `None` if the plugin hasn't announced itself (or
+    /// predates negotiation).
+    pub fn negotiated(&self) -> Option<(u16, Capabilities)> {
+        self.plugin.map(|(version, capabilities, _)| (
+            version.min(protocol::PROTOCOL_VERSION),
+            capabilities & self.capabilities,
+        ))
+    }
+
+    /// Returns the limits agreed upon with the plugin, which both sides
+    /// are held to, or the client's own if the plugin hasn't announced
+    /// itself.
+    pub fn limits(&self) -> Limits {
+        let ours = self.parser.limits();
+
+        self.plugin.map_or(ours, |(_, _, theirs)| ours.negotiate(theirs))
+    }
+
+    /// Approves or declines the session, or answers a request to
+    /// re-approve it.
+    pub fn respond(&mut self, approve: bool) -> Result<()> {
+        self.responded = true;
+        self.stream.write_all(if approve { b"y" } else { b"n" })
+    }
+
+    /// Approves or declines the session with a `comment` explaining
+    /// why, which is shown to the user and recorded alongside the
+    /// session. Plugins that didn't negotiate `Capabilities::COMMENTS`
+    /// would take a comment as declining the session, and comments
+    /// can't accompany re-approvals, so in either case the comment is
+    /// left out and only the response is sent. A comment longer than the
+    /// plugin accepts is cut short (see `protocol::truncate`).
+    pub fn respond_with_comment(&mut self, approve: bool, comment: &str) -> Result<()> {
+        let capabilities = self.negotiated()
+            .map(|(_, capabilities)| capabilities)
+            .unwrap_or_default();
+
+        if capabilities.contains(Capabilities::COMMENTS) && !self.responded {
+            let comment = truncate(comment, self.limits().comment);
+
+            self.stream.write_all(&protocol::comment(&comment))?;
+        }
+
+        self.respond(approve)
+    }
+
+    /// Answers an `Event::Authenticate` with the approver's `sshsig`
+    /// signature of its challenge (see `protocol::signature`). This has
+    /// to be sent before the approver responds, since the plugin checks
+    /// the signature when the session is approved. A signature too long
+    /// for the plugin to accept isn't sent, and is an `InvalidInput`
+    /// error.
+    pub fn authenticate(&mut self, sshsig: &[u8]) -> Result<()> {
+        self.send(&protocol::signature(sshsig), "signature")
+    }
+
+    /// Answers an `Event::OneTimeCode` with the approver's current
+    /// `code` and the name they're provisioned under (see
+    /// `protocol::one_time_code`). Like a signature, this has to be
+    /// sent before the approver responds.
+    pub fn send_one_time_code(&mut self, approver: &str, code: &str) -> Result<()> {
+        self.send(&protocol::one_time_code(approver, code), "one-time code")
+    }
+
+    /// Sends the user a message from the approver, shown on their
+    /// terminal, asking them to reply if `ask` is set (see
+    /// `protocol::chat`); their reply arrives as an `Event::Chat`. A
+    /// message longer than the plugin accepts is cut short. Messages can
+    /// only be sent once the approver has responded, to plugins that
+    /// negotiated `Capabilities::CHAT`, since any other plugin would
+    /// take one as declining the session; sending one otherwise is an
+    /// `InvalidInput` error.
+    pub fn chat(&mut self, text: &str, ask: bool) -> Result<()> {
+        let capabilities = self.negotiated()
+            .map(|(_, capabilities)| capabilities)
+            .unwrap_or_default();
+
+        if !capabilities.contains(Capabilities::CHAT) || !self.responded {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the plugin doesn't accept chat messages yet",
+            ));
+        }
+
+        let text = truncate(text, self.limits().chat);
+
+        self.send(&protocol::chat(&text, ask), "chat message")
+    }
+
+    /// Sends `message`, unless it's longer than the plugin accepts, in
+    /// which case the plugin would give up on reading it partway.
+    fn send(&mut self, message: &[u8], what: &str) -> Result<()> {
+        if message.len() > self.limits().message {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("the {} is longer than the plugin accepts", what),
+            ));
+        }
+
+        self.stream.write_all(message)
+    }
+
+    /// Returns another handle to the session's socket, for passing the
+    /// approver's keystrokes through to the plugin as they're typed, as
+    /// `socat` does for the sample approval script. It can be written
+    /// to from another thread while this one waits in `next_event`.
+    pub fn input(&self) -> Result<UnixStream> {
+        self.stream.try_clone()
+    }
+
+    /// Ends the session.
+    pub fn close(&mut self) -> Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+/// An iterator over the events from the plugin, returned by
+/// `Client::events`.
+#[derive(Debug)]
+pub struct Events<'a> {
+    client: &'a mut Client,
+
+    /// whether an error has been returned, after which there's nothing
+    /// more to read
+    failed: bool,
+}
+
+impl Events<'_> {
+    /// The client the events are read from, for responding to them.
+    pub fn client(&mut self) -> &mut Client {
+        self.client
+    }
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let next = self.client.next_event().transpose();
+
+        self.failed = matches!(next, Some(Err(_)));
+
+        next
+    }
+}
+
+/// Connects to the socket at `path`, or to the abstract socket it
+/// names if it starts with `@`.
+fn connect(path: &Path) -> Result<UnixStream> {
+    match path.as_os_str().as_bytes().split_first() {
+        Some((b'@', name)) => connect_abstract(name),
+        _                  => UnixStream::connect(path),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &[u8]) -> Result<UnixStream> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    UnixStream::connect_addr(&SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_: &[u8]) -> Result<UnixStream> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+/// The size of the terminal on stdout as rows and columns, or `None`
+/// if stdout isn't a terminal (or doesn't know its size).
+fn winsize() -> Option<(u16, u16)> {
+    unsafe {
+        let mut winsize = std::mem::zeroed::<libc::winsize>();
+
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut winsize) != 0 {
+            return None;
+        }
+
+        Some((winsize.ws_row, winsize.ws_col)).filter(|&(rows, cols)| rows > 0 && cols > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn iterates_over_events_and_responds_to_them() {
+        let dir  = std::env::temp_dir().join(format!("sudo_pair_client.{}", std::process::id()));
+        let path = dir.join("1000.4242.sock");
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let plugin   = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut response    = Vec::new();
+
+            stream.write_all(b"\x1b]5379;sudo_pair;preview;user=alice;command=ls\x07approve? ").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+
+            let _ = stream.read_to_end(&mut response).unwrap();
+
+            response
+        });
+
+        let mut client = Client::connect(&path, Capabilities::default()).unwrap();
+        let mut events = client.events();
+        let mut seen   = 0;
+
+        while let Some(event) = events.next() {
+            if let Event::Preview(ref preview) = event.unwrap() {
+                assert_eq!("ls", preview.command);
+                events.client().respond(true).unwrap();
+            }
+
+            seen += 1;
+        }
+
+        client.close().unwrap();
+
+        assert_eq!(2, seen);
+        assert_eq!(b"y".to_vec(), plugin.join().unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn holds_itself_to_the_plugins_limits() {
+        let (mut plugin, stream) = UnixStream::pair().unwrap();
+
+        plugin.write_all(b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x80;max_message=512;max_comment=16\x07").unwrap();
+
+        let mut client = Client::from_stream(stream, Capabilities::COMMENTS);
+
+        client.set_limits(Limits { comment: 64, ..Limits::DEFAULT });
+
+        assert!(matches!(client.next_event().unwrap(), Some(Event::Hello { .. })));
+        assert_eq!(Limits { message: 512, comment: 16, ..Limits::DEFAULT }, client.limits());
+
+        assert_eq!(
+            ErrorKind::InvalidInput,
+            client.authenticate(&[0; 1024]).unwrap_err().kind(),
+        );
+
+        client.respond_with_comment(true, "rotating the keys as planned").unwrap();
+        client.close().unwrap();
+
+        let mut sent = Vec::new();
+        let _        = plugin.read_to_end(&mut sent).unwrap();
+        let sent     = String::from_utf8(sent).unwrap();
+
+        assert!(sent.contains(";max_comment=64;"));
+        assert!(sent.ends_with("comment;text=rotating the ke%e2%80%a6\x07y"), "{}", sent);
+    }
+
+    #[test]
+    fn chats_only_once_approved() {
+        let (mut plugin, stream) = UnixStream::pair().unwrap();
+
+        plugin.write_all(b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x400;max_chat=16\x07").unwrap();
+
+        let mut client = Client::from_stream(stream, Capabilities::CHAT);
+
+        assert!(matches!(client.next_event().unwrap(), Some(Event::Hello { .. })));
+
+        assert_eq!(
+            ErrorKind::InvalidInput,
+            client.chat("before approving", false).unwrap_err().kind(),
+        );
+
+        client.respond(true).unwrap();
+        client.chat("hold on, checking the ticket", true).unwrap();
+        client.close().unwrap();
+
+        let mut sent = Vec::new();
+        let _        = plugin.read_to_end(&mut sent).unwrap();
+        let sent     = String::from_utf8(sent).unwrap();
+
+        assert!(sent.ends_with("\x07y\x1b]5379;sudo_pair;chat;text=hold on, checki%e2%80%a6;ask=1\x07"), "{}", sent);
+    }
+}
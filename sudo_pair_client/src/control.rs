@@ -0,0 +1,279 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::protocol::{BEL, PREFIX};
+use crate::{Capabilities, Client};
+
+use std::fs::{self, Permissions};
+use std::io::{Error, Read, Result};
+use std::mem::{self, size_of};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libc::{pid_t, uid_t};
+
+/// The longest announcement a session may send, which is far longer
+/// than any the plugin does.
+const MAX_ANNOUNCEMENT_LEN : usize = 256;
+
+/// How long a session has to announce itself once it's connected, so
+/// one that never does can't hold up the sessions behind it.
+const ANNOUNCEMENT_TIMEOUT : Duration = Duration::from_secs(5);
+
+/// A session announced on a control socket, identified as its own
+/// socket would have been.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Announcement {
+    /// The session's ID, as the plugin logs and records it.
+    pub session_id: String,
+
+    /// The real uid of the user who invoked `sudo`.
+    pub uid: uid_t,
+
+    /// The pid of the `sudo` process.
+    pub pid: pid_t,
+}
+
+impl Announcement {
+    /// Parses an announcement from the message the plugin sends ahead
+    /// of anything else, including its terminating `BEL`.
+    fn parse(message: &[u8]) -> Option<Self> {
+        let text = message.strip_prefix(PREFIX)?.strip_suffix(&[BEL])?;
+        let text = std::str::from_utf8(text).ok()?.strip_prefix("announce;")?;
+
+        let field = |name: &str| text.split(';').find_map(|field| {
+            field.strip_prefix(name)?.strip_prefix('=')
+        });
+
+        Some(Self {
+            session_id: field("session_id")?.into(),
+            uid:        field("uid")?.parse().ok()?,
+            pid:        field("pid")?.parse().ok()?,
+        })
+    }
+}
+
+/// The control socket of an approver daemon, which sessions configured
+/// with the plugin's `control_socket` option connect to and announce
+/// themselves on, rather than each waiting on a socket of its own. One
+/// daemon can then receive every session on the host as it starts.
+///
+/// Only connections from root (which `sudo` runs as) or the daemon's own
+/// user are accepted; anyone else could otherwise pose as a session. The
+/// socket is removed from the filesystem when this is dropped.
+#[derive(Debug)]
+pub struct ControlSocket {
+    listener: UnixListener,
+    path:     PathBuf,
+}
+
+impl ControlSocket {
+    /// Listens on a control socket created at `path`, replacing any
+    /// socket already there, that only its owner (and root) can
+    /// connect to. A `path` starting with `@` names a socket in Linux's
+    /// abstract namespace instead.
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let listener = match path.as_os_str().as_bytes().split_first() {
+            Some((b'@', name)) => bind_abstract(name)?,
+            _                  => {
+                if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    fs::remove_file(&path)?;
+                }
+
+                let listener = UnixListener::bind(&path)?;
+
+                fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+                listener
+            },
+        };
+
+        Ok(Self { listener, path })
+    }
+
+    /// Waits for the next session to announce itself, returning its
+    /// announcement and a client connected to it that announces
+    /// `capabilities` (see `Client::connect`). Connections from anyone
+    /// but root or the daemon's own user are dropped, as are those
+    /// that don't begin with an announcement (or take too long to
+    /// send one).
+    pub fn accept(&self, capabilities: Capabilities) -> Result<(Announcement, Client)> {
+        loop {
+            let (mut stream, _) = self.listener.accept()?;
+
+            if !is_trusted(&stream).unwrap_or(false) {
+                continue;
+            }
+
+            if let Ok(Some(announcement)) = read_announcement(&mut stream) {
+                return Ok((announcement, Client::from_stream(stream, capabilities)));
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().as_bytes().starts_with(b"@") {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Reads the announcement the session sends ahead of anything else,
+/// one byte at a time so that nothing after it is consumed. Returns
+/// `None` if something else was sent instead.
+fn read_announcement(stream: &mut UnixStream) -> Result<Option<Announcement>> {
+    let mut message = Vec::new();
+    let mut byte    = [0; 1];
+
+    stream.set_read_timeout(Some(ANNOUNCEMENT_TIMEOUT))?;
+
+    while message.len() < MAX_ANNOUNCEMENT_LEN {
+        if stream.read(&mut byte)? == 0 {
+            break;
+        }
+
+        message.push(byte[0]);
+
+        if byte[0] == BEL {
+            stream.set_read_timeout(None)?;
+
+            return Ok(Announcement::parse(&message));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether the process on the other end of `stream` is root or the
+/// daemon's own user.
+fn is_trusted(stream: &UnixStream) -> Result<bool> {
+    let uid = peer_uid(stream)?;
+
+    Ok(uid == 0 || uid == unsafe { libc::geteuid() })
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> Result<uid_t> {
+    let mut credentials = mem::MaybeUninit::<libc::ucred>::zeroed();
+    let mut len         = size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe { libc::getsockopt(
+        stream.as_raw_fd(),
+        libc::SOL_SOCKET,
+        libc::SO_PEERCRED,
+        credentials.as_mut_ptr().cast(),
+        &mut len,
+    ) };
+
+    if result == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(unsafe { credentials.assume_init() }.uid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> Result<uid_t> {
+    let mut uid = 0;
+    let mut gid = 0;
+
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(uid)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &[u8]) -> Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    UnixListener::bind_addr(&SocketAddr::from_abstract_name(name)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_: &[u8]) -> Result<UnixListener> {
+    Err(Error::other("abstract sockets are only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Event;
+
+    use std::io::Write;
+    use std::thread;
+
+    #[test]
+    fn parses_announcements() {
+        assert_eq!(
+            Some(Announcement { session_id: "1600000000-4242".into(), uid: 1000, pid: 4242 }),
+            Announcement::parse(b"\x1b]5379;sudo_pair;announce;session_id=1600000000-4242;uid=1000;pid=4242\x07"),
+        );
+
+        assert_eq!(None, Announcement::parse(b"\x1b]5379;sudo_pair;announce;uid=1000;pid=4242\x07"));
+        assert_eq!(None, Announcement::parse(b"\x1b]5379;sudo_pair;hello;version=1\x07"));
+    }
+
+    #[test]
+    fn accepts_announced_sessions() {
+        let dir  = std::env::temp_dir().join(format!("sudo_pair_client.control.{}", std::process::id()));
+        let path = dir.join("control.sock");
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let control = ControlSocket::bind(&path).unwrap();
+
+        assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        let session = {
+            let path = path.clone();
+
+            thread::spawn(move || {
+                // a connection that doesn't announce itself is dropped
+                let mut stray = UnixStream::connect(&path).unwrap();
+                stray.write_all(b"y").unwrap();
+                drop(stray);
+
+                let mut stream = UnixStream::connect(&path).unwrap();
+
+                stream.write_all(b"\x1b]5379;sudo_pair;announce;session_id=1-4242;uid=1000;pid=4242\x07").unwrap();
+                stream.write_all(b"approve? ").unwrap();
+            })
+        };
+
+        let (announcement, mut client) = control.accept(Capabilities::default()).unwrap();
+
+        session.join().unwrap();
+
+        assert_eq!(Announcement { session_id: "1-4242".into(), uid: 1000, pid: 4242 }, announcement);
+        assert_eq!(Some(Event::Output(b"approve? ".to_vec())), client.next_event().unwrap());
+
+        drop(control);
+
+        assert!(!path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
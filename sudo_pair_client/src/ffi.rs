@@ -0,0 +1,435 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! C bindings for third-party approval clients. The corresponding
+//! declarations are in `include/sudo_pair_client.h`, which must be kept
+//! in sync with this module.
+//!
+//! Functions returning a `c_int` return `0` (or `1` where documented)
+//! on success and `-1` on failure.
+
+// this module exists to be called from C
+#![allow(unsafe_code)]
+
+use crate::{Capabilities, Client, Event};
+
+use std::ffi::{CStr, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::ptr;
+use std::time::UNIX_EPOCH;
+
+use libc::{c_char, c_int, size_t};
+
+/// The event contains output to be displayed to the approver.
+pub const SUDO_PAIR_EVENT_OUTPUT        : c_int = 1;
+
+/// The event contains the plugin's version and capabilities.
+pub const SUDO_PAIR_EVENT_HELLO         : c_int = 2;
+
+/// The event contains a digest of the transcript so far.
+pub const SUDO_PAIR_EVENT_CHECKPOINT    : c_int = 3;
+
+/// The event contains a protocol message this library doesn't know.
+pub const SUDO_PAIR_EVENT_UNKNOWN       : c_int = 4;
+
+/// The event contains the size of the user's terminal.
+pub const SUDO_PAIR_EVENT_WINSIZE       : c_int = 5;
+
+/// The event asks the approver to re-approve the session.
+pub const SUDO_PAIR_EVENT_REAPPROVE     : c_int = 6;
+
+/// The event asks the approver to sign a challenge with their SSH key.
+pub const SUDO_PAIR_EVENT_AUTHENTICATE  : c_int = 7;
+
+/// The event asks for the approver's one-time code.
+pub const SUDO_PAIR_EVENT_ONE_TIME_CODE : c_int = 8;
+
+/// The event contains the name of the stream the output that follows
+/// comes from.
+pub const SUDO_PAIR_EVENT_STREAM        : c_int = 9;
+
+/// The event tells how many bytes of output were kept from the approver,
+/// and contains the reason why.
+pub const SUDO_PAIR_EVENT_SUPPRESSED    : c_int = 10;
+
+/// The event contains the tags describing the plugin's host, as
+/// comma-separated `key=value` pairs.
+pub const SUDO_PAIR_EVENT_TAGS          : c_int = 11;
+
+/// The event contains a preview of the session being approved, as
+/// `key=value` lines (e.g., `command=ls /root`), with a `warning` or
+/// `env` line for each warning or highlighted environment variable.
+pub const SUDO_PAIR_EVENT_PREVIEW       : c_int = 12;
+
+/// The event contains the user's reply to a message the approver sent
+/// them.
+pub const SUDO_PAIR_EVENT_CHAT          : c_int = 13;
+
+/// An opaque handle to a connected client.
+#[derive(Debug)]
+pub struct SudoPairClient {
+    client: Client,
+
+    /// backing storage for the `data` of the most recently returned
+    /// event, which must outlive the call that returned it
+    data: Vec<u8>,
+}
+
+/// An event received from the plugin. Which fields are meaningful
+/// depends on `kind`; `data` remains valid until the next call on the
+/// same client.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SudoPairEvent {
+    /// One of the `SUDO_PAIR_EVENT_*` constants.
+    pub kind: c_int,
+
+    /// Output, the name of a stream, the host's tags, a preview of the
+    /// session, the hex-encoded digest of a checkpoint, the challenge to
+    /// sign for an authenticate event, the user's reply for a chat
+    /// event, or the payload of an unknown message.
+    pub data: *const u8,
+
+    /// The length of `data`.
+    pub len: size_t,
+
+    /// The plugin's protocol version, for hello events.
+    pub version: u16,
+
+    /// The plugin's capabilities, for hello events.
+    pub capabilities: u32,
+
+    /// The number of bytes covered by a checkpoint.
+    pub bytes: u64,
+
+    /// The height of the user's terminal, for winsize events.
+    pub rows: u32,
+
+    /// The width of the user's terminal, for winsize events.
+    pub cols: u32,
+
+    /// When the session will be declined if it hasn't been approved, in
+    /// seconds since the epoch, for hello events (`0` if the plugin
+    /// doesn't enforce a deadline); or when it will be ended if it
+    /// hasn't been re-approved, for reapprove events.
+    pub deadline: u64,
+}
+
+/// Connects to the session socket at `path`, announcing
+/// `capabilities`. Returns `NULL` on failure.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_connect(
+    path:         *const c_char,
+    capabilities: u32,
+) -> *mut SudoPairClient {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+
+    let path = OsStr::from_bytes(CStr::from_ptr(path).to_bytes());
+
+    match Client::connect(path, Capabilities(capabilities)) {
+        Ok(client) => Box::into_raw(Box::new(SudoPairClient {
+            client,
+            data: Vec::new(),
+        })),
+
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Blocks until the next event is received, storing it in `event`.
+/// Returns `1` if an event was received and `0` once the session has
+/// ended.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed, and `event` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_next_event(
+    client: *mut SudoPairClient,
+    event:  *mut SudoPairEvent,
+) -> c_int {
+    let (client, event) = match (client.as_mut(), event.as_mut()) {
+        (Some(client), Some(event)) => (client, event),
+        _                           => return -1,
+    };
+
+    let next = match client.client.next_event() {
+        Ok(Some(next)) => next,
+        Ok(None)       => return 0,
+        Err(_)         => return -1,
+    };
+
+    *event = SudoPairEvent {
+        kind:         0,
+        data:         ptr::null(),
+        len:          0,
+        version:      0,
+        capabilities: 0,
+        bytes:        0,
+        rows:         0,
+        cols:         0,
+        deadline:     0,
+    };
+
+    match next {
+        Event::Output(data) => {
+            event.kind  = SUDO_PAIR_EVENT_OUTPUT;
+            client.data = data;
+        },
+
+        Event::Stream(stream) => {
+            event.kind  = SUDO_PAIR_EVENT_STREAM;
+            client.data = stream.name().as_bytes().to_vec();
+        },
+
+        Event::Suppressed { reason, bytes } => {
+            event.kind  = SUDO_PAIR_EVENT_SUPPRESSED;
+            event.bytes = bytes;
+            client.data = reason.into_bytes();
+        },
+
+        Event::Hello { version, capabilities, deadline, .. } => {
+            event.kind         = SUDO_PAIR_EVENT_HELLO;
+            event.version      = version;
+            event.capabilities = capabilities.0;
+            event.deadline     = deadline
+                .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |deadline| deadline.as_secs());
+            client.data.clear();
+        },
+
+        Event::Tags(tags) => {
+            let tags : Vec<_> = tags.iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+
+            event.kind  = SUDO_PAIR_EVENT_TAGS;
+            client.data = tags.join(",").into_bytes();
+        },
+
+        Event::Preview(preview) => {
+            // each field is a line of its own, so none can span lines
+            let lines : Vec<_> = preview.fields().iter()
+                .map(|(key, value)| format!("{}={}", key, value.replace('\n', " ")))
+                .collect();
+
+            event.kind  = SUDO_PAIR_EVENT_PREVIEW;
+            client.data = lines.join("\n").into_bytes();
+        },
+
+        Event::Checkpoint { bytes, sha256 } => {
+            event.kind  = SUDO_PAIR_EVENT_CHECKPOINT;
+            event.bytes = bytes;
+            client.data = sha256.into_bytes();
+        },
+
+        Event::Winsize { rows, cols } => {
+            event.kind = SUDO_PAIR_EVENT_WINSIZE;
+            event.rows = rows;
+            event.cols = cols;
+            client.data.clear();
+        },
+
+        Event::Reapprove { deadline } => {
+            event.kind     = SUDO_PAIR_EVENT_REAPPROVE;
+            event.deadline = deadline.duration_since(UNIX_EPOCH)
+                .map_or(0, |deadline| deadline.as_secs());
+            client.data.clear();
+        },
+
+        // the namespace is always `sudo_pair` today, so only the
+        // challenge is passed along
+        Event::Authenticate { challenge, .. } => {
+            event.kind  = SUDO_PAIR_EVENT_AUTHENTICATE;
+            client.data = challenge.into_bytes();
+        },
+
+        Event::OneTimeCode => {
+            event.kind = SUDO_PAIR_EVENT_ONE_TIME_CODE;
+            client.data.clear();
+        },
+
+        Event::Chat { text } => {
+            event.kind  = SUDO_PAIR_EVENT_CHAT;
+            client.data = text.into_bytes();
+        },
+
+        Event::Unknown(data) => {
+            event.kind  = SUDO_PAIR_EVENT_UNKNOWN;
+            client.data = data;
+        },
+    }
+
+    event.data = client.data.as_ptr();
+    event.len  = client.data.len();
+
+    1
+}
+
+/// Approves the session (or re-approves it, in answer to a reapprove
+/// event) if `approve` is nonzero, and declines it otherwise.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_respond(
+    client:  *mut SudoPairClient,
+    approve: c_int,
+) -> c_int {
+    match client.as_mut().map(|client| client.client.respond(approve != 0)) {
+        Some(Ok(())) => 0,
+        _            => -1,
+    }
+}
+
+/// Approves or declines the session, as `sudo_pair_client_respond`
+/// does, with a NUL-terminated `comment` explaining why. The comment is
+/// left out if the plugin doesn't accept one.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed, and `comment` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_respond_with_comment(
+    client:  *mut SudoPairClient,
+    approve: c_int,
+    comment: *const c_char,
+) -> c_int {
+    if comment.is_null() {
+        return -1;
+    }
+
+    let comment = CStr::from_ptr(comment).to_string_lossy();
+
+    match client.as_mut().map(|client| client.client.respond_with_comment(approve != 0, &comment)) {
+        Some(Ok(())) => 0,
+        _            => -1,
+    }
+}
+
+/// Answers an authenticate event with the `len`-byte binary `SSHSIG`
+/// signature at `sshsig`, which has to be sent before responding.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed, and `sshsig` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_authenticate(
+    client: *mut SudoPairClient,
+    sshsig: *const u8,
+    len:    size_t,
+) -> c_int {
+    if sshsig.is_null() {
+        return -1;
+    }
+
+    let sshsig = std::slice::from_raw_parts(sshsig, len);
+
+    match client.as_mut().map(|client| client.client.authenticate(sshsig)) {
+        Some(Ok(())) => 0,
+        _            => -1,
+    }
+}
+
+/// Answers a one-time code event with the approver's NUL-terminated
+/// `code` and the NUL-terminated name they're provisioned under
+/// (`approver`), which has to be sent before responding.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed, and `approver` and `code` must be valid
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_send_one_time_code(
+    client:   *mut SudoPairClient,
+    approver: *const c_char,
+    code:     *const c_char,
+) -> c_int {
+    if approver.is_null() || code.is_null() {
+        return -1;
+    }
+
+    let approver = CStr::from_ptr(approver).to_string_lossy();
+    let code     = CStr::from_ptr(code).to_string_lossy();
+
+    match client.as_mut().map(|client| client.client.send_one_time_code(&approver, &code)) {
+        Some(Ok(())) => 0,
+        _            => -1,
+    }
+}
+
+/// Sends the user the NUL-terminated message `text` from the approver,
+/// asking them to reply if `ask` is nonzero. Messages can only be sent
+/// once the approver has responded, to plugins that accept them.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed, and `text` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_chat(
+    client: *mut SudoPairClient,
+    text:   *const c_char,
+    ask:    c_int,
+) -> c_int {
+    if text.is_null() {
+        return -1;
+    }
+
+    let text = CStr::from_ptr(text).to_string_lossy();
+
+    match client.as_mut().map(|client| client.client.chat(&text, ask != 0)) {
+        Some(Ok(())) => 0,
+        _            => -1,
+    }
+}
+
+/// Ends the session. The client must still be freed afterward.
+///
+/// # Safety
+///
+/// `client` must have been returned by `sudo_pair_client_connect` and
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_close(client: *mut SudoPairClient) -> c_int {
+    match client.as_mut().map(|client| client.client.close()) {
+        Some(Ok(())) => 0,
+        _            => -1,
+    }
+}
+
+/// Frees a client, closing its connection if it's still open.
+///
+/// # Safety
+///
+/// `client` must be `NULL` or have been returned by
+/// `sudo_pair_client_connect`, and must not be used afterward.
+#[no_mangle]
+pub unsafe extern "C" fn sudo_pair_client_free(client: *mut SudoPairClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Client library for approving `sudo_pair` sessions.
+//!
+//! This crate implements the approver's side of the protocol spoken over
+//! a session's socket: separating the session's output from in-band
+//! protocol messages, negotiating capabilities, and responding to the
+//! approval prompt. It's usable from Rust directly, or from C (and
+//! anything that can call C) through the functions in [`ffi`] and the
+//! header in `include/sudo_pair_client.h`.
+//!
+//! Tools that review sessions themselves (TUIs, chat bots, web
+//! gateways) can embed it rather than wrapping `sudo_pair_approve`: find
+//! a session, connect to it, and respond to the events it sends.
+//!
+//! ```no_run
+//! use sudo_pair_client::{Capabilities, Event, Session, DEFAULT_SOCKET_DIR};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! for session in Session::list(DEFAULT_SOCKET_DIR)? {
+//!     let mut client = session.connect(Capabilities::default())?;
+//!     let mut events = client.events();
+//!
+//!     while let Some(event) = events.next() {
+//!         match event? {
+//!             Event::Preview(preview) if preview.warnings.is_empty() => {
+//!                 println!("approving `{}` for {}", preview.command, preview.user);
+//!                 events.client().respond(true)?;
+//!             },
+//!
+//!             Event::Preview(_) => events.client().respond(false)?,
+//!             Event::Output(output) => print!("{}", String::from_utf8_lossy(&output)),
+//!             _ => (),
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(missing_docs)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unreachable_pub)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+#![warn(variant_size_differences)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+pub mod ffi;
+pub mod protocol;
+pub mod time;
+
+mod client;
+mod control;
+mod session;
+
+pub use self::client::{Client, Events};
+pub use self::control::{Announcement, ControlSocket};
+pub use self::protocol::{Capabilities, Encoding, Event, Limits, Parser, Preview, Stream};
+pub use self::session::{Session, DEFAULT_SOCKET_DIR};
@@ -0,0 +1,1135 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The client's view of the session protocol.
+//!
+//! Protocol messages are sent in-band with the session's output as
+//! `OSC` escape sequences of the form:
+//!
+//! ```text
+//! ESC ] 5379 ; sudo_pair;<message>;<key>=<value>;... BEL
+//! ```
+//!
+//! A client may instead ask for everything it's sent once it's watching
+//! the session to be framed, in one of the `Encoding`s the plugin
+//! offers. The plugin then sends a `framing` message, after which every
+//! frame is a 4-byte big-endian length followed by a map (or for the
+//! `binary` encoding, a type, flags, and payload), and the parser turns
+//! each frame into the same `Event` its message would have been.
+//!
+//! Both sides announce their `Limits` in their `hello`s, and each is
+//! held to the smaller of the two: the plugin splits output across as
+//! many frames as it takes and cuts its reasons and chat messages
+//! short, and the client cuts comments and chat messages short and
+//! refuses to send longer messages.
+
+use std::convert::TryFrom;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use sudo_pair_types::protocol::{truncate, Capabilities, Encoding, Limits, Stream, PROTOCOL_VERSION};
+pub(crate) use sudo_pair_types::protocol::{BEL, PREFIX};
+use sudo_pair_types::base64;
+use sudo_pair_types::frame::{self, Value};
+use sudo_pair_types::protocol::{message, percent_decode, percent_encode, ESC};
+
+/// Something received from the plugin.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Output to be displayed to the approver.
+    Output(Vec<u8>),
+
+    /// The stream the output that follows comes from, sent whenever it
+    /// changes to clients that negotiated `Capabilities::STREAMS`.
+    /// Output before the first is from the plugin.
+    Stream(Stream),
+
+    /// Output that was kept from the approver, sent to clients that
+    /// negotiated `Capabilities::MARKERS`.
+    Suppressed {
+        /// Why it was kept from them: `binary`, `escapes`, or
+        /// `rate_limit`.
+        reason: String,
+
+        /// The number of bytes kept from them.
+        bytes: u64,
+    },
+
+    /// The plugin's protocol version and capabilities.
+    Hello {
+        /// The plugin's protocol version.
+        version: u16,
+
+        /// The capabilities the plugin supports.
+        capabilities: Capabilities,
+
+        /// When the session will be declined if it hasn't been approved,
+        /// if the plugin enforces a deadline.
+        deadline: Option<SystemTime>,
+
+        /// The framed encodings the plugin can send the session in.
+        encodings: Vec<Encoding>,
+
+        /// The largest of everything the plugin accepts from the client,
+        /// or `Limits::DEFAULT` if it didn't say.
+        limits: Limits,
+    },
+
+    /// Tags describing the plugin's host (e.g., its datacenter or
+    /// environment), as `(key, value)` pairs, sent after the plugin's
+    /// hello when it's configured with any.
+    Tags(Vec<(String, String)>),
+
+    /// What the approver is being asked to approve, sent ahead of the
+    /// prompt so it can be shown to them without picking it out of the
+    /// prompt's text. Nothing from the command itself is sent until the
+    /// session is approved with `Client::respond`.
+    Preview(Preview),
+
+    /// A digest of all output sent so far.
+    Checkpoint {
+        /// The number of bytes of output sent so far.
+        bytes: u64,
+
+        /// The hex-encoded SHA-256 digest of that output.
+        sha256: String,
+    },
+
+    /// The size of the user's terminal, sent when the session starts and
+    /// whenever it changes.
+    Winsize {
+        /// The height of the terminal, in rows.
+        rows: u32,
+
+        /// The width of the terminal, in columns.
+        cols: u32,
+    },
+
+    /// A request for the approver to re-approve the session, which they
+    /// answer with `Client::respond`. The session is ended if they
+    /// don't answer by the deadline.
+    Reapprove {
+        /// When the session will be ended if it hasn't been re-approved.
+        deadline: SystemTime,
+    },
+
+    /// A request for the approver to prove who they are before their
+    /// response is accepted, by signing `challenge` with an SSH key the
+    /// plugin trusts. The signature is sent with `Client::authenticate`.
+    Authenticate {
+        /// The namespace the signature must be made in (`ssh-keygen -Y
+        /// sign -n <namespace>`).
+        namespace: String,
+
+        /// The challenge to sign.
+        challenge: String,
+    },
+
+    /// A request for the approver's one-time code, which has to be sent
+    /// with `Client::send_one_time_code` before their response is
+    /// accepted.
+    OneTimeCode,
+
+    /// The user's reply to a message the approver sent them with
+    /// `Client::chat`, sent to clients that negotiated
+    /// `Capabilities::CHAT`.
+    Chat {
+        /// What the user replied, a single line of text.
+        text: String,
+    },
+
+    /// A well-formed protocol message this client doesn't understand,
+    /// which should be ignored.
+    Unknown(Vec<u8>),
+}
+
+/// The session an approver is asked to approve, as given by the
+/// plugin's `preview`. A field the plugin left out (because the preview
+/// would have been too long) is empty.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Preview {
+    /// The user running the command.
+    pub user: String,
+
+    /// The host the command is being run on.
+    pub host: String,
+
+    /// The user the command is being run as.
+    pub runas_user: String,
+
+    /// The group the command is being run as.
+    pub runas_group: String,
+
+    /// The directory the command is being run from.
+    pub cwd: String,
+
+    /// The command being run, possibly shortened.
+    pub command: String,
+
+    /// Warnings about the command (e.g., that it's a shell).
+    pub warnings: Vec<String>,
+
+    /// Environment variables the approver's attention is drawn to.
+    pub env: Vec<String>,
+}
+
+impl Preview {
+    /// Every field of the preview as `(key, value)` pairs, in the order
+    /// the plugin sends them, with a `warning` or `env` pair for each
+    /// warning or highlighted environment variable.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        let mut fields = vec![
+            ("user",        self.user.as_str()),
+            ("host",        self.host.as_str()),
+            ("runas_user",  self.runas_user.as_str()),
+            ("runas_group", self.runas_group.as_str()),
+            ("cwd",         self.cwd.as_str()),
+            ("command",     self.command.as_str()),
+        ];
+
+        fields.extend(self.warnings.iter().map(|warning| ("warning", warning.as_str())));
+        fields.extend(self.env     .iter().map(|line|    ("env",     line.as_str())));
+
+        fields
+    }
+
+    fn from_fields<'a>(fields: impl Iterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut preview = Self::default();
+
+        for (key, value) in fields {
+            let value = decode_text(value);
+
+            match key {
+                "user"        => preview.user        = value,
+                "host"        => preview.host        = value,
+                "runas_user"  => preview.runas_user  = value,
+                "runas_group" => preview.runas_group = value,
+                "cwd"         => preview.cwd         = value,
+                "command"     => preview.command     = value,
+                "warning"     => preview.warnings.push(value),
+                "env"         => preview.env.push(value),
+                _             => (),
+            }
+        }
+
+        preview
+    }
+}
+
+/// Splits the stream received from the plugin into `Event`s. Messages
+/// (and frames) may be split across reads, so any incomplete message is
+/// held back until the rest of it arrives.
+#[derive(Clone, Debug, Default)]
+pub struct Parser {
+    pending: Vec<u8>,
+
+    /// how the stream is encoded from here on, which changes once the
+    /// plugin says it's framing the session
+    encoding: Encoding,
+
+    /// the largest of everything accepted from the plugin: a longer
+    /// message is passed through as output rather than buffered
+    /// indefinitely, a longer frame can't have come from a well-behaved
+    /// plugin (so the stream is treated as plain output from then on),
+    /// and longer reasons are cut short
+    limits: Limits,
+}
+
+impl Parser {
+    /// Constructs a new parser.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holds the plugin to `limits` from here on, rather than to
+    /// `Limits::DEFAULT`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// The limits the plugin is held to.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Adds `data` to the stream, returning every event that can be
+    /// produced so far, in order.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Event> {
+        self.pending.extend_from_slice(data);
+
+        let mut events = Vec::new();
+
+        // each returns whether the encoding changed partway through,
+        // leaving the rest of the stream to be parsed in the new one
+        loop {
+            let changed = match self.encoding {
+                Encoding::Text => self.feed_text(&mut events),
+                _              => self.feed_frames(&mut events),
+            };
+
+            if !changed {
+                return events;
+            }
+        }
+    }
+
+    /// The encoding the stream is currently in.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn feed_text(&mut self, events: &mut Vec<Event>) -> bool {
+        let     buf     = std::mem::take(&mut self.pending);
+        let mut output  = Vec::new();
+        let mut changed = false;
+        let mut pos    = 0;
+
+        while pos < buf.len() {
+            let rest = &buf[pos..];
+
+            let esc = match rest.iter().position(|b| *b == ESC) {
+                Some(esc) => esc,
+                None      => {
+                    output.extend_from_slice(rest);
+                    break;
+                },
+            };
+
+            output.extend_from_slice(&rest[..esc]);
+
+            let candidate = &rest[esc..];
+
+            // the start of what may be a message, split across reads
+            if candidate.len() < PREFIX.len() && PREFIX.starts_with(candidate) {
+                self.pending.extend_from_slice(candidate);
+                break;
+            }
+
+            // some other escape sequence, which is just output
+            if !candidate.starts_with(PREFIX) {
+                output.push(ESC);
+                pos += esc + 1;
+                continue;
+            }
+
+            match candidate.iter().take(self.limits.message).position(|b| *b == BEL) {
+                Some(bel) => {
+                    if !output.is_empty() {
+                        events.push(Event::Output(std::mem::take(&mut output)));
+                    }
+
+                    let payload = &candidate[PREFIX.len() .. bel];
+
+                    pos += esc + bel + 1;
+
+                    if let Some(encoding) = Self::framing(payload) {
+                        self.encoding = encoding;
+                        self.pending.extend_from_slice(&buf[pos..]);
+                        changed = true;
+                        break;
+                    }
+
+                    events.push(self.decode(payload));
+                },
+
+                // an unterminated message that's still within bounds
+                None if candidate.len() < self.limits.message => {
+                    self.pending.extend_from_slice(candidate);
+                    break;
+                },
+
+                // too long to be a message from a well-behaved plugin
+                None => {
+                    output.push(ESC);
+                    pos += esc + 1;
+                },
+            }
+        }
+
+        if !output.is_empty() {
+            events.push(Event::Output(output));
+        }
+
+        changed
+    }
+
+    fn feed_frames(&mut self, events: &mut Vec<Event>) -> bool {
+        let mut pos = 0;
+
+        while let Some(header) = self.pending.get(pos .. pos + 4) {
+            let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+            if len > self.limits.frame {
+                self.encoding = Encoding::Text;
+                let _ = self.pending.drain(.. pos);
+
+                return true;
+            }
+
+            let body = match self.pending.get(pos + 4 .. pos + 4 + len) {
+                Some(body) => body,
+                None       => break,
+            };
+
+            events.push(self.decode_frame(body));
+            pos += 4 + len;
+        }
+
+        let _ = self.pending.drain(.. pos);
+
+        false
+    }
+
+    /// The encoding a `framing` message says everything after it is
+    /// in, or `None` if `payload` isn't one (or names an encoding that
+    /// isn't framed).
+    fn framing(payload: &[u8]) -> Option<Encoding> {
+        let text = str::from_utf8(payload).ok()?;
+
+        match Encoding::from_name(text.strip_prefix("framing;encoding=")?)? {
+            Encoding::Text => None,
+            encoding       => Some(encoding),
+        }
+    }
+
+    fn decode_frame(&self, body: &[u8]) -> Event {
+        let unknown = || Event::Unknown(body.to_vec());
+
+        let fields = match frame::decode(self.encoding, body) {
+            Some(fields) => fields,
+            None         => return unknown(),
+        };
+
+        let field = |name: &str| fields.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value);
+
+        let str  = |name: &str| match field(name) {
+            Some(Value::Str(s)) => Some(s.as_str()),
+            _                   => None,
+        };
+
+        let uint = |name: &str| match field(name) {
+            Some(Value::Uint(n)) => Some(*n),
+            _                    => None,
+        };
+
+        let event = match str("type") {
+            Some("output") => match field("data") {
+                Some(Value::Bytes(data)) => Some(Event::Output(data.clone())),
+                _                        => None,
+            },
+
+            Some("stream") => str("name").and_then(Stream::from_name).map(Event::Stream),
+
+            Some("suppressed") => str("reason").and_then(|reason|
+                uint("bytes").map(|bytes| Event::Suppressed {
+                    reason: truncate(reason, self.limits.reason).into(),
+                    bytes,
+                })
+            ),
+
+            // notices are shown to the approver just like output
+            Some("notice") => str("text").map(|text| Event::Output(text.as_bytes().to_vec())),
+
+            Some("chat") => str("text").map(|text| Event::Chat {
+                text: truncate(text, self.limits.chat).into(),
+            }),
+
+            Some("checkpoint") => uint("bytes").and_then(|bytes|
+                str("sha256").map(|sha256| Event::Checkpoint {
+                    bytes,
+                    sha256: sha256.into(),
+                })
+            ),
+
+            Some("winsize") => uint("rows").and_then(|r| u32::try_from(r).ok()).and_then(|rows|
+                uint("cols").and_then(|c| u32::try_from(c).ok()).map(|cols| Event::Winsize {
+                    rows,
+                    cols,
+                })
+            ),
+
+            Some("reapprove") => uint("deadline").map(|secs|
+                Event::Reapprove { deadline: UNIX_EPOCH + Duration::from_secs(secs) }
+            ),
+
+            _ => None,
+        };
+
+        event.unwrap_or_else(unknown)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Event {
+        let unknown = || Event::Unknown(payload.to_vec());
+
+        let text = match str::from_utf8(payload) {
+            Ok(text) => text,
+            Err(_)   => return unknown(),
+        };
+
+        let message = text.split(';').next().unwrap_or_default();
+        let field   = |name: &str| text.split(';').skip(1).find_map(|field| {
+            let mut kv = field.splitn(2, '=');
+
+            if kv.next() == Some(name) { kv.next() } else { None }
+        });
+
+        let event = match message {
+            "hello" => field("version").and_then(|v| v.parse().ok()).and_then(|version|
+                field("capabilities").and_then(parse_hex).map(|capabilities| Event::Hello {
+                    version,
+                    capabilities: Capabilities(capabilities),
+                    deadline:     field("deadline")
+                        .and_then(|d| d.parse().ok())
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                    encodings:    field("encodings")
+                        .map(|e| e.split(',').filter_map(Encoding::from_name).collect())
+                        .unwrap_or_default(),
+                    limits:       text.split(';').skip(1)
+                        .filter_map(|field| field.split_once('='))
+                        .fold(Limits::DEFAULT, |mut limits, (key, value)| {
+                            let _ = limits.set(key, value);
+                            limits
+                        }),
+                })
+            ),
+
+            "stream" => field("name").and_then(Stream::from_name).map(Event::Stream),
+
+            "suppressed" => field("reason").and_then(|reason|
+                field("bytes").and_then(|b| b.parse().ok()).map(|bytes| Event::Suppressed {
+                    reason: truncate(reason, self.limits.reason).into(),
+                    bytes,
+                })
+            ),
+
+            "checkpoint" => field("bytes").and_then(|b| b.parse().ok()).and_then(|bytes|
+                field("sha256").map(|sha256| Event::Checkpoint {
+                    bytes,
+                    sha256: sha256.into(),
+                })
+            ),
+
+            "winsize" => field("rows").and_then(|r| r.parse().ok()).and_then(|rows|
+                field("cols").and_then(|c| c.parse().ok()).map(|cols| Event::Winsize {
+                    rows,
+                    cols,
+                })
+            ),
+
+            "reapprove" => field("deadline").and_then(|d| d.parse().ok()).map(|secs|
+                Event::Reapprove { deadline: UNIX_EPOCH + Duration::from_secs(secs) }
+            ),
+
+            // a method this client doesn't know how to satisfy is left
+            // for the approver to deal with as an unknown message
+            "authenticate" if field("method") == Some("sshsig") => field("namespace").and_then(|namespace|
+                field("challenge").map(|challenge| Event::Authenticate {
+                    namespace: namespace.into(),
+                    challenge: challenge.into(),
+                })
+            ),
+
+            "authenticate" if field("method") == Some("totp") => Some(Event::OneTimeCode),
+
+            "chat" => field("text").map(|text| Event::Chat {
+                text: truncate(&decode_text(text), self.limits.chat).into(),
+            }),
+
+            "tags" => Some(Event::Tags(text.split(';').skip(1).filter_map(|field| {
+                let mut kv = field.splitn(2, '=');
+
+                Some((kv.next()?.into(), kv.next()?.into()))
+            }).collect())),
+
+            "preview" => Some(Event::Preview(Preview::from_fields(
+                text.split(';').skip(1).filter_map(|field| field.split_once('='))
+            ))),
+
+            _ => None,
+        };
+
+        event.unwrap_or_else(unknown)
+    }
+}
+
+/// Encodes a client `hello` announcing `capabilities`, and asking for
+/// the session to be sent in `encoding` once it's watched. Only ask for
+/// an encoding the plugin offered in its own `hello`. The approver's
+/// timezone is given as the seconds it's ahead of UTC, `utc_offset`,
+/// if it's known (see `time::utc_offset`), as is the size of their
+/// terminal, `winsize`, as rows and columns. The client's `limits` are
+/// the largest of everything it accepts from the plugin.
+pub fn hello(
+    capabilities: Capabilities,
+    encoding:     Encoding,
+    utc_offset:   Option<i32>,
+    winsize:      Option<(u16, u16)>,
+    limits:       Limits,
+) -> Vec<u8> {
+    let mut payload = format!(
+        "hello;version={};capabilities={}{}",
+        PROTOCOL_VERSION,
+        capabilities,
+        limits.fields(),
+    );
+
+    if encoding != Encoding::Text {
+        payload.push_str(&format!(";encoding={}", encoding.name()));
+    }
+
+    if let Some(utc_offset) = utc_offset {
+        payload.push_str(&format!(";utc_offset={}", utc_offset));
+    }
+
+    if let Some((rows, cols)) = winsize {
+        payload.push_str(&format!(";rows={};cols={}", rows, cols));
+    }
+
+    message(&payload)
+}
+
+/// Encodes the approver's `comment` on their response, to be sent
+/// just ahead of it. The plugin rejects comments that aren't a single
+/// line of printable text, or that are longer than it accepts (128
+/// characters, unless it announced otherwise).
+pub fn comment(comment: &str) -> Vec<u8> {
+    message(&format!("comment;text={}", percent_encode(comment.as_bytes())))
+}
+
+/// Encodes a message from the approver to the user, shown on the
+/// user's terminal once the session's been approved; if `ask` is set,
+/// the user is asked to reply, and their reply arrives as an
+/// `Event::Chat`. The plugin rejects messages that aren't a single line
+/// of printable text, or that are longer than it accepts (256
+/// characters, unless it announced otherwise), and ignores them unless
+/// `Capabilities::CHAT` was negotiated.
+pub fn chat(text: &str, ask: bool) -> Vec<u8> {
+    let mut payload = format!("chat;text={}", percent_encode(text.as_bytes()));
+
+    if ask {
+        payload.push_str(";ask=1");
+    }
+
+    message(&payload)
+}
+
+/// Encodes the approver's answer to an `Event::Authenticate`: the
+/// binary `SSHSIG` signature of its challenge, as written (armored) by
+/// `ssh-keygen -Y sign`, with the armor removed. It's sent just ahead of
+/// the response.
+pub fn signature(sshsig: &[u8]) -> Vec<u8> {
+    message(&format!("signature;sshsig={}", base64::encode(sshsig)))
+}
+
+/// Removes the armor `ssh-keygen -Y sign` writes signatures in (the
+/// `BEGIN SSH SIGNATURE` and `END SSH SIGNATURE` lines, and the line
+/// breaks of the base64 between them), returning the binary `SSHSIG`
+/// signature `signature` expects, or `None` if `armored` isn't an
+/// armored signature.
+pub fn dearmor(armored: &str) -> Option<Vec<u8>> {
+    let body = armored.trim()
+        .strip_prefix("-----BEGIN SSH SIGNATURE-----")?
+        .strip_suffix("-----END SSH SIGNATURE-----")?;
+
+    let encoded : String = body.split_whitespace().collect();
+
+    base64::decode(&encoded).filter(|sshsig| !sshsig.is_empty())
+}
+
+/// Encodes the approver's one-time `code` from their authenticator,
+/// along with the name they're provisioned under (`approver`), to be
+/// sent just ahead of the response. The plugin rejects names other than
+/// letters, digits, and `.`, `_`, `-`, and `@`, and codes other than six
+/// digits.
+pub fn one_time_code(approver: &str, code: &str) -> Vec<u8> {
+    message(&format!("totp;approver={};code={}", approver, code))
+}
+
+/// Decodes a value percent-encoded by the plugin, replacing anything
+/// that isn't UTF-8 once decoded. A value that can't be decoded is
+/// shown as it was sent.
+fn decode_text(value: &str) -> String {
+    match percent_decode(value) {
+        Some(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+        None          => value.into(),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(s: &[u8]) -> Event {
+        Event::Output(s.to_vec())
+    }
+
+    #[test]
+    fn passes_through_plain_output() {
+        let mut parser = Parser::new();
+
+        assert_eq!(vec![output(b"hello, world\n")], parser.feed(b"hello, world\n"));
+        assert_eq!(vec![output(b"\x1b[31mred\x1b[0m")], parser.feed(b"\x1b[31mred\x1b[0m"));
+    }
+
+    #[test]
+    fn separates_messages_from_output() {
+        let mut parser = Parser::new();
+
+        let events = parser.feed(
+            b"$ ls\x1b]5379;sudo_pair;checkpoint;bytes=4;sha256=abcd\x07README\n"
+        );
+
+        assert_eq!(vec![
+            output(b"$ ls"),
+            Event::Checkpoint { bytes: 4, sha256: "abcd".into() },
+            output(b"README\n"),
+        ], events);
+    }
+
+    #[test]
+    fn encodes_comments() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;comment;text=denied%3b wrong host %e2%80%94 use db02\x07".to_vec(),
+            comment("denied; wrong host \u{2014} use db02"),
+        );
+    }
+
+    #[test]
+    fn encodes_chats() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;chat;text=hold on%3b checking the ticket\x07".to_vec(),
+            chat("hold on; checking the ticket", false),
+        );
+
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;chat;text=which ticket?;ask=1\x07".to_vec(),
+            chat("which ticket?", true),
+        );
+    }
+
+    #[test]
+    fn parses_chats() {
+        let mut parser = Parser::new();
+
+        parser.set_limits(Limits { chat: 8, ..Limits::DEFAULT });
+
+        assert_eq!(
+            vec![
+                Event::Chat { text: "CHG-42;\u{2026}".into() },
+                Event::Chat { text: "ok".into() },
+            ],
+            parser.feed(b"\x1b]5379;sudo_pair;chat;text=CHG-42%3b done\x07\x1b]5379;sudo_pair;chat;text=ok\x07"),
+        );
+    }
+
+    #[test]
+    fn encodes_signatures() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;signature;sshsig=U1NIU0lHAA==\x07".to_vec(),
+            signature(b"SSHSIG\0"),
+        );
+    }
+
+    #[test]
+    fn encodes_one_time_codes() {
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;totp;approver=alice;code=005924\x07".to_vec(),
+            one_time_code("alice", "005924"),
+        );
+    }
+
+    #[test]
+    fn parses_authentication_requests() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![
+                Event::Authenticate { namespace: "sudo_pair".into(), challenge: "4f2b".into() },
+                Event::OneTimeCode,
+                Event::Unknown(b"authenticate;method=webauthn".to_vec()),
+            ],
+            parser.feed(&[
+                &b"\x1b]5379;sudo_pair;authenticate;method=sshsig;namespace=sudo_pair;challenge=4f2b\x07"[..],
+                &b"\x1b]5379;sudo_pair;authenticate;method=totp\x07"[..],
+                &b"\x1b]5379;sudo_pair;authenticate;method=webauthn\x07"[..],
+            ].concat()),
+        );
+    }
+
+    #[test]
+    fn parses_hello() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Hello { version: 1, capabilities: Capabilities::DIGESTS, deadline: None, encodings: vec![], limits: Limits::DEFAULT }],
+            parser.feed(b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x10\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_hello_deadlines() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Hello {
+                version:      1,
+                capabilities: Capabilities::DIGESTS,
+                deadline:     Some(UNIX_EPOCH + Duration::from_secs(1_600_000_300)),
+                encodings:    vec![],
+                limits:       Limits::DEFAULT,
+            }],
+            parser.feed(b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x10;deadline=1600000300\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_hello_encodings() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Hello {
+                version:      1,
+                capabilities: Capabilities::DIGESTS,
+                deadline:     None,
+                encodings:    vec![Encoding::Json, Encoding::Cbor],
+                limits:       Limits::DEFAULT,
+            }],
+            parser.feed(b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x10;encodings=json,yaml,cbor\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_hello_limits() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Hello {
+                version:      1,
+                capabilities: Capabilities::DIGESTS,
+                deadline:     None,
+                encodings:    vec![],
+                limits:       Limits { message: 512, comment: 64, ..Limits::DEFAULT },
+            }],
+            parser.feed(b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x10;max_message=512;max_comment=64;max_reason=lots\x07"),
+        );
+    }
+
+    #[test]
+    fn cuts_reasons_short() {
+        let mut parser = Parser::new();
+
+        parser.set_limits(Limits { reason: 4, ..Limits::DEFAULT });
+
+        assert_eq!(
+            vec![Event::Suppressed { reason: "rat\u{2026}".into(), bytes: 12 }],
+            parser.feed(b"\x1b]5379;sudo_pair;suppressed;reason=rate_limit;bytes=12\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_frames_once_framed() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![output(b"approve? y\n")],
+            parser.feed(b"approve? y\n\x1b]5379;sudo_pair;framing;encoding=msgpack\x07\0\0"),
+        );
+
+        assert_eq!(Encoding::MessagePack, parser.encoding());
+
+        assert_eq!(Vec::<Event>::new(), parser.feed(b"\0\x16\x82\xa4type\xa6output\xa4data\xc4\x02"));
+
+        assert_eq!(
+            vec![
+                output(b"ls"),
+                Event::Winsize { rows: 24, cols: 300 },
+                Event::Unknown(b"\x81\xa4type\xa6future".to_vec()),
+            ],
+            parser.feed(b"ls\0\0\0\x1c\x83\xa4type\xa7winsize\xa4rows\x18\xa4cols\xcd\x01\x2c\0\0\0\x0d\x81\xa4type\xa6future"),
+        );
+
+        // bytes that look like a message are just part of a frame
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![
+                Event::Output(b"\x1b]5379;sudo_pair;winsize;rows=1;cols=1\x07".to_vec()),
+                Event::Output(b"handed off\n".to_vec()),
+            ],
+            parser.feed(&[
+                &b"\x1b]5379;sudo_pair;framing;encoding=json\x07\0\0\0\x4f"[..],
+                &b"{\"type\":\"output\",\"data\":\"G101Mzc5O3N1ZG9fcGFpcjt3aW5zaXplO3Jvd3M9MTtjb2xzPTEH\"}"[..],
+                &b"\0\0\0\x27{\"type\":\"notice\",\"text\":\"handed off\\n\"}"[..],
+            ].concat()),
+        );
+    }
+
+    #[test]
+    fn parses_binary_frames() {
+        let mut parser = Parser::new();
+
+        let _ = parser.feed(b"\x1b]5379;sudo_pair;framing;encoding=binary\x07");
+
+        assert_eq!(
+            vec![
+                output(b"\x1b]5379;sudo_pair;kill\x07"),
+                Event::Stream(Stream::StdErr),
+                Event::Reapprove { deadline: UNIX_EPOCH + Duration::from_secs(1_600_000_060) },
+                Event::Unknown(b"\x01\x01ls".to_vec()),
+                output(b"handed off\n"),
+                Event::Chat { text: "on it".into() },
+            ],
+            parser.feed(&[
+                &b"\0\0\0\x18\x01\x00\x1b]5379;sudo_pair;kill\x07"[..],
+                &b"\0\0\0\x08\x02\x00stderr"[..],
+                &b"\0\0\0\x0a\x06\x00\0\0\0\0\x5f\x5e\x10\x3c"[..],
+
+                // flags this client doesn't know of leave the frame
+                // unread, rather than misread
+                &b"\0\0\0\x04\x01\x01ls"[..],
+                &b"\0\0\0\x0d\x07\x00handed off\n"[..],
+                &b"\0\0\0\x07\x08\x00on it"[..],
+            ].concat()),
+        );
+    }
+
+    #[test]
+    fn gives_up_on_oversized_frames() {
+        let mut parser = Parser::new();
+
+        let _ = parser.feed(b"\x1b]5379;sudo_pair;framing;encoding=cbor\x07");
+
+        assert_eq!(vec![output(b"\xff\xff\xff\xffls")], parser.feed(b"\xff\xff\xff\xffls"));
+        assert_eq!(Encoding::Text, parser.encoding());
+    }
+
+    #[test]
+    fn ignores_framing_in_unframed_encodings() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Unknown(b"framing;encoding=text".to_vec()), output(b"ls")],
+            parser.feed(b"\x1b]5379;sudo_pair;framing;encoding=text\x07ls"),
+        );
+
+        assert_eq!(Encoding::Text, parser.encoding());
+    }
+
+    #[test]
+    fn parses_reapproval_requests() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Reapprove { deadline: UNIX_EPOCH + Duration::from_secs(1_600_000_060) }],
+            parser.feed(b"\x1b]5379;sudo_pair;reapprove;deadline=1600000060\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_winsize() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Winsize { rows: 50, cols: 132 }],
+            parser.feed(b"\x1b]5379;sudo_pair;winsize;rows=50;cols=132\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_tags() {
+        assert_eq!(
+            vec![Event::Tags(vec![
+                ("datacenter".into(), "sfo1".into()),
+                ("team".into(),       "payments".into()),
+            ])],
+            Parser::new().feed(b"\x1b]5379;sudo_pair;tags;datacenter=sfo1;team=payments\x07"),
+        );
+    }
+
+    #[test]
+    fn parses_previews() {
+        assert_eq!(
+            vec![Event::Preview(Preview {
+                user:        "alice".into(),
+                host:        "db01".into(),
+                runas_user:  "root".into(),
+                runas_group: "".into(),
+                cwd:         "/home/alice".into(),
+                command:     "echo 100%; \u{65e5} \u{fffd}".into(),
+                warnings:    vec!["a shell".into(), "edits /etc/shadow".into()],
+                env:         vec!["LD_PRELOAD=x.so".into()],
+            })],
+            Parser::new().feed(
+                b"\x1b]5379;sudo_pair;preview;user=alice;host=db01;runas_user=root;cwd=/home/alice;\
+                  command=echo 100%25%3b %e6%97%a5 %ff;warning=a shell;warning=edits /etc/shadow;\
+                  env=LD_PRELOAD=x.so\x07",
+            ),
+        );
+    }
+
+    #[test]
+    fn parses_streams() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![
+                Event::Stream(Stream::StdErr),
+                output(b"oops\n"),
+                Event::Unknown(b"stream;name=stdin".to_vec()),
+            ],
+            parser.feed(b"\x1b]5379;sudo_pair;stream;name=stderr\x07oops\n\x1b]5379;sudo_pair;stream;name=stdin\x07"),
+        );
+
+        let _ = parser.feed(b"\x1b]5379;sudo_pair;framing;encoding=json\x07");
+
+        assert_eq!(
+            vec![Event::Stream(Stream::TtyOut)],
+            parser.feed(b"\0\0\0\x21{\"type\":\"stream\",\"name\":\"ttyout\"}"),
+        );
+
+        for stream in &[Stream::TtyOut, Stream::StdOut, Stream::StdErr, Stream::Plugin] {
+            assert_eq!(Some(*stream), Stream::from_name(stream.name()));
+        }
+    }
+
+    #[test]
+    fn parses_suppressions() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Suppressed { reason: "binary".into(), bytes: 4096 }],
+            parser.feed(b"\x1b]5379;sudo_pair;suppressed;reason=binary;bytes=4096\x07"),
+        );
+
+        let _ = parser.feed(b"\x1b]5379;sudo_pair;framing;encoding=json\x07");
+
+        assert_eq!(
+            vec![Event::Suppressed { reason: "rate_limit".into(), bytes: 512 }],
+            parser.feed(b"\0\0\0\x37{\"type\":\"suppressed\",\"reason\":\"rate_limit\",\"bytes\":512}"),
+        );
+    }
+
+    #[test]
+    fn reassembles_messages_split_across_reads() {
+        let mut parser = Parser::new();
+
+        assert_eq!(vec![output(b"a")], parser.feed(b"a\x1b]53"));
+        assert_eq!(Vec::<Event>::new(), parser.feed(b"79;sudo_pair;hello;vers"));
+        assert_eq!(
+            vec![Event::Hello { version: 2, capabilities: Capabilities(0), deadline: None, encodings: vec![], limits: Limits::DEFAULT }, output(b"b")],
+            parser.feed(b"ion=2;capabilities=0\x07b"),
+        );
+    }
+
+    #[test]
+    fn preserves_unknown_messages() {
+        let mut parser = Parser::new();
+
+        assert_eq!(
+            vec![Event::Unknown(b"future;x=1".to_vec())],
+            parser.feed(b"\x1b]5379;sudo_pair;future;x=1\x07"),
+        );
+    }
+
+    #[test]
+    fn gives_up_on_oversized_messages() {
+        let mut parser = Parser::new();
+        let mut stream = PREFIX.to_vec();
+
+        stream.extend_from_slice(&[b'x'; Limits::DEFAULT.message]);
+
+        assert_eq!(vec![output(&stream)], parser.feed(&stream));
+
+        // or ones longer than the client says it accepts
+        let mut parser = Parser::new();
+        let mut stream = PREFIX.to_vec();
+
+        parser.set_limits(Limits::MINIMUM);
+        stream.extend_from_slice(&[b'x'; 1024]);
+
+        assert_eq!(vec![output(&stream)], parser.feed(&stream));
+    }
+
+    #[test]
+    fn bounds_memory_for_runaway_messages() {
+        // more than would fit in memory were any of it retained, when
+        // tests are optimized (`cargo test --release`)
+        let huge  = if cfg!(debug_assertions) { 64 << 20 } else { 4 << 30 };
+        let chunk = vec![b'x'; 64 << 10];
+
+        let mut parser = Parser::new();
+        let mut bytes  = 0;
+
+        // a message that starts but never ends, trickled in a byte at a
+        // time at first so that it's held back
+        for byte in PREFIX {
+            assert!(parser.feed(&[*byte]).is_empty());
+        }
+
+        for _ in 0 .. huge / chunk.len() {
+            for event in parser.feed(&chunk) {
+                match event {
+                    Event::Output(data) => bytes += data.len(),
+                    _                   => panic!("unexpected event"),
+                }
+            }
+
+            assert!(parser.pending.len() < Limits::DEFAULT.message);
+        }
+
+        assert_eq!(huge + PREFIX.len(), bytes + parser.pending.len());
+    }
+
+    #[test]
+    fn encodes_hello() {
+        let limits = Limits::DEFAULT.fields();
+
+        assert_eq!(
+            b"\x1b]5379;sudo_pair;hello;version=1;capabilities=0x11;max_message=512;max_frame=4096;max_prompt=1024;max_comment=16;max_reason=16;max_chat=16\x07".to_vec(),
+            hello(Capabilities(0x11), Encoding::Text, None, None, Limits::MINIMUM),
+        );
+
+        assert_eq!(
+            format!("\x1b]5379;sudo_pair;hello;version=1;capabilities=0x0{};encoding=cbor\x07", limits).into_bytes(),
+            hello(Capabilities(0), Encoding::Cbor, None, None, Limits::DEFAULT),
+        );
+
+        assert_eq!(
+            format!("\x1b]5379;sudo_pair;hello;version=1;capabilities=0x0{};utc_offset=-16200\x07", limits).into_bytes(),
+            hello(Capabilities(0), Encoding::Text, Some(-16_200), None, Limits::DEFAULT),
+        );
+
+        assert_eq!(
+            format!("\x1b]5379;sudo_pair;hello;version=1;capabilities=0x0{};rows=24;cols=80\x07", limits).into_bytes(),
+            hello(Capabilities(0), Encoding::Text, None, Some((24, 80)), Limits::DEFAULT),
+        );
+    }
+
+    #[test]
+    fn dearmors_signatures() {
+        let armored = "-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\nAAAAAQ==\n-----END SSH SIGNATURE-----\n";
+
+        assert_eq!(Some(b"SSHSIG\0\0\0\x01".to_vec()), dearmor(armored));
+
+        assert_eq!(None, dearmor("U1NIU0lHAAAAAQ=="));
+        assert_eq!(None, dearmor("-----BEGIN SSH SIGNATURE-----\n!!\n-----END SSH SIGNATURE-----"));
+        assert_eq!(None, dearmor("-----BEGIN SSH SIGNATURE-----\n-----END SSH SIGNATURE-----"));
+    }
+}
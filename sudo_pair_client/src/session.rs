@@ -0,0 +1,106 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::{Capabilities, Client};
+
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use libc::{pid_t, uid_t};
+
+/// The directory the plugin places session sockets in by default.
+pub const DEFAULT_SOCKET_DIR : &str = "/var/run/sudo_pair";
+
+/// A session awaiting (or under) approval, as described by the name of
+/// its socket.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Session {
+    /// The path to the session's socket.
+    pub path: PathBuf,
+
+    /// The real uid of the user who invoked `sudo`.
+    pub uid: uid_t,
+
+    /// The pid of the `sudo` process.
+    pub pid: pid_t,
+}
+
+impl Session {
+    /// Describes the session whose socket is at `path`, or returns
+    /// `None` if `path` isn't named like a session socket
+    /// (`<uid>.<pid>.sock`, or `<uid>.<pid>.<anything>.sock` for
+    /// sockets whose usual name was taken).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        let name = path.file_name()?.to_str()?.strip_suffix(".sock")?;
+
+        let mut parts = name.splitn(3, '.');
+
+        let uid = parts.next()?.parse().ok()?;
+        let pid = parts.next()?.parse().ok()?;
+
+        Some(Self { path: path.to_owned(), uid, pid })
+    }
+
+    /// Connects to the session, announcing `capabilities` (see
+    /// `Client::connect`).
+    pub fn connect(&self, capabilities: Capabilities) -> Result<Client> {
+        Client::connect(&self.path, capabilities)
+    }
+
+    /// Lists the sessions whose sockets are in `dir`, ordered by uid
+    /// and then pid. Files not named like session sockets are ignored.
+    pub fn list<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>> {
+        let mut sessions = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            if let Some(session) = Self::from_path(entry?.path()) {
+                sessions.push(session);
+            }
+        }
+
+        sessions.sort_by_key(|session| (session.uid, session.pid));
+
+        Ok(sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_socket_names() {
+        assert_eq!(Some(Session {
+            path: "/var/run/sudo_pair/1000.4242.sock".into(),
+            uid:  1000,
+            pid:  4242,
+        }), Session::from_path("/var/run/sudo_pair/1000.4242.sock"));
+
+        assert_eq!(Some(Session {
+            path: "/var/run/sudo_pair/1000.4242.1600000000-4242.sock".into(),
+            uid:  1000,
+            pid:  4242,
+        }), Session::from_path("/var/run/sudo_pair/1000.4242.1600000000-4242.sock"));
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        assert_eq!(None, Session::from_path("/var/run/sudo_pair/1000.sock"));
+        assert_eq!(None, Session::from_path("/var/run/sudo_pair/a.b.sock"));
+        assert_eq!(None, Session::from_path("/var/run/sudo_pair/1000.4242"));
+        assert_eq!(None, Session::from_path("/var/run/sudo_pair"));
+    }
+}
@@ -0,0 +1,21 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Times and durations as they're shown to approvers. The plugin sends
+//! times as seconds since the epoch; they're shown here in the
+//! approver's own timezone, which the client also tells the plugin in
+//! its `hello` so the plugin's own messages can use it. They're shown
+//! just as the plugin shows them, by the same code.
+
+pub use sudo_pair_types::humanize::{clock, duration, utc_offset};
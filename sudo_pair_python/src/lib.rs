@@ -0,0 +1,470 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Python bindings for approving `sudo_pair` sessions.
+//!
+//! This crate wraps [`sudo_pair_client`] as a Python module named
+//! `sudo_pair`, so approval bots can be scripted without reimplementing
+//! the session protocol:
+//!
+//! ```python
+//! import sudo_pair
+//!
+//! APPROVED = {1000, 1001}
+//!
+//! for session in sudo_pair.sessions():
+//!     client = sudo_pair.Client(session.path, sudo_pair.DIGESTS)
+//!
+//!     for event in client:
+//!         if event.kind == "hello":
+//!             client.respond(session.uid in APPROVED)
+//!         elif event.kind == "checkpoint":
+//!             print(session.pid, event.bytes, event.sha256)
+//! ```
+//!
+//! It's built with [maturin](https://github.com/PyO3/maturin) (e.g.,
+//! `maturin build --release`) and isn't part of the default workspace,
+//! since building it requires a Python interpreter.
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_numeric_casts)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use sudo_pair_client::{Capabilities, Client, Event, Session, DEFAULT_SOCKET_DIR};
+
+/// A connection to a session awaiting (or under) approval.
+///
+/// Iterating over a client yields each `Event` received from the plugin
+/// until the session ends.
+#[pyclass(module = "sudo_pair", name = "Client")]
+#[derive(Debug)]
+struct PyClient {
+    client: Client,
+}
+
+#[pymethods]
+impl PyClient {
+    /// Connects to the session socket at `path`, announcing
+    /// `capabilities` (a bitmap of the module's capability constants).
+    #[new]
+    #[pyo3(signature = (path, capabilities = 0))]
+    fn new(py: Python<'_>, path: PathBuf, capabilities: u32) -> PyResult<Self> {
+        let client = py.allow_threads(||
+            Client::connect(path, Capabilities(capabilities))
+        )?;
+
+        Ok(Self { client })
+    }
+
+    /// Blocks until the next event arrives, returning `None` once the
+    /// session has ended.
+    fn next_event(&mut self, py: Python<'_>) -> PyResult<Option<PyEvent>> {
+        let client = &mut self.client;
+        let event  = py.allow_threads(|| client.next_event())?;
+
+        Ok(event.map(PyEvent::from))
+    }
+
+    /// The protocol version and capabilities agreed upon with the
+    /// plugin, as a `(version, capabilities)` tuple, or `None` if the
+    /// plugin hasn't announced itself.
+    #[getter]
+    fn negotiated(&self) -> Option<(u16, u32)> {
+        self.client.negotiated().map(|(version, capabilities)| (version, capabilities.0))
+    }
+
+    /// Approves the session (or re-approves it, in answer to a
+    /// `reapprove` event) if `approve` is true, and declines it
+    /// otherwise.
+    fn respond(&mut self, approve: bool) -> PyResult<()> {
+        Ok(self.client.respond(approve)?)
+    }
+
+    /// Answers an `authenticate` event with the approver's binary
+    /// `SSHSIG` signature of its `challenge`, before responding.
+    fn authenticate(&mut self, sshsig: &[u8]) -> PyResult<()> {
+        Ok(self.client.authenticate(sshsig)?)
+    }
+
+    /// Answers a `one_time_code` event with the approver's current
+    /// `code` and the name they're provisioned under, before
+    /// responding.
+    fn send_one_time_code(&mut self, approver: &str, code: &str) -> PyResult<()> {
+        Ok(self.client.send_one_time_code(approver, code)?)
+    }
+
+    /// Sends the user a message, shown on their terminal, asking them
+    /// to reply (as a `chat` event) if `ask` is true. Messages can only
+    /// be sent once the approver has responded, to plugins that
+    /// negotiated `CHAT`.
+    #[pyo3(signature = (text, ask = false))]
+    fn chat(&mut self, text: &str, ask: bool) -> PyResult<()> {
+        Ok(self.client.chat(text, ask)?)
+    }
+
+    /// Ends the session.
+    fn close(&mut self) -> PyResult<()> {
+        Ok(self.client.close()?)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyEvent>> {
+        self.next_event(py)
+    }
+}
+
+/// An event received from the plugin.
+///
+/// `kind` is one of `"output"`, `"stream"`, `"suppressed"`, `"hello"`,
+/// `"tags"`, `"preview"`, `"checkpoint"`, `"winsize"`, `"reapprove"`,
+/// `"authenticate"`, `"one_time_code"`, `"chat"`, or `"unknown"`.
+/// Attributes that don't apply to the event's kind are `None`.
+#[pyclass(module = "sudo_pair", name = "Event", frozen)]
+#[derive(Debug)]
+struct PyEvent {
+    /// which of the variants of `Event` this is
+    #[pyo3(get)]
+    kind: &'static str,
+
+    /// output, or the payload of an unknown message
+    data: Option<Vec<u8>>,
+
+    /// the stream the output that follows comes from (`"ttyout"`,
+    /// `"stdout"`, `"stderr"`, or `"plugin"`), for stream events
+    #[pyo3(get)]
+    stream: Option<&'static str>,
+
+    /// why output was kept from the approver (`"binary"`, `"escapes"`,
+    /// or `"rate_limit"`), for suppressed events
+    #[pyo3(get)]
+    reason: Option<String>,
+
+    /// the plugin's protocol version, for hello events
+    #[pyo3(get)]
+    version: Option<u16>,
+
+    /// the plugin's capabilities, for hello events
+    #[pyo3(get)]
+    capabilities: Option<u32>,
+
+    /// the tags describing the plugin's host, as `(key, value)` pairs,
+    /// for tags events
+    #[pyo3(get)]
+    tags: Option<Vec<(String, String)>>,
+
+    /// the session being approved, as `(key, value)` pairs (with a
+    /// `warning` or `env` pair for each warning or highlighted
+    /// environment variable), for preview events
+    #[pyo3(get)]
+    preview: Option<Vec<(String, String)>>,
+
+    /// the number of bytes covered by a checkpoint, or kept from the
+    /// approver
+    #[pyo3(get)]
+    bytes: Option<u64>,
+
+    /// the hex-encoded digest of a checkpoint
+    #[pyo3(get)]
+    sha256: Option<String>,
+
+    /// the height of the user's terminal, for winsize events
+    #[pyo3(get)]
+    rows: Option<u32>,
+
+    /// the width of the user's terminal, for winsize events
+    #[pyo3(get)]
+    cols: Option<u32>,
+
+    /// when the session will be declined if it hasn't been approved, in
+    /// seconds since the epoch, for hello events from plugins that
+    /// enforce a deadline; or when it will be ended if it hasn't been
+    /// re-approved, for reapprove events
+    #[pyo3(get)]
+    deadline: Option<u64>,
+
+    /// the challenge to sign, for authenticate events
+    #[pyo3(get)]
+    challenge: Option<String>,
+
+    /// the user's reply to a message from the approver, for chat events
+    #[pyo3(get)]
+    text: Option<String>,
+}
+
+#[pymethods]
+impl PyEvent {
+    /// Output to display to the approver, or the payload of an unknown
+    /// message, as `bytes`.
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyBytes>> {
+        self.data.as_ref().map(|data| PyBytes::new(py, data))
+    }
+
+    fn __repr__(&self) -> String {
+        match self.kind {
+            "stream" => format!(
+                "Event(kind='stream', stream='{}')",
+                self.stream.unwrap_or_default(),
+            ),
+
+            "suppressed" => format!(
+                "Event(kind='suppressed', reason='{}', bytes={})",
+                self.reason.as_deref().unwrap_or_default(),
+                self.bytes.unwrap_or_default(),
+            ),
+
+            "hello" => format!(
+                "Event(kind='hello', version={}, capabilities={:#x})",
+                self.version.unwrap_or_default(),
+                self.capabilities.unwrap_or_default(),
+            ),
+
+            "tags" => {
+                let tags : Vec<_> = self.tags.iter().flatten()
+                    .map(|(key, value)| format!("('{}', '{}')", key, value))
+                    .collect();
+
+                format!("Event(kind='tags', tags=[{}])", tags.join(", "))
+            },
+
+            "preview" => {
+                let preview : Vec<_> = self.preview.iter().flatten()
+                    .map(|(key, value)| format!("('{}', '{}')", key, value))
+                    .collect();
+
+                format!("Event(kind='preview', preview=[{}])", preview.join(", "))
+            },
+
+            "checkpoint" => format!(
+                "Event(kind='checkpoint', bytes={}, sha256='{}')",
+                self.bytes.unwrap_or_default(),
+                self.sha256.as_deref().unwrap_or_default(),
+            ),
+
+            "winsize" => format!(
+                "Event(kind='winsize', rows={}, cols={})",
+                self.rows.unwrap_or_default(),
+                self.cols.unwrap_or_default(),
+            ),
+
+            "reapprove" => format!(
+                "Event(kind='reapprove', deadline={})",
+                self.deadline.unwrap_or_default(),
+            ),
+
+            "authenticate" => format!(
+                "Event(kind='authenticate', challenge='{}')",
+                self.challenge.as_deref().unwrap_or_default(),
+            ),
+
+            "chat" => format!(
+                "Event(kind='chat', text='{}')",
+                self.text.as_deref().unwrap_or_default(),
+            ),
+
+            kind => format!(
+                "Event(kind='{}', len={})",
+                kind,
+                self.data.as_ref().map_or(0, Vec::len),
+            ),
+        }
+    }
+}
+
+impl From<Event> for PyEvent {
+    fn from(event: Event) -> Self {
+        let mut py = Self {
+            kind:         "unknown",
+            data:         None,
+            stream:       None,
+            reason:       None,
+            version:      None,
+            capabilities: None,
+            tags:         None,
+            preview:      None,
+            bytes:        None,
+            sha256:       None,
+            rows:         None,
+            cols:         None,
+            deadline:     None,
+            challenge:    None,
+            text:         None,
+        };
+
+        match event {
+            Event::Output(data) => {
+                py.kind = "output";
+                py.data = Some(data);
+            },
+
+            Event::Stream(stream) => {
+                py.kind   = "stream";
+                py.stream = Some(stream.name());
+            },
+
+            Event::Suppressed { reason, bytes } => {
+                py.kind   = "suppressed";
+                py.reason = Some(reason);
+                py.bytes  = Some(bytes);
+            },
+
+            Event::Hello { version, capabilities, deadline, .. } => {
+                py.kind         = "hello";
+                py.version      = Some(version);
+                py.capabilities = Some(capabilities.0);
+                py.deadline     = deadline
+                    .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+                    .map(|deadline| deadline.as_secs());
+            },
+
+            Event::Tags(tags) => {
+                py.kind = "tags";
+                py.tags = Some(tags);
+            },
+
+            Event::Preview(preview) => {
+                py.kind    = "preview";
+                py.preview = Some(preview.fields().into_iter()
+                    .map(|(key, value)| (key.into(), value.into()))
+                    .collect());
+            },
+
+            Event::Checkpoint { bytes, sha256 } => {
+                py.kind   = "checkpoint";
+                py.bytes  = Some(bytes);
+                py.sha256 = Some(sha256);
+            },
+
+            Event::Winsize { rows, cols } => {
+                py.kind = "winsize";
+                py.rows = Some(rows);
+                py.cols = Some(cols);
+            },
+
+            Event::Reapprove { deadline } => {
+                py.kind     = "reapprove";
+                py.deadline = deadline.duration_since(UNIX_EPOCH).ok()
+                    .map(|deadline| deadline.as_secs());
+            },
+
+            Event::Authenticate { challenge, .. } => {
+                py.kind      = "authenticate";
+                py.challenge = Some(challenge);
+            },
+
+            Event::OneTimeCode => {
+                py.kind = "one_time_code";
+            },
+
+            Event::Chat { text } => {
+                py.kind = "chat";
+                py.text = Some(text);
+            },
+
+            Event::Unknown(data) => {
+                py.data = Some(data);
+            },
+        }
+
+        py
+    }
+}
+
+/// A session awaiting (or under) approval, as described by the name of
+/// its socket.
+#[pyclass(module = "sudo_pair", name = "Session", frozen, get_all)]
+#[derive(Debug)]
+struct PySession {
+    /// the path to the session's socket
+    path: PathBuf,
+
+    /// the real uid of the user who invoked `sudo`
+    uid: u32,
+
+    /// the pid of the `sudo` process
+    pid: i32,
+}
+
+#[pymethods]
+impl PySession {
+    fn __repr__(&self) -> String {
+        format!(
+            "Session(path='{}', uid={}, pid={})",
+            self.path.display(),
+            self.uid,
+            self.pid,
+        )
+    }
+}
+
+/// Lists the sessions whose sockets are in `socket_dir`, ordered by uid
+/// and then pid.
+#[pyfunction]
+#[pyo3(signature = (socket_dir = PathBuf::from(DEFAULT_SOCKET_DIR)))]
+fn sessions(socket_dir: PathBuf) -> PyResult<Vec<PySession>> {
+    Ok(Session::list(socket_dir)?.into_iter().map(|session| PySession {
+        path: session.path,
+        uid:  session.uid,
+        pid:  session.pid,
+    }).collect())
+}
+
+/// Python bindings for approving `sudo_pair` sessions.
+#[pymodule]
+fn sudo_pair(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyClient>()?;
+    m.add_class::<PyEvent>()?;
+    m.add_class::<PySession>()?;
+    m.add_function(wrap_pyfunction!(sessions, m)?)?;
+
+    m.add("PROTOCOL_VERSION",   sudo_pair_client::protocol::PROTOCOL_VERSION)?;
+    m.add("DEFAULT_SOCKET_DIR", DEFAULT_SOCKET_DIR)?;
+
+    m.add("KILL",       Capabilities::KILL.0)?;
+    m.add("PAUSE",      Capabilities::PAUSE.0)?;
+    m.add("OBSERVERS",  Capabilities::OBSERVERS.0)?;
+    m.add("TIMESTAMPS", Capabilities::TIMESTAMPS.0)?;
+    m.add("DIGESTS",    Capabilities::DIGESTS.0)?;
+    m.add("WINSIZE",    Capabilities::WINSIZE.0)?;
+    m.add("REAPPROVAL", Capabilities::REAPPROVAL.0)?;
+    m.add("STREAMS",    Capabilities::STREAMS.0)?;
+    m.add("MARKERS",    Capabilities::MARKERS.0)?;
+    m.add("CHAT",       Capabilities::CHAT.0)?;
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Padded, standard base64 (RFC 4648), as used for output in `json`
+//! frames and for the `SSHSIG` signatures exchanged with approvers.
+//!
+//! Decoding is strict: padding may only end the last four characters,
+//! and nothing but the alphabet and its padding is accepted, so the
+//! plugin and the client agree on exactly which strings are base64.
+
+const ALPHABET : &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `bytes` as padded, standard base64.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() / 3 * 4 + 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate()
+            .fold(0_u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes padded, standard base64, returning `None` if `encoded`
+/// isn't.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let chunks = encoded.as_bytes().chunks_exact(4);
+    let count  = chunks.len();
+
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(count * 3);
+
+    for (i, chunk) in chunks.enumerate() {
+        let last    = i == count - 1;
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut n = 0_u32;
+
+        for &b in &chunk[.. 4 - padding] {
+            let value = ALPHABET.iter().position(|&c| c == b)?;
+
+            n = n << 6 | value as u32;
+        }
+
+        n <<= 6 * padding as u32;
+
+        decoded.extend_from_slice(&n.to_be_bytes()[1 .. 4 - padding]);
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!("",         encode(b""));
+        assert_eq!("Zg==",     encode(b"f"));
+        assert_eq!("Zm8=",     encode(b"fo"));
+        assert_eq!("Zm9v",     encode(b"foo"));
+        assert_eq!("Zm9vYg==", encode(b"foob"));
+        assert_eq!("/+8=",     encode(b"\xff\xef"));
+    }
+
+    #[test]
+    fn decodes_base64() {
+        for &bytes in &[&b""[..], b"f", b"fo", b"foo", b"foob", b"\xff\xef"] {
+            assert_eq!(Some(bytes.to_vec()), decode(&encode(bytes)));
+        }
+
+        assert_eq!(None, decode("Zg="));
+        assert_eq!(None, decode("Z==="));
+        assert_eq!(None, decode("Zm9*"));
+        assert_eq!(None, decode("Zm9v\n"));
+    }
+
+    #[test]
+    fn rejects_padding_before_the_end() {
+        assert_eq!(None, decode("Zg==Zg=="));
+        assert_eq!(None, decode("Zm8=Zm9v"));
+    }
+}
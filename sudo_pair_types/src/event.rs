@@ -0,0 +1,181 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Events describing sessions to the commands configured to react to
+//! them (e.g., the close hook), which are given each event as
+//! `SUDO_PAIR_*` environment variables.
+
+use crate::manifest::Outcome;
+
+use std::time::Duration;
+
+/// How a session ended, as described to the close hook.
+#[derive(Clone, Debug)]
+pub struct CloseEvent {
+    /// The session's ID, as in its manifest.
+    pub session_id: String,
+
+    /// The user who ran the command.
+    pub user: String,
+
+    /// The user the command ran as.
+    pub runas_user: String,
+
+    /// The command that was run.
+    pub executable: String,
+
+    /// Whether a pair approved the session.
+    pub paired: bool,
+
+    /// The ticket the user cited for the session, if any.
+    pub ticket: Option<String>,
+
+    /// The comments approvers attached to their approvals.
+    pub approver_comments: Vec<String>,
+
+    /// The labels the policy plugin attached to the session.
+    pub labels: Vec<String>,
+
+    /// The ID grouping the session with the others of the same change,
+    /// if it has one.
+    pub correlation_id: Option<String>,
+
+    /// How the command ended.
+    pub outcome: Outcome,
+
+    /// How long the session ran.
+    pub duration: Duration,
+
+    /// The number of bytes of output the command wrote.
+    pub output_bytes: u64,
+
+    /// The number of bytes sent to the approver, which may differ from
+    /// `output_bytes` if output was sanitized or summarized.
+    pub approver_bytes: u64,
+
+    /// The hex-encoded SHA-256 digest of the bytes sent to the
+    /// approver.
+    pub approver_sha256: String,
+}
+
+impl CloseEvent {
+    /// The environment the close hook is given. Variables that don't
+    /// apply to how the command ended are left unset, rather than
+    /// given a value that could be mistaken for a real one.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("SUDO_PAIR_EVENT",           "close".into()),
+            ("SUDO_PAIR_SESSION_ID",      self.session_id.clone()),
+            ("SUDO_PAIR_USER",            self.user.clone()),
+            ("SUDO_PAIR_RUNAS_USER",      self.runas_user.clone()),
+            ("SUDO_PAIR_COMMAND",         self.executable.clone()),
+            ("SUDO_PAIR_PAIRED",          if self.paired { "1" } else { "0" }.into()),
+            ("SUDO_PAIR_DURATION_MS",     self.duration.as_millis().to_string()),
+            ("SUDO_PAIR_OUTPUT_BYTES",    self.output_bytes.to_string()),
+            ("SUDO_PAIR_APPROVER_BYTES",  self.approver_bytes.to_string()),
+            ("SUDO_PAIR_APPROVER_SHA256", self.approver_sha256.clone()),
+        ];
+
+        let (outcome, detail) = match self.outcome {
+            Outcome::Exited(code)     => ("exited",   Some(("SUDO_PAIR_EXIT_STATUS", code))),
+            Outcome::Signaled(signal) => ("signaled", Some(("SUDO_PAIR_SIGNAL",      signal))),
+            Outcome::NotRun(errno)    => ("failed",   Some(("SUDO_PAIR_ERRNO",       errno))),
+            Outcome::Unknown          => ("unknown",  None),
+
+            // hooks run as the session closes, which an incomplete
+            // session never did
+            Outcome::Incomplete       => ("unknown",  None),
+        };
+
+        env.push(("SUDO_PAIR_OUTCOME", outcome.into()));
+
+        if let Some(ticket) = self.ticket.as_ref() {
+            env.push(("SUDO_PAIR_TICKET", ticket.clone()));
+        }
+
+        // comments are a single line each, so they can be told apart
+        if !self.approver_comments.is_empty() {
+            env.push(("SUDO_PAIR_APPROVER_COMMENTS", self.approver_comments.join("\n")));
+        }
+
+        if !self.labels.is_empty() {
+            env.push(("SUDO_PAIR_LABELS", self.labels.join(",")));
+        }
+
+        if let Some(id) = self.correlation_id.as_ref() {
+            env.push(("SUDO_PAIR_CORRELATION_ID", id.clone()));
+        }
+
+        if let Some((key, value)) = detail {
+            env.push((key, value.to_string()));
+        }
+
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn event(outcome: Outcome) -> CloseEvent {
+        CloseEvent {
+            session_id:     "1600000000-31337".into(),
+            user:           "alice".into(),
+            runas_user:     "postgres".into(),
+            executable:     "/usr/bin/psql".into(),
+            paired:         true,
+            outcome,
+            duration:       Duration::from_millis(61_500),
+            output_bytes:   42,
+
+            approver_bytes:  40,
+            approver_sha256: "ba7816bf".into(),
+
+            ticket:            Some("INC-1234".into()),
+            approver_comments: vec!["approved for INC-1234".into()],
+            labels:            vec!["pci-scope".into(), "prod".into()],
+            correlation_id:    Some("CHG-42".into()),
+        }
+    }
+
+    #[test]
+    fn describes_how_sessions_ended() {
+        let env : HashMap<_, _> = event(Outcome::Signaled(9)).env().into_iter().collect();
+
+        assert_eq!("close",    env["SUDO_PAIR_EVENT"]);
+        assert_eq!("signaled", env["SUDO_PAIR_OUTCOME"]);
+        assert_eq!("9",        env["SUDO_PAIR_SIGNAL"]);
+        assert_eq!("61500",    env["SUDO_PAIR_DURATION_MS"]);
+        assert_eq!("42",       env["SUDO_PAIR_OUTPUT_BYTES"]);
+        assert_eq!("40",       env["SUDO_PAIR_APPROVER_BYTES"]);
+        assert_eq!("ba7816bf", env["SUDO_PAIR_APPROVER_SHA256"]);
+        assert_eq!("1",        env["SUDO_PAIR_PAIRED"]);
+        assert!(!env.contains_key("SUDO_PAIR_EXIT_STATUS"));
+
+        assert_eq!("INC-1234",              env["SUDO_PAIR_TICKET"]);
+        assert_eq!("approved for INC-1234", env["SUDO_PAIR_APPROVER_COMMENTS"]);
+        assert_eq!("pci-scope,prod",        env["SUDO_PAIR_LABELS"]);
+        assert_eq!("CHG-42",                env["SUDO_PAIR_CORRELATION_ID"]);
+
+        let env : HashMap<_, _> = event(Outcome::Exited(0)).env().into_iter().collect();
+
+        assert_eq!("exited", env["SUDO_PAIR_OUTCOME"]);
+        assert_eq!("0",      env["SUDO_PAIR_EXIT_STATUS"]);
+        assert!(!env.contains_key("SUDO_PAIR_SIGNAL"));
+    }
+}
@@ -0,0 +1,841 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! The frames a session is sent in once it's watched, if the client
+//! asked for a framed `Encoding`.
+//!
+//! Every frame is a 4-byte big-endian length followed by its body. In
+//! the `json`, `msgpack`, and `cbor` encodings, the body is a map of a
+//! `type` and the fields of a message of that type, whose values are
+//! strings, unsigned integers, or (for the `data` of output) byte
+//! strings; since JSON has no byte strings, `data` is base64-encoded in
+//! JSON frames. `binary` frames aren't maps at all, but a byte giving
+//! their type (see `Message::code`), a byte of flags, and a payload laid
+//! out for the type, which are decoded into the same fields the other
+//! encodings would have sent. No flags are defined yet; they're always
+//! zero, and frames with any set are rejected, so flags can be added
+//! without older clients misreading the frames that carry them.
+//!
+//! The plugin encodes frames and the client decodes them with what's
+//! here, so the two can't drift apart. Only what the plugin produces is
+//! decoded, so these aren't general-purpose JSON, MessagePack, or CBOR
+//! parsers.
+
+use crate::base64;
+use crate::json::quote;
+use crate::protocol::{Encoding, Stream};
+
+use std::convert::TryFrom;
+use std::str;
+
+/// Something sent to the approver watching a session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Message<'a> {
+    /// Output of the session.
+    Output(&'a [u8]),
+
+    /// The stream the output that follows was written to.
+    Stream(Stream),
+
+    /// Output that wasn't sent, and why.
+    Suppressed {
+        /// why the output wasn't sent
+        reason: &'a str,
+
+        /// how much output wasn't sent
+        bytes: u64,
+    },
+
+    /// The digest of everything sent so far.
+    Checkpoint {
+        /// how many bytes have been sent so far
+        bytes: u64,
+
+        /// the hex-encoded SHA-256 digest of those bytes
+        sha256: &'a str,
+    },
+
+    /// The size of the user's terminal.
+    Winsize {
+        /// the terminal's height
+        rows: u32,
+
+        /// the terminal's width
+        cols: u32,
+    },
+
+    /// The session must be approved again by `deadline`, in seconds
+    /// since the epoch.
+    Reapprove {
+        /// when the session ends unless it's approved again
+        deadline: u64,
+    },
+
+    /// A notice from the plugin to the approver.
+    Notice(&'a str),
+
+    /// A message from the user.
+    Chat(&'a str),
+}
+
+/// A value in a frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    /// A string.
+    Str(String),
+
+    /// An unsigned integer.
+    Uint(u64),
+
+    /// A byte string (in JSON frames, decoded from base64).
+    Bytes(Vec<u8>),
+}
+
+/// A value in a frame being encoded.
+#[derive(Clone, Copy, Debug)]
+enum Field<'a> {
+    Str(&'a str),
+    Uint(u64),
+    Bytes(&'a [u8]),
+}
+
+impl Message<'_> {
+    /// The message's name, as the `type` of its frame.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Message::Output(_)         => "output",
+            Message::Stream(_)         => "stream",
+            Message::Suppressed { .. } => "suppressed",
+            Message::Checkpoint { .. } => "checkpoint",
+            Message::Winsize { .. }    => "winsize",
+            Message::Reapprove { .. }  => "reapprove",
+            Message::Notice(_)         => "notice",
+            Message::Chat(_)           => "chat",
+        }
+    }
+
+    /// The byte identifying the message's type in `binary` frames.
+    pub fn code(&self) -> u8 {
+        match *self {
+            Message::Output(_)         => 1,
+            Message::Stream(_)         => 2,
+            Message::Suppressed { .. } => 3,
+            Message::Checkpoint { .. } => 4,
+            Message::Winsize { .. }    => 5,
+            Message::Reapprove { .. }  => 6,
+            Message::Notice(_)         => 7,
+            Message::Chat(_)           => 8,
+        }
+    }
+
+    /// The message's type, followed by its fields.
+    fn fields(&self) -> Vec<(&'static str, Field<'_>)> {
+        let mut fields = match *self {
+            Message::Output(data) => vec![
+                ("data", Field::Bytes(data)),
+            ],
+
+            Message::Stream(stream) => vec![
+                ("name", Field::Str(stream.name())),
+            ],
+
+            Message::Suppressed { reason, bytes } => vec![
+                ("reason", Field::Str(reason)),
+                ("bytes",  Field::Uint(bytes)),
+            ],
+
+            Message::Checkpoint { bytes, sha256 } => vec![
+                ("bytes",  Field::Uint(bytes)),
+                ("sha256", Field::Str(sha256)),
+            ],
+
+            Message::Winsize { rows, cols } => vec![
+                ("rows", Field::Uint(rows.into())),
+                ("cols", Field::Uint(cols.into())),
+            ],
+
+            Message::Reapprove { deadline } => vec![
+                ("deadline", Field::Uint(deadline)),
+            ],
+
+            Message::Notice(text) | Message::Chat(text) => vec![
+                ("text", Field::Str(text)),
+            ],
+        };
+
+        fields.insert(0, ("type", Field::Str(self.name())));
+        fields
+    }
+}
+
+/// Encodes `message` as a frame in `encoding`, length and all, or
+/// `None` for the `text` encoding, which isn't framed.
+pub fn encode(encoding: Encoding, message: &Message<'_>) -> Option<Vec<u8>> {
+    let body = match encoding {
+        Encoding::Text        => return None,
+        Encoding::Json        => json(message),
+        Encoding::MessagePack => msgpack(message),
+        Encoding::Cbor        => cbor(message),
+        Encoding::Binary      => binary(message),
+    };
+
+    // frames are far smaller than 4GiB, since they hold at most one of
+    // the command's writes
+    let len = u32::try_from(body.len()).unwrap_or(u32::MAX);
+
+    Some([&len.to_be_bytes()[..], &body].concat())
+}
+
+/// Decodes the body of a frame (without its length) into its fields,
+/// in the order they were sent, or `None` if it isn't a well-formed
+/// frame.
+pub fn decode(encoding: Encoding, body: &[u8]) -> Option<Vec<(String, Value)>> {
+    let mut reader = Reader { bytes: body, pos: 0 };
+
+    let fields = match encoding {
+        Encoding::Text        => None,
+        Encoding::Json        => reader.json(),
+        Encoding::MessagePack => reader.msgpack(),
+        Encoding::Cbor        => reader.cbor(),
+        Encoding::Binary      => reader.binary(),
+    }?;
+
+    // anything after the map means it wasn't a frame we understand
+    if reader.pos != body.len() {
+        return None;
+    }
+
+    Some(fields)
+}
+
+fn json(message: &Message<'_>) -> Vec<u8> {
+    let fields = message.fields().into_iter().map(|(key, value)| {
+        let value = match value {
+            Field::Str(s)   => quote(s),
+            Field::Uint(n)  => n.to_string(),
+            Field::Bytes(b) => quote(&base64::encode(b)),
+        };
+
+        format!("{}:{}", quote(key), value)
+    });
+
+    format!("{{{}}}", fields.collect::<Vec<_>>().join(",")).into_bytes()
+}
+
+fn msgpack(message: &Message<'_>) -> Vec<u8> {
+    // the lengths of strings (0xa0, 0xd9, 0xda, 0xdb) and byte strings
+    // (0xc4, 0xc5, 0xc6), for lengths that fit in their short forms
+    fn header(out: &mut Vec<u8>, fix: Option<u8>, sized: [u8; 3], len: usize) {
+        match (fix, len) {
+            (Some(fix), len) if len < 32 => out.push(fix | len as u8),
+            (_, len) if len <= 0xff      => out.extend_from_slice(&[sized[0], len as u8]),
+            (_, len) if len <= 0xffff    => {
+                out.push(sized[1]);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            (_, len) => {
+                out.push(sized[2]);
+                out.extend_from_slice(&(len as u32).to_be_bytes());
+            },
+        }
+    }
+
+    let str = |out: &mut Vec<u8>, s: &str| {
+        header(out, Some(0xa0), [0xd9, 0xda, 0xdb], s.len());
+        out.extend_from_slice(s.as_bytes());
+    };
+
+    let fields  = message.fields();
+    let mut out = vec![0x80 | fields.len() as u8];
+
+    for (key, value) in fields {
+        str(&mut out, key);
+
+        match value {
+            Field::Str(s) => str(&mut out, s),
+
+            Field::Uint(n) => match n {
+                0 ..= 0x7f               => out.push(n as u8),
+                0x80 ..= 0xff            => out.extend_from_slice(&[0xcc, n as u8]),
+                0x100 ..= 0xffff         => {
+                    out.push(0xcd);
+                    out.extend_from_slice(&(n as u16).to_be_bytes());
+                },
+                0x1_0000 ..= 0xffff_ffff => {
+                    out.push(0xce);
+                    out.extend_from_slice(&(n as u32).to_be_bytes());
+                },
+                _ => {
+                    out.push(0xcf);
+                    out.extend_from_slice(&n.to_be_bytes());
+                },
+            },
+
+            Field::Bytes(b) => {
+                header(&mut out, None, [0xc4, 0xc5, 0xc6], b.len());
+                out.extend_from_slice(b);
+            },
+        }
+    }
+
+    out
+}
+
+fn cbor(message: &Message<'_>) -> Vec<u8> {
+    // every item begins with its major type and an argument (a length,
+    // or the value of an integer), in as few bytes as it fits
+    fn head(out: &mut Vec<u8>, major: u8, arg: u64) {
+        let major = major << 5;
+
+        match arg {
+            0 ..= 23          => out.push(major | arg as u8),
+            24 ..= 0xff       => out.extend_from_slice(&[major | 24, arg as u8]),
+            0x100 ..= 0xffff  => {
+                out.push(major | 25);
+                out.extend_from_slice(&(arg as u16).to_be_bytes());
+            },
+            0x1_0000 ..= 0xffff_ffff => {
+                out.push(major | 26);
+                out.extend_from_slice(&(arg as u32).to_be_bytes());
+            },
+            _ => {
+                out.push(major | 27);
+                out.extend_from_slice(&arg.to_be_bytes());
+            },
+        }
+    }
+
+    let str = |out: &mut Vec<u8>, s: &str| {
+        head(out, 3, s.len() as u64);
+        out.extend_from_slice(s.as_bytes());
+    };
+
+    let fields  = message.fields();
+    let mut out = Vec::new();
+
+    head(&mut out, 5, fields.len() as u64);
+
+    for (key, value) in fields {
+        str(&mut out, key);
+
+        match value {
+            Field::Str(s)   => str(&mut out, s),
+            Field::Uint(n)  => head(&mut out, 0, n),
+            Field::Bytes(b) => {
+                head(&mut out, 2, b.len() as u64);
+                out.extend_from_slice(b);
+            },
+        }
+    }
+
+    out
+}
+
+fn binary(message: &Message<'_>) -> Vec<u8> {
+    // no flags are defined yet
+    let mut out = vec![message.code(), 0];
+
+    // integers are big-endian, and come before any string, which runs
+    // to the end of the frame
+    match *message {
+        Message::Output(data) => out.extend_from_slice(data),
+        Message::Stream(stream) => out.extend_from_slice(stream.name().as_bytes()),
+
+        Message::Suppressed { reason, bytes } => {
+            out.extend_from_slice(&bytes.to_be_bytes());
+            out.extend_from_slice(reason.as_bytes());
+        },
+
+        Message::Checkpoint { bytes, sha256 } => {
+            out.extend_from_slice(&bytes.to_be_bytes());
+            out.extend_from_slice(sha256.as_bytes());
+        },
+
+        Message::Winsize { rows, cols } => {
+            out.extend_from_slice(&rows.to_be_bytes());
+            out.extend_from_slice(&cols.to_be_bytes());
+        },
+
+        Message::Reapprove { deadline } => out.extend_from_slice(&deadline.to_be_bytes()),
+        Message::Notice(text) | Message::Chat(text) => out.extend_from_slice(text.as_bytes()),
+    }
+
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos:   usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        let taken = self.bytes.get(self.pos .. self.pos.checked_add(n)?)?;
+
+        self.pos += n;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Reads an `n`-byte big-endian unsigned integer.
+    fn uint(&mut self, n: usize) -> Option<u64> {
+        Some(self.take(n)?.iter().fold(0, |uint, b| uint << 8 | u64::from(*b)))
+    }
+
+    fn string(&mut self, len: u64) -> Option<String> {
+        let bytes = self.take(usize::try_from(len).ok()?)?;
+
+        str::from_utf8(bytes).ok().map(Into::into)
+    }
+
+    fn bytes(&mut self, len: u64) -> Option<Vec<u8>> {
+        self.take(usize::try_from(len).ok()?).map(<[u8]>::to_vec)
+    }
+
+    /// Everything left in the frame.
+    fn rest(&mut self) -> Vec<u8> {
+        let rest = self.bytes[self.pos ..].to_vec();
+
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    fn rest_string(&mut self) -> Option<String> {
+        String::from_utf8(self.rest()).ok()
+    }
+
+    /// Reads a `binary` frame: its type, its flags, and a payload laid
+    /// out for the type, with any integers (big-endian) ahead of the
+    /// string or bytes that run to the end of the frame. No flags are
+    /// defined yet, so a frame with any set (or of a type that isn't
+    /// known) can't be read.
+    fn binary(&mut self) -> Option<Vec<(String, Value)>> {
+        let code = self.byte()?;
+
+        if self.byte()? != 0 {
+            return None;
+        }
+
+        let (kind, fields) = match code {
+            1 => ("output", vec![("data", Value::Bytes(self.rest()))]),
+            2 => ("stream", vec![("name", Value::Str(self.rest_string()?))]),
+
+            3 => {
+                let bytes = self.uint(8)?;
+
+                ("suppressed", vec![("reason", Value::Str(self.rest_string()?)), ("bytes", Value::Uint(bytes))])
+            },
+
+            4 => {
+                let bytes = self.uint(8)?;
+
+                ("checkpoint", vec![("bytes", Value::Uint(bytes)), ("sha256", Value::Str(self.rest_string()?))])
+            },
+
+            5 => ("winsize",   vec![("rows", Value::Uint(self.uint(4)?)), ("cols", Value::Uint(self.uint(4)?))]),
+            6 => ("reapprove", vec![("deadline", Value::Uint(self.uint(8)?))]),
+            7 => ("notice",    vec![("text", Value::Str(self.rest_string()?))]),
+            8 => ("chat",      vec![("text", Value::Str(self.rest_string()?))]),
+
+            _ => return None,
+        };
+
+        Some(Some(("type", Value::Str(kind.into()))).into_iter()
+            .chain(fields)
+            .map(|(key, value)| (key.into(), value))
+            .collect())
+    }
+
+    fn json(&mut self) -> Option<Vec<(String, Value)>> {
+        let mut fields = Vec::new();
+
+        self.json_skip_whitespace();
+
+        if self.byte()? != b'{' {
+            return None;
+        }
+
+        self.json_skip_whitespace();
+
+        if self.peek()? == b'}' {
+            self.pos += 1;
+            return Some(fields);
+        }
+
+        loop {
+            self.json_skip_whitespace();
+
+            let key = self.json_string()?;
+
+            self.json_skip_whitespace();
+
+            if self.byte()? != b':' {
+                return None;
+            }
+
+            self.json_skip_whitespace();
+
+            let value = match self.peek()? {
+                b'"'          => Value::Str(self.json_string()?),
+                b'0' ..= b'9' => Value::Uint(self.json_uint()?),
+                _             => return None,
+            };
+
+            // JSON has no byte strings, so output is base64-encoded
+            let value = match value {
+                Value::Str(ref data) if key == "data" => Value::Bytes(base64::decode(data)?),
+                value                                 => value,
+            };
+
+            fields.push((key, value));
+
+            self.json_skip_whitespace();
+
+            match self.byte()? {
+                b',' => continue,
+                b'}' => break,
+                _    => return None,
+            }
+        }
+
+        self.json_skip_whitespace();
+
+        Some(fields)
+    }
+
+    fn json_skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn json_uint(&mut self) -> Option<u64> {
+        let start = self.pos;
+
+        while let Some(b'0' ..= b'9') = self.peek() {
+            self.pos += 1;
+        }
+
+        str::from_utf8(&self.bytes[start .. self.pos]).ok()?.parse().ok()
+    }
+
+    fn json_string(&mut self) -> Option<String> {
+        if self.byte()? != b'"' {
+            return None;
+        }
+
+        let mut string = Vec::new();
+
+        loop {
+            match self.byte()? {
+                b'"'  => break,
+                b'\\' => match self.byte()? {
+                    b'"'  => string.push(b'"'),
+                    b'\\' => string.push(b'\\'),
+                    b'/'  => string.push(b'/'),
+                    b'b'  => string.push(0x08),
+                    b'f'  => string.push(0x0c),
+                    b'n'  => string.push(b'\n'),
+                    b'r'  => string.push(b'\r'),
+                    b't'  => string.push(b'\t'),
+
+                    // the plugin only escapes control characters this
+                    // way, so surrogate pairs aren't needed
+                    b'u'  => {
+                        let hex = str::from_utf8(self.take(4)?).ok()?;
+                        let c   = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+
+                        string.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    },
+
+                    _ => return None,
+                },
+
+                byte => string.push(byte),
+            }
+        }
+
+        String::from_utf8(string).ok()
+    }
+
+    fn msgpack(&mut self) -> Option<Vec<(String, Value)>> {
+        let len = match self.byte()? {
+            b @ 0x80 ..= 0x8f => u64::from(b & 0x0f),
+            0xde              => self.uint(2)?,
+            0xdf              => self.uint(4)?,
+            _                 => return None,
+        };
+
+        let mut fields = Vec::new();
+
+        for _ in 0 .. len {
+            let key = match self.msgpack_value()? {
+                Value::Str(key) => key,
+                _               => return None,
+            };
+
+            fields.push((key, self.msgpack_value()?));
+        }
+
+        Some(fields)
+    }
+
+    fn msgpack_value(&mut self) -> Option<Value> {
+        let value = match self.byte()? {
+            b @ 0x00 ..= 0x7f => Value::Uint(u64::from(b)),
+            b @ 0xa0 ..= 0xbf => Value::Str(self.string(u64::from(b & 0x1f))?),
+
+            0xcc => Value::Uint(self.uint(1)?),
+            0xcd => Value::Uint(self.uint(2)?),
+            0xce => Value::Uint(self.uint(4)?),
+            0xcf => Value::Uint(self.uint(8)?),
+
+            0xd9 => { let len = self.uint(1)?; Value::Str(self.string(len)?) },
+            0xda => { let len = self.uint(2)?; Value::Str(self.string(len)?) },
+            0xdb => { let len = self.uint(4)?; Value::Str(self.string(len)?) },
+
+            0xc4 => { let len = self.uint(1)?; Value::Bytes(self.bytes(len)?) },
+            0xc5 => { let len = self.uint(2)?; Value::Bytes(self.bytes(len)?) },
+            0xc6 => { let len = self.uint(4)?; Value::Bytes(self.bytes(len)?) },
+
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn cbor(&mut self) -> Option<Vec<(String, Value)>> {
+        let len = match self.cbor_head()? {
+            (5, len) => len,
+            _        => return None,
+        };
+
+        let mut fields = Vec::new();
+
+        for _ in 0 .. len {
+            let key = match self.cbor_value()? {
+                Value::Str(key) => key,
+                _               => return None,
+            };
+
+            fields.push((key, self.cbor_value()?));
+        }
+
+        Some(fields)
+    }
+
+    /// Reads an item's major type and its argument. Items of
+    /// indefinite length aren't supported.
+    fn cbor_head(&mut self) -> Option<(u8, u64)> {
+        let initial = self.byte()?;
+
+        let arg = match initial & 0x1f {
+            n @ 0 ..= 23 => u64::from(n),
+            24           => self.uint(1)?,
+            25           => self.uint(2)?,
+            26           => self.uint(4)?,
+            27           => self.uint(8)?,
+            _            => return None,
+        };
+
+        Some((initial >> 5, arg))
+    }
+
+    fn cbor_value(&mut self) -> Option<Value> {
+        match self.cbor_head()? {
+            (0, n)   => Some(Value::Uint(n)),
+            (2, len) => self.bytes(len).map(Value::Bytes),
+            (3, len) => self.string(len).map(Value::Str),
+            _        => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(fields: &[(&str, Value)]) -> Option<Vec<(String, Value)>> {
+        Some(fields.iter().map(|(key, value)| ((*key).into(), value.clone())).collect())
+    }
+
+    fn frame(encoding: Encoding, message: &Message<'_>) -> Vec<u8> {
+        encode(encoding, message).unwrap()
+    }
+
+    #[test]
+    fn leaves_text_unframed() {
+        assert_eq!(None, encode(Encoding::Text, &Message::Output(b"ls\n")));
+        assert_eq!(None, decode(Encoding::Text, b"ls\n"));
+    }
+
+    #[test]
+    fn frames_json() {
+        assert_eq!(
+            b"\0\0\0\x23{\"type\":\"output\",\"data\":\"bHMKG1s=\"}".to_vec(),
+            frame(Encoding::Json, &Message::Output(b"ls\n\x1b[")),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x2a{\"type\":\"reapprove\",\"deadline\":1600000060}".to_vec(),
+            frame(Encoding::Json, &Message::Reapprove { deadline: 1_600_000_060 }),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x21{\"type\":\"stream\",\"name\":\"stderr\"}".to_vec(),
+            frame(Encoding::Json, &Message::Stream(Stream::StdErr)),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x37{\"type\":\"suppressed\",\"reason\":\"rate_limit\",\"bytes\":512}".to_vec(),
+            frame(Encoding::Json, &Message::Suppressed { reason: "rate_limit", bytes: 512 }),
+        );
+    }
+
+    #[test]
+    fn frames_msgpack() {
+        // {"type": "winsize", "rows": 24, "cols": 300}
+        assert_eq!(
+            b"\0\0\0\x1c\x83\xa4type\xa7winsize\xa4rows\x18\xa4cols\xcd\x01\x2c".to_vec(),
+            frame(Encoding::MessagePack, &Message::Winsize { rows: 24, cols: 300 }),
+        );
+
+        // {"type": "output", "data": b"ls"}
+        assert_eq!(
+            b"\0\0\0\x16\x82\xa4type\xa6output\xa4data\xc4\x02ls".to_vec(),
+            frame(Encoding::MessagePack, &Message::Output(b"ls")),
+        );
+    }
+
+    #[test]
+    fn frames_cbor() {
+        // {"type": "checkpoint", "bytes": 300, "sha256": "abcd"}
+        assert_eq!(
+            b"\0\0\0\x26\xa3\x64type\x6acheckpoint\x65bytes\x19\x01\x2c\x66sha256\x64abcd".to_vec(),
+            frame(Encoding::Cbor, &Message::Checkpoint { bytes: 300, sha256: "abcd" }),
+        );
+
+        // {"type": "output", "data": b"ls"}
+        assert_eq!(
+            b"\0\0\0\x15\xa2\x64type\x66output\x64data\x42ls".to_vec(),
+            frame(Encoding::Cbor, &Message::Output(b"ls")),
+        );
+    }
+
+    #[test]
+    fn frames_binary() {
+        assert_eq!(
+            b"\0\0\0\x05\x01\0ls\n".to_vec(),
+            frame(Encoding::Binary, &Message::Output(b"ls\n")),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x0e\x04\0\0\0\0\0\0\0\x01\x2cabcd".to_vec(),
+            frame(Encoding::Binary, &Message::Checkpoint { bytes: 300, sha256: "abcd" }),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x0a\x05\0\0\0\0\x18\0\0\x01\x2c".to_vec(),
+            frame(Encoding::Binary, &Message::Winsize { rows: 24, cols: 300 }),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x08\x02\0stderr".to_vec(),
+            frame(Encoding::Binary, &Message::Stream(Stream::StdErr)),
+        );
+
+        assert_eq!(
+            b"\0\0\0\x04\x08\0ok".to_vec(),
+            frame(Encoding::Binary, &Message::Chat("ok")),
+        );
+    }
+
+    #[test]
+    fn decodes_json() {
+        assert_eq!(
+            fields(&[
+                ("type", Value::Str("notice".into())),
+                ("text", Value::Str("\"handed\\off\"\n\u{1b}".into())),
+            ]),
+            decode(Encoding::Json, br#" { "type" : "notice", "text" : "\"handed\\off\"\n\u001b" } "#),
+        );
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let messages = [
+            (Message::Output(b"ls\n\x1b[\xff"), vec![("data", Value::Bytes(b"ls\n\x1b[\xff".to_vec()))]),
+            (Message::Stream(Stream::StdErr),  vec![("name", Value::Str("stderr".into()))]),
+
+            (
+                Message::Suppressed { reason: "rate_limit", bytes: 512 },
+                vec![("reason", Value::Str("rate_limit".into())), ("bytes", Value::Uint(512))],
+            ),
+
+            (
+                Message::Checkpoint { bytes: 300, sha256: "abcd" },
+                vec![("bytes", Value::Uint(300)), ("sha256", Value::Str("abcd".into()))],
+            ),
+
+            (
+                Message::Winsize { rows: 24, cols: 300 },
+                vec![("rows", Value::Uint(24)), ("cols", Value::Uint(300))],
+            ),
+
+            (Message::Reapprove { deadline: 1_600_000_060 }, vec![("deadline", Value::Uint(1_600_000_060))]),
+            (Message::Notice("handed off\n\u{1b}"),         vec![("text", Value::Str("handed off\n\u{1b}".into()))]),
+            (Message::Chat("one sec"),                      vec![("text", Value::Str("one sec".into()))]),
+        ];
+
+        for encoding in &Encoding::FRAMED {
+            for (message, expected) in &messages {
+                let frame    = frame(*encoding, message);
+                let expected = Some(("type", Value::Str(message.name().into()))).into_iter()
+                    .chain(expected.iter().cloned())
+                    .collect::<Vec<_>>();
+
+                assert_eq!(fields(&expected), decode(*encoding, &frame[4 ..]), "{:?} {:?}", encoding, message);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        for (encoding, body) in &[
+            (Encoding::Json,        &b"{\"type\":\"output\""[..]),
+            (Encoding::Json,        &b"{\"type\":\"output\"} x"[..]),
+            (Encoding::Json,        &b"{\"data\":\"not base64\"}"[..]),
+            (Encoding::Json,        &b"{\"data\":\"Zg==Zg==\"}"[..]),
+            (Encoding::Json,        &b"[\"type\"]"[..]),
+            (Encoding::MessagePack, &b"\x81\xa4type"[..]),
+            (Encoding::MessagePack, &b"\x81\x01\x02"[..]),
+            (Encoding::Cbor,        &b"\xa1\x64type\x5f"[..]),
+            (Encoding::Cbor,        &b"\xa1\x64type\x64ls"[..]),
+            (Encoding::Binary,      &b"\x01"[..]),
+            (Encoding::Binary,      &b"\x01\x80ls"[..]),
+            (Encoding::Binary,      &b"\x05\0\0\0\0\x18"[..]),
+            (Encoding::Binary,      &b"\x63\0ls"[..]),
+        ] {
+            assert_eq!(None, decode(*encoding, body), "{:?}", body);
+        }
+    }
+}
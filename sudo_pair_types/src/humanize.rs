@@ -0,0 +1,155 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Times and durations as they're shown to people, rather than as
+//! they're recorded. Manifests, indexes, and logs keep to UTC (in ISO
+//! 8601) and plain seconds, so they can be compared across hosts; what
+//! approvers and users read is in their own timezone where it's known
+//! (which the client tells the plugin in its `hello`), and durations
+//! are spelled out. The plugin and the client both show them this way.
+
+use crate::manifest::utc;
+
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The units durations are spelled out in, the largest first.
+const UNITS : &[(u64, &str)] = &[
+    (86_400, "day"),
+    (3_600,  "hour"),
+    (60,     "minute"),
+    (1,      "second"),
+];
+
+/// Spells out `duration` in its largest unit and the one after it
+/// (e.g., `1 hour 5 minutes`, or `45 seconds`), dropping the rest.
+pub fn duration(duration: Duration) -> String {
+    let mut secs = duration.as_secs();
+
+    let largest = UNITS.iter()
+        .position(|&(unit, _)| secs >= unit)
+        .unwrap_or(UNITS.len() - 1);
+
+    let mut parts = Vec::new();
+
+    for &(unit, name) in UNITS.iter().skip(largest).take(2) {
+        let count = secs / unit;
+
+        secs %= unit;
+
+        if count > 0 || (parts.is_empty() && unit == 1) {
+            parts.push(format!("{} {}{}", count, name, if count == 1 { "" } else { "s" }));
+        }
+    }
+
+    parts.join(" ")
+}
+
+/// Formats `time` as a date and time in the timezone `utc_offset`
+/// seconds ahead of UTC (e.g., `2020-09-13 14:26:40 +02:00`), or in UTC
+/// if the timezone isn't known.
+pub fn local(time: SystemTime, utc_offset: Option<i32>) -> String {
+    let (date, clock) = split(time, utc_offset);
+
+    format!("{} {}", date, clock)
+}
+
+/// Formats just the time of day of `time`, as `local` does (e.g.,
+/// `14:26:40 +02:00`), for times that are only minutes away.
+pub fn clock(time: SystemTime, utc_offset: Option<i32>) -> String {
+    split(time, utc_offset).1
+}
+
+/// The seconds local time is ahead of UTC at `time`, as `TZ` (or
+/// failing that, the host's `/etc/localtime`) has it, or `None` if it
+/// can't be worked out.
+pub fn utc_offset(time: SystemTime) -> Option<i32> {
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as libc::time_t;
+
+    unsafe {
+        let mut tm = mem::zeroed::<libc::tm>();
+
+        if libc::localtime_r(&secs, &mut tm).is_null() {
+            return None;
+        }
+
+        Some(tm.tm_gmtoff as i32)
+    }
+}
+
+/// The date and the time of day of `time` in the timezone `utc_offset`
+/// seconds ahead of UTC, the latter followed by the timezone.
+fn split(time: SystemTime, utc_offset: Option<i32>) -> (String, String) {
+    let offset  = utc_offset.unwrap_or(0);
+    let shifted = if offset < 0 {
+        time.checked_sub(Duration::from_secs(offset.unsigned_abs().into()))
+    } else {
+        time.checked_add(Duration::from_secs(offset.unsigned_abs().into()))
+    }.unwrap_or(time);
+
+    // `utc` formats it as `YYYY-MM-DDTHH:MM:SSZ`
+    let stamp = utc(shifted);
+    let date  = stamp[.. 10].to_string();
+    let clock = &stamp[11 .. 19];
+
+    let zone = match utc_offset {
+        None => "UTC".into(),
+        Some(offset) => format!(
+            "{}{:02}:{:02}",
+            if offset < 0 { '-' } else { '+' },
+            offset.unsigned_abs() / 3_600,
+            offset.unsigned_abs() % 3_600 / 60,
+        ),
+    };
+
+    (date, format!("{} {}", clock, zone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spells_out_durations() {
+        assert_eq!("0 seconds",           duration(Duration::from_secs(0)));
+        assert_eq!("1 second",            duration(Duration::from_millis(1_999)));
+        assert_eq!("45 seconds",          duration(Duration::from_secs(45)));
+        assert_eq!("1 minute 30 seconds", duration(Duration::from_secs(90)));
+        assert_eq!("2 minutes",           duration(Duration::from_secs(120)));
+        assert_eq!("12 minutes",          duration(Duration::from_secs(720)));
+        assert_eq!("1 hour 5 minutes",    duration(Duration::from_secs(3_930)));
+        assert_eq!("3 hours",             duration(Duration::from_secs(3 * 3_600 + 59)));
+        assert_eq!("2 days 1 hour",       duration(Duration::from_secs(2 * 86_400 + 3_600 + 61)));
+    }
+
+    #[test]
+    fn formats_times_in_the_approvers_timezone() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        assert_eq!("2020-09-13 12:26:40 UTC",    local(time, None));
+        assert_eq!("2020-09-13 14:26:40 +02:00", local(time, Some(7_200)));
+        assert_eq!("2020-09-13 07:56:40 -04:30", local(time, Some(-16_200)));
+        assert_eq!("2020-09-14 00:26:40 +12:00", local(time, Some(43_200)));
+
+        assert_eq!("12:26:40 UTC",    clock(time, None));
+        assert_eq!("14:26:40 +02:00", clock(time, Some(7_200)));
+        assert_eq!("07:56:40 -04:30", clock(time, Some(-16_200)));
+        assert_eq!("00:26:40 +12:00", clock(time, Some(43_200)));
+    }
+
+    #[test]
+    fn knows_the_local_timezone() {
+        assert!(utc_offset(SystemTime::now()).is_some_and(|offset| offset.abs() < 86_400));
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Quoting strings for the JSON the plugin writes (manifests, `json`
+//! frames, and its reports), so everything reading it sees strings
+//! escaped the same way.
+
+use std::fmt::Write;
+
+/// Quotes `s` as a JSON string. Besides the quote and backslash, which
+/// JSON requires be escaped, every control character (including `DEL`)
+/// is escaped, so the result is safe to print to a terminal.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    let _ = write!(quoted, "\\u{:04x}", unit);
+                }
+            },
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_strings() {
+        assert_eq!(r#""select \"now\"()\n""#, quote("select \"now\"()\n"));
+        assert_eq!(r#""a\\b\t\r""#,           quote("a\\b\t\r"));
+        assert_eq!(r#""\u001b[2J\u007f""#,    quote("\x1b[2J\x7f"));
+        assert_eq!(r#""\u0085""#,             quote("\u{85}"));
+        assert_eq!("\"caf\u{e9}\"",         quote("caf\u{e9}"));
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Types shared by the `sudo_pair` plugin and everything that reads
+//! what it produces.
+//!
+//! The plugin, `sudo_pair_client`, and the `sudo_pair_replay` and
+//! `sudo_pair_check` tools all take these from here rather than each
+//! keeping their own copy, so what one of them writes is always what
+//! the others expect to read. Tools outside this repository (e.g., ones
+//! indexing recordings or reacting to close hooks) can depend on this
+//! crate alone, without pulling in the plugin or the client.
+//!
+//! * [`protocol`]: the protocol version, capabilities, encodings, and
+//!   streams negotiated and sent over a session's socket
+//! * [`frame`]: the frames sessions are sent in, in each of the framed
+//!   encodings
+//! * [`manifest`]: session manifests, their version, outcomes, and
+//!   timestamps, and how they're serialized
+//! * [`event`]: the events describing sessions to close hooks
+//! * [`humanize`]: times and durations as they're shown to approvers and
+//!   users
+//! * [`base64`] and [`json`]: the base64 and JSON string quoting used
+//!   by all of the above
+
+#![warn(bad_style)]
+#![warn(future_incompatible)]
+#![warn(nonstandard_style)]
+#![warn(rust_2018_compatibility)]
+#![warn(rust_2018_idioms)]
+#![warn(rustdoc)]
+#![warn(unused)]
+
+#![warn(bare_trait_objects)]
+#![warn(missing_copy_implementations)]
+#![warn(missing_debug_implementations)]
+#![warn(missing_docs)]
+#![warn(single_use_lifetimes)]
+#![warn(trivial_casts)]
+#![warn(trivial_numeric_casts)]
+#![warn(unreachable_pub)]
+#![warn(unstable_features)]
+#![warn(unused_import_braces)]
+#![warn(unused_lifetimes)]
+#![warn(unused_qualifications)]
+#![warn(unused_results)]
+#![warn(variant_size_differences)]
+
+#![cfg_attr(feature="cargo-clippy", warn(clippy::all))]
+
+pub mod base64;
+pub mod event;
+pub mod frame;
+pub mod humanize;
+pub mod json;
+pub mod manifest;
+pub mod protocol;
@@ -0,0 +1,760 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! The parts of session manifests that anything reading them needs to
+//! agree with the plugin on: the format's version, how the command
+//! ended, how edited files changed, and how times are written.
+//!
+//! Each manifest is a single line of JSON, with `started_at` and
+//! `ended_at` as ISO 8601 timestamps in UTC (see `utc`), and the
+//! `Outcome` as whichever of `exit_code`, `signal`, or `errno` applies
+//! (or `incomplete`, for sessions whose recordings were completed after
+//! `sudo` was killed).
+//!
+//! The plugin writes manifests with `Manifest::to_json`, so anything
+//! reading them can rely on the fields being named, ordered, and
+//! escaped exactly as they are here.
+
+use crate::json::quote;
+
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The version of the manifest's format, incremented whenever a field's
+/// meaning changes or a field is removed.
+pub const MANIFEST_VERSION : u32 = 1;
+
+/// How the command ended, as reported by sudo when the session closed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The command exited with a status code.
+    Exited(i32),
+
+    /// The command was killed by a signal.
+    Signaled(i32),
+
+    /// The command never ran, since sudo couldn't execute it, for the
+    /// given `errno`.
+    NotRun(i32),
+
+    /// Sudo didn't report how the command ended.
+    Unknown,
+
+    /// The session never ended, as far as the plugin knows: sudo was
+    /// killed before it could close the session, and its recording was
+    /// completed afterward by `sudo_pair_check --recover`.
+    Incomplete,
+}
+
+/// The manifest of a recorded session.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    /// The session's ID.
+    pub session_id: String,
+
+    /// The host the session was run on.
+    pub host: String,
+
+    /// The `host_tags` the plugin was configured with, as `(key,
+    /// value)` pairs.
+    pub host_tags: Vec<(String, String)>,
+
+    /// The user who ran the command.
+    pub user: String,
+
+    /// The uid of `user`.
+    pub uid: u32,
+
+    /// The user the command was run as.
+    pub runas_user: String,
+
+    /// The uid of `runas_user`.
+    pub runas_uid: u32,
+
+    /// The group the command was run as.
+    pub runas_group: String,
+
+    /// The gid of `runas_group`.
+    pub runas_gid: u32,
+
+    /// The directory the command was run in.
+    pub cwd: String,
+
+    /// The command's arguments, beginning with its name.
+    pub argv: Vec<String>,
+
+    /// The path sudo executed.
+    pub executable: String,
+
+    /// The command's binary with symlinks resolved, if it could be
+    /// inspected.
+    pub command_path: Option<String>,
+
+    /// The hex-encoded SHA-256 digest of the command's binary, if it
+    /// could be inspected.
+    pub command_sha256: Option<String>,
+
+    /// Whether a pair approved the session, rather than it being exempt
+    /// or allowed to proceed unattended.
+    pub paired: bool,
+
+    /// How many times the session was handed off to another approver.
+    pub transfers: u32,
+
+    /// The ticket the user cited for the session, if any.
+    pub ticket: Option<String>,
+
+    /// The comments approvers attached to their approvals.
+    pub approver_comments: Vec<String>,
+
+    /// The ID grouping the session with the others of the same change,
+    /// if it has one.
+    pub correlation_id: Option<String>,
+
+    /// The approver who approved the session from the user's own
+    /// terminal, if it was a co-located approval rather than a pair's.
+    pub colocated_approver: Option<String>,
+
+    /// The approver who approved the session through the approval
+    /// service at `approval_url`, if it was approved that way.
+    pub web_approver: Option<String>,
+
+    /// The commands the session's command ran, if sudo reported them.
+    pub subcommands: Vec<String>,
+
+    /// How many more commands the session's command ran than are listed
+    /// in `subcommands`.
+    pub subcommands_omitted: u64,
+
+    /// The ID of the maintenance window the session began during, if
+    /// any.
+    pub maintenance_window: Option<String>,
+
+    /// Who killed the session with `sudo_pair_admin`, if it was killed.
+    pub killed_by: Option<String>,
+
+    /// Why the session was killed, if it was and a reason was given.
+    pub kill_reason: Option<String>,
+
+    /// The labels the policy plugin attached to the session.
+    pub labels: Vec<String>,
+
+    /// The terminal multiplexer the session was run in, if it was and
+    /// `record_multiplexer` is enabled.
+    pub multiplexer: Option<Multiplexer>,
+
+    /// When the session began.
+    pub started_at: SystemTime,
+
+    /// When the session ended.
+    pub ended_at: SystemTime,
+
+    /// The number of bytes of output recorded.
+    pub output_bytes: u64,
+
+    /// The number of bytes relayed to the approver, if the session was
+    /// paired, so the recording can be checked against what the
+    /// approver was actually shown.
+    pub approver_bytes: Option<u64>,
+
+    /// The hex-encoded SHA-256 digest of what was relayed to the
+    /// approver, if the session was paired.
+    pub approver_sha256: Option<String>,
+
+    /// The timing of the user's keystrokes, if sudo logged their input
+    /// and they typed anything.
+    pub keystroke_latency: Option<LatencySummary>,
+
+    /// How the files being edited changed, if this was a `sudoedit`
+    /// session.
+    pub edits: Vec<FileEdit>,
+
+    /// How the command ended.
+    pub outcome: Outcome,
+}
+
+/// The timing of a session's keystrokes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LatencySummary {
+    /// How many keystrokes the user typed.
+    pub keystrokes: u64,
+
+    /// The intervals between keystrokes, if there was more than one.
+    pub intervals: Option<Percentiles>,
+
+    /// How long the command took to echo each keystroke, if it echoed
+    /// any.
+    pub echoes: Option<Percentiles>,
+}
+
+/// Percentiles of a set of durations, in microseconds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Percentiles {
+    /// The median.
+    pub p50: u64,
+
+    /// The 90th percentile.
+    pub p90: u64,
+
+    /// The 99th percentile.
+    pub p99: u64,
+
+    /// The longest.
+    pub max: u64,
+}
+
+/// The terminal multiplexer a session is run in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Multiplexer {
+    /// Which multiplexer it is (e.g., `tmux`).
+    pub name: &'static str,
+
+    /// The session it identified itself by in the environment (e.g.,
+    /// `$STY` for screen), if it did.
+    pub session: Option<String>,
+}
+
+/// How a file edited with `sudoedit` changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileEdit {
+    /// The path of the file that was edited.
+    pub path: String,
+
+    /// The hex-encoded SHA-256 digest of the file's contents before it
+    /// was edited, or `None` if it didn't exist (or couldn't be read).
+    pub before_sha256: Option<String>,
+
+    /// The hex-encoded SHA-256 digest of the file's contents after it
+    /// was edited, or `None` if it didn't exist (or couldn't be read).
+    pub after_sha256: Option<String>,
+
+    /// A unified diff of the changes, if diffs are enabled and the file
+    /// was small enough.
+    pub diff: Option<String>,
+}
+
+impl Manifest {
+    /// Serializes the manifest as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        let mut json = Object::default();
+
+        json.number("version",        MANIFEST_VERSION);
+        json.string("session_id",     &self.session_id);
+        json.string("host",           &self.host);
+
+        if !self.host_tags.is_empty() {
+            let mut object = Object::default();
+
+            for (key, value) in &self.host_tags {
+                object.string(key, value);
+            }
+
+            json.object("host_tags", object);
+        }
+
+        json.string("user",           &self.user);
+        json.number("uid",            self.uid);
+        json.string("runas_user",     &self.runas_user);
+        json.number("runas_uid",      self.runas_uid);
+        json.string("runas_group",    &self.runas_group);
+        json.number("runas_gid",      self.runas_gid);
+        json.string("cwd",            &self.cwd);
+        json.strings("argv",          &self.argv);
+        json.string("executable",     &self.executable);
+        json.optional("command_path",   self.command_path.as_deref());
+        json.optional("command_sha256", self.command_sha256.as_deref());
+        json.boolean("paired",        self.paired);
+        json.number("transfers",      self.transfers);
+
+        json.optional("ticket",         self.ticket.as_deref());
+        json.optional("correlation_id", self.correlation_id.as_deref());
+
+        if !self.approver_comments.is_empty() {
+            json.strings("approver_comments", &self.approver_comments);
+        }
+
+        json.optional("colocated_approver", self.colocated_approver.as_deref());
+        json.optional("web_approver",       self.web_approver.as_deref());
+
+        json.optional("maintenance_window", self.maintenance_window.as_deref());
+        json.optional("killed_by",          self.killed_by.as_deref());
+        json.optional("kill_reason",        self.kill_reason.as_deref());
+
+        if !self.labels.is_empty() {
+            json.strings("labels", &self.labels);
+        }
+
+        if let Some(multiplexer) = self.multiplexer.as_ref() {
+            let mut object = Object::default();
+
+            object.string("name",     multiplexer.name);
+            object.optional("session", multiplexer.session.as_deref());
+
+            json.object("multiplexer", object);
+        }
+
+        if !self.subcommands.is_empty() {
+            json.strings("subcommands", &self.subcommands);
+        }
+
+        if self.subcommands_omitted > 0 {
+            json.number("subcommands_omitted", self.subcommands_omitted);
+        }
+
+        json.string("started_at",     &utc(self.started_at));
+        json.string("ended_at",       &utc(self.ended_at));
+        json.number("output_bytes",   self.output_bytes);
+
+        if let Some(bytes) = self.approver_bytes {
+            json.number("approver_bytes", bytes);
+        }
+
+        json.optional("approver_sha256", self.approver_sha256.as_deref());
+
+        if let Some(latency) = self.keystroke_latency {
+            let mut object = Object::default();
+
+            object.number("keystrokes", latency.keystrokes);
+            object.percentiles("interval_us", latency.intervals);
+            object.percentiles("echo_us",     latency.echoes);
+
+            json.object("keystroke_latency", object);
+        }
+
+        if !self.edits.is_empty() {
+            let edits = self.edits.iter().map(|edit| {
+                let mut object = Object::default();
+
+                object.string("path",            &edit.path);
+                object.optional("before_sha256", edit.before_sha256.as_deref());
+                object.optional("after_sha256",  edit.after_sha256.as_deref());
+                object.optional("diff",          edit.diff.as_deref());
+                object
+            }).collect();
+
+            json.objects("edits", edits);
+        }
+
+        match self.outcome {
+            Outcome::Exited(code)     => json.number("exit_code", code),
+            Outcome::Signaled(signal) => json.number("signal",    signal),
+            Outcome::NotRun(errno)    => json.number("errno",     errno),
+            Outcome::Unknown          => {},
+            Outcome::Incomplete       => json.boolean("incomplete", true),
+        }
+
+        json.finish()
+    }
+}
+
+impl Manifest {
+    /// A manifest with plausible values, for tests.
+    #[doc(hidden)]
+    pub fn example() -> Self {
+        let started = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        Self {
+            session_id:     "1600000000-31337".into(),
+            host:           "db01".into(),
+            host_tags:      Vec::new(),
+            user:           "alice".into(),
+            uid:            1000,
+            runas_user:     "postgres".into(),
+            runas_uid:      109,
+            runas_group:    "postgres".into(),
+            runas_gid:      117,
+            cwd:            "/home/alice".into(),
+            argv:           vec!["psql".into(), "-c".into(), "select \"now\"()\n".into()],
+            executable:     "/usr/bin/psql".into(),
+            command_path:   Some("/usr/lib/postgresql/12/bin/psql".into()),
+            command_sha256: None,
+            paired:         true,
+            transfers:      1,
+            started_at:     started,
+            ended_at:       started + Duration::from_secs(61),
+            output_bytes:   42,
+            outcome:        Outcome::Exited(3),
+
+            approver_bytes:  None,
+            approver_sha256: None,
+
+            keystroke_latency: Some(LatencySummary {
+                keystrokes: 7,
+                intervals:  Some(Percentiles { p50: 120_000, p90: 250_000, p99: 800_000, max: 1_500_000 }),
+                echoes:     None,
+            }),
+
+            ticket:            Some("INC-1234".into()),
+            approver_comments: vec!["approved for INC-1234".into()],
+            correlation_id:    None,
+
+            colocated_approver: None,
+            web_approver:       None,
+
+            subcommands:         Vec::new(),
+            subcommands_omitted: 0,
+
+            maintenance_window: None,
+            killed_by:          None,
+            kill_reason:        None,
+            labels:             Vec::new(),
+            multiplexer:        None,
+
+            edits: Vec::new(),
+        }
+    }
+}
+
+/// Builds a JSON object one field at a time.
+#[derive(Debug, Default)]
+struct Object {
+    json: String,
+}
+
+impl Object {
+    fn key(&mut self, key: &str) {
+        self.json.push(if self.json.is_empty() { '{' } else { ',' });
+        self.json.push_str(&quote(key));
+        self.json.push(':');
+    }
+
+    fn string(&mut self, key: &str, value: &str) {
+        self.key(key);
+        self.json.push_str(&quote(value));
+    }
+
+    fn optional(&mut self, key: &str, value: Option<&str>) {
+        if let Some(value) = value {
+            self.string(key, value);
+        }
+    }
+
+    fn strings(&mut self, key: &str, values: &[String]) {
+        let values : Vec<_> = values.iter().map(|v| quote(v)).collect();
+
+        self.key(key);
+        self.json.push('[');
+        self.json.push_str(&values.join(","));
+        self.json.push(']');
+    }
+
+    fn number<N: std::fmt::Display>(&mut self, key: &str, value: N) {
+        self.key(key);
+        let _ = write!(self.json, "{}", value);
+    }
+
+    fn percentiles(&mut self, key: &str, value: Option<Percentiles>) {
+        if let Some(percentiles) = value {
+            let mut object = Object::default();
+
+            object.number("p50", percentiles.p50);
+            object.number("p90", percentiles.p90);
+            object.number("p99", percentiles.p99);
+            object.number("max", percentiles.max);
+
+            self.object(key, object);
+        }
+    }
+
+    fn object(&mut self, key: &str, value: Object) {
+        self.key(key);
+        self.json.push_str(&value.finish());
+    }
+
+    fn objects(&mut self, key: &str, values: Vec<Object>) {
+        let values : Vec<_> = values.into_iter().map(Object::finish).collect();
+
+        self.key(key);
+        self.json.push('[');
+        self.json.push_str(&values.join(","));
+        self.json.push(']');
+    }
+
+    fn boolean(&mut self, key: &str, value: bool) {
+        self.key(key);
+        self.json.push_str(if value { "true" } else { "false" });
+    }
+
+    fn finish(mut self) -> String {
+        if self.json.is_empty() {
+            self.json.push('{');
+        }
+
+        self.json.push('}');
+        self.json
+    }
+}
+
+/// Formats `time` as an ISO 8601 timestamp in UTC, to the second.
+pub fn utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+    let secs = secs % 86_400;
+
+    // the proleptic Gregorian date of `days` since the epoch; see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z     = days + 719_468;
+    let era   = z.div_euclid(146_097);
+    let doe   = z - era * 146_097;
+    let yoe   = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy   = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp    = (5 * doy + 2) / 153;
+    let day   = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year  = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day,
+        secs / 3_600, secs % 3_600 / 60, secs % 60,
+    )
+}
+
+/// Parses an ISO 8601 timestamp in UTC, to the second, as formatted by
+/// `utc`. Timestamps before the epoch aren't supported.
+pub fn parse_utc(s: &str) -> Option<SystemTime> {
+    let bytes = s.as_bytes();
+
+    let shape = bytes.len() == 20 && bytes.iter().enumerate().all(|(i, b)| match i {
+        4 | 7   => *b == b'-',
+        10      => *b == b'T',
+        13 | 16 => *b == b':',
+        19      => *b == b'Z',
+        _       => b.is_ascii_digit(),
+    });
+
+    if !shape {
+        return None;
+    }
+
+    let field = |range: std::ops::Range<usize>| s[range].parse::<i64>().ok();
+
+    let (year, month, day) = (field(0 .. 4)?, field(5 .. 7)?, field(8 .. 10)?);
+    let (hour, min, sec)   = (field(11 .. 13)?, field(14 .. 16)?, field(17 .. 19)?);
+
+    // the inverse of `utc`; see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y    = if month <= 2 { year - 1 } else { year };
+    let era  = y.div_euclid(400);
+    let yoe  = y - era * 400;
+    let mp   = if month > 2 { month - 3 } else { month + 9 };
+    let doy  = (153 * mp + 2) / 5 + day - 1;
+    let doe  = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let secs = days * 86_400 + hour * 3_600 + min * 60 + sec;
+
+    if secs < 0 {
+        return None;
+    }
+
+    let time = UNIX_EPOCH + Duration::from_secs(secs as u64);
+
+    // out-of-range fields (e.g., February 30th) don't survive the
+    // round trip
+    Some(time).filter(|time| utc(*time) == s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_to_json() {
+        assert_eq!(
+            "{\"version\":1,\"session_id\":\"1600000000-31337\",\"host\":\"db01\",\
+             \"user\":\"alice\",\"uid\":1000,\"runas_user\":\"postgres\",\"runas_uid\":109,\
+             \"runas_group\":\"postgres\",\"runas_gid\":117,\"cwd\":\"/home/alice\",\
+             \"argv\":[\"psql\",\"-c\",\"select \\\"now\\\"()\\n\"],\
+             \"executable\":\"/usr/bin/psql\",\
+             \"command_path\":\"/usr/lib/postgresql/12/bin/psql\",\"paired\":true,\"transfers\":1,\
+             \"ticket\":\"INC-1234\",\"approver_comments\":[\"approved for INC-1234\"],\
+             \"started_at\":\"2020-09-13T12:26:40Z\",\"ended_at\":\"2020-09-13T12:27:41Z\",\
+             \"output_bytes\":42,\"keystroke_latency\":{\"keystrokes\":7,\
+             \"interval_us\":{\"p50\":120000,\"p90\":250000,\"p99\":800000,\"max\":1500000}},\
+             \"exit_code\":3}",
+            Manifest::example().to_json(),
+        );
+    }
+
+    #[test]
+    fn serializes_host_tags() {
+        let mut manifest = Manifest::example();
+
+        manifest.host_tags = vec![
+            ("datacenter".into(), "sfo1".into()),
+            ("team".into(),       "payments".into()),
+        ];
+
+        assert!(manifest.to_json().contains(
+            ",\"host\":\"db01\",\"host_tags\":{\"datacenter\":\"sfo1\",\"team\":\"payments\"},\"user\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_maintenance_windows() {
+        let mut manifest = Manifest::example();
+
+        manifest.maintenance_window = Some("CHG-1234".into());
+
+        assert!(manifest.to_json().contains(
+            ",\"approver_comments\":[\"approved for INC-1234\"],\"maintenance_window\":\"CHG-1234\",\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_kills() {
+        let mut manifest = Manifest::example();
+
+        manifest.killed_by   = Some("bob".into());
+        manifest.kill_reason = Some("INC-1234".into());
+
+        assert!(manifest.to_json().contains(
+            ",\"approver_comments\":[\"approved for INC-1234\"],\"killed_by\":\"bob\",\"kill_reason\":\"INC-1234\",\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_colocated_approvals() {
+        let mut manifest = Manifest::example();
+
+        manifest.colocated_approver = Some("bob".into());
+
+        assert!(manifest.to_json().contains(
+            ",\"approver_comments\":[\"approved for INC-1234\"],\"colocated_approver\":\"bob\",\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_web_approvals() {
+        let mut manifest = Manifest::example();
+
+        manifest.web_approver = Some("bob".into());
+
+        assert!(manifest.to_json().contains(
+            ",\"approver_comments\":[\"approved for INC-1234\"],\"web_approver\":\"bob\",\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_labels() {
+        let mut manifest = Manifest::example();
+
+        manifest.labels = vec!["pci-scope".into(), "tier:1".into()];
+
+        assert!(manifest.to_json().contains(
+            ",\"approver_comments\":[\"approved for INC-1234\"],\"labels\":[\"pci-scope\",\"tier:1\"],\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_correlation_ids() {
+        let mut manifest = Manifest::example();
+
+        manifest.correlation_id = Some("CHG-42".into());
+
+        assert!(manifest.to_json().contains(
+            ",\"ticket\":\"INC-1234\",\"correlation_id\":\"CHG-42\",\"approver_comments\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_multiplexers() {
+        let mut manifest = Manifest::example();
+
+        manifest.multiplexer = Some(Multiplexer { name: "screen", session: Some("4242.pts-0.db01".into()) });
+
+        assert!(manifest.to_json().contains(
+            ",\"multiplexer\":{\"name\":\"screen\",\"session\":\"4242.pts-0.db01\"},\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_subcommands() {
+        let mut manifest = Manifest::example();
+
+        manifest.subcommands         = vec!["/usr/bin/vim /etc/hosts".into()];
+        manifest.subcommands_omitted = 3;
+
+        assert!(manifest.to_json().contains(
+            ",\"approver_comments\":[\"approved for INC-1234\"],\"subcommands\":[\"/usr/bin/vim /etc/hosts\"],\"subcommands_omitted\":3,\"started_at\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_approver_digests() {
+        let mut manifest = Manifest::example();
+
+        manifest.approver_bytes  = Some(3);
+        manifest.approver_sha256 = Some("ba7816bf".into());
+
+        assert!(manifest.to_json().contains(
+            ",\"output_bytes\":42,\"approver_bytes\":3,\"approver_sha256\":\"ba7816bf\",\"keystroke_latency\":"
+        ));
+    }
+
+    #[test]
+    fn serializes_edits() {
+        let mut manifest = Manifest::example();
+
+        manifest.edits = vec![
+            FileEdit {
+                path:          "/etc/hosts".into(),
+                before_sha256: Some("aa".into()),
+                after_sha256:  Some("bb".into()),
+                diff:          Some("@@ -1 +1 @@\n-a\n+b\n".into()),
+            },
+            FileEdit {
+                path:          "/etc/motd".into(),
+                before_sha256: None,
+                after_sha256:  Some("cc".into()),
+                diff:          None,
+            },
+        ];
+
+        assert!(manifest.to_json().contains(
+            ",\"edits\":[{\"path\":\"/etc/hosts\",\"before_sha256\":\"aa\",\"after_sha256\":\"bb\",\
+             \"diff\":\"@@ -1 +1 @@\\n-a\\n+b\\n\"},{\"path\":\"/etc/motd\",\"after_sha256\":\"cc\"}],"
+        ));
+    }
+
+    #[test]
+    fn serializes_commands_that_never_ran() {
+        let mut manifest = Manifest::example();
+
+        manifest.outcome = Outcome::NotRun(2);
+
+        assert!(manifest.to_json().ends_with(",\"errno\":2}"));
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!("1970-01-01T00:00:00Z", utc(UNIX_EPOCH));
+        assert_eq!("2000-02-29T23:59:59Z", utc(UNIX_EPOCH + Duration::from_secs(951_868_799)));
+        assert_eq!("2038-01-19T03:14:08Z", utc(UNIX_EPOCH + Duration::from_secs(1 << 31)));
+    }
+
+    #[test]
+    fn parses_utc_timestamps() {
+        assert_eq!(Some(UNIX_EPOCH), parse_utc("1970-01-01T00:00:00Z"));
+        assert_eq!(Some(UNIX_EPOCH + Duration::from_secs(951_868_799)), parse_utc("2000-02-29T23:59:59Z"));
+        assert_eq!(Some(UNIX_EPOCH + Duration::from_secs(1 << 31)),     parse_utc("2038-01-19T03:14:08Z"));
+
+        assert_eq!(None, parse_utc("2001-02-29T00:00:00Z"));
+        assert_eq!(None, parse_utc("2020-09-13T24:00:00Z"));
+        assert_eq!(None, parse_utc("1969-12-31T23:59:59Z"));
+        assert_eq!(None, parse_utc("2020-09-13 02:00:00Z"));
+        assert_eq!(None, parse_utc("2020-09-13T02:00:00+00:00"));
+    }
+}
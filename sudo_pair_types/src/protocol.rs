@@ -0,0 +1,488 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! The protocol spoken over a session's socket.
+//!
+//! Messages are sent in-band with the session's output as `OSC`
+//! (operating system command) escape sequences, which terminals ignore
+//! when they don't recognize them:
+//!
+//! ```text
+//! ESC ] 5379 ; sudo_pair;<message>;<key>=<value>;... BEL
+//! ```
+//!
+//! Values that could end a message, or be mistaken for part of its
+//! framing, are sent percent-encoded (see `percent_encode`).
+//!
+//! Each side announces its `PROTOCOL_VERSION` and `Capabilities` in a
+//! `hello`, and the session proceeds with the lower of the two versions
+//! and the capabilities both sides share. Each also announces its
+//! `Limits`, the largest of everything it accepts, and both sides are
+//! held to the smaller of each (see `Limits::negotiate`), so neither
+//! can make the other buffer without bound. Once the approver is watching
+//! the session, it can be sent in any `Encoding` the plugin offers and
+//! the client asks for, with its output labeled by the `Stream` it came
+//! from.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::BitAnd;
+
+/// The highest protocol version spoken by the plugin and client built
+/// alongside this crate.
+pub const PROTOCOL_VERSION : u16 = 1;
+
+/// The `OSC` number identifying sudo_pair messages.
+pub const OSC : u32 = 5379;
+
+/// Every protocol message begins with this prefix.
+pub const PREFIX : &[u8] = b"\x1b]5379;sudo_pair;";
+
+/// The byte beginning every escape sequence, and so every message.
+pub const ESC : u8 = 0x1b;
+
+/// The byte ending every message.
+pub const BEL : u8 = 0x07;
+
+/// Frames `payload` (`<message>;<key>=<value>;...`) as a message.
+pub fn message(payload: &str) -> Vec<u8> {
+    let mut message = PREFIX.to_vec();
+
+    message.extend_from_slice(payload.as_bytes());
+    message.push(BEL);
+    message
+}
+
+/// Percent-encodes everything in `value` that could end a message (or
+/// be mistaken for part of its framing): `%`, `;`, and anything that
+/// isn't printable ASCII.
+pub fn percent_encode(value: &[u8]) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for &byte in value {
+        match byte {
+            b'%' | b';'   => encoded.push_str(&format!("%{:02x}", byte)),
+            0x20 ..= 0x7e => encoded.push(char::from(byte)),
+            _             => encoded.push_str(&format!("%{:02x}", byte)),
+        }
+    }
+
+    encoded
+}
+
+/// Decodes a value encoded by `percent_encode`, or returns `None` if
+/// any `%` isn't followed by two hex digits.
+pub fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes   = value.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+
+        let hex = [bytes.next()?, bytes.next()?];
+
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+
+        decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+
+    Some(decoded)
+}
+
+/// A set of optional protocol features.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Capabilities(pub u32);
+
+// capabilities are assigned bits up front, even for features that
+// haven't been implemented, so that clients can rely on them
+impl Capabilities {
+    /// The approver can end the session at any time.
+    pub const KILL       : Self = Self(1 << 0);
+
+    /// The approver can pause and resume the session's output.
+    pub const PAUSE      : Self = Self(1 << 1);
+
+    /// Additional read-only observers may watch the session.
+    pub const OBSERVERS  : Self = Self(1 << 2);
+
+    /// Output is annotated with timestamps.
+    pub const TIMESTAMPS : Self = Self(1 << 3);
+
+    /// Digests of the transcript are sent periodically.
+    pub const DIGESTS    : Self = Self(1 << 4);
+
+    /// Changes to the size of the user's terminal are sent.
+    pub const WINSIZE    : Self = Self(1 << 5);
+
+    /// The approver is periodically asked to re-approve the session.
+    pub const REAPPROVAL : Self = Self(1 << 6);
+
+    /// The approver can comment on why they approved or declined.
+    pub const COMMENTS   : Self = Self(1 << 7);
+
+    /// Output is labeled with the stream it was written to.
+    pub const STREAMS    : Self = Self(1 << 8);
+
+    /// Output kept from the approver is marked with `suppressed`
+    /// messages.
+    pub const MARKERS    : Self = Self(1 << 9);
+
+    /// The approver and the user can send each other short messages.
+    pub const CHAT       : Self = Self(1 << 10);
+
+    /// Returns a set without any capabilities.
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns true if every capability in `other` is in this set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Adds every capability in `other` to this set.
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// The largest of everything one side of the session accepts from the
+/// other, announced in its `hello` as `max_<limit>=<size>` fields.
+///
+/// Each side refuses anything over its own limits, and keeps what it
+/// sends within the negotiated ones: output is split across as many
+/// frames as it takes, and comments, reasons, and chat messages are cut
+/// short with `truncate`. Prompts and previews are sent before the client's
+/// `hello` arrives, so they're only held to the plugin's limits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Limits {
+    /// The longest message, in bytes.
+    pub message: usize,
+
+    /// The longest frame, in bytes following its length.
+    pub frame: usize,
+
+    /// The longest prompt, in bytes.
+    pub prompt: usize,
+
+    /// The longest comment on an approval, in characters.
+    pub comment: usize,
+
+    /// The longest reason (e.g., why output was kept from the approver
+    /// or why the session ended), in characters.
+    pub reason: usize,
+
+    /// The longest chat message between the approver and the user, in
+    /// characters.
+    pub chat: usize,
+}
+
+impl Limits {
+    /// The limits taken of a side that doesn't announce any, which are
+    /// those clients have always accepted.
+    pub const DEFAULT : Self = Self {
+        message: 4096,
+        frame:   1 << 20,
+        prompt:  64 * 1024,
+        comment: 128,
+        reason:  256,
+        chat:    256,
+    };
+
+    /// The smallest limits a side may announce, below which the session
+    /// couldn't go on; anything smaller is taken as these.
+    pub const MINIMUM : Self = Self {
+        message: 512,
+        frame:   4096,
+        prompt:  1024,
+        comment: 16,
+        reason:  16,
+        chat:    16,
+    };
+
+    /// Returns the limits both sides are held to, the smaller of each
+    /// of these and the `other` side's.
+    pub fn negotiate(self, other: Self) -> Self {
+        let min = |ours: usize, theirs: usize, minimum: usize| ours.min(theirs.max(minimum));
+
+        Self {
+            message: min(self.message, other.message, Self::MINIMUM.message),
+            frame:   min(self.frame,   other.frame,   Self::MINIMUM.frame),
+            prompt:  min(self.prompt,  other.prompt,  Self::MINIMUM.prompt),
+            comment: min(self.comment, other.comment, Self::MINIMUM.comment),
+            reason:  min(self.reason,  other.reason,  Self::MINIMUM.reason),
+            chat:    min(self.chat,    other.chat,    Self::MINIMUM.chat),
+        }
+    }
+
+    /// The limits as the fields of a `hello`, each preceded by `;`.
+    pub fn fields(&self) -> String {
+        format!(
+            ";max_message={};max_frame={};max_prompt={};max_comment={};max_reason={};max_chat={}",
+            self.message,
+            self.frame,
+            self.prompt,
+            self.comment,
+            self.reason,
+            self.chat,
+        )
+    }
+
+    /// Sets the limit a `hello` field named `key` gives as `value`.
+    /// Returns false if it isn't a limit (or isn't a size), leaving the
+    /// limits as they were.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        let limit = match key {
+            "max_message" => &mut self.message,
+            "max_frame"   => &mut self.frame,
+            "max_prompt"  => &mut self.prompt,
+            "max_comment" => &mut self.comment,
+            "max_reason"  => &mut self.reason,
+            "max_chat"    => &mut self.chat,
+            _             => return false,
+        };
+
+        value.parse().map(|value| *limit = value).is_ok()
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Cuts `text` short to at most `limit` characters, ending what's left
+/// with an ellipsis (`…`) so it can't be mistaken for the whole of it.
+pub fn truncate(text: &str, limit: usize) -> Cow<'_, str> {
+    if text.chars().count() <= limit {
+        return Cow::Borrowed(text);
+    }
+
+    if limit == 0 {
+        return Cow::Borrowed("");
+    }
+
+    let kept : String = text.chars().take(limit - 1).collect();
+
+    Cow::Owned(kept + "\u{2026}")
+}
+
+/// How the session is sent to the client once it's watching it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Encoding {
+    /// Output as-is, with messages as `OSC` escape sequences.
+    #[default]
+    Text,
+
+    /// Frames of JSON objects, which are easy to debug.
+    Json,
+
+    /// Frames of MessagePack maps.
+    MessagePack,
+
+    /// Frames of CBOR maps.
+    Cbor,
+
+    /// Frames of a type, flags, and a payload laid out for the type,
+    /// which send output with the least overhead.
+    Binary,
+}
+
+impl Encoding {
+    /// The encodings that frame what's sent, which the plugin offers
+    /// clients in its `hello`.
+    pub const FRAMED : [Self; 4] = [Encoding::Json, Encoding::MessagePack, Encoding::Cbor, Encoding::Binary];
+
+    /// The name of the encoding in the protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Text        => "text",
+            Encoding::Json        => "json",
+            Encoding::MessagePack => "msgpack",
+            Encoding::Cbor        => "cbor",
+            Encoding::Binary      => "binary",
+        }
+    }
+
+    /// The encoding named `name` in the protocol, if there is one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text"    => Some(Encoding::Text),
+            "json"    => Some(Encoding::Json),
+            "msgpack" => Some(Encoding::MessagePack),
+            "cbor"    => Some(Encoding::Cbor),
+            "binary"  => Some(Encoding::Binary),
+            _         => None,
+        }
+    }
+}
+
+/// Where output sent to the approver came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Stream {
+    /// The command's output to the user's terminal.
+    TtyOut,
+
+    /// The command's standard output, when it isn't a terminal.
+    StdOut,
+
+    /// The command's standard error, when it isn't a terminal.
+    StdErr,
+
+    /// The plugin's own messages, like notices about the session.
+    Plugin,
+}
+
+impl Stream {
+    /// The name of the stream in the protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stream::TtyOut => "ttyout",
+            Stream::StdOut => "stdout",
+            Stream::StdErr => "stderr",
+            Stream::Plugin => "plugin",
+        }
+    }
+
+    /// The stream named `name` in the protocol, if there is one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ttyout" => Some(Stream::TtyOut),
+            "stdout" => Some(Stream::StdOut),
+            "stderr" => Some(Stream::StdErr),
+            "plugin" => Some(Stream::Plugin),
+            _        => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_messages_with_the_osc_number() {
+        assert_eq!(format!("\x1b]{};sudo_pair;", OSC).as_bytes(), PREFIX);
+        assert_eq!(Some(&ESC), PREFIX.first());
+    }
+
+    #[test]
+    fn frames_messages() {
+        assert_eq!(&b"\x1b]5379;sudo_pair;winsize;rows=24;cols=80\x07"[..], &message("winsize;rows=24;cols=80")[..]);
+    }
+
+    #[test]
+    fn percent_encodes_values() {
+        assert_eq!("100%25%3b caf%c3%a9%0a", percent_encode("100%; caf\u{e9}\n".as_bytes()));
+
+        assert_eq!(Some(b"100%; \xff"[..].to_vec()), percent_decode("100%25%3B %ff"));
+        assert_eq!(Some(Vec::new()),                percent_decode(""));
+
+        // escapes that are cut short or aren't hex are refused
+        assert_eq!(None, percent_decode("100%"));
+        assert_eq!(None, percent_decode("%2"));
+        assert_eq!(None, percent_decode("%+f"));
+        assert_eq!(None, percent_decode("%zz"));
+    }
+
+    #[test]
+    fn names_encodings_and_streams() {
+        for encoding in [Encoding::Text].iter().chain(&Encoding::FRAMED) {
+            assert_eq!(Some(*encoding), Encoding::from_name(encoding.name()));
+        }
+
+        for stream in &[Stream::TtyOut, Stream::StdOut, Stream::StdErr, Stream::Plugin] {
+            assert_eq!(Some(*stream), Stream::from_name(stream.name()));
+        }
+
+        assert_eq!(None, Encoding::from_name("xml"));
+        assert_eq!(None, Stream::from_name("stdin"));
+    }
+
+    #[test]
+    fn negotiates_capabilities() {
+        let mut plugin = Capabilities::empty();
+
+        plugin.insert(Capabilities::WINSIZE);
+        plugin.insert(Capabilities::COMMENTS);
+
+        let shared = plugin & Capabilities::COMMENTS;
+
+        assert!(shared.contains(Capabilities::COMMENTS));
+        assert!(!shared.contains(Capabilities::WINSIZE));
+        assert_eq!("0xa0", plugin.to_string());
+    }
+
+    #[test]
+    fn negotiates_limits() {
+        let mut client = Limits::DEFAULT;
+
+        assert!(client.set("max_frame", "65536"));
+        assert!(client.set("max_comment", "1000"));
+        assert!(client.set("max_reason", "1"));
+        assert!(client.set("max_chat", "64"));
+        assert!(!client.set("max_message", "lots"));
+        assert!(!client.set("max_widgets", "1"));
+
+        let limits = Limits::DEFAULT.negotiate(client);
+
+        assert_eq!(Limits::DEFAULT.message, limits.message);
+        assert_eq!(65536,                   limits.frame);
+        assert_eq!(Limits::DEFAULT.comment, limits.comment);
+        assert_eq!(Limits::MINIMUM.reason,  limits.reason);
+        assert_eq!(64,                      limits.chat);
+    }
+
+    #[test]
+    fn announces_limits() {
+        let mut limits = Limits::MINIMUM;
+
+        for field in Limits::DEFAULT.fields().split(';').skip(1) {
+            let (key, value) = field.split_once('=').unwrap();
+
+            assert!(limits.set(key, value));
+        }
+
+        assert_eq!(Limits::DEFAULT, limits);
+    }
+
+    #[test]
+    fn truncates_with_an_ellipsis() {
+        assert_eq!("fine", truncate("fine", 4));
+        assert_eq!("caf\u{e9}", truncate("caf\u{e9}", 4));
+        assert_eq!("to\u{2026}", truncate("too long", 3));
+        assert_eq!("", truncate("anything", 0));
+    }
+}
@@ -56,13 +56,18 @@
 #![cfg_attr(feature="cargo-clippy", allow(clippy::similar_names))]
 #![cfg_attr(feature="cargo-clippy", allow(clippy::type_complexity))]
 
-use std::os::raw::{c_int, c_uint};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
 pub const SUDO_API_VERSION: c_uint =
     SUDO_API_VERSION_MAJOR << 16 | SUDO_API_VERSION_MINOR;
 
+// not in the vendored `sudo_plugin.h`; sudo sends these messages to its
+// debug log (as configured by `Debug` lines in `sudo.conf`) rather than
+// to the user
+pub const SUDO_CONV_DEBUG_MSG : u32 = 0x0006;
+
 pub const SUDO_PLUGIN_OPEN_SUCCESS       : c_int =  1;
 pub const SUDO_PLUGIN_OPEN_FAILURE       : c_int =  0;
 pub const SUDO_PLUGIN_OPEN_GENERAL_ERROR : c_int = -1;
@@ -85,3 +90,89 @@ pub const IO_PLUGIN_EMPTY : io_plugin = io_plugin {
     #[cfg(feature = "min_sudo_plugin_1_12")]
     change_winsize: None,
 };
+
+// not in the vendored `sudo_plugin.h`; audit plugins arrived in version
+// 1.17 of the plugin API (sudo 1.9.0), and are told about every command
+// sudo accepts, including (since sudo 1.9.8, with the `intercept` or
+// `log_subcmds` sudoers options) those run by the command itself
+pub const SUDO_AUDIT_PLUGIN : c_uint = 3;
+
+pub const SUDO_AUDIT_API_VERSION : c_uint = 1 << 16 | 17;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct audit_plugin {
+    pub type_: c_uint,
+    pub version: c_uint,
+    pub open: Option<
+        unsafe extern "C" fn(
+            version: c_uint,
+            conversation: sudo_conv_t,
+            sudo_printf: sudo_printf_t,
+            settings: *const *mut c_char,
+            user_info: *const *mut c_char,
+            submit_optind: c_int,
+            submit_argv: *const *mut c_char,
+            submit_envp: *const *mut c_char,
+            plugin_options: *const *mut c_char,
+            errstr: *mut *const c_char,
+        ) -> c_int,
+    >,
+    pub close: Option<unsafe extern "C" fn(status_type: c_int, status: c_int)>,
+    pub accept: Option<
+        unsafe extern "C" fn(
+            plugin_name: *const c_char,
+            plugin_type: c_uint,
+            command_info: *const *mut c_char,
+            run_argv: *const *mut c_char,
+            run_envp: *const *mut c_char,
+            errstr: *mut *const c_char,
+        ) -> c_int,
+    >,
+    pub reject: Option<
+        unsafe extern "C" fn(
+            plugin_name: *const c_char,
+            plugin_type: c_uint,
+            audit_msg: *const c_char,
+            command_info: *const *mut c_char,
+            errstr: *mut *const c_char,
+        ) -> c_int,
+    >,
+    pub error: Option<
+        unsafe extern "C" fn(
+            plugin_name: *const c_char,
+            plugin_type: c_uint,
+            audit_msg: *const c_char,
+            command_info: *const *mut c_char,
+            errstr: *mut *const c_char,
+        ) -> c_int,
+    >,
+    pub show_version: Option<unsafe extern "C" fn(verbose: c_int) -> c_int>,
+    pub register_hooks: Option<
+        unsafe extern "C" fn(
+            version: c_int,
+            register_hook: Option<unsafe extern "C" fn(hook: *mut sudo_hook) -> c_int>,
+        ),
+    >,
+    pub deregister_hooks: Option<
+        unsafe extern "C" fn(
+            version: c_int,
+            deregister_hook: Option<unsafe extern "C" fn(hook: *mut sudo_hook) -> c_int>,
+        ),
+    >,
+    pub event_alloc: Option<unsafe extern "C" fn() -> *mut c_void>,
+}
+
+pub const AUDIT_PLUGIN_EMPTY : audit_plugin = audit_plugin {
+    type_:            SUDO_AUDIT_PLUGIN,
+    version:          SUDO_AUDIT_API_VERSION,
+    open:             None,
+    close:            None,
+    accept:           None,
+    reject:           None,
+    error:            None,
+    show_version:     None,
+    register_hooks:   None,
+    deregister_hooks: None,
+    event_alloc:      None,
+};
@@ -0,0 +1,175 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Benchmarks of what every plugin pays each time sudo opens it, before
+//! it's had a chance to decide whether there's anything to do. Plugins
+//! that let most commands through untouched (e.g., sudo_pair, for users
+//! exempt from pairing) pay this on every `sudo` on a host, so it should
+//! stay a small fraction of what sudo itself takes.
+//!
+//! ```text
+//! cargo bench -p sudo_plugin
+//! ```
+
+use sudo_plugin::{OptionMap, Plugin, PrintFacility};
+
+use std::ffi::CString;
+use std::ptr;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use libc::c_char;
+
+/// `sudo systemctl restart nginx`, run by a user with a typical login
+/// environment (which sudo passes through as-is when `env_reset` is
+/// off, and which is most of what there is to parse).
+struct Invocation {
+    argv:           Vec<CString>,
+    settings:       Vec<CString>,
+    user_info:      Vec<CString>,
+    command_info:   Vec<CString>,
+    user_env:       Vec<CString>,
+    plugin_options: Vec<CString>,
+}
+
+impl Invocation {
+    fn new() -> Self {
+        let mut user_env = entries(&[
+            "HOME=/home/alice", "LANG=en_US.UTF-8", "LOGNAME=alice",
+            "MAIL=/var/mail/alice", "SHELL=/bin/bash", "TERM=xterm-256color",
+            "USER=alice", "SUDO_USER=alice", "SUDO_UID=1000", "SUDO_GID=1000",
+            "SUDO_COMMAND=/usr/bin/systemctl restart nginx",
+            "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+            "LS_COLORS=rs=0:di=01;34:ln=01;36:mh=00:pi=40;33:so=01;35:do=01;35:bd=40;33;01",
+        ]);
+
+        // the rest of a login environment, which is mostly variables
+        // nothing ever looks at
+        user_env.extend((0 .. 64).map(|i| CString::new(
+            format!("VAR_{}=/opt/tools/{}/bin:/opt/tools/{}/lib", i, i, i),
+        ).unwrap()));
+
+        Self {
+            argv: entries(&["systemctl", "restart", "nginx"]),
+
+            settings: entries(&[
+                "plugin_dir=/usr/libexec/sudo",
+                "plugin_path=/usr/libexec/sudo/sudo_pair.so",
+                "progname=sudo",
+                "network_addrs=10.0.0.12/255.255.255.0 fe80::1/ffff:ffff:ffff:ffff::",
+                "max_groups=-1",
+            ]),
+
+            user_info: entries(&[
+                "user=alice", "pid=4242", "ppid=4241", "pgid=4242",
+                "tcpgid=4242", "sid=4100", "uid=1000", "euid=0", "gid=1000",
+                "egid=1000", "groups=1000,4,24,27,30,46,110", "cwd=/home/alice",
+                "tty=/dev/pts/3", "host=web-01", "lines=48", "cols=160",
+            ]),
+
+            command_info: entries(&[
+                "command=/usr/bin/systemctl", "runas_uid=0", "runas_gid=0",
+                "runas_euid=0", "runas_egid=0", "runas_groups=0",
+                "closefrom=3", "set_utmp=true", "use_pty=true", "umask=022",
+                "iolog_ttyin=false", "iolog_ttyout=true",
+                "cwd=/home/alice", "preserve_fds=",
+            ]),
+
+            user_env,
+
+            plugin_options: entries(&[
+                "socket_dir=/var/run/sudo_pair",
+                "gids_exempted=0,27",
+            ]),
+        }
+    }
+
+    fn open(&self) -> Plugin {
+        let argv           = pointers(&self.argv);
+        let settings       = pointers(&self.settings);
+        let user_info      = pointers(&self.user_info);
+        let command_info   = pointers(&self.command_info);
+        let user_env       = pointers(&self.user_env);
+        let plugin_options = pointers(&self.plugin_options);
+
+        unsafe {
+            let (stdout, stderr) = PrintFacility::new(Some("bench"), None);
+
+            Plugin::new(
+                "bench".into(),
+                None,
+                sudo_plugin::sys::SUDO_API_VERSION,
+                self.argv.len() as _,
+                argv.as_ptr(),
+                settings.as_ptr(),
+                user_info.as_ptr(),
+                command_info.as_ptr(),
+                user_env.as_ptr(),
+                plugin_options.as_ptr(),
+                stdout,
+                stderr,
+                None,
+            ).expect("the plugin should open")
+        }
+    }
+}
+
+fn entries(entries: &[&str]) -> Vec<CString> {
+    entries.iter().map(|entry| CString::new(*entry).unwrap()).collect()
+}
+
+/// The NULL-terminated array of pointers to `entries` that sudo would
+/// pass.
+fn pointers(entries: &[CString]) -> Vec<*mut c_char> {
+    entries.iter()
+        .map(|entry| entry.as_ptr() as *mut c_char)
+        .chain(Some(ptr::null_mut()))
+        .collect()
+}
+
+fn open(c: &mut Criterion) {
+    let invocation = Invocation::new();
+
+    c.bench_function("Plugin::new", |b| b.iter(|| invocation.open()));
+
+    // what a plugin that only needs to know who's running what (e.g., to
+    // decide it's exempt) pays, compared to one that reads the
+    // environment too
+    c.bench_function("Plugin::new, reading user_env", |b| b.iter(|| {
+        let plugin = invocation.open();
+
+        let _ = black_box(plugin.user_env.get_str("TERM"));
+
+        plugin
+    }));
+}
+
+fn option_map(c: &mut Criterion) {
+    let invocation = Invocation::new();
+    let user_env   = pointers(&invocation.user_env);
+
+    c.bench_function("OptionMap::from_raw", |b| b.iter(||
+        unsafe { OptionMap::from_raw(black_box(user_env.as_ptr()) as _) }
+    ));
+
+    c.bench_function("OptionMap::get_str, once", |b| b.iter(|| {
+        let map = unsafe { OptionMap::from_raw(black_box(user_env.as_ptr()) as _) };
+
+        let _ = black_box(map.get_str("TERM"));
+
+        map
+    }));
+}
+
+criterion_group!(benches, open, option_map);
+criterion_main!(benches);
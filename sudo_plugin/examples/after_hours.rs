@@ -0,0 +1,96 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Refuses commands outside of working hours, as given in local time by
+//! the `hours` plugin option (by default, nine to five):
+//!
+//! ```ignore
+//! Plugin after_hours libafter_hours.so hours=8-18
+//! ```
+//!
+//! Members of the group given by the `exempt_gid` option (if any) can
+//! run commands at any time.
+
+use sudo_plugin::errors::*;
+use sudo_plugin::*;
+
+use std::io::Write;
+use std::ops::Range;
+
+sudo_io_plugin! { after_hours : AfterHours { } }
+
+/// The hours commands are allowed in when no `hours` are given.
+const DEFAULT_HOURS : Range<u32> = 9 .. 17;
+
+struct AfterHours;
+
+impl AfterHours {
+    fn open(plugin: &'static Plugin) -> Result<Self> {
+        let hours = match plugin.plugin_options.get::<String>("hours") {
+            Ok(hours) => parse_hours(&hours)
+                .chain_err(|| format!("hours={} isn't a range of hours like 9-17", hours))?,
+            Err(_)    => DEFAULT_HOURS,
+        };
+
+        let exempt = matches!(
+            plugin.plugin_options.get::<libc::gid_t>("exempt_gid"),
+            Ok(gid) if plugin.is_user_in_group(gid)
+        );
+
+        // if the time can't be determined, it can't be working hours
+        let working = matches!(current_hour(), Some(hour) if hours.contains(&hour));
+
+        if exempt || working {
+            return Ok(Self);
+        }
+
+        let _ = plugin.print(Level::Error, format!(
+            "commands can only be run between {}:00 and {}:00",
+            hours.start,
+            hours.end,
+        ).as_bytes());
+
+        Err(ErrorKind::Unauthorized.into())
+    }
+}
+
+/// Parses a range of hours like `9-17`, which allows commands from
+/// 9:00 until 16:59.
+fn parse_hours(hours: &str) -> Option<Range<u32>> {
+    let mut bounds = hours.splitn(2, '-').map(|bound| bound.trim().parse::<u32>().ok());
+
+    let start = bounds.next()??;
+    let end   = bounds.next()??;
+
+    if start >= end || end > 24 {
+        return None;
+    }
+
+    Some(start .. end)
+}
+
+/// The current hour of the day, in local time.
+fn current_hour() -> Option<u32> {
+    unsafe {
+        let     now = libc::time(std::ptr::null_mut());
+        let mut tm  = std::mem::zeroed();
+
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return None;
+        }
+
+        Some(tm.tm_hour as u32)
+    }
+}
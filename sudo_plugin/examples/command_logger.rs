@@ -0,0 +1,68 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Logs every command run through sudo, one line per command, to a file
+//! given by the `path` plugin option:
+//!
+//! ```ignore
+//! Plugin command_logger libcommand_logger.so path=/var/log/sudo-commands.log
+//! ```
+//!
+//! Each line records the user, who the command ran as, the directory it
+//! ran in, and what was typed (as a JSON array of its arguments, so a
+//! newline in one can't start a line of its own), and is appended when
+//! the command starts rather than when it ends, so commands that never
+//! exit are still logged.
+
+use sudo_plugin::errors::*;
+use sudo_plugin::*;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+sudo_io_plugin! { command_logger : CommandLogger { } }
+
+/// Where commands are logged when no `path` is given.
+const DEFAULT_PATH : &str = "/var/log/sudo-commands.log";
+
+struct CommandLogger;
+
+impl CommandLogger {
+    fn open(plugin: &'static Plugin) -> Result<Self> {
+        let path = plugin.plugin_options.get::<PathBuf>("path")
+            .unwrap_or_else(|_| DEFAULT_PATH.into());
+
+        let mut line = format!(
+            "user={} runas={} cwd={} command=",
+            plugin.user_name(),
+            plugin.runas_user_name(),
+            plugin.cwd().display(),
+        ).into_bytes();
+
+        line.extend_from_slice(plugin.invocation_json().as_bytes());
+        line.push(b'\n');
+
+        // a command that can't be logged isn't run
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut log| log.write_all(&line))
+            .chain_err(|| format!("couldn't log the command to {}", path.display()))?;
+
+        Ok(Self)
+    }
+}
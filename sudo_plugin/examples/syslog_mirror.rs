@@ -0,0 +1,97 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Mirrors the output of every command run through sudo to syslog, a
+//! line at a time, without changing what the user sees:
+//!
+//! ```ignore
+//! Plugin syslog_mirror libsyslog_mirror.so
+//! ```
+//!
+//! Output is buffered until a newline (or until a line gets too long),
+//! and anything left over is logged when the command exits.
+
+use sudo_plugin::errors::*;
+use sudo_plugin::*;
+
+use std::ffi::CString;
+use std::io::Write;
+
+sudo_io_plugin! {
+    syslog_mirror : SyslogMirror {
+        close:      close,
+        log_ttyout: log_output,
+        log_stdout: log_output,
+        log_stderr: log_output,
+    }
+}
+
+/// The longest line logged at once; longer ones are split.
+const MAX_LINE_LEN : usize = 1024;
+
+struct SyslogMirror {
+    /// what's been logged as, which has to outlive every `syslog` call
+    _ident: CString,
+
+    /// output since the last complete line
+    line: Vec<u8>,
+}
+
+impl SyslogMirror {
+    fn open(plugin: &'static Plugin) -> Result<Self> {
+        let ident = CString::new(format!("sudo[{}]", plugin.user_name()))
+            .chain_err(|| "the user's name contains a NUL byte")?;
+
+        unsafe { libc::openlog(ident.as_ptr(), libc::LOG_PID, libc::LOG_AUTHPRIV) };
+
+        Ok(Self { _ident: ident, line: Vec::new() })
+    }
+
+    fn close(&mut self, _: CommandStatus) {
+        self.flush();
+
+        unsafe { libc::closelog() };
+    }
+
+    fn log_output(&mut self, log: &[u8]) -> Result<()> {
+        for &b in log {
+            match b {
+                b'\n' => self.flush(),
+                b'\r' => {},
+                _     => self.line.push(b),
+            }
+
+            if self.line.len() >= MAX_LINE_LEN {
+                self.flush();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Logs the current line, if there is one.
+    fn flush(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+
+        // NUL bytes would otherwise end the message early
+        self.line.retain(|&b| b != 0);
+
+        if let Ok(line) = CString::new(std::mem::take(&mut self.line)) {
+            unsafe { libc::syslog(libc::LOG_INFO, b"%s\0".as_ptr() as _, line.as_ptr()) };
+        }
+    }
+}
@@ -22,11 +22,14 @@
 #![allow(single_use_lifetimes)]
 #![allow(variant_size_differences)]
 
+use crate::plugin::State;
 use crate::version::Version;
 
 use std::fmt;
 
+#[cfg(feature = "ffi")]
 use sudo_plugin_sys as sys;
+#[cfg(feature = "ffi")]
 use libc::c_int;
 use error_chain::*;
 
@@ -63,6 +66,23 @@ error_chain! {
             display("sudo called this plugin with an API version of {}, but a minimum of {} is required", cur, Version::minimum()),
         }
 
+        /// An error which can be returned when an option that's required
+        /// (whether by sudo's plugin API or by the plugin) wasn't
+        /// provided.
+        MissingOption(key: String) {
+            description("a required option wasn't provided to the plugin"),
+            display("option {} wasn't provided to the plugin", key),
+        }
+
+        /// An error which can be returned when an option's value couldn't
+        /// be parsed as the type it's read as. The value is included (up
+        /// to a point) so the problem can be diagnosed from the error
+        /// alone.
+        InvalidOption(key: String, value: String, expected: String) {
+            description("an option's value couldn't be parsed"),
+            display("option {} couldn't be parsed as {} (its value is {:?})", key, expected, value),
+        }
+
         /// An error which can be returned when there's a general error
         /// when initiailizing the plugin.
         Uninitialized {
@@ -76,6 +96,76 @@ error_chain! {
             description("command unauthorized"),
             display("command unauthorized"),
         }
+
+        /// An error which can be returned when sudo didn't provide a
+        /// conversation function, or has no way to reach the user with
+        /// it (e.g., there's no terminal and no askpass helper).
+        ConversationUnavailable {
+            description("the conversation function is unavailable"),
+            display("the conversation function is unavailable"),
+        }
+
+        /// An error which can be returned when a conversation is
+        /// interrupted by a signal before the user responds (e.g., they
+        /// pressed Ctrl-C).
+        ConversationInterrupted {
+            description("the conversation was interrupted"),
+            display("the conversation was interrupted"),
+        }
+
+        /// An error which can be returned when the user doesn't respond
+        /// to a prompt before its timeout elapses.
+        ConversationTimedOut {
+            description("the conversation timed out"),
+            display("the conversation timed out"),
+        }
+
+        /// An error which can be returned when sudo calls one of the
+        /// plugin's callbacks at a point in its lifecycle where it
+        /// can't be (e.g., calling `open` twice, or logging output
+        /// after `close`).
+        UnexpectedCallback(callback: &'static str, state: State) {
+            description("sudo called the plugin out of order"),
+            display("sudo called {} while the plugin was {}", callback, state),
+        }
+
+        /// An error which can be returned by every callback once the
+        /// plugin has ended the session (see `Plugin::terminate`), for
+        /// the reason it gave.
+        Terminated(reason: String) {
+            description("the plugin ended the session"),
+            display("{}", reason),
+        }
+    }
+}
+
+impl Error {
+    /// A short message for the user invoking sudo, saying what went
+    /// wrong without the chain of causes behind it, which are rarely
+    /// anything they can act on (and may say more about the host than
+    /// they should see). Plugins' own errors are wrapped in
+    /// `Unauthorized`, so the error it wraps is what's explained.
+    pub fn user_message(&self) -> String {
+        match (self.kind(), self.iter().nth(1)) {
+            (ErrorKind::Unauthorized, Some(cause)) => format!("{}: {}", self, cause),
+            _                                      => self.to_string(),
+        }
+    }
+
+    /// Everything known about the error, for debugging rather than for
+    /// the user: each error in its chain, outermost first, and where it
+    /// was raised if a backtrace was captured.
+    pub fn debug_message(&self) -> String {
+        let mut message = self.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        if let Some(backtrace) = self.backtrace() {
+            message.push_str(&format!("\n{:?}", backtrace));
+        }
+
+        message
     }
 }
 
@@ -89,6 +179,7 @@ error_chain! {
 /// *  0: Failure
 /// * -1: General error
 /// * -2: Usage error
+#[cfg(feature = "ffi")]
 pub trait AsSudoPluginRetval {
     /// Converts the error to its corresponding integer error code for
     /// the I/O plugin `open` function.
@@ -99,6 +190,7 @@ pub trait AsSudoPluginRetval {
     fn as_sudo_io_plugin_log_retval(&self) -> c_int;
 }
 
+#[cfg(feature = "ffi")]
 impl<T, E: AsSudoPluginRetval> AsSudoPluginRetval for ::std::result::Result<T, E> {
     fn as_sudo_io_plugin_open_retval(&self) -> c_int {
         match *self {
@@ -115,18 +207,96 @@ impl<T, E: AsSudoPluginRetval> AsSudoPluginRetval for ::std::result::Result<T, E
     }
 }
 
+/// A user who interrupts a conversation or doesn't answer it in time
+/// has effectively declined to continue, so both reject the command
+/// rather than reporting an error, as does a session the plugin has
+/// terminated. An unavailable conversation is a
+/// problem with the environment `sudo` was run in, and is an error.
+#[cfg(feature = "ffi")]
 impl AsSudoPluginRetval for Error {
     fn as_sudo_io_plugin_open_retval(&self) -> c_int {
         match *self {
-            Error(ErrorKind::Unauthorized, _) => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
-            Error(_, _)                       => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(ErrorKind::Unauthorized, _)            => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
+            Error(ErrorKind::ConversationUnavailable, _) => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
+            Error(ErrorKind::ConversationInterrupted, _) => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(ErrorKind::ConversationTimedOut, _)    => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(ErrorKind::UnexpectedCallback(..), _)  => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
+            Error(ErrorKind::Terminated(_), _)           => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(_, _)                                  => sys::SUDO_PLUGIN_OPEN_FAILURE,
         }
     }
 
     fn as_sudo_io_plugin_log_retval(&self) -> c_int {
         match *self {
-            Error(ErrorKind::Unauthorized, _) => sys::SUDO_PLUGIN_OPEN_FAILURE,
-            Error(_, _)                       => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
+            Error(ErrorKind::Unauthorized, _)            => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(ErrorKind::ConversationUnavailable, _) => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
+            Error(ErrorKind::ConversationInterrupted, _) => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(ErrorKind::ConversationTimedOut, _)    => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(ErrorKind::Terminated(_), _)           => sys::SUDO_PLUGIN_OPEN_FAILURE,
+            Error(_, _)                                  => sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+
+    #[test]
+    fn separates_user_and_debug_messages() {
+        let cause = io::Error::new(io::ErrorKind::NotFound, "/var/log/sudo_pair/123: no such file");
+        let error = Error::with_chain(
+            Error::with_chain(cause, "the session couldn't be recorded"),
+            ErrorKind::Unauthorized,
+        );
+
+        assert_eq!("command unauthorized: the session couldn't be recorded", error.user_message());
+        assert!(error.debug_message().starts_with(
+            "command unauthorized: the session couldn't be recorded: /var/log/sudo_pair/123: no such file",
+        ));
+
+        let missing : Error = ErrorKind::MissingOption("uid".into()).into();
+
+        assert_eq!("option uid wasn't provided to the plugin", missing.user_message());
+    }
+}
+
+#[cfg(all(test, feature = "ffi"))]
+mod ffi_tests {
+    use super::*;
+
+    #[test]
+    fn conversation_retvals() {
+        let unavailable : Error = ErrorKind::ConversationUnavailable.into();
+        let interrupted : Error = ErrorKind::ConversationInterrupted.into();
+        let timed_out   : Error = ErrorKind::ConversationTimedOut.into();
+
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR, unavailable.as_sudo_io_plugin_open_retval());
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_FAILURE,       interrupted.as_sudo_io_plugin_open_retval());
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_FAILURE,       timed_out.as_sudo_io_plugin_open_retval());
+
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR, unavailable.as_sudo_io_plugin_log_retval());
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_FAILURE,       interrupted.as_sudo_io_plugin_log_retval());
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_FAILURE,       timed_out.as_sudo_io_plugin_log_retval());
+    }
+
+    #[test]
+    fn unexpected_callback_retvals() {
+        let unexpected : Error = ErrorKind::UnexpectedCallback("open", State::Open).into();
+
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR, unexpected.as_sudo_io_plugin_open_retval());
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_GENERAL_ERROR, unexpected.as_sudo_io_plugin_log_retval());
+        assert_eq!("sudo called open while the plugin was open", unexpected.to_string());
+    }
+
+    #[test]
+    fn terminated_retvals() {
+        let terminated : Error = ErrorKind::Terminated("the session ran too long".into()).into();
+
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_FAILURE, terminated.as_sudo_io_plugin_open_retval());
+        assert_eq!(sys::SUDO_PLUGIN_OPEN_FAILURE, terminated.as_sudo_io_plugin_log_retval());
+        assert_eq!("the session ran too long", terminated.to_string());
+    }
+}
@@ -15,6 +15,14 @@
 //! description = "Macros to simplify writing sudo plugins"
 //!
 //! TODO: explain
+//!
+//! The parsing of the values sudo passes plugins (`OptionMap`,
+//! `Settings`, `UserInfo`, `CommandInfo`, and the types options are
+//! parsed into) doesn't depend on sudo itself. With the default `ffi`
+//! feature disabled, only that is built, without the bindings to the
+//! sudo plugin API or anything that calls through them, so fuzzers and
+//! other tools can use it without building (or linking against) any of
+//! it.
 
 // TODO: provide the Plugin object to all callbacks?
 
@@ -51,11 +59,17 @@
 #![cfg_attr(feature="cargo-clippy", allow(clippy::similar_names))]
 
 pub mod errors;
+#[cfg(feature = "ffi")]
 pub mod macros;
 pub mod plugin;
 
 mod version;
 
+#[cfg(feature = "ffi")]
 pub use sudo_plugin_sys as sys;
 
+/// Wipes what it holds when dropped; sensitive values (e.g., the
+/// user's replies to prompts) are returned wrapped in it.
+pub use zeroize::Zeroizing;
+
 pub use self::plugin::*;
@@ -17,6 +17,28 @@
 /// Emits the boilerplate stanza for creating and initializing a custom
 /// sudo I/O plugin.
 ///
+/// Only the callbacks that are listed are registered with sudo; the rest
+/// are left `NULL`, so sudo doesn't do any work on their behalf (e.g.,
+/// it only copies the data read from stdin if `log_stdin` is
+/// registered). Each callback can be given `#[cfg]` attributes to
+/// register it only when a feature is enabled.
+///
+/// Every plugin reports its version when sudo is run with `-V`. A plugin
+/// that has more to say (e.g., when `-V` is run by root, which sudo
+/// considers verbose) can name an associated function taking the
+/// `Plugin` and whether sudo asked for verbose output as `show_version`,
+/// which is called after the version is printed. Since no command is
+/// run, the plugin's `open` is never called, and the function is given
+/// no instance.
+///
+/// The generated callbacks follow the plugin's `Lifecycle`. If sudo
+/// calls `open` twice, calls any other callback before `open` or after
+/// `close`, or calls one while another is still running, the callback
+/// prints an error and returns one to sudo rather than running. Once
+/// the plugin has ended the session with `Plugin::terminate`, every
+/// callback that follows rejects the command, telling the user why only
+/// the first time.
+///
 /// # Example
 ///
 /// ```rust
@@ -28,6 +50,9 @@
 ///     example : Example {
 ///         close:      close,
 ///         log_stdout: log_stdout,
+///
+///         #[cfg(feature = "change_winsize")]
+///         change_winsize: change_winsize,
 ///     }
 /// }
 ///
@@ -42,7 +67,7 @@
 ///         Ok(Example { plugin })
 ///     }
 ///
-///     fn close(&mut self, _: i32, _: i32) {
+///     fn close(&mut self, _: CommandStatus) {
 ///         self.plugin.stdout().write(b"example sudo plugin exited");
 ///     }
 ///
@@ -53,6 +78,11 @@
 ///
 ///         Ok(())
 ///     }
+///
+///     #[cfg(feature = "change_winsize")]
+///     fn change_winsize(&mut self, _: u32, _: u32) -> Result<()> {
+///         Ok(())
+///     }
 /// }
 /// ```
 ///
@@ -64,13 +94,56 @@
 /// ```ignore
 /// Plugin example example.so
 /// ```
+///
+/// # Subcommands
+///
+/// A plugin can also be told about the commands run by the command sudo
+/// was asked to run (e.g., by a shell), which sudo 1.9.8 and later
+/// report to audit plugins when the sudoers `intercept` or
+/// `log_subcmds` options are enabled. Naming an audit plugin after the
+/// I/O plugin's callbacks generates one that passes each `Subcommand`
+/// the policy plugin accepts to the named method:
+///
+/// ```ignore
+/// sudo_io_plugin! {
+///     example : Example {
+///         close: close,
+///     }
+///
+///     example_audit : audit {
+///         subcommand: subcommand,
+///     }
+/// }
+///
+/// impl Example {
+///     fn subcommand(&mut self, subcommand: Subcommand) -> Result<()> {
+///         writeln!(self.plugin.stdout(), "ran {}", subcommand)?;
+///
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// Errors are printed, but never stop a subcommand from running. The
+/// audit plugin has to be loaded from the same library as the I/O
+/// plugin (so that they share the session), and only by versions of
+/// sudo that support audit plugins:
+///
+/// ```ignore
+/// Plugin example example.so
+/// Plugin example_audit example.so
+/// ```
 #[macro_export]
 macro_rules! sudo_io_plugin {
-    ( $name:ident : $ty:ty { $($cb:ident : $fn:ident),* $(,)* } ) => {
+    (
+        $name:ident : $ty:ty { $( $(#[$attr:meta])* $cb:ident : $fn:ident ),* $(,)? }
+        $( $audit:ident : audit { subcommand : $subcommand:ident $(,)? } )?
+    ) => {
         use ::sudo_plugin::errors::AsSudoPluginRetval;
 
         static mut PLUGIN:   Option<::sudo_plugin::Plugin> = None;
         static mut INSTANCE: Option<$ty>                   = None;
+        static LIFECYCLE:    ::sudo_plugin::Lifecycle      = ::sudo_plugin::Lifecycle::new();
 
         #[no_mangle]
         #[allow(non_upper_case_globals)]
@@ -78,7 +151,7 @@ macro_rules! sudo_io_plugin {
         pub static $name: ::sudo_plugin::sys::io_plugin = {
             ::sudo_plugin::sys::io_plugin {
                 // construct the plugin using any callbacks specified
-                $( $cb: sudo_io_fn!($cb, $name, PLUGIN, INSTANCE, $fn) ),*,
+                $( $(#[$attr])* $cb: sudo_io_fn!($cb, $name, $ty, PLUGIN, INSTANCE, LIFECYCLE, $fn), )*
 
                 // and for anything not specified, use the defaults
                 .. ::sudo_plugin::sys::io_plugin {
@@ -108,59 +181,80 @@ macro_rules! sudo_io_plugin {
                 Some(stringify!($name)), plugin_printf
             );
 
-            let plugin = ::sudo_plugin::Plugin::new(
-                stringify!($name).into(),
-                option_env!("CARGO_PKG_VERSION").map(Into::into),
-                version,
-                argc, argv,
-                settings_ptr,
-                user_info_ptr,
-                command_info_ptr,
-                user_env_ptr,
-                plugin_options_ptr,
-
-                stdout,
-                stderr.clone(), // we need stderr ourselves if `open` fails
-                conversation,
-            );
+            // refuse to be opened a second time, which would replace the
+            // plugin out from under any callbacks still using it
+            if let Err(e) = LIFECYCLE.open() {
+                let _ = stderr.report_error(&e);
+                return e.as_sudo_io_plugin_open_retval();
+            }
 
-            match plugin {
-                Ok(p)  => PLUGIN = Some(p),
-                Err(e) => {
-                    let _ = stderr.write_error(&e);
-                    return e.as_sudo_io_plugin_open_retval();
-                },
-            };
+            // open the plugin, noting whether it succeeded on the way out
+            let retval = (|| {
+                let plugin = ::sudo_plugin::Plugin::new(
+                    stringify!($name).into(),
+                    option_env!("CARGO_PKG_VERSION").map(Into::into),
+                    version,
+                    argc, argv,
+                    settings_ptr,
+                    user_info_ptr,
+                    command_info_ptr,
+                    user_env_ptr,
+                    plugin_options_ptr,
+
+                    stdout,
+                    stderr.clone(), // we need stderr ourselves if `open` fails
+                    conversation,
+                );
 
-            // unwrap should be panic-safe here, since we just assigned
-            // a value to $plugin
-            let plugin = PLUGIN.as_ref().unwrap();
+                match plugin {
+                    Ok(p)  => PLUGIN = Some(p),
+                    Err(e) => {
+                        let _ = stderr.report_error(&e);
+                        return e.as_sudo_io_plugin_open_retval();
+                    },
+                };
+
+                // unwrap should be panic-safe here, since we just assigned
+                // a value to $plugin
+                let plugin = PLUGIN.as_ref().unwrap();
+
+                // if we're only being asked to report our version (with
+                // `-V`), there's no reason to fully invoke the plugin
+                // through its `open` function
+                if !plugin.invocation_kind().runs_command() {
+                    return ::sudo_plugin::sys::SUDO_PLUGIN_OPEN_SUCCESS;
+                }
 
-            // if the command is empty, to the best of my knowledge
-            // we're being called with `-V` to report our version; in
-            // this case there's no reason to fully invoke the plugin
-            // through its `open` function
-            if plugin.command_info.command == ::std::path::PathBuf::default() {
-                return ::sudo_plugin::sys::SUDO_PLUGIN_OPEN_SUCCESS;
-            }
+                // call the plugin's `open` function
+                match <$ty>::open(plugin) {
+                    Ok(i)  => INSTANCE = Some(i),
+                    Err(e) => {
+                        let e: ::sudo_plugin::errors::Error = e.into();
+                        let _ = stderr.report_error(&e);
+                        return e.as_sudo_io_plugin_open_retval();
+                    },
+                }
 
-            // call the plugin's `open` function
-            match <$ty>::open(plugin) {
-                Ok(i)  => INSTANCE = Some(i),
-                Err(e) => {
-                    let e: ::sudo_plugin::errors::Error = e.into();
-                    let _ = stderr.write_error(&e);
-                    return e.as_sudo_io_plugin_open_retval();
-                },
-            }
+                ::sudo_plugin::sys::SUDO_PLUGIN_OPEN_SUCCESS
+            })();
 
-            ::sudo_plugin::sys::SUDO_PLUGIN_OPEN_SUCCESS
+            LIFECYCLE.opened(retval == ::sudo_plugin::sys::SUDO_PLUGIN_OPEN_SUCCESS);
+
+            retval
         }
 
         unsafe extern "C" fn close(
             _exit_status: ::libc::c_int,
             _error:       ::libc::c_int,
         ) {
+            if let Err(e) = LIFECYCLE.close() {
+                if let Some(p) = PLUGIN.as_ref() {
+                    let _ = p.stderr().report_error(&e);
+                }
+
+                return;
+            }
+
             // force the instance to be dropped
             let _ = INSTANCE.take();
         }
@@ -183,44 +277,130 @@ macro_rules! sudo_io_plugin {
 
             0
         }
+
+        $( sudo_audit_plugin!($audit, PLUGIN, INSTANCE, LIFECYCLE, $subcommand); )?
     }
 }
 
+/// Internal macro used by `sudo_io_plugin` that generates the audit
+/// plugin that forwards subcommands to an I/O plugin's instance.
+#[macro_export]
+macro_rules! sudo_audit_plugin {
+    ( $audit:ident , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        #[allow(missing_docs)]
+        pub static $audit: ::sudo_plugin::sys::audit_plugin = ::sudo_plugin::sys::audit_plugin {
+            open:   Some(audit_open),
+            accept: Some(audit_accept),
+            .. ::sudo_plugin::sys::AUDIT_PLUGIN_EMPTY
+        };
+
+        unsafe extern "C" fn audit_open(
+            _version:        ::libc::c_uint,
+            _conversation:   ::sudo_plugin::sys::sudo_conv_t,
+            _plugin_printf:  ::sudo_plugin::sys::sudo_printf_t,
+            _settings:       *const *mut ::libc::c_char,
+            _user_info:      *const *mut ::libc::c_char,
+            _submit_optind:  ::libc::c_int,
+            _submit_argv:    *const *mut ::libc::c_char,
+            _submit_envp:    *const *mut ::libc::c_char,
+            _plugin_options: *const *mut ::libc::c_char,
+            _errstr:         *mut *const ::libc::c_char,
+        ) -> ::libc::c_int {
+            // everything the audit plugin needs is set up when the I/O
+            // plugin is opened
+            ::sudo_plugin::sys::SUDO_PLUGIN_OPEN_SUCCESS
+        }
+
+        unsafe extern "C" fn audit_accept(
+            _plugin_name:     *const ::libc::c_char,
+            plugin_type:      ::libc::c_uint,
+            command_info_ptr: *const *mut ::libc::c_char,
+            run_argv:         *const *mut ::libc::c_char,
+            _run_envp:        *const *mut ::libc::c_char,
+            _errstr:          *mut *const ::libc::c_char,
+        ) -> ::libc::c_int {
+            // sudo reports each plugin that accepts a command, and the
+            // command it was asked to run is accepted before the I/O
+            // plugin is even opened, so only what the policy plugin
+            // accepts while the I/O plugin is open is a subcommand
+            if plugin_type != ::sudo_plugin::sys::SUDO_POLICY_PLUGIN {
+                return 1;
+            }
+
+            // the guard keeps other callbacks out until this one is
+            // done; if the I/O plugin isn't open, there's no session
+            // to tell about the subcommand
+            let _running = match $lifecycle.enter("accept") {
+                Ok(running) => running,
+                Err(_)      => return 1,
+            };
+
+            let result : ::std::result::Result<(), ::sudo_plugin::errors::Error> = ::sudo_plugin::Subcommand::from_raw(
+                command_info_ptr as *const _,
+                run_argv         as *const _,
+            ).and_then(|subcommand| match ($plugin.as_ref(), $instance.as_mut()) {
+                (Some(p), Some(i)) => p.unless_terminated(|| i.$fn(subcommand).map_err(|e| e.into())),
+                _                  => Ok(()),
+            });
+
+            if let (Some(p), Err(e)) = ($plugin.as_ref(), result.as_ref()) {
+                if p.should_report(e) {
+                    let _ = p.stderr().report_error(&e);
+                }
+            }
+
+            // whether a subcommand may run is the policy plugin's
+            // decision, so it's never rejected here
+            1
+        }
+    };
+}
+
 /// Internal macro used by `sudo_io_plugin` that  generates the actual
 /// callback implementations for I/O plugins.
 #[macro_export]
 macro_rules! sudo_io_fn {
-    ( close , $name:tt , $plugin:expr , $instance:expr , $fn:ident ) => {{
+    ( close , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {{
         unsafe extern "C" fn close(
             exit_status: ::libc::c_int,
             error:       ::libc::c_int,
         ) {
+            if let Err(e) = $lifecycle.close() {
+                if let Some(p) = $plugin.as_ref() {
+                    let _ = p.stderr().report_error(&e);
+                }
+
+                return;
+            }
+
             if let Some(mut i) = $instance.take() {
-                i.$fn(exit_status as _, error as _);
+                i.$fn(::sudo_plugin::CommandStatus::from_close(exit_status, error));
             }
         }
 
         Some(close)
     }};
 
-    ( log_ttyin , $name:tt, $plugin:expr , $instance:expr , $fn:ident ) => {
-        sudo_io_fn!(log, log_ttyin, $name, $plugin, $instance, $fn)
+    ( log_ttyin , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {
+        sudo_io_fn!(log, log_ttyin, $name, $plugin, $instance, $lifecycle, $fn)
     };
 
-    ( log_ttyout , $name:tt, $plugin:expr , $instance:expr , $fn:ident ) => {
-        sudo_io_fn!(log, log_ttyout, $name, $plugin, $instance, $fn)
+    ( log_ttyout , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {
+        sudo_io_fn!(log, log_ttyout, $name, $plugin, $instance, $lifecycle, $fn)
     };
 
-    ( log_stdin , $name:tt, $plugin:expr , $instance:expr , $fn:ident ) => {
-        sudo_io_fn!(log, log_stdin, $name, $plugin, $instance, $fn)
+    ( log_stdin , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {
+        sudo_io_fn!(log, log_stdin, $name, $plugin, $instance, $lifecycle, $fn)
     };
 
-    ( log_stdout , $name:tt, $plugin:expr , $instance:expr , $fn:ident ) => {
-        sudo_io_fn!(log, log_stdout, $name, $plugin, $instance, $fn)
+    ( log_stdout , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {
+        sudo_io_fn!(log, log_stdout, $name, $plugin, $instance, $lifecycle, $fn)
     };
 
-    ( log_stderr , $name:tt, $plugin:expr , $instance:expr , $fn:ident ) => {
-        sudo_io_fn!(log, log_stderr, $name, $plugin, $instance, $fn)
+    ( log_stderr , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {
+        sudo_io_fn!(log, log_stderr, $name, $plugin, $instance, $lifecycle, $fn)
     };
 
     (
@@ -229,6 +409,7 @@ macro_rules! sudo_io_fn {
         $name:tt ,
         $plugin:expr ,
         $instance:expr ,
+        $lifecycle:expr ,
         $fn:ident
     ) => {{
         unsafe extern "C" fn $log_fn(
@@ -240,17 +421,24 @@ macro_rules! sudo_io_fn {
                 len as _,
             );
 
-            let result : ::std::result::Result<(), ::sudo_plugin::errors::Error> = $instance
-                .as_mut()
-                .map_or_else(
-                  || Err(::sudo_plugin::errors::ErrorKind::Uninitialized.into()),
-                  |i| i.$fn(slice).map_err(|e| e.into()),
-                );
+            // the guard keeps other callbacks out until this one is
+            // done, and once the plugin has terminated the session,
+            // every callback rejects it
+            let result : ::std::result::Result<(), ::sudo_plugin::errors::Error> = match $lifecycle.enter(stringify!($log_fn)) {
+                Ok(_running) => match ($plugin.as_ref(), $instance.as_mut()) {
+                    (Some(p), Some(i)) => p.unless_terminated(|| i.$fn(slice).map_err(|e| e.into())),
+                    _                  => Err(::sudo_plugin::errors::ErrorKind::Uninitialized.into()),
+                },
+
+                Err(e) => Err(e),
+            };
 
             // if there was an error (and we can unwrap the plugin),
             // write it out
             if let (Some(p), Err(e)) = ($plugin.as_ref(), result.as_ref()) {
-                let _ = p.stderr().write_error(&e);
+                if p.should_report(e) {
+                    let _ = p.stderr().report_error(&e);
+                }
             }
 
             result.as_sudo_io_plugin_log_retval()
@@ -259,25 +447,53 @@ macro_rules! sudo_io_fn {
         Some($log_fn)
     }};
 
-    ( change_winsize , $name:tt , $plugin:expr , $instance:expr , $fn:ident ) => {{
+    ( show_version , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {{
+        unsafe extern "C" fn show_version_and_more(
+            verbose: ::libc::c_int,
+        ) -> ::libc::c_int {
+            // every plugin reports its version the same way, before
+            // whatever else it has to say
+            let retval = show_version(verbose);
+
+            let result : ::std::result::Result<(), ::sudo_plugin::errors::Error> = match $lifecycle.enter("show_version") {
+                Ok(_running) => match $plugin.as_ref() {
+                    Some(p) => <$ty>::$fn(p, verbose != 0).map_err(|e| e.into()),
+                    None    => Err(::sudo_plugin::errors::ErrorKind::Uninitialized.into()),
+                },
+
+                Err(e) => Err(e),
+            };
+
+            if let (Some(p), Err(e)) = ($plugin.as_ref(), result.as_ref()) {
+                let _ = p.stderr().report_error(&e);
+            }
+
+            retval
+        }
+
+        Some(show_version_and_more)
+    }};
+
+    ( change_winsize , $name:tt , $ty:ty , $plugin:expr , $instance:expr , $lifecycle:expr , $fn:ident ) => {{
         unsafe extern "C" fn change_winsize(
-            lines: ::libc::c_uint,
-            cols:  ::libc::c_uint,
-        ) {
-            let result : ::std::result::Result<(), ::sudo_plugin::errors::Error> = $instance
-                .as_mut()
-                .map_or_else(
-                  || Err(::sudo_plugin::errors::ErrorKind::Uninitialized.into()),
-                  |i| i.$fn(slice).map_err(|e| e.into()),
-                );
+            rows: ::libc::c_uint,
+            cols: ::libc::c_uint,
+        ) -> ::libc::c_int {
+            let result : ::std::result::Result<(), ::sudo_plugin::errors::Error> = match $lifecycle.enter("change_winsize") {
+                Ok(_running) => match ($plugin.as_ref(), $instance.as_mut()) {
+                    (Some(p), Some(i)) => p.unless_terminated(|| i.$fn(rows as _, cols as _).map_err(|e| e.into())),
+                    _                  => Err(::sudo_plugin::errors::ErrorKind::Uninitialized.into()),
+                },
+
+                Err(e) => Err(e),
+            };
 
             // if there was an error (and we can unwrap the plugin),
             // write it out
             if let (Some(p), Err(e)) = ($plugin.as_ref(), result.as_ref()) {
-                let _ = p.stderr().write_error(
-                    p.plugin_name.as_bytes(),
-                    e,
-                );
+                if p.should_report(e) {
+                    let _ = p.stderr().report_error(&e);
+                }
             }
 
             result.as_sudo_io_plugin_log_retval()
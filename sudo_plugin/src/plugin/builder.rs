@@ -0,0 +1,376 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+
+//! Construction of a `Plugin` from owned values, rather than the raw
+//! pointers sudo passes to `open`.
+
+use super::{compat, Conversation, Level, OptionMap, Plugin, PrintFacility};
+use super::identity::NameCache;
+use crate::errors::*;
+use crate::version::Version;
+
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::AtomicBool;
+
+use libc::c_uint;
+
+/// Builds a `Plugin` from owned Rust values, for testing plugins and
+/// for running them somewhere other than inside sudo. Nothing here is
+/// unsafe: the values are parsed and checked exactly as they would be
+/// had sudo passed them to `open`.
+///
+/// Options are given as keys and values, as though sudo had provided
+/// them as `key=value` entries. Plugin options given in `sudo.conf`
+/// without a value are provided by sudo with the option's name as its
+/// value (e.g., `quiet` is equivalent to `quiet=quiet`).
+///
+/// By default, the plugin is built for the plugin API version this
+/// library was compiled against, with nothing to print through and no
+/// conversation function, so anything it prints is discarded and any
+/// conversation fails as it would if sudo provided none.
+///
+/// # Example
+///
+/// ```rust
+/// use sudo_plugin::PluginBuilder;
+///
+/// let mut builder = PluginBuilder::new("example")
+///     .command(&["/bin/ls", "-la"])
+///     .setting("plugin_dir", "/usr/libexec/sudo")
+///     .setting("plugin_path", "/usr/libexec/sudo/example.so")
+///     .setting("progname", "sudo")
+///     .plugin_option("quiet", "quiet");
+///
+/// let user_info = &[
+///     ("cwd", "/home/alice"), ("egid", "1000"), ("euid", "1000"),
+///     ("gid", "1000"), ("groups", "1000"), ("host", "example"),
+///     ("pgid", "4242"), ("pid", "4242"), ("ppid", "4241"),
+///     ("uid", "1000"), ("user", "alice"),
+/// ];
+///
+/// for (k, v) in user_info {
+///     builder = builder.user_info(k, v);
+/// }
+///
+/// let plugin = builder.build().expect("the plugin should initialize");
+///
+/// assert_eq!("alice", plugin.user_name());
+/// ```
+#[allow(missing_debug_implementations)]
+pub struct PluginBuilder {
+    pub(super) plugin_name:    String,
+    pub(super) plugin_version: Option<String>,
+    pub(super) version:        c_uint,
+    pub(super) command:        Vec<CString>,
+    pub(super) settings:       OptionMap,
+    pub(super) user_info:      OptionMap,
+    pub(super) command_info:   OptionMap,
+    pub(super) user_env:       OptionMap,
+    pub(super) plugin_options: OptionMap,
+    pub(super) stdout:         Option<(PrintFacility, PrintFacility)>,
+    pub(super) conversation:   Option<Conversation>,
+}
+
+impl PluginBuilder {
+    /// Starts building a plugin named `plugin_name`, with no command,
+    /// options, or environment.
+    pub fn new<S: Into<String>>(plugin_name: S) -> Self {
+        Self {
+            plugin_name:    plugin_name.into(),
+            plugin_version: None,
+            version:        crate::sys::SUDO_API_VERSION,
+            command:        Vec::new(),
+            settings:       OptionMap::default(),
+            user_info:      OptionMap::default(),
+            command_info:   OptionMap::default(),
+            user_env:       OptionMap::default(),
+            plugin_options: OptionMap::default(),
+            stdout:         None,
+            conversation:   None,
+        }
+    }
+
+    /// Sets the plugin's own version, as reported by `sudo -V`.
+    pub fn plugin_version<S: Into<String>>(mut self, plugin_version: S) -> Self {
+        self.plugin_version = Some(plugin_version.into());
+        self
+    }
+
+    /// Sets the plugin API version sudo reports supporting, in the form
+    /// sudo passes it (the major version in the upper 16 bits, and the
+    /// minor version in the lower 16).
+    pub fn api_version(mut self, version: c_uint) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the command being run, starting with the name it was
+    /// invoked by. Arguments containing NUL bytes are truncated at the
+    /// first one, since sudo couldn't have passed them any other way.
+    pub fn command<S: AsRef<[u8]>>(mut self, argv: &[S]) -> Self {
+        self.command = argv.iter()
+            .map(|arg| {
+                let arg = arg.as_ref();
+                let len = arg.iter().position(|b| *b == 0).unwrap_or(arg.len());
+
+                CString::new(&arg[..len]).expect("NUL bytes were removed")
+            })
+            .collect();
+
+        self
+    }
+
+    /// Adds an entry to the `settings` sudo provides.
+    pub fn setting<K: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, k: K, v: V) -> Self {
+        self.settings.insert(k.as_ref(), v.as_ref());
+        self
+    }
+
+    /// Adds an entry to the `user_info` sudo provides.
+    pub fn user_info<K: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, k: K, v: V) -> Self {
+        self.user_info.insert(k.as_ref(), v.as_ref());
+        self
+    }
+
+    /// Adds an entry to the `command_info` sudo provides.
+    pub fn command_info<K: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, k: K, v: V) -> Self {
+        self.command_info.insert(k.as_ref(), v.as_ref());
+        self
+    }
+
+    /// Adds a variable to the environment the command will be run
+    /// with.
+    pub fn user_env<K: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, k: K, v: V) -> Self {
+        self.user_env.insert(k.as_ref(), v.as_ref());
+        self
+    }
+
+    /// Adds an option to those given after the plugin's path in
+    /// `sudo.conf`.
+    pub fn plugin_option<K: AsRef<[u8]>, V: AsRef<[u8]>>(mut self, k: K, v: V) -> Self {
+        self.plugin_options.insert(k.as_ref(), v.as_ref());
+        self
+    }
+
+    /// Sets the facilities the plugin prints to the user's STDOUT and
+    /// STDERR through.
+    pub fn print_facilities(mut self, stdout: PrintFacility, stderr: PrintFacility) -> Self {
+        self.stdout = Some((stdout, stderr));
+        self
+    }
+
+    /// Sets the facility the plugin converses with the user through.
+    pub fn conversation(mut self, conversation: Conversation) -> Self {
+        self.conversation = Some(conversation);
+        self
+    }
+
+    /// Builds the plugin, checking the API version and parsing each of
+    /// sudo's vectors as `Plugin::new` does.
+    ///
+    /// Returns an error if the API version isn't supported, or if any
+    /// vector is missing something sudo always provides or has a value
+    /// that can't be parsed.
+    pub fn build(self) -> Result<Plugin> {
+        let version = Version::from(self.version).check()?;
+
+        let (stdout, stderr) = match self.stdout {
+            Some(facilities) => facilities,
+
+            // without a `printf` function, nothing is ever printed
+            None => unsafe { PrintFacility::new(Some(&self.plugin_name), None) },
+        };
+
+        let conversation = self.conversation.unwrap_or_else(||
+            // without a conversation function, every conversation fails
+            unsafe { Conversation::new(None) }
+        );
+
+        let mut user_info = self.user_info;
+
+        // older versions of sudo omit some of what newer ones provide
+        compat::adjust(version, &mut user_info, compat::current_umask);
+
+        // options are named the same in different vectors (e.g., `cwd`
+        // in both `user_info` and `command_info`), so errors name the
+        // vector they're from too
+        let settings = self.settings.try_into()
+            .chain_err(|| "sudo's settings couldn't be parsed")?;
+
+        let user_info = user_info.try_into()
+            .chain_err(|| "sudo's user_info couldn't be parsed")?;
+
+        let command_info = self.command_info.try_into()
+            .chain_err(|| "sudo's command_info couldn't be parsed")?;
+
+        Ok(Plugin {
+            plugin_name:    self.plugin_name,
+            plugin_version: self.plugin_version,
+
+            version,
+            command: self.command,
+
+            settings,
+            user_info,
+            command_info,
+            user_env:       self.user_env,
+            plugin_options: self.plugin_options,
+
+            debug: stdout.debug(),
+            stdout,
+            stderr,
+
+            min_level: Mutex::new(Level::Debug),
+
+            termination:          OnceLock::new(),
+            termination_reported: AtomicBool::new(false),
+
+            conversation,
+
+            names: NameCache::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_INFO : &[(&str, &str)] = &[
+        ("cwd", "/home/alice"), ("egid", "1000"),    ("euid", "1000"),
+        ("gid", "1000"),        ("groups", "1000,27"), ("host", "example"),
+        ("pgid", "4242"),       ("pid", "4242"),     ("ppid", "4241"),
+        ("uid", "1000"),        ("user", "alice"),
+    ];
+
+    fn builder() -> PluginBuilder {
+        USER_INFO.iter().fold(
+            PluginBuilder::new("test")
+                .setting("plugin_dir", "/usr/libexec/sudo")
+                .setting("plugin_path", "/usr/libexec/sudo/test.so")
+                .setting("progname", "sudo"),
+            |builder, (k, v)| builder.user_info(k, v),
+        )
+    }
+
+    #[test]
+    fn builds_plugins_from_owned_values() {
+        let plugin = builder()
+            .plugin_version("1.0.0")
+            .command(&["/bin/ls", "-la"])
+            .command_info("command", "/usr/bin/ls")
+            .user_env("TERM", "xterm")
+            .plugin_option("quiet", "quiet")
+            .build()
+            .expect("the plugin should initialize");
+
+        assert_eq!("test",        plugin.plugin_name);
+        assert_eq!(Some("1.0.0"), plugin.plugin_version.as_deref());
+        assert_eq!("alice",       plugin.user_name());
+        assert_eq!(Some("xterm"), plugin.user_env.get_str("TERM"));
+        assert_eq!(Some("quiet"), plugin.plugin_options.get_str("quiet"));
+        assert_eq!(b"sudo /bin/ls -la".to_vec(), plugin.invocation());
+        assert!(plugin.is_user_in_group(27));
+        assert_eq!(
+            vec![CString::new("/bin/ls").unwrap(), CString::new("-la").unwrap()],
+            plugin.command,
+        );
+    }
+
+    #[test]
+    fn fills_in_what_older_versions_omit() {
+        let plugin = builder()
+            .api_version(1 << 16 | 9)
+            .build()
+            .expect("the plugin should initialize");
+
+        assert!(plugin.user_info.umask.is_some());
+    }
+
+    #[test]
+    fn rejects_callbacks_once_terminated() {
+        let plugin = builder().build().expect("the plugin should initialize");
+
+        assert!(plugin.unless_terminated(|| Ok(())).is_ok());
+
+        // the callback that terminates the session is rejected too
+        let result = plugin.unless_terminated(|| {
+            plugin.terminate("the session ran too long");
+            plugin.terminate("the session was idle");
+
+            Ok(())
+        });
+
+        let error = result.expect_err("the callback should be rejected");
+
+        assert_eq!("the session ran too long", error.to_string());
+        assert_eq!(Some("the session ran too long"), plugin.termination());
+        assert!(plugin.should_report(&error));
+
+        let mut called = false;
+        let error      = plugin.unless_terminated(|| { called = true; Ok(()) })
+            .expect_err("the callback should be rejected");
+
+        assert!(!called);
+
+        // but the user's only told once
+        assert!(!plugin.should_report(&error));
+        assert!(plugin.should_report(&ErrorKind::Unauthorized.into()));
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let error = builder()
+            .api_version(1 << 16 | 8)
+            .build()
+            .err()
+            .expect("the plugin shouldn't initialize");
+
+        match error.kind() {
+            ErrorKind::UnsupportedApiVersion(_) => {},
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn names_missing_and_malformed_options() {
+        let error = builder()
+            .user_info("uid", "alice")
+            .build()
+            .err()
+            .expect("the plugin shouldn't initialize");
+
+        let messages : Vec<_> = error.iter().map(ToString::to_string).collect();
+
+        assert_eq!(vec![
+            "sudo's user_info couldn't be parsed",
+            "option uid couldn't be parsed as u32 (its value is \"alice\")",
+        ], messages);
+
+        let error = PluginBuilder::new("test")
+            .build()
+            .err()
+            .expect("the plugin shouldn't initialize");
+
+        let messages : Vec<_> = error.iter().map(ToString::to_string).collect();
+
+        assert_eq!(vec![
+            "sudo's settings couldn't be parsed",
+            "option plugin_dir wasn't provided to the plugin",
+        ], messages);
+    }
+}
@@ -13,22 +13,39 @@
 // permissions and limitations under the License.
 
 use crate::errors::*;
+use super::user_info::parse_umask;
 use super::option_map::*;
 
 use std::convert::TryFrom;
 use std::os::unix::io::RawFd;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use libc::{self, gid_t, mode_t, uid_t};
 
+/// The `command_info` sudo provides to I/O plugins, describing the
+/// command the policy plugin has decided to run.
+///
+/// Each field corresponds to the option of the same name described in
+/// `sudo_plugin(8)`, and `raw` holds every option as it was provided.
+///
+/// With the `serde` feature, every field but `raw` is serialized; `raw`
+/// can be serialized on its own.
+#[allow(missing_docs)]
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct CommandInfo {
-    pub chroot:            Option<String>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::optional_path"))]
+    pub chroot:            Option<PathBuf>,
     pub close_from:        Option<u64>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::path"))]
     pub command:           PathBuf,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::optional_path"))]
     pub cwd:               Option<PathBuf>,
+    pub cwd_optional:      bool,
     pub exec_background:   bool,
     pub exec_fd:           Option<u64>,
+    pub intercept:         bool,
     pub iolog_compress:    bool,
     pub iolog_group:       Option<String>,
     pub iolog_mode:        Option<String>,
@@ -55,11 +72,13 @@ pub struct CommandInfo {
     pub sudoedit:          bool,
     pub sudoedit_checkdir: bool,
     pub sudoedit_follow:   bool,
-    pub timeout:           Option<u64>,
+    pub timeout:           Option<Duration>,
     pub umask:             mode_t,
+    pub umask_override:    bool,
     pub use_pty:           bool,
     pub utmp_user:         Option<String>,
 
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub raw: OptionMap,
 }
 
@@ -67,52 +86,55 @@ impl TryFrom<OptionMap> for CommandInfo {
     type Error = Error;
 
     fn try_from(value: OptionMap) -> Result<Self> {
-        let runas_gid = value.get("runas_gid")
-            .unwrap_or_else(|_| unsafe { libc::getegid() });
+        let runas_gid = value.get_optional("runas_gid")
+            .unwrap_or_else(|| unsafe { libc::getegid() });
 
-        let runas_uid = value.get("runas_uid")
-            .unwrap_or_else(|_| unsafe { libc::geteuid() });
+        let runas_uid = value.get_optional("runas_uid")
+            .unwrap_or_else(|| unsafe { libc::geteuid() });
 
         Ok(Self {
             // in the event that the `-V` flag is passed to `sudo`,
             // there's no command
-            command:       value.get("command").unwrap_or_default(),
+            command:       value.get_optional("command").unwrap_or_default(),
             runas_gid,
             runas_uid,
-            runas_egid:    value.get("runas_egid").unwrap_or(runas_gid),
-            runas_euid:    value.get("runas_euid").unwrap_or(runas_uid),
-            umask:         value.get("umask").unwrap_or(0o7777),
+            runas_egid:    value.get_optional("runas_egid").unwrap_or(runas_gid),
+            runas_euid:    value.get_optional("runas_euid").unwrap_or(runas_uid),
+            umask:         value.get_str("umask").and_then(parse_umask).unwrap_or(0o7777),
 
-            chroot:            value.get("chroot")            .ok(),
-            close_from:        value.get("closefrom")         .ok(),
-            cwd:               value.get("cwd")               .ok(),
-            exec_background:   value.get("exec_background")   .unwrap_or(false),
-            exec_fd:           value.get("execfd")            .ok(),
-            iolog_compress:    value.get("iolog_compress")    .unwrap_or(false),
-            iolog_group:       value.get("iolog_group")       .ok(),
-            iolog_mode:        value.get("iolog_mode")        .ok(),
-            iolog_path:        value.get("iolog_path")        .ok(),
-            iolog_stdin:       value.get("iolog_stdin")       .unwrap_or(false),
-            iolog_stdout:      value.get("iolog_stdout")      .unwrap_or(false),
-            iolog_stderr:      value.get("iolog_stderr")      .unwrap_or(false),
-            iolog_ttyin:       value.get("iolog_ttyin")       .unwrap_or(false),
-            iolog_ttyout:      value.get("iolog_ttyout")      .unwrap_or(false),
-            iolog_user:        value.get("iolog_user")        .ok(),
-            login_class:       value.get("login_class")       .ok(),
-            nice:              value.get("nice")              .ok(),
-            noexec:            value.get("noexec")            .unwrap_or(false),
-            preserve_fds:      value.get("preserve_fds")      .unwrap_or_default(),
-            preserve_groups:   value.get("preserve_groups")   .unwrap_or(false),
-            runas_groups:      value.get("runas_groups")      .ok(),
-            selinux_role:      value.get("selinux_role")      .ok(),
-            selinux_type:      value.get("selinux_type")      .ok(),
-            set_utmp:          value.get("set_utmp")          .unwrap_or(false),
-            sudoedit:          value.get("sudoedit")          .unwrap_or(false),
-            sudoedit_checkdir: value.get("sudoedit_checkdir") .unwrap_or(true),
-            sudoedit_follow:   value.get("sudoedit_follow")   .unwrap_or(false),
-            timeout:           value.get("timeout")           .ok(),
-            use_pty:           value.get("use_pty")           .unwrap_or(false),
-            utmp_user:         value.get("utmp_user")         .ok(),
+            chroot:            value.get_optional("chroot"),
+            close_from:        value.get_optional("closefrom"),
+            cwd:               value.get_optional("cwd"),
+            cwd_optional:      value.get_optional("cwd_optional")      .unwrap_or(false),
+            exec_background:   value.get_optional("exec_background")   .unwrap_or(false),
+            exec_fd:           value.get_optional("execfd"),
+            intercept:         value.get_optional("intercept")         .unwrap_or(false),
+            iolog_compress:    value.get_optional("iolog_compress")    .unwrap_or(false),
+            iolog_group:       value.get_optional("iolog_group"),
+            iolog_mode:        value.get_optional("iolog_mode"),
+            iolog_path:        value.get_optional("iolog_path"),
+            iolog_stdin:       value.get_optional("iolog_stdin")       .unwrap_or(false),
+            iolog_stdout:      value.get_optional("iolog_stdout")      .unwrap_or(false),
+            iolog_stderr:      value.get_optional("iolog_stderr")      .unwrap_or(false),
+            iolog_ttyin:       value.get_optional("iolog_ttyin")       .unwrap_or(false),
+            iolog_ttyout:      value.get_optional("iolog_ttyout")      .unwrap_or(false),
+            iolog_user:        value.get_optional("iolog_user"),
+            login_class:       value.get_optional("login_class"),
+            nice:              value.get_optional("nice"),
+            noexec:            value.get_optional("noexec")            .unwrap_or(false),
+            preserve_fds:      value.get_optional("preserve_fds")      .unwrap_or_default(),
+            preserve_groups:   value.get_optional("preserve_groups")   .unwrap_or(false),
+            runas_groups:      value.get_optional("runas_groups"),
+            selinux_role:      value.get_optional("selinux_role"),
+            selinux_type:      value.get_optional("selinux_type"),
+            set_utmp:          value.get_optional("set_utmp")          .unwrap_or(false),
+            sudoedit:          value.get_optional("sudoedit")          .unwrap_or(false),
+            sudoedit_checkdir: value.get_optional("sudoedit_checkdir") .unwrap_or(true),
+            sudoedit_follow:   value.get_optional("sudoedit_follow")   .unwrap_or(false),
+            timeout:           value.get_optional("timeout"),
+            umask_override:    value.get_optional("umask_override")    .unwrap_or(false),
+            use_pty:           value.get_optional("use_pty")           .unwrap_or(false),
+            utmp_user:         value.get_optional("utmp_user"),
 
             raw: value,
         })
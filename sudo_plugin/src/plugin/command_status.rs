@@ -0,0 +1,88 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::fmt;
+use std::io;
+
+use libc::c_int;
+
+/// How the command ended, as sudo reports it to a plugin's `close`
+/// callback.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommandStatus {
+    /// The command exited with a status code.
+    Exited(i32),
+
+    /// The command was killed by a signal.
+    Signaled(i32),
+
+    /// The command never ran, because sudo couldn't execute it; holds
+    /// the `errno` it failed with.
+    CommandNotRun(i32),
+
+    /// Sudo didn't report how the command ended (e.g., because it was
+    /// stopped rather than ended).
+    Unknown,
+}
+
+impl CommandStatus {
+    /// Interprets the arguments sudo passes to a plugin's `close`
+    /// callback: an `errno` if the command couldn't be executed, or
+    /// else its `wait(2)` status.
+    pub fn from_close(exit_status: c_int, error: c_int) -> Self {
+        if error != 0 {
+            return Self::CommandNotRun(error);
+        }
+
+        if libc::WIFEXITED(exit_status) {
+            Self::Exited(libc::WEXITSTATUS(exit_status))
+        } else if libc::WIFSIGNALED(exit_status) {
+            Self::Signaled(libc::WTERMSIG(exit_status))
+        } else {
+            Self::Unknown
+        }
+    }
+
+    /// Returns whether the command was run at all.
+    pub fn was_run(self) -> bool {
+        !matches!(self, Self::CommandNotRun(_))
+    }
+}
+
+impl fmt::Display for CommandStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Exited(code)         => write!(f, "exited with status {}", code),
+            Self::Signaled(signal)     => write!(f, "killed by signal {}", signal),
+            Self::CommandNotRun(errno) => write!(f, "not run: {}", io::Error::from_raw_os_error(errno)),
+            Self::Unknown              => f.write_str("ended in an unknown way"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interprets_close() {
+        assert_eq!(CommandStatus::Exited(0),        CommandStatus::from_close(0, 0));
+        assert_eq!(CommandStatus::Exited(1),        CommandStatus::from_close(1 << 8, 0));
+        assert_eq!(CommandStatus::Signaled(9),      CommandStatus::from_close(9, 0));
+        assert_eq!(CommandStatus::CommandNotRun(2), CommandStatus::from_close(0, 2));
+
+        assert!(CommandStatus::Signaled(9).was_run());
+        assert!(!CommandStatus::CommandNotRun(2).was_run());
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Adjustments for the differences between what versions of sudo
+//! provide plugins, so that a plugin built once behaves the same under
+//! every version of sudo it supports.
+//!
+//! Plugins are told sudo's plugin API version rather than its release.
+//! Within the versions this library supports, the API has changed as
+//! follows (per the version history in `sudo_plugin(8)`):
+//!
+//! | API  | sudo   | change                                               | handling                |
+//! |------|--------|------------------------------------------------------|-------------------------|
+//! | 1.9  | 1.8.16 | `execfd` added to `command_info`                     | optional                |
+//! | 1.10 | 1.8.19 | `umask` added to `user_info`; `iolog_group`,         | `umask` filled in below |
+//! |      |        | `iolog_mode`, and `iolog_user` to `command_info`     |                         |
+//! | 1.11 | 1.8.20 | `timeout` added to `settings`                        | optional                |
+//! | 1.12 | 1.8.21 | `change_winsize` callback added                      | `calls_change_winsize`  |
+//! | 1.13 | 1.8.26 | `log_suspend` callback added                         | unused                  |
+//! | 1.14 | 1.8.29 | `umask_override` added to `command_info`             | `false` when missing    |
+//! | 1.15 | 1.9.0  | `cwd_optional` added to `command_info`; callbacks    | `false` when missing;   |
+//! |      |        | are passed an `errstr` to report errors through      | unused                  |
+//!
+//! Entries that were added are parsed as optional (or with the default
+//! that matches how older versions behaved), so for the most part
+//! nothing needs adjusting. Where a later version provides something
+//! an earlier one can be made to provide too, it's filled in here.
+
+use super::option_map::OptionMap;
+use crate::version::Version;
+
+use libc::mode_t;
+
+/// The first API version providing the invoking user's `umask` in
+/// `user_info`.
+const USER_UMASK : Version = Version::new(1, 10);
+
+/// The first API version calling the `change_winsize` callback.
+const CHANGE_WINSIZE : Version = Version::new(1, 12);
+
+/// Fills in what sudo `version` omitted from its vectors but later
+/// versions provide. Entries sudo did provide are never changed.
+///
+/// `umask` is only called if the invoking user's umask is needed, and
+/// must return it.
+pub(crate) fn adjust<F: FnOnce() -> mode_t>(
+    version:   Version,
+    user_info: &mut OptionMap,
+    umask:     F,
+) {
+    // sudo 1.8.19 began providing the umask sudo was invoked with,
+    // which it reads from its own process before running anything;
+    // when plugins are opened, it's still the user's, so older
+    // versions' can be read the same way
+    if version < USER_UMASK {
+        user_info.insert_default("umask", format!("0{:o}", umask()).as_bytes());
+    }
+}
+
+/// Returns whether sudo `version` calls the `change_winsize` callback
+/// when the user's terminal is resized. Older versions ignore it, so
+/// plugins only ever learn the terminal's size when they're opened.
+pub(crate) fn calls_change_winsize(version: Version) -> bool {
+    version >= CHANGE_WINSIZE
+}
+
+/// Returns the process's umask, leaving it unchanged.
+pub(crate) fn current_umask() -> mode_t {
+    // the umask can only be read by setting it, so it's immediately
+    // set back; sudo is single-threaded while plugins are opened
+    unsafe {
+        let umask = libc::umask(0);
+        let _     = libc::umask(umask);
+
+        umask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    fn user_info(entries: &[&[u8]]) -> OptionMap {
+        let mut pointers : Vec<_> = entries.iter()
+            .map(|entry| entry.as_ptr() as *const _)
+            .collect();
+
+        pointers.push(ptr::null());
+
+        unsafe { OptionMap::from_raw(pointers.as_ptr()) }
+    }
+
+    #[test]
+    fn fills_in_the_umask_for_older_versions() {
+        let mut old = user_info(&[b"user=alice\0"]);
+
+        adjust(Version::new(1, 9), &mut old, || 0o022);
+
+        assert_eq!(Some("022"), old.get_str("umask"));
+    }
+
+    #[test]
+    fn leaves_what_sudo_provided() {
+        let mut new = user_info(&[b"user=alice\0"]);
+
+        adjust(Version::new(1, 10), &mut new, || panic!("the umask isn't needed"));
+
+        assert!(new.get_str("umask").is_none());
+
+        let mut provided = user_info(&[b"umask=0077\0"]);
+
+        adjust(Version::new(1, 9), &mut provided, || 0o022);
+
+        assert_eq!(Some("0077"), provided.get_str("umask"));
+    }
+
+    #[test]
+    fn knows_which_versions_report_resizes() {
+        assert!(!calls_change_winsize(Version::new(1, 11)));
+        assert!( calls_change_winsize(Version::new(1, 12)));
+        assert!( calls_change_winsize(Version::new(1, 17)));
+    }
+}
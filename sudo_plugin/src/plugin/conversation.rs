@@ -0,0 +1,249 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::errors::*;
+use crate::sys;
+
+use sudo_plugin_sys::sudo_conv_t;
+use zeroize::{Zeroize, Zeroizing};
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A facility that allows two-way communication with the user invoking
+/// `sudo` through the conversation function provided by the
+/// sudo_plugin API. Unlike [`PrintFacility`](super::PrintFacility),
+/// `sudo` is responsible for choosing how to reach the user (e.g., a
+/// TTY, an askpass helper, or plain STDERR), so this can be used even
+/// when the user has no terminal.
+#[derive(Clone, Debug)]
+pub struct Conversation {
+    /// A function pointer to the underlying conversation facility
+    /// provided by the sudo_plugin API.
+    facility: Arc<Mutex<sudo_conv_t>>,
+}
+
+impl Conversation {
+    /// Constructs a new `Conversation` from the function pointer provided
+    /// to the plugin's `open` callback.
+    ///
+    /// # Safety
+    ///
+    /// This function *must* be provided with either a `None` or a real
+    /// pointer to a conversation function as defined by the sudo_plugin
+    /// API. Once provided to this function, the function pointer should
+    /// be discarded and never used, as it is unsafe for this function to
+    /// be called concurrently.
+    #[must_use]
+    pub unsafe fn new(conversation: sudo_conv_t) -> Self {
+        Self { facility: Arc::new(Mutex::new(conversation)) }
+    }
+
+    /// Displays an informational message to the user.
+    pub fn info(&self, message: &[u8]) -> Result<()> {
+        self.converse(sys::SUDO_CONV_INFO_MSG, message, None).map(|_| ())
+    }
+
+    /// Displays an error message to the user.
+    pub fn error(&self, message: &[u8]) -> Result<()> {
+        self.converse(sys::SUDO_CONV_ERROR_MSG, message, None).map(|_| ())
+    }
+
+    /// Prompts the user for input, returning their response. If `echo`
+    /// is `false`, the user's input will not be echoed back to them as
+    /// they type (e.g., for passwords).
+    ///
+    /// Returns `ConversationInterrupted` if a signal interrupts the
+    /// prompt, and `ConversationUnavailable` if sudo can't reach the
+    /// user.
+    ///
+    /// The response may be a secret, so it's wiped from memory when
+    /// it's dropped.
+    pub fn prompt(&self, message: &[u8], echo: bool) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(self.converse(Self::prompt_type(echo), message, None)?.unwrap_or_default())
+    }
+
+    /// Prompts the user for input like `prompt`, but gives up with
+    /// `ConversationTimedOut` if they haven't responded within
+    /// `timeout`. Timeouts are measured in whole seconds, and are
+    /// rounded up to at least one.
+    pub fn prompt_timeout(&self, message: &[u8], echo: bool, timeout: Duration) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(self.converse(Self::prompt_type(echo), message, Some(timeout))?.unwrap_or_default())
+    }
+
+    fn prompt_type(echo: bool) -> u32 {
+        if echo {
+            sys::SUDO_CONV_PROMPT_ECHO_ON
+        } else {
+            sys::SUDO_CONV_PROMPT_ECHO_OFF
+        }
+    }
+
+    fn converse(
+        &self,
+        msg_type: u32,
+        message:  &[u8],
+        timeout:  Option<Duration>,
+    ) -> Result<Option<Zeroizing<Vec<u8>>>> {
+        let guard = self.facility.lock().map_err(|_|
+            Error::from("couldn't acquire conversation mutex")
+        )?;
+
+        let conversation = guard.ok_or_else(||
+            Error::from(ErrorKind::ConversationUnavailable)
+        )?;
+
+        let message = CString::new(message)
+            .chain_err(|| "conversation message contained a NUL byte")?;
+
+        let msg = sys::sudo_conv_message {
+            msg_type: msg_type as _,
+            timeout:  timeout.map_or(0, timeout_secs),
+            msg:      message.as_ptr(),
+        };
+
+        let mut reply = sys::sudo_conv_reply {
+            reply: ptr::null_mut(),
+        };
+
+        let start = Instant::now();
+        let ret   = unsafe {
+            (conversation)(1, &msg, &mut reply, ptr::null_mut())
+        };
+        let errno = io::Error::last_os_error().raw_os_error();
+
+        // sudo allocates the reply (if any) with `malloc(3)`, so it's
+        // our responsibility to copy it out and free it regardless of
+        // whether or not the call succeeded; it's wiped first, since
+        // freeing it would leave it readable in the heap
+        let response = if reply.reply.is_null() {
+            None
+        } else {
+            unsafe {
+                let len   = CStr::from_ptr(reply.reply).to_bytes().len();
+                let bytes = slice::from_raw_parts_mut(reply.reply.cast::<u8>(), len);
+                let copy  = Zeroizing::new(bytes.to_vec());
+
+                bytes.zeroize();
+                libc::free(reply.reply as _);
+
+                Some(copy)
+            }
+        };
+
+        if ret == -1 {
+            return Err(failure_kind(errno, timeout, start.elapsed()).into());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Converts `timeout` to the whole number of seconds sudo expects,
+/// rounding up so that short timeouts don't become "no timeout".
+fn timeout_secs(timeout: Duration) -> libc::c_int {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+
+    secs.max(1).min(libc::c_int::MAX as u64) as _
+}
+
+/// Classifies a failed conversation. sudo doesn't report why a
+/// conversation failed, but it leaves `errno` set by whatever went
+/// wrong: reading a response is interrupted by a signal (`EINTR`)
+/// whether the user sent it or the timeout did, so the two are told
+/// apart by whether the timeout has elapsed.
+fn failure_kind(errno: Option<i32>, timeout: Option<Duration>, elapsed: Duration) -> ErrorKind {
+    match (errno, timeout) {
+        (Some(libc::EINTR), Some(t)) if elapsed >= t     => ErrorKind::ConversationTimedOut,
+        (Some(libc::EINTR), _)                           => ErrorKind::ConversationInterrupted,
+        (Some(libc::ENOTTY), _) | (Some(libc::ENXIO), _) => ErrorKind::ConversationUnavailable,
+        _                                                => ErrorKind::Msg(
+            "the conversation function returned an error".into()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_failures() {
+        let second = Duration::from_secs(1);
+
+        assert!(matches!(
+            failure_kind(Some(libc::EINTR), Some(second), second),
+            ErrorKind::ConversationTimedOut,
+        ));
+
+        assert!(matches!(
+            failure_kind(Some(libc::EINTR), Some(second), Duration::from_millis(10)),
+            ErrorKind::ConversationInterrupted,
+        ));
+
+        assert!(matches!(
+            failure_kind(Some(libc::EINTR), None, second),
+            ErrorKind::ConversationInterrupted,
+        ));
+
+        assert!(matches!(
+            failure_kind(Some(libc::ENOTTY), None, second),
+            ErrorKind::ConversationUnavailable,
+        ));
+
+        assert!(matches!(
+            failure_kind(Some(libc::EIO), None, second),
+            ErrorKind::Msg(_),
+        ));
+    }
+
+    #[test]
+    fn rounds_timeouts_up() {
+        assert_eq!(1, timeout_secs(Duration::from_millis(1)));
+        assert_eq!(1, timeout_secs(Duration::from_secs(0)));
+        assert_eq!(3, timeout_secs(Duration::from_millis(2500)));
+        assert_eq!(5, timeout_secs(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn copies_out_replies() {
+        unsafe extern "C" fn reply(
+            _:       libc::c_int,
+            _:       *const sys::sudo_conv_message,
+            replies: *mut sys::sudo_conv_reply,
+            _:       *mut sys::sudo_conv_callback,
+        ) -> libc::c_int {
+            (*replies).reply = libc::strdup(b"hunter2\0".as_ptr().cast());
+            0
+        }
+
+        let conversation = unsafe { Conversation::new(Some(reply)) };
+
+        assert_eq!(b"hunter2".to_vec(), *conversation.prompt(b"password: ", false).unwrap());
+    }
+
+    #[test]
+    fn missing_conversation_is_unavailable() {
+        let conversation = unsafe { Conversation::new(None) };
+
+        assert!(matches!(
+            conversation.info(b"hello").unwrap_err().kind(),
+            ErrorKind::ConversationUnavailable,
+        ));
+    }
+}
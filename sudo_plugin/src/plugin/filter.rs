@@ -0,0 +1,531 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! External processes that a session's I/O is passed through (e.g., to
+//! redact secrets from it before it's logged). Plugins run inside sudo
+//! itself, so a filter has to be careful not to take sudo down with it:
+//! its I/O never blocks indefinitely, writing to one that's exited
+//! doesn't raise `SIGPIPE`, and it's never left running once the
+//! session is over.
+
+use std::io::{Error, ErrorKind, Read, Result};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+
+/// How long a filter has to exit once its input is closed before it's
+/// killed, unless `Filter::set_grace` says otherwise.
+const DEFAULT_GRACE : Duration = Duration::from_secs(1);
+
+/// How much of a filter's output is read at a time.
+const READ_SIZE : usize = 8 * 1024;
+
+/// How often a filter is checked on while waiting for it to exit.
+const EXIT_POLL : Duration = Duration::from_millis(5);
+
+/// Writes to a socket whose reader has gone away fail with `EPIPE`,
+/// rather than raising `SIGPIPE`, which sudo may not be ignoring.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SEND_FLAGS : c_int = libc::MSG_NOSIGNAL;
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SEND_FLAGS : c_int = 0;
+
+/// A child process that data is written to on its standard input and
+/// read back from its standard output, both without blocking. Its
+/// lifecycle is tied to the session's: it's in a process group of its
+/// own, so signals sent to the user's terminal don't reach it; on Linux
+/// it's killed if sudo itself dies; and it's stopped and reaped when
+/// it's finished or dropped, at the latest when the plugin is closed.
+///
+/// Filters are chained by passing the output of one to the next.
+///
+/// ```no_run
+/// use sudo_plugin::Filter;
+/// use std::process::Command;
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut redact = Filter::spawn(&mut Command::new("/usr/local/bin/redact"))?;
+/// let mut upcase = Filter::spawn(Command::new("tr").args(&["a-z", "A-Z"]))?;
+///
+/// let mut redacted = Vec::new();
+/// let mut upcased  = Vec::new();
+///
+/// // what each filter has output so far is appended to its buffer
+/// redact.pump(b"password: hunter2\n", &mut redacted, Duration::from_millis(50))?;
+/// upcase.pump(&redacted, &mut upcased, Duration::from_millis(50))?;
+///
+/// // whatever's left comes out once their input is closed
+/// let (rest, _) = redact.finish(Duration::from_secs(1))?;
+/// upcase.pump(&rest, &mut upcased, Duration::from_millis(50))?;
+/// upcased.extend(upcase.finish(Duration::from_secs(1))?.0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Filter {
+    child:  Child,
+
+    /// our end of the filter's standard input, until it's closed
+    input:  Option<UnixStream>,
+
+    /// the filter's standard output, until it's reached its end
+    output: Option<ChildStdout>,
+
+    /// how long the filter has to exit on its own when dropped
+    grace:  Duration,
+}
+
+impl Filter {
+    /// Spawns `command` as a filter, with its standard input and output
+    /// connected to the plugin. Everything else about it (its
+    /// arguments, environment, standard error, and the user and group
+    /// it runs as) is left as `command` has it; the latter default to
+    /// sudo's own, so the filter runs as root unless told otherwise.
+    pub fn spawn(command: &mut Command) -> Result<Self> {
+        // the filter's standard input is a socket rather than a pipe,
+        // so that writing to a filter that's exited can fail without
+        // raising `SIGPIPE`
+        let (input, theirs) = UnixStream::pair()?;
+
+        nosigpipe(&input)?;
+
+        let _ = command
+            .stdin(Stdio::from(OwnedFd::from(theirs)))
+            .stdout(Stdio::piped());
+
+        unsafe {
+            let _ = command.pre_exec(detach);
+        }
+
+        let mut child = command.spawn()?;
+        let output    = child.stdout.take();
+
+        let filter = Self {
+            child,
+            input:  Some(input),
+            output,
+            grace:  DEFAULT_GRACE,
+        };
+
+        // if either can't be made non-blocking, dropping the filter
+        // stops it
+        if let Some(ref input) = filter.input {
+            input.set_nonblocking(true)?;
+        }
+
+        if let Some(ref output) = filter.output {
+            nonblocking(output.as_raw_fd())?;
+        }
+
+        Ok(filter)
+    }
+
+    /// Sets how long the filter has to exit on its own once its input
+    /// is closed when it's dropped, before it's killed. Defaults to one
+    /// second.
+    pub fn set_grace(&mut self, grace: Duration) {
+        self.grace = grace;
+    }
+
+    /// The process id of the filter.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// The descriptor the filter's input is written to, for waiting
+    /// until it can be (e.g., with `poll(2)`), or `None` once it's been
+    /// closed.
+    pub fn input_fd(&self) -> Option<RawFd> {
+        self.input.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    /// The descriptor the filter's output is read from, for waiting
+    /// until there's some, or `None` once it's all been read.
+    pub fn output_fd(&self) -> Option<RawFd> {
+        self.output.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    /// Writes as much of `data` as the filter's input can take without
+    /// blocking, returning how much that was. Fails with `WouldBlock`
+    /// if it can't take any, and with `BrokenPipe` if the filter's
+    /// stopped reading it (or it's been closed).
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let input = self.input.as_ref().ok_or_else(|| Error::from(ErrorKind::BrokenPipe))?;
+
+        loop {
+            let written = unsafe {
+                libc::send(input.as_raw_fd(), data.as_ptr() as *const _, data.len(), SEND_FLAGS)
+            };
+
+            if written >= 0 {
+                return Ok(written as usize);
+            }
+
+            let error = Error::last_os_error();
+
+            if error.kind() != ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Reads what the filter's output into `buf` without blocking,
+    /// returning how much was read, or `0` once the filter's closed its
+    /// output. Fails with `WouldBlock` if it hasn't output anything
+    /// since it was last read.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let output = match self.output {
+            Some(ref mut output) => output,
+            None                 => return Ok(0),
+        };
+
+        loop {
+            match output.read(buf) {
+                Ok(0) => {
+                    self.output = None;
+                    return Ok(0);
+                },
+
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+
+                result => return result,
+            }
+        }
+    }
+
+    /// Writes all of `data` to the filter, appending its output to
+    /// `out` as it goes (so a filter that won't read more until its
+    /// output has been can't deadlock), and then whatever else it's
+    /// output by the time it's all been written. Fails with `TimedOut`
+    /// if the filter hasn't taken all of `data` within `timeout`, in
+    /// which case some of it may have been written.
+    pub fn pump(&mut self, data: &[u8], out: &mut Vec<u8>, timeout: Duration) -> Result<()> {
+        let deadline    = Instant::now() + timeout;
+        let mut written = 0;
+
+        while written < data.len() {
+            match self.write(&data[written..]) {
+                Ok(count) => written += count,
+
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    let _ = self.drain(out)?;
+
+                    if !self.wait(true, deadline)? {
+                        return Err(ErrorKind::TimedOut.into());
+                    }
+                },
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        let _ = self.drain(out)?;
+
+        Ok(())
+    }
+
+    /// Closes the filter's input, telling it there's nothing more to
+    /// filter.
+    pub fn close(&mut self) {
+        self.input = None;
+    }
+
+    /// Returns the filter's exit status if it's exited, without waiting
+    /// for it to.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Closes the filter's input and collects the rest of its output
+    /// until it closes its output (which it usually does by exiting) or
+    /// `timeout` passes, after which it's killed. Returns that output
+    /// and how the filter exited.
+    pub fn finish(mut self, timeout: Duration) -> Result<(Vec<u8>, ExitStatus)> {
+        let deadline = Instant::now() + timeout;
+        let mut out  = Vec::new();
+
+        self.close();
+
+        while self.drain(&mut out)? {
+            if !self.wait(false, deadline)? {
+                break;
+            }
+        }
+
+        let status = self.stop(deadline)?;
+
+        Ok((out, status))
+    }
+
+    /// Appends what the filter's output to `out` without blocking,
+    /// returning whether there may be more to come.
+    fn drain(&mut self, out: &mut Vec<u8>) -> Result<bool> {
+        let mut buf = [0; READ_SIZE];
+
+        loop {
+            match self.read(&mut buf) {
+                Ok(0)     => return Ok(false),
+                Ok(count) => out.extend_from_slice(&buf[..count]),
+
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits until the filter's output can be read (or, if `writing`,
+    /// its input written) or `deadline` passes, returning whether it
+    /// hasn't.
+    fn wait(&self, writing: bool, deadline: Instant) -> Result<bool> {
+        let mut fds = Vec::with_capacity(2);
+
+        if let Some(fd) = self.output_fd() {
+            fds.push(libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+        }
+
+        if let Some(fd) = self.input_fd().filter(|_| writing) {
+            fds.push(libc::pollfd { fd, events: libc::POLLOUT, revents: 0 });
+        }
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+
+            if left.is_zero() {
+                return Ok(false);
+            }
+
+            // rounded up, so a deadline less than a millisecond away is
+            // still waited for
+            let millis = left.as_millis().saturating_add(1).min(c_int::MAX as u128) as c_int;
+            let ready  = unsafe {
+                libc::poll(fds.as_mut_ptr(), fds.len() as _, millis)
+            };
+
+            match ready {
+                -1 => {
+                    let error = Error::last_os_error();
+
+                    if error.kind() != ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                },
+
+                0 => continue,
+                _ => return Ok(true),
+            }
+        }
+    }
+
+    /// Waits until `deadline` for the filter to exit, then kills it if
+    /// it hasn't, and reaps it.
+    fn stop(&mut self, deadline: Instant) -> Result<ExitStatus> {
+        self.close();
+
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status);
+            }
+
+            thread::sleep(EXIT_POLL);
+        }
+
+        // the filter may have exited since it was last checked on, in
+        // which case there's nothing left to kill
+        let _ = self.child.kill();
+
+        self.child.wait()
+    }
+}
+
+impl Drop for Filter {
+    fn drop(&mut self) {
+        let deadline = Instant::now() + self.grace;
+
+        self.output = None;
+
+        let _ = self.stop(deadline);
+    }
+}
+
+/// Run in the filter between forking and executing it, to keep
+/// signals meant for the user's command away from it, and (where it's
+/// possible) to have it killed if sudo goes away without stopping it.
+fn detach() -> Result<()> {
+    check(unsafe { libc::setpgid(0, 0) })?;
+
+    #[cfg(target_os = "linux")]
+    check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL as libc::c_ulong) })?;
+
+    Ok(())
+}
+
+/// Keeps writes to `socket` from raising `SIGPIPE` on platforms where
+/// `send(2)` can't be told not to.
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+fn nosigpipe(socket: &UnixStream) -> Result<()> {
+    let on : c_int = 1;
+
+    check(unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_NOSIGPIPE,
+            std::ptr::addr_of!(on).cast(),
+            std::mem::size_of::<c_int>() as _,
+        )
+    })
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+fn nosigpipe(_: &UnixStream) -> Result<()> {
+    Ok(())
+}
+
+/// Makes reads from `fd` return `WouldBlock` rather than block.
+fn nonblocking(fd: RawFd) -> Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+
+    check(flags)?;
+    check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })
+}
+
+/// Converts the return value of a libc function that returns `-1` on
+/// failure into a `Result`.
+fn check(ret: c_int) -> Result<()> {
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT : Duration = Duration::from_secs(5);
+
+    fn filter(program: &str, args: &[&str]) -> Filter {
+        let mut command = Command::new(program);
+        let _ = command.args(args);
+
+        Filter::spawn(&mut command).unwrap()
+    }
+
+    #[test]
+    fn filters_data() {
+        let mut filter = filter("tr", &["a-z", "A-Z"]);
+        let mut out    = Vec::new();
+
+        filter.pump(b"hello, ", &mut out, TIMEOUT).unwrap();
+        filter.pump(b"world\n", &mut out, TIMEOUT).unwrap();
+
+        let (rest, status) = filter.finish(TIMEOUT).unwrap();
+
+        out.extend(rest);
+
+        assert_eq!(b"HELLO, WORLD\n".to_vec(), out);
+        assert!(status.success());
+    }
+
+    #[test]
+    fn chains_filters() {
+        let mut first  = filter("tr", &["a-z", "A-Z"]);
+        let mut second = filter("tr", &["L", "1"]);
+        let mut middle = Vec::new();
+        let mut out    = Vec::new();
+
+        first.pump(b"hello\n", &mut middle, TIMEOUT).unwrap();
+        middle.extend(first.finish(TIMEOUT).unwrap().0);
+
+        second.pump(&middle, &mut out, TIMEOUT).unwrap();
+        out.extend(second.finish(TIMEOUT).unwrap().0);
+
+        assert_eq!(b"HE11O\n".to_vec(), out);
+    }
+
+    #[test]
+    fn doesnt_deadlock_on_full_pipes() {
+        let mut filter = filter("cat", &[]);
+        let mut out    = Vec::new();
+        let data       = vec![b'x'; 4 * 1024 * 1024];
+
+        filter.pump(&data, &mut out, TIMEOUT).unwrap();
+        out.extend(filter.finish(TIMEOUT).unwrap().0);
+
+        assert_eq!(data.len(), out.len());
+    }
+
+    #[test]
+    fn fails_to_write_to_exited_filters() {
+        let mut filter = filter("true", &[]);
+
+        while filter.try_wait().unwrap().is_none() {
+            thread::sleep(EXIT_POLL);
+        }
+
+        // the first write may be buffered before the socket notices
+        // its reader has gone
+        let error = (0..2)
+            .map(|_| filter.write(b"data"))
+            .find_map(Result::err)
+            .unwrap();
+
+        assert_eq!(ErrorKind::BrokenPipe, error.kind());
+    }
+
+    #[test]
+    fn times_out_on_filters_that_stop_reading() {
+        let mut filter = filter("sleep", &["60"]);
+        let data       = vec![0; 4 * 1024 * 1024];
+
+        filter.set_grace(Duration::from_millis(10));
+
+        let error = filter.pump(&data, &mut Vec::new(), Duration::from_millis(50)).unwrap_err();
+
+        assert_eq!(ErrorKind::TimedOut, error.kind());
+    }
+
+    #[test]
+    fn kills_filters_when_dropped() {
+        let mut filter = filter("sleep", &["60"]);
+        let pid        = filter.id() as libc::pid_t;
+
+        filter.set_grace(Duration::from_millis(10));
+        drop(filter);
+
+        // reaped, so there's no longer a process with its id
+        assert_eq!(-1, unsafe { libc::kill(pid, 0) });
+    }
+
+    #[test]
+    fn kills_filters_that_dont_finish() {
+        let filter = filter("sleep", &["60"]);
+        let start  = Instant::now();
+
+        let (out, status) = filter.finish(Duration::from_millis(50)).unwrap();
+
+        assert!(out.is_empty());
+        assert!(!status.success());
+        assert!(start.elapsed() < TIMEOUT);
+    }
+}
@@ -0,0 +1,660 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use libc::{c_char, c_int, gid_t, uid_t};
+
+/// The initial size of the buffer handed to `getpwuid_r(3)` and
+/// `getgrgid_r(3)`; it's doubled on `ERANGE` up to `MAX_BUFFER_SIZE`.
+const INITIAL_BUFFER_SIZE : usize = 1024;
+const MAX_BUFFER_SIZE     : usize = 1024 * 1024;
+
+/// The initial number of groups `getgrouplist(3)` is asked for; it's
+/// grown as needed up to `MAX_GROUPS`, Linux's `NGROUPS_MAX`.
+const INITIAL_GROUPS : usize = 64;
+const MAX_GROUPS     : usize = 65536;
+
+const DEFAULT_PASSWD_PATH : &str = "/etc/passwd";
+const DEFAULT_GROUP_PATH  : &str = "/etc/group";
+
+/// A source of names for user and group ids.
+///
+/// `sudo` runs plugins in a wide variety of environments, and the usual
+/// NSS lookups through libc may be unavailable, slow, or hang outright
+/// (e.g., in a chroot or minimal container, or when a network directory
+/// service is unreachable). Implementations of this trait allow plugins
+/// to choose how names are resolved.
+pub trait IdentityResolver: fmt::Debug + Send + Sync {
+    /// Returns the name of the user with the given `uid`, or `None` if
+    /// it can't be determined.
+    fn user_name(&self, uid: uid_t) -> Option<String>;
+
+    /// Returns the name of the group with the given `gid`, or `None` if
+    /// it can't be determined.
+    fn group_name(&self, gid: gid_t) -> Option<String>;
+
+    /// Returns the uid of the user with the given `name`, or `None` if
+    /// it can't be determined.
+    fn user_id(&self, name: &str) -> Option<uid_t>;
+
+    /// Returns the gid of the group with the given `name`, or `None` if
+    /// it can't be determined.
+    fn group_id(&self, name: &str) -> Option<gid_t>;
+
+    /// Returns the gids of every group the user with the given `uid` is
+    /// a member of, including their primary group, as `initgroups(3)`
+    /// would set them when they log in, or `None` if they can't be
+    /// determined. Resolvers that don't know about memberships can't.
+    fn group_ids(&self, uid: uid_t) -> Option<Vec<gid_t>> {
+        let _ = uid;
+
+        None
+    }
+}
+
+/// Resolves names through libc (`getpwuid_r(3)` and `getgrgid_r(3)`),
+/// and therefore through whatever NSS modules the system has configured.
+/// This is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NssResolver;
+
+impl IdentityResolver for NssResolver {
+    fn user_name(&self, uid: uid_t) -> Option<String> {
+        user_name(uid)
+    }
+
+    fn group_name(&self, gid: gid_t) -> Option<String> {
+        group_name(gid)
+    }
+
+    fn user_id(&self, name: &str) -> Option<uid_t> {
+        user_id(name)
+    }
+
+    fn group_id(&self, name: &str) -> Option<gid_t> {
+        group_id(name)
+    }
+
+    fn group_ids(&self, uid: uid_t) -> Option<Vec<gid_t>> {
+        group_ids(uid)
+    }
+}
+
+/// Resolves names by reading files in the format of `passwd(5)` and
+/// `group(5)` directly, bypassing NSS entirely.
+#[derive(Clone, Debug)]
+pub struct FileResolver {
+    passwd: PathBuf,
+    group:  PathBuf,
+}
+
+impl FileResolver {
+    /// Constructs a resolver reading users from `passwd` and groups from
+    /// `group`.
+    pub fn new<P: Into<PathBuf>, G: Into<PathBuf>>(passwd: P, group: G) -> Self {
+        Self { passwd: passwd.into(), group: group.into() }
+    }
+}
+
+impl Default for FileResolver {
+    /// Reads from `/etc/passwd` and `/etc/group`.
+    fn default() -> Self {
+        Self::new(DEFAULT_PASSWD_PATH, DEFAULT_GROUP_PATH)
+    }
+}
+
+impl IdentityResolver for FileResolver {
+    fn user_name(&self, uid: uid_t) -> Option<String> {
+        let contents = fs::read(&self.passwd).ok()?;
+
+        find_name(&String::from_utf8_lossy(&contents), uid).map(String::from)
+    }
+
+    fn group_name(&self, gid: gid_t) -> Option<String> {
+        let contents = fs::read(&self.group).ok()?;
+
+        find_name(&String::from_utf8_lossy(&contents), gid).map(String::from)
+    }
+
+    fn user_id(&self, name: &str) -> Option<uid_t> {
+        let contents = fs::read(&self.passwd).ok()?;
+
+        find_id(&String::from_utf8_lossy(&contents), name)
+    }
+
+    fn group_id(&self, name: &str) -> Option<gid_t> {
+        let contents = fs::read(&self.group).ok()?;
+
+        find_id(&String::from_utf8_lossy(&contents), name)
+    }
+
+    fn group_ids(&self, uid: uid_t) -> Option<Vec<gid_t>> {
+        let passwd = fs::read(&self.passwd).ok()?;
+        let group  = fs::read(&self.group).ok()?;
+
+        find_memberships(
+            &String::from_utf8_lossy(&passwd),
+            &String::from_utf8_lossy(&group),
+            uid,
+        )
+    }
+}
+
+/// Resolves names from a fixed mapping of ids to names, e.g., one
+/// provided through plugin configuration.
+#[derive(Clone, Debug, Default)]
+pub struct StaticResolver {
+    users:  HashMap<uid_t, String>,
+    groups: HashMap<gid_t, String>,
+}
+
+impl StaticResolver {
+    /// Constructs a resolver from maps of uids and gids to their names.
+    pub fn new(users: HashMap<uid_t, String>, groups: HashMap<gid_t, String>) -> Self {
+        Self { users, groups }
+    }
+}
+
+impl IdentityResolver for StaticResolver {
+    fn user_name(&self, uid: uid_t) -> Option<String> {
+        self.users.get(&uid).cloned()
+    }
+
+    fn group_name(&self, gid: gid_t) -> Option<String> {
+        self.groups.get(&gid).cloned()
+    }
+
+    fn user_id(&self, name: &str) -> Option<uid_t> {
+        self.users.iter()
+            .find(|&(_, user)| user == name)
+            .map(|(&uid, _)| uid)
+    }
+
+    fn group_id(&self, name: &str) -> Option<gid_t> {
+        self.groups.iter()
+            .find(|&(_, group)| group == name)
+            .map(|(&gid, _)| gid)
+    }
+}
+
+/// Wraps another resolver, giving up on any lookup that doesn't
+/// complete within a timeout.
+///
+/// Lookups are performed on a separate thread. Since there's no way to
+/// safely interrupt a call into libc, a lookup that hangs forever will
+/// leave its thread behind until `sudo` exits.
+#[derive(Debug)]
+pub struct TimeoutResolver<R> {
+    inner:   Arc<R>,
+    timeout: Duration,
+}
+
+impl<R: IdentityResolver + 'static> TimeoutResolver<R> {
+    /// Constructs a resolver that waits at most `timeout` for `inner` to
+    /// resolve each name.
+    pub fn new(inner: R, timeout: Duration) -> Self {
+        Self { inner: Arc::new(inner), timeout }
+    }
+
+    fn with_timeout<T, F>(&self, lookup: F) -> Option<T>
+        where
+            T: Send + 'static,
+            F: FnOnce(&R) -> Option<T> + Send + 'static,
+    {
+        let inner    = Arc::clone(&self.inner);
+        let (tx, rx) = mpsc::channel();
+
+        let _ = thread::Builder::new()
+            .name("identity lookup".into())
+            .spawn(move || { let _ = tx.send(lookup(&inner)); })
+            .ok()?;
+
+        rx.recv_timeout(self.timeout).ok().flatten()
+    }
+}
+
+impl<R: IdentityResolver + 'static> IdentityResolver for TimeoutResolver<R> {
+    fn user_name(&self, uid: uid_t) -> Option<String> {
+        self.with_timeout(move |inner| inner.user_name(uid))
+    }
+
+    fn group_name(&self, gid: gid_t) -> Option<String> {
+        self.with_timeout(move |inner| inner.group_name(gid))
+    }
+
+    fn user_id(&self, name: &str) -> Option<uid_t> {
+        let name = name.to_owned();
+
+        self.with_timeout(move |inner| inner.user_id(&name))
+    }
+
+    fn group_id(&self, name: &str) -> Option<gid_t> {
+        let name = name.to_owned();
+
+        self.with_timeout(move |inner| inner.group_id(&name))
+    }
+
+    fn group_ids(&self, uid: uid_t) -> Option<Vec<gid_t>> {
+        self.with_timeout(move |inner| inner.group_ids(uid))
+    }
+}
+
+/// A cache of user and group names, so that repeatedly asking for the
+/// name of the same id only hits the resolver once. Ids that can't be
+/// resolved are rendered as their numeric value.
+#[derive(Debug)]
+pub(crate) struct NameCache {
+    resolver:    Mutex<Box<dyn IdentityResolver>>,
+    users:       Mutex<HashMap<uid_t, String>>,
+    groups:      Mutex<HashMap<gid_t, String>>,
+    memberships: Mutex<HashMap<uid_t, Vec<gid_t>>>,
+}
+
+impl Default for NameCache {
+    fn default() -> Self {
+        Self {
+            resolver:    Mutex::new(Box::new(NssResolver)),
+            users:       Mutex::default(),
+            groups:      Mutex::default(),
+            memberships: Mutex::default(),
+        }
+    }
+}
+
+impl NameCache {
+    /// Replaces the resolver used for future lookups, discarding any
+    /// names that were previously resolved.
+    pub(crate) fn set_resolver(&self, resolver: Box<dyn IdentityResolver>) {
+        *lock(&self.resolver) = resolver;
+
+        lock(&self.users).clear();
+        lock(&self.groups).clear();
+        lock(&self.memberships).clear();
+    }
+
+    /// Returns the uid of the user named `name`. Unlike names, uids are
+    /// only looked up for configuration and aren't cached.
+    pub(crate) fn user_id(&self, name: &str) -> Option<uid_t> {
+        lock(&self.resolver).user_id(name)
+    }
+
+    /// Returns the gid of the group named `name`. Unlike names, gids are
+    /// only looked up for configuration and aren't cached.
+    pub(crate) fn group_id(&self, name: &str) -> Option<gid_t> {
+        lock(&self.resolver).group_id(name)
+    }
+
+    /// Returns the gids of the groups the user with `uid` is a member
+    /// of. Memberships that were found are cached, but failures to find
+    /// them aren't, since they're often only a directory service that
+    /// was briefly too slow to answer.
+    pub(crate) fn group_ids(&self, uid: uid_t) -> Option<Vec<gid_t>> {
+        if let Some(gids) = lock(&self.memberships).get(&uid) {
+            return Some(gids.clone());
+        }
+
+        let gids = lock(&self.resolver).group_ids(uid)?;
+
+        let _ = lock(&self.memberships).insert(uid, gids.clone());
+
+        Some(gids)
+    }
+
+    pub(crate) fn user(&self, uid: uid_t) -> String {
+        self.lookup(&self.users, uid, |resolver| resolver.user_name(uid))
+    }
+
+    pub(crate) fn group(&self, gid: gid_t) -> String {
+        self.lookup(&self.groups, gid, |resolver| resolver.group_name(gid))
+    }
+
+    fn lookup<F>(&self, cache: &Mutex<HashMap<u32, String>>, id: u32, resolve: F) -> String
+        where F: FnOnce(&dyn IdentityResolver) -> Option<String>
+    {
+        let mut cache = lock(cache);
+
+        cache.entry(id).or_insert_with(||
+            resolve(&**lock(&self.resolver)).unwrap_or_else(|| id.to_string())
+        ).clone()
+    }
+}
+
+/// Acquires a mutex, ignoring poisoning. A poisoned mutex only means
+/// someone panicked while holding it, which can't leave any of the
+/// values guarded here in an inconsistent state.
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Finds the name for `id` in the contents of a `passwd(5)` or
+/// `group(5)` file, both of which have the name in their first field
+/// and the id in their third.
+fn find_name(contents: &str, id: u32) -> Option<&str> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 3 && !fields[0].is_empty())
+        .find(|fields| fields[2].parse() == Ok(id))
+        .map(|fields| fields[0])
+}
+
+/// Finds the id for `name` in the contents of a `passwd(5)` or
+/// `group(5)` file.
+fn find_id(contents: &str, name: &str) -> Option<u32> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 3)
+        .find(|fields| fields[0] == name)
+        .and_then(|fields| fields[2].parse().ok())
+}
+
+/// Finds the gids of the groups the user with `uid` is a member of in
+/// the contents of a `passwd(5)` and a `group(5)` file: their primary
+/// group, and every group listing them among its members.
+fn find_memberships(passwd: &str, group: &str, uid: uid_t) -> Option<Vec<gid_t>> {
+    let user = passwd
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 4 && !fields[0].is_empty())
+        .find(|fields| fields[2].parse() == Ok(uid))?;
+
+    let     name = user[0];
+    let mut gids = vec![user[3].parse().ok()?];
+
+    let memberships = group
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() >= 4)
+        .filter(|fields| fields[3].split(',').any(|member| member == name))
+        .filter_map(|fields| fields[2].parse().ok());
+
+    // users are sometimes listed as members of their primary group too
+    for gid in memberships {
+        if !gids.contains(&gid) {
+            gids.push(gid);
+        }
+    }
+
+    Some(gids)
+}
+
+/// Returns the name of the user with the given `uid`, as resolved
+/// through NSS.
+fn user_name(uid: uid_t) -> Option<String> {
+    let mut passwd = mem::MaybeUninit::<libc::passwd>::uninit();
+
+    resolve(|buf, result: *mut *mut libc::passwd| unsafe {
+        libc::getpwuid_r(uid, passwd.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), result)
+    }, |passwd| to_string(passwd.pw_name))
+}
+
+/// Returns the name of the group with the given `gid`, as resolved
+/// through NSS.
+fn group_name(gid: gid_t) -> Option<String> {
+    let mut group = mem::MaybeUninit::<libc::group>::uninit();
+
+    resolve(|buf, result: *mut *mut libc::group| unsafe {
+        libc::getgrgid_r(gid, group.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), result)
+    }, |group| to_string(group.gr_name))
+}
+
+/// Returns the uid of the user with the given `name`, as resolved
+/// through NSS.
+fn user_id(name: &str) -> Option<uid_t> {
+    let     name   = CString::new(name).ok()?;
+    let mut passwd = mem::MaybeUninit::<libc::passwd>::uninit();
+
+    resolve(|buf, result: *mut *mut libc::passwd| unsafe {
+        libc::getpwnam_r(name.as_ptr(), passwd.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), result)
+    }, |passwd| passwd.pw_uid)
+}
+
+/// Returns the gid of the group with the given `name`, as resolved
+/// through NSS.
+fn group_id(name: &str) -> Option<gid_t> {
+    let     name  = CString::new(name).ok()?;
+    let mut group = mem::MaybeUninit::<libc::group>::uninit();
+
+    resolve(|buf, result: *mut *mut libc::group| unsafe {
+        libc::getgrnam_r(name.as_ptr(), group.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), result)
+    }, |group| group.gr_gid)
+}
+
+/// Returns the gids of the groups the user with the given `uid` is a
+/// member of, as resolved through NSS.
+fn group_ids(uid: uid_t) -> Option<Vec<gid_t>> {
+    let mut passwd = mem::MaybeUninit::<libc::passwd>::uninit();
+
+    let (name, gid) = resolve(|buf, result: *mut *mut libc::passwd| unsafe {
+        libc::getpwuid_r(uid, passwd.as_mut_ptr(), buf.as_mut_ptr(), buf.len(), result)
+    }, |passwd| (unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned(), passwd.pw_gid))?;
+
+    let mut gids = vec![0; INITIAL_GROUPS];
+
+    loop {
+        let mut count = c_int::try_from(gids.len()).ok()?;
+
+        let result = unsafe {
+            getgrouplist(name.as_ptr(), gid, gids.as_mut_ptr(), &mut count)
+        };
+
+        let count = usize::try_from(count).ok()?;
+
+        if result != -1 {
+            gids.truncate(count);
+
+            return Some(gids);
+        }
+
+        if gids.len() >= MAX_GROUPS {
+            return None;
+        }
+
+        // glibc reports how many groups there are, but others only that
+        // there are more than were asked for
+        let len = gids.len();
+        gids.resize(count.max(len * 2).min(MAX_GROUPS), 0);
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+unsafe fn getgrouplist(name: *const c_char, gid: gid_t, gids: *mut gid_t, count: *mut c_int) -> c_int {
+    libc::getgrouplist(name, gid, gids, count)
+}
+
+/// macOS declares `getgrouplist(3)` with `int` gids, which are the same
+/// size as `gid_t`.
+#[cfg(target_os = "macos")]
+unsafe fn getgrouplist(name: *const c_char, gid: gid_t, gids: *mut gid_t, count: *mut c_int) -> c_int {
+    libc::getgrouplist(name, gid as c_int, gids.cast(), count)
+}
+
+/// Drives one of the reentrant `get*_r(3)` functions, growing the
+/// scratch buffer as needed, and extracts a value from the result.
+fn resolve<T, V, F, E>(mut lookup: F, extract: E) -> Option<V>
+    where
+        F: FnMut(&mut Vec<c_char>, *mut *mut T) -> c_int,
+        E: FnOnce(&T) -> V,
+{
+    let mut buf    = vec![0; INITIAL_BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+
+    loop {
+        match lookup(&mut buf, &mut result) {
+            libc::ERANGE if buf.len() < MAX_BUFFER_SIZE => {
+                let len = buf.len();
+                buf.resize(len * 2, 0);
+            },
+
+            0 if !result.is_null() => break,
+
+            // either an error, or there's no entry for the id
+            _ => return None,
+        }
+    }
+
+    // the returned entry points into `buf`, which is still alive
+    Some(extract(unsafe { &*result }))
+}
+
+/// Copies a string out of an entry returned by one of the `get*_r(3)`
+/// functions, which must be called while its buffer is still alive.
+fn to_string(ptr: *const c_char) -> String {
+    unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_root() {
+        assert_eq!(Some("root".into()), user_name(0));
+        assert_eq!(Some(0),             user_id("root"));
+
+        // gid 0 is `root` on Linux but `wheel` on macOS
+        let root_group = group_name(0).unwrap();
+
+        assert_eq!(Some(0), group_id(&root_group));
+
+        assert!(group_ids(0).unwrap().contains(&0));
+    }
+
+    #[test]
+    fn falls_back_to_numeric_id() {
+        let cache = NameCache::default();
+
+        assert_eq!("4294967294", cache.user(4_294_967_294));
+        assert_eq!("4294967294", cache.group(4_294_967_294));
+    }
+
+    #[test]
+    fn finds_names_in_files() {
+        let passwd = "\
+            # comment\n\
+            root:x:0:0:root:/root:/bin/bash\n\
+            \n\
+            malformed\n\
+            postgres:x:109:117::/var/lib/postgresql:/bin/bash\n\
+        ";
+
+        assert_eq!(Some("root"),     find_name(passwd, 0));
+        assert_eq!(Some("postgres"), find_name(passwd, 109));
+        assert_eq!(None,             find_name(passwd, 117));
+
+        assert_eq!(Some(109),        find_id(passwd, "postgres"));
+        assert_eq!(None,             find_id(passwd, "malformed"));
+        assert_eq!(None,             find_id(passwd, "nobody"));
+    }
+
+    #[test]
+    fn finds_memberships_in_files() {
+        let passwd = "\
+            root:x:0:0:root:/root:/bin/bash\n\
+            alice:x:1000:1000::/home/alice:/bin/bash\n\
+            bob:x:1001:1001::/home/bob:/bin/bash\n\
+        ";
+
+        let group = "\
+            # comment\n\
+            root:x:0:\n\
+            sudo:x:27:bob,alice\n\
+            alice:x:1000:alice\n\
+            malformed\n\
+            ops:x:2000:alicea,bob\n\
+            eng:x:2001:alice\n\
+        ";
+
+        assert_eq!(Some(vec![1000, 27, 2001]), find_memberships(passwd, group, 1000));
+        assert_eq!(Some(vec![0]),              find_memberships(passwd, group, 0));
+        assert_eq!(None,                       find_memberships(passwd, group, 1002));
+    }
+
+    #[test]
+    fn static_resolver() {
+        let resolver = StaticResolver::new(
+            vec![(1000, "alice".into())].into_iter().collect(),
+            vec![(2000, "staff".into())].into_iter().collect(),
+        );
+
+        assert_eq!(Some("alice".into()), resolver.user_name(1000));
+        assert_eq!(None,                 resolver.user_name(0));
+        assert_eq!(None,                 resolver.group_name(1000));
+        assert_eq!(Some(1000),           resolver.user_id("alice"));
+        assert_eq!(None,                 resolver.user_id("staff"));
+        assert_eq!(Some(2000),           resolver.group_id("staff"));
+        assert_eq!(None,                 resolver.group_id("alice"));
+        assert_eq!(None,                 resolver.group_ids(1000));
+    }
+
+    #[test]
+    fn replacing_resolver_clears_cache() {
+        let cache = NameCache::default();
+
+        assert_eq!("4294967294", cache.user(4_294_967_294));
+
+        cache.set_resolver(Box::new(StaticResolver::new(
+            vec![(4_294_967_294, "nobody".into())].into_iter().collect(),
+            HashMap::new(),
+        )));
+
+        assert_eq!("nobody", cache.user(4_294_967_294));
+    }
+
+    #[derive(Debug)]
+    struct Hang;
+
+    impl IdentityResolver for Hang {
+        fn user_name(&self, _: uid_t) -> Option<String> {
+            thread::sleep(Duration::from_secs(60));
+            None
+        }
+
+        fn group_name(&self, gid: gid_t) -> Option<String> {
+            Some(gid.to_string())
+        }
+
+        fn user_id(&self, _: &str) -> Option<uid_t> {
+            None
+        }
+
+        fn group_id(&self, _: &str) -> Option<gid_t> {
+            None
+        }
+    }
+
+    #[test]
+    fn timeout_resolver_gives_up() {
+        let resolver = TimeoutResolver::new(Hang, Duration::from_millis(10));
+
+        assert_eq!(None,             resolver.user_name(0));
+        assert_eq!(Some("0".into()), resolver.group_name(0));
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Distinguishes why sudo opened the plugin.
+//!
+//! sudo handles listing privileges (`-l`), validating (`-v`), and
+//! invalidating (`-k` and `-K` without a command) entirely within the
+//! policy plugin, and exits without ever opening I/O plugins, so those
+//! never reach a plugin built with this library. What does reach one is
+//! a command being run, files being edited, or a request for the
+//! plugin's version.
+
+use super::command_info::CommandInfo;
+use super::settings::Settings;
+
+/// Why sudo opened the plugin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum InvocationKind {
+    /// A command is being run (e.g., `sudo ls`).
+    Run,
+
+    /// Files are being edited with `sudoedit` (or `sudo -e`).
+    Edit,
+
+    /// sudo was invoked with `-V`, and is only asking the plugin for its
+    /// version. No command is run, and the plugin is closed right after.
+    Version,
+}
+
+impl InvocationKind {
+    /// Determines why sudo opened the plugin from the `settings` and
+    /// `command_info` it was opened with.
+    // only needed to wrap a plugin as sudo opens it
+    #[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+    pub(crate) fn detect(settings: &Settings, command_info: &CommandInfo) -> Self {
+        // sudo doesn't provide a `command_info` when it's invoked with
+        // `-V`, since the policy plugin hasn't decided on a command
+        if command_info.command.as_os_str().is_empty() {
+            return Self::Version;
+        }
+
+        // sudo sets `sudoedit` in `settings` when it's invoked as
+        // `sudoedit` or with `-e`, and policies confirm it (or decide
+        // on it themselves) in `command_info`
+        if settings.sudoedit || command_info.sudoedit {
+            return Self::Edit;
+        }
+
+        Self::Run
+    }
+
+    /// Returns whether a command (or an editor) is actually run.
+    pub fn runs_command(self) -> bool {
+        match self {
+            Self::Run | Self::Edit => true,
+            Self::Version          => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::option_map::OptionMap;
+
+    use std::convert::TryFrom;
+    use std::ptr;
+
+    fn map(entries: &[&[u8]]) -> OptionMap {
+        let mut pointers : Vec<_> = entries.iter()
+            .map(|entry| entry.as_ptr() as *const _)
+            .collect();
+
+        pointers.push(ptr::null());
+
+        unsafe { OptionMap::from_raw(pointers.as_ptr()) }
+    }
+
+    fn detect(settings: &[&[u8]], command_info: &[&[u8]]) -> InvocationKind {
+        let mut all : Vec<&[u8]> = vec![
+            b"plugin_dir=/usr/libexec/sudo\0",
+            b"plugin_path=sudo_pair.so\0",
+            b"progname=sudo\0",
+        ];
+
+        all.extend_from_slice(settings);
+
+        InvocationKind::detect(
+            &Settings   ::try_from(map(&all)).unwrap(),
+            &CommandInfo::try_from(map(command_info)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn detects_commands() {
+        assert_eq!(InvocationKind::Run, detect(&[], &[b"command=/bin/ls\0"]));
+        assert!(InvocationKind::Run.runs_command());
+    }
+
+    #[test]
+    fn detects_editing() {
+        assert_eq!(
+            InvocationKind::Edit,
+            detect(&[b"sudoedit=true\0"], &[b"command=/usr/bin/vi\0", b"sudoedit=true\0"]),
+        );
+    }
+
+    #[test]
+    fn detects_version_requests() {
+        assert_eq!(InvocationKind::Version, detect(&[], &[]));
+        assert!(!InvocationKind::Version.runs_command());
+    }
+}
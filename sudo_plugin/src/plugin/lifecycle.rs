@@ -0,0 +1,224 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Tracks where a plugin is in its lifecycle, so the callbacks generated
+//! by `sudo_io_plugin!` can refuse to run when sudo (or a buggy
+//! front-end) calls them out of order, instead of touching state that
+//! was never initialized or has already been torn down.
+
+use crate::errors::*;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Where a plugin is in its lifecycle.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    /// `open` hasn't been called yet.
+    Unopened,
+
+    /// `open` is in progress.
+    Opening,
+
+    /// `open` succeeded, and no callback is running.
+    Open,
+
+    /// a callback is running.
+    Running,
+
+    /// `open` failed.
+    Failed,
+
+    /// `close` has been called.
+    Closed,
+}
+
+impl State {
+    const ALL : [Self; 6] = [
+        Self::Unopened,
+        Self::Opening,
+        Self::Open,
+        Self::Running,
+        Self::Failed,
+        Self::Closed,
+    ];
+
+    fn from_u8(state: u8) -> Self {
+        Self::ALL[usize::from(state)]
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            Self::Unopened => "not yet opened",
+            Self::Opening  => "still opening",
+            Self::Open     => "open",
+            Self::Running  => "running another callback",
+            Self::Failed   => "failed to open",
+            Self::Closed   => "already closed",
+        })
+    }
+}
+
+/// The lifecycle of a plugin, shared by each of its callbacks. Every
+/// transition is checked, and one that's out of order is reported as an
+/// `UnexpectedCallback` error rather than carried out.
+#[derive(Debug)]
+pub struct Lifecycle(AtomicU8);
+
+impl Lifecycle {
+    /// A plugin that hasn't been opened.
+    pub const fn new() -> Self {
+        Self(AtomicU8::new(State::Unopened as u8))
+    }
+
+    /// Where the plugin currently is in its lifecycle.
+    pub fn state(&self) -> State {
+        State::from_u8(self.0.load(Ordering::SeqCst))
+    }
+
+    /// Starts opening the plugin, which can only be done once.
+    pub fn open(&self) -> Result<()> {
+        self.transition("open", State::Unopened, State::Opening)
+    }
+
+    /// Finishes opening the plugin, recording whether it succeeded.
+    pub fn opened(&self, success: bool) {
+        let state = if success { State::Open } else { State::Failed };
+
+        self.0.store(state as u8, Ordering::SeqCst);
+    }
+
+    /// Enters the callback named `callback`, which can only be done
+    /// once the plugin has been opened and while no other callback is
+    /// running. The plugin is left again when the returned guard is
+    /// dropped.
+    pub fn enter(&self, callback: &'static str) -> Result<Running<'_>> {
+        self.transition(callback, State::Open, State::Running)?;
+
+        Ok(Running(self))
+    }
+
+    /// Closes the plugin, which can be done once it's been opened
+    /// (whether or not that succeeded) and while no other callback is
+    /// running.
+    pub fn close(&self) -> Result<()> {
+        self.transition("close", State::Open, State::Closed)
+            .or_else(|_| self.transition("close", State::Failed, State::Closed))
+    }
+
+    fn transition(&self, callback: &'static str, from: State, to: State) -> Result<()> {
+        match self.0.compare_exchange(from as u8, to as u8, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_)      => Ok(()),
+            Err(state) => Err(ErrorKind::UnexpectedCallback(callback, State::from_u8(state)).into()),
+        }
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A callback that's running, which leaves the plugin open once it's
+/// dropped.
+#[must_use = "the callback is left as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct Running<'a>(&'a Lifecycle);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.0.store(State::Open as u8, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unexpected<T: fmt::Debug>(result: Result<T>) -> (&'static str, State) {
+        match result.unwrap_err() {
+            Error(ErrorKind::UnexpectedCallback(callback, state), _) => (callback, state),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn follows_the_lifecycle() {
+        let lifecycle = Lifecycle::new();
+
+        assert_eq!(State::Unopened, lifecycle.state());
+
+        lifecycle.open().unwrap();
+        assert_eq!(State::Opening, lifecycle.state());
+
+        lifecycle.opened(true);
+        assert_eq!(State::Open, lifecycle.state());
+
+        {
+            let _running = lifecycle.enter("log_ttyout").unwrap();
+            assert_eq!(State::Running, lifecycle.state());
+        }
+
+        assert_eq!(State::Open, lifecycle.state());
+
+        lifecycle.close().unwrap();
+        assert_eq!(State::Closed, lifecycle.state());
+    }
+
+    #[test]
+    fn closes_after_a_failed_open() {
+        let lifecycle = Lifecycle::new();
+
+        lifecycle.open().unwrap();
+        lifecycle.opened(false);
+
+        assert_eq!(("log_ttyout", State::Failed), unexpected(lifecycle.enter("log_ttyout")));
+
+        lifecycle.close().unwrap();
+        assert_eq!(State::Closed, lifecycle.state());
+    }
+
+    #[test]
+    fn rejects_callbacks_out_of_order() {
+        let lifecycle = Lifecycle::new();
+
+        assert_eq!(("log_ttyin", State::Unopened), unexpected(lifecycle.enter("log_ttyin")));
+        assert_eq!(("close",     State::Unopened), unexpected(lifecycle.close()));
+
+        lifecycle.open().unwrap();
+
+        assert_eq!(("open",      State::Opening), unexpected(lifecycle.open()));
+        assert_eq!(("log_ttyin", State::Opening), unexpected(lifecycle.enter("log_ttyin")));
+
+        lifecycle.opened(true);
+
+        {
+            let _running = lifecycle.enter("log_ttyin").unwrap();
+
+            assert_eq!(("log_ttyout", State::Running), unexpected(lifecycle.enter("log_ttyout")));
+            assert_eq!(("close",      State::Running), unexpected(lifecycle.close()));
+        }
+
+        assert_eq!(("open", State::Open), unexpected(lifecycle.open()));
+
+        lifecycle.close().unwrap();
+
+        assert_eq!(("open",      State::Closed), unexpected(lifecycle.open()));
+        assert_eq!(("log_ttyin", State::Closed), unexpected(lifecycle.enter("log_ttyin")));
+        assert_eq!(("close",     State::Closed), unexpected(lifecycle.close()));
+    }
+}
@@ -16,35 +16,98 @@
 //! configured with.
 
 mod option_map;
+#[cfg(feature = "ffi")]
+mod builder;
 mod command_info;
+mod command_status;
+#[cfg(feature = "ffi")]
+mod compat;
+#[cfg(feature = "ffi")]
+mod conversation;
+mod filter;
+#[cfg(feature = "ffi")]
+mod identity;
+mod invocation;
+mod lifecycle;
+mod net;
 mod settings;
+mod subcommand;
 mod user_info;
+#[cfg(feature = "ffi")]
 mod print_facility;
+#[cfg(feature = "ffi")]
+mod privileges;
+mod quote;
 mod traits;
+#[cfg(feature = "serde")]
+mod serialize;
+mod units;
 
+#[cfg(feature = "ffi")]
+pub use self::builder::PluginBuilder;
+pub use self::command_info::CommandInfo;
+pub use self::command_status::CommandStatus;
+#[cfg(feature = "ffi")]
+pub use self::conversation::Conversation;
+pub use self::filter::Filter;
+#[cfg(feature = "ffi")]
+pub use self::identity::{IdentityResolver, NssResolver, FileResolver, StaticResolver, TimeoutResolver};
+pub use self::invocation::InvocationKind;
+pub use self::lifecycle::{Lifecycle, Running, State};
+pub use self::net::{Cidr, ParseCidrError};
+pub use self::option_map::{OptionMap, split_list};
+#[cfg(feature = "ffi")]
+pub use self::print_facility::{Level, PrintFacility};
+#[cfg(feature = "ffi")]
+pub use self::privileges::DroppedPrivileges;
+pub use self::settings::Settings;
+pub use self::subcommand::Subcommand;
+pub use self::traits::{FromSudoOption, FromSudoOptionList, ParseListError};
+pub use self::units::{ByteSize, ParseUnitError};
+pub use self::user_info::UserInfo;
+
+// everything below wraps a plugin as sudo opens it, so it's only built
+// with the FFI bindings; the parsing of what sudo passes plugins above
+// is built either way
+#[cfg(feature = "ffi")]
 use super::errors::*;
+#[cfg(feature = "ffi")]
 use super::version::Version;
 
-pub use self::option_map::OptionMap;
-pub use self::print_facility::PrintFacility;
-
-use self::command_info::CommandInfo;
-use self::settings::Settings;
-use self::user_info::UserInfo;
+#[cfg(feature = "ffi")]
+use self::identity::NameCache;
 
-use std::convert::TryInto;
+#[cfg(feature = "ffi")]
+use std::convert::TryFrom;
+#[cfg(feature = "ffi")]
 use std::collections::HashSet;
+#[cfg(feature = "ffi")]
 use std::path::PathBuf;
-use std::ffi::{CString, CStr};
+#[cfg(feature = "ffi")]
+use std::ffi::{CString, CStr, OsStr};
+#[cfg(feature = "ffi")]
 use std::fs::{File, OpenOptions};
+#[cfg(feature = "ffi")]
 use std::io::{self, Write};
+#[cfg(feature = "ffi")]
+use std::net::IpAddr;
+#[cfg(feature = "ffi")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(feature = "ffi")]
 use std::path::Path;
+#[cfg(feature = "ffi")]
 use std::slice;
+#[cfg(feature = "ffi")]
+use std::sync::{Mutex, OnceLock};
+#[cfg(feature = "ffi")]
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use libc::{c_char, c_int, c_uint, gid_t};
+#[cfg(feature = "ffi")]
+use libc::{c_char, c_int, c_uint, gid_t, uid_t};
 
 /// An implementation of a sudo plugin, initialized and parsed from the
 /// values passed to the underlying `open` callback.
+#[cfg(feature = "ffi")]
 #[allow(missing_debug_implementations)]
 pub struct Plugin {
     /// The name of the plugin. This will be the generally be the same
@@ -73,7 +136,14 @@ pub struct Plugin {
     /// A map of information about the command being run.
     pub command_info: CommandInfo,
 
-    /// A map of the user's environment variables.
+    /// A map of the environment variables the command will be run
+    /// with. Despite the name, sudo provides I/O plugins with the
+    /// environment after the policy plugin has scrubbed it (e.g., by
+    /// `env_reset`), not the environment `sudo` itself was invoked with.
+    ///
+    /// It's often the largest of sudo's vectors, and isn't indexed until
+    /// a variable is first looked up, so plugins that can tell they've
+    /// nothing to do without reading it needn't pay for it.
     pub user_env: OptionMap,
 
     /// A map of options provided to the plugin after the its path in
@@ -87,10 +157,22 @@ pub struct Plugin {
 
     stdout: PrintFacility,
     stderr: PrintFacility,
+    debug:  PrintFacility,
+
+    /// the least severe messages `print` emits
+    min_level: Mutex<Level>,
 
-    _conversation: crate::sys::sudo_conv_t,
+    /// why the plugin ended the session, if it has, and whether the
+    /// user has been told
+    termination:          OnceLock<String>,
+    termination_reported: AtomicBool,
+
+    conversation: Conversation,
+
+    names: NameCache,
 }
 
+#[cfg(feature = "ffi")]
 impl Plugin {
     /// Initializes a `Plugin` from the arguments provided to the
     /// underlying C `open` callback function. Verifies the API version
@@ -99,6 +181,9 @@ impl Plugin {
     /// facilities.
     ///
     /// Returns an error if there was a problem initializing the plugin.
+    ///
+    /// To construct a `Plugin` from owned values instead (e.g., in
+    /// tests), use `PluginBuilder`.
     #[cfg_attr(feature="cargo-clippy", allow(clippy::new_ret_no_self))]
     #[cfg_attr(feature="cargo-clippy", allow(clippy::cast_sign_loss))]
     #[cfg_attr(feature="cargo-clippy", allow(clippy::too_many_arguments))]
@@ -118,39 +203,54 @@ impl Plugin {
         stderr:         PrintFacility,
         conversation:   crate::sys::sudo_conv_t,
     ) -> Result<Self> {
-        let version = Version::from(version).check()?;
+        // parse the argv into the command being run; `argc` is zero and
+        // `argv` may be NULL when there's no command (e.g., for `-V`)
+        let argc = match usize::try_from(argc) {
+            Ok(argc) => argc,
+            Err(_)   => return uninitialized(format!("sudo passed a negative argc ({})", argc)),
+        };
 
-        // parse the argv into the command being run
-        let mut argv = slice::from_raw_parts(
-            argv,
-            argc as usize
-        ).to_vec();
+        if argc > 0 && argv.is_null() {
+            return uninitialized(format!("sudo passed a NULL argv with an argc of {}", argc));
+        }
+
+        let argv = if argc == 0 { &[] } else { slice::from_raw_parts(argv, argc) };
+
+        let command = argv.iter().enumerate()
+            .map(|(i, ptr)| if ptr.is_null() {
+                uninitialized(format!("sudo passed a NULL argv[{}]", i))
+            } else {
+                Ok(CStr::from_ptr(*ptr).to_owned())
+            })
+            .collect::<Result<_>>()?;
+
+        // every invocation has settings and user_info, but not every
+        // one has a command (and so `command_info`), an environment, or
+        // plugin options, which are left empty
+        if settings.is_null() {
+            return uninitialized("sudo passed NULL settings".into());
+        }
 
-        let command = argv
-            .iter_mut()
-            .map(|ptr| CStr::from_ptr(*ptr).to_owned())
-            .collect();
+        if user_info.is_null() {
+            return uninitialized("sudo passed a NULL user_info".into());
+        }
 
-        let plugin = Self {
+        PluginBuilder {
             plugin_name,
             plugin_version,
 
             version,
             command,
 
-            settings:       OptionMap::from_raw(settings as _).try_into()?,
-            user_info:      OptionMap::from_raw(user_info as _).try_into()?,
-            command_info:   OptionMap::from_raw(command_info as _).try_into()?,
+            settings:       OptionMap::from_raw(settings as _),
+            user_info:      OptionMap::from_raw(user_info as _),
+            command_info:   OptionMap::from_raw(command_info as _),
             user_env:       OptionMap::from_raw(user_env as _),
             plugin_options: OptionMap::from_raw(plugin_options as _),
 
-            stdout,
-            stderr,
-
-            _conversation: conversation,
-        };
-
-        Ok(plugin)
+            stdout:       Some((stdout, stderr)),
+            conversation: Some(Conversation::new(conversation)),
+        }.build()
     }
 
     ///
@@ -169,6 +269,107 @@ impl Plugin {
         self.stderr.clone()
     }
 
+    ///
+    /// Prints `message` as a line at the given `level`, prefixed by the
+    /// plugin's name and the level, unless it's less severe than the
+    /// minimum set by `set_min_level`. Debug messages go to sudo's
+    /// debug log, informational ones to the user's STDOUT, and warnings
+    /// and errors to the user's STDERR.
+    ///
+    pub fn print(&self, level: Level, message: &[u8]) -> io::Result<()> {
+        // a poisoned lock still holds a valid level
+        if level < *self.min_level.lock().unwrap_or_else(|e| e.into_inner()) {
+            return Ok(());
+        }
+
+        let mut facility = match level {
+            Level::Debug               => self.debug.clone(),
+            Level::Info                => self.stdout(),
+            Level::Warn | Level::Error => self.stderr(),
+        };
+
+        facility.write_prefixed_line(level.prefix(), message)
+    }
+
+    ///
+    /// Suppresses messages printed through `print` that are less severe
+    /// than `level`. By default, nothing is suppressed.
+    ///
+    pub fn set_min_level(&self, level: Level) {
+        *self.min_level.lock().unwrap_or_else(|e| e.into_inner()) = level;
+    }
+
+    ///
+    /// Ends the session for `reason` (e.g., because it's run for too
+    /// long). Every I/O callback from then on, including the one that's
+    /// running, rejects the command with a `Terminated` error, and the
+    /// user is told `reason` once. A session can only be terminated
+    /// once, so later reasons are ignored.
+    ///
+    pub fn terminate<S: Into<String>>(&self, reason: S) {
+        let _ = self.termination.set(reason.into());
+    }
+
+    ///
+    /// Why the session was terminated, if it has been.
+    ///
+    pub fn termination(&self) -> Option<&str> {
+        self.termination.get().map(String::as_str)
+    }
+
+    ///
+    /// Runs `callback` on behalf of one of sudo's I/O callbacks, unless
+    /// the session has been terminated. If it has been (whether before
+    /// or during `callback`), the command is rejected for that reason
+    /// instead. The callbacks generated by `sudo_io_plugin!` are run
+    /// through this.
+    ///
+    pub fn unless_terminated<F: FnOnce() -> Result<()>>(&self, callback: F) -> Result<()> {
+        self.check_terminated()?;
+
+        let result = callback();
+
+        self.check_terminated().and(result)
+    }
+
+    ///
+    /// Whether `error`, returned by one of sudo's callbacks, should be
+    /// shown to the user. Every callback rejects a terminated session,
+    /// but the user is only told why by the first of them.
+    ///
+    pub fn should_report(&self, error: &Error) -> bool {
+        match *error.kind() {
+            ErrorKind::Terminated(_) => !self.termination_reported.swap(true, Ordering::SeqCst),
+            _                        => true,
+        }
+    }
+
+    fn check_terminated(&self) -> Result<()> {
+        match self.termination() {
+            Some(reason) => Err(ErrorKind::Terminated(reason.into()).into()),
+            None         => Ok(()),
+        }
+    }
+
+    ///
+    /// Returns true if the invoking `sudo` calls the plugin's
+    /// `change_winsize` callback when the user's terminal is resized.
+    /// Older versions of sudo never call it, so the terminal's size in
+    /// `user_info` is the only one plugins learn of.
+    ///
+    pub fn calls_change_winsize(&self) -> bool {
+        compat::calls_change_winsize(self.version)
+    }
+
+    ///
+    /// Returns a facility that can send messages to and prompt the
+    /// invoking user through sudo's conversation function. This works
+    /// even when the user doesn't have a TTY.
+    ///
+    pub fn conversation(&self) -> Conversation {
+        self.conversation.clone()
+    }
+
     ///
     /// Returns a facility implementing `std::io::Write` that emits to
     /// the user's TTY, if sudo detected one.
@@ -179,26 +380,181 @@ impl Plugin {
         )
     }
 
+    ///
+    /// Returns true if `sudo` was invoked from a process group that
+    /// isn't in the foreground of its terminal (e.g., `sudo command &`),
+    /// meaning the user can't interact with it through the terminal.
+    ///
+    /// Note that sudo's own `-b` (`--background`) flag only takes
+    /// effect once the command is executed and isn't communicated to
+    /// plugins, so it can't be detected here.
+    ///
+    pub fn is_background(&self) -> bool {
+        // a `tcpgid` of -1 indicates there's no terminal (or sudo
+        // couldn't determine its foreground process group), in which
+        // case there's no foreground to be in the background of
+        self.user_info.tcpgid > 0 &&
+            self.user_info.tcpgid != self.user_info.pgid
+    }
+
+    ///
+    /// Why sudo opened the plugin: to run a command, to edit files with
+    /// `sudoedit`, or only to report the plugin's version.
+    ///
+    pub fn invocation_kind(&self) -> InvocationKind {
+        InvocationKind::detect(&self.settings, &self.command_info)
+    }
+
+    ///
+    /// Replaces how user and group names are resolved by the `*_name`
+    /// methods below. By default, names are resolved through libc and
+    /// NSS, which may be unavailable or hang in some environments (e.g.,
+    /// chroots and minimal containers).
+    ///
+    pub fn set_identity_resolver<R: IdentityResolver + 'static>(&self, resolver: R) {
+        self.names.set_resolver(Box::new(resolver))
+    }
+
+    ///
+    /// The name of the user invoking `sudo`.
+    ///
+    pub fn user_name(&self) -> String {
+        self.user_info.user.clone()
+    }
+
+    ///
+    /// The name of the primary group of the user invoking `sudo`. If the
+    /// group can't be resolved, its numeric gid is returned instead.
+    ///
+    pub fn group_name(&self) -> String {
+        self.names.group(self.user_info.gid)
+    }
+
+    ///
+    /// The name of the user the command will be run as. If the user
+    /// can't be resolved, its numeric uid is returned instead.
+    ///
+    pub fn runas_user_name(&self) -> String {
+        self.names.user(self.command_info.runas_euid)
+    }
+
+    ///
+    /// The name of the group the command will be run as. If the group
+    /// can't be resolved, its numeric gid is returned instead.
+    ///
+    pub fn runas_group_name(&self) -> String {
+        self.names.group(self.command_info.runas_egid)
+    }
+
+    ///
+    /// The uid of the user named `name`, resolved through the same
+    /// mechanism as the `*_name` methods.
+    ///
+    pub fn user_id(&self, name: &str) -> Option<uid_t> {
+        self.names.user_id(name)
+    }
+
+    ///
+    /// The gid of the group named `name`, resolved through the same
+    /// mechanism as the `*_name` methods.
+    ///
+    pub fn group_id(&self, name: &str) -> Option<gid_t> {
+        self.names.group_id(name)
+    }
+
+    ///
+    /// The complete set of groups the user with `uid` is a member of,
+    /// including their primary group, resolved through the same
+    /// mechanism as the `*_name` methods. Unlike `user_gids`, this is
+    /// looked up rather than taken from what sudo was told, so it can
+    /// answer for other users (e.g., the peer of a socket), and reflects
+    /// memberships added since they logged in. `None` if the resolver
+    /// can't say.
+    ///
+    pub fn gids_of(&self, uid: uid_t) -> Option<HashSet<gid_t>> {
+        self.names.group_ids(uid).map(|gids| gids.into_iter().collect())
+    }
+
+    ///
+    /// The complete set of groups the invoking user is a member of,
+    /// including both their primary group and any supplementary groups.
+    ///
+    pub fn user_gids(&self) -> HashSet<gid_t> {
+        let mut set : HashSet<_> = self.user_info.groups.iter().cloned().collect();
+
+        // the primary gid isn't guaranteed to be repeated in the list of
+        // supplementary groups
+        let _ = set.insert(self.user_info.gid);
+
+        set
+    }
+
+    ///
+    /// Returns true if the invoking user is a member of the group
+    /// `gid`, either as their primary group or a supplementary one.
+    ///
+    pub fn is_user_in_group(&self, gid: gid_t) -> bool {
+        self.user_info.gid == gid || self.user_info.groups.contains(&gid)
+    }
+
+    ///
+    /// Returns true if the command will be run with the privileges of
+    /// the group `gid`. See `runas_gids` for details.
+    ///
+    pub fn is_runas_in_group(&self, gid: gid_t) -> bool {
+        self.runas_gids().contains(&gid)
+    }
+
+    ///
+    /// The IP addresses of the host `sudo` is running on, as determined
+    /// by `sudo` from its network interfaces.
+    ///
+    pub fn host_addrs(&self) -> Vec<IpAddr> {
+        self.settings.network_addrs.iter().map(|net| net.addr).collect()
+    }
+
     ///
     /// As best as can be reconstructed, what was actually typed at the
-    /// shell in order to launch this invocation of sudo.
+    /// shell in order to launch this invocation of sudo, quoted so it
+    /// could be pasted back into a POSIX shell (e.g., `sudo vim
+    /// 'my notes.txt'`). Control characters aren't escaped, so this
+    /// must still be made safe before being displayed on a terminal.
     ///
     // TODO: I don't really like this name
     pub fn invocation(&self) -> Vec<u8> {
-        let mut sudo    = self.settings.progname.as_bytes().to_vec();
-        let     flags   = self.settings.flags();
+        quote::sh(&self.invocation_args())
+    }
 
-        if !flags.is_empty() {
-            sudo.push(b' ');
-            sudo.extend_from_slice(&flags.join(&b' ')[..]);
-        }
+    ///
+    /// The same invocation as `invocation`, rendered as a JSON array of
+    /// its arguments (e.g., `["sudo","vim","my notes.txt"]`) for
+    /// anything that needs to recover them unambiguously. Arguments
+    /// that aren't UTF-8 are rendered lossily.
+    ///
+    pub fn invocation_json(&self) -> String {
+        quote::json(&self.invocation_args())
+    }
 
-        for entry in &self.command {
-            sudo.push(b' ');
-            sudo.extend_from_slice(entry.as_bytes());
-        }
+    ///
+    /// The arguments `sudo` was most likely invoked with, including the
+    /// name it was invoked by as the first element.
+    ///
+    pub fn invocation_args(&self) -> Vec<Vec<u8>> {
+        let mut args = vec![self.settings.progname.as_bytes().to_vec()];
+
+        args.extend(self.settings.flag_args());
+
+        // `sudoedit` is passed along as the command's name, which the
+        // user didn't type, and a shell run by `-s` or `-i` without a
+        // command is one the user didn't name at all
+        let command = match self.invocation_kind() {
+            InvocationKind::Edit             => self.edited_args(),
+            _ if self.settings.implied_shell => &[],
+            _                                => &self.command[..],
+        };
 
-        sudo
+        args.extend(command.iter().map(|entry| entry.as_bytes().to_vec()));
+        args
     }
 
     ///
@@ -213,6 +569,96 @@ impl Plugin {
         )
     }
 
+    ///
+    /// The path of the executable sudo will run, as resolved by the
+    /// policy plugin (e.g., `/usr/bin/ls` for `sudo ls`). This may not
+    /// match the first element of `argv`, and may itself be a symlink
+    /// to a binary with an entirely different name (e.g., `busybox`).
+    ///
+    pub fn executable(&self) -> &Path {
+        &self.command_info.command
+    }
+
+    ///
+    /// The command's arguments as the user provided them, including
+    /// the name it was invoked by as the first element. This is the
+    /// same list as `command`.
+    ///
+    pub fn argv(&self) -> &[CString] {
+        &self.command
+    }
+
+    ///
+    /// The files being edited, if sudo was invoked as `sudoedit` (or
+    /// with `-e`), relative to `cwd`. For any other invocation, this is
+    /// empty.
+    ///
+    pub fn edited_files(&self) -> Vec<PathBuf> {
+        if self.invocation_kind() != InvocationKind::Edit {
+            return Vec::new();
+        }
+
+        self.edited_args().iter()
+            .map(|arg| self.cwd().join(OsStr::from_bytes(arg.as_bytes())))
+            .collect()
+    }
+
+    /// The arguments naming the files being edited. sudo passes them
+    /// after `sudoedit` in place of the command's name, or after a `--`
+    /// if it's been given the editor's full command line.
+    fn edited_args(&self) -> &[CString] {
+        match self.command.iter().position(|arg| arg.as_bytes() == b"--") {
+            Some(i) => &self.command[i + 1 ..],
+            None    => self.command.get(1 ..).unwrap_or_default(),
+        }
+    }
+
+    ///
+    /// The shell sudo will run, if the user asked for one with `-i`
+    /// (`--login`) or `-s` (`--shell`) rather than naming a command.
+    /// The policy plugin resolves which shell this is: the runas user's
+    /// shell from the password database for a login shell, or usually
+    /// the invoking user's `SHELL` otherwise.
+    ///
+    /// Any command given alongside either flag is run through the shell
+    /// with `-c`, so this is returned even when a command was named.
+    ///
+    pub fn runas_shell(&self) -> Option<&Path> {
+        if !self.settings.login_shell && !self.settings.run_shell {
+            return None;
+        }
+
+        Some(self.executable())
+    }
+
+    ///
+    /// Returns true if sudo is running the runas user's login shell
+    /// (`sudo -i`), which reads their login scripts and starts in their
+    /// home directory.
+    ///
+    pub fn is_login_shell(&self) -> bool {
+        self.settings.login_shell
+    }
+
+    ///
+    /// The BSD login class the command will run under, if any. This is
+    /// the class chosen by the policy plugin, falling back to one the
+    /// user requested with `-c` (`--login-class`).
+    ///
+    pub fn login_class(&self) -> Option<&str> {
+        self.command_info.login_class.as_deref()
+            .or(self.settings.login_class.as_deref())
+    }
+
+    ///
+    /// The environment the command will receive through `execve(2)`.
+    /// This is the same map as `user_env`, under a name that reflects
+    /// what it actually contains.
+    ///
+    pub fn command_env(&self) -> &OptionMap {
+        &self.user_env
+    }
+
     ///
     /// The complete set of groups the invoked command will have
     /// privileges for. If the `-P` (`--preserve-groups`) flag was
@@ -250,19 +696,29 @@ impl Plugin {
     }
 }
 
+/// Fails to initialize the plugin because sudo passed it something it
+/// can't safely read, described by `reason`.
+#[cfg(feature = "ffi")]
+fn uninitialized<T>(reason: String) -> Result<T> {
+    Err(Error::from(reason)).chain_err(|| ErrorKind::Uninitialized)
+}
+
 ///
 /// A facility implementing `std::io::Write` that allows printing
 /// output to directly to the terminal of the user invoking `sudo`.
 ///
+#[cfg(feature = "ffi")]
 #[derive(Debug)]
 pub struct Tty(File);
 
+#[cfg(feature = "ffi")]
 impl Tty {
     fn try_from(path: &Path) -> io::Result<Self> {
         OpenOptions::new().write(true).open(path).map(Tty)
     }
 }
 
+#[cfg(feature = "ffi")]
 impl Write for Tty {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.0.write(buf)
@@ -272,3 +728,298 @@ impl Write for Tty {
         self.0.flush()
     }
 }
+
+#[cfg(all(test, feature = "ffi"))]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    const SETTINGS : &[&[u8]] = &[
+        b"plugin_dir=/usr/libexec/sudo\0",
+        b"plugin_path=/usr/libexec/sudo/sudo_pair.so\0",
+        b"progname=sudo\0",
+    ];
+
+    const USER_INFO : &[&[u8]] = &[
+        b"cwd=/home/alice\0", b"egid=1000\0",   b"euid=1000\0",
+        b"gid=1000\0",        b"groups=1000\0", b"host=example\0",
+        b"pgid=4242\0",       b"pid=4242\0",    b"ppid=4241\0",
+        b"uid=1000\0",        b"user=alice\0",  b"umask=022\0",
+    ];
+
+    /// A NULL-terminated vector of pointers, as sudo passes to `open`.
+    struct Vector(Vec<*mut c_char>);
+
+    impl Vector {
+        fn new(entries: &[&[u8]]) -> Self {
+            let mut pointers : Vec<_> = entries.iter()
+                .map(|entry| entry.as_ptr() as *mut _)
+                .collect();
+
+            pointers.push(ptr::null_mut());
+
+            Self(pointers)
+        }
+
+        fn as_ptr(&self) -> *const *mut c_char {
+            self.0.as_ptr()
+        }
+    }
+
+    /// Opens a plugin the way sudo's `open` callback would, with the
+    /// given `argc`, `argv`, `settings`, and `user_info`.
+    fn open(
+        argc:      c_int,
+        argv:      *const *mut c_char,
+        settings:  *const *mut c_char,
+        user_info: *const *mut c_char,
+    ) -> Result<Plugin> {
+        open_command(argc, argv, settings, user_info, ptr::null())
+    }
+
+    /// Opens a plugin as `open` does, but with a `command_info` for
+    /// the command being run.
+    fn open_command(
+        argc:         c_int,
+        argv:         *const *mut c_char,
+        settings:     *const *mut c_char,
+        user_info:    *const *mut c_char,
+        command_info: *const *mut c_char,
+    ) -> Result<Plugin> {
+        unsafe {
+            let (stdout, stderr) = PrintFacility::new(Some("test"), None);
+
+            Plugin::new(
+                "test".into(), None, crate::sys::SUDO_API_VERSION,
+                argc, argv, settings, user_info, command_info,
+                ptr::null(), ptr::null(),
+                stdout, stderr, None,
+            )
+        }
+    }
+
+    fn assert_uninitialized(result: Result<Plugin>, reason: &str) {
+        let error = result.err().expect("the plugin shouldn't initialize");
+
+        match error.kind() {
+            ErrorKind::Uninitialized => {},
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        assert!(
+            error.iter().any(|e| e.to_string().contains(reason)),
+            "{:?} doesn't mention {:?}", error, reason,
+        );
+    }
+
+    #[test]
+    fn opens_without_a_command() {
+        let settings  = Vector::new(SETTINGS);
+        let user_info = Vector::new(USER_INFO);
+
+        let plugin = open(0, ptr::null(), settings.as_ptr(), user_info.as_ptr())
+            .expect("the plugin should initialize");
+
+        assert!(plugin.command.is_empty());
+        assert!(plugin.user_env.keys().next().is_none());
+        assert!(plugin.plugin_options.keys().next().is_none());
+
+        let argv   = Vector::new(&[]);
+        let plugin = open(0, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr())
+            .expect("the plugin should initialize");
+
+        assert!(plugin.command.is_empty());
+    }
+
+    #[test]
+    fn opens_with_a_command() {
+        let argv      = Vector::new(&[b"/bin/ls\0", b"-la\0"]);
+        let settings  = Vector::new(SETTINGS);
+        let user_info = Vector::new(USER_INFO);
+
+        let plugin = open(2, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr())
+            .expect("the plugin should initialize");
+
+        assert_eq!(
+            vec![CString::new("/bin/ls").unwrap(), CString::new("-la").unwrap()],
+            plugin.command,
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_argv() {
+        let argv      = Vector::new(&[b"/bin/ls\0"]);
+        let settings  = Vector::new(SETTINGS);
+        let user_info = Vector::new(USER_INFO);
+
+        assert_uninitialized(
+            open(-1, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr()),
+            "negative argc",
+        );
+
+        assert_uninitialized(
+            open(1, ptr::null(), settings.as_ptr(), user_info.as_ptr()),
+            "NULL argv",
+        );
+
+        // argc counts past the terminating NULL
+        assert_uninitialized(
+            open(2, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr()),
+            "NULL argv[1]",
+        );
+    }
+
+    #[test]
+    fn rejects_missing_vectors() {
+        let settings  = Vector::new(SETTINGS);
+        let user_info = Vector::new(USER_INFO);
+
+        assert_uninitialized(
+            open(0, ptr::null(), ptr::null(), user_info.as_ptr()),
+            "NULL settings",
+        );
+
+        assert_uninitialized(
+            open(0, ptr::null(), settings.as_ptr(), ptr::null()),
+            "NULL user_info",
+        );
+    }
+
+    #[test]
+    fn lists_edited_files() {
+        let argv         = Vector::new(&[b"sudoedit\0", b"/etc/shadow\0", b"hosts\0"]);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/usr/bin/vi\0", b"sudoedit=true\0"]);
+        let settings     = Vector::new(&[
+            SETTINGS[0], SETTINGS[1], b"progname=sudoedit\0", b"sudoedit=true\0",
+        ]);
+
+        let plugin = open_command(
+            3, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(InvocationKind::Edit, plugin.invocation_kind());
+        assert_eq!(
+            vec![PathBuf::from("/etc/shadow"), PathBuf::from("/home/alice/hosts")],
+            plugin.edited_files(),
+        );
+        assert_eq!(b"sudoedit /etc/shadow hosts".to_vec(), plugin.invocation());
+
+        // some policies pass along the editor's whole command line
+        let argv   = Vector::new(&[b"vi\0", b"-R\0", b"--\0", b"/etc/shadow\0"]);
+        let plugin = open_command(
+            4, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(vec![PathBuf::from("/etc/shadow")], plugin.edited_files());
+    }
+
+    #[test]
+    fn quotes_invocations() {
+        let argv         = Vector::new(&[b"/bin/echo\0", b"it's\0", b"\0", b"a b\0"]);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/bin/echo\0"]);
+        let settings     = Vector::new(&[
+            SETTINGS[0], SETTINGS[1], b"progname=sudo\0", b"runas_user=postgres\0", b"login_shell=true\0",
+        ]);
+
+        let plugin = open_command(
+            4, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(
+            b"sudo --user postgres --login /bin/echo 'it'\\''s' '' 'a b'".to_vec(),
+            plugin.invocation(),
+        );
+        assert_eq!(
+            r#"["sudo","--user","postgres","--login","/bin/echo","it's","","a b"]"#,
+            plugin.invocation_json(),
+        );
+    }
+
+    #[test]
+    fn leaves_implied_shells_out_of_invocations() {
+        let argv         = Vector::new(&[b"-bash\0"]);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/bin/bash\0"]);
+        let settings     = Vector::new(&[
+            SETTINGS[0], SETTINGS[1], b"progname=sudo\0", b"implied_shell=true\0", b"login_shell=true\0",
+        ]);
+
+        let plugin = open_command(
+            1, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(b"sudo --login".to_vec(), plugin.invocation());
+        assert_eq!(r#"["sudo","--login"]"#,  plugin.invocation_json());
+    }
+
+    #[test]
+    fn edits_nothing_when_running_commands() {
+        let argv         = Vector::new(&[b"/bin/ls\0", b"/etc\0"]);
+        let settings     = Vector::new(SETTINGS);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/bin/ls\0"]);
+
+        let plugin = open_command(
+            2, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(InvocationKind::Run, plugin.invocation_kind());
+        assert!(plugin.edited_files().is_empty());
+    }
+
+    #[test]
+    fn parses_umasks_in_octal() {
+        let argv         = Vector::new(&[b"/bin/ls\0"]);
+        let settings     = Vector::new(SETTINGS);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/bin/ls\0", b"umask=0077\0"]);
+
+        let plugin = open_command(
+            1, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(Some(0o022), plugin.user_info.umask);
+        assert_eq!(0o077,       plugin.command_info.umask);
+    }
+
+    #[test]
+    fn resolves_login_shells() {
+        let argv         = Vector::new(&[b"-bash\0"]);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/bin/bash\0", b"login_class=staff\0"]);
+
+        let mut settings = SETTINGS.to_vec();
+        settings.push(b"login_shell=true\0");
+        let settings = Vector::new(&settings);
+
+        let plugin = open_command(
+            1, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(Some(Path::new("/bin/bash")), plugin.runas_shell());
+        assert!(plugin.is_login_shell());
+        assert_eq!(Some("staff"), plugin.login_class());
+    }
+
+    #[test]
+    fn resolves_no_shell_for_commands() {
+        let argv         = Vector::new(&[b"/bin/ls\0"]);
+        let user_info    = Vector::new(USER_INFO);
+        let command_info = Vector::new(&[b"command=/bin/ls\0"]);
+
+        let mut settings = SETTINGS.to_vec();
+        settings.push(b"login_class=default\0");
+        let settings = Vector::new(&settings);
+
+        let plugin = open_command(
+            1, argv.as_ptr(), settings.as_ptr(), user_info.as_ptr(), command_info.as_ptr(),
+        ).expect("the plugin should initialize");
+
+        assert_eq!(None, plugin.runas_shell());
+        assert!(!plugin.is_login_shell());
+        assert_eq!(Some("default"), plugin.login_class());
+    }
+}
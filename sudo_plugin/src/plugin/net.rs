@@ -0,0 +1,145 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use super::traits::*;
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// An error returned when a CIDR block couldn't be parsed.
+#[derive(Clone, Copy, Debug)]
+pub struct ParseCidrError();
+
+/// A block of IP addresses in CIDR notation, like `10.0.0.0/8` or
+/// `2001:db8::/32`. A bare address is a block containing only that
+/// address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr:   IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Returns true if `addr` is within this block. IPv4 addresses are
+    /// never within IPv6 blocks, and vice versa.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, *addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(addr) & mask
+            },
+
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(addr) & mask
+            },
+
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromSudoOption for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+
+        let addr : IpAddr = parts.next()
+            .and_then(|addr| addr.parse().ok())
+            .ok_or(ParseCidrError())?;
+
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse().map_err(|_| ParseCidrError())?,
+            None         => max,
+        };
+
+        if prefix > max {
+            return Err(ParseCidrError());
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl FromSudoOptionList for Cidr {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        Cidr::from_sudo_option(s).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn contains_ipv4() {
+        let block = cidr("10.20.0.0/16");
+
+        assert!( block.contains(&ip("10.20.0.1")));
+        assert!( block.contains(&ip("10.20.255.255")));
+        assert!(!block.contains(&ip("10.21.0.1")));
+        assert!(!block.contains(&ip("::ffff:10.20.0.1")));
+    }
+
+    #[test]
+    fn contains_ipv6() {
+        let block = cidr("2001:db8::/32");
+
+        assert!( block.contains(&ip("2001:db8::1")));
+        assert!(!block.contains(&ip("2001:db9::1")));
+        assert!(!block.contains(&ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn bare_address_is_a_single_host() {
+        assert!( cidr("192.168.1.1").contains(&ip("192.168.1.1")));
+        assert!(!cidr("192.168.1.1").contains(&ip("192.168.1.2")));
+        assert!( cidr("::1").contains(&ip("::1")));
+    }
+
+    #[test]
+    fn zero_prefix_contains_everything() {
+        assert!(cidr("0.0.0.0/0").contains(&ip("203.0.113.7")));
+        assert!(cidr("::/0").contains(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        assert!(Cidr::from_sudo_option("").is_err());
+        assert!(Cidr::from_sudo_option("10.0.0.0/33").is_err());
+        assert!(Cidr::from_sudo_option("::/129").is_err());
+        assert!(Cidr::from_sudo_option("10.0.0.0/").is_err());
+        assert!(Cidr::from_sudo_option("10.0.0/8").is_err());
+        assert!(Cidr::from_sudo_option("example.com/8").is_err());
+    }
+}
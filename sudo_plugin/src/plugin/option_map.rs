@@ -15,22 +15,59 @@
 use crate::errors::*;
 use super::traits::*;
 
-use std::collections::HashMap;
-use std::ffi::CStr;
+use std::borrow::Cow;
+use std::iter::FromIterator;
+use std::ffi::{CStr, OsStr};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
 use std::str;
+use std::sync::OnceLock;
 
 use libc::c_char;
 
 const OPTIONS_SEPARATOR: u8 = b'=';
 
+const LIST_ESCAPE: char = '\\';
+const LIST_QUOTE:  char = '"';
+
+/// The most characters of a value included in an error.
+const MAX_EXCERPT_LEN: usize = 64;
+
 /// A HashMap-like list of options parsed from the pointers provided by
 /// the underlying sudo plugin API.
 ///
 /// Allows for automatic parsing of values into any type which implements
 /// the `FromSudoOption` trait as well as values into a `Vec` of any type
 /// which implements the `FromSudoOptionList` trait.
-#[derive(Clone, Debug)]
-pub struct OptionMap(HashMap<Vec<u8>, Vec<u8>>);
+///
+/// Keys and values are kept as the bytes sudo provided, which needn't be
+/// UTF-8 (e.g., a path or an environment variable can hold any bytes).
+/// They can be retrieved losslessly as bytes or an `OsStr`, or lossily
+/// as a string for display.
+///
+/// Options are copied as they're provided, but aren't indexed until the
+/// first time one is looked up, so those that are never read (often the
+/// whole of `user_env`) cost little more than the copy.
+#[derive(Clone, Default)]
+pub struct OptionMap {
+    /// every key and value provided, one after another
+    bytes: Vec<u8>,
+
+    /// where in `bytes` each entry's key and value are, in the order
+    /// they were provided
+    entries: Vec<Entry>,
+
+    /// the positions in `entries` of the entries that can be looked up
+    /// (the last of any repeated key), sorted by key
+    index: OnceLock<Vec<usize>>,
+}
+
+/// The bounds of an entry's key and value within an `OptionMap`'s bytes.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    key:   (usize, usize),
+    value: (usize, usize),
+}
 
 // TOOD: in policy plugins, some of these values can be written back to
 // by the plugin in order to change the execution of sudo itself (e.g.,
@@ -52,12 +89,12 @@ impl OptionMap {
     /// such a NULL byte, there is no other way to detect the end of
     /// the options list.
     pub unsafe fn from_raw(mut ptr: *const *const c_char) -> Self {
-        let mut map = HashMap::new();
+        let mut map = Self::default();
 
         // if the pointer is null, we weren't given a list of settings,
         // so go ahead and return the empty map
         if ptr.is_null() {
-            return OptionMap(map);
+            return map;
         }
 
         // iterate through each pointer in the array until encountering
@@ -75,24 +112,15 @@ impl OptionMap {
             // the code above selects `sep` such that it's guaranteed to
             // be within the slice
             #[cfg_attr(feature="cargo-clippy", allow(clippy::indexing_slicing))]
-            let (k, v) = match sep {
-                Some(s) => { ( &bytes[..s], &bytes[s+1..] ) }
-                None    => { ( &bytes[..],  &bytes[..] ) }
-            };
-
-            // the return value is ignored because there's not an
-            // otherwise-reasonable way to handle duplicate key names;
-            // that said, the implications of this are that the last
-            // value of a given key is the one that's set
-            let _ = map.insert(
-                k.to_owned(),
-                v.to_owned(),
-            );
+            match sep {
+                Some(s) => map.push(&bytes[..s], Some(&bytes[s+1..])),
+                None    => map.push(bytes,       None),
+            }
 
             ptr = ptr.offset(1);
         }
 
-        OptionMap(map)
+        map
     }
 
     /// Gets the value of a key as any arbitrary type that implements the
@@ -100,14 +128,35 @@ impl OptionMap {
     /// was provided during initialization. Also returns `Err(_)` if the
     /// value was not interpretable as a UTF-8 string or if there was an
     /// error parsing the value to the requested type.
+    ///
+    /// Values that aren't UTF-8 can still be parsed into types that
+    /// override `FromSudoOption::from_sudo_option_bytes`, like `PathBuf`
+    /// and `OsString`, and are parsed into them losslessly.
+    ///
+    /// Errors name the key, and for values that couldn't be parsed, the
+    /// type they were parsed as and (the beginning of) the value.
     pub fn get<T: FromSudoOption>(&self, k: &str) -> Result<T> {
-        let v = self.get_str(k).chain_err(|| {
-            format!("option {} wasn't provided to the plugin", k)
-        })?;
+        let v = self.get_bytes(k.as_bytes())
+            .ok_or_else(|| ErrorKind::MissingOption(k.into()))?;
+
+        FromSudoOption::from_sudo_option_bytes(v).ok_or_else(|| ErrorKind::InvalidOption(
+            k.into(),
+            excerpt(v),
+            type_name::<T>(),
+        ).into())
+    }
 
-        FromSudoOption::from_sudo_option(v)
-            .ok()
-            .chain_err(|| format!("option {} couldn't be parsed", k))
+    /// Gets the value of a key as any arbitrary type that implements the
+    /// `FromSudoOption` trait. Returns `None` if no such key/value-pair
+    /// was provided during initialization, or if its value couldn't be
+    /// parsed to the requested type.
+    ///
+    /// This is `get(k).ok()`, but without building an error that's only
+    /// thrown away, which matters for options sudo usually doesn't
+    /// provide: errors capture a backtrace when `RUST_BACKTRACE` is set,
+    /// which sudo leaves as the invoking user had it.
+    pub fn get_optional<T: FromSudoOption>(&self, k: &str) -> Option<T> {
+        self.get_bytes(k.as_bytes()).and_then(FromSudoOption::from_sudo_option_bytes)
     }
 
     /// Gets the value of a key as a string. Returns `None` if no such
@@ -117,12 +166,242 @@ impl OptionMap {
         self.get_bytes(k.as_bytes()).and_then(|b| str::from_utf8(b).ok())
     }
 
+    /// Gets the value of a key as an `OsStr`, regardless of whether
+    /// it's UTF-8. Returns `None` if no such key/value-pair was provided
+    /// during initialization.
+    pub fn get_os_str(&self, k: &str) -> Option<&OsStr> {
+        self.get_bytes(k.as_bytes()).map(OsStr::from_bytes)
+    }
+
+    /// Gets the value of a key as a string for display, with any bytes
+    /// that aren't UTF-8 replaced by `U+FFFD`. Returns `None` if no such
+    /// key/value-pair was provided during initialization.
+    pub fn get_lossy(&self, k: &str) -> Option<Cow<'_, str>> {
+        self.get_bytes(k.as_bytes()).map(String::from_utf8_lossy)
+    }
+
     /// Fetches a raw byte value using a bytes as the key. This is
     /// provided to allow plugins to retrieve values for keys when the
     /// value and/or key are not guaranteed to be UTF-8 strings.
     pub fn get_bytes(&self, k: &[u8]) -> Option<&[u8]> {
-        self.0.get(k).map(Vec::as_slice)
+        let index = self.index();
+
+        index.binary_search_by(|i| self.key(*i).cmp(k))
+            .ok()
+            .and_then(|position| index.get(position))
+            .map(|i| self.value(*i))
+    }
+
+    /// Returns an iterator over every key provided during
+    /// initialization, sorted bytewise.
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.index().iter().map(move |i| self.key(*i))
+    }
+
+    /// Returns an iterator over every key provided during
+    /// initialization and its value, sorted bytewise by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.index().iter().map(move |i| (self.key(*i), self.value(*i)))
+    }
+
+    /// Sets the value of `k` to `v`, unless it was already provided.
+    // only needed to wrap a plugin as sudo opens it
+    #[cfg_attr(not(feature = "ffi"), allow(dead_code))]
+    pub(crate) fn insert_default(&mut self, k: &str, v: &[u8]) {
+        if self.get_bytes(k.as_bytes()).is_none() {
+            self.push(k.as_bytes(), Some(v));
+        }
+    }
+
+    /// Sets the value of `k` to `v`, replacing any earlier value as
+    /// `from_raw` does for repeated keys.
+    pub(crate) fn insert(&mut self, k: &[u8], v: &[u8]) {
+        self.push(k, Some(v));
     }
+
+    /// Appends an entry, whose value is its key if it has none.
+    fn push(&mut self, k: &[u8], v: Option<&[u8]>) {
+        let start = self.bytes.len();
+
+        self.bytes.extend_from_slice(k);
+
+        let key   = (start, self.bytes.len());
+        let value = match v {
+            Some(v) => {
+                let start = self.bytes.len();
+
+                self.bytes.extend_from_slice(v);
+
+                (start, self.bytes.len())
+            },
+
+            None => key,
+        };
+
+        self.entries.push(Entry { key, value });
+
+        // the entry may replace one that's already indexed
+        let _ = self.index.take();
+    }
+
+    /// Indexes the entries, if they haven't been since the last was
+    /// added.
+    fn index(&self) -> &[usize] {
+        self.index.get_or_init(|| {
+            let mut index : Vec<_> = (0 .. self.entries.len()).collect();
+
+            // the last value of a given key is the one that's set, so
+            // among entries with the same key, later ones sort first and
+            // are the ones deduplication keeps
+            index.sort_unstable_by(|a, b| self.key(*a).cmp(self.key(*b)).then(b.cmp(a)));
+            index.dedup_by(|a, b| self.key(*a) == self.key(*b));
+
+            index
+        })
+    }
+
+    fn key(&self, i: usize) -> &[u8] {
+        self.slice(self.entries.get(i).map(|entry| entry.key))
+    }
+
+    fn value(&self, i: usize) -> &[u8] {
+        self.slice(self.entries.get(i).map(|entry| entry.value))
+    }
+
+    fn slice(&self, bounds: Option<(usize, usize)>) -> &[u8] {
+        bounds
+            .and_then(|(start, end)| self.bytes.get(start .. end))
+            .unwrap_or_default()
+    }
+}
+
+/// Formats as a map of every key provided to its value.
+impl fmt::Debug for OptionMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(k, v)| (
+                String::from_utf8_lossy(k),
+                String::from_utf8_lossy(v),
+            )))
+            .finish()
+    }
+}
+
+/// Collects keys and values into an `OptionMap`, as though sudo had
+/// provided them as `key=value` entries.
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> FromIterator<(K, V)> for OptionMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+
+        for (k, v) in iter {
+            map.insert(k.as_ref(), v.as_ref());
+        }
+
+        map
+    }
+}
+
+/// The beginning of `value`, for errors. Values can be arbitrarily long
+/// (e.g., `command_info`'s `command` can hold an entire script), so
+/// only as much as is needed to recognize them is kept.
+fn excerpt(value: &[u8]) -> String {
+    let value = String::from_utf8_lossy(value);
+
+    match value.char_indices().nth(MAX_EXCERPT_LEN) {
+        Some((i, _)) => format!("{}...", &value[..i]),
+        None         => value.into_owned(),
+    }
+}
+
+/// The name of `T` without the paths of the modules it (and any types
+/// it's generic over) are defined in, e.g., `Vec<IpAddr>` rather than
+/// `alloc::vec::Vec<std::net::ip_addr::IpAddr>`.
+fn type_name<T>() -> String {
+    let mut name  = String::new();
+    let mut ident = String::new();
+
+    for c in std::any::type_name::<T>().chars() {
+        match c {
+            // a path separator, so the identifier so far is a module
+            ':' => ident.clear(),
+
+            c if c.is_alphanumeric() || c == '_' => ident.push(c),
+
+            c => {
+                name.push_str(&ident);
+                name.push(c);
+                ident.clear();
+            },
+        }
+    }
+
+    name + &ident
+}
+
+/// Splits a list-valued option into its elements, following sudo's
+/// conventions for lists: a backslash escapes the character after it
+/// (including `separator` and another backslash), and separators
+/// between double quotes are part of the element. Escapes and quotes
+/// are removed from the elements returned.
+///
+/// An empty value is an empty list. In lists separated by whitespace,
+/// runs of separators are treated as one and leading or trailing
+/// separators are ignored, as they are in `sudoers`; otherwise, each
+/// separator delimits an element, even an empty one.
+pub fn split_list(value: &str, separator: char) -> Vec<Cow<'_, str>> {
+    let mut items = Vec::new();
+
+    if value.is_empty() {
+        return items;
+    }
+
+    // elements are only copied once they've been found to contain an
+    // escape or a quote; until then, they're borrowed from `value`
+    let mut start  = 0;
+    let mut owned  = None;
+    let mut quoted = false;
+    let mut chars  = value.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            LIST_ESCAPE => {
+                let owned = owned.get_or_insert_with(|| value[start..i].to_owned());
+
+                // a trailing backslash has nothing to escape, so it's
+                // kept as-is
+                owned.push(chars.next().map_or(LIST_ESCAPE, |(_, c)| c));
+            },
+
+            LIST_QUOTE => {
+                let _ = owned.get_or_insert_with(|| value[start..i].to_owned());
+                quoted = !quoted;
+            },
+
+            c if c == separator && !quoted => {
+                items.push(owned.take().map_or_else(
+                    || Cow::Borrowed(&value[start..i]),
+                    Cow::Owned,
+                ));
+
+                start = i + c.len_utf8();
+            },
+
+            c => if let Some(owned) = owned.as_mut() {
+                owned.push(c);
+            },
+        }
+    }
+
+    items.push(owned.map_or_else(
+        || Cow::Borrowed(&value[start..]),
+        Cow::Owned,
+    ));
+
+    if separator.is_whitespace() {
+        items.retain(|item| !item.is_empty());
+    }
+
+    items
 }
 
 #[cfg(test)]
@@ -130,6 +409,7 @@ mod tests {
     use super::*;
 
     use std::collections::HashSet;
+    use std::ffi::OsString;
     use std::path::PathBuf;
     use std::ptr;
 
@@ -154,7 +434,7 @@ mod tests {
     fn new_parses_null_options() {
         let map = unsafe { OptionMap::from_raw(ptr::null()) };
 
-        assert!(map.0.is_empty())
+        assert!(map.entries.is_empty())
     }
 
     #[test]
@@ -178,6 +458,59 @@ mod tests {
         assert_eq!(&b"\x80"[..], map.get_bytes(b"key").unwrap());
     }
 
+    #[test]
+    fn get_parses_non_utf8_values_losslessly() {
+        let map = unsafe { OptionMap::from_raw([
+            b"cwd=/home/\xe9mile\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        let cwd = OsStr::from_bytes(b"/home/\xe9mile");
+
+        assert_eq!(PathBuf::from(cwd),      map.get::<PathBuf>("cwd").unwrap());
+        assert_eq!(cwd.to_owned(),          map.get::<OsString>("cwd").unwrap());
+        assert_eq!(Some(cwd),               map.get_os_str("cwd"));
+        assert_eq!(Some("/home/\u{fffd}mile"), map.get_lossy("cwd").as_deref());
+
+        // types that can't hold arbitrary bytes fail to parse, rather
+        // than being reported as missing
+        let err = map.get::<String>("cwd").unwrap_err();
+
+        assert_eq!(
+            "option cwd couldn't be parsed as String (its value is \"/home/\u{fffd}mile\")",
+            err.to_string(),
+        );
+
+        let err = map.get::<String>("missing").unwrap_err();
+
+        assert_eq!("option missing wasn't provided to the plugin", err.to_string());
+    }
+
+    #[test]
+    fn errors_describe_the_expected_type_and_value() {
+        let long = format!("uid={}\0", "9".repeat(100));
+        let map  = unsafe { OptionMap::from_raw([
+            b"groups=1000,wheel\0".as_ptr() as _,
+            long.as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(
+            "option groups couldn't be parsed as Vec<u32> (its value is \"1000,wheel\")",
+            map.get::<Vec<u32>>("groups").unwrap_err().to_string(),
+        );
+
+        assert_eq!(
+            format!("option uid couldn't be parsed as u32 (its value is \"{}...\")", "9".repeat(64)),
+            map.get::<u32>("uid").unwrap_err().to_string(),
+        );
+
+        match map.get::<u32>("gid").unwrap_err().kind() {
+            ErrorKind::MissingOption(key) => assert_eq!("gid", key),
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
     #[test]
     fn new_parses_repeated_keys() {
         let map = unsafe { OptionMap::from_raw([
@@ -211,6 +544,50 @@ mod tests {
         assert_eq!(None,          map.get_str("key=value"));
     }
 
+    #[test]
+    fn get_optional_parses_present_values() {
+        let map = unsafe { OptionMap::from_raw([
+            b"uid=1000\0"   .as_ptr() as _,
+            b"gid=wheel\0"  .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(Some(1000), map.get_optional::<u32>("uid"));
+        assert_eq!(None,       map.get_optional::<u32>("gid"));
+        assert_eq!(None,       map.get_optional::<u32>("euid"));
+    }
+
+    #[test]
+    fn insert_replaces_looked_up_values() {
+        let mut map : OptionMap = vec![("key", "value1")].into_iter().collect();
+
+        assert_eq!(Some("value1"), map.get_str("key"));
+
+        map.insert(b"key",   b"value2");
+        map.insert(b"other", b"value3");
+
+        assert_eq!(Some("value2"), map.get_str("key"));
+        assert_eq!(Some("value3"), map.get_str("other"));
+        assert_eq!(2,              map.keys().count());
+    }
+
+    #[test]
+    fn iter_returns_every_entry() {
+        let map = unsafe { OptionMap::from_raw([
+            b"key2\0"       .as_ptr() as _,
+            b"key1=value1\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        // entries come sorted by key, not in the order they were given
+        assert_eq!(vec![
+            (&b"key1"[..], &b"value1"[..]),
+            (&b"key2"[..], &b"key2"[..]),
+        ], map.iter().collect::<Vec<_>>());
+
+        assert_eq!(vec![&b"key1"[..], &b"key2"[..]], map.keys().collect::<Vec<_>>());
+    }
+
     #[test]
     fn get_parses_common_types() {
         let map = unsafe { OptionMap::from_raw([
@@ -257,6 +634,37 @@ mod tests {
         assert_eq!(vec!["a,b,c"],       map.get::<Vec<String>>("str") .unwrap());
     }
 
+    #[test]
+    fn get_parses_lists_with_escapes() {
+        let map = unsafe { OptionMap::from_raw([
+            b"strs=a\\|b|\"c|d\"\0".as_ptr() as _,
+            b"ints=1,\\2\0"          .as_ptr() as _,
+            b"empty=\0"              .as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(vec!["a|b", "c|d"], map.get::<Vec<String>>("strs").unwrap());
+        assert_eq!(vec![1, 2],         map.get::<Vec<u8>>("ints")    .unwrap());
+        assert!(map.get::<Vec<u8>>("empty").unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_list_handles_escapes_and_quotes() {
+        assert_eq!(vec!["a", "b", "c"],   split_list("a,b,c",       ','));
+        assert_eq!(vec!["a,b", "c"],      split_list("a\\,b,c",     ','));
+        assert_eq!(vec!["a\\b", "c"],     split_list("a\\\\b,c",    ','));
+        assert_eq!(vec!["a, b", "c"],     split_list("\"a, b\",c",  ','));
+        assert_eq!(vec!["a", "", "c"],    split_list("a,,c",        ','));
+        assert_eq!(vec!["a\\"],           split_list("a\\",         ','));
+        assert_eq!(Vec::<&str>::new(),    split_list("",            ','));
+
+        assert_eq!(vec!["a", "b c", "d"], split_list(" a  b\\ c \"d\" ", ' '));
+        assert_eq!(Vec::<&str>::new(),    split_list("   ",         ' '));
+
+        // elements are only copied when they have to be
+        assert!(matches!(split_list("a,\"b\"", ',')[..], [Cow::Borrowed(_), Cow::Owned(_)]));
+    }
+
     #[test]
     fn get_parses_hashsets() {
 
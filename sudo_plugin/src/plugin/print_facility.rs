@@ -3,17 +3,55 @@ use crate::sys;
 
 use sudo_plugin_sys::sudo_printf_t;
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
 
+/// The most bytes handed to `printf` in a single call. Longer writes
+/// are only partially written, and `write_all` (which every method here
+/// uses) sends the rest in later calls.
+const MAX_MESSAGE_LEN : usize = 8 * 1024;
+
 #[derive(Clone, Copy, Debug)]
 #[repr(u32)]
-enum Level {
+enum MessageType {
+    Debug = sys::SUDO_CONV_DEBUG_MSG,
     Info  = sys::SUDO_CONV_INFO_MSG,
     Error = sys::SUDO_CONV_ERROR_MSG,
 }
 
+/// The severity of a message printed through `Plugin::print`, from
+/// least to most severe.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Level {
+    /// Details for debugging the plugin, which sudo writes to its debug
+    /// log (as configured by `Debug` lines in `sudo.conf`) rather than
+    /// showing to the user.
+    Debug,
+
+    /// Informational messages, printed to the user's STDOUT.
+    Info,
+
+    /// Problems the user should know about, but that don't stop the
+    /// command, printed to the user's STDERR.
+    Warn,
+
+    /// Problems that stop the command, printed to the user's STDERR.
+    Error,
+}
+
+impl Level {
+    /// The prefix for messages of this level, after the plugin's name.
+    pub(crate) fn prefix(self) -> &'static [u8] {
+        match self {
+            Level::Debug => b"debug: ",
+            Level::Info  => b"",
+            Level::Warn  => b"warning: ",
+            Level::Error => b"error: ",
+        }
+    }
+}
+
 /// A facility implementing `std::io::Write` that allows printing
 /// output to the user invoking `sudo`. Technically, the user may
 /// not be present on a local tty, but this will be wired up to a
@@ -24,10 +62,10 @@ pub struct PrintFacility {
     /// by the sudo_plugin API.
     facility: Arc<Mutex<sudo_printf_t>>,
 
-    /// The [`Level`] to send messages at. The sudo_plugin API only
-    /// supports distinguishing between informational messages and error
+    /// The [`MessageType`] to send messages as. The sudo_plugin API
+    /// only distinguishes between informational, error, and debug
     /// messages.
-    level: Level,
+    message_type: MessageType,
 
     /// An optional tag to prepend to any logged messages.
     tag: Vec<u8>,
@@ -55,25 +93,55 @@ impl PrintFacility {
             .map(|name| format!("{}: ", name).into())
             .unwrap_or_default();
 
-        let     stdout = Self { tag, facility: printf, level: Level::Info };
-        let mut stderr = stdout.clone();
-
-        stderr.level = Level::Error;
+        let stdout = Self { tag, facility: printf, message_type: MessageType::Info };
+        let stderr = stdout.as_type(MessageType::Error);
 
         (stdout, stderr)
     }
 
+    /// A facility sending messages to sudo's debug log, rather than to
+    /// the user.
+    pub(crate) fn debug(&self) -> Self {
+        self.as_type(MessageType::Debug)
+    }
+
+    fn as_type(&self, message_type: MessageType) -> Self {
+        Self { message_type, ..self.clone() }
+    }
+
     /// Pretty-prints a line, prefixed by the name of the plugin.
     pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.write_prefixed_line(b"", line)
+    }
+
+    /// Pretty-prints a line, prefixed by the name of the plugin and
+    /// then by `prefix`.
+    pub(crate) fn write_prefixed_line(&mut self, prefix: &[u8], line: &[u8]) -> io::Result<()> {
         let tag = self.tag.clone();
 
         self.write_all(tag.as_slice())?;
+        self.write_all(prefix)?;
         self.write_all(line)?;
         self.write_all(b"\n")?;
 
         Ok(())
     }
 
+    /// Reports `error` to the user with a short message (see
+    /// `Error::user_message`), and sends the details behind it (see
+    /// `Error::debug_message`) to sudo's debug log, rather than showing
+    /// the user the whole chain of causes as `write_error` does.
+    pub fn report_error(&mut self, error: &Error) -> io::Result<()> {
+        // the details are only logged if sudo's debugging them, so
+        // failing to send them isn't worth reporting
+        let _ = self.debug().write_prefixed_line(Level::Error.prefix(), error.debug_message().as_bytes());
+
+        // errors are prefixed with a newline for clarity, since they
+        // might be emitted while an existing line has output on it
+        self.write_all(b"\n")?;
+        self.write_line(error.user_message().as_bytes())
+    }
+
     /// Pretty-prints nested errors to the user.
     pub fn write_error(&mut self, error: &Error) -> io::Result<()> {
         // errors are prefixed with a newline for clarity, since they
@@ -98,24 +166,170 @@ impl Write for PrintFacility {
             io::Error::new(io::ErrorKind::NotConnected, "no printf provided")
         )?;
 
-        let message = CString::new(buf).map_err(|err|
-            io::Error::new(io::ErrorKind::InvalidData, err)
-        )?;
+        let message_type = self.message_type;
 
-        let count = unsafe {
-            // TODO: level should be bitflags when we start implementing the
-            // full conversation interface
-            (printf)(self.level as _, message.as_ptr())
-        };
+        write_message(buf, |message| {
+            // the message is passed as an argument rather than as the
+            // format, so any `%` in it is printed as-is
+            let count = unsafe {
+                // TODO: level should be bitflags when we start implementing the
+                // full conversation interface
+                (printf)(message_type as _, b"%s\0".as_ptr() as _, message.as_ptr())
+            };
 
-        #[allow(clippy::cast_sign_loss)]
-        match count {
-            c if c < 0 => Err(io::Error::last_os_error()),
-            c          => Ok(c as _)
-        }
+            #[allow(clippy::cast_sign_loss)]
+            match count {
+                c if c < 0 => Err(io::Error::last_os_error()),
+                c          => Ok(c as _)
+            }
+        })
     }
 
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
+
+/// Writes as much of `buf` as it can as a single message through
+/// `printf`, which reports how many bytes it printed. Messages are
+/// limited to `MAX_MESSAGE_LEN` bytes, and can't contain a NUL, so
+/// they end before the first one; a NUL at the start of `buf` is
+/// skipped, since it wouldn't have been visible anyway.
+///
+/// Whatever's left of a message that was only partly printed is sent
+/// again, and calls interrupted by a signal are retried, so nothing is
+/// silently dropped. An error is only returned if nothing could be
+/// printed.
+fn write_message<F>(buf: &[u8], mut printf: F) -> io::Result<usize>
+    where F: FnMut(&CStr) -> io::Result<usize>
+{
+    let buf = &buf[..buf.len().min(MAX_MESSAGE_LEN)];
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+
+    if len == 0 {
+        return Ok(buf.len().min(1));
+    }
+
+    let mut written = 0;
+
+    while written < len {
+        let message = CString::new(&buf[written..len])
+            .expect("messages end before the first NUL");
+
+        match printf(&message) {
+            Ok(0)                                                => break,
+            Ok(n)                                                => written += n.min(len - written),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if written == 0                               => return Err(e),
+            Err(_)                                               => break,
+        }
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every message printed by a mock `printf`.
+    type Printed = Arc<Mutex<Vec<Vec<u8>>>>;
+
+    /// A stand-in for sudo's `printf` that prints at most `limit`
+    /// bytes at a time, after failing with each of `errors`.
+    fn mock(limit: usize, mut errors: Vec<i32>) -> (impl FnMut(&CStr) -> io::Result<usize>, Printed) {
+        let printed = Arc::new(Mutex::new(Vec::new()));
+        let calls   = Arc::clone(&printed);
+
+        errors.reverse();
+
+        let printf = move |message: &CStr| {
+            if let Some(errno) = errors.pop() {
+                return Err(io::Error::from_raw_os_error(errno));
+            }
+
+            let bytes = message.to_bytes();
+            let bytes = &bytes[..bytes.len().min(limit)];
+
+            calls.lock().unwrap().push(bytes.to_vec());
+
+            Ok(bytes.len())
+        };
+
+        (printf, printed)
+    }
+
+    #[test]
+    fn resends_partially_printed_messages() {
+        let (printf, printed) = mock(4, vec![]);
+
+        assert_eq!(11, write_message(b"hello world", printf).unwrap());
+        assert_eq!(
+            vec![b"hell".to_vec(), b"o wo".to_vec(), b"rld".to_vec()],
+            *printed.lock().unwrap(),
+        );
+    }
+
+    #[test]
+    fn retries_interrupted_messages() {
+        let (printf, printed) = mock(usize::MAX, vec![libc::EINTR, libc::EINTR]);
+
+        assert_eq!(5, write_message(b"hello", printf).unwrap());
+        assert_eq!(vec![b"hello".to_vec()], *printed.lock().unwrap());
+
+        let (printf, _) = mock(usize::MAX, vec![libc::EIO]);
+
+        assert_eq!(Some(libc::EIO), write_message(b"hello", printf).unwrap_err().raw_os_error());
+
+        // once something's been printed, the failure is left for the
+        // next write to report
+        let mut calls = 0;
+
+        assert_eq!(2, write_message(b"hello", |_| {
+            calls += 1;
+
+            match calls {
+                1 => Ok(2),
+                _ => Err(io::Error::from_raw_os_error(libc::EIO)),
+            }
+        }).unwrap());
+    }
+
+    #[test]
+    fn limits_message_lengths() {
+        let (printf, printed) = mock(usize::MAX, vec![]);
+        let message           = vec![b'x'; MAX_MESSAGE_LEN + 10];
+
+        assert_eq!(MAX_MESSAGE_LEN, write_message(&message, printf).unwrap());
+        assert_eq!(MAX_MESSAGE_LEN, printed.lock().unwrap()[0].len());
+    }
+
+    #[test]
+    fn stops_at_nuls() {
+        let (printf, printed) = mock(usize::MAX, vec![]);
+
+        assert_eq!(1, write_message(b"a\0b", printf).unwrap());
+        assert_eq!(vec![b"a".to_vec()], *printed.lock().unwrap());
+
+        let (printf, printed) = mock(usize::MAX, vec![]);
+
+        assert_eq!(1, write_message(b"\0b", printf).unwrap());
+        assert!(printed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn orders_levels_by_severity() {
+        assert!(Level::Debug < Level::Info);
+        assert!(Level::Info  < Level::Warn);
+        assert!(Level::Warn  < Level::Error);
+
+        assert_eq!(b"warning: ", Level::Warn.prefix());
+    }
+
+    #[test]
+    fn requires_a_printf() {
+        let (mut stdout, _) = unsafe { PrintFacility::new(Some("test"), None) };
+
+        assert_eq!(io::ErrorKind::NotConnected, stdout.write_line(b"hello").unwrap_err().kind());
+    }
+}
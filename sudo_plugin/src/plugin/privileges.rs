@@ -0,0 +1,173 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use std::io::{Error, Result};
+
+use libc::{c_int, gid_t, uid_t};
+
+/// Temporarily gives up root's privileges by switching the process's
+/// effective uid and gid (and clearing its supplementary groups), so
+/// that files and sockets created in the meantime are owned by, and
+/// only accessible to, an unprivileged user. Root's privileges are
+/// restored when the guard is dropped, even if whatever was done in
+/// between failed.
+///
+/// The effective ids are shared by every thread in the process, so
+/// nothing that needs root should run on another thread while a guard
+/// is alive. Plugins that only need their files owned by someone else
+/// can `chown(2)` them once they're created instead, as sudo_pair does
+/// with its recordings and sockets.
+///
+/// ```no_run
+/// use sudo_plugin::DroppedPrivileges;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let privileges = DroppedPrivileges::new(65534, 65534)?;
+///
+/// // owned by 65534:65534, or not created if they can't create it
+/// std::fs::write("/var/log/audit/session.log", b"...")?;
+///
+/// privileges.restore()
+/// # }
+/// ```
+#[must_use = "privileges are restored as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct DroppedPrivileges {
+    uid:    uid_t,
+    gid:    gid_t,
+    groups: Vec<gid_t>,
+
+    /// whether the original ids have all been restored, so that a
+    /// restoration that failed partway is retried when the guard is
+    /// dropped
+    restored: bool,
+}
+
+impl DroppedPrivileges {
+    /// Switches the effective uid to `uid` and the effective gid to
+    /// `gid`, with no supplementary groups. If any of these can't be
+    /// changed, whatever was already changed is restored and the error
+    /// is returned.
+    pub fn new(uid: uid_t, gid: gid_t) -> Result<Self> {
+        let privileges = Self {
+            uid:      unsafe { libc::geteuid() },
+            gid:      unsafe { libc::getegid() },
+            groups:   groups()?,
+            restored: false,
+        };
+
+        // the groups have to be changed first, since doing so requires
+        // the privileges being given up
+        check(unsafe { libc::setgroups(1, &gid) })?;
+
+        // if anything below fails, the guard is dropped and restores
+        // whatever had been changed
+        check(unsafe { libc::setegid(gid) })?;
+        check(unsafe { libc::seteuid(uid) })?;
+
+        Ok(privileges)
+    }
+
+    /// Restores the original effective ids and supplementary groups,
+    /// reporting whether they could be. Dropping the guard does the
+    /// same, but has to ignore any errors.
+    pub fn restore(mut self) -> Result<()> {
+        self.restore_ids()
+    }
+
+    // setgroups(2) takes the number of groups as a size_t on some
+    // platforms, and an int on others
+    #[allow(trivial_numeric_casts)]
+    fn restore_ids(&mut self) -> Result<()> {
+        if self.restored {
+            return Ok(());
+        }
+
+        // the uid is restored first, since root's privileges are needed
+        // to restore the rest
+        check(unsafe { libc::seteuid(self.uid) })?;
+        check(unsafe { libc::setegid(self.gid) })?;
+        check(unsafe { libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) })?;
+
+        self.restored = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for DroppedPrivileges {
+    fn drop(&mut self) {
+        let _ = self.restore_ids();
+    }
+}
+
+/// The supplementary groups of the process.
+fn groups() -> Result<Vec<gid_t>> {
+    let len = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+
+    check(len)?;
+
+    let mut groups = vec![0; len as usize];
+    let len        = unsafe { libc::getgroups(len, groups.as_mut_ptr()) };
+
+    check(len)?;
+    groups.truncate(len as usize);
+
+    Ok(groups)
+}
+
+/// Converts the return value of a libc function that returns `-1` on
+/// failure into a `Result`.
+fn check(ret: c_int) -> Result<()> {
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_privileges() {
+        // only root can give up (and regain) its privileges
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let gid    = unsafe { libc::getegid() };
+        let groups = groups().unwrap();
+
+        {
+            let _privileges = DroppedPrivileges::new(65534, 65534).unwrap();
+
+            assert_eq!(65534,       unsafe { libc::geteuid() });
+            assert_eq!(65534,       unsafe { libc::getegid() });
+            assert_eq!(vec![65534], super::groups().unwrap());
+
+            // root's privileges really are gone in the meantime
+            assert!(DroppedPrivileges::new(0, 0).is_err());
+        }
+
+        assert_eq!(0,      unsafe { libc::geteuid() });
+        assert_eq!(gid,    unsafe { libc::getegid() });
+        assert_eq!(groups, super::groups().unwrap());
+
+        DroppedPrivileges::new(65534, 65534).unwrap().restore().unwrap();
+
+        assert_eq!(0, unsafe { libc::geteuid() });
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Renders a list of arguments (e.g., a reconstructed `sudo`
+//! invocation) as a single line, either quoted for a POSIX shell or as
+//! a JSON array of strings.
+//!
+//! Neither form escapes control characters for a terminal: shell
+//! quoting passes them through as they are, so anything displaying an
+//! invocation to a person must still make them visible itself.
+
+use sudo_pair_types::json;
+
+/// Joins `words` with spaces, quoting any that a POSIX shell would
+/// otherwise split, expand, or interpret, so pasting the result into
+/// `sh` reproduces the same arguments.
+pub(crate) fn sh<W: AsRef<[u8]>>(words: &[W]) -> Vec<u8> {
+    let mut line = Vec::new();
+
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+
+        sh_word(&mut line, word.as_ref());
+    }
+
+    line
+}
+
+/// Renders `words` as a JSON array of strings. Arguments needn't be
+/// UTF-8, so any that aren't are rendered lossily, with invalid bytes
+/// replaced by `U+FFFD`.
+pub(crate) fn json<W: AsRef<[u8]>>(words: &[W]) -> String {
+    let mut array = String::from("[");
+
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            array.push(',');
+        }
+
+        array.push_str(&json::quote(&String::from_utf8_lossy(word.as_ref())));
+    }
+
+    array.push(']');
+    array
+}
+
+/// Appends `word` to `line`, single-quoted unless it's made entirely of
+/// characters no shell treats specially. Single quotes can't appear
+/// within single quotes, so each is written as `'\''`: closing the
+/// quotes, an escaped quote, and reopening them.
+fn sh_word(line: &mut Vec<u8>, word: &[u8]) {
+    let safe = |b: &u8| b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(b);
+
+    if !word.is_empty() && word.iter().all(safe) {
+        line.extend_from_slice(word);
+        return;
+    }
+
+    line.push(b'\'');
+
+    for &b in word {
+        match b {
+            b'\'' => line.extend_from_slice(b"'\\''"),
+            _     => line.push(b),
+        }
+    }
+
+    line.push(b'\'');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_for_the_shell() {
+        assert_eq!(&b"sudo --user postgres /bin/ls -la"[..], &sh(&["sudo", "--user", "postgres", "/bin/ls", "-la"])[..]);
+
+        assert_eq!(&b"echo 'hello world' '' '$HOME' 'a;b'"[..], &sh(&["echo", "hello world", "", "$HOME", "a;b"])[..]);
+        assert_eq!(&b"echo 'it'\\''s' '*'"[..],                &sh(&["echo", "it's", "*"])[..]);
+        assert_eq!(&b"printf '\xff\n'"[..],                   &sh(&[&b"printf"[..], &b"\xff\n"[..]])[..]);
+
+        assert!(sh::<&str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn renders_json_arrays() {
+        assert_eq!(r#"["sudo","/bin/ls","-la"]"#,          json(&["sudo", "/bin/ls", "-la"]));
+        assert_eq!(r#"["echo","say \"hi\"","a\\b",""]"#,   json(&["echo", "say \"hi\"", "a\\b", ""]));
+        assert_eq!(r#"["\n\t\u001b[2J","�"]"#,             json(&[&b"\n\t\x1b[2J"[..], &b"\xff"[..]]));
+
+        assert_eq!("[]", json::<&str>(&[]));
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+//! Serialization of the options sudo provides plugins, with the `serde`
+//! feature, so plugins can persist or transmit them (e.g., in an audit
+//! event).
+//!
+//! Sudo's options needn't be UTF-8, but most formats serde serializes
+//! to require strings that are, so keys, values, and paths that aren't
+//! are serialized lossily, with invalid bytes replaced by `U+FFFD`.
+
+use super::option_map::OptionMap;
+
+use std::path::{Path, PathBuf};
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+/// Serializes as a map of every key provided to its value, ordered by
+/// key so the same options are always serialized the same way.
+impl Serialize for OptionMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entries : Vec<_> = self.iter().collect();
+        entries.sort_unstable();
+
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+
+        for (k, v) in entries {
+            map.serialize_entry(
+                &String::from_utf8_lossy(k),
+                &String::from_utf8_lossy(v),
+            )?;
+        }
+
+        map.end()
+    }
+}
+
+/// Serializes `path` as a string, even when it isn't UTF-8 (which
+/// serde's own implementation refuses to do).
+pub(crate) fn path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+/// Serializes `path`, if there is one, as a string.
+pub(crate) fn optional_path<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_some(&path.to_string_lossy()),
+        None       => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{CommandInfo, Settings, UserInfo};
+
+    use std::convert::TryFrom;
+    use std::ptr;
+
+    use serde_json::json;
+
+    macro_rules! option_map {
+        ($($entry:expr),* $(,)?) => {
+            unsafe { OptionMap::from_raw([
+                $(concat!($entry, "\0").as_ptr() as _,)*
+                ptr::null(),
+            ].as_ptr()) }
+        };
+    }
+
+    #[test]
+    fn serializes_option_maps_in_order() {
+        let map = option_map!["user=alice", "cwd=/home/\u{e9}", "noexec"];
+
+        assert_eq!(
+            r#"{"cwd":"/home/é","noexec":"noexec","user":"alice"}"#,
+            serde_json::to_string(&map).unwrap(),
+        );
+
+        let map = unsafe { OptionMap::from_raw([
+            b"cwd=/home/\xe9\0".as_ptr() as _,
+            ptr::null(),
+        ].as_ptr()) };
+
+        assert_eq!(json!({ "cwd": "/home/\u{fffd}" }), serde_json::to_value(&map).unwrap());
+    }
+
+    #[test]
+    fn serializes_parsed_options() {
+        let settings = Settings::try_from(option_map![
+            "plugin_dir=/usr/libexec/sudo",
+            "plugin_path=sudo_pair.so",
+            "progname=sudo",
+            "network_addrs=10.0.0.1/255.0.0.0",
+        ]).unwrap();
+
+        let user_info = UserInfo::try_from(option_map![
+            "cwd=/home/alice", "egid=1000", "euid=0", "gid=1000",
+            "groups=1000,27", "host=db01", "pgid=1", "pid=1", "ppid=1",
+            "uid=1000", "user=alice",
+        ]).unwrap();
+
+        let command_info = CommandInfo::try_from(option_map![
+            "command=/usr/bin/psql", "runas_uid=109", "runas_gid=109",
+        ]).unwrap();
+
+        let settings     = serde_json::to_value(&settings).unwrap();
+        let user_info    = serde_json::to_value(&user_info).unwrap();
+        let command_info = serde_json::to_value(&command_info).unwrap();
+
+        assert_eq!(json!("sudo_pair.so"),                              settings["plugin_path"]);
+        assert_eq!(json!([{ "addr": "10.0.0.1", "mask": "255.0.0.0" }]), settings["network_addrs"]);
+        assert_eq!(json!("/home/alice"),                               user_info["cwd"]);
+        assert_eq!(json!([1000, 27]),                                  user_info["groups"]);
+        assert_eq!(json!(null),                                        user_info["tty"]);
+        assert_eq!(json!("/usr/bin/psql"),                             command_info["command"]);
+        assert_eq!(json!(109),                                         command_info["runas_euid"]);
+
+        // what sudo provided is available (in full) from `raw`
+        assert!(settings.get("raw").is_none());
+    }
+}
@@ -17,10 +17,22 @@ use super::option_map::*;
 use super::traits::*;
 
 use std::convert::TryFrom;
-use std::net::{AddrParseError, IpAddr};
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
 use std::str;
-
+use std::time::Duration;
+
+/// The `settings` sudo provides to every plugin, describing how `sudo`
+/// itself was invoked.
+///
+/// Each field corresponds to the option of the same name described in
+/// `sudo_plugin(8)`, and `raw` holds every option as it was provided.
+///
+/// With the `serde` feature, every field but `raw` is serialized; `raw`
+/// can be serialized on its own.
+#[allow(missing_docs)]
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Settings {
     pub bsd_auth_type:        Option<String>,
     pub close_from:           Option<u64>,
@@ -33,8 +45,10 @@ pub struct Settings {
     pub max_groups:           Option<u64>,
     pub network_addrs:        Vec<NetAddr>,
     pub noninteractive:       bool,
-    pub plugin_dir:           String,
-    pub plugin_path:          String,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::path"))]
+    pub plugin_dir:           PathBuf,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::path"))]
+    pub plugin_path:          PathBuf,
     pub preserve_environment: bool,
     pub preserve_groups:      bool,
     pub progname:             String,
@@ -47,110 +61,109 @@ pub struct Settings {
     pub selinux_type:         Option<String>,
     pub set_home:             bool,
     pub sudoedit:             bool,
-    pub timeout:              Option<String>,
+    pub timeout:              Option<Duration>,
 
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub raw: OptionMap,
 }
 
 impl Settings {
-    // TODO: surely this can be made more cleanly; also, it would be
-    // great if we could actually get the full original `sudo`
-    // invocation without having to reconstruct it by hand
+    /// Reconstructs the flags `sudo` was most likely invoked with, each
+    /// with its value if it takes one (e.g., `--user postgres`).
+    pub fn flags(&self) -> Vec<Vec<u8>> {
+        self.flag_values().into_iter()
+            .map(|(flag, value)| match value {
+                Some(value) => [flag, b" ", &value].concat(),
+                None        => flag.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Reconstructs the flags `sudo` was most likely invoked with, as
+    /// the separate arguments they would have been passed as (e.g.,
+    /// `--user` and `postgres`).
+    pub fn flag_args(&self) -> Vec<Vec<u8>> {
+        self.flag_values().into_iter()
+            .flat_map(|(flag, value)| Some(flag.to_vec()).into_iter().chain(value))
+            .collect()
+    }
+
+    // TODO: it would be great if we could actually get the full
+    // original `sudo` invocation without having to reconstruct it by
+    // hand
     //
     // TODO: maybe if /proc/$$/cmd exists I can prefer to use it
-    pub fn flags(&self) -> Vec<Vec<u8>> {
-        let mut flags : Vec<Vec<u8>> = vec![];
+    fn flag_values(&self) -> Vec<(&'static [u8], Option<Vec<u8>>)> {
+        let mut flags : Vec<(&'static [u8], Option<Vec<u8>>)> = vec![];
+
+        let value = |value: &str| Some(value.as_bytes().to_vec());
 
         // `sudoedit` is set if the flag was provided *or* if sudo
         // was invoked as `sudoedit` directly; try our best to intrepret
         // this case, although we'll technically get it wrong in the
         // case of `sudoedit -e ...`
         if self.sudoedit && self.progname != "sudoedit" {
-            flags.push(b"--edit".to_vec());
+            flags.push((b"--edit", None));
         }
 
         if let Some(ref runas_user) = self.runas_user {
-            let mut flag = b"--user ".to_vec();
-            flag.extend_from_slice(runas_user.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--user", value(runas_user)));
         }
 
         if let Some(ref runas_group) = self.runas_group {
-            let mut flag = b"--group ".to_vec();
-            flag.extend_from_slice(runas_group.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--group", value(runas_group)));
         }
 
         if let Some(ref prompt) = self.prompt {
-            let mut flag = b"--prompt ".to_vec();
-            flag.extend_from_slice(prompt.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--prompt", value(prompt)));
         }
 
         if self.login_shell {
-            flags.push(b"--login".to_vec());
+            flags.push((b"--login", None));
         }
 
         if self.run_shell {
-            flags.push(b"--shell".to_vec());
+            flags.push((b"--shell", None));
         }
 
         if self.set_home {
-            flags.push(b"--set-home".to_vec());
+            flags.push((b"--set-home", None));
         }
 
         if self.preserve_environment {
-            flags.push(b"--preserve-env".to_vec());
+            flags.push((b"--preserve-env", None));
         }
 
         if self.preserve_groups {
-            flags.push(b"--preserve-groups".to_vec());
+            flags.push((b"--preserve-groups", None));
         }
 
         if self.ignore_ticket {
-            flags.push(b"--reset-timestamp".to_vec());
+            flags.push((b"--reset-timestamp", None));
         }
 
         if self.noninteractive {
-            flags.push(b"--non-interactive".to_vec());
+            flags.push((b"--non-interactive", None));
         }
 
         if let Some(ref login_class) = self.login_class {
-            let mut flag = b"--login-class ".to_vec();
-            flag.extend_from_slice(login_class.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--login-class", value(login_class)));
         }
 
         if let Some(ref selinux_role) = self.selinux_role {
-            let mut flag = b"--role ".to_vec();
-            flag.extend_from_slice(selinux_role.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--role", value(selinux_role)));
         }
 
         if let Some(ref selinux_type) = self.selinux_type {
-            let mut flag = b"--type ".to_vec();
-            flag.extend_from_slice(selinux_type.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--type", value(selinux_type)));
         }
 
         if let Some(ref bsd_auth_type) = self.bsd_auth_type {
-            let mut flag = b"--auth-type ".to_vec();
-            flag.extend_from_slice(bsd_auth_type.as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--auth-type", value(bsd_auth_type)));
         }
 
         if let Some(close_from) = self.close_from {
-            let mut flag = b"--close-from ".to_vec();
-            flag.extend_from_slice(close_from.to_string().as_bytes());
-
-            flags.push(flag);
+            flags.push((b"--close-from", value(&close_from.to_string())));
         }
 
         flags
@@ -166,36 +179,39 @@ impl TryFrom<OptionMap> for Settings {
             plugin_path: value.get("plugin_path")?,
             progname:    value.get("progname")?,
 
-            bsd_auth_type:        value.get("bsd_auth_type")       .ok(),
-            close_from:           value.get("closefrom")           .ok(),
-            debug_flags:          value.get("debug_flags")         .ok(),
-            debug_level:          value.get("debug_level")         .ok(),
-            ignore_ticket:        value.get("ignore_ticket")       .unwrap_or(false),
-            implied_shell:        value.get("implied_shell")       .unwrap_or(false),
-            login_class:          value.get("login_class")         .ok(),
-            login_shell:          value.get("login_shell")         .unwrap_or(false),
-            max_groups:           value.get("max_groups")          .ok(),
-            network_addrs:        value.get("network_addrs")       .unwrap_or_else(|_| vec![]),
-            noninteractive:       value.get("noninteractive")      .unwrap_or(false),
-            preserve_environment: value.get("preserve_environment").unwrap_or(false),
-            preserve_groups:      value.get("preserve_groups")     .unwrap_or(false),
-            prompt:               value.get("prompt")              .ok(),
-            remote_host:          value.get("remote_host")         .ok(),
-            run_shell:            value.get("run_shell")           .unwrap_or(false),
-            runas_group:          value.get("runas_group")         .ok(),
-            runas_user:           value.get("runas_user")          .ok(),
-            selinux_role:         value.get("selinux_role")        .ok(),
-            selinux_type:         value.get("selinux_type")        .ok(),
-            set_home:             value.get("set_home")            .unwrap_or(false),
-            sudoedit:             value.get("sudoedit")            .unwrap_or(false),
-            timeout:              value.get("timeout")             .ok(),
+            bsd_auth_type:        value.get_optional("bsd_auth_type"),
+            close_from:           value.get_optional("closefrom"),
+            debug_flags:          value.get_optional("debug_flags"),
+            debug_level:          value.get_optional("debug_level"),
+            ignore_ticket:        value.get_optional("ignore_ticket")       .unwrap_or(false),
+            implied_shell:        value.get_optional("implied_shell")       .unwrap_or(false),
+            login_class:          value.get_optional("login_class"),
+            login_shell:          value.get_optional("login_shell")         .unwrap_or(false),
+            max_groups:           value.get_optional("max_groups"),
+            network_addrs:        value.get_optional("network_addrs")       .unwrap_or_default(),
+            noninteractive:       value.get_optional("noninteractive")      .unwrap_or(false),
+            preserve_environment: value.get_optional("preserve_environment").unwrap_or(false),
+            preserve_groups:      value.get_optional("preserve_groups")     .unwrap_or(false),
+            prompt:               value.get_optional("prompt"),
+            remote_host:          value.get_optional("remote_host"),
+            run_shell:            value.get_optional("run_shell")           .unwrap_or(false),
+            runas_group:          value.get_optional("runas_group"),
+            runas_user:           value.get_optional("runas_user"),
+            selinux_role:         value.get_optional("selinux_role"),
+            selinux_type:         value.get_optional("selinux_type"),
+            set_home:             value.get_optional("set_home")            .unwrap_or(false),
+            sudoedit:             value.get_optional("sudoedit")            .unwrap_or(false),
+            timeout:              value.get_optional("timeout"),
 
             raw: value,
         })
     }
 }
 
+/// An address of one of the host's network interfaces, along with its
+/// netmask, as provided by sudo in `network_addrs`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct NetAddr {
     pub addr: IpAddr,
     pub mask: IpAddr,
@@ -204,19 +220,18 @@ pub struct NetAddr {
 impl FromSudoOption for NetAddr {
     type Err = AddrParseError;
 
-    // indexing into an array can panic, but there's no cleaner way in
-    // rust to split a byte array on a delimiter, and the code below
-    // selects the midpoint such that it's guaranteed to be within the
-    // slice
-    #[cfg_attr(feature="cargo-clippy", allow(clippy::indexing_slicing))]
     fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
-        let bytes = s.as_bytes();
-        let mid   = bytes.iter()
-            .position(|b| *b == b'/' )
-            .unwrap_or_else(|| bytes.len());
+        let mut parts = s.splitn(2, '/');
+
+        let addr : IpAddr = parts.next().unwrap_or_default().parse()?;
 
-        let addr = s[        .. mid].parse()?;
-        let mask = s[mid + 1 ..    ].parse()?;
+        // sudo always provides a netmask, but if it's ever missing the
+        // address is treated as a single host rather than panicking
+        let mask = match parts.next() {
+            Some(mask) => mask.parse()?,
+            None if addr.is_ipv4() => Ipv4Addr::from(u32::MAX).into(),
+            None                   => Ipv6Addr::from(u128::MAX).into(),
+        };
 
         Ok(Self {
             addr,
@@ -0,0 +1,123 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use crate::errors::*;
+use super::option_map::OptionMap;
+
+use std::ffi::{CStr, OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use libc::c_char;
+
+/// A command run by the command sudo was asked to run (e.g., by the
+/// shell a user sudoed to), as sudo reports it to audit plugins when
+/// the sudoers `intercept` or `log_subcmds` options are enabled (which
+/// needs sudo 1.9.8 or later).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Subcommand {
+    /// The fully-qualified path to the command that was run.
+    pub command: PathBuf,
+
+    /// The arguments the command was run with, starting with the name
+    /// it was run as.
+    pub argv: Vec<OsString>,
+
+    /// The directory the command was run in, if sudo reported it.
+    pub cwd: Option<PathBuf>,
+}
+
+impl Subcommand {
+    /// Parses a subcommand from the `command_info` and `run_argv` sudo
+    /// passes to an audit plugin's `accept` callback. Both are arrays of
+    /// NUL-terminated strings, terminated by a NULL pointer.
+    ///
+    /// # Safety
+    ///
+    /// This method cannot be safe, since it relies on the caller to
+    /// terminate both arrays with a NULL pointer.
+    pub unsafe fn from_raw(
+        command_info: *const *const c_char,
+        mut argv:     *const *const c_char,
+    ) -> Result<Self> {
+        let command_info = OptionMap::from_raw(command_info);
+        let mut args     = Vec::new();
+
+        while !argv.is_null() && !(*argv).is_null() {
+            args.push(OsStr::from_bytes(CStr::from_ptr(*argv).to_bytes()).to_owned());
+
+            argv = argv.offset(1);
+        }
+
+        Ok(Self {
+            command: command_info.get("command")?,
+            argv:    args,
+            cwd:     command_info.get_optional("cwd"),
+        })
+    }
+}
+
+impl fmt::Display for Subcommand {
+    /// Displays the command as it was run: its full path, followed by
+    /// its arguments.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.command.display())?;
+
+        for arg in self.argv.iter().skip(1) {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    #[test]
+    fn parses_subcommands() {
+        let subcommand = unsafe { Subcommand::from_raw(
+            [
+                b"command=/usr/bin/vim\0".as_ptr() as _,
+                b"cwd=/etc\0".as_ptr() as _,
+                b"runas_uid=0\0".as_ptr() as _,
+                ptr::null(),
+            ].as_ptr(),
+            [
+                b"vim\0".as_ptr() as _,
+                b"hosts\0".as_ptr() as _,
+                ptr::null(),
+            ].as_ptr(),
+        ) }.expect("the subcommand should parse");
+
+        assert_eq!(PathBuf::from("/usr/bin/vim"), subcommand.command);
+        assert_eq!(vec![OsString::from("vim"), OsString::from("hosts")], subcommand.argv);
+        assert_eq!(Some(PathBuf::from("/etc")), subcommand.cwd);
+        assert_eq!("/usr/bin/vim hosts", subcommand.to_string());
+    }
+
+    #[test]
+    fn requires_a_command() {
+        let subcommand = unsafe { Subcommand::from_raw(
+            [b"cwd=/etc\0".as_ptr() as _, ptr::null()].as_ptr(),
+            ptr::null(),
+        ) };
+
+        assert!(subcommand.is_err());
+    }
+}
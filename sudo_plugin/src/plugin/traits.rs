@@ -12,18 +12,40 @@
 // implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
+use super::option_map::split_list;
+
+use std::borrow::Cow;
 use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
 use std::hash::Hash;
+use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
-use std::str::FromStr;
+use std::str::{self, FromStr};
 
+/// An error returned when one or more elements of a list-valued option
+/// couldn't be parsed.
 #[derive(Clone, Copy, Debug)]
 pub struct ParseListError();
 
+/// A type that can be parsed from the value of a sudo option. This is
+/// the analogue of `FromStr` for values provided by the sudo_plugin API,
+/// and can be implemented by plugins for their own types so they can be
+/// retrieved through `OptionMap::get`.
 pub trait FromSudoOption: Sized {
+    /// The error returned when the value can't be parsed.
     type Err;
 
+    /// Parses the option's value into an instance of this type.
     fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err>;
+
+    /// Parses the option's value from the raw bytes sudo provided,
+    /// returning `None` if it couldn't be parsed. By default, values
+    /// that aren't UTF-8 can't be parsed; types that can hold arbitrary
+    /// bytes (like paths) override this to parse them losslessly.
+    fn from_sudo_option_bytes(bytes: &[u8]) -> Option<Self> {
+        str::from_utf8(bytes).ok()
+            .and_then(|s| Self::from_sudo_option(s).ok())
+    }
 }
 
 impl FromSudoOption for bool {
@@ -113,6 +135,22 @@ impl FromSudoOption for PathBuf {
     fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
         Ok(s.into())
     }
+
+    fn from_sudo_option_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(OsStr::from_bytes(bytes).into())
+    }
+}
+
+impl FromSudoOption for OsString {
+    type Err = ::std::string::ParseError;
+
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+
+    fn from_sudo_option_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(OsStr::from_bytes(bytes).into())
+    }
 }
 
 impl<T> FromSudoOption for Vec<T>
@@ -126,7 +164,7 @@ where
         let mut items = Self::with_capacity(list.len());
 
         for element in list {
-            let item = FromSudoOption::from_sudo_option(element)
+            let item = FromSudoOption::from_sudo_option(&element)
                 .map_err(|_| ParseListError())?;
 
             items.push(item);
@@ -147,11 +185,18 @@ where
     }
 }
 
+/// A type that can be parsed from a list of sudo option values. Types
+/// implementing this trait can be retrieved from an `OptionMap` as a
+/// `Vec` or `HashSet`.
 pub trait FromSudoOptionList: Sized {
+    /// The character separating elements of the list.
     const SEPARATOR: char = ',';
 
-    fn from_sudo_option_list(s: &str) -> Vec<&str> {
-        s.split(|b| b == Self::SEPARATOR).collect()
+    /// Splits the option's value into its individual elements. By
+    /// default, separators can be escaped or quoted as described by
+    /// `split_list`.
+    fn from_sudo_option_list(s: &str) -> Vec<Cow<'_, str>> {
+        split_list(s, Self::SEPARATOR)
     }
 }
 
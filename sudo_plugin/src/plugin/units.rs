@@ -0,0 +1,246 @@
+// Copyright 2018 Square Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or
+// implied. See the License for the specific language governing
+// permissions and limitations under the License.
+
+use super::traits::*;
+
+use std::fmt;
+use std::time::Duration;
+
+/// An error returned when a duration or size couldn't be parsed.
+#[derive(Clone, Copy, Debug)]
+pub struct ParseUnitError();
+
+/// A number of bytes, parsed from human-friendly values like `4MiB`.
+///
+/// Binary suffixes (`K`, `KiB`, `M`, `MiB`, ...) are powers of 1024, and
+/// decimal suffixes (`KB`, `MB`, ...) are powers of 1000. Suffixes are
+/// case-insensitive, and a bare number (or one suffixed with `B`) is a
+/// number of bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    /// Returns the number of bytes.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}B", self.0)
+    }
+}
+
+impl FromSudoOption for ByteSize {
+    type Err = ParseUnitError;
+
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let (number, suffix) = split_number(s.trim())?;
+
+        let multiplier : u64 = match suffix.to_ascii_lowercase().as_str() {
+            "" | "b"     => 1,
+            "k" | "kib"  => 1 << 10,
+            "m" | "mib"  => 1 << 20,
+            "g" | "gib"  => 1 << 30,
+            "t" | "tib"  => 1 << 40,
+            "kb"         => 1_000,
+            "mb"         => 1_000_000,
+            "gb"         => 1_000_000_000,
+            "tb"         => 1_000_000_000_000,
+            _            => return Err(ParseUnitError()),
+        };
+
+        number.checked_mul(multiplier)
+            .map(ByteSize)
+            .ok_or(ParseUnitError())
+    }
+}
+
+/// Parses durations like `90s`, `30d`, or `1h30m`. Each component is a
+/// number followed by a case-insensitive unit of `ms`, `s`, `m`, `h`,
+/// `d`, or `w`. A bare number is a number of seconds, which is also the
+/// format sudo itself uses in `command_info`.
+impl FromSudoOption for Duration {
+    type Err = ParseUnitError;
+
+    fn from_sudo_option(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let mut rest  = s.trim();
+        let mut total = Duration::default();
+
+        if rest.is_empty() {
+            return Err(ParseUnitError());
+        }
+
+        // a bare number is a count of seconds
+        if let Ok(seconds) = rest.parse() {
+            return Ok(Duration::from_secs(seconds));
+        }
+
+        while !rest.is_empty() {
+            let (number, remainder) = split_number(rest)?;
+
+            let unit_len = remainder
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(remainder.len());
+
+            let (unit, remainder) = remainder.split_at(unit_len);
+
+            let component = match unit.to_ascii_lowercase().as_str() {
+                "ms" => Some(Duration::from_millis(number)),
+                "s"  => Some(Duration::from_secs(number)),
+                "m"  => number.checked_mul(60).map(Duration::from_secs),
+                "h"  => number.checked_mul(60 * 60).map(Duration::from_secs),
+                "d"  => number.checked_mul(60 * 60 * 24).map(Duration::from_secs),
+                "w"  => number.checked_mul(60 * 60 * 24 * 7).map(Duration::from_secs),
+                _    => return Err(ParseUnitError()),
+            };
+
+            total = component
+                .and_then(|c| total.checked_add(c))
+                .ok_or(ParseUnitError())?;
+
+            rest = remainder.trim_start();
+        }
+
+        Ok(total)
+    }
+}
+
+/// Splits a string into its leading number and whatever follows it.
+fn split_number(s: &str) -> ::std::result::Result<(u64, &str), ParseUnitError> {
+    let len = s
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(s.len());
+
+    let (number, rest) = s.split_at(len);
+
+    number.parse()
+        .map(|n| (n, rest.trim_start()))
+        .map_err(|_| ParseUnitError())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duration(s: &str) -> Option<Duration> {
+        Duration::from_sudo_option(s).ok()
+    }
+
+    fn size(s: &str) -> Option<u64> {
+        ByteSize::from_sudo_option(s).ok().map(ByteSize::as_u64)
+    }
+
+    #[test]
+    fn duration_bare_seconds() {
+        assert_eq!(Some(Duration::from_secs(0)),  duration("0"));
+        assert_eq!(Some(Duration::from_secs(90)), duration("90"));
+    }
+
+    #[test]
+    fn duration_units() {
+        assert_eq!(Some(Duration::from_millis(250)),       duration("250ms"));
+        assert_eq!(Some(Duration::from_secs(90)),          duration("90s"));
+        assert_eq!(Some(Duration::from_secs(5 * 60)),      duration("5m"));
+        assert_eq!(Some(Duration::from_secs(2 * 3600)),    duration("2h"));
+        assert_eq!(Some(Duration::from_secs(30 * 86400)),  duration("30d"));
+        assert_eq!(Some(Duration::from_secs(7 * 86400)),   duration("1w"));
+    }
+
+    #[test]
+    fn duration_is_case_insensitive() {
+        assert_eq!(duration("1h30m"), duration("1H30M"));
+        assert_eq!(duration("10s"),   duration("10S"));
+    }
+
+    #[test]
+    fn duration_combines_components() {
+        assert_eq!(Some(Duration::from_secs(5400)),      duration("1h30m"));
+        assert_eq!(Some(Duration::from_secs(93_784)),    duration("1d2h3m4s"));
+        assert_eq!(Some(Duration::from_millis(1_500)),   duration("1s500ms"));
+    }
+
+    #[test]
+    fn duration_ignores_surrounding_whitespace() {
+        assert_eq!(Some(Duration::from_secs(90)),   duration(" 90s "));
+        assert_eq!(Some(Duration::from_secs(5400)), duration("1h 30m"));
+    }
+
+    #[test]
+    fn duration_rejects_invalid() {
+        assert_eq!(None, duration(""));
+        assert_eq!(None, duration("s"));
+        assert_eq!(None, duration("10x"));
+        assert_eq!(None, duration("-10s"));
+        assert_eq!(None, duration("1.5h"));
+        assert_eq!(None, duration("ten seconds"));
+    }
+
+    #[test]
+    fn duration_rejects_overflow() {
+        assert_eq!(None, duration("18446744073709551615w"));
+        assert_eq!(None, duration("99999999999999999999"));
+    }
+
+    #[test]
+    fn size_bare_bytes() {
+        assert_eq!(Some(0),    size("0"));
+        assert_eq!(Some(4096), size("4096"));
+        assert_eq!(Some(4096), size("4096B"));
+    }
+
+    #[test]
+    fn size_binary_units() {
+        assert_eq!(Some(4 << 10), size("4K"));
+        assert_eq!(Some(4 << 10), size("4KiB"));
+        assert_eq!(Some(4 << 20), size("4MiB"));
+        assert_eq!(Some(4 << 30), size("4GiB"));
+        assert_eq!(Some(4 << 40), size("4TiB"));
+    }
+
+    #[test]
+    fn size_decimal_units() {
+        assert_eq!(Some(4_000),             size("4KB"));
+        assert_eq!(Some(4_000_000),         size("4MB"));
+        assert_eq!(Some(4_000_000_000),     size("4GB"));
+        assert_eq!(Some(4_000_000_000_000), size("4TB"));
+    }
+
+    #[test]
+    fn size_is_case_insensitive() {
+        assert_eq!(size("4MiB"), size("4mib"));
+        assert_eq!(size("4M"),   size("4m"));
+    }
+
+    #[test]
+    fn size_allows_space_before_unit() {
+        assert_eq!(Some(4 << 20), size("4 MiB"));
+    }
+
+    #[test]
+    fn size_rejects_invalid() {
+        assert_eq!(None, size(""));
+        assert_eq!(None, size("MiB"));
+        assert_eq!(None, size("4XB"));
+        assert_eq!(None, size("4.5MiB"));
+        assert_eq!(None, size("-4MiB"));
+        assert_eq!(None, size("4MiB4"));
+    }
+
+    #[test]
+    fn size_rejects_overflow() {
+        assert_eq!(None, size("18446744073709551615K"));
+    }
+}
@@ -18,11 +18,25 @@ use super::option_map::*;
 use std::convert::TryFrom;
 use std::path::PathBuf;
 
-use libc::{gid_t, pid_t, uid_t};
+use libc::{gid_t, mode_t, pid_t, uid_t};
 
+/// The `user_info` sudo provides to every plugin, describing the user
+/// invoking `sudo`.
+///
+/// Each field corresponds to the option of the same name described in
+/// `sudo_plugin(8)`, and `raw` holds every option as it was provided.
+/// Versions of sudo older than 1.8.19 don't provide `umask`, so it's
+/// read from the process (as later versions do) and added to `raw`.
+/// It's given in octal, and is `None` only if it isn't.
+///
+/// With the `serde` feature, every field but `raw` is serialized; `raw`
+/// can be serialized on its own.
+#[allow(missing_docs)]
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct UserInfo {
     pub cols:   u64,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::path"))]
     pub cwd:    PathBuf,
     pub egid:   gid_t,
     pub euid:   uid_t,
@@ -35,11 +49,13 @@ pub struct UserInfo {
     pub ppid:   pid_t,
     pub sid:    pid_t,
     pub tcpgid: pid_t,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "super::serialize::optional_path"))]
     pub tty:    Option<PathBuf>,
     pub uid:    uid_t,
-    pub umask:  Option<String>,
+    pub umask:  Option<mode_t>,
     pub user:   String,
 
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub raw: OptionMap,
 }
 
@@ -60,14 +76,20 @@ impl TryFrom<OptionMap> for UserInfo {
             uid:    value.get("uid")?,
             user:   value.get("user")?,
 
-            umask:  value.get("umask") .ok(),
-            cols:   value.get("cols")  .unwrap_or(80),
-            lines:  value.get("lines") .unwrap_or(24),
-            sid:    value.get("sid")   .unwrap_or(0),
-            tcpgid: value.get("tcpgid").unwrap_or(-1),
-            tty:    value.get("tty")   .ok(),
+            umask:  value.get_str("umask").and_then(parse_umask),
+            cols:   value.get_optional("cols")  .unwrap_or(80),
+            lines:  value.get_optional("lines") .unwrap_or(24),
+            sid:    value.get_optional("sid")   .unwrap_or(0),
+            tcpgid: value.get_optional("tcpgid").unwrap_or(-1),
+            tty:    value.get_optional("tty"),
 
             raw: value,
         })
     }
 }
+
+/// Parses a umask, which sudo gives in octal (e.g., `022` or `0077`).
+pub(crate) fn parse_umask(umask: &str) -> Option<mode_t> {
+    mode_t::from_str_radix(umask, 8).ok()
+        .filter(|umask| umask & !0o777 == 0)
+}